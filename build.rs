@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use std::collections::BTreeSet;
 use std::env;
 use std::fs::File;
@@ -12,6 +12,7 @@ use std::path::Path;
 fn main() -> Result<()> {
     use cargo_metadata::MetadataCommand;
     let mut cmds = BTreeSet::new();
+    let mut mutating = BTreeSet::new();
 
     // MetadataCommand doesn't emit this, so we should
     println!("cargo:rerun-if-changed=Cargo.toml");
@@ -40,12 +41,77 @@ fn dcmds() -> Vec<CommandDescription> {{
 "##
     )?;
 
+    //
+    // A command is only registered if its crate has been enabled:  command
+    // crates that are not safe for production use are optional dependencies
+    // (enabled by the "full" feature), and when building a binary for
+    // operators (the "operator" feature), only commands that are explicitly
+    // marked as safe are registered.  (A command that is not a dependency at
+    // all -- e.g., the REPL -- is part of the binary itself.)
+    //
+    let root = metadata.root_package().unwrap();
+    let operator = env::var_os("CARGO_FEATURE_OPERATOR").is_some();
+
     for id in &metadata.workspace_members {
         let package =
             metadata.packages.iter().find(|p| &p.id == id).unwrap().clone();
 
-        if let Some(cmd) = package.name.strip_prefix("humility-cmd-") {
-            cmds.insert(cmd.to_string().replace('-', "_"));
+        let cmd = match package.name.strip_prefix("humility-cmd-") {
+            Some(cmd) => cmd,
+            None => continue,
+        };
+
+        let safe =
+            package.metadata["humility"]["operator"].as_bool() == Some(true);
+
+        //
+        // A command that modifies the target (e.g., by writing to memory,
+        // flashing, or performing writes over I2C) must be marked as such via
+        // "mutating = true" in its [package.metadata.humility]; such commands
+        // support dry runs, and can't be safe for operators.
+        //
+        let mutates =
+            package.metadata["humility"]["mutating"].as_bool() == Some(true);
+
+        if safe && mutates {
+            bail!(
+                "{} is marked as mutating, and cannot also be marked as \
+                safe for operators",
+                package.name
+            );
+        }
+
+        let dep = root.dependencies.iter().find(|d| d.name == package.name);
+
+        if let Some(dep) = dep {
+            let name = dep.rename.as_ref().unwrap_or(&dep.name);
+
+            if !dep.optional && !safe {
+                bail!(
+                    "{} is not marked as safe for operators, and must be \
+                    an optional dependency enabled by the \"full\" feature",
+                    name
+                );
+            }
+
+            let feature = format!(
+                "CARGO_FEATURE_{}",
+                name.to_uppercase().replace('-', "_")
+            );
+
+            if dep.optional && env::var_os(feature).is_none() {
+                continue;
+            }
+        }
+
+        if operator && !safe {
+            continue;
+        }
+
+        cmds.insert(cmd.to_string().replace('-', "_"));
+
+        if mutates {
+            mutating.insert(cmd.to_string());
         }
     }
 
@@ -62,5 +128,16 @@ fn dcmds() -> Vec<CommandDescription> {{
 
     write!(output, "    ]\n}}")?;
 
+    //
+    // Emit the names of the commands that modify the target.
+    //
+    writeln!(output, "\n\nconst MUTATING: &[&str] = &[")?;
+
+    for cmd in mutating.iter() {
+        writeln!(output, "    \"{}\",", cmd)?;
+    }
+
+    writeln!(output, "];")?;
+
     Ok(())
 }
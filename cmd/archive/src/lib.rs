@@ -0,0 +1,447 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility archive`
+//!
+//! `humility archive` operates on Hubris archives without connecting to a
+//! target.  Currently, its only subcommand is `diff`, which compares two
+//! archives (an older and a newer one), reporting tasks that have been added
+//! or removed; changes to task configuration (including priorities); changes
+//! to the size of each section of the kernel and of each task; changes to
+//! the memory map; changes to the kernel configuration; and changes to the
+//! I2C devices and sensors in the manifest:
+//!
+//! ```console
+//! $ humility archive diff build-gimlet-c-v1.0.2.zip build-gimlet-c-v1.0.3.zip
+//! tasks:
+//!   + sensor_polling
+//!   ~ thermal.priority: 5 -> 4
+//!   ~ net.max-sizes.ram: 65536 -> 131072
+//! sizes:
+//!   OBJECT           SECTION                 OLD        NEW      DELTA
+//!   kernel           .text                 26204      26460       +256
+//!   net              .text                103768     105112      +1344
+//!   net              .bss                  49152      98304     +49152
+//!   sensor_polling   .text                     -       8816      +8816
+//! memory:
+//!   ~ net: 0x08040000+0x20000 r-x, 0x24000000+0x10000 rw-
+//!       -> 0x08040000+0x20000 r-x, 0x24000000+0x20000 rw-
+//! kernel:
+//!   ~ kernel.stacksize: 2048 -> 3072
+//! i2c devices:
+//!   + tmp117 at 2:F 0x49 (Southeast temperature sensor)
+//! sensors:
+//!   + Southeast (temperature, i2c id=37)
+//! ```
+//!
+//! Sections that are the same size in both archives are not shown; to
+//! show them, use `--verbose` (`-v`).  To generate JSON output (e.g., for
+//! release tooling), use `--json`.
+//!
+
+use anyhow::{anyhow, Context, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::ArchiveCore;
+use humility::hubris::*;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Command, CommandKind};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Parser, Debug)]
+enum ArchiveCommand {
+    /// Compare two Hubris archives
+    Diff {
+        /// show sections that have not changed in size
+        #[clap(long, short)]
+        verbose: bool,
+
+        /// generate JSON output
+        #[clap(long)]
+        json: bool,
+
+        /// older archive
+        old: String,
+
+        /// newer archive
+        new: String,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[clap(name = "archive", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct ArchiveArgs {
+    #[clap(subcommand)]
+    cmd: ArchiveCommand,
+}
+
+///
+/// The attributes of an archive that we compare.  Everything other than
+/// section sizes is represented as strings, keyed by what they describe.
+///
+#[derive(Default)]
+struct ArchiveSummary {
+    tasks: BTreeMap<String, BTreeMap<String, String>>,
+    sizes: BTreeMap<(String, String), u64>,
+    memory: BTreeMap<String, String>,
+    kernel: BTreeMap<String, String>,
+    i2c: BTreeSet<String>,
+    sensors: BTreeSet<String>,
+}
+
+///
+/// A difference between two archives:  something added (no old value),
+/// removed (no new value), or changed.
+///
+struct Change {
+    name: String,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+fn flatten(
+    prefix: &str,
+    value: &toml::Value,
+    out: &mut BTreeMap<String, String>,
+) {
+    match value {
+        toml::Value::Table(t) => {
+            for (k, v) in t {
+                flatten(&format!("{prefix}.{k}"), v, out);
+            }
+        }
+        toml::Value::String(s) => {
+            out.insert(prefix.to_string(), s.clone());
+        }
+        v => {
+            out.insert(prefix.to_string(), v.to_string());
+        }
+    }
+}
+
+fn sections(
+    hubris: &HubrisArchive,
+    object: &str,
+    file: &str,
+    sizes: &mut BTreeMap<(String, String), u64>,
+) -> Result<()> {
+    let contents = match hubris.read_file(file)? {
+        Some(contents) => contents,
+        None => return Ok(()),
+    };
+
+    let elf = goblin::elf::Elf::parse(&contents)
+        .map_err(|e| anyhow!("failed to parse {file}: {e}"))?;
+
+    for sh in elf.section_headers.iter() {
+        if sh.sh_flags & u64::from(goblin::elf::section_header::SHF_ALLOC) == 0
+            || sh.sh_size == 0
+        {
+            continue;
+        }
+
+        if let Some(Ok(name)) = elf.shdr_strtab.get(sh.sh_name) {
+            let key = (object.to_string(), name.to_string());
+            *sizes.entry(key).or_default() += sh.sh_size;
+        }
+    }
+
+    Ok(())
+}
+
+fn summarize(path: &str) -> Result<ArchiveSummary> {
+    let mut hubris = HubrisArchive::new()?;
+
+    hubris
+        .load(path, HubrisArchiveDoneness::Cook)
+        .with_context(|| format!("failed to load {path}"))?;
+
+    let mut summary = ArchiveSummary::default();
+
+    //
+    // Task and kernel configuration come from the app.toml in the archive.
+    //
+    let app = hubris
+        .read_file("app.toml")?
+        .ok_or_else(|| anyhow!("{path} does not contain app.toml"))?;
+    let app: toml::Value = toml::from_slice(&app)?;
+
+    if let Some(toml::Value::Table(tasks)) = app.get("tasks") {
+        for (name, task) in tasks {
+            let mut config = BTreeMap::new();
+            flatten(name, task, &mut config);
+            summary.tasks.insert(name.clone(), config);
+        }
+    }
+
+    if let Some(kernel) = app.get("kernel") {
+        flatten("kernel", kernel, &mut summary.kernel);
+    }
+
+    sections(&hubris, "kernel", "elf/kernel", &mut summary.sizes)?;
+
+    for module in hubris.modules() {
+        if let HubrisTask::Task(_) = module.task {
+            let file = format!("elf/task/{}", module.name);
+            sections(&hubris, &module.name, &file, &mut summary.sizes)?;
+        }
+    }
+
+    //
+    // The memory map comes from the kernel's task table, which is in flash
+    // and can therefore be read from the archive itself.
+    //
+    let mut core = ArchiveCore::new(&hubris)?;
+    let mut memory: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for region in hubris.regions(&mut core)?.values() {
+        let attr = &region.attr;
+
+        let desc = format!(
+            "0x{:08x}+0x{:x} {}{}{}{}{}",
+            region.base,
+            region.size,
+            if attr.read { "r" } else { "-" },
+            if attr.write { "w" } else { "-" },
+            if attr.execute { "x" } else { "-" },
+            if attr.device { " device" } else { "" },
+            if attr.dma { " dma" } else { "" },
+        );
+
+        for task in &region.tasks {
+            let name = match task {
+                HubrisTask::Kernel => "kernel".to_string(),
+                _ => hubris.lookup_module(*task)?.name.clone(),
+            };
+
+            memory.entry(name).or_default().push(desc.clone());
+        }
+    }
+
+    for (name, regions) in memory {
+        summary.memory.insert(name, regions.join(", "));
+    }
+
+    for d in &hubris.manifest.i2c_devices {
+        let mux = match (d.mux, d.segment) {
+            (Some(m), Some(s)) => format!(":{m}:{s}"),
+            _ => String::new(),
+        };
+
+        summary.i2c.insert(format!(
+            "{} at {}:{}{} 0x{:02x} ({})",
+            d.device, d.controller, d.port.name, mux, d.address, d.description
+        ));
+    }
+
+    for s in &hubris.manifest.sensors {
+        let device = match &s.device {
+            HubrisSensorDevice::I2c(ndx) => format!("i2c id={}", ndx),
+            HubrisSensorDevice::Other(dev, _) => dev.to_string(),
+        };
+
+        summary.sensors.insert(format!(
+            "{} ({}, {})",
+            s.name,
+            s.kind.to_string(),
+            device
+        ));
+    }
+
+    Ok(summary)
+}
+
+fn diff_maps(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> Vec<Change> {
+    let keys = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+
+    keys.into_iter()
+        .filter(|k| old.get(*k) != new.get(*k))
+        .map(|k| Change {
+            name: k.clone(),
+            old: old.get(k).cloned(),
+            new: new.get(k).cloned(),
+        })
+        .collect()
+}
+
+fn diff_sets(old: &BTreeSet<String>, new: &BTreeSet<String>) -> Vec<Change> {
+    let removed = old.difference(new).map(|s| Change {
+        name: s.clone(),
+        old: Some(s.clone()),
+        new: None,
+    });
+
+    let added = new.difference(old).map(|s| Change {
+        name: s.clone(),
+        old: None,
+        new: Some(s.clone()),
+    });
+
+    removed.chain(added).collect()
+}
+
+///
+/// Compares tasks:  a task that has been added or removed is reported as
+/// such, while a task in both archives has its configuration compared.
+///
+fn diff_tasks(old: &ArchiveSummary, new: &ArchiveSummary) -> Vec<Change> {
+    let mut changes = vec![];
+    let names = old.tasks.keys().chain(new.tasks.keys());
+
+    for name in names.collect::<BTreeSet<_>>() {
+        match (old.tasks.get(name), new.tasks.get(name)) {
+            (Some(o), Some(n)) => changes.extend(diff_maps(o, n)),
+            (o, n) => changes.push(Change {
+                name: name.clone(),
+                old: o.map(|_| name.clone()),
+                new: n.map(|_| name.clone()),
+            }),
+        }
+    }
+
+    changes
+}
+
+fn print_changes(title: &str, changes: &[Change]) {
+    if changes.is_empty() {
+        return;
+    }
+
+    println!("{title}:");
+
+    for c in changes {
+        match (&c.old, &c.new) {
+            (None, Some(n)) if *n == c.name => println!("  + {}", c.name),
+            (Some(o), None) if *o == c.name => println!("  - {}", c.name),
+            (None, Some(n)) => println!("  + {}: {}", c.name, n),
+            (Some(o), None) => println!("  - {}: {}", c.name, o),
+            (Some(o), Some(n)) if o.len() + n.len() + c.name.len() > 60 => {
+                println!("  ~ {}: {}\n      -> {}", c.name, o, n)
+            }
+            (Some(o), Some(n)) => println!("  ~ {}: {} -> {}", c.name, o, n),
+            (None, None) => {}
+        }
+    }
+}
+
+fn changes_json(changes: &[Change]) -> serde_json::Value {
+    changes
+        .iter()
+        .map(|c| json!({ "name": c.name, "old": c.old, "new": c.new }))
+        .collect()
+}
+
+fn archive_diff(old: &str, new: &str, verbose: bool, json: bool) -> Result<()> {
+    let o = summarize(old)?;
+    let n = summarize(new)?;
+
+    let tasks = diff_tasks(&o, &n);
+    let memory = diff_maps(&o.memory, &n.memory);
+    let kernel = diff_maps(&o.kernel, &n.kernel);
+    let i2c = diff_sets(&o.i2c, &n.i2c);
+    let sensors = diff_sets(&o.sensors, &n.sensors);
+
+    let keys = o.sizes.keys().chain(n.sizes.keys()).collect::<BTreeSet<_>>();
+    let sizes = keys
+        .into_iter()
+        .map(|k| (k, o.sizes.get(k).copied(), n.sizes.get(k).copied()))
+        .filter(|(_, o, n)| verbose || o != n)
+        .collect::<Vec<_>>();
+
+    if json {
+        let sizes = sizes
+            .iter()
+            .map(|((object, section), old, new)| {
+                json!({
+                    "object": object,
+                    "section": section,
+                    "old": old,
+                    "new": new,
+                })
+            })
+            .collect::<serde_json::Value>();
+
+        let out = json!({
+            "old": old,
+            "new": new,
+            "tasks": changes_json(&tasks),
+            "sizes": sizes,
+            "memory": changes_json(&memory),
+            "kernel": changes_json(&kernel),
+            "i2c_devices": changes_json(&i2c),
+            "sensors": changes_json(&sensors),
+        });
+
+        println!("{}", serde_json::to_string_pretty(&out)?);
+        return Ok(());
+    }
+
+    print_changes("tasks", &tasks);
+
+    if !sizes.is_empty() {
+        let size = |s: Option<u64>| match s {
+            Some(s) => s.to_string(),
+            None => "-".to_string(),
+        };
+
+        println!("sizes:");
+        println!(
+            "  {:16} {:16} {:>10} {:>10} {:>10}",
+            "OBJECT", "SECTION", "OLD", "NEW", "DELTA"
+        );
+
+        for ((object, section), old, new) in &sizes {
+            let delta = new.unwrap_or(0) as i64 - old.unwrap_or(0) as i64;
+
+            println!(
+                "  {:16} {:16} {:>10} {:>10} {:>+10}",
+                object,
+                section,
+                size(*old),
+                size(*new),
+                delta
+            );
+        }
+    }
+
+    print_changes("memory", &memory);
+    print_changes("kernel", &kernel);
+    print_changes("i2c devices", &i2c);
+    print_changes("sensors", &sensors);
+
+    if tasks.is_empty()
+        && sizes.is_empty()
+        && memory.is_empty()
+        && kernel.is_empty()
+        && i2c.is_empty()
+        && sensors.is_empty()
+    {
+        humility::msg!("no differences found");
+    }
+
+    Ok(())
+}
+
+fn archivecmd(context: &mut ExecutionContext) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = ArchiveArgs::try_parse_from(subargs)?;
+
+    match subargs.cmd {
+        ArchiveCommand::Diff { old, new, verbose, json } => {
+            archive_diff(&old, &new, verbose, json)
+        }
+    }
+}
+
+pub fn init() -> Command {
+    Command {
+        app: ArchiveArgs::command(),
+        name: "archive",
+        run: archivecmd,
+        kind: CommandKind::Unattached { archive: Archive::Ignored },
+    }
+}
@@ -0,0 +1,798 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility battery`
+//!
+//! Query batteries via their gas gauges, decoding charge state, capacity,
+//! health, cycle count and any protection status.  Gauges that implement
+//! the Smart Battery Data Specification (SBS) are supported, along with
+//! TI's Impedance Track extensions (BQ40Z50, BQ40Z80) and the ModelGauge
+//! register set of the MAX17048/MAX17049.
+//!
+//! When run without arguments, `humility battery` will query every gas
+//! gauge found in the archive's I2C manifest, using the manifest's name and
+//! description to identify each battery:
+//!
+//! ```console
+//! $ humility battery
+//! humility: attached via ST-Link V3
+//! BATTERY bq40z50 (backup) on I2C2, port F, dev 0x0b
+//!   description          RTC/NVRAM backup battery
+//!   manufacturer         Texas Inst.
+//!   device               bq40z50-R2
+//!   chemistry            LION
+//!   serial               0x0001
+//!   voltage              12.146 V
+//!   current              -0.012 A
+//!   average current      -0.011 A
+//!   temperature          24.6°C
+//!   relative charge      87%
+//!   absolute charge      80%
+//!   remaining capacity   3826 mAh
+//!   full capacity        4412 mAh
+//!   design capacity      4800 mAh
+//!   health               92%
+//!   cycle count          17
+//!   time to empty        20868 min
+//!   time to full         -
+//!   status               0x00c0 = INITIALIZED | DISCHARGING
+//!   safety status        0x00000000
+//!   PF status            0x00000000
+//! ```
+//!
+//! Any alarm, safety or permanent failure (PF) condition is highlighted.  A
+//! single battery can be selected by its device type or by its name in the
+//! manifest via `--device` (`-d`):
+//!
+//! ```console
+//! $ humility battery -d backup
+//! ```
+//!
+//! A gauge that is not in the manifest can be queried by specifying its
+//! bus (or controller and port), multiplexer and address along with the
+//! kind of gauge via `--gauge` (`-g`):
+//!
+//! ```console
+//! $ humility battery -b rear -d 0x36 -g max17048
+//! humility: attached via ST-Link V3
+//! BATTERY max17048 on I2C4, port F, dev 0x36
+//!   version              0x0012
+//!   voltage              3.912 V
+//!   charge               81.23%
+//!   charge rate          -0.624%/hr
+//!   status               0x0100 = RI
+//! ```
+//!
+//! To list the gas gauges in the archive without querying them, use
+//! `--list` (`-l`).
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use colored::Colorize;
+use hif::*;
+use humility::core::Core;
+use humility::hubris::*;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_hiffy::*;
+use humility_i2c::{I2cArgs, I2cRetryPolicy};
+
+#[derive(Parser, Debug)]
+#[clap(name = "battery", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct BatteryArgs {
+    /// sets timeout
+    #[clap(
+        long, short = 'T', default_value_t = 5000, value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+
+    /// list gas gauges found in the archive
+    #[clap(long, short, conflicts_with_all = &["gauge", "bus", "controller"])]
+    list: bool,
+
+    /// specifies an I2C bus by name
+    #[clap(long, short, value_name = "bus",
+        conflicts_with_all = &["port", "controller"]
+    )]
+    bus: Option<String>,
+
+    /// specifies an I2C controller
+    #[clap(long, short, value_name = "controller",
+        parse(try_from_str = parse_int::parse),
+    )]
+    controller: Option<u8>,
+
+    /// specifies an I2C controller port
+    #[clap(long, short, value_name = "port")]
+    port: Option<String>,
+
+    /// specifies I2C multiplexer and segment
+    #[clap(long, short, value_name = "mux:segment")]
+    mux: Option<String>,
+
+    /// specifies a gauge by address, device type or name
+    #[clap(long, short, value_name = "device")]
+    device: Option<String>,
+
+    /// specifies the kind of gas gauge
+    #[clap(long, short, arg_enum, value_name = "gauge")]
+    gauge: Option<Gauge>,
+}
+
+#[derive(clap::ArgEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum Gauge {
+    /// Generic Smart Battery Data Specification gauge
+    Sbs,
+    /// TI BQ40Z50/BQ40Z80, with Impedance Track extensions
+    Bq40z50,
+    /// Maxim MAX17048/MAX17049 ModelGauge
+    Max17048,
+}
+
+impl Gauge {
+    fn from_device(device: &str) -> Option<Self> {
+        match device {
+            "sbs" => Some(Gauge::Sbs),
+            "bq40z50" | "bq40z80" => Some(Gauge::Bq40z50),
+            "max17048" | "max17049" => Some(Gauge::Max17048),
+            _ => None,
+        }
+    }
+
+    fn fields(&self) -> Vec<&'static Field> {
+        match self {
+            Gauge::Sbs => SBS_FIELDS.iter().collect(),
+            Gauge::Bq40z50 => {
+                SBS_FIELDS.iter().chain(BQ40Z50_FIELDS.iter()).collect()
+            }
+            Gauge::Max17048 => MAX17048_FIELDS.iter().collect(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Read {
+    /// Little-endian SMBus word
+    Word,
+    /// Big-endian word, as used by ModelGauge devices
+    WordBE,
+    /// SMBus block read
+    Block,
+}
+
+/// The result of decoding a field:  its displayed value, and whether it
+/// indicates a condition that merits the operator's attention.
+struct Decoded {
+    value: String,
+    alert: bool,
+}
+
+impl Decoded {
+    fn new(value: String) -> Self {
+        Self { value, alert: false }
+    }
+}
+
+struct Field {
+    name: &'static str,
+    register: u8,
+    read: Read,
+    decode: fn(&[u8]) -> Result<Decoded>,
+}
+
+fn word(buf: &[u8], read: Read) -> Result<u16> {
+    if buf.len() < 2 {
+        bail!("short read ({} bytes)", buf.len());
+    }
+
+    Ok(match read {
+        Read::WordBE => u16::from_be_bytes([buf[0], buf[1]]),
+        _ => u16::from_le_bytes([buf[0], buf[1]]),
+    })
+}
+
+fn le32(buf: &[u8]) -> Result<u32> {
+    if buf.len() < 4 {
+        bail!("short block ({} bytes)", buf.len());
+    }
+
+    Ok(u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]))
+}
+
+fn flags(val: u32, width: usize, bits: &[(u32, &str)]) -> String {
+    let set = bits
+        .iter()
+        .filter(|(bit, _)| val & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+
+    let hex = format!("0x{:0width$x}", val, width = width);
+
+    if set.is_empty() {
+        hex
+    } else {
+        format!("{} = {}", hex, set.join(" | "))
+    }
+}
+
+fn decode_string(buf: &[u8]) -> Result<Decoded> {
+    let s = String::from_utf8_lossy(buf);
+    Ok(Decoded::new(s.trim_end_matches('\0').trim().to_string()))
+}
+
+fn decode_hex(buf: &[u8]) -> Result<Decoded> {
+    Ok(Decoded::new(format!("0x{:04x}", word(buf, Read::Word)?)))
+}
+
+fn decode_count(buf: &[u8]) -> Result<Decoded> {
+    Ok(Decoded::new(format!("{}", word(buf, Read::Word)?)))
+}
+
+fn decode_millivolts(buf: &[u8]) -> Result<Decoded> {
+    let mv = word(buf, Read::Word)?;
+    Ok(Decoded::new(format!("{:.3} V", mv as f32 / 1000.0)))
+}
+
+fn decode_milliamps(buf: &[u8]) -> Result<Decoded> {
+    let ma = word(buf, Read::Word)? as i16;
+    Ok(Decoded::new(format!("{:.3} A", ma as f32 / 1000.0)))
+}
+
+fn decode_decikelvin(buf: &[u8]) -> Result<Decoded> {
+    let dk = word(buf, Read::Word)?;
+    Ok(Decoded::new(format!("{:.1}°C", dk as f32 / 10.0 - 273.15)))
+}
+
+fn decode_percent(buf: &[u8]) -> Result<Decoded> {
+    let pct = word(buf, Read::Word)?;
+    Ok(Decoded { value: format!("{}%", pct), alert: pct <= 10 })
+}
+
+fn decode_capacity(buf: &[u8]) -> Result<Decoded> {
+    //
+    // Capacities are in units of 10 mWh rather than mAh if CAPACITY_MODE
+    // is set in BatteryMode; we assume the (default) current mode.
+    //
+    Ok(Decoded::new(format!("{} mAh", word(buf, Read::Word)?)))
+}
+
+fn decode_minutes(buf: &[u8]) -> Result<Decoded> {
+    //
+    // A value of 65535 indicates that the battery is not being charged (or
+    // discharged, as the case may be).
+    //
+    Ok(Decoded::new(match word(buf, Read::Word)? {
+        0xffff => "-".to_string(),
+        min => format!("{} min", min),
+    }))
+}
+
+fn decode_battery_status(buf: &[u8]) -> Result<Decoded> {
+    let val = word(buf, Read::Word)?;
+
+    let bits = [
+        (15, "OVER_CHARGED_ALARM"),
+        (14, "TERMINATE_CHARGE_ALARM"),
+        (12, "OVER_TEMP_ALARM"),
+        (11, "TERMINATE_DISCHARGE_ALARM"),
+        (9, "REMAINING_CAPACITY_ALARM"),
+        (8, "REMAINING_TIME_ALARM"),
+        (7, "INITIALIZED"),
+        (6, "DISCHARGING"),
+        (5, "FULLY_CHARGED"),
+        (4, "FULLY_DISCHARGED"),
+    ];
+
+    let mut value = flags(val as u32, 4, &bits);
+
+    //
+    // The bottom nibble contains the error code for the last command.
+    //
+    let err = match val & 0xf {
+        0 => None,
+        1 => Some("Busy"),
+        2 => Some("ReservedCommand"),
+        3 => Some("UnsupportedCommand"),
+        4 => Some("AccessDenied"),
+        5 => Some("Overflow/Underflow"),
+        6 => Some("BadSize"),
+        _ => Some("UnknownError"),
+    };
+
+    if let Some(err) = err {
+        value = format!("{} (last error: {})", value, err);
+    }
+
+    Ok(Decoded { value, alert: val & 0xdb00 != 0 })
+}
+
+fn decode_safety_status(buf: &[u8]) -> Result<Decoded> {
+    let val = le32(buf)?;
+
+    let bits = [
+        (0, "CUV"),
+        (1, "COV"),
+        (2, "OCC1"),
+        (3, "OCC2"),
+        (4, "OCD1"),
+        (5, "OCD2"),
+        (6, "AOLD"),
+        (7, "AOLDL"),
+        (8, "ASCC"),
+        (9, "ASCCL"),
+        (10, "ASCD"),
+        (11, "ASCDL"),
+        (12, "OTC"),
+        (13, "OTD"),
+        (14, "CUVC"),
+        (16, "OTF"),
+        (18, "PTO"),
+        (19, "PTOS"),
+        (20, "CTO"),
+        (21, "CTOS"),
+        (22, "OC"),
+        (23, "CHGC"),
+        (24, "CHGV"),
+        (26, "UTC"),
+        (27, "UTD"),
+    ];
+
+    Ok(Decoded { value: flags(val, 8, &bits), alert: val != 0 })
+}
+
+fn decode_pf_status(buf: &[u8]) -> Result<Decoded> {
+    let val = le32(buf)?;
+
+    let bits = [
+        (0, "SUV"),
+        (1, "SOV"),
+        (2, "SOCC"),
+        (3, "SOCD"),
+        (4, "SOT"),
+        (6, "SOTF"),
+        (7, "QIM"),
+        (8, "CB"),
+        (9, "IMP"),
+        (10, "CD"),
+        (11, "VIMR"),
+        (12, "VIMA"),
+        (15, "CFETF"),
+        (16, "DFETF"),
+        (18, "FUSE"),
+        (19, "AFER"),
+        (20, "AFEC"),
+        (21, "2LVL"),
+        (22, "PTC"),
+        (23, "IFC"),
+        (24, "OPNCELL"),
+        (25, "DFW"),
+        (27, "TS1"),
+        (28, "TS2"),
+        (29, "TS3"),
+        (30, "TS4"),
+    ];
+
+    Ok(Decoded { value: flags(val, 8, &bits), alert: val != 0 })
+}
+
+fn decode_vcell(buf: &[u8]) -> Result<Decoded> {
+    // 78.125 uV per LSB
+    let val = word(buf, Read::WordBE)? as f32;
+    Ok(Decoded::new(format!("{:.3} V", val * 78.125 / 1_000_000.0)))
+}
+
+fn decode_soc(buf: &[u8]) -> Result<Decoded> {
+    // 1/256 percent per LSB
+    let pct = word(buf, Read::WordBE)? as f32 / 256.0;
+    Ok(Decoded { value: format!("{:.2}%", pct), alert: pct <= 10.0 })
+}
+
+fn decode_crate(buf: &[u8]) -> Result<Decoded> {
+    // 0.208 percent per hour per LSB
+    let val = word(buf, Read::WordBE)? as i16 as f32;
+    Ok(Decoded::new(format!("{:.3}%/hr", val * 0.208)))
+}
+
+fn decode_version(buf: &[u8]) -> Result<Decoded> {
+    Ok(Decoded::new(format!("0x{:04x}", word(buf, Read::WordBE)?)))
+}
+
+fn decode_max_status(buf: &[u8]) -> Result<Decoded> {
+    let val = word(buf, Read::WordBE)?;
+
+    let bits = [
+        (8, "RI"),
+        (9, "VH"),
+        (10, "VL"),
+        (11, "VR"),
+        (12, "HD"),
+        (13, "SC"),
+        (14, "ENVR"),
+    ];
+
+    Ok(Decoded { value: flags(val as u32, 4, &bits), alert: val & 0x1600 != 0 })
+}
+
+static SBS_FIELDS: [Field; 17] = [
+    Field {
+        name: "manufacturer",
+        register: 0x20,
+        read: Read::Block,
+        decode: decode_string,
+    },
+    Field {
+        name: "device",
+        register: 0x21,
+        read: Read::Block,
+        decode: decode_string,
+    },
+    Field {
+        name: "chemistry",
+        register: 0x22,
+        read: Read::Block,
+        decode: decode_string,
+    },
+    Field {
+        name: "serial",
+        register: 0x1c,
+        read: Read::Word,
+        decode: decode_hex,
+    },
+    Field {
+        name: "voltage",
+        register: 0x09,
+        read: Read::Word,
+        decode: decode_millivolts,
+    },
+    Field {
+        name: "current",
+        register: 0x0a,
+        read: Read::Word,
+        decode: decode_milliamps,
+    },
+    Field {
+        name: "average current",
+        register: 0x0b,
+        read: Read::Word,
+        decode: decode_milliamps,
+    },
+    Field {
+        name: "temperature",
+        register: 0x08,
+        read: Read::Word,
+        decode: decode_decikelvin,
+    },
+    Field {
+        name: "relative charge",
+        register: 0x0d,
+        read: Read::Word,
+        decode: decode_percent,
+    },
+    Field {
+        name: "absolute charge",
+        register: 0x0e,
+        read: Read::Word,
+        decode: decode_percent,
+    },
+    Field {
+        name: "remaining capacity",
+        register: 0x0f,
+        read: Read::Word,
+        decode: decode_capacity,
+    },
+    Field {
+        name: "full capacity",
+        register: 0x10,
+        read: Read::Word,
+        decode: decode_capacity,
+    },
+    Field {
+        name: "design capacity",
+        register: 0x18,
+        read: Read::Word,
+        decode: decode_capacity,
+    },
+    Field {
+        name: "cycle count",
+        register: 0x17,
+        read: Read::Word,
+        decode: decode_count,
+    },
+    Field {
+        name: "time to empty",
+        register: 0x11,
+        read: Read::Word,
+        decode: decode_minutes,
+    },
+    Field {
+        name: "time to full",
+        register: 0x13,
+        read: Read::Word,
+        decode: decode_minutes,
+    },
+    Field {
+        name: "status",
+        register: 0x16,
+        read: Read::Word,
+        decode: decode_battery_status,
+    },
+];
+
+static BQ40Z50_FIELDS: [Field; 3] = [
+    Field {
+        name: "health",
+        register: 0x4f,
+        read: Read::Word,
+        decode: decode_percent,
+    },
+    Field {
+        name: "safety status",
+        register: 0x51,
+        read: Read::Block,
+        decode: decode_safety_status,
+    },
+    Field {
+        name: "PF status",
+        register: 0x53,
+        read: Read::Block,
+        decode: decode_pf_status,
+    },
+];
+
+static MAX17048_FIELDS: [Field; 5] = [
+    Field {
+        name: "version",
+        register: 0x08,
+        read: Read::WordBE,
+        decode: decode_version,
+    },
+    Field {
+        name: "voltage",
+        register: 0x02,
+        read: Read::WordBE,
+        decode: decode_vcell,
+    },
+    Field {
+        name: "charge",
+        register: 0x04,
+        read: Read::WordBE,
+        decode: decode_soc,
+    },
+    Field {
+        name: "charge rate",
+        register: 0x16,
+        read: Read::WordBE,
+        decode: decode_crate,
+    },
+    Field {
+        name: "status",
+        register: 0x1a,
+        read: Read::WordBE,
+        decode: decode_max_status,
+    },
+];
+
+/// A gauge to be queried, along with any manifest information about it.
+struct Battery<'a> {
+    hargs: I2cArgs<'a>,
+    gauge: Gauge,
+    device: Option<&'a HubrisI2cDevice>,
+}
+
+impl std::fmt::Display for Battery<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.device {
+            Some(d) => {
+                write!(f, "{}", d.device)?;
+
+                if let Some(name) = &d.name {
+                    write!(f, " ({})", name)?;
+                }
+            }
+            None => write!(f, "{:?}", self.gauge)?,
+        }
+
+        write!(f, " on {}", self.hargs)
+    }
+}
+
+fn batteries<'a>(
+    hubris: &'a HubrisArchive,
+    subargs: &BatteryArgs,
+) -> Result<Vec<Battery<'a>>> {
+    //
+    // If we have been given a bus or controller, we are being pointed at a
+    // specific device, which may or may not be in the manifest.
+    //
+    if subargs.bus.is_some() || subargs.controller.is_some() {
+        let hargs = I2cArgs::parse(
+            hubris,
+            &subargs.bus,
+            subargs.controller,
+            &subargs.port,
+            &subargs.mux,
+            &subargs.device,
+        )?;
+
+        if hargs.address.is_none() {
+            bail!("must specify the address of the gauge via --device");
+        }
+
+        let device = hubris
+            .manifest
+            .i2c_devices
+            .iter()
+            .find(|d| hargs.matches_device(d));
+
+        let gauge = match (subargs.gauge, device) {
+            (Some(gauge), _) => gauge,
+            (None, Some(d)) => match Gauge::from_device(&d.device) {
+                Some(gauge) => gauge,
+                None => {
+                    bail!(
+                        "{} is not a known gas gauge; specify the kind of \
+                        gauge via --gauge",
+                        d.device
+                    );
+                }
+            },
+            (None, None) => {
+                bail!("device is not in the manifest; must specify --gauge");
+            }
+        };
+
+        return Ok(vec![Battery { hargs, gauge, device }]);
+    }
+
+    let mut rval = vec![];
+
+    for device in &hubris.manifest.i2c_devices {
+        let gauge = match (subargs.gauge, Gauge::from_device(&device.device)) {
+            (_, None) => continue,
+            (Some(gauge), Some(_)) => gauge,
+            (None, Some(gauge)) => gauge,
+        };
+
+        if let Some(ref d) = subargs.device {
+            if device.device != *d && device.name.as_ref() != Some(d) {
+                continue;
+            }
+        }
+
+        rval.push(Battery {
+            hargs: I2cArgs::from_device(device),
+            gauge,
+            device: Some(device),
+        });
+    }
+
+    if rval.is_empty() {
+        match &subargs.device {
+            Some(d) => bail!("no gas gauge matches {}", d),
+            None => bail!("no gas gauges found in archive"),
+        }
+    }
+
+    Ok(rval)
+}
+
+fn battery_query(
+    context: &mut HiffyContext,
+    core: &mut dyn Core,
+    battery: &Battery,
+) -> Result<()> {
+    let i2c_read = context.get_function("I2cRead", 7)?;
+    let hargs = &battery.hargs;
+    let fields = battery.gauge.fields();
+
+    let mut ops = vec![Op::Push(hargs.controller), Op::Push(hargs.port.index)];
+
+    if let Some(mux) = hargs.mux {
+        ops.push(Op::Push(mux.0));
+        ops.push(Op::Push(mux.1));
+    } else {
+        ops.push(Op::PushNone);
+        ops.push(Op::PushNone);
+    }
+
+    ops.push(Op::Push(hargs.address.unwrap()));
+
+    for field in &fields {
+        ops.push(Op::Push(field.register));
+
+        match field.read {
+            Read::Word | Read::WordBE => ops.push(Op::Push(2)),
+            Read::Block => ops.push(Op::PushNone),
+        }
+
+        ops.push(Op::Call(i2c_read.id));
+        ops.push(Op::DropN(2));
+    }
+
+    ops.push(Op::Done);
+
+    let retry = I2cRetryPolicy::default();
+    let results = retry.run(context, core, &ops, None, &i2c_read)?;
+
+    println!("{} {}", "BATTERY".bold(), battery);
+
+    if let Some(d) = battery.device {
+        println!("  {:<20} {}", "description", d.description);
+    }
+
+    for (field, result) in fields.iter().zip(results.iter()) {
+        let value = match result {
+            Ok(buf) => match (field.decode)(buf) {
+                Ok(d) if d.alert => d.value.red().to_string(),
+                Ok(d) => d.value,
+                Err(err) => format!("<{}>", err).dimmed().to_string(),
+            },
+            Err(code) => {
+                format!("Err({})", i2c_read.strerror(*code)).red().to_string()
+            }
+        };
+
+        println!("  {:<20} {}", field.name, value);
+    }
+
+    Ok(())
+}
+
+fn battery(context: &mut ExecutionContext) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+    let subargs = BatteryArgs::try_parse_from(subargs)?;
+
+    let batteries = batteries(hubris, &subargs)?;
+
+    if subargs.list {
+        println!(
+            "{:<10} {:<12} {:<24} DESCRIPTION",
+            "DEVICE", "NAME", "LOCATION"
+        );
+
+        for battery in &batteries {
+            let d = battery.device.unwrap();
+            let location = battery.hargs.to_string();
+
+            println!(
+                "{:<10} {:<12} {:<24} {}",
+                d.device,
+                d.name.as_deref().unwrap_or("-"),
+                location,
+                d.description
+            );
+        }
+
+        return Ok(());
+    }
+
+    let core = &mut **context.core.as_mut().unwrap();
+    let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
+
+    for (ndx, battery) in batteries.iter().enumerate() {
+        if ndx > 0 {
+            println!();
+        }
+
+        battery_query(&mut context, core, battery)?;
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: BatteryArgs::command(),
+        name: "battery",
+        run: battery,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -0,0 +1,362 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility bench`
+//!
+//! `humility bench` measures the performance of the debug transport between
+//! Humility and the attached target:  the latency and throughput of
+//! individual word reads and writes, the throughput of bulk reads and writes
+//! of varying sizes, and the round-trip latency of executing a (trivial) HIF
+//! program via the `hiffy` task:
+//!
+//! ```console
+//! $ humility bench
+//! humility: attached via ST-Link V3
+//! OPERATION           COUNT    BYTES    MEAN     MIN     MAX   THROUGHPUT
+//! word read            1000   3.9KiB   142us   131us   402us   27.5KiB/s
+//! word write           1000   3.9KiB   139us   128us   377us   28.1KiB/s
+//! bulk read 256         256  64.0KiB   368us   351us   612us  679.3KiB/s
+//! bulk read 1024         64  64.0KiB  1133us  1101us  1410us  882.5KiB/s
+//! bulk read 4096         16  64.0KiB  4290us  4254us  4398us  932.4KiB/s
+//! bulk write 256        256  64.0KiB   402us   389us   655us  621.9KiB/s
+//! bulk write 1024        64  64.0KiB  1274us  1245us  1502us  784.8KiB/s
+//! hiffy round-trip      100        -  2317us  2204us  3109us            -
+//! ```
+//!
+//! Word operations are performed on a kernel variable, and bulk reads are of
+//! kernel text; the number of word operations can be specified with
+//! `--count` (`-c`), the sizes of bulk operations with `--size` (`-s`), and
+//! the number of HIF programs to execute with `--runs` (`-r`).  Bulk sizes
+//! are limited by the size of the memory being read or written, and
+//! operations are repeated such that at least 64KiB is transferred for each
+//! size.  All writes are of the value that was just read, and are performed
+//! with the target halted; the target is allowed to run once writes are
+//! complete.
+//!
+//! To understand the impact of the debug transport on a particular
+//! operation, any command can be run with `--verbose` (`-v`), which will
+//! report the throughput of the reads and writes that the command made of
+//! the target (and, in the case of `humility flash`, of the flashing
+//! itself).
+//!
+
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use hif::*;
+use humility::core::Core;
+use humility::hubris::*;
+use humility::msg;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_hiffy::*;
+use std::time::{Duration, Instant};
+
+#[derive(Parser, Debug)]
+#[clap(name = "bench", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct BenchArgs {
+    /// sets timeout
+    #[clap(
+        long, short = 'T', default_value_t = 5000, value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+
+    /// number of word reads and writes to perform
+    #[clap(
+        long, short, default_value_t = 1000, value_name = "count",
+        parse(try_from_str = parse_int::parse)
+    )]
+    count: u32,
+
+    /// sizes of bulk reads and writes
+    #[clap(
+        long, short, value_name = "bytes", use_value_delimiter = true,
+        default_value = "256,1024,4096",
+        parse(try_from_str = parse_int::parse)
+    )]
+    size: Vec<u32>,
+
+    /// number of HIF programs to execute
+    #[clap(
+        long, short, default_value_t = 100, value_name = "runs",
+        parse(try_from_str = parse_int::parse)
+    )]
+    runs: u32,
+}
+
+///
+/// The minimum number of bytes to transfer for each bulk operation size.
+///
+const BULK_BYTES: u32 = 64 * 1024;
+
+///
+/// The results of a single benchmark:  the number of operations performed,
+/// the number of bytes transferred (if any) and the duration of each
+/// operation.
+///
+struct Measurement {
+    name: String,
+    nbytes: Option<u64>,
+    times: Vec<Duration>,
+}
+
+impl Measurement {
+    fn new(name: &str, nbytes: Option<u64>) -> Self {
+        Self { name: name.to_string(), nbytes, times: vec![] }
+    }
+
+    fn time<T>(&mut self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let started = Instant::now();
+        let rval = f()?;
+        self.times.push(started.elapsed());
+        Ok(rval)
+    }
+
+    fn print(&self) {
+        let usecs = |d: Duration| format!("{}us", d.as_micros());
+        let total = self.times.iter().sum::<Duration>();
+        let count = self.times.len() as u32;
+
+        let (mean, min, max) = if count == 0 {
+            ("-".to_string(), "-".to_string(), "-".to_string())
+        } else {
+            (
+                usecs(total / count),
+                usecs(*self.times.iter().min().unwrap()),
+                usecs(*self.times.iter().max().unwrap()),
+            )
+        };
+
+        let (bytes, rate) = match self.nbytes {
+            Some(n) => {
+                let n = n * count as u64;
+
+                let bytes = match n {
+                    n if n < 1024 => format!("{n}"),
+                    n if n < 1024 * 1024 => {
+                        format!("{:.1}KiB", n as f64 / 1024.0)
+                    }
+                    n => format!("{:.1}MiB", n as f64 / (1024.0 * 1024.0)),
+                };
+
+                (bytes, humility::stats::rate(n, total))
+            }
+            None => ("-".to_string(), "-".to_string()),
+        };
+
+        println!(
+            "{:17} {:>7} {:>8} {:>7} {:>7} {:>7} {:>12}",
+            self.name, count, bytes, mean, min, max, rate
+        );
+    }
+}
+
+///
+/// Returns the kernel region that matches the specified predicate, preferring
+/// the largest.
+///
+fn bench_region(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    pred: impl Fn(&HubrisRegion) -> bool,
+) -> Result<Option<HubrisRegion>> {
+    core.op_start()?;
+    let regions = hubris.regions(core);
+    core.op_done()?;
+
+    Ok(regions?
+        .into_values()
+        .filter(|r| r.tasks.contains(&HubrisTask::Kernel) && pred(r))
+        .max_by_key(|r| r.mapsize))
+}
+
+fn bench_words(
+    core: &mut dyn Core,
+    addr: u32,
+    count: u32,
+) -> Result<Vec<Measurement>> {
+    let mut read = Measurement::new("word read", Some(4));
+    let mut write = Measurement::new("word write", Some(4));
+
+    core.op_start()?;
+
+    for _ in 0..count {
+        read.time(|| core.read_word_32(addr))?;
+    }
+
+    core.op_done()?;
+
+    core.halt()?;
+
+    let rval: Result<()> = (|| {
+        let val = core.read_word_32(addr)?;
+
+        for _ in 0..count {
+            write.time(|| core.write_word_32(addr, val))?;
+        }
+
+        Ok(())
+    })();
+
+    core.run()?;
+    rval?;
+
+    Ok(vec![read, write])
+}
+
+fn bench_bulk_read(
+    core: &mut dyn Core,
+    region: &HubrisRegion,
+    sizes: &[u32],
+) -> Result<Vec<Measurement>> {
+    let mut rval = vec![];
+
+    core.op_start()?;
+
+    for &size in sizes.iter().filter(|&&s| s <= region.mapsize) {
+        let mut m =
+            Measurement::new(&format!("bulk read {size}"), Some(size as u64));
+        let mut buf = vec![0u8; size as usize];
+
+        for _ in 0..std::cmp::max(BULK_BYTES / size, 1) {
+            m.time(|| core.read_8(region.base, &mut buf))?;
+        }
+
+        rval.push(m);
+    }
+
+    core.op_done()?;
+
+    Ok(rval)
+}
+
+fn bench_bulk_write(
+    core: &mut dyn Core,
+    region: &HubrisRegion,
+    sizes: &[u32],
+) -> Result<Vec<Measurement>> {
+    let mut rval = vec![];
+
+    core.halt()?;
+
+    let result: Result<()> = (|| {
+        for &size in sizes.iter().filter(|&&s| s <= region.size) {
+            let mut m = Measurement::new(
+                &format!("bulk write {size}"),
+                Some(size as u64),
+            );
+            let mut buf = vec![0u8; size as usize];
+            core.read_8(region.base, &mut buf)?;
+
+            for _ in 0..std::cmp::max(BULK_BYTES / size, 1) {
+                m.time(|| core.write_8(region.base, &buf))?;
+            }
+
+            rval.push(m);
+        }
+
+        Ok(())
+    })();
+
+    core.run()?;
+    result?;
+
+    Ok(rval)
+}
+
+fn bench_hiffy(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    timeout: u32,
+    runs: u32,
+) -> Result<Measurement> {
+    let mut context = HiffyContext::new(hubris, core, timeout)?;
+    let mut m = Measurement::new("hiffy round-trip", None);
+    let ops = [Op::Done];
+
+    for _ in 0..runs {
+        m.time(|| {
+            context.start(core, &ops, None)?;
+
+            let started = Instant::now();
+
+            while !context.done(core)? {
+                if started.elapsed() > Duration::from_millis(timeout as u64) {
+                    bail!("HIF execution timed out");
+                }
+            }
+
+            context.results(core)
+        })?;
+    }
+
+    Ok(m)
+}
+
+fn bench(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = BenchArgs::try_parse_from(subargs)?;
+
+    if subargs.size.iter().any(|&s| s == 0) {
+        bail!("bulk sizes must be non-zero");
+    }
+
+    let mut results = vec![];
+
+    if !core.is_net() {
+        let addr = hubris
+            .lookup_symword("CURRENT_TASK_PTR")
+            .context("failed to find kernel variable for word operations")?;
+
+        results.extend(bench_words(core, addr, subargs.count)?);
+
+        match bench_region(hubris, core, |r| !r.attr.write)? {
+            Some(region) => {
+                results.extend(bench_bulk_read(core, &region, &subargs.size)?)
+            }
+            None => msg!("no kernel text found; skipping bulk reads"),
+        }
+
+        match bench_region(hubris, core, |r| r.attr.write && !r.attr.device)? {
+            Some(region) => {
+                results.extend(bench_bulk_write(core, &region, &subargs.size)?)
+            }
+            None => msg!("no kernel RAM found; skipping bulk writes"),
+        }
+    } else {
+        msg!("attached via network; skipping reads and writes");
+    }
+
+    match bench_hiffy(hubris, core, subargs.timeout, subargs.runs) {
+        Ok(m) => results.push(m),
+        Err(err) => msg!("skipping HIF execution: {err:?}"),
+    }
+
+    println!(
+        "{:17} {:>7} {:>8} {:>7} {:>7} {:>7} {:>12}",
+        "OPERATION", "COUNT", "BYTES", "MEAN", "MIN", "MAX", "THROUGHPUT"
+    );
+
+    for m in &results {
+        m.print();
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: BenchArgs::command(),
+        name: "bench",
+        run: bench,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -0,0 +1,405 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility caboose`
+//!
+//! The caboose is a region at the end of a Hubris image that contains
+//! metadata about the image (e.g., its version, the git commit from which
+//! it was built and the board for which it was built), stored as TLV-C
+//! records.  It begins with a magic number and ends with a word containing
+//! its total size.
+//!
+//! `humility caboose read` displays the records in the caboose, reading it
+//! from an attached target, a dump or (if neither is present) the archive
+//! itself:
+//!
+//! ```console
+//! $ humility caboose read
+//! humility: attached via ST-Link V3
+//! humility: caboose at 0x080fff00 (256 bytes)
+//! GITC => 753a57169eba699e73ee59e0cf5345eb1d6e1ae2
+//! BORD => gimlet-c
+//! NAME => gimlet-c-image-default
+//! VERS => 1.0.3
+//! ```
+//!
+//! Values that are not printable strings are displayed as bytes.
+//!
+//! `humility caboose write` writes records into the caboose of the archive's
+//! image, writing a new archive that can then be flashed.  Records are
+//! specified with `--set` (`-s`) as `KEY=VALUE`, where `KEY` is a four
+//! character tag; records that already exist in the caboose are replaced:
+//!
+//! ```console
+//! $ humility -a build.zip caboose write -s VERS=1.0.4 -s SERL=BRM42 -o new.zip
+//! humility: caboose at 0x080fff00 (256 bytes)
+//! humility: wrote caboose with 5 records to new.zip
+//! ```
+//!
+//! The caboose is updated in both `img/final.bin` and `img/final.elf`; other
+//! representations of the image (e.g., `img/final.ihex`) are not updated,
+//! and are therefore omitted from the new archive.
+//!
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::msg;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use std::io::{Cursor, Write};
+
+#[derive(Parser, Debug)]
+enum CabooseCommand {
+    /// Display the records in the caboose
+    Read,
+    /// Write records into the caboose of the archive's image
+    Write {
+        /// record to write, as KEY=VALUE
+        #[clap(
+            long,
+            short,
+            value_name = "KEY=VALUE",
+            multiple_occurrences = true,
+            required = true
+        )]
+        set: Vec<String>,
+
+        /// archive to write
+        #[clap(long, short, value_name = "archive")]
+        output: String,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[clap(name = "caboose", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct CabooseArgs {
+    #[clap(subcommand)]
+    cmd: CabooseCommand,
+}
+
+const CABOOSE_MAGIC: u32 = 0xcab0005e;
+
+///
+/// A parsed caboose: its location, its size (including the magic and the
+/// size words) and its records.
+///
+struct Caboose {
+    addr: u32,
+    size: u32,
+    records: Vec<([u8; 4], Vec<u8>)>,
+}
+
+///
+/// Returns the extent of the image as a base address and a length, as
+/// determined by the loadable program headers in the final ELF object.
+///
+fn image_extent(elf: &goblin::elf::Elf) -> Result<(u32, u32)> {
+    use goblin::elf::program_header::PT_LOAD;
+
+    let loaded = elf
+        .program_headers
+        .iter()
+        .filter(|h| h.p_type == PT_LOAD && h.p_filesz > 0);
+
+    let base = loaded.clone().map(|h| h.p_paddr).min();
+    let end = loaded.map(|h| h.p_paddr + h.p_filesz).max();
+
+    match (base, end) {
+        (Some(base), Some(end)) => Ok((base as u32, (end - base) as u32)),
+        _ => bail!("final.elf does not contain an image"),
+    }
+}
+
+fn final_elf(hubris: &HubrisArchive) -> Result<Vec<u8>> {
+    hubris
+        .read_file("img/final.elf")?
+        .ok_or_else(|| anyhow!("could not find final.elf in archive"))
+}
+
+///
+/// Locates the caboose, given a function to read the image.
+///
+fn caboose_find(
+    hubris: &HubrisArchive,
+    mut read: impl FnMut(u32, &mut [u8]) -> Result<()>,
+) -> Result<(u32, u32)> {
+    let contents = final_elf(hubris)?;
+    let elf = goblin::elf::Elf::parse(&contents)
+        .map_err(|e| anyhow!("failed to parse final.elf: {e}"))?;
+
+    let (base, len) = image_extent(&elf)?;
+    let end = base + len;
+
+    let mut word = [0u8; 4];
+    read(end - 4, &mut word)?;
+    let size = u32::from_le_bytes(word);
+
+    if size < 8 || size > len {
+        bail!("image does not appear to have a caboose (size is 0x{size:x})");
+    }
+
+    let addr = end - size;
+    read(addr, &mut word)?;
+
+    if u32::from_le_bytes(word) != CABOOSE_MAGIC {
+        bail!(
+            "bad caboose magic at 0x{addr:08x}: found 0x{:08x}, \
+            expected 0x{CABOOSE_MAGIC:08x}",
+            u32::from_le_bytes(word)
+        );
+    }
+
+    Ok((addr, size))
+}
+
+fn caboose_records(data: &[u8]) -> Result<Vec<([u8; 4], Vec<u8>)>> {
+    //
+    // The records are followed by erased flash; trim it (rounding up to
+    // a word, as TLV-C chunks are word-aligned) before we parse them.
+    //
+    let len = data.iter().rposition(|&b| b != 0xff).map_or(0, |p| p + 1);
+    let data = &data[..(len + 3) & !3];
+
+    let mut reader = tlvc::TlvcReader::begin(data)
+        .map_err(|e| anyhow!("failed to read caboose: {e:?}"))?;
+
+    let mut records = vec![];
+
+    loop {
+        match reader.next() {
+            Ok(Some(chunk)) => {
+                let mut body = vec![0u8; chunk.len() as usize];
+
+                chunk
+                    .read_exact(0, &mut body)
+                    .map_err(|e| anyhow!("failed to read record: {e:?}"))?;
+
+                records.push((chunk.header().tag, body));
+            }
+            Ok(None) => break,
+            Err(e) => bail!("malformed caboose: {e:?}"),
+        }
+    }
+
+    Ok(records)
+}
+
+fn caboose_read(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+) -> Result<Caboose> {
+    let (addr, size) =
+        caboose_find(hubris, |addr, buf| core.read_8(addr, buf))?;
+
+    let mut data = vec![0u8; (size - 8) as usize];
+
+    for (i, chunk) in data.chunks_mut(1024).enumerate() {
+        core.read_8(addr + 4 + (i * 1024) as u32, chunk)?;
+    }
+
+    Ok(Caboose { addr, size, records: caboose_records(&data)? })
+}
+
+fn caboose_print(caboose: &Caboose) {
+    msg!("caboose at 0x{:08x} ({} bytes)", caboose.addr, caboose.size);
+
+    for (tag, value) in &caboose.records {
+        let tag = String::from_utf8_lossy(tag);
+
+        match std::str::from_utf8(value) {
+            Ok(s) if s.chars().all(|c| !c.is_control()) => {
+                println!("{tag} => {s}");
+            }
+            _ => println!("{tag} => {value:x?}"),
+        }
+    }
+}
+
+///
+/// Packs records into the body of a caboose.  We express them in the text
+/// form of TLV-C and let `tlvc_text` do the packing.
+///
+fn caboose_pack(records: &[([u8; 4], Vec<u8>)]) -> Result<Vec<u8>> {
+    let mut text = String::from("[");
+
+    for (tag, value) in records {
+        let tag = std::str::from_utf8(tag)?;
+
+        match std::str::from_utf8(value) {
+            Ok(s) => text.push_str(&format!("({tag:?}, [{s:?}]),")),
+            Err(_) => text.push_str(&format!("({tag:?}, [{value:?}]),")),
+        }
+    }
+
+    text.push(']');
+
+    let pieces = tlvc_text::load(text.as_bytes())
+        .map_err(|e| anyhow!("failed to encode caboose: {e:?}"))?;
+
+    Ok(tlvc_text::pack(&pieces))
+}
+
+fn caboose_write(
+    hubris: &HubrisArchive,
+    set: &[String],
+    output: &str,
+) -> Result<()> {
+    let mut bin = hubris
+        .read_file("img/final.bin")?
+        .ok_or_else(|| anyhow!("could not find final.bin in archive"))?;
+    let mut elf = final_elf(hubris)?;
+
+    let (base, _) = image_extent(
+        &goblin::elf::Elf::parse(&elf)
+            .map_err(|e| anyhow!("failed to parse final.elf: {e}"))?,
+    )?;
+
+    //
+    // We read the existing caboose out of final.bin, which is the image
+    // laid out starting at its base address.
+    //
+    let read = |addr: u32, buf: &mut [u8]| {
+        let offset = (addr - base) as usize;
+
+        match bin.get(offset..offset + buf.len()) {
+            Some(data) => {
+                buf.copy_from_slice(data);
+                Ok(())
+            }
+            None => Err(anyhow!("0x{addr:08x} is not in final.bin")),
+        }
+    };
+
+    let (addr, size) = caboose_find(hubris, read)?;
+    let offset = (addr - base) as usize;
+    let body = &bin[offset + 4..offset + size as usize - 4];
+    let mut records = caboose_records(body)?;
+
+    msg!("caboose at 0x{addr:08x} ({size} bytes)");
+
+    for s in set {
+        let (key, value) = match s.split_once('=') {
+            Some((key, value)) if key.len() == 4 && key.is_ascii() => {
+                (key.as_bytes().try_into().unwrap(), value.as_bytes())
+            }
+            _ => bail!(
+                "\"{s}\" must be of the form KEY=VALUE, with a \
+                four character key"
+            ),
+        };
+
+        match records.iter_mut().find(|(tag, _)| *tag == key) {
+            Some(record) => record.1 = value.to_vec(),
+            None => records.push((key, value.to_vec())),
+        }
+    }
+
+    let packed = caboose_pack(&records)?;
+
+    if packed.len() + 8 > size as usize {
+        bail!(
+            "records require {} bytes, but caboose can only hold {}",
+            packed.len(),
+            size - 8
+        );
+    }
+
+    let mut caboose = vec![0xffu8; size as usize];
+    caboose[..4].copy_from_slice(&CABOOSE_MAGIC.to_le_bytes());
+    caboose[4..4 + packed.len()].copy_from_slice(&packed);
+    caboose[size as usize - 4..].copy_from_slice(&size.to_le_bytes());
+
+    bin[offset..offset + size as usize].copy_from_slice(&caboose);
+
+    //
+    // Now find the caboose in final.elf by way of the program header that
+    // contains it.
+    //
+    let foffset = {
+        let parsed = goblin::elf::Elf::parse(&elf)
+            .map_err(|e| anyhow!("failed to parse final.elf: {e}"))?;
+
+        parsed
+            .program_headers
+            .iter()
+            .find(|h| {
+                let paddr = h.p_paddr as u32;
+                addr >= paddr && addr + size <= paddr + h.p_filesz as u32
+            })
+            .map(|h| (h.p_offset as u32 + (addr - h.p_paddr as u32)) as usize)
+            .ok_or_else(|| anyhow!("caboose is not in final.elf"))?
+    };
+
+    elf[foffset..foffset + size as usize].copy_from_slice(&caboose);
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(hubris.archive()))?;
+    let file = std::fs::File::create(output)
+        .with_context(|| format!("failed to create {output}"))?;
+    let mut zip = zip::ZipWriter::new(file);
+
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        let name = entry.name().to_string();
+
+        let contents = match name.as_str() {
+            "img/final.bin" => &bin,
+            "img/final.elf" => &elf,
+            n if n.starts_with("img/final.") => {
+                msg!("omitting {n}, which has not been updated");
+                continue;
+            }
+            _ => {
+                zip.raw_copy_file(entry)?;
+                continue;
+            }
+        };
+
+        let options = zip::write::FileOptions::default()
+            .compression_method(entry.compression());
+
+        zip.start_file(&name, options)?;
+        zip.write_all(contents)?;
+    }
+
+    zip.finish()?;
+
+    msg!("wrote caboose with {} records to {output}", records.len());
+
+    Ok(())
+}
+
+fn caboose(context: &mut ExecutionContext) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = CabooseArgs::try_parse_from(subargs)?;
+
+    match subargs.cmd {
+        CabooseCommand::Read => humility_cmd::attach(
+            context,
+            Attach::Any,
+            Validate::None,
+            |context| {
+                let hubris = context.archive.as_ref().unwrap();
+                let core = &mut **context.core.as_mut().unwrap();
+
+                caboose_print(&caboose_read(hubris, core)?);
+                Ok(())
+            },
+        ),
+        CabooseCommand::Write { set, output } => {
+            let hubris = context.archive.as_ref().unwrap();
+            caboose_write(hubris, &set, &output)
+        }
+    }
+}
+
+pub fn init() -> Command {
+    Command {
+        app: CabooseArgs::command(),
+        name: "caboose",
+        run: caboose,
+        kind: CommandKind::Unattached { archive: Archive::Required },
+    }
+}
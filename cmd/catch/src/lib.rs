@@ -0,0 +1,368 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility catch`
+//!
+//! `humility catch` configures vector catch in the Debug Exception and
+//! Monitor Control Register (DEMCR) and then allows the target to run.  When
+//! a caught exception is taken, the core halts at the entry to its handler
+//! -- that is, before the kernel has had any opportunity to handle the
+//! fault, and with the faulting context intact.  The fault status registers
+//! are then decoded, and the faulting task is identified, along with its
+//! registers and its stack at the point of the fault:
+//!
+//! ```console
+//! $ humility catch
+//! humility: attached via ST-Link V3
+//! humility: catching hard, mem, bus, usage (^C to stop)
+//! humility: caught bus fault in task pong
+//! SCB: CFSR = 0x00008200, HFSR = 0x00000000
+//!      bus fault: BFSR { bfarvalid: true, lsperr: false, stkerr: false, unstkerr: false, impreciserr: false, preciserr: true, ibuserr: false } at 0x00000004
+//!    R0 = 0x00000000    R1 = 0x00000003    R2 = 0x00000000    R3 = 0x0802a5d5
+//!    R4 = 0x00000000    R5 = 0x00000000    R6 = 0x00000001    R7 = 0x20006ea8
+//!    R8 = 0x00000000    R9 = 0x00000000   R10 = 0x00000000   R11 = 0x00000000
+//!   R12 = 0x00000000    SP = 0x20006ea0    LR = 0x0802a5c9    PC = 0x0802a5d6
+//!   PSR = 0x61000000
+//!       |
+//!       +--->  0x20006ea0 0x0802a5d6 task_pong::main
+//! humility: leaving target halted at fault entry
+//! ```
+//!
+//! The exceptions to catch are specified with `--vector` (`-v`) as a
+//! comma-separated list of `hard` (hard fault), `mem` (memory management
+//! fault), `bus` (bus fault), `usage` (usage fault), `int` (faults during
+//! exception entry or return), and `reset`; by default, hard, memory
+//! management, bus and usage faults are caught.  Note that a configurable
+//! fault (memory management, bus or usage) that is not enabled in the
+//! System Handler Control and State Register escalates to a hard fault,
+//! and must therefore be caught as such.
+//!
+//! Once an exception has been caught, vector catch is disabled and the
+//! target is left halted so that it may be further examined (e.g., with
+//! `humility readmem` or `humility gdb`).  If the command is interrupted,
+//! vector catch is disabled and the target is left running.  To leave
+//! vector catch configured without waiting for an exception (e.g., to catch
+//! a fault that may take some time to manifest), use `--no-wait`; to
+//! subsequently disable it, use `--clear`.
+//!
+
+use anyhow::{bail, Result};
+use clap::{ArgEnum, CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::msg;
+use humility_arch_arm::ARMRegister;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_cortex::debug::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[derive(ArgEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum Vector {
+    Hard,
+    Mem,
+    Bus,
+    Usage,
+    Int,
+    Reset,
+}
+
+impl Vector {
+    ///
+    /// Returns the vector catch bits in DEMCR that correspond to this
+    /// exception.  A usage fault has several distinct causes, each with
+    /// its own bit.
+    ///
+    fn mask(&self) -> u32 {
+        match self {
+            Vector::Reset => 1 << 0,
+            Vector::Mem => 1 << 4,
+            Vector::Usage => (1 << 5) | (1 << 6) | (1 << 7),
+            Vector::Bus => 1 << 8,
+            Vector::Int => 1 << 9,
+            Vector::Hard => 1 << 10,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Vector::Hard => "hard",
+            Vector::Mem => "mem",
+            Vector::Bus => "bus",
+            Vector::Usage => "usage",
+            Vector::Int => "int",
+            Vector::Reset => "reset",
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[clap(name = "catch", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct CatchArgs {
+    /// exceptions to catch
+    #[clap(
+        long,
+        short,
+        arg_enum,
+        value_name = "vector",
+        use_value_delimiter = true,
+        default_value = "hard,mem,bus,usage"
+    )]
+    vector: Vec<Vector>,
+
+    /// configure vector catch and resume without waiting for an exception
+    #[clap(long, conflicts_with = "clear")]
+    no_wait: bool,
+
+    /// disable vector catch
+    #[clap(long)]
+    clear: bool,
+
+    /// show line number information with stack backtrace
+    #[clap(long, short)]
+    line: bool,
+}
+
+///
+/// Returns the name of the exception denoted by the specified exception
+/// number (as found in IPSR).
+///
+fn catch_exception(exception: u32) -> String {
+    match exception {
+        2 => "NMI".to_string(),
+        3 => "hard fault".to_string(),
+        4 => "memory management fault".to_string(),
+        5 => "bus fault".to_string(),
+        6 => "usage fault".to_string(),
+        7 => "secure fault".to_string(),
+        _ => format!("exception {exception}"),
+    }
+}
+
+///
+/// Returns the registers of the context that took the exception.  We are
+/// halted at the entry to the handler, so the callee-saved registers are
+/// still live, LR contains the EXC_RETURN value, and the remaining registers
+/// are in the exception frame that the processor has just stacked.
+///
+fn catch_regs(
+    core: &mut dyn Core,
+    exc_return: u32,
+) -> Result<BTreeMap<ARMRegister, u32>> {
+    let mut regs = BTreeMap::new();
+
+    let sp = if humility_arch_arm::exc_return_process_stack(exc_return) {
+        core.read_reg(ARMRegister::PSP)?
+    } else {
+        core.read_reg(ARMRegister::MSP)?
+    };
+
+    for r in [
+        ARMRegister::R4,
+        ARMRegister::R5,
+        ARMRegister::R6,
+        ARMRegister::R7,
+        ARMRegister::R8,
+        ARMRegister::R9,
+        ARMRegister::R10,
+        ARMRegister::R11,
+    ] {
+        regs.insert(r, core.read_reg(r)?);
+    }
+
+    let frame = humility_arch_arm::exception_frame_registers();
+
+    for (i, r) in frame.iter().enumerate() {
+        regs.insert(*r, core.read_word_32(sp + i as u32 * 4)?);
+    }
+
+    let sp = sp
+        + humility_arch_arm::exception_frame_size(exc_return)
+        + humility_arch_arm::exception_stack_realign(&regs);
+
+    regs.insert(ARMRegister::SP, sp);
+
+    Ok(regs)
+}
+
+fn catch_report(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &CatchArgs,
+) -> Result<()> {
+    let psr = core.read_reg(ARMRegister::PSR)?;
+    let exception = psr & 0x1ff;
+
+    //
+    // A reset is caught before the first instruction of the reset handler
+    // (and in thread mode), so there is no faulting context to speak of.
+    //
+    if exception <= 1 {
+        let pc = core.read_reg(ARMRegister::PC)?;
+
+        match hubris.instr_sym(pc) {
+            Some((sym, _)) => msg!("caught reset at 0x{pc:08x} ({sym})"),
+            None => msg!("caught reset at 0x{pc:08x}"),
+        }
+
+        return Ok(());
+    }
+
+    let exc_return = core.read_reg(ARMRegister::LR)?;
+
+    if !humility_arch_arm::is_exc_return(exc_return) {
+        bail!("LR (0x{exc_return:08x}) is not an EXC_RETURN value");
+    }
+
+    //
+    // If the exception frame was stacked on the process stack, we faulted
+    // in the current task; otherwise, we faulted in the kernel.
+    //
+    let task = if humility_arch_arm::exc_return_process_stack(exc_return) {
+        hubris.current_task(core)?.unwrap_or(HubrisTask::Kernel)
+    } else {
+        HubrisTask::Kernel
+    };
+
+    let name = match task {
+        HubrisTask::Kernel => "kernel".to_string(),
+        _ => match hubris.lookup_module(task) {
+            Ok(module) => format!("task {}", module.name),
+            Err(_) => format!("task {task}"),
+        },
+    };
+
+    msg!("caught {} in {}", catch_exception(exception), name);
+
+    print_scb(core)?;
+
+    let regs = catch_regs(core, exc_return)?;
+
+    humility_stack::print_registers(&regs);
+
+    let regions = hubris.regions(core)?;
+
+    let printer = humility_stack::StackPrinter {
+        indent: 6,
+        line: subargs.line,
+        ..Default::default()
+    };
+
+    printer.print_from_registers(hubris, core, &regions, task, &regs);
+
+    Ok(())
+}
+
+///
+/// Waits for the target to halt on a caught exception, returning false if
+/// we are interrupted first.
+///
+fn catch_wait(core: &mut dyn Core, done: &AtomicBool) -> Result<bool> {
+    loop {
+        if done.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+
+        if DHCSR::read(core)?.halted() {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    //
+    // The DFSR bits are write-one-to-clear; clear what we have seen.
+    //
+    let dfsr = DFSR::read(core)?;
+    dfsr.write(core)?;
+
+    if !dfsr.vector_catch() {
+        bail!(
+            "target halted for another reason (DFSR = 0x{:x})",
+            u32::from(dfsr)
+        );
+    }
+
+    Ok(true)
+}
+
+fn catch(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = CatchArgs::try_parse_from(subargs)?;
+
+    if subargs.clear {
+        core.vector_catch(0)?;
+        msg!("vector catch disabled");
+        return Ok(());
+    }
+
+    let mask = subargs.vector.iter().fold(0, |mask, v| mask | v.mask());
+
+    let names = subargs.vector.iter().map(Vector::name).collect::<Vec<_>>();
+
+    //
+    // Configure vector catch with the target halted, and clear any stale
+    // indication of a prior vector catch before letting it run.
+    //
+    core.halt()?;
+    core.vector_catch(mask)?;
+
+    let dfsr = DFSR::read(core)?;
+    dfsr.write(core)?;
+
+    if subargs.no_wait {
+        core.run()?;
+        msg!("catching {}; use --clear to disable", names.join(", "));
+        return Ok(());
+    }
+
+    let done = humility_cmd::interrupted()?;
+
+    msg!("catching {} (^C to stop)", names.join(", "));
+
+    core.run()?;
+
+    let caught = catch_wait(core, done);
+
+    //
+    // Regardless of how we got here, disable vector catch.  If we caught
+    // something, leave the target halted so it can be examined.
+    //
+    let cleared = core.vector_catch(0);
+
+    match caught {
+        Ok(true) => {
+            catch_report(hubris, core, &subargs)?;
+            msg!("leaving target halted at fault entry");
+        }
+        Ok(false) => {
+            msg!("interrupted; vector catch disabled");
+        }
+        Err(e) => {
+            core.run()?;
+            return Err(e);
+        }
+    }
+
+    cleared
+}
+
+pub fn init() -> Command {
+    Command {
+        app: CatchArgs::command(),
+        name: "catch",
+        run: catch,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -0,0 +1,271 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility clocks`
+//!
+//! `humility clocks` reads the clock configuration registers of the target
+//! (the RCC on STM32H7, or SYSCON on LPC55) and displays the clock tree
+//! that they describe: oscillators, PLLs (and their dividers and
+//! multipliers), the system clock, and the CPU and bus clocks derived from
+//! it.  It then displays the peripherals on each bus that have their clocks
+//! enabled:
+//!
+//! ```console
+//! $ humility clocks --oscillator 8000000
+//! humility: attached via ST-Link V3
+//! CLOCK        SOURCE         FREQUENCY NOTES
+//! hsi          -             64.000 MHz /1
+//! csi          -                      - off
+//! hse          -              8.000 MHz bypass
+//! pll1         hse          800.000 MHz /1 x100
+//! pll1_p       pll1         400.000 MHz /2
+//! pll1_q       pll1         200.000 MHz /4
+//! pll1_r       pll1                   - disabled
+//! pll2         hse                    - disabled
+//! ...
+//! sys_ck       pll1_p       400.000 MHz
+//! cpu          sys_ck       400.000 MHz
+//! hclk         cpu          200.000 MHz /2
+//! pclk1        hclk         100.000 MHz /2
+//! pclk2        hclk         100.000 MHz /2
+//! pclk3        hclk         100.000 MHz /2
+//! pclk4        hclk         100.000 MHz /2
+//!
+//! BUS    ENABLED
+//! AHB1   DMA1, DMA2, ETH1MAC, ETH1TX, ETH1RX
+//! AHB2   RNG, SRAM1, SRAM2, SRAM3
+//! AHB3   QSPI
+//! AHB4   GPIOA, GPIOB, GPIOC, GPIOD, GPIOE, GPIOF, GPIOG, BDMA, HSEM
+//! APB1L  SPI2, USART3, I2C2, I2C3
+//! APB1H  -
+//! APB2   SPI4
+//! APB3   -
+//! APB4   SYSCFG, I2C4
+//!
+//! humility: CPU clock matches kernel clock of 400.000 MHz
+//! ```
+//!
+//! The frequency of an external oscillator (HSE on STM32H7, CLKIN on
+//! LPC55) is a property of the board rather than the part, and can't be
+//! determined from the registers; it should be specified (in Hz) with
+//! `--oscillator` (`-o`).  If it is not specified, any clocks derived from
+//! the external oscillator will be displayed as `-`.
+//!
+//! If the archive specifies the CPU clock that the kernel was built for,
+//! it will be compared to the CPU clock derived from the registers, and
+//! the command will fail if they do not match.
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::HubrisArchive;
+use humility::msg;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+
+mod lpc55;
+mod stm32h7;
+
+#[derive(Parser, Debug)]
+#[clap(name = "clocks", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct ClocksArgs {
+    /// frequency of the external oscillator, in Hz
+    #[clap(
+        long, short, value_name = "hz",
+        parse(try_from_str = parse_int::parse)
+    )]
+    oscillator: Option<u32>,
+}
+
+pub(crate) struct Clock {
+    name: String,
+    source: String,
+    hz: Option<f64>,
+    note: String,
+}
+
+pub(crate) struct ClockTree {
+    clocks: Vec<Clock>,
+    gates: Vec<(&'static str, Vec<&'static str>)>,
+    cpu: Option<f64>,
+}
+
+impl ClockTree {
+    ///
+    /// Returns the frequency of the named clock, if it is known.
+    ///
+    fn hz(&self, name: &str) -> Option<f64> {
+        self.clocks.iter().find(|c| c.name == name).and_then(|c| c.hz)
+    }
+
+    fn oscillator(
+        &mut self,
+        name: &str,
+        on: bool,
+        hz: Option<f64>,
+        note: String,
+    ) {
+        self.clocks.push(Clock {
+            name: name.to_string(),
+            source: "-".to_string(),
+            hz: hz.filter(|_| on),
+            note: if on { note } else { "off".to_string() },
+        });
+    }
+
+    ///
+    /// Adds a clock that is derived from another by a divider.
+    ///
+    fn derived(&mut self, name: &str, source: &str, div: u32) {
+        self.clocks.push(Clock {
+            name: name.to_string(),
+            source: source.to_string(),
+            hz: self.hz(source).map(|hz| hz / div as f64),
+            note: if div != 1 { format!("/{div}") } else { String::new() },
+        });
+    }
+}
+
+///
+/// Returns the names of the peripherals that are enabled in a clock gate
+/// register.
+///
+pub(crate) fn gates(
+    val: u32,
+    names: &[(u32, &'static str)],
+) -> Vec<&'static str> {
+    names
+        .iter()
+        .filter(|(bit, _)| val & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+fn frequency(hz: Option<f64>) -> String {
+    match hz {
+        Some(hz) if hz >= 1_000_000.0 => format!("{:.3} MHz", hz / 1_000_000.0),
+        Some(hz) => format!("{:.3} kHz", hz / 1_000.0),
+        None => "-".to_string(),
+    }
+}
+
+fn clock_tree(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    oscillator: Option<f64>,
+) -> Result<ClockTree> {
+    match hubris.chip() {
+        Some(chip) if chip.starts_with("STM32H7") => {
+            stm32h7::clocks(core, oscillator)
+        }
+        Some(chip) if chip.starts_with("LPC55") => {
+            lpc55::clocks(core, oscillator)
+        }
+        Some(chip) => bail!("clocks on {chip} are not supported"),
+        None => bail!("could not determine chip from archive"),
+    }
+}
+
+///
+/// Returns the CPU clock (in Hz) that the kernel was built for, if the
+/// archive specifies it.
+///
+pub fn kernel_clock(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+) -> Result<Option<f64>> {
+    //
+    // The kernel's notion of the CPU clock is in kHz.
+    //
+    Ok(hubris.clock(core)?.map(|khz| khz as f64 * 1000.0))
+}
+
+///
+/// Returns the CPU clock (in Hz) as derived from the clock configuration
+/// registers, if it can be determined.  If the CPU clock is derived from
+/// an external oscillator, its frequency must be specified.
+///
+pub fn cpu_clock(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    oscillator: Option<f64>,
+) -> Result<Option<f64>> {
+    Ok(clock_tree(hubris, core, oscillator)?.cpu)
+}
+
+fn clocks(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = ClocksArgs::try_parse_from(subargs)?;
+    let oscillator = subargs.oscillator.map(|hz| hz as f64);
+
+    let tree = clock_tree(hubris, core, oscillator)?;
+
+    println!("{:12} {:12} {:>12} NOTES", "CLOCK", "SOURCE", "FREQUENCY");
+
+    for clock in &tree.clocks {
+        println!(
+            "{:12} {:12} {:>12} {}",
+            clock.name,
+            clock.source,
+            frequency(clock.hz),
+            clock.note
+        );
+    }
+
+    println!("\n{:6} ENABLED", "BUS");
+
+    for (bus, enabled) in &tree.gates {
+        println!(
+            "{:6} {}",
+            bus,
+            if enabled.is_empty() {
+                "-".to_string()
+            } else {
+                enabled.join(", ")
+            }
+        );
+    }
+
+    println!();
+
+    let expected = match kernel_clock(hubris, core)? {
+        Some(hz) => hz,
+        None => {
+            msg!("archive does not specify a kernel clock");
+            return Ok(());
+        }
+    };
+
+    match tree.cpu {
+        Some(cpu) if (cpu - expected).abs() < 1000.0 => {
+            msg!("CPU clock matches kernel clock of {}", frequency(Some(cpu)));
+            Ok(())
+        }
+        Some(cpu) => bail!(
+            "CPU clock of {} does not match kernel clock of {}",
+            frequency(Some(cpu)),
+            frequency(Some(expected))
+        ),
+        None => {
+            msg!("CPU clock could not be determined; specify --oscillator?");
+            Ok(())
+        }
+    }
+}
+
+pub fn init() -> Command {
+    Command {
+        app: ClocksArgs::command(),
+        name: "clocks",
+        run: clocks,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
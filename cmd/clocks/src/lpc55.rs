@@ -0,0 +1,223 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Decoding of the LPC55 system configuration (SYSCON) clock registers, as
+//! described in UM11126.  The main clock is selected from either one of the
+//! oscillators or one of the two PLLs; the CPU (and AHB) clock is the main
+//! clock divided by the AHB clock divider.
+//!
+
+use crate::{Clock, ClockTree};
+use anyhow::Result;
+use humility::core::Core;
+
+const SYSCON: u32 = 0x5000_0000;
+
+const SYSCON_MAINCLKSELA: u32 = 0x280;
+const SYSCON_MAINCLKSELB: u32 = 0x284;
+const SYSCON_PLL0CLKSEL: u32 = 0x290;
+const SYSCON_PLL1CLKSEL: u32 = 0x294;
+const SYSCON_AHBCLKDIV: u32 = 0x380;
+const SYSCON_PLL1CTRL: u32 = 0x560;
+const SYSCON_PLL1STAT: u32 = 0x564;
+const SYSCON_PLL1NDEC: u32 = 0x568;
+const SYSCON_PLL1MDEC: u32 = 0x56c;
+const SYSCON_PLL1PDEC: u32 = 0x570;
+const SYSCON_PLL0CTRL: u32 = 0x580;
+const SYSCON_PLL0STAT: u32 = 0x584;
+const SYSCON_PLL0NDEC: u32 = 0x588;
+const SYSCON_PLL0PDEC: u32 = 0x58c;
+const SYSCON_PLL0SSCG0: u32 = 0x590;
+const SYSCON_PLL0SSCG1: u32 = 0x594;
+
+const PLL_CTRL_BYPASSPLL: u32 = 1 << 15;
+const PLL_CTRL_BYPASSPOSTDIV: u32 = 1 << 16;
+const PLL_CTRL_BYPASSPOSTDIV2: u32 = 1 << 20;
+const PLL_CTRL_CLKEN: u32 = 1 << 21;
+const PLL_STAT_LOCK: u32 = 1 << 0;
+
+const PLL0_SSCG1_SEL_EXT: u32 = 1 << 28;
+
+//
+// The peripheral clock control registers, along with the peripherals whose
+// clocks they gate.
+//
+#[rustfmt::skip]
+const GATES: &[(&str, u32, &[(u32, &str)])] = &[
+    ("AHB0", 0x200, &[
+        (1, "ROM"), (3, "SRAM1"), (4, "SRAM2"), (5, "SRAM3"), (6, "SRAM4"),
+        (7, "FLASH"), (8, "FMC"), (11, "MUX"), (13, "IOCON"), (14, "GPIO0"),
+        (15, "GPIO1"), (18, "PINT"), (19, "GINT"), (20, "DMA0"),
+        (21, "CRCGEN"), (22, "WWDT"), (23, "RTC"), (26, "MAILBOX"),
+        (27, "ADC"),
+    ]),
+    ("AHB1", 0x204, &[
+        (0, "MRT"), (1, "OSTIMER"), (2, "SCT"), (10, "UTICK"), (11, "FC0"),
+        (12, "FC1"), (13, "FC2"), (14, "FC3"), (15, "FC4"), (16, "FC5"),
+        (17, "FC6"), (18, "FC7"), (22, "TIMER2"), (25, "USB0_DEV"),
+        (26, "TIMER0"), (27, "TIMER1"),
+    ]),
+    ("AHB2", 0x208, &[
+        (1, "DMA1"), (2, "COMP"), (3, "SDIO"), (4, "USB1_HOST"),
+        (5, "USB1_DEV"), (6, "USB1_RAM"), (7, "USB1_PHY"), (8, "FREQME"),
+        (13, "RNG"), (15, "SYSCTL"), (16, "USB0_HOSTM"), (17, "USB0_HOSTS"),
+        (18, "HASH_AES"), (19, "PQ"), (20, "PLULUT"), (21, "TIMER3"),
+        (22, "TIMER4"), (23, "PUF"), (24, "CASPER"), (27, "ANALOG_CTRL"),
+        (28, "HS_LSPI"), (29, "GPIO_SEC"), (30, "GPIO_SEC_INT"),
+    ]),
+];
+
+///
+/// Returns the name of the clock selected by a PLL clock source select.
+///
+fn pll_source(sel: u32) -> Option<&'static str> {
+    match sel & 0b111 {
+        0 => Some("fro_12m"),
+        1 => Some("clk_in"),
+        2 => Some("fro_1m"),
+        3 => Some("osc_32k"),
+        _ => None,
+    }
+}
+
+struct Pll {
+    name: &'static str,
+    src: Option<&'static str>,
+    ctrl: u32,
+    stat: u32,
+    n: u32,
+    m: f64,
+    p: u32,
+}
+
+pub fn clocks(core: &mut dyn Core, clk_in: Option<f64>) -> Result<ClockTree> {
+    let mut reg = |offset| core.read_word_32(SYSCON + offset);
+
+    let mainclksela = reg(SYSCON_MAINCLKSELA)?;
+    let mainclkselb = reg(SYSCON_MAINCLKSELB)?;
+    let ahbclkdiv = reg(SYSCON_AHBCLKDIV)?;
+
+    //
+    // PLL0's multiplier is either specified directly, or (when spread
+    // spectrum is in use) as a fixed-point value with 25 fractional bits.
+    //
+    let sscg0 = reg(SYSCON_PLL0SSCG0)?;
+    let sscg1 = reg(SYSCON_PLL0SSCG1)?;
+
+    let m0 = if sscg1 & PLL0_SSCG1_SEL_EXT != 0 {
+        ((sscg1 >> 10) & 0xffff) as f64
+    } else {
+        ((((sscg1 & 1) as u64) << 32) | sscg0 as u64) as f64
+            / (1u64 << 25) as f64
+    };
+
+    let plls = [
+        Pll {
+            name: "pll0",
+            src: pll_source(reg(SYSCON_PLL0CLKSEL)?),
+            ctrl: reg(SYSCON_PLL0CTRL)?,
+            stat: reg(SYSCON_PLL0STAT)?,
+            n: reg(SYSCON_PLL0NDEC)? & 0xff,
+            m: m0,
+            p: reg(SYSCON_PLL0PDEC)? & 0x1f,
+        },
+        Pll {
+            name: "pll1",
+            src: pll_source(reg(SYSCON_PLL1CLKSEL)?),
+            ctrl: reg(SYSCON_PLL1CTRL)?,
+            stat: reg(SYSCON_PLL1STAT)?,
+            n: reg(SYSCON_PLL1NDEC)? & 0xff,
+            m: (reg(SYSCON_PLL1MDEC)? & 0xffff) as f64,
+            p: reg(SYSCON_PLL1PDEC)? & 0x1f,
+        },
+    ];
+
+    let mut gates = vec![];
+
+    for (bus, offset, names) in GATES {
+        gates.push((*bus, crate::gates(reg(*offset)?, names)));
+    }
+
+    let mut tree = ClockTree { clocks: vec![], gates, cpu: None };
+
+    //
+    // The free-running oscillators are always shown at their nominal
+    // frequencies; the frequency of the external clock input is a property
+    // of the board, so we only know it if we have been told it.
+    //
+    let nominal = "nominal".to_string();
+
+    tree.oscillator("fro_12m", true, Some(12_000_000.0), nominal.clone());
+    tree.oscillator("fro_1m", true, Some(1_000_000.0), nominal.clone());
+    tree.oscillator("fro_hf", true, Some(96_000_000.0), nominal.clone());
+    tree.oscillator("osc_32k", true, Some(32_768.0), nominal);
+    tree.oscillator(
+        "clk_in",
+        true,
+        clk_in,
+        match clk_in {
+            None => "frequency unknown".to_string(),
+            Some(_) => String::new(),
+        },
+    );
+
+    for pll in &plls {
+        let bypass = pll.ctrl & PLL_CTRL_BYPASSPLL != 0;
+        let on = pll.ctrl & PLL_CTRL_CLKEN != 0
+            && (bypass || pll.stat & PLL_STAT_LOCK != 0);
+
+        let postdiv = if pll.ctrl & PLL_CTRL_BYPASSPOSTDIV != 0 {
+            1
+        } else if pll.ctrl & PLL_CTRL_BYPASSPOSTDIV2 != 0 {
+            pll.p.max(1)
+        } else {
+            2 * pll.p.max(1)
+        };
+
+        let hz = match (on, pll.src) {
+            (true, Some(src)) => tree.hz(src).map(|hz| {
+                let vco =
+                    if bypass { hz } else { hz / pll.n.max(1) as f64 * pll.m };
+                vco / postdiv as f64
+            }),
+            _ => None,
+        };
+
+        tree.clocks.push(Clock {
+            name: pll.name.to_string(),
+            source: pll.src.unwrap_or("-").to_string(),
+            hz,
+            note: if !on {
+                "disabled".to_string()
+            } else if bypass {
+                format!("bypass /{postdiv}")
+            } else {
+                format!("/{} x{} /{}", pll.n.max(1), pll.m, postdiv)
+            },
+        });
+    }
+
+    let main_a = match mainclksela & 0b111 {
+        0 => "fro_12m",
+        1 => "clk_in",
+        2 => "fro_1m",
+        _ => "fro_hf",
+    };
+
+    let main = match mainclkselb & 0b11 {
+        0 => "main_clk_a",
+        1 => "pll0",
+        2 => "pll1",
+        _ => "osc_32k",
+    };
+
+    tree.derived("main_clk_a", main_a, 1);
+    tree.derived("main_clk", main, 1);
+    tree.derived("cpu", "main_clk", (ahbclkdiv & 0xff) + 1);
+
+    tree.cpu = tree.hz("cpu");
+
+    Ok(tree)
+}
@@ -0,0 +1,239 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Decoding of the STM32H7 reset and clock control (RCC) block, as
+//! described in RM0433.  We derive the system clock from its oscillator
+//! through the PLLs, and the CPU and bus clocks from the system clock
+//! through the domain prescalers.
+//!
+
+use crate::{Clock, ClockTree};
+use anyhow::Result;
+use humility::core::Core;
+
+const RCC: u32 = 0x5802_4400;
+
+const RCC_CR: u32 = 0x00;
+const RCC_CFGR: u32 = 0x10;
+const RCC_D1CFGR: u32 = 0x18;
+const RCC_D2CFGR: u32 = 0x1c;
+const RCC_D3CFGR: u32 = 0x20;
+const RCC_PLLCKSELR: u32 = 0x28;
+const RCC_PLLCFGR: u32 = 0x2c;
+const RCC_PLL1DIVR: u32 = 0x30;
+const RCC_PLL1FRACR: u32 = 0x34;
+
+const HSI_HZ: f64 = 64_000_000.0;
+const CSI_HZ: f64 = 4_000_000.0;
+
+//
+// The peripheral clock enable registers, along with the peripherals whose
+// clocks they gate.
+//
+#[rustfmt::skip]
+const GATES: &[(&str, u32, &[(u32, &str)])] = &[
+    ("AHB1", 0xd8, &[
+        (0, "DMA1"), (1, "DMA2"), (5, "ADC12"), (15, "ETH1MAC"),
+        (16, "ETH1TX"), (17, "ETH1RX"), (25, "USB1OTG"), (26, "USB1ULPI"),
+        (27, "USB2OTG"), (28, "USB2ULPI"),
+    ]),
+    ("AHB2", 0xdc, &[
+        (0, "DCMI"), (4, "CRYPT"), (5, "HASH"), (6, "RNG"), (9, "SDMMC2"),
+        (29, "SRAM1"), (30, "SRAM2"), (31, "SRAM3"),
+    ]),
+    ("AHB3", 0xd4, &[
+        (0, "MDMA"), (4, "DMA2D"), (5, "JPGDEC"), (12, "FMC"), (14, "QSPI"),
+        (16, "SDMMC1"),
+    ]),
+    ("AHB4", 0xe0, &[
+        (0, "GPIOA"), (1, "GPIOB"), (2, "GPIOC"), (3, "GPIOD"), (4, "GPIOE"),
+        (5, "GPIOF"), (6, "GPIOG"), (7, "GPIOH"), (8, "GPIOI"), (9, "GPIOJ"),
+        (10, "GPIOK"), (19, "CRC"), (21, "BDMA"), (24, "ADC3"), (25, "HSEM"),
+        (28, "BKPRAM"),
+    ]),
+    ("APB1L", 0xe8, &[
+        (0, "TIM2"), (1, "TIM3"), (2, "TIM4"), (3, "TIM5"), (4, "TIM6"),
+        (5, "TIM7"), (6, "TIM12"), (7, "TIM13"), (8, "TIM14"), (9, "LPTIM1"),
+        (14, "SPI2"), (15, "SPI3"), (16, "SPDIFRX"), (17, "USART2"),
+        (18, "USART3"), (19, "UART4"), (20, "UART5"), (21, "I2C1"),
+        (22, "I2C2"), (23, "I2C3"), (27, "CEC"), (29, "DAC12"), (30, "UART7"),
+        (31, "UART8"),
+    ]),
+    ("APB1H", 0xec, &[
+        (1, "CRS"), (2, "SWPMI"), (4, "OPAMP"), (5, "MDIOS"), (8, "FDCAN"),
+    ]),
+    ("APB2", 0xf0, &[
+        (0, "TIM1"), (1, "TIM8"), (4, "USART1"), (5, "USART6"), (12, "SPI1"),
+        (13, "SPI4"), (16, "TIM15"), (17, "TIM16"), (18, "TIM17"),
+        (20, "SPI5"), (22, "SAI1"), (23, "SAI2"), (24, "SAI3"),
+        (28, "DFSDM1"), (29, "HRTIM"),
+    ]),
+    ("APB3", 0xe4, &[(3, "LTDC"), (6, "WWDG1")]),
+    ("APB4", 0xf4, &[
+        (1, "SYSCFG"), (3, "LPUART1"), (5, "SPI6"), (7, "I2C4"),
+        (9, "LPTIM2"), (10, "LPTIM3"), (11, "LPTIM4"), (12, "LPTIM5"),
+        (14, "COMP12"), (15, "VREF"), (16, "RTCAPB"), (21, "SAI4"),
+    ]),
+];
+
+///
+/// Returns the divisor encoded in an AHB prescaler (HPRE or D1CPRE) field.
+///
+fn ahb_divisor(val: u32) -> u32 {
+    match val & 0b1111 {
+        0..=7 => 1,
+        8 => 2,
+        9 => 4,
+        10 => 8,
+        11 => 16,
+        12 => 64,
+        13 => 128,
+        14 => 256,
+        _ => 512,
+    }
+}
+
+///
+/// Returns the divisor encoded in an APB prescaler (DxPPRE) field.
+///
+fn apb_divisor(val: u32) -> u32 {
+    match val & 0b111 {
+        0..=3 => 1,
+        div => 1 << (div - 3),
+    }
+}
+
+pub fn clocks(core: &mut dyn Core, hse: Option<f64>) -> Result<ClockTree> {
+    let mut reg = |offset| core.read_word_32(RCC + offset);
+
+    let cr = reg(RCC_CR)?;
+    let cfgr = reg(RCC_CFGR)?;
+    let d1cfgr = reg(RCC_D1CFGR)?;
+    let d2cfgr = reg(RCC_D2CFGR)?;
+    let d3cfgr = reg(RCC_D3CFGR)?;
+    let pllckselr = reg(RCC_PLLCKSELR)?;
+    let pllcfgr = reg(RCC_PLLCFGR)?;
+
+    let mut pllr = vec![];
+
+    for pll in 0..3 {
+        pllr.push((
+            reg(RCC_PLL1DIVR + pll * 8)?,
+            reg(RCC_PLL1FRACR + pll * 8)?,
+        ));
+    }
+
+    let mut gates = vec![];
+
+    for (bus, offset, names) in GATES {
+        gates.push((*bus, crate::gates(reg(*offset)?, names)));
+    }
+
+    let bit = |val: u32, n: u32| val & (1 << n) != 0;
+    let mut tree = ClockTree { clocks: vec![], gates, cpu: None };
+
+    //
+    // First, our oscillators.  The HSE frequency is a property of the board
+    // rather than the part, so we only know it if we have been told it.
+    //
+    let hsidiv = 1 << ((cr >> 3) & 0b11);
+
+    tree.oscillator(
+        "hsi",
+        bit(cr, 2),
+        Some(HSI_HZ / hsidiv as f64),
+        format!("/{hsidiv}"),
+    );
+
+    tree.oscillator("csi", bit(cr, 8), Some(CSI_HZ), String::new());
+    tree.oscillator(
+        "hse",
+        bit(cr, 17),
+        hse,
+        match (hse, bit(cr, 18)) {
+            (None, _) => "frequency unknown".to_string(),
+            (Some(_), true) => "bypass".to_string(),
+            (Some(_), false) => String::new(),
+        },
+    );
+
+    //
+    // All three PLLs share a source, but have their own pre-divider (DIVM)
+    // and multiplier (DIVN, with an optional fractional part).
+    //
+    let src = match pllckselr & 0b11 {
+        0 => Some("hsi"),
+        1 => Some("csi"),
+        2 => Some("hse"),
+        _ => None,
+    };
+
+    for (pll, (divr, fracr)) in pllr.iter().enumerate() {
+        let pll = pll as u32;
+        let name = format!("pll{}", pll + 1);
+        let on = bit(cr, 24 + pll * 2) && bit(cr, 25 + pll * 2);
+        let divm = (pllckselr >> (4 + pll * 8)) & 0x3f;
+        let divn = (divr & 0x1ff) + 1;
+        let frac =
+            if bit(pllcfgr, pll * 4) { (fracr >> 3) & 0x1fff } else { 0 };
+
+        let vco = match (on, divm, src) {
+            (true, 1.., Some(src)) => tree.hz(src).map(|hz| {
+                hz / divm as f64 * (divn as f64 + frac as f64 / 8192.0)
+            }),
+            _ => None,
+        };
+
+        tree.clocks.push(Clock {
+            name: name.clone(),
+            source: src.unwrap_or("-").to_string(),
+            hz: vco,
+            note: match (on, frac) {
+                (false, _) => "disabled".to_string(),
+                (true, 0) => format!("/{divm} x{divn}"),
+                (true, _) => format!("/{divm} x{divn}+{frac}/8192"),
+            },
+        });
+
+        for (i, output) in ["p", "q", "r"].iter().enumerate() {
+            let i = i as u32;
+            let div = ((divr >> (9 + i * 7)) & 0x7f) + 1;
+            let enabled = bit(pllcfgr, 16 + pll * 3 + i);
+
+            tree.clocks.push(Clock {
+                name: format!("{name}_{output}"),
+                source: name.clone(),
+                hz: vco.filter(|_| enabled).map(|hz| hz / div as f64),
+                note: if enabled {
+                    format!("/{div}")
+                } else {
+                    "disabled".to_string()
+                },
+            });
+        }
+    }
+
+    //
+    // And now the system clock and everything derived from it.
+    //
+    let sys = match (cfgr >> 3) & 0b111 {
+        0 => "hsi",
+        1 => "csi",
+        2 => "hse",
+        _ => "pll1_p",
+    };
+
+    tree.derived("sys_ck", sys, 1);
+    tree.derived("cpu", "sys_ck", ahb_divisor(d1cfgr >> 8));
+    tree.derived("hclk", "cpu", ahb_divisor(d1cfgr));
+    tree.derived("pclk1", "hclk", apb_divisor(d2cfgr >> 4));
+    tree.derived("pclk2", "hclk", apb_divisor(d2cfgr >> 8));
+    tree.derived("pclk3", "hclk", apb_divisor(d1cfgr >> 4));
+    tree.derived("pclk4", "hclk", apb_divisor(d3cfgr >> 4));
+
+    tree.cpu = tree.hz("cpu");
+
+    Ok(tree)
+}
@@ -6,7 +6,6 @@ mod picocom_map;
 
 use std::fs::File;
 use std::io::{Read, Write};
-use std::os::unix::io::AsRawFd;
 use std::time::Duration;
 use std::{io, thread};
 
@@ -14,11 +13,11 @@ use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
 use crossbeam_channel::{select, Sender};
 use picocom_map::RemapRules;
-use termios::Termios;
 
 use humility::core::Core;
 use humility::hubris::HubrisArchive;
 use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::UnrawTermiosGuard;
 use humility_hiffy::{HiffyContext, HiffyLease};
 use humility_idol::{HubrisIdol, IdolArgument};
 
@@ -286,29 +285,6 @@ fn stdin_reader(raw: bool, tx: Sender<Vec<u8>>, remap: RemapRules) {
     }
 }
 
-struct UnrawTermiosGuard {
-    stdout: i32,
-    ios: Termios,
-}
-
-impl Drop for UnrawTermiosGuard {
-    fn drop(&mut self) {
-        termios::tcsetattr(self.stdout, termios::TCSAFLUSH, &self.ios).unwrap();
-    }
-}
-
-impl UnrawTermiosGuard {
-    fn make_stdout_raw() -> Result<Self> {
-        let stdout = io::stdout().as_raw_fd();
-        let orig_termios = termios::Termios::from_fd(stdout)?;
-        let mut termios = orig_termios;
-        termios::cfmakeraw(&mut termios);
-        termios::tcsetattr(stdout, termios::TCSANOW, &termios)?;
-        termios::tcflush(stdout, termios::TCIOFLUSH)?;
-        Ok(Self { stdout, ios: orig_termios })
-    }
-}
-
 pub(super) fn console_proxy(context: &mut ExecutionContext) -> Result<()> {
     let core = &mut **context.core.as_mut().unwrap();
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
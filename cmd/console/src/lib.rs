@@ -0,0 +1,123 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility console`
+//!
+//! `humility console` provides an interactive console to the attached
+//! target, over whichever transport its firmware uses.  Three transports
+//! are supported:
+//!
+//! - `rtt`: up and down channels of SEGGER's Real-Time Transfer (RTT)
+//!   protocol (see `humility rtt`), if the archive contains an RTT control
+//!   block and the target has initialized it
+//! - `uart`: the console UART, via the `control_plane_agent` task (see
+//!   `humility console-proxy`)
+//! - `itm`: ITM stimulus ports 0 and 1 (see `humility itm`), if ITM has
+//!   been enabled on the target; as ITM is output-only, input is disabled
+//!
+//! By default, these are tried in the above order, and the first that the
+//! target supports is used; a transport can be specified explicitly with
+//! `--transport` (`-t`).  (Semihosting, which requires the debugger to
+//! service breakpoints on the target's behalf, is not supported.)
+//!
+//! ```console
+//! $ humility console
+//! humility: attached via ST-Link V3
+//! humility: console via RTT (up channel 0 "Terminal", down channel 0 "Terminal")
+//! humility: exit with Control-C, or Control-D on an empty line
+//! sequencer: A2 power good
+//! > status
+//! all rails nominal
+//! ```
+//!
+//! Input is edited a line at a time, and is sent to the target when Enter
+//! is pressed.  Backspace, Control-U (erase line) and Control-W (erase
+//! word) are supported, as is recalling previously sent lines with the up
+//! and down arrows.  Output from the target continues to be displayed while
+//! a line is being edited.
+//!
+//! To prefix each line of output with the kernel's tick count and the
+//! host's wall clock time (as with `humility itm --timeline`), use
+//! `--timestamps`; to additionally append output to a file, use `--log`
+//! (`-l`).  For RTT, the up and down channels can be specified with
+//! `--channel` (`-c`); for ITM, the trace identifier can be specified with
+//! `--traceid`.
+//!
+
+use clap::{ArgEnum, CommandFactory, Parser};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use std::path::PathBuf;
+
+#[cfg(not(windows))]
+mod posix;
+
+#[cfg(not(windows))]
+use posix::console;
+
+#[cfg(windows)]
+fn console(
+    _context: &mut humility_cli::ExecutionContext,
+) -> anyhow::Result<()> {
+    anyhow::bail!("the console subcommand is not available on Windows")
+}
+
+#[derive(ArgEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum Transport {
+    Rtt,
+    Uart,
+    Itm,
+}
+
+#[derive(Parser, Debug)]
+#[clap(name = "console", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct ConsoleArgs {
+    /// transport to use rather than detecting it
+    #[clap(long, short, arg_enum, value_name = "transport")]
+    transport: Option<Transport>,
+
+    /// RTT up and down channel
+    #[clap(long, short, default_value_t = 0, value_name = "channel")]
+    channel: usize,
+
+    /// sets ITM trace identifier
+    #[clap(
+        long, default_value_t = 0x3a, value_name = "identifier",
+        parse(try_from_str = parse_int::parse)
+    )]
+    traceid: u8,
+
+    /// prefix each line with kernel ticks and wall clock time
+    #[clap(long)]
+    timestamps: bool,
+
+    /// append output to the specified file
+    #[clap(long, short, value_name = "filename")]
+    log: Option<PathBuf>,
+
+    /// interval at which to poll for output, in milliseconds
+    #[clap(long, short, default_value_t = 10, value_name = "ms")]
+    poll_interval: u64,
+
+    /// timeout for UART operations, in milliseconds
+    #[clap(
+        long,
+        short = 'T',
+        default_value_t = 5000,
+        value_name = "timeout_ms"
+    )]
+    timeout: u32,
+}
+
+pub fn init() -> Command {
+    Command {
+        app: ConsoleArgs::command(),
+        name: "console",
+        run: console,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
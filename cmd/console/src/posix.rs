@@ -0,0 +1,641 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+
+use humility::core::Core;
+use humility::hubris::HubrisArchive;
+use humility::msg;
+use humility::rtt::{self, RttBuffer, RttControlBlock};
+use humility::timebase::Timebase;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::UnrawTermiosGuard;
+use humility_cortex::itm::{itm_ingest, ITMPayload, ITM_TCR};
+use humility_cortex::scs::{CoreInfo, CoreSightComponent};
+use humility_hiffy::{HiffyContext, HiffyLease};
+use humility_idol::{HubrisIdol, IdolArgument};
+
+use super::{ConsoleArgs, Transport};
+
+const UART_BUF_SIZE: usize = 256;
+
+///
+/// A console that can be read from and (if it has somewhere to send input)
+/// written to:  an RTT up channel (and down channel, if there is one), or
+/// the console UART via the `control_plane_agent` task.
+///
+enum Channel<'a> {
+    Rtt { up: RttBuffer, down: Option<RttBuffer> },
+    Uart { hubris: &'a HubrisArchive, context: Box<HiffyContext<'a>> },
+}
+
+fn uart_call(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    name: &str,
+    args: &[(&str, IdolArgument)],
+    lease: Option<HiffyLease>,
+) -> Result<humility::reflect::Value> {
+    let op = hubris.get_idol_command(&format!("ControlPlaneAgent.{name}"))?;
+
+    match humility_hiffy::hiffy_call(hubris, core, context, &op, args, lease)? {
+        Ok(value) => Ok(value),
+        Err(e) => bail!("{name} failed: {e}"),
+    }
+}
+
+fn uart_len(name: &str, value: humility::reflect::Value) -> Result<usize> {
+    let len = value
+        .as_base()?
+        .as_u32()
+        .ok_or_else(|| anyhow!("{name} did not return a length"))?;
+
+    Ok(len as usize)
+}
+
+impl Channel<'_> {
+    fn read(&mut self, core: &mut dyn Core) -> Result<Vec<u8>> {
+        match self {
+            Channel::Rtt { up, .. } => {
+                core.op_start()?;
+                let data = up.drain(core);
+                core.op_done()?;
+                data
+            }
+            Channel::Uart { hubris, context } => {
+                let mut buf = vec![0u8; UART_BUF_SIZE];
+                let lease = Some(HiffyLease::Read(&mut buf));
+                let value =
+                    uart_call(*hubris, core, context, "uart_read", &[], lease)?;
+                let len = uart_len("uart_read", value)?;
+                buf.truncate(len);
+                Ok(buf)
+            }
+        }
+    }
+
+    ///
+    /// Writes as much of the data as the target will take, returning the
+    /// number of bytes written.
+    ///
+    fn write(&mut self, core: &mut dyn Core, data: &[u8]) -> Result<usize> {
+        match self {
+            Channel::Rtt { down: Some(down), .. } => {
+                core.op_start()?;
+                let written = down.fill(core, data);
+                core.op_done()?;
+                written
+            }
+            Channel::Rtt { down: None, .. } => Ok(data.len()),
+            Channel::Uart { hubris, context } => {
+                let data = &data[..data.len().min(UART_BUF_SIZE)];
+                let lease = Some(HiffyLease::Write(data));
+                let value = uart_call(
+                    *hubris,
+                    core,
+                    context,
+                    "uart_write",
+                    &[],
+                    lease,
+                )?;
+                uart_len("uart_write", value)
+            }
+        }
+    }
+
+    ///
+    /// Detaches from the console.  For the UART, this restores the SP's
+    /// default behavior of reading and discarding console data.
+    ///
+    fn detach(&mut self, core: &mut dyn Core) -> Result<()> {
+        if let Channel::Uart { hubris, context } = self {
+            let args = [("attach", IdolArgument::String("false"))];
+            let name = "set_humility_uart_client";
+            uart_call(*hubris, core, context, name, &args, None)?;
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// Our output, which is displayed (with any line being edited redrawn
+/// beneath it) and optionally logged.  If timestamps have been requested,
+/// each line is prefixed with one.
+///
+struct Output {
+    log: Option<File>,
+    timebase: Option<Timebase>,
+    start: Instant,
+    raw: bool,
+
+    /// The line currently being output, as it has been displayed
+    line: Vec<u8>,
+}
+
+impl Output {
+    fn at_start(&self) -> bool {
+        self.line.is_empty()
+    }
+
+    fn display(
+        &mut self,
+        data: &[u8],
+        editor: Option<&LineEditor>,
+    ) -> Result<()> {
+        let mut out = vec![];
+        let mut logged = vec![];
+
+        //
+        // If we are editing a line, it has been drawn after the line
+        // currently being output; erase it and redraw our line.
+        //
+        if editor.is_some() {
+            out.extend_from_slice(b"\r\x1b[K");
+            out.extend_from_slice(&self.line);
+        }
+
+        for &b in data.iter().filter(|&&b| b != b'\r') {
+            if self.line.is_empty() {
+                if let Some(timebase) = &self.timebase {
+                    let elapsed = self.start.elapsed().as_secs_f64();
+                    let stamp = format!("{} ", timebase.elapsed(elapsed));
+                    self.line.extend_from_slice(stamp.as_bytes());
+                    out.extend_from_slice(stamp.as_bytes());
+                    logged.extend_from_slice(stamp.as_bytes());
+                }
+            }
+
+            if b == b'\n' {
+                let eol: &[u8] = if self.raw { b"\r\n" } else { b"\n" };
+                out.extend_from_slice(eol);
+                self.line.clear();
+            } else {
+                self.line.push(b);
+                out.push(b);
+            }
+
+            logged.push(b);
+        }
+
+        if let Some(editor) = editor {
+            editor.render(&mut out, self.at_start());
+        }
+
+        if let Some(log) = self.log.as_mut() {
+            log.write_all(&logged)
+                .and_then(|()| log.flush())
+                .context("error writing to log")?;
+        }
+
+        let mut stdout = io::stdout().lock();
+
+        stdout
+            .write_all(&out)
+            .and_then(|()| stdout.flush())
+            .context("error writing to stdout")
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Escape {
+    None,
+    Esc,
+    Csi,
+}
+
+enum Key {
+    Continue,
+    Redraw,
+    Submit(Vec<u8>),
+    Quit,
+}
+
+///
+/// A minimal line editor, operating on keystrokes from a terminal in raw
+/// mode.  Lines may only be edited at their end; previously submitted lines
+/// can be recalled with the up and down arrows.
+///
+struct LineEditor {
+    buf: Vec<u8>,
+    history: Vec<Vec<u8>>,
+    recalled: Option<usize>,
+    escape: Escape,
+}
+
+impl LineEditor {
+    fn new() -> Self {
+        Self {
+            buf: vec![],
+            history: vec![],
+            recalled: None,
+            escape: Escape::None,
+        }
+    }
+
+    fn render(&self, out: &mut Vec<u8>, prompt: bool) {
+        if prompt {
+            out.extend_from_slice(b"> ");
+        }
+
+        out.extend_from_slice(&self.buf);
+    }
+
+    fn recall(&mut self, up: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+
+        self.recalled = match (self.recalled, up) {
+            (None, true) => Some(self.history.len() - 1),
+            (None, false) => return,
+            (Some(ndx), true) => Some(ndx.saturating_sub(1)),
+            (Some(ndx), false) if ndx + 1 < self.history.len() => Some(ndx + 1),
+            (Some(_), false) => None,
+        };
+
+        self.buf = match self.recalled {
+            Some(ndx) => self.history[ndx].clone(),
+            None => vec![],
+        };
+    }
+
+    fn key(&mut self, b: u8) -> Key {
+        const CTRL_C: u8 = b'\x03';
+        const CTRL_D: u8 = b'\x04';
+        const CTRL_H: u8 = b'\x08';
+        const CTRL_U: u8 = b'\x15';
+        const CTRL_W: u8 = b'\x17';
+        const ESC: u8 = b'\x1b';
+        const DEL: u8 = b'\x7f';
+
+        match self.escape {
+            Escape::Esc => {
+                self.escape = match b {
+                    b'[' | b'O' => Escape::Csi,
+                    _ => Escape::None,
+                };
+
+                return Key::Continue;
+            }
+
+            Escape::Csi => {
+                //
+                // A control sequence is terminated by a byte in the range
+                // 0x40-0x7e; we only act on the up and down arrows.
+                //
+                if !(0x40..=0x7e).contains(&b) {
+                    return Key::Continue;
+                }
+
+                self.escape = Escape::None;
+
+                return match b {
+                    b'A' => {
+                        self.recall(true);
+                        Key::Redraw
+                    }
+                    b'B' => {
+                        self.recall(false);
+                        Key::Redraw
+                    }
+                    _ => Key::Continue,
+                };
+            }
+
+            Escape::None => {}
+        }
+
+        match b {
+            ESC => {
+                self.escape = Escape::Esc;
+                Key::Continue
+            }
+            CTRL_C => Key::Quit,
+            CTRL_D if self.buf.is_empty() => Key::Quit,
+            b'\r' | b'\n' => {
+                let line = std::mem::take(&mut self.buf);
+
+                if !line.is_empty() && self.history.last() != Some(&line) {
+                    self.history.push(line.clone());
+                }
+
+                self.recalled = None;
+                Key::Submit(line)
+            }
+            DEL | CTRL_H => {
+                self.buf.pop();
+                Key::Redraw
+            }
+            CTRL_U => {
+                self.buf.clear();
+                Key::Redraw
+            }
+            CTRL_W => {
+                while self.buf.last() == Some(&b' ') {
+                    self.buf.pop();
+                }
+
+                while matches!(self.buf.last(), Some(c) if *c != b' ') {
+                    self.buf.pop();
+                }
+
+                Key::Redraw
+            }
+            0x20..=0x7e => {
+                self.buf.push(b);
+                Key::Redraw
+            }
+            _ => Key::Continue,
+        }
+    }
+}
+
+fn console_interactive(
+    core: &mut dyn Core,
+    channel: &mut Channel,
+    output: &mut Output,
+    interval: Duration,
+) -> Result<()> {
+    let _guard = UnrawTermiosGuard::make_stdout_raw()?;
+    output.raw = true;
+
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        let mut buf = [0u8; 256];
+
+        while let Ok(n) = stdin.read(&mut buf) {
+            if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut editor = LineEditor::new();
+    let mut pending = vec![];
+
+    output.display(&[], Some(&editor))?;
+
+    loop {
+        let mut redraw = false;
+
+        while let Ok(keys) = rx.try_recv() {
+            for b in keys {
+                match editor.key(b) {
+                    Key::Continue => {}
+                    Key::Redraw => redraw = true,
+                    Key::Submit(line) => {
+                        //
+                        // The submitted line is displayed (and logged) as if
+                        // it were output, so it remains in context.
+                        //
+                        let mut echo = if output.at_start() {
+                            b"> ".to_vec()
+                        } else {
+                            vec![]
+                        };
+
+                        echo.extend_from_slice(&line);
+                        echo.push(b'\n');
+                        output.display(&echo, Some(&editor))?;
+
+                        pending.extend(line);
+                        pending.push(b'\n');
+                    }
+                    Key::Quit => {
+                        output.display(b"\n", None)?;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        if !pending.is_empty() {
+            let written = channel.write(core, &pending)?;
+            pending.drain(..written);
+        }
+
+        let data = channel.read(core)?;
+
+        if !data.is_empty() {
+            output.display(&data, Some(&editor))?;
+            continue;
+        }
+
+        if redraw {
+            output.display(&[], Some(&editor))?;
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+fn console_itm(
+    core: &mut dyn Core,
+    traceid: Option<u8>,
+    output: &mut Output,
+    interval: Duration,
+) -> Result<()> {
+    let mut bytes: Vec<u8> = vec![];
+    let mut ndx = 0;
+    let start = Instant::now();
+
+    let done = humility_cmd::interrupted()?;
+
+    let stats = itm_ingest(
+        traceid,
+        || {
+            while ndx == bytes.len() {
+                if done.load(Ordering::SeqCst) {
+                    return Ok(None);
+                }
+
+                bytes = core.read_swv()?;
+                ndx = 0;
+
+                if bytes.is_empty() {
+                    thread::sleep(interval);
+                }
+            }
+
+            ndx += 1;
+            Ok(Some((bytes[ndx - 1], start.elapsed().as_secs_f64())))
+        },
+        |packet| {
+            if let ITMPayload::Instrumentation { payload, port } =
+                &packet.payload
+            {
+                if *port <= 1 {
+                    output.display(payload, None)?;
+                }
+            }
+
+            Ok(())
+        },
+    )?;
+
+    stats.report();
+
+    Ok(())
+}
+
+fn console_rtt<'a>(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    channel: usize,
+) -> Result<Channel<'a>> {
+    let addr = rtt::address(hubris, None)?;
+
+    core.op_start()?;
+    let cb = RttControlBlock::read(core, addr);
+    core.op_done()?;
+    let mut cb = cb?;
+
+    if channel >= cb.up.len() {
+        bail!("RTT up channel {channel} does not exist; see \"humility rtt\"");
+    }
+
+    let up = cb.up.swap_remove(channel);
+    let down = (channel < cb.down.len()).then(|| cb.down.swap_remove(channel));
+
+    match &down {
+        Some(down) => msg!(
+            "console via RTT (up channel {channel} \"{}\", \
+            down channel {channel} \"{}\")",
+            up.name,
+            down.name
+        ),
+        None => msg!(
+            "console via RTT (up channel {channel} \"{}\"); there is no \
+            down channel {channel}, so input is disabled",
+            up.name
+        ),
+    }
+
+    Ok(Channel::Rtt { up, down })
+}
+
+///
+/// Determines the transport to use:  RTT if the archive has a control
+/// block and the target has initialized it, the UART if there is a
+/// `control_plane_agent` that can proxy it, or ITM if it has been enabled.
+///
+fn console_detect(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+) -> Result<Transport> {
+    if let Ok(addr) = rtt::address(hubris, None) {
+        core.op_start()?;
+        let cb = RttControlBlock::read(core, addr);
+        core.op_done()?;
+
+        match cb {
+            Ok(cb) if !cb.up.is_empty() => return Ok(Transport::Rtt),
+            Ok(_) => msg!("not using RTT: control block has no up channels"),
+            Err(e) => msg!("not using RTT: {e}"),
+        }
+    }
+
+    if hubris.get_idol_command("ControlPlaneAgent.uart_read").is_ok() {
+        return Ok(Transport::Uart);
+    }
+
+    core.op_start()?;
+    let tcr = ITM_TCR::read(core);
+    core.op_done()?;
+
+    if tcr?.itm_enable() {
+        return Ok(Transport::Itm);
+    }
+
+    bail!(
+        "no console found: archive has neither an RTT control block nor \
+        a control_plane_agent task, and ITM is not enabled \
+        (see \"humility itm\")"
+    );
+}
+
+pub(super) fn console(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = ConsoleArgs::try_parse_from(subargs)?;
+    let hubris = context.archive.as_ref().unwrap();
+
+    if context.is_interactive {
+        bail!("`console` cannot be used from the REPL");
+    }
+
+    let log = subargs
+        .log
+        .map(|path| {
+            File::options()
+                .append(true)
+                .create(true)
+                .open(&path)
+                .with_context(|| format!("failed to open {}", path.display()))
+        })
+        .transpose()?;
+
+    let timebase = if subargs.timestamps {
+        Some(Timebase::new(hubris, core)?)
+    } else {
+        None
+    };
+
+    let mut output = Output {
+        log,
+        timebase,
+        start: Instant::now(),
+        raw: false,
+        line: vec![],
+    };
+
+    let interval = Duration::from_millis(subargs.poll_interval);
+
+    let transport = match subargs.transport {
+        Some(transport) => transport,
+        None => console_detect(hubris, core)?,
+    };
+
+    let mut channel = match transport {
+        Transport::Rtt => console_rtt(hubris, core, subargs.channel)?,
+        Transport::Uart => {
+            let context = HiffyContext::new(hubris, core, subargs.timeout)?;
+            let context = Box::new(context);
+            msg!("console via UART (control_plane_agent)");
+            Channel::Uart { hubris, context }
+        }
+        Transport::Itm => {
+            let coreinfo = CoreInfo::read(core)?;
+
+            let traceid = match coreinfo.address(CoreSightComponent::SWO) {
+                Some(_) => None,
+                None => Some(subargs.traceid),
+            };
+
+            msg!("console via ITM; input is disabled");
+            msg!("exit with Control-C");
+
+            return console_itm(core, traceid, &mut output, interval);
+        }
+    };
+
+    msg!("exit with Control-C, or Control-D on an empty line");
+
+    let rval = console_interactive(core, &mut channel, &mut output, interval);
+    let detached = channel.detach(core);
+
+    rval.and(detached)
+}
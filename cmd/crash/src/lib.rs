@@ -0,0 +1,318 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility crash`
+//!
+//! `humility crash` produces a triage report for a crashed target or dump,
+//! assembling in one place what would otherwise require `humility tasks`,
+//! `humility registers` and `humility ringbuf` (and some manual
+//! cross-referencing between them).  If the kernel has panicked, its epitaph
+//! is shown; on a live target, the fault status registers in the System
+//! Control Block are decoded.  Then, for each faulted task, the report
+//! contains the fault (as recorded by the kernel), the task's stack backtrace
+//! (with line numbers and inlined frames), its registers, and the most recent
+//! entries in each of its ring buffers:
+//!
+//! ```console
+//! $ humility -d ./hubris.core.7 crash
+//! humility: attached to dump
+//! system time = 1791860
+//! 1 faulted task
+//!
+//!  7 pong                 gen 3: FAULT: mem fault (precise: 0x0) in task code
+//!    (was: Runnable)
+//!    |
+//!    +--->  0x20006ea0 0x0802a5d6 task_pong::main
+//!    |                 @ /home/bmc/hubris/task/pong/src/main.rs:33
+//!    |
+//!    +--->   R0 = 0x00000000   R1 = 0x00000003   R2 = 0x00000000   R3 = 0x0802a5d5
+//!            R4 = 0x00000000   R5 = 0x00000000   R6 = 0x00000001   R7 = 0x20006ea8
+//!            R8 = 0x00000000   R9 = 0x00000000  R10 = 0x00000000  R11 = 0x00000000
+//!           R12 = 0x00000000   SP = 0x20006ea0   LR = 0x0802a5c9   PC = 0x0802a5d6
+//!           PSR = 0x61000000
+//!
+//!    ring buffer task_pong::__RINGBUF (last 3 of 3 entries):
+//!     NDX LINE      GEN    COUNT PAYLOAD
+//!       0   29        1        1 Pinged(0x1)
+//!       1   29        1        1 Pinged(0x2)
+//!       2   29        1        1 Pinged(0x3)
+//! ```
+//!
+//! To triage a task that has not faulted (e.g., one that appears to be
+//! wedged), provide its name as an argument.  By default, the last 16 entries
+//! of each ring buffer are shown; use `--entries` (`-n`) to change this.
+//!
+
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::reflect::{self, Load, Value};
+use humility_arch_arm::ARMRegister;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_cortex::debug::*;
+use humility_doppel::{self as doppel, Ringbuf, RingbufEntry, Task, TaskState};
+use num_traits::FromPrimitive;
+use std::collections::BTreeMap;
+
+#[derive(Parser, Debug)]
+#[clap(name = "crash", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct CrashArgs {
+    /// number of entries to show from each of a task's ring buffers
+    #[clap(
+        long, short = 'n', default_value_t = 16, value_name = "entries",
+        parse(try_from_str = parse_int::parse)
+    )]
+    entries: usize,
+
+    /// task to triage, whether or not it has faulted
+    task: Option<String>,
+}
+
+fn print_regs(regs: &BTreeMap<ARMRegister, u32>) {
+    print!("   |\n   +--->");
+
+    for r in 0..=16 {
+        let reg = ARMRegister::from_usize(r).unwrap();
+
+        if r != 0 && r % 4 == 0 {
+            print!("{:8}", "");
+        }
+
+        match regs.get(&reg) {
+            Some(val) => print!("  {:>3} = 0x{:08x}", reg, val),
+            None => print!("  {:>3} = {:10}", reg, "?"),
+        }
+
+        if r % 4 == 3 {
+            println!();
+        }
+    }
+
+    println!();
+}
+
+///
+/// Prints the last `entries` entries in a ring buffer.
+///
+fn print_ringbuf(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    name: &str,
+    definition: &HubrisStruct,
+    var: &HubrisVariable,
+    entries: usize,
+) -> Result<()> {
+    let mut buf = vec![0; var.size];
+    core.read_8(var.addr, &mut buf)?;
+
+    let ringbuf = Ringbuf::load(hubris, definition, &buf)?;
+
+    if ringbuf.last.is_none() {
+        println!("   ring buffer {name} is empty\n");
+        return Ok(());
+    }
+
+    let slots = ringbuf.slots();
+    let shown = &slots[slots.len().saturating_sub(entries)..];
+
+    println!(
+        "   ring buffer {} (last {} of {} entries):",
+        name,
+        shown.len(),
+        slots.len()
+    );
+
+    println!("   {}", RingbufEntry::HEADER);
+
+    for &slot in shown {
+        println!("   {}", ringbuf.buffer[slot].display(hubris, slot)?);
+    }
+
+    println!();
+    Ok(())
+}
+
+fn triage(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &CrashArgs,
+) -> Result<()> {
+    let (base, task_count) = hubris.task_table(core)?;
+    let task_t = hubris.lookup_struct_byname("Task")?;
+    let task_dump = hubris.task_dump();
+
+    if task_dump.is_none() {
+        if let Some(epitaph) = hubris.epitaph(core)? {
+            println!("kernel has panicked: {}", epitaph);
+        }
+    }
+
+    println!("system time = {}", hubris.ticks(core)?);
+
+    //
+    // The SCB isn't in a dump, so we can only decode it on a live target.
+    //
+    if !core.is_dump() {
+        print_scb(core)?;
+    }
+
+    let mut taskblock = vec![0; task_t.size * task_count as usize];
+
+    match task_dump {
+        Some(HubrisTask::Task(i)) => {
+            let offs = i as usize * task_t.size;
+            let addr = base + offs as u32;
+            core.read_8(addr, &mut taskblock[offs..offs + task_t.size])?;
+        }
+        _ => core.read_8(base, &mut taskblock)?,
+    }
+
+    let mut triaged = vec![];
+
+    for i in 0..task_count {
+        if let Some(HubrisTask::Task(ndx)) = task_dump {
+            if ndx != i {
+                continue;
+            }
+        }
+
+        let offs = i as usize * task_t.size;
+
+        let value: Value = reflect::load(hubris, &taskblock, task_t, offs)
+            .with_context(|| {
+                format!("loading task control block for task {}", i)
+            })?;
+
+        let task = Task::from_value(&value)?;
+        let module = hubris.lookup_module(HubrisTask::Task(i))?;
+
+        let selected = match &subargs.task {
+            Some(name) => *name == module.name,
+            None => matches!(task.state, TaskState::Faulted { .. }),
+        };
+
+        if selected {
+            triaged.push((i, module, task));
+        }
+    }
+
+    match (&subargs.task, triaged.len()) {
+        (Some(name), 0) => bail!("\"{}\" is not a valid task", name),
+        (None, 0) => {
+            println!("no faulted tasks");
+            return Ok(());
+        }
+        (None, 1) => println!("1 faulted task"),
+        (None, n) => println!("{} faulted tasks", n),
+        (Some(_), _) => {}
+    }
+
+    let printer = humility_stack::StackPrinter {
+        indent: 3,
+        line: true,
+        additional: true,
+    };
+
+    let ringbufs = hubris
+        .qualified_variables()
+        .filter_map(|(name, var)| {
+            let def = hubris.lookup_struct(var.goff).ok()?;
+
+            if def.name.contains("Ringbuf") {
+                Some((name, var, def))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    for (i, module, task) in triaged {
+        let t = HubrisTask::Task(i);
+        let regs = hubris.registers(core, t)?;
+
+        print!(
+            "\n{:2} {:20} gen {}: ",
+            i,
+            module.name,
+            u32::from(task.generation)
+        );
+
+        match task.state {
+            TaskState::Faulted { fault: fi, original_state } => {
+                let reg = |r| regs.get(&r).copied();
+                println!("FAULT: {}", fi.describe(hubris, core, reg)?);
+                println!("   (was: {:?})", original_state);
+            }
+            TaskState::Healthy(state) => println!("{:?}", state),
+        }
+
+        let desc: doppel::TaskDesc = task.descriptor.load_from(hubris, core)?;
+
+        match hubris.stack(core, t, desc.initial_stack, &regs) {
+            Ok(stack) => printer.print(hubris, &stack),
+            Err(e) => println!("   stack unwind failed: {:?}", e),
+        }
+
+        print_regs(&regs);
+
+        for (name, var, def) in &ringbufs {
+            if HubrisTask::from(var.goff) != t {
+                continue;
+            }
+
+            if let Err(e) =
+                print_ringbuf(hubris, core, name, def, var, subargs.entries)
+            {
+                println!("   ring buffer {name} could not be read: {e}\n");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn crash(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+    let subargs = CrashArgs::try_parse_from(subargs)?;
+
+    if core.is_net() {
+        bail!("crash triage requires a debugger or a dump");
+    }
+
+    //
+    // We keep the target halted for the duration to get a consistent view
+    // of it, running it again even if triage fails -- but only if it was
+    // running to begin with.
+    //
+    let halted = core.is_dump() || DHCSR::read(core)?.halted();
+
+    if !halted {
+        core.halt()?;
+    }
+
+    let rval = triage(hubris, core, &subargs);
+
+    if !halted {
+        core.run()?;
+    }
+
+    rval
+}
+
+pub fn init() -> Command {
+    Command {
+        app: CrashArgs::command(),
+        name: "crash",
+        run: crash,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::Any,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -0,0 +1,339 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility deadlock`
+//!
+//! `humility deadlock` reads the state of every task from the kernel and
+//! constructs the graph of IPC waits:  a task that is sending to another
+//! task (or awaiting its reply) is waiting on that task, as is a task that
+//! is in a closed receive from a specific sender.  For each wait, the
+//! operation is decoded from the sender's saved registers and -- if the
+//! task being waited on serves an Idol interface -- named.  Any cycles in
+//! the graph (that is, deadlocks) are reported, as are chains of waits that
+//! are at least `--depth` (`-D`) waits long (by default, 3):
+//!
+//! ```console
+//! $ humility deadlock
+//! humility: attached via ST-Link V3
+//! system time = 3104432
+//! WAITER               STATE  WAITING ON           OPERATION
+//! spd                  reply  i2c_driver           I2c.WriteRead (1)
+//! thermal              send   sensor               Sensor.post (4)
+//! sensor               send   thermal              Thermal.get_mode (2)
+//! power                reply  spd                  Spd.read (3)
+//!
+//! DEADLOCK: thermal -> sensor -> thermal
+//! ```
+//!
+//! The end of a chain that is not a deadlock is also described, as a
+//! chain that ends in a faulted task will not make progress until that task
+//! is restarted:
+//!
+//! ```console
+//! $ humility deadlock -D 2
+//! ...
+//! chain of 2 waits: power -> spd -> i2c_driver (ready)
+//! ```
+//!
+
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::reflect::{self, Load};
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_doppel::{SchedState, Task, TaskId, TaskState};
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Parser, Debug)]
+#[clap(name = "deadlock", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct DeadlockArgs {
+    /// report chains of at least this many waits
+    #[clap(
+        long, short = 'D', default_value_t = 3, value_name = "waits",
+        parse(try_from_str = parse_int::parse)
+    )]
+    depth: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum WaitKind {
+    Send,
+    Reply,
+    Recv,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Wait {
+    kind: WaitKind,
+    target: u32,
+    operation: Option<u16>,
+}
+
+fn task_name(hubris: &HubrisArchive, index: u32) -> String {
+    match hubris.lookup_module(HubrisTask::Task(index)) {
+        Ok(module) => module.name.clone(),
+        Err(_) => format!("unknown#{index}"),
+    }
+}
+
+///
+/// Names an operation on the interface served by the specified task.  Idol
+/// operations are numbered from 1 in the order in which they are defined.
+///
+fn operation_name(hubris: &HubrisArchive, target: u32, op: u16) -> String {
+    let iface = hubris
+        .lookup_module(HubrisTask::Task(target))
+        .ok()
+        .and_then(|module| module.iface.as_ref());
+
+    match iface {
+        Some(iface) => {
+            match iface.ops.keys().nth((op as usize).wrapping_sub(1)) {
+                Some(name) => format!("{}.{} ({})", iface.name, name, op),
+                None => format!("{}.<unknown> ({})", iface.name, op),
+            }
+        }
+        None => format!("{op}"),
+    }
+}
+
+fn describe(state: &TaskState) -> &'static str {
+    match state {
+        TaskState::Faulted { .. } => "FAULTED",
+        TaskState::Healthy(SchedState::Stopped) => "stopped",
+        TaskState::Healthy(SchedState::Runnable) => "ready",
+        TaskState::Healthy(SchedState::InSend(_)) => "send",
+        TaskState::Healthy(SchedState::InReply(_)) => "reply",
+        TaskState::Healthy(SchedState::InRecv(_)) => "recv",
+    }
+}
+
+fn deadlock(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+    let subargs = DeadlockArgs::try_parse_from(subargs)?;
+
+    if core.is_net() {
+        bail!("deadlock analysis requires a debugger or a dump");
+    }
+
+    if hubris.task_dump().is_some() {
+        bail!("deadlock analysis requires all tasks; this is a task dump");
+    }
+
+    let (base, task_count) = hubris.task_table(core)?;
+    let task_t = hubris.lookup_struct_byname("Task")?;
+    let save = task_t.lookup_member("save")?.offset;
+    let state = hubris.lookup_struct_byname("SavedState")?;
+    let r4 = save + state.lookup_member("r4")?.offset;
+
+    //
+    // Read the entire task table at once (and with the target halted) to
+    // get a consistent snapshot.
+    //
+    let mut taskblock = vec![0; task_t.size * task_count as usize];
+
+    core.halt()?;
+    let rval = core.read_8(base, &mut taskblock);
+    let ticks = hubris.ticks(core);
+    core.run()?;
+    rval?;
+
+    println!("system time = {}", ticks?);
+
+    let mut tasks = vec![];
+    let mut waits = BTreeMap::new();
+
+    for i in 0..task_count {
+        let offs = i as usize * task_t.size;
+
+        let value = reflect::load(hubris, &taskblock, task_t, offs)
+            .with_context(|| {
+                format!("loading task control block for task {}", i)
+            })?;
+
+        let task = Task::from_value(&value)?;
+
+        //
+        // For a send (and therefore for the reply that follows it), the
+        // operation is in the lower half of R4 (the upper half being the
+        // target), which is preserved in the saved state while the task is
+        // blocked.
+        //
+        let o = offs + r4;
+        let r4 = u32::from_le_bytes(taskblock[o..o + 4].try_into().unwrap());
+        let operation = Some(r4 as u16);
+
+        let wait = |kind, tid: TaskId, operation| {
+            if tid == TaskId::KERNEL {
+                None
+            } else {
+                Some(Wait { kind, target: tid.index() as u32, operation })
+            }
+        };
+
+        let w = match task.state {
+            TaskState::Healthy(SchedState::InSend(tid)) => {
+                wait(WaitKind::Send, tid, operation)
+            }
+            TaskState::Healthy(SchedState::InReply(tid)) => {
+                wait(WaitKind::Reply, tid, operation)
+            }
+            TaskState::Healthy(SchedState::InRecv(Some(tid))) => {
+                wait(WaitKind::Recv, tid, None)
+            }
+            _ => None,
+        };
+
+        if let Some(w) = w {
+            waits.insert(i, w);
+        }
+
+        tasks.push(task);
+    }
+
+    if waits.is_empty() {
+        println!("no tasks are waiting on other tasks");
+        return Ok(());
+    }
+
+    println!("{:20} {:6} {:20} OPERATION", "WAITER", "STATE", "WAITING ON");
+
+    for (&i, w) in &waits {
+        let op = match (w.kind, w.operation) {
+            (WaitKind::Recv, _) => "-".to_string(),
+            (_, Some(op)) => operation_name(hubris, w.target, op),
+            (_, None) => "?".to_string(),
+        };
+
+        println!(
+            "{:20} {:6} {:20} {}",
+            task_name(hubris, i),
+            match w.kind {
+                WaitKind::Send => "send",
+                WaitKind::Reply => "reply",
+                WaitKind::Recv => "recv",
+            },
+            task_name(hubris, w.target),
+            op
+        );
+    }
+
+    println!();
+
+    //
+    // Each task waits on at most one other task, so following the waits
+    // from any task yields a single path that either ends at a task that
+    // isn't waiting or enters a cycle.  We follow the waits from each task
+    // that no other task is waiting on (the start of a chain) and then from
+    // every task that has not otherwise been visited (which can only be in
+    // a cycle that no chain leads into).
+    //
+    let waited: Vec<u32> = waits.values().map(|w| w.target).collect();
+    let mut starts: Vec<u32> =
+        waits.keys().copied().filter(|i| !waited.contains(i)).collect();
+    starts.extend(waits.keys().copied().filter(|i| waited.contains(i)));
+
+    let mut visited = vec![false; task_count as usize];
+    let mut reported = HashSet::new();
+    let mut found = false;
+
+    for start in starts {
+        if visited[start as usize] {
+            continue;
+        }
+
+        let mut path = vec![start];
+        let mut cur = start;
+
+        let cycle = loop {
+            visited[cur as usize] = true;
+
+            let next = match waits.get(&cur) {
+                Some(w) => w.target,
+                None => break None,
+            };
+
+            if let Some(pos) = path.iter().position(|&t| t == next) {
+                break Some(pos);
+            }
+
+            path.push(next);
+
+            if next >= task_count {
+                break None;
+            }
+
+            cur = next;
+        };
+
+        let names = |path: &[u32]| {
+            path.iter()
+                .map(|&t| task_name(hubris, t))
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        };
+
+        match cycle {
+            Some(pos) => {
+                //
+                // Several chains may lead into the same cycle; report it
+                // only once.
+                //
+                let mut cycle = path[pos..].to_vec();
+
+                if reported.insert(*cycle.iter().min().unwrap()) {
+                    cycle.push(path[pos]);
+                    println!("DEADLOCK: {}", names(&cycle));
+                }
+
+                found = true;
+            }
+            None => {
+                let nwaits = path.len() - 1;
+
+                if nwaits >= subargs.depth {
+                    let last = *path.last().unwrap();
+                    let state = match tasks.get(last as usize) {
+                        Some(task) => describe(&task.state),
+                        None => "invalid",
+                    };
+
+                    println!(
+                        "chain of {} waits: {} ({})",
+                        nwaits,
+                        names(&path),
+                        state
+                    );
+                    found = true;
+                }
+            }
+        }
+    }
+
+    if !found {
+        println!(
+            "no deadlocks or chains of {} or more waits found",
+            subargs.depth
+        );
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: DeadlockArgs::command(),
+        name: "deadlock",
+        run: deadlock,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::Any,
+            validate: Validate::Booted,
+        },
+    }
+}
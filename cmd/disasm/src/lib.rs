@@ -0,0 +1,377 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility disasm`
+//!
+//! `humility disasm` disassembles a function (specified by name) or a range
+//! of memory (specified by address), annotating the targets of branches and
+//! calls with the symbols that contain them:
+//!
+//! ```console
+//! $ humility disasm task_jefe::main
+//! humility: disassembling task_jefe::main (0x08010ac8, 412 bytes) from archive
+//! task_jefe::main:
+//!   0x08010ac8  b5f0       push {r4, r5, r6, r7, lr}
+//!   0x08010aca  af03       add r7, sp, #0xc
+//!   0x08010acc  e92d 0f00  push.w {r8, sb, sl, fp}
+//!   0x08010ad0  b09c       sub sp, #0x70
+//!   0x08010ad2  f000 f8e5  bl #0x8010ca0  ; <task_jefe::dump::initialize>
+//!   ...
+//! ```
+//!
+//! A symbol name can be fully qualified or can be the last component(s) of
+//! a path (e.g., `dump::initialize`); if a name matches more than one
+//! symbol, the matching symbols are listed.  When disassembling by address,
+//! the number of bytes to disassemble can be specified with `--length`
+//! (`-l`); it defaults to the size of the symbol when disassembling by name.
+//!
+//! By default, instructions are taken from the archive.  To instead
+//! disassemble memory read from the attached target (e.g., to see what is
+//! actually in flash), use `--live`.
+//!
+//! To interleave source lines, use `--source` (`-s`).  This relies on DWARF
+//! line information in the archive; the text of each line is displayed if
+//! the source file can be found on the local filesystem:
+//!
+//! ```console
+//! $ humility disasm --source --length 8 0x08010ad2
+//! humility: disassembling 0x08010ad2 (8 bytes) from archive
+//! task_jefe::main+0xa:
+//!   ; /hubris/task/jefe/src/main.rs:61
+//!   ;     dump::initialize();
+//!   0x08010ad2  f000 f8e5  bl #0x8010ca0  ; <task_jefe::dump::initialize>
+//!   ; /hubris/task/jefe/src/main.rs:63
+//!   ;     let mut task_states = [TaskStatus::default(); NUM_TASKS];
+//!   0x08010ad6  a804       add r0, sp, #0x10
+//!   0x08010ad8  2170       movs r1, #0x70
+//! ```
+//!
+
+use anyhow::{anyhow, bail, Result};
+use capstone::prelude::*;
+use capstone::InsnGroupType;
+use clap::{CommandFactory, Parser};
+use humility::core::{ArchiveCore, Core, CORE_MAX_READSIZE};
+use humility::hubris::*;
+use humility::msg;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{attach_live, Archive, Command, CommandKind};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Parser, Debug)]
+#[clap(name = "disasm", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct DisasmArgs {
+    /// disassemble memory read from the attached target
+    #[clap(long)]
+    live: bool,
+
+    /// interleave source lines
+    #[clap(long, short)]
+    source: bool,
+
+    /// number of bytes to disassemble
+    #[clap(
+        long, short, value_name = "bytes",
+        parse(try_from_str = parse_int::parse)
+    )]
+    length: Option<u32>,
+
+    /// symbol or address to disassemble
+    target: String,
+}
+
+///
+/// The number of bytes that we disassemble by default when given an
+/// address rather than a symbol.
+///
+const DEFAULT_LENGTH: u32 = 32;
+
+///
+/// Resolves what we have been asked to disassemble into a description,
+/// an address and a length.
+///
+fn resolve(
+    hubris: &HubrisArchive,
+    subargs: &DisasmArgs,
+) -> Result<(String, u32, u32)> {
+    if let Ok(addr) = parse_int::parse::<u32>(&subargs.target) {
+        let len = subargs.length.unwrap_or(DEFAULT_LENGTH);
+        return Ok((format!("0x{addr:08x}"), addr & !1, len));
+    }
+
+    let mut syms = hubris.lookup_symbols(&subargs.target);
+    syms.dedup_by_key(|(_, addr, _)| *addr);
+
+    match syms[..] {
+        [] => bail!("no symbol matches \"{}\"", subargs.target),
+        [(name, addr, size)] => {
+            Ok((name.to_string(), addr, subargs.length.unwrap_or(size)))
+        }
+        _ => {
+            for (name, addr, size) in &syms {
+                msg!("0x{addr:08x} {size:>6} {name}");
+            }
+
+            bail!("\"{}\" matches more than one symbol", subargs.target);
+        }
+    }
+}
+
+///
+/// Returns the file and line for each address in the specified range for
+/// which the DWARF line program has a row.
+///
+fn lines(
+    hubris: &HubrisArchive,
+    addr: u32,
+    len: u32,
+) -> Result<BTreeMap<u32, (String, u64)>> {
+    let mut rval = BTreeMap::new();
+
+    let module = match hubris
+        .modules()
+        .find(|m| addr >= m.textbase && addr - m.textbase < m.textsize)
+    {
+        Some(module) => module,
+        None => return Ok(rval),
+    };
+
+    let file = match module.task {
+        HubrisTask::Kernel => "elf/kernel".to_string(),
+        HubrisTask::Task(_) => format!("elf/task/{}", module.name),
+    };
+
+    let buffer = hubris
+        .read_file(&file)?
+        .ok_or_else(|| anyhow!("archive is missing {file}"))?;
+
+    let elf = goblin::elf::Elf::parse(&buffer)
+        .map_err(|e| anyhow!("failed to parse {file}: {e}"))?;
+
+    let dwarf = gimli::Dwarf::<&[u8]>::load(
+        |id| {
+            let sec = elf.section_headers.iter().find(|sh| {
+                matches!(
+                    elf.shdr_strtab.get(sh.sh_name),
+                    Some(Ok(name)) if name == id.name()
+                )
+            });
+
+            match sec {
+                Some(sec) => {
+                    let offset = sec.sh_offset as usize;
+                    let size = sec.sh_size as usize;
+                    buffer.get(offset..offset + size).ok_or_else(|| {
+                        anyhow!("bad offset/size for ELF section {}", id.name())
+                    })
+                }
+                None => Ok(&[]),
+            }
+        },
+        |_| Ok(&[]),
+    )?;
+
+    let dwarf = dwarf.borrow(|section| {
+        gimli::EndianSlice::new(section, gimli::LittleEndian)
+    });
+
+    let mut units = dwarf.units();
+
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+
+        let program = match unit.line_program.clone() {
+            Some(program) => program,
+            None => continue,
+        };
+
+        let mut rows = program.rows();
+
+        while let Some((header, row)) = rows.next_row()? {
+            let a = row.address() as u32;
+
+            if a < addr || a - addr >= len || row.end_sequence() {
+                continue;
+            }
+
+            let (file, line) = match (row.file(header), row.line()) {
+                (Some(file), Some(line)) => (file, line),
+                _ => continue,
+            };
+
+            let name = dwarf.attr_string(&unit, file.path_name())?;
+            let name = name.to_string_lossy()?;
+
+            let path = match file.directory(header) {
+                Some(dir) if !name.starts_with('/') => {
+                    let dir = dwarf.attr_string(&unit, dir)?;
+                    format!("{}/{}", dir.to_string_lossy()?, name)
+                }
+                _ => name.into_owned(),
+            };
+
+            rval.insert(a, (path, line));
+        }
+    }
+
+    Ok(rval)
+}
+
+fn branch_target(cs: &Capstone, instr: &capstone::Insn) -> Option<u32> {
+    const BREL: u8 = InsnGroupType::CS_GRP_BRANCH_RELATIVE as u8;
+
+    let detail = cs.insn_detail(instr).ok()?;
+
+    if !detail.groups().any(|g| g == InsnGroupId(BREL)) {
+        return None;
+    }
+
+    match detail.arch_detail().operands().last()? {
+        arch::ArchOperand::ArmOperand(op) => match op.op_type {
+            arch::arm::ArmOperandType::Imm(a) => Some(a as u32),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn symbolize(hubris: &HubrisArchive, addr: u32) -> Option<String> {
+    hubris.instr_sym(addr).map(|(name, base)| {
+        if base == addr {
+            name.to_string()
+        } else {
+            format!("{}+0x{:x}", name, addr - base)
+        }
+    })
+}
+
+fn disasm(context: &mut ExecutionContext) -> Result<()> {
+    let hubris = context.archive.as_ref().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = DisasmArgs::try_parse_from(subargs)?;
+
+    let (desc, addr, len) = resolve(hubris, &subargs)?;
+
+    if len == 0 {
+        bail!("nothing to disassemble at 0x{addr:08x}");
+    }
+
+    let mut core: Box<dyn Core> = if subargs.live {
+        attach_live(&context.cli, hubris)?
+    } else {
+        Box::new(ArchiveCore::new(hubris)?)
+    };
+
+    let mut buf = vec![0u8; len as usize];
+
+    for (i, chunk) in buf.chunks_mut(CORE_MAX_READSIZE).enumerate() {
+        core.read_8(addr + (i * CORE_MAX_READSIZE) as u32, chunk)?;
+    }
+
+    let from = if subargs.live { "target" } else { "archive" };
+
+    if desc.starts_with("0x") {
+        msg!("disassembling {desc} ({len} bytes) from {from}");
+    } else {
+        msg!("disassembling {desc} (0x{addr:08x}, {len} bytes) from {from}");
+    }
+
+    let mut cs = Capstone::new()
+        .arm()
+        .mode(arch::arm::ArchMode::Thumb)
+        .extra_mode(std::iter::once(arch::arm::ArchExtraMode::MClass))
+        .detail(true)
+        .build()
+        .map_err(|e| anyhow!("failed to initialize disassembler: {e:?}"))?;
+    cs.set_skipdata(true).expect("failed to set skipdata");
+
+    let instrs = cs
+        .disasm_all(&buf, addr.into())
+        .map_err(|e| anyhow!("failed to disassemble {desc}: {e}"))?;
+
+    let lines = if subargs.source {
+        lines(hubris, addr, len)?
+    } else {
+        BTreeMap::new()
+    };
+
+    let mut sources: HashMap<String, Option<Vec<String>>> = HashMap::new();
+    let mut last = None;
+    let mut sym = None;
+
+    for instr in instrs.iter() {
+        let a = instr.address() as u32;
+
+        let s = hubris.instr_sym(a).map(|(_, base)| base);
+
+        if s != sym || a == addr {
+            if let Some(name) = symbolize(hubris, a) {
+                println!("{name}:");
+            }
+
+            sym = s;
+        }
+
+        if let Some((file, line)) = lines.get(&a) {
+            if last != Some((file, line)) {
+                println!("  ; {file}:{line}");
+
+                let text = sources.entry(file.clone()).or_insert_with(|| {
+                    std::fs::read_to_string(file)
+                        .ok()
+                        .map(|s| s.lines().map(str::to_string).collect())
+                });
+
+                if let Some(l) = text
+                    .as_ref()
+                    .and_then(|t| t.get((*line as usize).saturating_sub(1)))
+                {
+                    println!("  ;     {}", l.trim());
+                }
+
+                last = Some((file, line));
+            }
+        }
+
+        let bytes = instr
+            .bytes()
+            .chunks(2)
+            .map(|c| match c {
+                [lo, hi] => format!("{hi:02x}{lo:02x}"),
+                _ => c.iter().map(|b| format!("{b:02x}")).collect(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let text = format!(
+            "{} {}",
+            instr.mnemonic().unwrap_or("?"),
+            instr.op_str().unwrap_or("")
+        );
+
+        match branch_target(&cs, instr).and_then(|t| symbolize(hubris, t)) {
+            Some(target) => println!(
+                "  0x{:08x}  {:<9}  {}  ; <{}>",
+                a,
+                bytes,
+                text.trim_end(),
+                target
+            ),
+            None => {
+                println!("  0x{:08x}  {:<9}  {}", a, bytes, text.trim_end())
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: DisasmArgs::command(),
+        name: "disasm",
+        run: disasm,
+        kind: CommandKind::Unattached { archive: Archive::Required },
+    }
+}
@@ -10,9 +10,29 @@ use std::io::Write;
 use std::path::Path;
 use std::process::Command;
 
+///
+/// Substitutes the list of commands that are safe for operators (that is,
+/// those with `operator = true` in their `[package.metadata.humility]`) into
+/// the README.
+///
+fn operator_commands(input: &str, operator: &mut Vec<String>) -> String {
+    operator.sort();
+
+    if let Some(last) = operator.pop() {
+        if !operator.is_empty() {
+            operator.push(format!("and {}", last));
+        } else {
+            operator.push(last);
+        }
+    }
+
+    input.replace("@OPERATOR_COMMANDS@", &format!("{}.", operator.join(", ")))
+}
+
 fn main() -> Result<()> {
     use cargo_metadata::MetadataCommand;
     let mut cmds = BTreeMap::new();
+    let mut operator = vec![];
 
     // MetadataCommand doesn't emit this, so we should
     println!("cargo:rerun-if-changed=Cargo.toml",);
@@ -37,6 +57,11 @@ fn main() -> Result<()> {
                 }
             };
 
+            if package.metadata["humility"]["operator"].as_bool() == Some(true)
+            {
+                operator.push(format!("`{}`", cmd));
+            }
+
             cmds.insert(cmd.to_string(), (description, package.manifest_path));
         }
     }
@@ -113,9 +138,10 @@ fn docs() -> &'static str {{
 
     let root = metadata.workspace_root;
     println!("cargo:rerun-if-changed={}", root.join("README.md.in").display());
-    let input = std::fs::read(root.join("README.md.in"))?;
+    let input = std::fs::read_to_string(root.join("README.md.in"))?;
+    let input = operator_commands(&input, &mut operator);
 
-    output.write_all(&input)?;
+    output.write_all(input.as_bytes())?;
 
     writeln!(
         output,
@@ -64,11 +64,76 @@
 //! 25 idle                         0   8 RUNNING
 //! ```
 //!
+//! Dumps that have been taken by the kernel and stored by the dump agent
+//! (e.g., dumps of tasks that have faulted) can be retrieved -- via hiffy,
+//! or via UDP when attached over the network -- with `--from-agent`.  The
+//! compressed segments are reassembled, and each dump is written to a dump
+//! file that can be used with any command that operates on a dump.  If the
+//! dump agent holds a system dump, it is written to the specified dump file;
+//! if it holds task dumps, each is written to its own file named for its
+//! task (or, with `--area`, only the dump in the specified area is
+//! retrieved):
+//!
+//! ```console
+//! $ humility -i fe80::0c1d:9aff:fe64:b8c2%en0 dump --from-agent
+//! humility: using UDP dump agent
+//! humility: retrieving dump of spd (area 0)
+//! humility: dumping to hubris.core.spd.0
+//! humility: dumped 23.04KB in 1 second
+//! humility: retrieving dump of thermal (area 1)
+//! humility: dumping to hubris.core.thermal.0
+//! humility: dumped 31.55KB in 1 second
+//! humility: retaining dump agent state
+//! ```
+//!
+//! Retrieving dumps does not clear them from the dump agent; to do this, use
+//! `--initialize-dump-agent`.
+//!
+//! For targets with large amounts of RAM, much of which is often zero-filled,
+//! `--sparse` elides any zero-filled 1 KiB blocks from the dump, recording the
+//! elided ranges in an index within the dump; when the dump is subsequently
+//! used, reads from elided ranges return zeroes:
+//!
+//! ```console
+//! $ humility dump --sparse
+//! humility: attached via ST-Link
+//! humility: core halted
+//! humility: dumping to hubris.core.0
+//! humility: dumped 212.5KB (eliding 931.5KB of zeroes in 41 ranges) in 25 seconds
+//! humility: core resumed
+//! ```
+//!
+//! To reduce the size of a dump, `--compress` (`-z`) compresses it with
+//! zstd; compressed dumps can be used with any command that operates on a
+//! dump.  Every dump records the image ID of the archive, the time at which
+//! it was taken and the probe via which it was taken; an operator's note may
+//! also be recorded with `--comment`, all of which are displayed by
+//! `humility manifest`:
+//!
+//! ```console
+//! $ humility dump -z --comment "sequencer stuck in A2" hubris.core.a2
+//! humility: attached via ST-Link V3
+//! humility: core halted
+//! humility: dumping to hubris.core.a2
+//! humility: dumped 1.12MB in 24 seconds
+//! humility: compressed 1.12MB to 161.23KB
+//! humility: core resumed
+//! $ humility -d hubris.core.a2 manifest | grep -A2 "dump time"
+//!    dump time => 1697366400 (Unix time)
+//!   dumped via => STLink V3, VID 0483, PID 374e (002F00174741500520383733)
+//!      comment => sequencer stuck in A2
+//! ```
+//!
+//! When operating on a dump, reads from memory that is not in the dump (e.g.,
+//! because a dump is of a single task) fail with a message indicating the
+//! nearest region that is in the dump.
+//!
 
 use anyhow::{bail, Result};
 use clap::{ArgGroup, CommandFactory, Parser};
 use humility::core::Core;
 use humility::hubris::*;
+use humility::Diagnostic;
 use humility_arch_arm::ARMRegister;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
@@ -76,7 +141,7 @@ use humility_dump_agent::{
     task_areas, DumpAgent, DumpAgentCore, DumpAgentExt, DumpArea,
     HiffyDumpAgent, UdpDumpAgent,
 };
-use humpty::DumpTask;
+use humpty::{DumpAreaHeader, DumpTask};
 use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
 use num_traits::FromPrimitive;
 use std::cell::RefCell;
@@ -168,6 +233,16 @@ struct DumpArgs {
     )]
     all: bool,
 
+    /// retrieves dumps stored by the dump agent, without taking a new one
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "simulation", "list", "task", "all", "force-overwrite",
+            "initialize-dump-agent", "force-manual-initiation",
+        ]
+    )]
+    from_agent: bool,
+
     #[clap(short, long, conflicts_with_all = &["simulation", "list"])]
     area: Option<usize>,
 
@@ -178,9 +253,48 @@ struct DumpArgs {
     #[clap(long, short, conflicts_with_all = &["simulation", "area"])]
     list: bool,
 
+    /// write a sparse dump, eliding zero-filled memory
+    #[clap(long, conflicts_with_all = &["list", "dump-agent-status"])]
+    sparse: bool,
+
+    /// compress the dump with zstd
+    #[clap(
+        long, short = 'z',
+        conflicts_with_all = &["list", "dump-agent-status"]
+    )]
+    compress: bool,
+
+    /// comment to record in the dump
+    #[clap(
+        long, value_name = "comment",
+        conflicts_with_all = &["list", "dump-agent-status"]
+    )]
+    comment: Option<String>,
+
     dumpfile: Option<String>,
 }
 
+/// Granularity at which zero-filled memory is elided from a sparse dump
+const SPARSE_GRANULARITY: usize = 1024;
+
+impl DumpArgs {
+    ///
+    /// Returns the options with which to write a dump taken via the
+    /// specified core.
+    ///
+    fn options(&self, core: &dyn Core) -> HubrisDumpOptions {
+        let (probe, serial) = core.info();
+
+        HubrisDumpOptions {
+            sparse: self.sparse.then_some(SPARSE_GRANULARITY),
+            compress: self.compress,
+            probe: Some(probe),
+            serial,
+            comment: self.comment.clone(),
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 fn emulate_dump(
@@ -318,6 +432,7 @@ fn dump_via_agent(
     core: &mut dyn Core,
     subargs: &DumpArgs,
 ) -> Result<()> {
+    let options = subargs.options(core);
     let mut out = DumpAgentCore::new(HubrisFlashMap::new(hubris)?);
     let started = Some(Instant::now());
     let mut area = subargs.area.map(DumpArea::ByIndex);
@@ -465,12 +580,13 @@ fn dump_via_agent(
                 && !subargs.force_overwrite
                 && task.is_none()
             {
-                bail!(
-                    "there appears to already be one or more dumps in situ; \
-                    list them with --list, clear them with \
-                    --initialize-dump-agent, or force them to be overwritten \
-                    with --force-overwrite"
+                bail!(Diagnostic::error(
+                    "there appears to already be one or more dumps in situ"
                 )
+                .code("dumps-in-situ")
+                .hint("list them with --list")
+                .hint("clear them with --initialize-dump-agent")
+                .hint("force them to be overwritten with --force-overwrite"))
             }
 
             if task.is_none() || subargs.initialize_dump_agent {
@@ -570,7 +686,13 @@ fn dump_via_agent(
         }
     }
 
-    hubris.dump(&mut out, task, subargs.dumpfile.as_deref(), started)?;
+    hubris.dump_with_options(
+        &mut out,
+        task,
+        subargs.dumpfile.as_deref(),
+        started,
+        &options,
+    )?;
 
     Ok(())
 }
@@ -580,6 +702,7 @@ fn dump_task_via_agent(
     core: &mut dyn Core,
     subargs: &DumpArgs,
 ) -> Result<()> {
+    let options = subargs.options(core);
     let mut out = DumpAgentCore::new(HubrisFlashMap::new(hubris)?);
     let started = Some(Instant::now());
 
@@ -602,7 +725,13 @@ fn dump_task_via_agent(
         true,
     )?;
     assert!(task.is_some());
-    hubris.dump(&mut out, task, subargs.dumpfile.as_deref(), started)?;
+    hubris.dump_with_options(
+        &mut out,
+        task,
+        subargs.dumpfile.as_deref(),
+        started,
+        &options,
+    )?;
 
     Ok(())
 }
@@ -654,11 +783,42 @@ fn dump_list(
     Ok(())
 }
 
+///
+/// Returns the name of the task (or task region) dumped in an area.
+///
+fn area_task_name(
+    hubris: &HubrisArchive,
+    task: &DumpTask,
+    headers: &[DumpAreaHeader],
+) -> Result<String> {
+    Ok(match hubris.lookup_module(HubrisTask::Task(task.id.into())) {
+        Ok(module) => match headers[0].contents {
+            humpty::DUMP_CONTENTS_SINGLETASK => module.name.to_owned(),
+            humpty::DUMP_CONTENTS_TASKREGION => {
+                format!("{}.region", module.name.to_owned())
+            }
+            c => bail!("unknown contents type: {c}"),
+        },
+        _ => "<unknown>".to_owned(),
+    })
+}
+
+///
+/// Returns the first dump file name for the named task that doesn't exist.
+///
+fn area_dumpfile(task_name: &str) -> String {
+    (0..)
+        .map(|i| format!("hubris.core.{task_name}.{i}"))
+        .find(|f| std::fs::File::open(f).is_err())
+        .unwrap()
+}
+
 fn dump_all(
     hubris: &HubrisArchive,
     core: &mut dyn Core,
     subargs: &DumpArgs,
 ) -> Result<()> {
+    let options = subargs.options(core);
     let mut agent = get_dump_agent(hubris, core, subargs)?;
     let headers = agent.read_dump_headers(false)?;
     if headers.is_empty() || headers[0].0.dumper == humpty::DUMPER_NONE {
@@ -675,23 +835,8 @@ fn dump_all(
     } else {
         let areas = task_areas(&headers);
         for (area, (task, headers)) in &areas {
-            let task_name = match hubris
-                .lookup_module(HubrisTask::Task(task.id.into()))
-            {
-                Ok(module) => match headers[0].contents {
-                    humpty::DUMP_CONTENTS_SINGLETASK => module.name.to_owned(),
-                    humpty::DUMP_CONTENTS_TASKREGION => {
-                        format!("{}.region", module.name.to_owned())
-                    }
-                    c => bail!("unknown contents type: {c}"),
-                },
-                _ => "<unknown>".to_owned(),
-            };
-
-            let dumpfile = (0..)
-                .map(|i| format!("hubris.core.{task_name}.{i}"))
-                .find(|f| std::fs::File::open(f).is_err())
-                .unwrap();
+            let task_name = area_task_name(hubris, task, headers)?;
+            let dumpfile = area_dumpfile(&task_name);
             humility::msg!("dumping {task_name} (area {area})");
 
             let mut out = DumpAgentCore::new(HubrisFlashMap::new(hubris)?);
@@ -702,7 +847,13 @@ fn dump_all(
                 true,
             )?;
             assert!(task.is_some());
-            hubris.dump(&mut out, task, Some(&dumpfile), started)?;
+            hubris.dump_with_options(
+                &mut out,
+                task,
+                Some(&dumpfile),
+                started,
+                &options,
+            )?;
         }
 
         if !subargs.retain_state {
@@ -715,6 +866,89 @@ fn dump_all(
     }
 }
 
+fn dump_from_agent(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &DumpArgs,
+) -> Result<()> {
+    let options = subargs.options(core);
+    let mut agent = get_dump_agent(hubris, core, subargs)?;
+    let headers = agent.read_dump_headers(false)?;
+
+    if headers.is_empty() || headers[0].0.dumper == humpty::DUMPER_NONE {
+        bail!(Diagnostic::error("there are no dumps in situ")
+            .code("no-dumps-in-situ")
+            .hint("take a dump with --force-dump-agent"));
+    }
+
+    if headers[0].1.is_none() {
+        if subargs.area.is_some() {
+            bail!("dump in situ is a system dump; --area cannot be specified");
+        }
+
+        let mut out = DumpAgentCore::new(HubrisFlashMap::new(hubris)?);
+        let started = Some(Instant::now());
+
+        humility::msg!("retrieving system dump");
+        let task = agent.read_dump(None, &mut out, true)?;
+
+        hubris.dump_with_options(
+            &mut out,
+            task,
+            subargs.dumpfile.as_deref(),
+            started,
+            &options,
+        )?;
+    } else {
+        let areas = task_areas(&headers);
+
+        let areas = match subargs.area {
+            Some(area) => match areas.get(&area) {
+                Some(entry) => vec![(area, entry)],
+                None => bail!("area {area} does not contain a task dump"),
+            },
+            None => areas.iter().map(|(area, entry)| (*area, entry)).collect(),
+        };
+
+        if areas.len() > 1 && subargs.dumpfile.is_some() {
+            bail!(
+                "there are {} task dumps in situ; specify one with --area \
+                to specify a dump file",
+                areas.len()
+            );
+        }
+
+        for (area, (task, headers)) in areas {
+            let task_name = area_task_name(hubris, task, headers)?;
+            let dumpfile = match &subargs.dumpfile {
+                Some(dumpfile) => dumpfile.clone(),
+                None => area_dumpfile(&task_name),
+            };
+
+            humility::msg!("retrieving dump of {task_name} (area {area})");
+
+            let mut out = DumpAgentCore::new(HubrisFlashMap::new(hubris)?);
+            let started = Some(Instant::now());
+            let task = agent.read_dump(
+                Some(DumpArea::ByIndex(area)),
+                &mut out,
+                true,
+            )?;
+
+            hubris.dump_with_options(
+                &mut out,
+                task,
+                Some(&dumpfile),
+                started,
+                &options,
+            )?;
+        }
+    }
+
+    humility::msg!("retaining dump agent state");
+    Ok(())
+}
+
 fn dump_agent_status(
     hubris: &HubrisArchive,
     core: &mut dyn Core,
@@ -738,7 +972,9 @@ fn dumpcmd(context: &mut ExecutionContext) -> Result<()> {
         bail!("can only force the dump agent when attached via debug probe");
     }
 
-    if subargs.all {
+    if subargs.from_agent {
+        dump_from_agent(hubris, core, &subargs)
+    } else if subargs.all {
         dump_all(hubris, core, &subargs)
     } else if subargs.list {
         dump_list(hubris, core, &subargs)
@@ -757,13 +993,25 @@ fn dumpcmd(context: &mut ExecutionContext) -> Result<()> {
         dump_via_agent(hubris, core, &subargs)
     } else {
         if subargs.initialize_dump_agent {
-            bail!("must also use --force-dump-agent to initialize dump agent");
+            bail!(Diagnostic::error(
+                "cannot initialize dump agent without using it"
+            )
+            .code("requires-dump-agent")
+            .hint("also use --force-dump-agent to initialize dump agent"));
         }
 
+        let options = subargs.options(core);
+
         core.halt()?;
         humility::msg!("core halted");
 
-        let rval = hubris.dump(core, None, subargs.dumpfile.as_deref(), None);
+        let rval = hubris.dump_with_options(
+            core,
+            None,
+            subargs.dumpfile.as_deref(),
+            None,
+            &options,
+        );
 
         if !subargs.leave_halted {
             core.run()?;
@@ -128,8 +128,30 @@
 //! extracting the entire archive requires the specification of an output file
 //! to prevent accidental blasts of binary content to the console.)
 //!
+//! To restrict listing or extraction to the files pertaining to a particular
+//! task (or to the kernel), use the `--task` (`-t`) option.  If no file is
+//! specified, the ELF object for the task is extracted:
+//!
+//! ```console
+//! $ humility -a /path/to/my/hubris-archive.zip extract --list --task ping
+//!         SIZE NAME
+//!        87284 elf/task/ping
+//! $ humility -a /path/to/my/hubris-archive.zip extract -t ping -o ping.o
+//! humility: extracting elf/task/ping to ping.o
+//! ```
+//!
+//! To write out the ELF object for a task (or the kernel) ready for use with
+//! a debugger, use the `--elf` option.  Unless an output file is specified,
+//! the object is written to a file named for the task:
+//!
+//! ```console
+//! $ humility -a /path/to/my/hubris-archive.zip extract --elf ping
+//! humility: extracting elf/task/ping to ping.elf
+//! $ arm-none-eabi-gdb ping.elf
+//! ```
+//!
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use clap::{CommandFactory, Parser};
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Command, CommandKind};
@@ -149,10 +171,41 @@ struct ExtractArgs {
     #[clap(long, short)]
     output: Option<String>,
 
+    /// restrict to the files of the specified task (or the kernel)
+    #[clap(long, short, value_name = "task", conflicts_with = "elf")]
+    task: Option<String>,
+
+    /// write the ELF object of the specified task (or the kernel) to a file
+    #[clap(long, value_name = "task", conflicts_with_all = &["list", "file"])]
+    elf: Option<String>,
+
     /// Optional file to extract
     file: Option<String>,
 }
 
+///
+/// Returns true if the specified archive member pertains to the specified
+/// task:  that is, if any component of its path (less any extension) is the
+/// name of the task.
+///
+fn task_member(name: &str, task: &str) -> bool {
+    std::path::Path::new(name).iter().any(|c| {
+        std::path::Path::new(c).file_stem().map_or(false, |s| s == task)
+    })
+}
+
+///
+/// Returns the name of the archive member containing the ELF object for the
+/// specified task (or the kernel).
+///
+fn task_elf(task: &str) -> String {
+    if task == "kernel" {
+        "elf/kernel".to_string()
+    } else {
+        format!("elf/task/{task}")
+    }
+}
+
 fn extract(context: &mut ExecutionContext) -> Result<()> {
     let hubris = context.archive.as_ref().unwrap();
     let archive = hubris.archive();
@@ -167,12 +220,44 @@ fn extract(context: &mut ExecutionContext) -> Result<()> {
 
         for i in 0..archive.len() {
             let file = archive.by_index(i)?;
+
+            if let Some(ref task) = subargs.task {
+                if !task_member(file.name(), task) {
+                    continue;
+                }
+            }
+
             println!("{:12} {}", file.size(), file.name());
         }
 
         return Ok(());
     }
 
+    let destination = subargs.output.as_deref().unwrap_or("stdout");
+
+    if let Some(ref task) = subargs.elf {
+        let cursor = Cursor::new(archive);
+        let mut archive = zip::ZipArchive::new(cursor)?;
+        let name = task_elf(task);
+
+        let mut file = archive
+            .by_name(&name)
+            .map_err(|_| anyhow!("no ELF object found for \"{task}\""))?;
+
+        let output = match subargs.output {
+            Some(ref output) => output.clone(),
+            None => format!("{task}.elf"),
+        };
+
+        msg!("extracting {name} to {output}");
+
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        File::create(output)?.write_all(&buffer)?;
+
+        return Ok(());
+    }
+
     let buffer = if let Some(ref filename) = subargs.file {
         let cursor = Cursor::new(archive);
         let mut archive = zip::ZipArchive::new(cursor)?;
@@ -181,6 +266,12 @@ fn extract(context: &mut ExecutionContext) -> Result<()> {
         for i in 0..archive.len() {
             let file = archive.by_index(i)?;
 
+            if let Some(ref task) = subargs.task {
+                if !task_member(file.name(), task) {
+                    continue;
+                }
+            }
+
             if file.name().contains(filename) {
                 found.push((i, file.name().to_string()));
             }
@@ -205,10 +296,24 @@ fn extract(context: &mut ExecutionContext) -> Result<()> {
             );
         }
 
-        msg!("extracting {} to stdout", found[0].1);
+        msg!("extracting {} to {destination}", found[0].1);
 
         let mut file = archive.by_index(found[0].0)?;
 
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        buffer
+    } else if let Some(ref task) = subargs.task {
+        let cursor = Cursor::new(archive);
+        let mut archive = zip::ZipArchive::new(cursor)?;
+        let name = task_elf(task);
+
+        let mut file = archive
+            .by_name(&name)
+            .map_err(|_| anyhow!("no ELF object found for \"{task}\""))?;
+
+        msg!("extracting {name} to {destination}");
+
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer)?;
         buffer
@@ -224,7 +329,7 @@ fn extract(context: &mut ExecutionContext) -> Result<()> {
         // As a precaution against naive use, we force an output file to be
         // specified if the entire archive is to be written.
         //
-        if subargs.file.is_none() {
+        if subargs.file.is_none() && subargs.task.is_none() {
             bail!("must specify output file name to extract entire archive");
         }
 
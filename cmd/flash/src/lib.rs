@@ -31,10 +31,34 @@
 //! includes a task with the `AuxFlash` API, two slots of auxiliary flash
 //! will be programmed after the image is written.  See RFD 311 for more
 //! information about auxiliary flash management.
+//!
+//! To also program external (QSPI or SPI NOR) flash attached to the target
+//! -- e.g., host boot flash -- specify an image for it with `--qspi`.  Once
+//! the archive has been flashed and the target has been reset, the external
+//! flash is erased and written by the new image via `hiffy`, and is then
+//! verified by comparing the hash of each sector as computed by the target
+//! to that of the image:
+//!
+//! ```console
+//! $ humility flash --qspi ./milan-spew-115k2-2dpc-0.4.1.bin
+//! humility: attaching with chip set to "STM32H753ZITx"
+//! humility: attached via ST-Link V3
+//! humility: flashing done
+//! humility: programming external flash with milan-spew-115k2-2dpc-0.4.1.bin
+//! humility: skipping sector 0
+//! humility: erased 16.00MB in 1 minute
+//! humility: flashed 16.00MB in 5 minutes
+//! humility: verified 16.00MB in 16 seconds
+//! ```
+//!
+//! To program only the external flash (leaving the image on the target
+//! as it is), use `--qspi-only`; in this case, the archive must already be
+//! flashed on the target.  As with `humility qspi`, sector 0 of external
+//! flash is not written unless `--qspi-write-sector0` is specified.
 
 use anyhow::{bail, Context, Result};
 use clap::{CommandFactory, Parser};
-use humility::{core::Core, hubris::*};
+use humility::{core::Core, hubris::*, Diagnostic};
 use humility_cli::{
     Cli, {ExecutionContext, Subcommand},
 };
@@ -78,6 +102,21 @@ struct FlashArgs {
     /// do not flash, just check if archive has been flashed
     #[clap(long, short = 'C', conflicts_with_all = &["force", "verify"])]
     check: bool,
+
+    /// image to program into external (QSPI/SPI NOR) flash
+    #[clap(long, value_name = "filename", conflicts_with = "check")]
+    qspi: Option<String>,
+
+    /// program only external flash; the archive must already be flashed
+    #[clap(
+        long, requires = "qspi",
+        conflicts_with_all = &["force", "verify", "force_openocd"]
+    )]
+    qspi_only: bool,
+
+    /// allow external flash image to write sector 0
+    #[clap(long, requires = "qspi")]
+    qspi_write_sector0: bool,
 }
 
 fn force_openocd(
@@ -94,6 +133,11 @@ fn force_openocd(
         bail!("cannot program an image with auxiliary flash through OpenOCD");
     }
 
+    // Likewise, external flash is programmed via hiffy.
+    if subargs.qspi.is_some() {
+        bail!("cannot program external flash through OpenOCD");
+    }
+
     //
     // We need to attach to (1) confirm that we're plugged into something
     // and (2) extract serial information.
@@ -104,7 +148,8 @@ fn force_openocd(
     };
 
     let serial = {
-        let mut c = humility::core::attach(probe, hubris)?;
+        let c = humility::core::attach(probe, hubris)?;
+        let mut c = humility_cmd::dry_run(args, c);
         let core = c.as_mut();
 
         validate(hubris, core, subargs)?;
@@ -256,18 +301,20 @@ fn validate(
                         return Ok(());
                     }
 
-                    bail!(
-                        "archive is already flashed on attached device; \
-                        use -F (\"--force\") to force re-flash"
-                    );
+                    bail!(Diagnostic::error(
+                        "archive is already flashed on attached device"
+                    )
+                    .code("already-flashed")
+                    .hint("use -F (\"--force\") to force re-flash"));
                 }
             } else {
                 core.run()?;
-                bail!(
-                    "archive appears to be already flashed on attached \
-                    device; use -F (\"--force\") to force re-flash or \
-                    -V (\"--verify\") to verify contents"
-                );
+                bail!(Diagnostic::error(
+                    "archive appears to be already flashed on attached device"
+                )
+                .code("already-flashed")
+                .hint("use -F (\"--force\") to force re-flash")
+                .hint("use -V (\"--verify\") to verify contents"));
             }
         }
         Err(err) => {
@@ -284,7 +331,8 @@ fn validate(
 fn flashcmd(context: &mut ExecutionContext) -> Result<()> {
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
     let hubris = context.archive.as_mut().unwrap();
-    let subargs = FlashArgs::try_parse_from(subargs)?;
+    let mut subargs = FlashArgs::try_parse_from(subargs)?;
+    subargs.dryrun |= context.cli.dry_run;
 
     let config = hubris.load_flash_config()?;
 
@@ -318,9 +366,18 @@ fn flashcmd(context: &mut ExecutionContext) -> Result<()> {
     };
 
     humility::msg!("attaching with chip set to {chip:x?}");
-    let mut c = humility::core::attach_for_flashing(probe, hubris, &chip)?;
+    let c = humility::core::attach_for_flashing(probe, hubris, &chip)?;
+    let mut c = humility_cmd::dry_run(&context.cli, c);
     let core = c.as_mut();
 
+    if subargs.qspi_only {
+        hubris.validate(core, HubrisValidate::ArchiveMatch).context(
+            "archive must be flashed to program only external flash",
+        )?;
+
+        return program_qspi(hubris, core, &subargs);
+    }
+
     validate(hubris, core, &subargs)?;
 
     if subargs.check {
@@ -337,8 +394,23 @@ fn flashcmd(context: &mut ExecutionContext) -> Result<()> {
     // error.  (It will hopefully be pretty clear to the user that a
     // half-flashed part is going to be in an ill-defined state!)
     //
+    let started = std::time::Instant::now();
     core.load(ihex_path)?;
 
+    if context.cli.verbose > 0 {
+        let nbytes = elf_chunks(&config.elf)?
+            .iter()
+            .map(|(_, slice)| slice.len() as u64)
+            .sum::<u64>();
+        let elapsed = started.elapsed();
+
+        humility::msg!(
+            "flashed {nbytes} bytes in {:.3}s ({})",
+            elapsed.as_secs_f64(),
+            humility::stats::rate(nbytes, elapsed)
+        );
+    }
+
     //
     // On Gimlet Rev B, the BOOT0 pin is unstrapped -- and during a flash,
     // it seems to float high enough to bounce the part onto the wrong
@@ -366,9 +438,34 @@ fn flashcmd(context: &mut ExecutionContext) -> Result<()> {
     // This is called out in RFD 311 as a weakness of our approach!
     try_program_auxflash(hubris, core)?;
     humility::msg!("flashing done");
+
+    if subargs.qspi.is_some() {
+        program_qspi(hubris, core, &subargs)?;
+    }
+
     Ok(())
 }
 
+fn program_qspi(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &FlashArgs,
+) -> Result<()> {
+    let filename = subargs.qspi.as_ref().unwrap();
+    let image = std::fs::read(filename)
+        .with_context(|| format!("failed to read {filename}"))?;
+
+    humility::msg!("programming external flash with {filename}");
+
+    cmd_qspi::qspi_write_image(
+        hubris,
+        core,
+        &image,
+        subargs.qspi_write_sector0,
+        30_000,
+    )
+}
+
 fn try_program_auxflash(
     hubris: &HubrisArchive,
     core: &mut dyn Core,
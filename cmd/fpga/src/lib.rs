@@ -0,0 +1,370 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility fpga`
+//!
+//! `humility fpga` displays the state of an FPGA managed by a task that
+//! implements the `Fpga` Idol interface, and can load a bitstream into it.
+//! If more than one task implements `Fpga`, the task must be specified
+//! with `--task` (`-t`); the FPGA is specified by its index among the
+//! devices of that task via `--device` (`-d`), and defaults to 0:
+//!
+//! ```console
+//! $ humility fpga --task ecp5_front_io
+//! humility: attached via ST-Link V3
+//!          task => ecp5_front_io
+//!        device => 0
+//!         state => RunningUserDesign
+//!     device ID => 0x41111043
+//!   user design => enabled
+//!       version => 0x00000003
+//! ```
+//!
+//! To load a bitstream, use `--load` (`-l`), specifying `--compressed` if
+//! the bitstream is compressed.  The FPGA is locked for the duration of the
+//! load, and the bitstream is delivered to the task in chunks; once the
+//! load is complete, the state of the FPGA is checked and its design
+//! version register is read back:
+//!
+//! ```console
+//! $ humility fpga --task ecp5_front_io --load front_io.bit
+//! humility: attached via ST-Link V3
+//! humility: front_io.bit is 2215144 bytes, CRC32 0x8c6e3c6a
+//! humility: loading [##############################] 2215144/2215144
+//! humility: FPGA is RunningUserDesign; design version is 0x00000003
+//! ```
+//!
+//! The FPGA itself checks the integrity of the bitstream as it is loaded,
+//! and rejects a bitstream that fails this check; such a failure will be
+//! reported as the FPGA failing to reach the `RunningUserDesign` state.  To
+//! additionally guard against loading the wrong (or a corrupted) file, the
+//! expected CRC32 of the bitstream can be specified with `--crc`; the load
+//! will not be attempted if the bitstream does not match.
+//!
+//! The design version is read from the registers of the user design
+//! starting at the address specified by `--version-addr` (0x4 by default),
+//! as a big-endian 32-bit value.
+//!
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::reflect::Value;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_hiffy::*;
+use humility_idol::{IdolArgument, IdolOperation};
+use indicatif::{ProgressBar, ProgressStyle};
+
+#[derive(Parser, Debug)]
+#[clap(name = "fpga", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct FpgaArgs {
+    /// sets timeout
+    #[clap(
+        long, short = 'T', default_value_t = 15000, value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+
+    /// task that manages the FPGA
+    #[clap(long, short, value_name = "task")]
+    task: Option<String>,
+
+    /// index of the FPGA among the task's devices
+    #[clap(
+        long, short, value_name = "index", default_value_t = 0,
+        parse(try_from_str = parse_int::parse)
+    )]
+    device: u8,
+
+    /// load the specified bitstream
+    #[clap(long, short, value_name = "bitstream")]
+    load: Option<String>,
+
+    /// the bitstream is compressed
+    #[clap(long, requires = "load")]
+    compressed: bool,
+
+    /// expected CRC32 of the bitstream
+    #[clap(
+        long, value_name = "crc", requires = "load",
+        parse(try_from_str = parse_int::parse)
+    )]
+    crc: Option<u32>,
+
+    /// address of the design version register
+    #[clap(
+        long, value_name = "addr", default_value_t = 0x4,
+        parse(try_from_str = parse_int::parse)
+    )]
+    version_addr: u16,
+}
+
+struct FpgaHandler<'a> {
+    hubris: &'a HubrisArchive,
+    core: &'a mut dyn Core,
+    context: HiffyContext<'a>,
+    task: HubrisTask,
+    device: u8,
+}
+
+impl<'a> FpgaHandler<'a> {
+    fn call(
+        &mut self,
+        name: &str,
+        args: &[(&str, IdolArgument)],
+        lease: Option<HiffyLease>,
+    ) -> Result<Value> {
+        let op =
+            IdolOperation::new(self.hubris, "Fpga", name, Some(&self.task))
+                .with_context(|| format!("could not find Fpga.{name}"))?;
+
+        match hiffy_call(
+            self.hubris,
+            self.core,
+            &mut self.context,
+            &op,
+            args,
+            lease,
+        )? {
+            Ok(val) => Ok(val),
+            Err(e) => bail!("Fpga.{name} failed: {e}"),
+        }
+    }
+
+    fn call_device(&mut self, name: &str) -> Result<Value> {
+        let device = ("device_index", IdolArgument::Scalar(self.device.into()));
+        self.call(name, &[device], None)
+    }
+
+    fn state(&mut self) -> Result<String> {
+        let val = self.call_device("device_state")?;
+        Ok(val.as_enum()?.disc().to_string())
+    }
+
+    fn id(&mut self) -> Result<u32> {
+        let val = self.call_device("device_id")?;
+
+        val.as_base()?
+            .as_u32()
+            .ok_or_else(|| anyhow!("Fpga.device_id returned {val:?}"))
+    }
+
+    fn user_design_enabled(&mut self) -> Result<bool> {
+        let val = self.call_device("user_design_enabled")?;
+
+        val.as_base()?
+            .as_bool()
+            .ok_or_else(|| anyhow!("Fpga.user_design_enabled returned {val:?}"))
+    }
+
+    fn read_reg(&mut self, addr: u16) -> Result<u8> {
+        let val = self.call(
+            "user_design_read_reg",
+            &[
+                ("device_index", IdolArgument::Scalar(self.device.into())),
+                ("addr", IdolArgument::Scalar(addr.into())),
+            ],
+            None,
+        )?;
+
+        val.as_base()?.as_u8().ok_or_else(|| {
+            anyhow!("Fpga.user_design_read_reg returned {val:?}")
+        })
+    }
+
+    fn version(&mut self, addr: u16) -> Result<u32> {
+        let mut version = 0;
+
+        for offset in 0..4 {
+            version = (version << 8) | u32::from(self.read_reg(addr + offset)?);
+        }
+
+        Ok(version)
+    }
+
+    fn load(&mut self, bitstream: &[u8], compressed: bool) -> Result<()> {
+        self.call_device("lock")?;
+
+        let rval = self.load_locked(bitstream, compressed);
+
+        //
+        // Release our lock regardless of the outcome of the load, lest the
+        // FPGA be rendered inaccessible to the task's other clients.
+        //
+        self.call("release", &[], None)?;
+
+        rval
+    }
+
+    fn load_locked(
+        &mut self,
+        bitstream: &[u8],
+        compressed: bool,
+    ) -> Result<()> {
+        let kind = if compressed { "Compressed" } else { "Uncompressed" };
+
+        self.call(
+            "start_bitstream_load",
+            &[
+                ("device_index", IdolArgument::Scalar(self.device.into())),
+                ("bitstream_type", IdolArgument::String(kind)),
+            ],
+            None,
+        )?;
+
+        let bar = ProgressBar::new(bitstream.len() as u64);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("humility: loading [{bar:30}] {bytes}/{total_bytes}"),
+        );
+
+        let chunksize = self.context.data_size();
+
+        for (i, chunk) in bitstream.chunks(chunksize).enumerate() {
+            self.call(
+                "continue_bitstream_load",
+                &[],
+                Some(HiffyLease::Write(chunk)),
+            )?;
+
+            bar.set_position((i * chunksize + chunk.len()) as u64);
+        }
+
+        bar.finish_and_clear();
+
+        self.call("finish_bitstream_load", &[], None)?;
+
+        Ok(())
+    }
+}
+
+///
+/// Determines the task that manages the FPGA, which must be specified if
+/// more than one task implements the `Fpga` interface.
+///
+fn fpga_task(hubris: &HubrisArchive, task: Option<&str>) -> Result<HubrisTask> {
+    let mut found = vec![];
+
+    for i in 0..hubris.ntasks() {
+        let module = hubris.lookup_module(HubrisTask::Task(i as u32))?;
+
+        if module.iface.as_ref().map_or(false, |iface| iface.name == "Fpga") {
+            found.push((module.name.as_str(), module.task));
+        }
+    }
+
+    if let Some(task) = task {
+        return match found.iter().find(|(name, _)| *name == task) {
+            Some((_, t)) => Ok(*t),
+            None => bail!("task \"{task}\" does not implement Fpga"),
+        };
+    }
+
+    match found[..] {
+        [] => bail!("no task implements the Fpga interface"),
+        [(_, task)] => Ok(task),
+        _ => bail!(
+            "more than one task implements Fpga; specify one with --task: {}",
+            found.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+fn fpga(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = FpgaArgs::try_parse_from(subargs)?;
+
+    let task = fpga_task(hubris, subargs.task.as_deref())?;
+
+    let bitstream = match &subargs.load {
+        Some(filename) => {
+            let bitstream = std::fs::read(filename)
+                .with_context(|| format!("failed to read {filename}"))?;
+
+            let mut crc = crc_any::CRCu32::crc32();
+            crc.digest(&bitstream);
+            let crc = crc.get_crc();
+
+            humility::msg!(
+                "{filename} is {} bytes, CRC32 0x{crc:08x}",
+                bitstream.len()
+            );
+
+            if let Some(expected) = subargs.crc {
+                if crc != expected {
+                    bail!(
+                        "CRC32 of {filename} is 0x{crc:08x}, \
+                        expected 0x{expected:08x}"
+                    );
+                }
+            }
+
+            Some(bitstream)
+        }
+        None => None,
+    };
+
+    let context = HiffyContext::new(hubris, core, subargs.timeout)?;
+
+    let mut fpga =
+        FpgaHandler { hubris, core, context, task, device: subargs.device };
+
+    if let Some(bitstream) = bitstream {
+        fpga.load(&bitstream, subargs.compressed)?;
+
+        let state = fpga.state()?;
+
+        if state != "RunningUserDesign" {
+            bail!("bitstream loaded, but FPGA is in state {state}");
+        }
+
+        let version = fpga.version(subargs.version_addr)?;
+        humility::msg!("FPGA is {state}; design version is 0x{version:08x}");
+
+        return Ok(());
+    }
+
+    let state = fpga.state()?;
+    let id = fpga.id()?;
+    let enabled = fpga.user_design_enabled()?;
+
+    let version = if state == "RunningUserDesign" {
+        format!("0x{:08x}", fpga.version(subargs.version_addr)?)
+    } else {
+        "-".to_string()
+    };
+
+    let name = &hubris.lookup_module(task)?.name;
+
+    println!("{:>13} => {name}", "task");
+    println!("{:>13} => {}", "device", subargs.device);
+    println!("{:>13} => {state}", "state");
+    println!("{:>13} => 0x{id:08x}", "device ID");
+    println!(
+        "{:>13} => {}",
+        "user design",
+        if enabled { "enabled" } else { "disabled" }
+    );
+    println!("{:>13} => {version}", "version");
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: FpgaArgs::command(),
+        name: "fpga",
+        run: fpga,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
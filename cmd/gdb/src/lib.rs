@@ -16,17 +16,48 @@
 //! because `humility gdb` connects to it multiple times (once to check the
 //! app id, then again to run the console).
 //!
+//! Alternatively, `humility gdb` can itself act as a GDB server: the `--listen`
+//! option serves the GDB remote serial protocol on the specified port over
+//! whatever core Humility attaches to (a probe, OpenOCD, or a dump), allowing
+//! GDB or LLDB to connect directly:
+//!
+//! ```console
+//! % humility -a ./target/gimlet/dist/default/build-gimlet.zip gdb --listen 3333
+//! humility: attached via ST-Link V3
+//! humility: listening for GDB on 127.0.0.1:3333
+//! ```
+//!
+//! And then, in another terminal:
+//!
+//! ```console
+//! % arm-none-eabi-gdb -ex "target extended-remote :3333" final.elf
+//! ```
+//!
+//! The target is halted when GDB connects, and resumed when GDB detaches.
+//! Hubris tasks are presented as GDB threads (`info threads`), with the thread
+//! ID of a task being one more than its task index; selecting a thread that is
+//! not running displays the registers saved for that task. Breakpoints are
+//! implemented with the Flash Patch and Breakpoint unit, and are therefore
+//! limited in number; when serving a dump, memory and registers can be examined
+//! but execution cannot be controlled.
+//!
 
+use std::net::TcpListener;
 use std::process::{Command, Stdio};
 
 use cmd_openocd::get_probe_serial;
 
+use humility::msg;
 use humility_cli::{ExecutionContext, Subcommand};
-use humility_cmd::{Archive, Command as HumilityCmd, CommandKind};
+use humility_cmd::{
+    Archive, Attach, Command as HumilityCmd, CommandKind, Validate,
+};
 
 use anyhow::{bail, Context, Result};
 use clap::{CommandFactory, Parser};
 
+mod stub;
+
 #[derive(Parser, Debug)]
 #[clap(
     name = "gdb", about = env!("CARGO_PKG_DESCRIPTION"),
@@ -47,17 +78,55 @@ struct GdbArgs {
     /// specifies the probe serial number to use with OpenOCD
     #[clap(long, requires = "run_openocd")]
     serial: Option<String>,
+
+    /// rather than launching GDB, serve the GDB remote protocol on the
+    /// specified port over whatever core Humility is attached to
+    #[clap(
+        long, value_name = "port",
+        conflicts_with_all = &["load", "run_openocd", "openocd", "serial"]
+    )]
+    listen: Option<u16>,
+}
+
+fn gdb_serve(context: &mut ExecutionContext, port: u16) -> Result<()> {
+    let hubris = context.archive.as_ref().unwrap();
+    let core = &mut **context.core.as_mut().unwrap();
+
+    if core.is_net() {
+        bail!("cannot serve GDB over a network-attached core");
+    }
+
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .with_context(|| format!("failed to listen on port {port}"))?;
+
+    msg!("listening for GDB on {}", listener.local_addr()?);
+
+    let (stream, peer) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    msg!("accepted connection from {peer}");
+
+    stub::GdbStub::new(hubris, core, stream)?.serve()
 }
 
 fn gdb(context: &mut ExecutionContext) -> Result<()> {
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = GdbArgs::try_parse_from(subargs)?;
+
+    if let Some(port) = subargs.listen {
+        return humility_cmd::attach(
+            context,
+            Attach::Any,
+            Validate::Booted,
+            |context| gdb_serve(context, port),
+        );
+    }
+
     let hubris = context.archive.as_ref().unwrap();
 
     if context.cli.probe.is_some() {
         bail!("Cannot specify --probe with `gdb` subcommand");
     }
 
-    let subargs = GdbArgs::try_parse_from(subargs)?;
     let serial = get_probe_serial(&context.cli, subargs.serial.clone())?;
 
     let work_dir = tempfile::tempdir()?;
@@ -193,7 +262,7 @@ fn gdb(context: &mut ExecutionContext) -> Result<()> {
     cmd.current_dir(work_dir.path());
 
     // Run GDB, ignoring Ctrl-C (so it can handle them)
-    ctrlc::set_handler(|| {}).expect("Error setting Ctrl-C handler");
+    humility_cmd::interrupted()?;
     let status = cmd.status()?;
     if !status.success() {
         anyhow::bail!("command failed, see output for details");
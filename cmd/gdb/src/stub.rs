@@ -0,0 +1,862 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! A GDB remote serial protocol server, backed by a Humility `Core`.
+//!
+//! Hubris tasks are presented to GDB as threads:  the thread ID of a task is
+//! its index plus one (GDB reserves 0), and the kernel -- when it is what the
+//! CPU is running -- is presented as one additional thread.  Registers of the
+//! thread that the CPU is running are read from (and written to) the core;
+//! registers of all other tasks are recovered from their saved state, and
+//! are read-only.
+//!
+//! Breakpoints are implemented using the Flash Patch and Breakpoint unit;
+//! because Hubris executes from flash, software breakpoints are implemented
+//! as hardware breakpoints.
+//!
+
+use anyhow::{bail, Result};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::msg;
+use humility_arch_arm::ARMRegister;
+use humility_cortex::debug::{DHCSR, FP_COMP0, FP_CTRL};
+use num_traits::FromPrimitive;
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+/// The registers that we present, in the order of our target description
+const REGISTERS: [ARMRegister; 17] = [
+    ARMRegister::R0,
+    ARMRegister::R1,
+    ARMRegister::R2,
+    ARMRegister::R3,
+    ARMRegister::R4,
+    ARMRegister::R5,
+    ARMRegister::R6,
+    ARMRegister::R7,
+    ARMRegister::R8,
+    ARMRegister::R9,
+    ARMRegister::R10,
+    ARMRegister::R11,
+    ARMRegister::R12,
+    ARMRegister::SP,
+    ARMRegister::LR,
+    ARMRegister::PC,
+    ARMRegister::PSR,
+];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>arm</architecture>
+  <feature name="org.gnu.gdb.arm.m-profile">
+    <reg name="r0" bitsize="32"/>
+    <reg name="r1" bitsize="32"/>
+    <reg name="r2" bitsize="32"/>
+    <reg name="r3" bitsize="32"/>
+    <reg name="r4" bitsize="32"/>
+    <reg name="r5" bitsize="32"/>
+    <reg name="r6" bitsize="32"/>
+    <reg name="r7" bitsize="32"/>
+    <reg name="r8" bitsize="32"/>
+    <reg name="r9" bitsize="32"/>
+    <reg name="r10" bitsize="32"/>
+    <reg name="r11" bitsize="32"/>
+    <reg name="r12" bitsize="32"/>
+    <reg name="sp" bitsize="32" type="data_ptr"/>
+    <reg name="lr" bitsize="32"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="xpsr" bitsize="32" regnum="25"/>
+  </feature>
+</target>
+"#;
+
+/// The largest memory read that we will perform for a single packet
+const MAX_READ: usize = 2048;
+
+/// SIGTRAP, which we report for every stop
+const SIGTRAP: u8 = 5;
+
+#[derive(Debug, PartialEq)]
+enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+/// Our state of the Flash Patch and Breakpoint unit
+struct Fpb {
+    ctrl: FP_CTRL,
+    comparators: Vec<Option<u32>>,
+}
+
+pub struct GdbStub<'a> {
+    hubris: &'a HubrisArchive,
+    core: &'a mut dyn Core,
+    stream: TcpStream,
+    pending: Vec<u8>,
+    noack: bool,
+    ntasks: u32,
+    /// The thread selected for register operations, if any
+    thread: Option<u32>,
+    fpb: Option<Fpb>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>> {
+    let bytes = s.as_bytes();
+
+    if bytes.len() % 2 != 0 {
+        bail!("odd-length hex string");
+    }
+
+    let digit = |c: u8| match (c as char).to_digit(16) {
+        Some(d) => Ok(d as u8),
+        None => bail!("invalid hex digit in \"{s}\""),
+    };
+
+    bytes
+        .chunks_exact(2)
+        .map(|pair| Ok((digit(pair[0])? << 4) | digit(pair[1])?))
+        .collect()
+}
+
+///
+/// Frames a packet for sending:  escapes the bytes that must be escaped, and
+/// appends the checksum (which is over the packet as sent).
+///
+fn frame(data: &str) -> Vec<u8> {
+    let mut framed = vec![b'$'];
+
+    for &c in data.as_bytes() {
+        match c {
+            b'#' | b'$' | b'}' | b'*' => {
+                framed.push(b'}');
+                framed.push(c ^ 0x20);
+            }
+            _ => framed.push(c),
+        }
+    }
+
+    let checksum = framed[1..].iter().fold(0u8, |s, &c| s.wrapping_add(c));
+    framed.extend_from_slice(format!("#{checksum:02x}").as_bytes());
+    framed
+}
+
+///
+/// Reads a packet (or an interrupt) from a source of bytes, discarding
+/// anything that precedes it, and returning `None` if the source is
+/// exhausted first.  A packet is returned unescaped, along with whether its
+/// checksum was valid.
+///
+fn read_packet(
+    mut next: impl FnMut() -> Result<Option<u8>>,
+) -> Result<Option<(Packet, bool)>> {
+    loop {
+        match next()? {
+            None => return Ok(None),
+            Some(0x03) => return Ok(Some((Packet::Interrupt, true))),
+            Some(b'$') => break,
+            Some(_) => continue,
+        }
+    }
+
+    //
+    // The checksum is over the packet as sent -- that is, before any
+    // escaped bytes are unescaped.
+    //
+    let mut data = vec![];
+    let mut sum = 0u8;
+
+    loop {
+        match next()? {
+            None => return Ok(None),
+            Some(b'#') => break,
+            Some(b'}') => match next()? {
+                Some(c) => {
+                    sum = sum.wrapping_add(b'}').wrapping_add(c);
+                    data.push(c ^ 0x20);
+                }
+                None => return Ok(None),
+            },
+            Some(c) => {
+                sum = sum.wrapping_add(c);
+                data.push(c);
+            }
+        }
+    }
+
+    let mut checksum = [0u8; 2];
+
+    for c in checksum.iter_mut() {
+        match next()? {
+            Some(b) => *c = b,
+            None => return Ok(None),
+        }
+    }
+
+    let valid = std::str::from_utf8(&checksum)
+        .ok()
+        .and_then(|c| u8::from_str_radix(c, 16).ok())
+        == Some(sum);
+
+    let command = String::from_utf8_lossy(&data).to_string();
+
+    Ok(Some((Packet::Command(command), valid)))
+}
+
+fn parse_hex(s: &str) -> Result<u32> {
+    Ok(u32::from_str_radix(s, 16)?)
+}
+
+/// Parses a packet argument of the form `addr,len`
+fn parse_range(s: &str) -> Result<(u32, usize)> {
+    match s.split_once(',') {
+        Some((addr, len)) => Ok((parse_hex(addr)?, parse_hex(len)? as usize)),
+        None => bail!("malformed range \"{s}\""),
+    }
+}
+
+/// Parses a thread ID, returning `None` for "any thread" or "all threads"
+fn parse_thread(s: &str) -> Result<Option<u32>> {
+    match s {
+        "0" | "-1" => Ok(None),
+        _ => Ok(Some(parse_hex(s)?)),
+    }
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(
+        hubris: &'a HubrisArchive,
+        core: &'a mut dyn Core,
+        stream: TcpStream,
+    ) -> Result<Self> {
+        let (_, ntasks) = hubris.task_table(core)?;
+
+        Ok(Self {
+            hubris,
+            core,
+            stream,
+            pending: vec![],
+            noack: false,
+            ntasks,
+            thread: None,
+            fpb: None,
+        })
+    }
+
+    fn is_live(&self) -> bool {
+        !self.core.is_dump() && !self.core.is_archive()
+    }
+
+    /// Returns the thread that the CPU is currently running
+    fn current_thread(&mut self) -> Result<u32> {
+        Ok(match self.hubris.current_task(self.core)? {
+            Some(HubrisTask::Task(ndx)) => ndx + 1,
+            _ => self.ntasks + 1,
+        })
+    }
+
+    fn thread_name(&self, thread: u32) -> String {
+        if !(1..=self.ntasks).contains(&thread) {
+            "kernel".to_string()
+        } else {
+            match self.hubris.task_name(thread as usize - 1) {
+                Some(name) => name.to_string(),
+                None => format!("task {}", thread - 1),
+            }
+        }
+    }
+
+    fn valid_thread(&mut self, thread: u32) -> Result<bool> {
+        Ok((1..=self.ntasks).contains(&thread)
+            || thread == self.current_thread()?)
+    }
+
+    fn recv_byte(&mut self) -> Result<Option<u8>> {
+        if self.pending.is_empty() {
+            let mut buf = [0u8; 4096];
+            let n = self.stream.read(&mut buf)?;
+
+            if n == 0 {
+                return Ok(None);
+            }
+
+            self.pending.extend_from_slice(&buf[..n]);
+        }
+
+        Ok(Some(self.pending.remove(0)))
+    }
+
+    fn recv_packet(&mut self) -> Result<Option<Packet>> {
+        loop {
+            let (packet, valid) = match read_packet(|| self.recv_byte())? {
+                Some(received) => received,
+                None => return Ok(None),
+            };
+
+            if !self.noack && matches!(packet, Packet::Command(_)) {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+
+                //
+                // Having rejected the packet, we expect it to be resent.
+                //
+                if !valid {
+                    continue;
+                }
+            }
+
+            return Ok(Some(packet));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> Result<()> {
+        self.stream.write_all(&frame(data))?;
+        self.stream.flush()?;
+
+        Ok(())
+    }
+
+    fn stop_reply(&mut self) -> Result<String> {
+        let thread = self.current_thread()?;
+        Ok(format!("T{SIGTRAP:02x}thread:{thread:x};"))
+    }
+
+    ///
+    /// Returns the registers for the selected thread:  the live registers if
+    /// it is the thread that the CPU is running, and the saved registers
+    /// otherwise.
+    ///
+    fn registers(&mut self) -> Result<BTreeMap<ARMRegister, u32>> {
+        let current = self.current_thread()?;
+
+        match self.thread {
+            Some(thread) if thread != current && thread <= self.ntasks => {
+                let task = HubrisTask::Task(thread - 1);
+                self.hubris.registers(self.core, task)
+            }
+            _ => {
+                let mut regs = BTreeMap::new();
+
+                for reg in REGISTERS {
+                    regs.insert(reg, self.core.read_reg(reg)?);
+                }
+
+                Ok(regs)
+            }
+        }
+    }
+
+    fn writable(&mut self) -> Result<bool> {
+        let current = self.current_thread()?;
+
+        Ok(self.is_live()
+            && self.thread.map(|thread| thread == current).unwrap_or(true))
+    }
+
+    fn read_registers(&mut self) -> Result<String> {
+        let regs = self.registers()?;
+
+        Ok(REGISTERS
+            .iter()
+            .map(|reg| match regs.get(reg) {
+                Some(val) => hex(&val.to_le_bytes()),
+                None => "xxxxxxxx".to_string(),
+            })
+            .collect())
+    }
+
+    fn write_registers(&mut self, data: &str) -> Result<String> {
+        if !self.writable()? {
+            return Ok("E01".to_string());
+        }
+
+        let bytes = unhex(data)?;
+
+        for (reg, val) in REGISTERS.iter().zip(bytes.chunks_exact(4)) {
+            let val = u32::from_le_bytes(val.try_into().unwrap());
+            self.core.write_reg(*reg, val)?;
+        }
+
+        Ok("OK".to_string())
+    }
+
+    fn register_index(&self, ndx: u32) -> Option<ARMRegister> {
+        //
+        // Our target description places xPSR at register 25, where GDB
+        // expects it on M-profile parts.
+        //
+        match ndx {
+            0..=15 => ARMRegister::from_u32(ndx),
+            25 => Some(ARMRegister::PSR),
+            _ => None,
+        }
+    }
+
+    fn read_register(&mut self, arg: &str) -> Result<String> {
+        let Some(reg) = self.register_index(parse_hex(arg)?) else {
+            return Ok("E01".to_string());
+        };
+
+        Ok(match self.registers()?.get(&reg) {
+            Some(val) => hex(&val.to_le_bytes()),
+            None => "xxxxxxxx".to_string(),
+        })
+    }
+
+    fn write_register(&mut self, arg: &str) -> Result<String> {
+        let Some((ndx, val)) = arg.split_once('=') else {
+            bail!("malformed register write \"{arg}\"");
+        };
+
+        let Some(reg) = self.register_index(parse_hex(ndx)?) else {
+            return Ok("E01".to_string());
+        };
+
+        let bytes = unhex(val)?;
+
+        if bytes.len() != 4 || !self.writable()? {
+            return Ok("E01".to_string());
+        }
+
+        self.core
+            .write_reg(reg, u32::from_le_bytes(bytes.try_into().unwrap()))?;
+        Ok("OK".to_string())
+    }
+
+    fn read_memory(&mut self, arg: &str) -> Result<String> {
+        let (addr, len) = parse_range(arg)?;
+        let mut buf = vec![0u8; len.min(MAX_READ)];
+
+        Ok(match self.core.read_8(addr, &mut buf) {
+            Ok(()) => hex(&buf),
+            Err(_) => "E01".to_string(),
+        })
+    }
+
+    fn write_memory(&mut self, arg: &str) -> Result<String> {
+        let Some((range, data)) = arg.split_once(':') else {
+            bail!("malformed memory write \"{arg}\"");
+        };
+
+        let (addr, len) = parse_range(range)?;
+        let data = unhex(data)?;
+
+        if data.len() != len || !self.is_live() {
+            return Ok("E01".to_string());
+        }
+
+        Ok(match self.core.write_8(addr, &data) {
+            Ok(()) => "OK".to_string(),
+            Err(_) => "E01".to_string(),
+        })
+    }
+
+    fn fpb(&mut self) -> Result<&mut Fpb> {
+        if self.fpb.is_none() {
+            let mut ctrl = FP_CTRL::read(self.core)?;
+            let ncomp = ctrl.num_code() as usize;
+
+            if ncomp == 0 {
+                bail!("no breakpoint comparators available");
+            }
+
+            ctrl.set_key(true);
+            ctrl.set_enable(true);
+            ctrl.write(self.core)?;
+
+            self.fpb = Some(Fpb { ctrl, comparators: vec![None; ncomp] });
+        }
+
+        Ok(self.fpb.as_mut().unwrap())
+    }
+
+    fn breakpoint(&mut self, arg: &str, insert: bool) -> Result<String> {
+        let mut fields = arg.splitn(3, ',');
+
+        let (Some(kind), Some(addr)) = (fields.next(), fields.next()) else {
+            bail!("malformed breakpoint \"{arg}\"");
+        };
+
+        //
+        // Software breakpoints are implemented with hardware breakpoints;
+        // we don't support watchpoints.
+        //
+        if !matches!(kind, "0" | "1") {
+            return Ok("".to_string());
+        }
+
+        if !self.is_live() {
+            return Ok("E01".to_string());
+        }
+
+        let addr = parse_hex(addr)?;
+        let fpb = self.fpb()?;
+
+        let slot = if insert {
+            if fpb.comparators.contains(&Some(addr)) {
+                return Ok("OK".to_string());
+            }
+
+            fpb.comparators.iter().position(|c| c.is_none())
+        } else {
+            fpb.comparators.iter().position(|&c| c == Some(addr))
+        };
+
+        let Some(slot) = slot else {
+            return Ok(if insert { "E02" } else { "OK" }.to_string());
+        };
+
+        let val = if insert {
+            match fpb.ctrl.comparator(addr) {
+                Ok(val) => val,
+                Err(_) => return Ok("E03".to_string()),
+            }
+        } else {
+            0
+        };
+
+        fpb.comparators[slot] = if insert { Some(addr) } else { None };
+
+        self.core.write_word_32(FP_COMP0 + (slot as u32) * 4, val)?;
+        Ok("OK".to_string())
+    }
+
+    ///
+    /// Clears any breakpoints that we have set; this is called on detach to
+    /// assure that we don't leave the target with breakpoints armed.
+    ///
+    fn clear_breakpoints(&mut self) -> Result<()> {
+        if let Some(fpb) = self.fpb.take() {
+            for (slot, addr) in fpb.comparators.iter().enumerate() {
+                if addr.is_some() {
+                    self.core.write_word_32(FP_COMP0 + (slot as u32) * 4, 0)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks for an interrupt from GDB without blocking
+    fn interrupted(&mut self) -> Result<bool> {
+        let mut buf = [0u8; 256];
+
+        self.stream.set_nonblocking(true)?;
+        let rval = self.stream.read(&mut buf);
+        self.stream.set_nonblocking(false)?;
+
+        match rval {
+            Ok(0) => bail!("connection closed"),
+            Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        match self.pending.iter().position(|&c| c == 0x03) {
+            Some(pos) => {
+                self.pending.remove(pos);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn resume(&mut self, arg: &str, step: bool) -> Result<String> {
+        if !self.is_live() {
+            humility::warn!("cannot resume execution of a dump");
+            return Ok("E01".to_string());
+        }
+
+        if !arg.is_empty() {
+            self.core.write_reg(ARMRegister::PC, parse_hex(arg)?)?;
+        }
+
+        self.thread = None;
+
+        if step {
+            self.core.step()?;
+            return self.stop_reply();
+        }
+
+        self.core.run()?;
+
+        loop {
+            if DHCSR::read(self.core)?.halted() {
+                break;
+            }
+
+            if self.interrupted()? {
+                self.core.halt()?;
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        self.stop_reply()
+    }
+
+    fn query(&mut self, query: &str) -> Result<String> {
+        let (name, args) = match query.split_once(':') {
+            Some((name, args)) => (name, args),
+            None => (query, ""),
+        };
+
+        Ok(match name {
+            "Supported" => {
+                "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+"
+                    .to_string()
+            }
+            "Attached" => "1".to_string(),
+            "C" => format!("QC{:x}", self.current_thread()?),
+            "fThreadInfo" => {
+                let mut threads = (1..=self.ntasks)
+                    .map(|t| format!("{t:x}"))
+                    .collect::<Vec<_>>();
+
+                let current = self.current_thread()?;
+
+                if current > self.ntasks {
+                    threads.push(format!("{current:x}"));
+                }
+
+                format!("m{}", threads.join(","))
+            }
+            "sThreadInfo" => "l".to_string(),
+            "Symbol" => "OK".to_string(),
+            "Xfer" => {
+                let prefix = "features:read:target.xml:";
+
+                let Some(range) = args.strip_prefix(prefix) else {
+                    return Ok("E00".to_string());
+                };
+
+                let (offset, len) = parse_range(range)?;
+                let xml = TARGET_XML.as_bytes();
+                let offset = offset as usize;
+
+                if offset >= xml.len() {
+                    "l".to_string()
+                } else {
+                    let end = (offset + len).min(xml.len());
+                    let more = if end < xml.len() { "m" } else { "l" };
+                    format!(
+                        "{more}{}",
+                        String::from_utf8_lossy(&xml[offset..end])
+                    )
+                }
+            }
+            _ => {
+                if let Some(thread) = query.strip_prefix("ThreadExtraInfo,") {
+                    let name = self.thread_name(parse_hex(thread)?);
+                    hex(name.as_bytes())
+                } else {
+                    "".to_string()
+                }
+            }
+        })
+    }
+
+    fn dispatch(&mut self, packet: &str) -> Result<Option<String>> {
+        let len = packet.chars().next().map(char::len_utf8).unwrap_or(0);
+        let (cmd, arg) = packet.split_at(len);
+
+        Ok(Some(match cmd {
+            "?" => self.stop_reply()?,
+            "g" => self.read_registers()?,
+            "G" => self.write_registers(arg)?,
+            "p" => self.read_register(arg)?,
+            "P" => self.write_register(arg)?,
+            "m" => self.read_memory(arg)?,
+            "M" => self.write_memory(arg)?,
+            "c" => self.resume(arg, false)?,
+            "s" => self.resume(arg, true)?,
+            "Z" => self.breakpoint(arg, true)?,
+            "z" => self.breakpoint(arg, false)?,
+            "H" => {
+                if let Some(thread) = arg.strip_prefix('g') {
+                    let thread = parse_thread(thread)?;
+
+                    match thread {
+                        Some(t) if !self.valid_thread(t)? => "E01".to_string(),
+                        _ => {
+                            self.thread = thread;
+                            "OK".to_string()
+                        }
+                    }
+                } else {
+                    "OK".to_string()
+                }
+            }
+            "T" => match parse_thread(arg)? {
+                Some(t) if !self.valid_thread(t)? => "E01".to_string(),
+                _ => "OK".to_string(),
+            },
+            "q" => self.query(arg)?,
+            "Q" if arg == "StartNoAckMode" => "OK".to_string(),
+            "D" => {
+                self.send("OK")?;
+                return Ok(None);
+            }
+            "k" => return Ok(None),
+            _ => "".to_string(),
+        }))
+    }
+
+    ///
+    /// Serves GDB on our connection until it detaches or disconnects.  The
+    /// target is halted upon connection, and resumed upon detach.
+    ///
+    pub fn serve(&mut self) -> Result<()> {
+        if self.is_live() {
+            self.core.halt()?;
+        }
+
+        let result = self.serve_packets();
+
+        if self.is_live() {
+            self.clear_breakpoints()?;
+            self.core.run()?;
+        }
+
+        result
+    }
+
+    fn serve_packets(&mut self) -> Result<()> {
+        loop {
+            let packet = match self.recv_packet()? {
+                None => {
+                    msg!("connection closed");
+                    return Ok(());
+                }
+                Some(Packet::Interrupt) => {
+                    let reply = self.stop_reply()?;
+                    self.send(&reply)?;
+                    continue;
+                }
+                Some(Packet::Command(packet)) => packet,
+            };
+
+            let reply = match self.dispatch(&packet) {
+                Ok(Some(reply)) => reply,
+                Ok(None) => {
+                    msg!("gdb detached");
+                    return Ok(());
+                }
+                Err(err) => {
+                    humility::warn!("\"{packet}\" failed: {err:?}");
+                    "E01".to_string()
+                }
+            };
+
+            self.send(&reply)?;
+
+            //
+            // Acknowledgements are disabled only after we have replied.
+            //
+            if packet == "QStartNoAckMode" {
+                self.noack = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(bytes: &[u8]) -> Option<(Packet, bool)> {
+        let mut bytes = bytes.iter().copied();
+        read_packet(|| Ok(bytes.next())).unwrap()
+    }
+
+    fn command(s: &str) -> Packet {
+        Packet::Command(s.to_string())
+    }
+
+    #[test]
+    fn frame_checksum() {
+        assert_eq!(frame(""), b"$#00");
+        assert_eq!(frame("OK"), b"$OK#9a");
+        assert_eq!(frame("qSupported"), b"$qSupported#37");
+    }
+
+    #[test]
+    fn frame_escaping() {
+        assert_eq!(frame("a#b"), b"$a}\x03b#43");
+        assert_eq!(frame("$}*"), b"$}\x04}]}\x0a#e2");
+    }
+
+    #[test]
+    fn read_valid() {
+        assert_eq!(read(b"$OK#9a"), Some((command("OK"), true)));
+        assert_eq!(read(b"$#00"), Some((command(""), true)));
+        assert_eq!(read(b"$OK#9A"), Some((command("OK"), true)));
+    }
+
+    #[test]
+    fn read_skips_leading_bytes() {
+        assert_eq!(read(b"+-junk$OK#9a"), Some((command("OK"), true)));
+    }
+
+    #[test]
+    fn read_invalid_checksum() {
+        assert_eq!(read(b"$OK#9b"), Some((command("OK"), false)));
+        assert_eq!(read(b"$OK#zz"), Some((command("OK"), false)));
+    }
+
+    #[test]
+    fn read_unescapes() {
+        for data in ["a#b", "$}*", "X0,4:}}}}"] {
+            let framed = frame(data);
+            assert_eq!(read(&framed), Some((command(data), true)));
+        }
+    }
+
+    #[test]
+    fn read_interrupt() {
+        assert_eq!(read(b"\x03"), Some((Packet::Interrupt, true)));
+    }
+
+    #[test]
+    fn read_truncated() {
+        assert_eq!(read(b""), None);
+        assert_eq!(read(b"$OK"), None);
+        assert_eq!(read(b"$OK#9"), None);
+        assert_eq!(read(b"$O}"), None);
+    }
+
+    #[test]
+    fn unhex_valid() {
+        assert_eq!(unhex("").unwrap(), vec![]);
+        assert_eq!(unhex("00ff7fA5").unwrap(), vec![0x00, 0xff, 0x7f, 0xa5]);
+        assert_eq!(
+            unhex(&hex(&[0xde, 0xad, 0xbe, 0xef])).unwrap(),
+            [0xde, 0xad, 0xbe, 0xef]
+        );
+    }
+
+    #[test]
+    fn unhex_invalid() {
+        assert!(unhex("0").is_err());
+        assert!(unhex("0g").is_err());
+        assert!(unhex("+1").is_err());
+        assert!(unhex(" 1").is_err());
+    }
+
+    #[test]
+    fn unhex_non_ascii() {
+        assert!(unhex("\u{e9}").is_err());
+        assert!(unhex("\u{e9}\u{e9}").is_err());
+        assert!(unhex("a\u{e9}b").is_err());
+    }
+}
@@ -13,6 +13,8 @@
 //! - `--input` (`-i`): Queries the state of a pin (or all pins if no pin
 //!   is specified)
 //! - `--configure` (`-c`): Configures a pin
+//! - `--dump` (`-d`): Dumps the configuration of all pins
+//! - `--diff`: Compares the configuration of all pins to the archive
 //!
 //! ### Set, reset, toggle
 //!
@@ -42,6 +44,21 @@
 //! [Ok([])]
 //! ```
 //!
+//! To toggle a pin repeatedly at a given frequency (e.g., to find it with
+//! a probe during bring-up), specify the pin and the frequency in Hz as
+//! arguments to `--toggle`.  The pin will be toggled until `^C` is hit, at
+//! which point it will be restored to its original state:
+//!
+//! ```console
+//! $ humility gpio --toggle E:1 2
+//! humility: attached via ST-Link V3
+//! humility: toggling E:1 at 2 Hz; ^C to stop
+//! ^Chumility: toggled E:1 18 times
+//! ```
+//!
+//! As each toggle is made via a HIF call, frequencies higher than a few
+//! tens of Hz are unlikely to be achieved.
+//!
 //! ### Input
 //!
 //! To get input values for a particular pin:
@@ -91,11 +108,49 @@
 //! $ humility gpio -c Output:PushPull:High:None:AF0 -p A:5
 //! ```
 //!
+//! ### Dump and diff
+//!
+//! To see the complete configuration of every pin, use `--dump` (`-d`).
+//! This reads the GPIO registers directly rather than making HIF calls,
+//! and shows the pin mux specified in the archive (if any) for each pin:
+//!
+//! ```console
+//! $ humility gpio --dump
+//! humility: attached via ST-Link V3
+//! PIN   MODE      OTYPE     SPEED    PULL AF   IN OUT  MUX
+//! A:0   Analog    PushPull  Low      None -     0   0  -
+//! ...
+//! B:6   Alternate OpenDrain Low      None AF4   1   0  i2c1:AF4
+//! B:7   Alternate OpenDrain Low      None AF4   1   0  i2c1:AF4
+//! ...
+//! ```
+//!
+//! To check that the pins are configured as the archive expects, use
+//! `--diff`.  Any pin assigned to an I2C or SPI controller that is not in
+//! its expected alternate function will be displayed, and the command
+//! will fail.  Pins of controllers that are muxed among several ports are
+//! not checked, as only one such port will be configured at a time:
+//!
+//! ```console
+//! $ humility gpio --diff
+//! humility: attached via ST-Link V3
+//! humility: skipped 4 pins of peripherals with multiple ports
+//! PIN   OWNER    EXPECTED   ACTUAL
+//! B:7   i2c1     AF4        Input
+//! Error: 1 of 22 pins do not match the pin mux in the archive
+//! ```
+//!
 
+use humility::core::Core;
+use humility::hubris::HubrisArchive;
+use humility::msg;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
-use humility_hiffy::HiffyContext;
+use humility_hiffy::{HiffyContext, HiffyFunction};
 use std::str;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
@@ -120,12 +175,13 @@ struct GpioArgs {
     )]
     input: bool,
 
-    /// toggle specified pins
+    /// toggle specified pins, or toggle a pin at the specified frequency
     #[clap(
-        long, short, requires = "pins",
+        long, short, min_values = 0, max_values = 2,
+        value_names = &["pin", "hz"],
         conflicts_with_all = &["set", "reset", "configure"]
     )]
-    toggle: bool,
+    toggle: Option<Vec<String>>,
 
     /// sets specified pins
     #[clap(
@@ -147,6 +203,314 @@ struct GpioArgs {
     /// specifies GPIO pins on which to operate
     #[clap(long, short, value_name = "pins", use_value_delimiter = true)]
     pins: Option<Vec<String>>,
+
+    /// dumps the configuration of all pins
+    #[clap(
+        long, short,
+        conflicts_with_all = &["input", "toggle", "set", "reset", "configure"]
+    )]
+    dump: bool,
+
+    /// compares the configuration of pins to the pin mux in the archive
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "input", "toggle", "set", "reset", "configure", "dump"
+        ]
+    )]
+    diff: bool,
+}
+
+const GPIO_MODER: u32 = 0x00;
+const GPIO_OTYPER: u32 = 0x04;
+const GPIO_OSPEEDR: u32 = 0x08;
+const GPIO_PUPDR: u32 = 0x0c;
+const GPIO_IDR: u32 = 0x10;
+const GPIO_ODR: u32 = 0x14;
+const GPIO_AFRL: u32 = 0x20;
+const GPIO_AFRH: u32 = 0x24;
+
+const GPIO_MODE_ALTERNATE: u32 = 2;
+
+//
+// These are the names used for the configuration tuple.
+//
+const MODES: [&str; 4] = ["Input", "Output", "Alternate", "Analog"];
+const OUTPUT_TYPES: [&str; 2] = ["PushPull", "OpenDrain"];
+const SPEEDS: [&str; 4] = ["Low", "Medium", "High", "VeryHigh"];
+const PULLS: [&str; 4] = ["None", "Up", "Down", "Reserved"];
+
+struct PinConfig {
+    mode: u32,
+    otype: u32,
+    speed: u32,
+    pull: u32,
+    af: u32,
+    input: u32,
+    output: u32,
+}
+
+///
+/// Returns the GPIO ports (and their base addresses) from the peripherals
+/// in the archive.
+///
+fn gpio_ports(hubris: &HubrisArchive) -> Result<Vec<(String, u32)>> {
+    let ports = hubris
+        .manifest
+        .peripherals
+        .iter()
+        .filter_map(|(name, addr)| {
+            let port = name.strip_prefix("gpio")?;
+
+            match port.chars().collect::<Vec<_>>()[..] {
+                [c] if c.is_ascii_lowercase() => {
+                    Some((c.to_ascii_uppercase().to_string(), *addr))
+                }
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if ports.is_empty() {
+        bail!("no STM32 GPIO ports found in archive");
+    }
+
+    Ok(ports)
+}
+
+///
+/// Reads the configuration of every pin on a port directly from the GPIO
+/// registers.
+///
+fn gpio_config(core: &mut dyn Core, base: u32) -> Result<Vec<PinConfig>> {
+    let mut reg = |offset| core.read_word_32(base + offset);
+
+    let moder = reg(GPIO_MODER)?;
+    let otyper = reg(GPIO_OTYPER)?;
+    let ospeedr = reg(GPIO_OSPEEDR)?;
+    let pupdr = reg(GPIO_PUPDR)?;
+    let idr = reg(GPIO_IDR)?;
+    let odr = reg(GPIO_ODR)?;
+    let afr = (reg(GPIO_AFRH)? as u64) << 32 | reg(GPIO_AFRL)? as u64;
+
+    Ok((0..16)
+        .map(|pin| PinConfig {
+            mode: (moder >> (pin * 2)) & 0b11,
+            otype: (otyper >> pin) & 1,
+            speed: (ospeedr >> (pin * 2)) & 0b11,
+            pull: (pupdr >> (pin * 2)) & 0b11,
+            af: ((afr >> (pin * 4)) & 0b1111) as u32,
+            input: (idr >> pin) & 1,
+            output: (odr >> pin) & 1,
+        })
+        .collect())
+}
+
+fn gpio_dump(hubris: &HubrisArchive, core: &mut dyn Core) -> Result<()> {
+    let mux = &hubris.manifest.pin_mux;
+
+    println!(
+        "{:<5} {:<9} {:<9} {:<8} {:<4} {:<4} {:>2} {:>3}  MUX",
+        "PIN", "MODE", "OTYPE", "SPEED", "PULL", "AF", "IN", "OUT"
+    );
+
+    for (port, base) in gpio_ports(hubris)? {
+        for (pin, c) in gpio_config(core, base)?.iter().enumerate() {
+            let expected = mux
+                .iter()
+                .filter(|m| m.port == port && m.pin as usize == pin)
+                .map(|m| format!("{}:AF{}", m.owner, m.af))
+                .collect::<Vec<_>>();
+
+            println!(
+                "{:<5} {:<9} {:<9} {:<8} {:<4} {:<4} {:>2} {:>3}  {}",
+                format!("{port}:{pin}"),
+                MODES[c.mode as usize],
+                OUTPUT_TYPES[c.otype as usize],
+                SPEEDS[c.speed as usize],
+                PULLS[c.pull as usize],
+                if c.mode == GPIO_MODE_ALTERNATE {
+                    format!("AF{}", c.af)
+                } else {
+                    "-".to_string()
+                },
+                c.input,
+                c.output,
+                if expected.is_empty() {
+                    "-".to_string()
+                } else {
+                    expected.join(",")
+                }
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn gpio_diff(hubris: &HubrisArchive, core: &mut dyn Core) -> Result<()> {
+    let ports = gpio_ports(hubris)?;
+    let mut configs = vec![];
+
+    for (port, base) in &ports {
+        configs.push((port, gpio_config(core, *base)?));
+    }
+
+    let mut mismatches = vec![];
+    let mut checked = 0;
+    let mut skipped = 0;
+
+    for m in &hubris.manifest.pin_mux {
+        //
+        // Pins of peripherals that switch among several ports are only
+        // configured when their port is in use, so we can't expect them
+        // to be in any particular state.
+        //
+        if m.muxed {
+            skipped += 1;
+            continue;
+        }
+
+        let c = match configs.iter().find(|(port, _)| **port == m.port) {
+            Some((_, config)) => &config[m.pin as usize % 16],
+            None => {
+                bail!("{} uses port {}, which was not found", m.owner, m.port)
+            }
+        };
+
+        checked += 1;
+
+        if c.mode != GPIO_MODE_ALTERNATE || c.af != m.af as u32 {
+            mismatches.push((m, c));
+        }
+    }
+
+    if skipped > 0 {
+        msg!("skipped {skipped} pins of peripherals with multiple ports");
+    }
+
+    if mismatches.is_empty() {
+        msg!("all {checked} pins match the pin mux in the archive");
+        return Ok(());
+    }
+
+    println!("{:<5} {:<8} {:<10} ACTUAL", "PIN", "OWNER", "EXPECTED");
+
+    for (m, c) in &mismatches {
+        let actual = if c.mode == GPIO_MODE_ALTERNATE {
+            format!("Alternate:AF{}", c.af)
+        } else {
+            MODES[c.mode as usize].to_string()
+        };
+
+        println!(
+            "{:<5} {:<8} {:<10} {}",
+            format!("{}:{}", m.port, m.pin),
+            m.owner,
+            format!("AF{}", m.af),
+            actual
+        );
+    }
+
+    bail!(
+        "{} of {} pins do not match the pin mux in the archive",
+        mismatches.len(),
+        checked
+    );
+}
+
+///
+/// Parses a pin specified as port:pin, returning the port (as an argument
+/// to the specified function) and the pin.
+///
+fn gpio_pin(
+    hubris: &HubrisArchive,
+    func: &HiffyFunction,
+    pin: &str,
+) -> Result<(u16, u8)> {
+    let p: Vec<&str> = pin.split(':').collect();
+
+    if p.len() != 2 {
+        bail!("expected both a port and a pin number");
+    }
+
+    let port = func.lookup_argument(hubris, "port", 0, p[0])?;
+    let pin = match parse_int::parse::<u8>(p[1]) {
+        Ok(pin) if pin < 16 => pin,
+        _ => {
+            bail!("invalid pin {}", p[1]);
+        }
+    };
+
+    Ok((port, pin))
+}
+
+///
+/// Toggles a pin repeatedly at the specified frequency until interrupted,
+/// leaving the pin in its original state.  Because each toggle is a HIF
+/// call, this is only useful at low frequencies.
+///
+fn gpio_toggle_periodic(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    pin: &str,
+    hz: &str,
+) -> Result<()> {
+    let gpio_toggle = context.get_function("GpioToggle", 2)?;
+    let (port, p) = gpio_pin(hubris, &gpio_toggle, pin)?;
+
+    let hz = match hz.parse::<f64>() {
+        Ok(hz) if hz > 0.0 => hz,
+        _ => bail!("invalid frequency {hz}"),
+    };
+
+    let ops = vec![
+        Op::Push16(port),
+        Op::Push(p),
+        Op::Call(gpio_toggle.id),
+        Op::DropN(2),
+        Op::Done,
+    ];
+
+    let done = humility_cmd::interrupted()?;
+
+    msg!("toggling {pin} at {hz} Hz; ^C to stop");
+
+    let period = Duration::from_secs_f64(0.5 / hz);
+    let mut next = Instant::now();
+    let mut toggles = 0u64;
+    let mut late = 0u64;
+
+    //
+    // If we have toggled an odd number of times, we toggle once more to
+    // return the pin to its original state.
+    //
+    while !done.load(Ordering::SeqCst) || toggles % 2 != 0 {
+        if let Err(e) = context.run(core, ops.as_slice(), None)?[0] {
+            bail!("failed to toggle {pin}: {}", gpio_toggle.strerror(e));
+        }
+
+        toggles += 1;
+        next += period;
+
+        let now = Instant::now();
+
+        if next > now {
+            thread::sleep(next - now);
+        } else {
+            late += 1;
+            next = now;
+        }
+    }
+
+    msg!("toggled {pin} {toggles} times");
+
+    if late > 0 {
+        msg!("{late} toggles were late; frequency is too high");
+    }
+
+    Ok(())
 }
 
 fn gpio(context: &mut ExecutionContext) -> Result<()> {
@@ -154,8 +518,29 @@ fn gpio(context: &mut ExecutionContext) -> Result<()> {
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
     let hubris = context.archive.as_ref().unwrap();
     let subargs = GpioArgs::try_parse_from(subargs)?;
+
+    if subargs.dump {
+        return gpio_dump(hubris, core);
+    }
+
+    if subargs.diff {
+        return gpio_diff(hubris, core);
+    }
+
     let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
 
+    let toggle = match subargs.toggle.as_deref() {
+        Some([pin, hz]) => {
+            return gpio_toggle_periodic(hubris, core, &mut context, pin, hz);
+        }
+        Some([]) if subargs.pins.is_none() => {
+            bail!("expected pins to toggle to be specified");
+        }
+        Some([]) => true,
+        Some(_) => bail!("expected a pin and a frequency at which to toggle"),
+        None => false,
+    };
+
     let gpio_toggle = context.get_function("GpioToggle", 2)?;
     let gpio_set = context.get_function("GpioSet", 2)?;
     let gpio_reset = context.get_function("GpioReset", 2)?;
@@ -163,7 +548,7 @@ fn gpio(context: &mut ExecutionContext) -> Result<()> {
     let gpio_configure = context.get_function("GpioConfigure", 7)?;
     let mut configure_args = vec![];
 
-    let target = if subargs.toggle {
+    let target = if toggle {
         gpio_toggle.id
     } else if subargs.set {
         gpio_set.id
@@ -200,21 +585,10 @@ fn gpio(context: &mut ExecutionContext) -> Result<()> {
 
     if let Some(ref pins) = subargs.pins {
         for pin in pins {
-            let p: Vec<&str> = pin.split(':').collect();
-
-            if p.len() != 2 {
-                bail!("expected both a port and a pin number");
-            }
-
-            let port = gpio_toggle.lookup_argument(hubris, "port", 0, p[0])?;
-            let pin = match parse_int::parse::<u8>(p[1]) {
-                Ok(pin) if pin < 16 => pin,
-                _ => {
-                    bail!("invalid pin {}", p[1]);
-                }
-            };
+            let (port, p) = gpio_pin(hubris, &gpio_toggle, pin)?;
+            let name = pin.split(':').next().unwrap().to_string();
 
-            args.push((port, Some(pin), p[0].to_string()));
+            args.push((port, Some(p), name));
         }
     }
 
@@ -0,0 +1,497 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility heap`
+//!
+//! `humility heap` finds the heaps of tasks that use a known allocator --
+//! currently, `linked_list_allocator`, either directly or as wrapped by
+//! `embedded-alloc` (née `alloc-cortex-m`) -- and walks the allocator's
+//! free list on a live target or in a dump, reporting on each heap:
+//!
+//! ```console
+//! $ humility heap
+//! humility: attached via ST-Link V3
+//! TASK         HEAP             SIZE   USED   FREE REGIONS HOLES LARGEST FRAG
+//! net          task_net::HEAP  16384  12872   3512       9     4    2048  42%
+//! ```
+//!
+//! `USED` is the allocator's own count of allocated bytes; `FREE` is the sum
+//! of the free blocks (holes) on the free list, the largest of which is
+//! `LARGEST`.  `FRAG` is the fragmentation of free memory:  the fraction of
+//! free memory that is not in the largest free block (and therefore cannot
+//! satisfy an allocation as large as the total free memory).  Note that the
+//! allocator does not record the boundaries between adjacent allocations, so
+//! individual allocations cannot be seen; `REGIONS` is the number of runs
+//! of allocated memory between free blocks, each of which may contain any
+//! number of allocations.  To list the free blocks and allocated regions,
+//! use `--regions` (`-r`).
+//!
+//! To look for leaks, use `--leaks` (`-l`).  This scans the task's memory
+//! (including its stack and its heap) for words that point into each
+//! allocated region; an allocated region that is not pointed to from outside
+//! of itself is reported as a leak suspect, along with its first words:
+//!
+//! ```console
+//! $ humility heap --leaks net
+//! humility: attached via ST-Link V3
+//! TASK         HEAP             SIZE   USED   FREE REGIONS HOLES LARGEST FRAG
+//! net          task_net::HEAP  16384  12872   3512       9     4    2048  42%
+//! humility: net: 1 leak suspect
+//!        ADDRESS   SIZE CONTENTS
+//!     0x24011a40    512 0x00000200 0x24011a48 0x00000000 0x00000000
+//! ```
+//!
+//! This is a heuristic, and its results should be treated as leads rather
+//! than conclusions.  Because the analysis is of allocated regions rather
+//! than of allocations, a leaked allocation that is adjacent to one that is
+//! still referenced will not be reported, and a reported region may contain
+//! several allocations, not all of which have necessarily leaked.  As the
+//! allocator does not record the call site of an allocation, suspects are
+//! identified by address and size only.  Any word that happens to look like
+//! a pointer into a region will keep it from being reported, but pointers
+//! that are held only in registers are not seen:  an allocation that is only
+//! referred to by a register may be reported as a leak suspect.
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::{Core, CORE_MAX_READSIZE};
+use humility::hubris::*;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+
+#[derive(Parser, Debug)]
+#[clap(name = "heap", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct HeapArgs {
+    /// list free blocks and allocated regions
+    #[clap(long, short)]
+    regions: bool,
+
+    /// report allocated regions that are not referenced (leak suspects)
+    #[clap(long, short)]
+    leaks: bool,
+
+    /// display only the heaps of the specified task
+    task: Option<String>,
+}
+
+//
+// An upper bound on the number of free blocks that we will follow, lest a
+// corrupt free list send us around in circles.
+//
+const MAX_HOLES: usize = 16384;
+
+///
+/// The extent of a heap:  older versions of `linked_list_allocator` record
+/// the heap's size, newer versions record a pointer to its top.
+///
+#[derive(Copy, Clone, Debug)]
+enum Extent {
+    Size(usize),
+    Top(usize),
+}
+
+///
+/// The offsets of the parts of a `linked_list_allocator` heap that we care
+/// about, relative to the variable that contains it.
+///
+#[derive(Copy, Clone, Debug)]
+struct HeapLayout {
+    used: usize,
+    bottom: usize,
+    extent: Extent,
+    /// The sentinel hole that heads the free list
+    first: usize,
+    /// Offsets of the members of a hole, relative to the hole
+    hole_size: usize,
+    hole_next: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Block {
+    addr: u32,
+    size: u32,
+}
+
+impl Block {
+    fn contains(&self, addr: u32) -> bool {
+        addr >= self.addr && addr - self.addr < self.size
+    }
+}
+
+struct Heap {
+    size: u32,
+    used: u32,
+    holes: Vec<Block>,
+    allocated: Vec<Block>,
+}
+
+fn heap_layout(
+    hubris: &HubrisArchive,
+    heap: &HubrisStruct,
+    base: usize,
+) -> Result<HeapLayout> {
+    let used = heap.lookup_member("used")?;
+    let holes = heap.lookup_member("holes")?;
+    let list = hubris.lookup_struct(holes.goff)?;
+    let first = list.lookup_member("first")?;
+    let hole = hubris.lookup_struct(first.goff)?;
+    let list_base = base + holes.offset;
+
+    let (bottom, extent) =
+        match (heap.lookup_member("bottom"), heap.lookup_member("size")) {
+            (Ok(bottom), Ok(size)) => {
+                (base + bottom.offset, Extent::Size(base + size.offset))
+            }
+            _ => (
+                list_base + list.lookup_member("bottom")?.offset,
+                Extent::Top(list_base + list.lookup_member("top")?.offset),
+            ),
+        };
+
+    Ok(HeapLayout {
+        used: base + used.offset,
+        bottom,
+        extent,
+        first: list_base + first.offset,
+        hole_size: hole.lookup_member("size")?.offset,
+        hole_next: hole.lookup_member("next")?.offset,
+    })
+}
+
+///
+/// Looks for a `linked_list_allocator` heap within the specified structure,
+/// descending into its members (e.g., the `Mutex<RefCell<Heap>>` within an
+/// `embedded-alloc` heap) to the specified depth.
+///
+fn heap_find(
+    hubris: &HubrisArchive,
+    s: &HubrisStruct,
+    base: usize,
+    depth: usize,
+) -> Option<HeapLayout> {
+    if s.name == "Heap" {
+        if let Ok(layout) = heap_layout(hubris, s, base) {
+            return Some(layout);
+        }
+    }
+
+    if depth == 0 {
+        return None;
+    }
+
+    s.members.iter().find_map(|m| {
+        let child = hubris.lookup_struct(m.goff).ok()?;
+        heap_find(hubris, child, base + m.offset, depth - 1)
+    })
+}
+
+fn heap_read(
+    core: &mut dyn Core,
+    var: &HubrisVariable,
+    layout: &HeapLayout,
+) -> Result<Heap> {
+    let mut buf = vec![0u8; var.size];
+    core.read_8(var.addr, &mut buf)?;
+
+    let word = |offs: usize| {
+        u32::from_le_bytes(buf[offs..offs + 4].try_into().unwrap())
+    };
+
+    let bottom = word(layout.bottom);
+    let size = match layout.extent {
+        Extent::Size(offs) => word(offs),
+        Extent::Top(offs) => word(offs).wrapping_sub(bottom),
+    };
+
+    let top = bottom.saturating_add(size);
+    let mut holes = vec![];
+    let mut addr = word(layout.first + layout.hole_next);
+
+    while addr != 0 {
+        if addr < bottom || addr >= top {
+            bail!(
+                "free list is corrupt: hole at 0x{:08x} is outside heap",
+                addr
+            );
+        }
+
+        if holes.len() >= MAX_HOLES {
+            bail!("free list is corrupt: more than {} holes", MAX_HOLES);
+        }
+
+        let size = core.read_word_32(addr + layout.hole_size as u32)?;
+        holes.push(Block { addr, size });
+        addr = core.read_word_32(addr + layout.hole_next as u32)?;
+    }
+
+    holes.sort_by_key(|h| h.addr);
+
+    //
+    // Everything in the heap that isn't in a hole is allocated.
+    //
+    let mut allocated = vec![];
+    let mut cur = bottom;
+
+    for hole in &holes {
+        if hole.addr > cur {
+            allocated.push(Block { addr: cur, size: hole.addr - cur });
+        }
+
+        cur = std::cmp::max(cur, hole.addr.saturating_add(hole.size));
+    }
+
+    if cur < top {
+        allocated.push(Block { addr: cur, size: top - cur });
+    }
+
+    Ok(Heap { size, used: word(layout.used), holes, allocated })
+}
+
+///
+/// Scans the specified task's writable memory for pointers into the heap's
+/// allocated regions, returning those regions that are not referred to from
+/// outside of themselves.  Free blocks are not scanned, as they may contain
+/// stale pointers.
+///
+fn heap_leaks(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    task: HubrisTask,
+    heap: &Heap,
+) -> Result<Vec<Block>> {
+    let mut referenced = vec![false; heap.allocated.len()];
+
+    for region in hubris.regions(core)?.values() {
+        if !region.tasks.contains(&task)
+            || !region.attr.write
+            || region.attr.device
+        {
+            continue;
+        }
+
+        let mut buf = vec![0u8; region.size as usize];
+
+        for (i, chunk) in buf.chunks_mut(CORE_MAX_READSIZE).enumerate() {
+            core.read_8(region.base + (i * CORE_MAX_READSIZE) as u32, chunk)?;
+        }
+
+        for (i, w) in buf.chunks_exact(4).enumerate() {
+            let addr = region.base + i as u32 * 4;
+            let val = u32::from_le_bytes(w.try_into().unwrap());
+
+            if heap.holes.iter().any(|h| h.contains(addr)) {
+                continue;
+            }
+
+            let ndx = heap.allocated.partition_point(|b| b.addr <= val);
+
+            if ndx == 0 {
+                continue;
+            }
+
+            let block = &heap.allocated[ndx - 1];
+
+            if block.contains(val) && !block.contains(addr) {
+                referenced[ndx - 1] = true;
+            }
+        }
+    }
+
+    Ok(heap
+        .allocated
+        .iter()
+        .zip(referenced.iter())
+        .filter(|(_, &r)| !r)
+        .map(|(b, _)| *b)
+        .collect())
+}
+
+fn heap_print(taskname: &str, name: &str, heap: &Heap) {
+    let free: u32 = heap.holes.iter().map(|h| h.size).sum();
+    let largest = heap.holes.iter().map(|h| h.size).max().unwrap_or(0);
+
+    let frag =
+        if free == 0 { 0 } else { 100 - (largest as u64 * 100 / free as u64) };
+
+    println!(
+        "{:12} {:14} {:>6} {:>6} {:>6} {:>7} {:>5} {:>7} {:>3}%",
+        taskname,
+        name,
+        heap.size,
+        heap.used,
+        free,
+        heap.allocated.len(),
+        heap.holes.len(),
+        largest,
+        frag
+    );
+}
+
+fn heap_regions(heap: &Heap) {
+    let mut blocks = heap
+        .holes
+        .iter()
+        .map(|b| (b, "free"))
+        .chain(heap.allocated.iter().map(|b| (b, "allocated")))
+        .collect::<Vec<_>>();
+
+    blocks.sort_by_key(|(b, _)| b.addr);
+
+    println!("    {:>10} {:>10} {:>6} STATE", "START", "END", "SIZE");
+
+    for (b, state) in blocks {
+        println!(
+            "    0x{:08x} 0x{:08x} {:>6} {}",
+            b.addr,
+            b.addr + b.size - 1,
+            b.size,
+            state
+        );
+    }
+}
+
+///
+/// A heap to report on:  the name of its task, the task, the name of the
+/// variable that contains it, the variable, and the heap's layout.
+///
+type HeapSource<'a> =
+    (&'a str, HubrisTask, &'a str, &'a HubrisVariable, HeapLayout);
+
+fn heap_report(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &HeapArgs,
+    heaps: &[HeapSource],
+) -> Result<()> {
+    for &(taskname, task, name, var, ref layout) in heaps {
+        let heap = match heap_read(core, var, layout) {
+            Ok(heap) => heap,
+            Err(e) => {
+                humility::msg!("{}: {}: {}", taskname, name, e);
+                continue;
+            }
+        };
+
+        heap_print(taskname, name, &heap);
+
+        if subargs.regions {
+            heap_regions(&heap);
+        }
+
+        if subargs.leaks {
+            let leaks = heap_leaks(hubris, core, task, &heap)?;
+
+            humility::msg!(
+                "{}: {} leak suspect{}",
+                taskname,
+                leaks.len(),
+                if leaks.len() == 1 { "" } else { "s" }
+            );
+
+            if leaks.is_empty() {
+                continue;
+            }
+
+            println!("    {:>10} {:>6} CONTENTS", "ADDRESS", "SIZE");
+
+            for leak in &leaks {
+                let n = std::cmp::min(leak.size / 4, 4) as usize;
+                let mut buf = vec![0u8; n * 4];
+                core.read_8(leak.addr, &mut buf)?;
+
+                let contents = buf
+                    .chunks_exact(4)
+                    .map(|w| {
+                        let w = u32::from_le_bytes(w.try_into().unwrap());
+                        format!("0x{:08x}", w)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                println!(
+                    "    0x{:08x} {:>6} {}",
+                    leak.addr, leak.size, contents
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn heap(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let subargs = HeapArgs::try_parse_from(subargs)?;
+
+    if core.is_net() {
+        bail!("heap inspection requires a debugger or a dump");
+    }
+
+    let mut heaps = vec![];
+
+    for (name, var) in hubris.qualified_variables() {
+        let task = HubrisTask::from(var.goff);
+        let module = hubris.lookup_module(task)?;
+
+        if let Some(ref t) = subargs.task {
+            if &module.name != t {
+                continue;
+            }
+        }
+
+        let s = match hubris.lookup_struct(var.goff) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+
+        if let Some(layout) = heap_find(hubris, s, 0, 6) {
+            heaps.push((module.name.as_str(), task, name, var, layout));
+        }
+    }
+
+    if heaps.is_empty() {
+        match subargs.task {
+            Some(t) => bail!("no heaps found in task {}", t),
+            None => bail!("no heaps found"),
+        }
+    }
+
+    println!(
+        "{:12} {:14} {:>6} {:>6} {:>6} {:>7} {:>5} {:>7} FRAG",
+        "TASK", "HEAP", "SIZE", "USED", "FREE", "REGIONS", "HOLES", "LARGEST"
+    );
+
+    //
+    // Halt the target (if it's live) so that the free list doesn't change
+    // out from under us.
+    //
+    if !core.is_dump() {
+        core.halt()?;
+    }
+
+    let rval = heap_report(hubris, core, &subargs, &heaps);
+
+    if !core.is_dump() {
+        core.run()?;
+    }
+
+    rval
+}
+
+pub fn init() -> Command {
+    Command {
+        app: HeapArgs::command(),
+        name: "heap",
+        run: heap,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::Any,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -42,18 +42,46 @@
 //! ```
 //!
 //! To view the raw HIF functions provided to programmatic HIF consumers
-//! within Humility, use `-L` (`--list-functions`).
+//! within Humility, use `-L` (`--list-functions`), optionally specifying
+//! the functions of interest.  With `-v` (`--verbose`), the types of each
+//! function's arguments (which are positional) and its error type and
+//! variants are also displayed:
+//!
+//! ```console
+//! $ humility hiffy -L -v I2cRead
+//! humility: attached via ST-Link V3
+//!  ID FUNCTION                       #ARGS
+//!   5 I2cRead                        7
+//!         arg0                       u8
+//!         arg1                       Controller
+//!         arg2                       PortIndex
+//!         arg3                       Option<(u8, u8)>
+//!         arg4                       u8
+//!         arg5                       Option<u8>
+//!         arg6                       usize
+//!         <error>                    ResponseCode
+//!           1                        BadResponse
+//!           2                        BadArg
+//!           3                        NoDevice
+//! ...
+//! ```
+//!
+//! `-l -v` lists both Idol interfaces and HIF functions, allowing one to
+//! see every operation that a given image supports.  To list either as JSON
+//! (e.g., for consumption by other tooling), add `--json`.
 //!
 
 use ::idol::syntax::{Operation, Reply};
 use anyhow::{anyhow, bail, Context, Result};
 use clap::{CommandFactory, Parser};
 use humility::hubris::*;
-use humility::warn;
+use humility::{warn, Diagnostic};
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Dumper, Validate};
 use humility_hiffy::*;
 use humility_idol as idol;
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::io::Read;
 
 #[derive(Parser, Debug)]
@@ -102,11 +130,101 @@ struct HiffyArgs {
     #[clap(long, short, use_value_delimiter = true, requires = "call")]
     arguments: Vec<String>,
 
+    /// with -L, show each function's arguments and errors; with -l, also
+    /// list HIF functions
+    #[clap(long, short)]
+    verbose: bool,
+
+    /// emit list output as JSON
+    #[clap(long)]
+    json: bool,
+
     /// filter for list output
     #[clap(use_value_delimiter = true)]
     filter: Vec<String>,
 }
 
+#[derive(Serialize)]
+struct HiffyArgInfo {
+    name: String,
+    #[serde(rename = "type")]
+    ty: String,
+}
+
+#[derive(Serialize)]
+struct HiffyErrorInfo {
+    #[serde(rename = "type")]
+    ty: String,
+    variants: BTreeMap<u32, String>,
+}
+
+#[derive(Serialize)]
+struct HiffyFunctionInfo {
+    id: u8,
+    name: String,
+    args: Vec<HiffyArgInfo>,
+    error: HiffyErrorInfo,
+}
+
+#[derive(Serialize)]
+struct HiffyOperationInfo {
+    name: String,
+    args: Vec<HiffyArgInfo>,
+    ok: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HiffyInterfaceInfo {
+    name: String,
+    task: String,
+    operations: Vec<HiffyOperationInfo>,
+}
+
+///
+/// Returns the types of the success and error replies of an operation, if
+/// they can be determined.
+///
+fn hiffy_reply(
+    hubris: &HubrisArchive,
+    module: &HubrisModule,
+    op: &(&String, &Operation),
+) -> (Option<String>, Option<String>) {
+    match idol::lookup_reply(hubris, module, op.0) {
+        Ok((_, idol::IdolError::CLike(e))) => match &op.1.reply {
+            Reply::Result { ok, .. } => {
+                (Some(ok.ty.0.to_string()), Some(e.name.to_string()))
+            }
+            _ => {
+                warn!("mismatch on reply: found {op:?}");
+                (None, None)
+            }
+        },
+        Ok((_, idol::IdolError::Complex(t))) => match &op.1.reply {
+            Reply::Result { ok, .. } => {
+                (Some(ok.ty.0.to_string()), Some(t.to_string()))
+            }
+            _ => {
+                warn!("mismatch on reply: found {op:?}");
+                (None, None)
+            }
+        },
+
+        //
+        // A Result with no error is possible if the only error is
+        // ServerDeath
+        //
+        Ok((_, idol::IdolError::None)) => match &op.1.reply {
+            Reply::Result { ok, .. } => (Some(ok.ty.0.to_string()), None),
+            Reply::Simple(ok) => (Some(ok.ty.0.to_string()), None),
+        },
+        Err(e) => {
+            warn!("{}", e);
+            (None, None)
+        }
+    }
+}
+
 pub fn hiffy_list(hubris: &HubrisArchive, filter: Vec<String>) -> Result<()> {
     let print_args = |op: &(&String, &Operation), module, margin| {
         let mut args = op.1.args.iter();
@@ -122,36 +240,14 @@ pub fn hiffy_list(hubris: &HubrisArchive, filter: Vec<String>) -> Result<()> {
             }
         }
 
-        match idol::lookup_reply(hubris, module, op.0) {
-            Ok((_, idol::IdolError::CLike(e))) => match &op.1.reply {
-                Reply::Result { ok, .. } => {
-                    println!("{}{:<27} {}", margin, "<ok>", ok.ty.0);
-                    println!("{}{:<27} {}", margin, "<error>", e.name);
-                }
-                _ => warn!("mismatch on reply: found {op:?}"),
-            },
-            Ok((_, idol::IdolError::Complex(t))) => match &op.1.reply {
-                Reply::Result { ok, .. } => {
-                    println!("{}{:<27} {}", margin, "<ok>", ok.ty.0);
-                    println!("{}{:<27} {}", margin, "<error>", t);
-                }
-                _ => warn!("mismatch on reply: found {op:?}"),
-            },
+        let (ok, err) = hiffy_reply(hubris, module, op);
 
-            Ok((_, idol::IdolError::None)) => match &op.1.reply {
-                Reply::Result { ok, .. } => {
-                    //
-                    // This is possible if the only error is ServerDeath
-                    //
-                    println!("{}{:<27} {}", margin, "<ok>", ok.ty.0);
-                }
-                Reply::Simple(ok) => {
-                    println!("{}{:<27} {}", margin, "<ok>", ok.ty.0);
-                }
-            },
-            Err(e) => {
-                warn!("{}", e);
-            }
+        if let Some(ok) = ok {
+            println!("{}{:<27} {}", margin, "<ok>", ok);
+        }
+
+        if let Some(err) = err {
+            println!("{}{:<27} {}", margin, "<error>", err);
         }
     };
 
@@ -188,16 +284,173 @@ pub fn hiffy_list(hubris: &HubrisArchive, filter: Vec<String>) -> Result<()> {
     }
 
     if !filter.is_empty() && !matches {
-        bail!(
-            "filter \"{}\" did not match any task or interface; \
-            use --list without an argument to list all interfaces",
+        bail!(Diagnostic::error(format!(
+            "filter \"{}\" did not match any task or interface",
             filter.join(",")
-        );
+        ))
+        .code("no-match")
+        .hint("use --list without an argument to list all interfaces"));
     }
 
     Ok(())
 }
 
+fn hiffy_interfaces(
+    hubris: &HubrisArchive,
+    filter: &[String],
+) -> Result<Vec<HiffyInterfaceInfo>> {
+    let mut interfaces = vec![];
+
+    for i in 0..hubris.ntasks() {
+        let module = hubris.lookup_module(HubrisTask::Task(i as u32))?;
+
+        if let Some(iface) = &module.iface {
+            if !filter.is_empty()
+                && !filter.iter().any(|f| iface.name == *f || module.name == *f)
+            {
+                continue;
+            }
+
+            let operations = iface
+                .ops
+                .iter()
+                .map(|op| {
+                    let (ok, error) = hiffy_reply(hubris, module, &op);
+
+                    HiffyOperationInfo {
+                        name: op.0.to_string(),
+                        args: op
+                            .1
+                            .args
+                            .iter()
+                            .map(|(name, arg)| HiffyArgInfo {
+                                name: name.to_string(),
+                                ty: arg.ty.0.to_string(),
+                            })
+                            .collect(),
+                        ok,
+                        error,
+                    }
+                })
+                .collect();
+
+            interfaces.push(HiffyInterfaceInfo {
+                name: iface.name.to_string(),
+                task: module.name.to_string(),
+                operations,
+            });
+        }
+    }
+
+    Ok(interfaces)
+}
+
+///
+/// Returns the HIF functions exported by the target, in ID order.  HIF
+/// function arguments are positional, and are named by their position.
+///
+fn hiffy_functions(
+    hubris: &HubrisArchive,
+    context: &HiffyContext,
+    filter: &[String],
+) -> Result<Vec<HiffyFunctionInfo>> {
+    let funcs = context.functions();
+    let mut byid: Vec<Option<(&String, &HiffyFunction)>> = vec![];
+
+    byid.resize(funcs.len(), None);
+
+    for (name, func) in &funcs.0 {
+        let ndx = func.id.0 as usize;
+
+        if ndx >= byid.len() {
+            bail!("ID for function {} ({}) exceeds bounds", name, ndx);
+        }
+
+        if let Some((_, _)) = byid[ndx] {
+            bail!("function ID {} has conflics", ndx);
+        }
+
+        byid[ndx] = Some((name, func));
+    }
+
+    let tyname = |goff| -> Result<String> {
+        Ok(hubris.lookup_type(goff)?.name(hubris)?.to_string())
+    };
+
+    let mut rval = vec![];
+
+    for (i, id) in byid.iter().enumerate() {
+        let Some((name, func)) = id else {
+            bail!("missing function for ID {}", i);
+        };
+
+        if !filter.is_empty()
+            && !filter.iter().any(|f| f.eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+
+        let args = func
+            .args
+            .iter()
+            .enumerate()
+            .map(|(ndx, &goff)| {
+                Ok(HiffyArgInfo {
+                    name: format!("arg{ndx}"),
+                    ty: tyname(goff)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        rval.push(HiffyFunctionInfo {
+            id: func.id.0,
+            name: name.to_string(),
+            args,
+            error: HiffyErrorInfo {
+                ty: tyname(func.error)?,
+                variants: func
+                    .errmap
+                    .iter()
+                    .map(|(code, name)| (*code, name.clone()))
+                    .collect(),
+            },
+        });
+    }
+
+    if !filter.is_empty() && rval.is_empty() {
+        bail!(Diagnostic::error(format!(
+            "filter \"{}\" did not match any HIF function",
+            filter.join(",")
+        ))
+        .code("no-match")
+        .hint("use -L without an argument to list all functions"));
+    }
+
+    Ok(rval)
+}
+
+fn hiffy_functions_print(funcs: &[HiffyFunctionInfo], verbose: bool) {
+    println!("{:>3} {:30} #ARGS", "ID", "FUNCTION");
+
+    for func in funcs {
+        println!("{:3} {:30} {}", func.id, func.name, func.args.len());
+
+        if !verbose {
+            continue;
+        }
+
+        for arg in &func.args {
+            println!("{:8}{:<26} {}", "", arg.name, arg.ty);
+        }
+
+        println!("{:8}{:<26} {}", "", "<error>", func.error.ty);
+
+        for (code, name) in &func.error.variants {
+            println!("{:10}{:<24} {}", "", code, name);
+        }
+    }
+}
+
 fn hiffy(context: &mut ExecutionContext) -> Result<()> {
     let core = &mut **context.core.as_mut().unwrap();
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
@@ -205,10 +458,23 @@ fn hiffy(context: &mut ExecutionContext) -> Result<()> {
 
     let subargs = HiffyArgs::try_parse_from(subargs)?;
 
-    if subargs.list {
-        hiffy_list(hubris, subargs.filter)?;
+    if (subargs.verbose || subargs.json) && !subargs.list && !subargs.listfuncs
+    {
+        bail!("--verbose and --json require -l or -L");
+    }
+
+    if subargs.list && !subargs.verbose {
+        if subargs.json {
+            let interfaces = hiffy_interfaces(hubris, &subargs.filter)?;
+            let out = serde_json::json!({ "interfaces": interfaces });
+            println!("{}", serde_json::to_string_pretty(&out)?);
+        } else {
+            hiffy_list(hubris, subargs.filter)?;
+        }
+
         return Ok(());
-    } else if !subargs.filter.is_empty() {
+    } else if !subargs.filter.is_empty() && !subargs.list && !subargs.listfuncs
+    {
         //
         // It is likely that the user has provided an argument to a HIF
         // call without specifying -a; generate a message that tries to
@@ -313,7 +579,7 @@ fn hiffy(context: &mut ExecutionContext) -> Result<()> {
             (hiffy_call(hubris, core, &mut context, &op, &args, None)?, None)
         };
 
-        hiffy_print_result(hubris, &op, return_code)?;
+        println!("{}", hiffy_format_call(hubris, &op, return_code));
         if let Some(data) = data {
             if let Some(out) = &subargs.output {
                 std::fs::write(out, &data)
@@ -329,39 +595,35 @@ fn hiffy(context: &mut ExecutionContext) -> Result<()> {
         return Ok(());
     }
 
-    if !subargs.listfuncs {
+    if !subargs.listfuncs && !subargs.list {
         bail!("expected one of -l, -L, or -c");
     }
 
-    let funcs = context.functions();
-    let mut byid: Vec<Option<(&String, &HiffyFunction)>> = vec![];
-
-    byid.resize(funcs.len(), None);
-
-    for (name, func) in &funcs.0 {
-        let ndx = func.id.0 as usize;
-
-        if ndx >= byid.len() {
-            bail!("ID for function {} ({}) exceeds bounds", name, ndx);
-        }
+    //
+    // With -l, the filter applies to interfaces rather than functions.
+    //
+    let ffilter = if subargs.list { &[][..] } else { &subargs.filter[..] };
+    let funcs = hiffy_functions(hubris, &context, ffilter)?;
 
-        if let Some((_, _)) = byid[ndx] {
-            bail!("function ID {} has conflics", ndx);
-        }
+    if subargs.json {
+        let out = if subargs.list {
+            let interfaces = hiffy_interfaces(hubris, &subargs.filter)?;
+            serde_json::json!({ "interfaces": interfaces, "functions": funcs })
+        } else {
+            serde_json::json!({ "functions": funcs })
+        };
 
-        byid[ndx] = Some((name, func));
+        println!("{}", serde_json::to_string_pretty(&out)?);
+        return Ok(());
     }
 
-    println!("{:>3} {:30} #ARGS", "ID", "FUNCTION");
-
-    for (i, id) in byid.iter().enumerate() {
-        if let Some((name, func)) = id {
-            println!("{:3} {:30} {}", i, name, func.args.len());
-        } else {
-            bail!("missing function for ID {}", i);
-        }
+    if subargs.list {
+        hiffy_list(hubris, subargs.filter.clone())?;
+        println!();
     }
 
+    hiffy_functions_print(&funcs, subargs.verbose);
+
     Ok(())
 }
 
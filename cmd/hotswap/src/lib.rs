@@ -0,0 +1,585 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility hotswap`
+//!
+//! `humility hotswap` reads and decodes the fault state and telemetry of
+//! the hot-swap controllers in the system, including the peak (and, where
+//! the part records them, minimum) values that the controller has observed
+//! since they were last cleared.  This allows for post-mortem analysis of
+//! (e.g.) an overcurrent trip without resorting to the vendor's tools.
+//! ADM1272 and LTC4282 controllers are supported; by default, all such
+//! controllers in the manifest are displayed:
+//!
+//! ```console
+//! $ humility hotswap
+//! humility: attached via ST-Link V3
+//! V54_HSC: adm1272 at I2C2, port B, address 0x16 (54V hot swap controller)
+//!   STATUS_WORD        0x0000
+//!   STATUS_MFR         0x00
+//!   STATUS_IOUT        0x00
+//!   STATUS_INPUT       0x00
+//!   STATUS_TEMPERATURE 0x00
+//!                 CURRENT          MIN         PEAK
+//!   VIN            53.87V            -       54.43V
+//!   VOUT           53.81V            -       54.38V
+//!   IOUT            2.14A            -       14.76A
+//!   PIN           115.32W            -      801.12W
+//!   TEMP          33.80°C            -      41.20°C
+//! V12P0_FRONT_IO: ltc4282 at I2C2, port F, address 0x54 (Front I/O ...)
+//!   FAULT_LOG          0x04
+//!     b2 = OC_FAULT
+//!   ADC_ALERT_LOG      0x00
+//!   STATUS             0x18
+//!     b3 = POWER_GOOD
+//!     b4 = ON_STATUS
+//!                 CURRENT          MIN         PEAK
+//!   VSOURCE        12.08V       11.92V       12.21V
+//!   VSENSE         3.12mV       0.00mV      40.00mV
+//!   VGPIO           1.02V        1.01V        1.03V
+//! ```
+//!
+//! Bits set in the fault and status registers are decoded by name.  For
+//! ADM1272 controllers, registers and values are interpreted according to
+//! the part's PMBus definitions; the peak registers are interpreted as the
+//! corresponding telemetry.  The LTC4282 measures current as the voltage
+//! across its sense resistor; if the value of that resistor (in milliohms)
+//! is specified with `--rsense`, currents and power will be displayed as
+//! well.
+//!
+//! To display a single controller, specify its rail with `--rail` (`-r`).
+//! To clear the fault logs and peak registers after displaying them, use
+//! `--clear`.
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use hif::*;
+use humility::core::Core;
+use humility::hubris::*;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_hiffy::*;
+use humility_i2c::I2cRetryPolicy;
+use pmbus::commands::CommandCode;
+
+#[derive(Parser, Debug)]
+#[clap(name = "hotswap", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct HotswapArgs {
+    /// sets timeout
+    #[clap(
+        long, short = 'T', default_value_t = 5000, value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+
+    /// display only the controller for the specified rail
+    #[clap(long, short, value_name = "rail")]
+    rail: Option<String>,
+
+    /// value of the LTC4282 sense resistor, in milliohms
+    #[clap(long, value_name = "milliohms")]
+    rsense: Option<f64>,
+
+    /// clear fault logs and peak registers after displaying them
+    #[clap(long)]
+    clear: bool,
+}
+
+///
+/// ADM1272 peak registers, along with the telemetry that each tracks.  Each
+/// peak register is cleared by writing zero to it.
+///
+const ADM1272_PEAKS: &[(&str, u8, CommandCode)] = &[
+    ("VIN", 0xd1, CommandCode::READ_VIN),
+    ("VOUT", 0xd2, CommandCode::READ_VOUT),
+    ("IOUT", 0xd0, CommandCode::READ_IOUT),
+    ("PIN", 0xda, CommandCode::READ_PIN),
+    ("TEMP", 0xd7, CommandCode::READ_TEMPERATURE_1),
+];
+
+const ADM1272_STATUS: &[(&str, CommandCode, usize)] = &[
+    ("STATUS_WORD", CommandCode::STATUS_WORD, 2),
+    ("STATUS_MFR", CommandCode::STATUS_MFR_SPECIFIC, 1),
+    ("STATUS_IOUT", CommandCode::STATUS_IOUT, 1),
+    ("STATUS_INPUT", CommandCode::STATUS_INPUT, 1),
+    ("STATUS_TEMPERATURE", CommandCode::STATUS_TEMPERATURE, 1),
+];
+
+const LTC4282_FAULT_LOG: u8 = 0x04;
+const LTC4282_ADC_ALERT_LOG: u8 = 0x05;
+const LTC4282_ILIM_ADJUST: u8 = 0x11;
+const LTC4282_STATUS: u8 = 0x1f;
+
+const LTC4282_FAULT_LOG_BITS: &[&str] = &[
+    "OV_FAULT",
+    "UV_FAULT",
+    "OC_FAULT",
+    "POWER_BAD_FAULT",
+    "ON_FAULT",
+    "FET_SHORT_FAULT",
+    "FET_BAD_FAULT",
+    "EEPROM_DONE",
+];
+
+const LTC4282_ADC_ALERT_LOG_BITS: &[&str] = &[
+    "VSENSE_ALARM_LOW",
+    "VSENSE_ALARM_HIGH",
+    "VGPIO_ALARM_LOW",
+    "VGPIO_ALARM_HIGH",
+    "VSOURCE_ALARM_LOW",
+    "VSOURCE_ALARM_HIGH",
+    "POWER_ALARM_LOW",
+    "POWER_ALARM_HIGH",
+];
+
+const LTC4282_STATUS_BITS: &[&str] = &[
+    "OV_STATUS",
+    "UV_STATUS",
+    "OC_STATUS",
+    "POWER_GOOD",
+    "ON_STATUS",
+    "FET_SHORT_PRESENT",
+    "FET_BAD_COOLDOWN",
+    "EEPROM_BUSY",
+];
+
+#[derive(Copy, Clone, Debug)]
+enum Ltc4282Adc {
+    Vgpio,
+    Vsource,
+    Vsense,
+    Power,
+}
+
+///
+/// The LTC4282's ADC registers:  each is a 16-bit value, followed by its
+/// minimum and its maximum.
+///
+const LTC4282_ADC: &[(&str, u8, Ltc4282Adc)] = &[
+    ("VSOURCE", 0x3a, Ltc4282Adc::Vsource),
+    ("VSENSE", 0x40, Ltc4282Adc::Vsense),
+    ("POWER", 0x46, Ltc4282Adc::Power),
+    ("VGPIO", 0x34, Ltc4282Adc::Vgpio),
+];
+
+/// LTC4282 full-scale sense voltage, in millivolts
+const LTC4282_VSENSE_FS: f64 = 40.0;
+
+/// LTC4282 full-scale GPIO voltage, in volts
+const LTC4282_VGPIO_FS: f64 = 1.28;
+
+///
+/// A program of I2C reads to be performed against a single device.
+///
+struct HotswapReader<'a> {
+    context: HiffyContext<'a>,
+    read: HiffyFunction,
+    write: HiffyFunction,
+    ops: Vec<Op>,
+}
+
+impl HotswapReader<'_> {
+    fn begin(&mut self, dev: &HubrisI2cDevice) {
+        self.ops.push(Op::Push(dev.controller));
+        self.ops.push(Op::Push(dev.port.index));
+
+        match (dev.mux, dev.segment) {
+            (Some(mux), Some(segment)) => {
+                self.ops.push(Op::Push(mux));
+                self.ops.push(Op::Push(segment));
+            }
+            _ => {
+                self.ops.push(Op::PushNone);
+                self.ops.push(Op::PushNone);
+            }
+        }
+
+        self.ops.push(Op::Push(dev.address));
+    }
+
+    fn read(&mut self, reg: u8, nbytes: usize) {
+        self.ops.push(Op::Push(reg));
+        self.ops.push(Op::Push(nbytes as u8));
+        self.ops.push(Op::Call(self.read.id));
+        self.ops.push(Op::DropN(2));
+    }
+
+    fn write(&mut self, reg: u8, data: &[u8]) {
+        self.ops.push(Op::Push(reg));
+
+        for &b in data {
+            self.ops.push(Op::Push(b));
+        }
+
+        self.ops.push(Op::Push(data.len() as u8));
+        self.ops.push(Op::Call(self.write.id));
+        self.ops.push(Op::DropN(data.len() as u8 + 2));
+    }
+
+    ///
+    /// Sends a byte that is itself the command (a PMBus "send byte"), by
+    /// indicating the register to be None.
+    ///
+    fn send(&mut self, code: u8) {
+        self.ops.push(Op::PushNone);
+        self.ops.push(Op::Push(code));
+        self.ops.push(Op::Push(1));
+        self.ops.push(Op::Call(self.write.id));
+        self.ops.push(Op::DropN(3));
+    }
+
+    fn run(
+        &mut self,
+        core: &mut dyn Core,
+    ) -> Result<Vec<Result<Vec<u8>, u32>>> {
+        self.ops.push(Op::DropN(5));
+        self.ops.push(Op::Done);
+
+        let ops = std::mem::take(&mut self.ops);
+
+        I2cRetryPolicy::default().run(
+            &mut self.context,
+            core,
+            &ops,
+            None,
+            &self.read,
+        )
+    }
+
+    fn strerror(&self, err: u32) -> String {
+        self.read.strerror(err)
+    }
+}
+
+fn hotswap_bits(val: u32, bits: &[&str]) {
+    for (i, name) in bits.iter().enumerate() {
+        if val & (1 << i) != 0 {
+            println!("    b{i} = {name}");
+        }
+    }
+}
+
+fn hotswap_telemetry_header() {
+    println!("  {:8} {:>12} {:>12} {:>12}", "", "CURRENT", "MIN", "PEAK");
+}
+
+fn hotswap_telemetry(name: &str, vals: [Option<String>; 3]) {
+    let [cur, min, peak] = vals.map(|v| v.unwrap_or_else(|| "-".to_string()));
+
+    println!("  {name:8} {cur:>12} {min:>12} {peak:>12}");
+}
+
+fn adm1272(
+    reader: &mut HotswapReader,
+    core: &mut dyn Core,
+    dev: &HubrisI2cDevice,
+    clear: bool,
+) -> Result<()> {
+    let device = pmbus::Device::Adm1272;
+
+    reader.begin(dev);
+
+    for (_, code, nbytes) in ADM1272_STATUS {
+        reader.read(*code as u8, *nbytes);
+    }
+
+    for (_, peak, code) in ADM1272_PEAKS {
+        reader.read(*code as u8, 2);
+        reader.read(*peak, 2);
+    }
+
+    let results = reader.run(core)?;
+    let mode = || pmbus::commands::VOUT_MODE::CommandData(0);
+
+    for ((name, code, _), result) in ADM1272_STATUS.iter().zip(&results) {
+        let val = match result {
+            Ok(val) => val,
+            Err(err) => {
+                println!("  {name:18} <{}>", reader.strerror(*err));
+                continue;
+            }
+        };
+
+        match val[..] {
+            [b] => println!("  {name:18} 0x{b:02x}"),
+            [lo, hi] => println!("  {name:18} 0x{hi:02x}{lo:02x}"),
+            _ => println!("  {name:18} {val:x?}"),
+        }
+
+        let _ = device.interpret(*code as u8, val, mode, |field, value| {
+            if !field.bitfield() || value.raw() == 0 {
+                return;
+            }
+
+            let (pos, width) = field.bits();
+
+            if width.0 == 1 {
+                println!("    b{} = {}", pos.0, field.name());
+            } else {
+                println!(
+                    "    b{}:{} = {} ({value})",
+                    pos.0 + width.0 - 1,
+                    pos.0,
+                    field.name(),
+                );
+            }
+        });
+    }
+
+    //
+    // The peak registers have the same format as the telemetry that they
+    // track, so we interpret them as that telemetry.
+    //
+    let interpret = |code: CommandCode, result: &Result<Vec<u8>, u32>| {
+        let val = result.as_ref().ok()?;
+        let mut rval = None;
+
+        let _ = device.interpret(code as u8, val, mode, |field, value| {
+            if !field.bitfield() {
+                rval = Some(format!("{value}"));
+            }
+        });
+
+        rval.or_else(|| Some(format!("{val:x?}")))
+    };
+
+    hotswap_telemetry_header();
+
+    let telemetry = results[ADM1272_STATUS.len()..].chunks(2);
+
+    for ((name, _, code), results) in ADM1272_PEAKS.iter().zip(telemetry) {
+        hotswap_telemetry(
+            name,
+            [
+                interpret(*code, &results[0]),
+                None,
+                interpret(*code, &results[1]),
+            ],
+        );
+    }
+
+    if clear {
+        reader.begin(dev);
+
+        for (_, peak, _) in ADM1272_PEAKS {
+            reader.write(*peak, &[0, 0]);
+        }
+
+        reader.send(CommandCode::CLEAR_FAULTS as u8);
+
+        for result in reader.run(core)? {
+            if let Err(err) = result {
+                bail!("failed to clear faults: {}", reader.strerror(err));
+            }
+        }
+
+        humility::msg!("cleared peak registers and faults");
+    }
+
+    Ok(())
+}
+
+fn ltc4282(
+    reader: &mut HotswapReader,
+    core: &mut dyn Core,
+    dev: &HubrisI2cDevice,
+    rsense: Option<f64>,
+    clear: bool,
+) -> Result<()> {
+    let regs = [
+        ("FAULT_LOG", LTC4282_FAULT_LOG, LTC4282_FAULT_LOG_BITS),
+        ("ADC_ALERT_LOG", LTC4282_ADC_ALERT_LOG, LTC4282_ADC_ALERT_LOG_BITS),
+        ("STATUS", LTC4282_STATUS, LTC4282_STATUS_BITS),
+    ];
+
+    reader.begin(dev);
+    reader.read(LTC4282_ILIM_ADJUST, 1);
+
+    for (_, reg, _) in &regs {
+        reader.read(*reg, 1);
+    }
+
+    for (_, reg, _) in LTC4282_ADC {
+        reader.read(*reg, 6);
+    }
+
+    let results = reader.run(core)?;
+
+    for ((name, _, bits), result) in regs.iter().zip(&results[1..]) {
+        match result {
+            Ok(val) if val.len() == 1 => {
+                println!("  {name:18} 0x{:02x}", val[0]);
+                hotswap_bits(val[0].into(), bits);
+            }
+            Ok(val) => println!("  {name:18} {val:x?}"),
+            Err(err) => println!("  {name:18} <{}>", reader.strerror(*err)),
+        }
+    }
+
+    //
+    // The full-scale source voltage depends on the VIN_MODE in bits 2:1
+    // of ILIM_ADJUST.
+    //
+    let vsource_fs = match &results[0] {
+        Ok(val) if val.len() == 1 => match (val[0] >> 1) & 0b11 {
+            0b00 => 5.54,
+            0b01 => 8.32,
+            0b10 => 16.64,
+            _ => 33.28,
+        },
+        _ => bail!("failed to read ILIM_ADJUST"),
+    };
+
+    let convert = |adc: Ltc4282Adc, raw: u16| -> Option<String> {
+        let frac = f64::from(raw) / 65535.0;
+
+        match adc {
+            Ltc4282Adc::Vsource => Some(format!("{:.2}V", frac * vsource_fs)),
+            Ltc4282Adc::Vgpio => {
+                Some(format!("{:.2}V", frac * LTC4282_VGPIO_FS))
+            }
+            Ltc4282Adc::Vsense => {
+                Some(format!("{:.2}mV", frac * LTC4282_VSENSE_FS))
+            }
+            Ltc4282Adc::Power => rsense.map(|r| {
+                let watts = frac * vsource_fs * LTC4282_VSENSE_FS / r;
+                format!("{watts:.2}W")
+            }),
+        }
+    };
+
+    hotswap_telemetry_header();
+
+    let adc = &results[1 + regs.len()..];
+
+    for ((name, _, kind), result) in LTC4282_ADC.iter().zip(adc) {
+        let val = match result {
+            Ok(val) if val.len() == 6 => val,
+            Ok(val) => {
+                println!("  {name:8} short read: {val:x?}");
+                continue;
+            }
+            Err(err) => {
+                println!("  {name:8} <{}>", reader.strerror(*err));
+                continue;
+            }
+        };
+
+        //
+        // Each ADC register is followed by its minimum and its maximum.
+        //
+        let vals = [0, 2, 4].map(|i| u16::from_be_bytes([val[i], val[i + 1]]));
+
+        if matches!(kind, Ltc4282Adc::Power) && rsense.is_none() {
+            hotswap_telemetry(name, vals.map(|v| Some(format!("0x{v:04x}"))));
+            continue;
+        }
+
+        hotswap_telemetry(name, vals.map(|v| convert(*kind, v)));
+
+        if let (Ltc4282Adc::Vsense, Some(r)) = (kind, rsense) {
+            let amps = vals.map(|v| {
+                let mv = f64::from(v) / 65535.0 * LTC4282_VSENSE_FS;
+                Some(format!("{:.2}A", mv / r))
+            });
+
+            hotswap_telemetry("IOUT", amps);
+        }
+    }
+
+    if clear {
+        reader.begin(dev);
+        reader.write(LTC4282_FAULT_LOG, &[0]);
+        reader.write(LTC4282_ADC_ALERT_LOG, &[0]);
+
+        for result in reader.run(core)? {
+            if let Err(err) = result {
+                bail!("failed to clear logs: {}", reader.strerror(err));
+            }
+        }
+
+        humility::msg!("cleared fault and ADC alert logs");
+    }
+
+    Ok(())
+}
+
+fn hotswap(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = HotswapArgs::try_parse_from(subargs)?;
+
+    let rails = |dev: &HubrisI2cDevice| match &dev.class {
+        HubrisI2cDeviceClass::Pmbus { rails } => {
+            rails.iter().map(|r| r.name.clone()).collect::<Vec<_>>()
+        }
+        _ => vec![],
+    };
+
+    let devices = hubris
+        .manifest
+        .i2c_devices
+        .iter()
+        .filter(|d| d.device == "adm1272" || d.device == "ltc4282")
+        .filter(|d| match &subargs.rail {
+            Some(rail) => rails(d).contains(rail),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+
+    if devices.is_empty() {
+        match &subargs.rail {
+            Some(rail) => bail!("no hot-swap controller for rail {rail}"),
+            None => bail!("no hot-swap controllers found in the manifest"),
+        }
+    }
+
+    let context = HiffyContext::new(hubris, core, subargs.timeout)?;
+    let read = context.get_function("I2cRead", 7)?;
+    let write = context.get_function("I2cWrite", 8)?;
+
+    let mut reader = HotswapReader { context, read, write, ops: vec![] };
+
+    for dev in devices {
+        let rails = rails(dev);
+
+        println!(
+            "{}: {} at I2C{}, port {}, address 0x{:02x} ({})",
+            if rails.is_empty() { "-".to_string() } else { rails.join(", ") },
+            dev.device,
+            dev.controller,
+            dev.port.name,
+            dev.address,
+            dev.description
+        );
+
+        let rval = if dev.device == "adm1272" {
+            adm1272(&mut reader, core, dev, subargs.clear)
+        } else {
+            ltc4282(&mut reader, core, dev, subargs.rsense, subargs.clear)
+        };
+
+        if let Err(err) = rval {
+            humility::warn!("{}: {err}", dev.description);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: HotswapArgs::command(),
+        name: "hotswap",
+        run: hotswap,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
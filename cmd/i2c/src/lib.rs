@@ -99,16 +99,84 @@
 //! last selected mux/segment for I2C2, port F: mux 3, segment 2
 //! ```
 //!
+//! To perform an arbitrary sequence of messages on a device, use
+//! `--transaction` (`-x`), specifying each message as either a write (`w`,
+//! optionally followed by a colon and a comma-separated list of bytes) or a
+//! read (`r`, followed by a colon and the number of bytes to read).  A
+//! single-byte write followed by a read is performed with a repeated start; a
+//! lone `w` is a zero-length write that can be used to probe for a device.  For
+//! example, to read two bytes from an EEPROM with 16-bit addressing:
+//!
+//! ```console
+//! $ humility i2c -b mid -d 0x50 -x "w:0x00,0x10 r:2"
+//! humility: attached via ST-Link V3
+//! I2C3, port H, dev 0x50, transaction:
+//!   write 0x00 0x10                          = Success
+//!   read 2                                   = 0x4f 0x58
+//! ```
+//!
+//! Note that the `I2cRead` HIF function can only express a repeated start
+//! after a single-byte write.  If the image provides the `I2cWriteRead` HIF
+//! function, a multi-byte write followed by a read is performed with a repeated
+//! start; otherwise (as in the example above), it is performed as a write with
+//! a stop followed by a read.  A single write is limited to 253 bytes.
+//!
+//! To localize problems on buses with multiple segments, `--verbose` (`-v`)
+//! prints the planned sequence of phases (mux segment selection, along with the
+//! address, direction and length of each transfer) before performing the
+//! operation, and then annotates each phase with its result.  As the mux is
+//! selected by the I2C driver as part of each transfer, an error from the mux
+//! is attributed to the mux rather than to the transfer:
+//!
+//! ```console
+//! $ humility i2c -b mid -m 1:2 -d 0x50 -x "w:0x00,0x10 r:2" -v
+//! humility: attached via ST-Link V3
+//! I2C3, port H, seg 1:2, dev 0x50, planned:
+//!    1. mux 1: enable segment 2
+//!    2. dev 0x50: write 2 bytes (0x00 0x10), stop
+//!    3. dev 0x50: read 2 bytes
+//! I2C3, port H, seg 1:2, dev 0x50, results:
+//!    1. mux 1: enable segment 2                      = Err(MuxMissing)
+//!    2. dev 0x50: write 2 bytes (0x00 0x10), stop    = Not performed
+//!    3. dev 0x50: read 2 bytes                       = Not performed
+//! ```
+//!
+//! On buses that have no header to which a protocol analyzer can be
+//! attached, `--sniff` captures the traffic on a bus from the target itself
+//! (via the `I2cSniff` HIF function, which must be supported by the
+//! firmware), streaming the captured transfers back and decoding them until
+//! interrupted (or until `--duration` seconds have elapsed).  Transfers are
+//! attributed to the devices on the bus as described by the archive; for
+//! PMBus devices, command codes are named, and the PEC of a read (if
+//! present) is checked.  To see only the traffic to one device, specify it
+//! via `-d`:
+//!
+//! ```console
+//! $ humility i2c -b mid --sniff --duration 5
+//! humility: attached via ST-Link V3
+//! humility: sniffing I2C3, port H; ^C to stop
+//!    SEQ ADDR DEVICE           TRANSFER
+//!      0 0x24 tps546b24a       read 0x8b READ_VOUT => 0x9a 0x01 (PEC ok)
+//!      1 0x24 tps546b24a       read 0x79 STATUS_WORD => 0x00 0x00
+//!      2 0x48 tmp117           write 0x00
+//!      3 0x48 tmp117           read 2 => 0x0c 0x80
+//!      4 0x4a -                write 0x00 => NAK
+//! ```
+//!
 
 use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
 use hif::*;
+use humility::core::Core;
+use humility::hubris::*;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Dumper, Validate};
 use humility_hiffy::*;
+use humility_i2c::{I2cOperation, I2cRetryPolicy, I2cTransaction};
 use humility_log::msg;
 
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::io::Read;
@@ -117,6 +185,8 @@ use std::time::Instant;
 use indicatif::{HumanBytes, HumanDuration};
 use indicatif::{ProgressBar, ProgressStyle};
 
+mod sniff;
+
 #[derive(Parser, Debug, Default)]
 #[clap(name = "i2c", about = env!("CARGO_PKG_DESCRIPTION"))]
 pub struct I2cArgs {
@@ -215,6 +285,39 @@ pub struct I2cArgs {
         ],
     )]
     lastmux: bool,
+
+    /// perform a transaction consisting of a sequence of messages, e.g.
+    /// "w:0x12,0x34 r:2" to write a 16-bit register address and then read
+    /// two bytes, or "w" for a zero-length write
+    #[clap(long, short = 'x', value_name = "messages",
+        conflicts_with_all = &[
+            "write", "raw", "nbytes", "register", "scan", "scanreg",
+            "writeraw", "flash", "lastmux", "block",
+        ],
+        requires = "device",
+    )]
+    transaction: Option<I2cTransaction>,
+
+    /// print the planned sequence of bus transactions before performing
+    /// them, and annotate each with its result
+    #[clap(long, short,
+        conflicts_with_all = &["scan", "scanreg", "flash", "lastmux"],
+    )]
+    verbose: bool,
+
+    /// sniff the bus, capturing transactions on the target and decoding
+    /// them until interrupted
+    #[clap(long,
+        conflicts_with_all = &[
+            "write", "raw", "nbytes", "register", "scan", "scanreg",
+            "writeraw", "flash", "lastmux", "block", "transaction",
+        ],
+    )]
+    sniff: bool,
+
+    /// with --sniff, the number of seconds for which to sniff
+    #[clap(long, value_name = "seconds", requires = "sniff")]
+    duration: Option<u64>,
 }
 
 fn i2c_done(
@@ -439,6 +542,367 @@ fn i2c_done(
     Ok(())
 }
 
+/// A phase of an I2C operation, as planned before the operation is
+/// performed.
+#[derive(Clone, Debug)]
+enum I2cPhase {
+    /// Enabling a segment on a mux.  This is performed by the I2C driver as
+    /// part of each transfer to a device behind the mux; it has no HIF call
+    /// of its own.
+    Mux { mux: u8, segment: u8 },
+    /// A write of the specified bytes, followed by a stop
+    Write { address: u8, bytes: Vec<u8> },
+    /// A read of the specified number of bytes (or of a block, if none),
+    /// preceded by a write of the register and a repeated start if a
+    /// register is specified
+    Read { address: u8, register: Option<u8>, nbytes: Option<u8> },
+    /// A write of the specified bytes, followed by a repeated start and a
+    /// read of the specified number of bytes
+    WriteRead { address: u8, bytes: Vec<u8>, nbytes: u8 },
+}
+
+impl fmt::Display for I2cPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+
+        match self {
+            I2cPhase::Mux { mux, segment } => {
+                write!(f, "mux {}: enable segment {}", mux, segment)
+            }
+            I2cPhase::Write { address, bytes } if bytes.is_empty() => {
+                write!(f, "dev 0x{:02x}: write 0 bytes, stop", address)
+            }
+            I2cPhase::Write { address, bytes } => write!(
+                f,
+                "dev 0x{:02x}: write {} byte{} ({}), stop",
+                address,
+                bytes.len(),
+                plural(bytes.len()),
+                bytes
+                    .iter()
+                    .map(|b| format!("0x{:02x}", b))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            I2cPhase::Read { address, register, nbytes } => {
+                write!(f, "dev 0x{:02x}: ", address)?;
+
+                if let Some(register) = register {
+                    write!(f, "write 1 byte (0x{:02x}), restart, ", register)?;
+                }
+
+                match nbytes {
+                    Some(n) => {
+                        write!(f, "read {} byte{}", n, plural(*n as usize))
+                    }
+                    None => write!(f, "read block"),
+                }
+            }
+            I2cPhase::WriteRead { address, bytes, nbytes } => write!(
+                f,
+                "dev 0x{:02x}: write {} byte{} ({}), restart, read {} byte{}",
+                address,
+                bytes.len(),
+                plural(bytes.len()),
+                bytes
+                    .iter()
+                    .map(|b| format!("0x{:02x}", b))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                nbytes,
+                plural(*nbytes as usize),
+            ),
+        }
+    }
+}
+
+///
+/// Plans the phases of an operation:  the selection of the mux segment (if
+/// any) followed by a phase for each HIF call, in order.
+///
+fn i2c_plan(
+    hargs: &humility_i2c::I2cArgs,
+    calls: impl IntoIterator<Item = I2cPhase>,
+) -> Vec<I2cPhase> {
+    let mut plan = vec![];
+
+    if let Some((mux, segment)) = hargs.mux {
+        plan.push(I2cPhase::Mux { mux, segment });
+    }
+
+    plan.extend(calls);
+    plan
+}
+
+fn i2c_plan_print(hargs: &humility_i2c::I2cArgs, plan: &[I2cPhase]) {
+    println!("{}, planned:", hargs);
+
+    for (ndx, phase) in plan.iter().enumerate() {
+        println!("  {:>2}. {}", ndx + 1, phase);
+    }
+}
+
+///
+/// Annotates each phase of a plan with its result.  Because mux selection
+/// is performed as part of each transfer, an error from the mux (which we
+/// identify by name) is attributed to the mux phase rather than to the
+/// transfer that encountered it.  (`I2cRead` and `I2cWrite` share an error
+/// type, so either function can be used to name an error.)
+///
+fn i2c_plan_done(
+    hargs: &humility_i2c::I2cArgs,
+    plan: &[I2cPhase],
+    results: &[Result<Vec<u8>, u32>],
+    func: &HiffyFunction,
+) {
+    let mux_error = |err: u32| {
+        func.errmap.get(&err).map_or(false, |name| {
+            name.contains("Mux") || name.contains("Segment")
+        })
+    };
+
+    let bytes = |val: &[u8]| {
+        val.iter().map(|b| format!("0x{:02x}", b)).collect::<Vec<_>>().join(" ")
+    };
+
+    println!("{}, results:", hargs);
+
+    let mut call = 0;
+
+    for (ndx, phase) in plan.iter().enumerate() {
+        print!("  {:>2}. {:<44} = ", ndx + 1, phase.to_string());
+
+        if let I2cPhase::Mux { .. } = phase {
+            let err = results.iter().find_map(|r| match r {
+                Err(err) if mux_error(*err) => Some(*err),
+                _ => None,
+            });
+
+            match err {
+                Some(err) => println!("Err({})", func.strerror(err)),
+                None if results.is_empty() => println!("Timed out"),
+                None => println!("Success"),
+            }
+
+            continue;
+        }
+
+        match results.get(call) {
+            None => println!("Timed out"),
+            Some(Err(err)) if mux_error(*err) => println!("Not performed"),
+            Some(Err(err)) => println!("Err({})", func.strerror(*err)),
+            Some(Ok(val)) if val.is_empty() => println!("Success"),
+            Some(Ok(val)) if val.len() > 8 => {
+                println!("{} bytes", val.len());
+                let mut dumper = Dumper::new();
+                dumper.indent = 6;
+                dumper.dump(val, 0);
+            }
+            Some(Ok(val)) => println!("{}", bytes(val)),
+        }
+
+        call += 1;
+    }
+}
+
+fn i2c_transaction(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &I2cArgs,
+    transaction: &I2cTransaction,
+) -> Result<()> {
+    let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
+    let read_func = context.get_function("I2cRead", 7)?;
+    let write_func = context.get_function("I2cWrite", 8)?;
+    let write_read_func = context.get_function("I2cWriteRead", 8).ok();
+
+    let hargs = humility_i2c::I2cArgs::parse(
+        hubris,
+        &subargs.bus,
+        subargs.controller,
+        &subargs.port,
+        &subargs.mux,
+        &subargs.device,
+    )?;
+
+    let address = match hargs.address {
+        Some(address) => address,
+        None => bail!("expected device"),
+    };
+
+    let operations = transaction.operations(write_read_func.is_some())?;
+
+    let plan = i2c_plan(
+        &hargs,
+        operations.iter().map(|op| match op {
+            I2cOperation::Write(bytes) => {
+                I2cPhase::Write { address, bytes: bytes.clone() }
+            }
+            I2cOperation::Read { register, nbytes } => I2cPhase::Read {
+                address,
+                register: *register,
+                nbytes: Some(*nbytes),
+            },
+            I2cOperation::WriteRead { bytes, nbytes } => I2cPhase::WriteRead {
+                address,
+                bytes: bytes.clone(),
+                nbytes: *nbytes,
+            },
+        }),
+    );
+
+    if subargs.verbose {
+        i2c_plan_print(&hargs, &plan);
+    }
+
+    let mut ops = vec![Op::Push(hargs.controller), Op::Push(hargs.port.index)];
+
+    if let Some(mux) = hargs.mux {
+        ops.push(Op::Push(mux.0));
+        ops.push(Op::Push(mux.1));
+    } else {
+        ops.push(Op::PushNone);
+        ops.push(Op::PushNone);
+    }
+
+    ops.push(Op::Push(address));
+
+    for op in &operations {
+        match op {
+            I2cOperation::Write(bytes) => {
+                //
+                // We perform all writes as raw writes (that is, with a None
+                // register), with the payload constituting the entire
+                // message.
+                //
+                ops.push(Op::PushNone);
+
+                for b in bytes {
+                    ops.push(Op::Push(*b));
+                }
+
+                //
+                // The bytes, the register and the length are all dropped in
+                // a single DropN; the parser bounds the length of a write to
+                // ensure that this fits.
+                //
+                let drop = u8::try_from(bytes.len() + 2)?;
+
+                ops.push(Op::Push32(bytes.len() as u32));
+                ops.push(Op::Call(write_func.id));
+                ops.push(Op::DropN(drop));
+            }
+            I2cOperation::WriteRead { bytes, nbytes } => {
+                let func = write_read_func.as_ref().unwrap();
+                let drop = u8::try_from(bytes.len() + 2)?;
+
+                for b in bytes {
+                    ops.push(Op::Push(*b));
+                }
+
+                ops.push(Op::Push32(bytes.len() as u32));
+                ops.push(Op::Push(*nbytes));
+                ops.push(Op::Call(func.id));
+                ops.push(Op::DropN(drop));
+            }
+            I2cOperation::Read { register, nbytes } => {
+                match register {
+                    Some(register) => ops.push(Op::Push(*register)),
+                    None => ops.push(Op::PushNone),
+                }
+
+                ops.push(Op::Push(*nbytes));
+                ops.push(Op::Call(read_func.id));
+                ops.push(Op::DropN(2));
+            }
+        }
+    }
+
+    ops.push(Op::Done);
+
+    //
+    // A transaction is performed exactly once, without retries:  we don't
+    // know that an arbitrary sequence of messages is idempotent.
+    //
+    let results = context.run(core, ops.as_slice(), None)?;
+
+    if subargs.verbose {
+        i2c_plan_done(&hargs, &plan, &results, &read_func);
+        return Ok(());
+    }
+
+    println!("{}, transaction:", hargs);
+
+    for (ndx, op) in operations.iter().enumerate() {
+        let (desc, func) = match op {
+            I2cOperation::Write(bytes) if bytes.is_empty() => {
+                ("write (zero-length)".to_string(), &write_func)
+            }
+            I2cOperation::Write(bytes) => (
+                format!(
+                    "write {}",
+                    bytes
+                        .iter()
+                        .map(|b| format!("0x{:02x}", b))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                ),
+                &write_func,
+            ),
+            I2cOperation::Read { register: Some(r), nbytes } => (
+                format!("write 0x{:02x}, restart, read {}", r, nbytes),
+                &read_func,
+            ),
+            I2cOperation::Read { register: None, nbytes } => {
+                (format!("read {}", nbytes), &read_func)
+            }
+            I2cOperation::WriteRead { bytes, nbytes } => (
+                format!(
+                    "write {}, restart, read {}",
+                    bytes
+                        .iter()
+                        .map(|b| format!("0x{:02x}", b))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    nbytes
+                ),
+                write_read_func.as_ref().unwrap(),
+            ),
+        };
+
+        print!("  {:<40} = ", desc);
+
+        match results.get(ndx) {
+            None => {
+                println!("Timed out");
+            }
+            Some(Err(err)) => {
+                println!("Err({})", func.strerror(*err));
+            }
+            Some(Ok(val)) if val.len() > 2 => {
+                println!();
+                let mut dumper = Dumper::new();
+                dumper.indent = 4;
+                dumper.dump(val, 0);
+            }
+            Some(Ok(val)) if !val.is_empty() => {
+                println!(
+                    "{}",
+                    val.iter()
+                        .map(|b| format!("0x{:02x}", b))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                );
+            }
+            Some(Ok(_)) => {
+                println!("Success");
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn i2c(context: &mut ExecutionContext) -> Result<()> {
     let core = &mut **context.core.as_mut().unwrap();
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
@@ -452,13 +916,34 @@ fn i2c(context: &mut ExecutionContext) -> Result<()> {
         && !subargs.raw
         && subargs.flash.is_none()
         && !subargs.lastmux
+        && subargs.transaction.is_none()
+        && !subargs.sniff
     {
         bail!(
             "must indicate a scan (-s/-S), specify a register (-r), \
-            indicate raw (-R), flash (-f), or last selected mux/segment (-l)"
+            indicate raw (-R), flash (-f), last selected mux/segment (-l), \
+            a transaction (-x), or a sniff (--sniff)"
         );
     }
 
+    if let Some(ref transaction) = subargs.transaction {
+        return i2c_transaction(hubris, core, &subargs, transaction);
+    }
+
+    if subargs.sniff {
+        let hargs = humility_i2c::I2cArgs::parse(
+            hubris,
+            &subargs.bus,
+            subargs.controller,
+            &subargs.port,
+            &subargs.mux,
+            &subargs.device,
+        )?;
+
+        let (timeout, duration) = (subargs.timeout, subargs.duration);
+        return sniff::i2c_sniff(hubris, core, timeout, duration, &hargs);
+    }
+
     let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
 
     let (fname, args) = if subargs.flash.is_some() {
@@ -662,12 +1147,15 @@ fn i2c(context: &mut ExecutionContext) -> Result<()> {
         return Ok(());
     }
 
+    let mut plan = vec![];
+
     if !subargs.scan && subargs.scanreg.is_none() {
-        if let Some(address) = hargs.address {
-            ops.push(Op::Push(address));
-        } else {
-            bail!("expected device");
-        }
+        let address = match hargs.address {
+            Some(address) => address,
+            None => bail!("expected device"),
+        };
+
+        ops.push(Op::Push(address));
 
         if let Some(ref write) = subargs.write {
             if let Some(register) = subargs.register {
@@ -692,6 +1180,13 @@ fn i2c(context: &mut ExecutionContext) -> Result<()> {
             }
 
             ops.push(Op::Push32(arr.len() as u32));
+
+            //
+            // A write to a register is a write of the register followed by
+            // the payload.
+            //
+            let bytes = subargs.register.iter().chain(arr.iter()).copied();
+            plan.push(I2cPhase::Write { address, bytes: bytes.collect() });
         } else if subargs.writeraw {
             //
             // We know that we have a register when -W has been specified; use
@@ -700,6 +1195,11 @@ fn i2c(context: &mut ExecutionContext) -> Result<()> {
             ops.push(Op::PushNone);
             ops.push(Op::Push(subargs.register.unwrap()));
             ops.push(Op::Push(1));
+
+            plan.push(I2cPhase::Write {
+                address,
+                bytes: vec![subargs.register.unwrap()],
+            });
         } else {
             if let Some(register) = subargs.register {
                 ops.push(Op::Push(register));
@@ -707,13 +1207,22 @@ fn i2c(context: &mut ExecutionContext) -> Result<()> {
                 ops.push(Op::PushNone);
             }
 
-            if let Some(nbytes) = subargs.nbytes {
-                ops.push(Op::Push(nbytes));
-            } else if subargs.block {
-                ops.push(Op::PushNone);
-            } else {
-                ops.push(Op::Push(1));
+            let nbytes = match subargs.nbytes {
+                Some(nbytes) => Some(nbytes),
+                None if subargs.block => None,
+                None => Some(1),
+            };
+
+            match nbytes {
+                Some(nbytes) => ops.push(Op::Push(nbytes)),
+                None => ops.push(Op::PushNone),
             }
+
+            plan.push(I2cPhase::Read {
+                address,
+                register: subargs.register,
+                nbytes,
+            });
         }
 
         ops.push(Op::Call(func.id));
@@ -751,7 +1260,24 @@ fn i2c(context: &mut ExecutionContext) -> Result<()> {
 
     ops.push(Op::Done);
 
-    let results = context.run(core, ops.as_slice(), None)?;
+    let plan = i2c_plan(&hargs, plan);
+
+    if subargs.verbose {
+        i2c_plan_print(&hargs, &plan);
+    }
+
+    let results = I2cRetryPolicy::default().run(
+        &mut context,
+        core,
+        ops.as_slice(),
+        None,
+        &func,
+    )?;
+
+    if subargs.verbose {
+        i2c_plan_done(&hargs, &plan, &results, &func);
+        return Ok(());
+    }
 
     i2c_done(&subargs, &hargs, &results, &func)?;
 
@@ -0,0 +1,341 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Sniffing of I2C traffic.  The capture itself is performed on the target
+//! (by the `I2cSniff` HIF function), which records the transactions that it
+//! observes on a bus; we repeatedly collect these and decode them.  The
+//! capture is returned as a sequence of records, one per transfer:
+//!
+//! - a byte containing the 7-bit address shifted left by one, with the low
+//!   bit set if the transfer is a read;
+//! - a byte of flags (see the `I2C_SNIFF_*` constants below);
+//! - a byte containing the number of data bytes transferred;
+//! - the data bytes themselves.
+//!
+
+use anyhow::{bail, Result};
+use hif::*;
+use humility::core::Core;
+use humility::hubris::*;
+use humility_hiffy::*;
+use humility_log::msg;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+/// The address was not acknowledged
+const I2C_SNIFF_NAK: u8 = 1 << 0;
+
+/// The transfer was terminated by a repeated start rather than a stop
+const I2C_SNIFF_RESTART: u8 = 1 << 1;
+
+/// Transfers were lost (because the capture buffer filled) before this one
+const I2C_SNIFF_OVERRUN: u8 = 1 << 2;
+
+#[derive(Clone, Debug)]
+struct I2cTransfer {
+    address: u8,
+    read: bool,
+    flags: u8,
+    data: Vec<u8>,
+}
+
+fn i2c_sniff_parse(buf: &[u8]) -> Result<Vec<I2cTransfer>> {
+    let mut transfers = vec![];
+    let mut offs = 0;
+
+    while offs < buf.len() {
+        if offs + 3 > buf.len() {
+            bail!("truncated capture record at offset {offs}");
+        }
+
+        let len = buf[offs + 2] as usize;
+        let data = match buf.get(offs + 3..offs + 3 + len) {
+            Some(data) => data.to_vec(),
+            None => bail!("capture record at offset {offs} is too short"),
+        };
+
+        transfers.push(I2cTransfer {
+            address: buf[offs] >> 1,
+            read: buf[offs] & 1 != 0,
+            flags: buf[offs + 1],
+            data,
+        });
+
+        offs += 3 + len;
+    }
+
+    Ok(transfers)
+}
+
+///
+/// Computes the SMBus Packet Error Code (a CRC-8 with a polynomial of 0x07)
+/// over the specified bytes.
+///
+fn i2c_pec(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |crc, &b| {
+        (0..8).fold(crc ^ b, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn i2c_bytes(data: &[u8]) -> String {
+    data.iter().map(|b| format!("0x{b:02x}")).collect::<Vec<_>>().join(" ")
+}
+
+///
+/// The context for decoding transfers:  the devices on the bus (and their
+/// PMBus drivers, if they are PMBus devices), along with any transfer that
+/// was terminated by a repeated start and may be the first half of an SMBus
+/// read.
+///
+struct I2cSniffer<'a> {
+    devices: Vec<(&'a HubrisI2cDevice, Option<pmbus::Device>)>,
+    pending: Option<I2cTransfer>,
+    filter: Option<u8>,
+    seq: usize,
+}
+
+impl<'a> I2cSniffer<'a> {
+    fn new(hubris: &'a HubrisArchive, hargs: &humility_i2c::I2cArgs) -> Self {
+        let devices = hubris
+            .manifest
+            .i2c_devices
+            .iter()
+            .filter(|d| {
+                d.controller == hargs.controller
+                    && d.port.index == hargs.port.index
+                    && hargs.mux.map_or(true, |(mux, segment)| {
+                        d.mux == Some(mux) && d.segment == Some(segment)
+                    })
+            })
+            .map(|d| {
+                let driver = match d.class {
+                    HubrisI2cDeviceClass::Pmbus { .. } => Some(
+                        pmbus::Device::from_str(&d.device)
+                            .unwrap_or(pmbus::Device::Common),
+                    ),
+                    _ => None,
+                };
+
+                (d, driver)
+            })
+            .collect();
+
+        Self { devices, pending: None, filter: hargs.address, seq: 0 }
+    }
+
+    fn device(
+        &self,
+        address: u8,
+    ) -> Option<&(&'a HubrisI2cDevice, Option<pmbus::Device>)> {
+        self.devices.iter().find(|(d, _)| d.address == address)
+    }
+
+    ///
+    /// Describes an SMBus command code, naming it if the device is a PMBus
+    /// device that knows it.
+    ///
+    fn command(&self, address: u8, code: u8) -> String {
+        let mut name = None;
+
+        if let Some((_, Some(driver))) = self.device(address) {
+            driver.command(code, |cmd| name = Some(cmd.name().to_string()));
+        }
+
+        match name {
+            Some(name) => format!("0x{code:02x} {name}"),
+            None => format!("0x{code:02x}"),
+        }
+    }
+
+    ///
+    /// Determines the validity of the PEC of an SMBus read, if the length
+    /// of the read indicates that it includes one.
+    ///
+    fn read_pec(&self, address: u8, code: u8, data: &[u8]) -> Option<bool> {
+        let (_, driver) = self.device(address)?;
+        let mut expected = None;
+
+        driver.as_ref()?.command(code, |cmd| {
+            expected = match cmd.read_op() {
+                pmbus::Operation::ReadByte => Some(1),
+                pmbus::Operation::ReadWord => Some(2),
+                pmbus::Operation::ReadWord32 => Some(4),
+                pmbus::Operation::ReadBlock => {
+                    data.first().map(|&n| n as usize + 1)
+                }
+                _ => None,
+            }
+        });
+
+        let (pec, payload) = data.split_last()?;
+
+        if expected? != payload.len() {
+            return None;
+        }
+
+        let mut bytes = vec![address << 1, code, (address << 1) | 1];
+        bytes.extend_from_slice(payload);
+
+        Some(i2c_pec(&bytes) == *pec)
+    }
+
+    fn print(&mut self, address: u8, nak: bool, what: String) {
+        if self.filter.map_or(false, |f| f != address) {
+            return;
+        }
+
+        let device = match self.device(address) {
+            Some((d, _)) => d.name.as_ref().unwrap_or(&d.device).as_str(),
+            None => "-",
+        };
+
+        println!(
+            "{:>6} 0x{:02x} {:<16} {}{}",
+            self.seq,
+            address,
+            device,
+            what,
+            if nak { " => NAK" } else { "" }
+        );
+
+        self.seq += 1;
+    }
+
+    fn flush(&mut self) {
+        if let Some(t) = self.pending.take() {
+            self.write(t);
+        }
+    }
+
+    fn write(&mut self, t: I2cTransfer) {
+        let nak = t.flags & I2C_SNIFF_NAK != 0;
+
+        let what = match t.data.split_first() {
+            Some((&code, rest)) if !nak => {
+                let cmd = self.command(t.address, code);
+
+                if rest.is_empty() {
+                    format!("write {cmd}")
+                } else {
+                    format!("write {cmd} = {}", i2c_bytes(rest))
+                }
+            }
+            _ => format!("write {}", i2c_bytes(&t.data)),
+        };
+
+        self.print(t.address, nak, what);
+    }
+
+    fn transfer(&mut self, t: I2cTransfer) {
+        if t.flags & I2C_SNIFF_OVERRUN != 0 {
+            self.flush();
+            msg!("capture overrun: transfers were lost");
+        }
+
+        if !t.read {
+            self.flush();
+
+            if t.flags & I2C_SNIFF_RESTART != 0 && t.data.len() == 1 {
+                //
+                // This may be the command code of an SMBus read; hold it
+                // until we see the next transfer.
+                //
+                self.pending = Some(t);
+            } else {
+                self.write(t);
+            }
+
+            return;
+        }
+
+        let nak = t.flags & I2C_SNIFF_NAK != 0;
+
+        let what = match self.pending.take() {
+            Some(p) if p.address == t.address => {
+                let code = p.data[0];
+                let cmd = self.command(t.address, code);
+
+                match self.read_pec(t.address, code, &t.data) {
+                    Some(pec) => format!(
+                        "read {cmd} => {} (PEC {})",
+                        i2c_bytes(&t.data[..t.data.len() - 1]),
+                        if pec { "ok" } else { "BAD" }
+                    ),
+                    None => format!("read {cmd} => {}", i2c_bytes(&t.data)),
+                }
+            }
+            p => {
+                if let Some(p) = p {
+                    self.write(p);
+                }
+
+                format!("read {} => {}", t.data.len(), i2c_bytes(&t.data))
+            }
+        };
+
+        self.print(t.address, nak, what);
+    }
+}
+
+pub fn i2c_sniff(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    timeout: u32,
+    duration: Option<u64>,
+    hargs: &humility_i2c::I2cArgs,
+) -> Result<()> {
+    let mut context = HiffyContext::new(hubris, core, timeout)?;
+    let func = context.get_function("I2cSniff", 4)?;
+
+    let mut ops = vec![Op::Push(hargs.controller), Op::Push(hargs.port.index)];
+
+    if let Some((mux, segment)) = hargs.mux {
+        ops.push(Op::Push(mux));
+        ops.push(Op::Push(segment));
+    } else {
+        ops.push(Op::PushNone);
+        ops.push(Op::PushNone);
+    }
+
+    ops.push(Op::Call(func.id));
+    ops.push(Op::Done);
+
+    let done = humility_cmd::interrupted()?;
+
+    let deadline = duration.map(|d| Instant::now() + Duration::from_secs(d));
+    let mut sniffer = I2cSniffer::new(hubris, hargs);
+
+    msg!("sniffing {hargs}; ^C to stop");
+
+    println!("{:>6} {:4} {:<16} {}", "SEQ", "ADDR", "DEVICE", "TRANSFER");
+
+    while !done.load(Ordering::SeqCst)
+        && deadline.map_or(true, |d| Instant::now() < d)
+    {
+        let results = context.run(core, ops.as_slice(), None)?;
+
+        match &results[0] {
+            Ok(buf) => {
+                for t in i2c_sniff_parse(buf)? {
+                    sniffer.transfer(t);
+                }
+            }
+            Err(err) => match func.errmap.get(err) {
+                Some(name) => bail!("capture failed: {name}"),
+                None => bail!("capture failed: error {err}"),
+            },
+        }
+    }
+
+    sniffer.flush();
+
+    Ok(())
+}
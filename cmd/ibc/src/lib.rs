@@ -6,8 +6,26 @@
 //!
 //! Interface to BMR491 power regulator
 //!
-//! This regulator is present on Gimlet and Sidecar PCAs.  Right now,
-//! `humility ibc` only exposes one subcommand: `black-box`.
+//! This regulator is present on Gimlet and Sidecar PCAs.  `humility ibc`
+//! has three subcommands: `info`, `black-box` and `snapshot`.  If the
+//! system has more than one BMR491, the device on which to operate must be
+//! specified by name with `--device` (`-d`).
+//!
+//! `humility ibc info` displays the identity of the power converter,
+//! including its firmware revision:
+//!
+//! ```console
+//! $ humility ibc info
+//! humility: attached via ST-Link V3
+//! humility: BMR491 is at I2C4, port F, address 0x67
+//!    PMBUS_REVISION => 0x22
+//!            MFR_ID => Flex
+//!         MFR_MODEL => BMR4910302/851
+//!      MFR_REVISION => R1A
+//!      MFR_LOCATION => 14
+//!          MFR_DATE => 2213
+//!        MFR_SERIAL => T22134E0MX5
+//! ```
 //!
 //! `humility ibc black-box` allows you to read out the blackbox log from the
 //! power converter.  This can be used to debug previous faults.  The log is
@@ -24,6 +42,7 @@
 //!   EVENT_ID            0x0000
 //!   STATUS_WORD         0x0010
 //!   STATUS_IOUT         0x0080
+//!     b7 = IoutOvercurrentFault
 //!   V_IN                0xf869 = 52.50V
 //!   V_OUT               0x5f00 = 11.88V
 //!   I_OUT               0x004e = 78.00A
@@ -45,6 +64,7 @@
 //!   EVENT_ID            0x0002
 //!   STATUS_WORD         0x0010
 //!   STATUS_IOUT         0x0080
+//!     b7 = IoutOvercurrentFault
 //!   V_IN                0xf877 = 59.50V
 //!   V_OUT               0x5f00 = 11.88V
 //!   I_OUT               0x0070 = 112.00A
@@ -65,13 +85,38 @@
 //! The log doesn't appear to be _completely_ reliable, so take it with a grain
 //! of salt and with the datasheet close at hand.  For example, the machine in
 //! the example above had **not** be up for 776 days.
+//!
+//! Bits set in the status registers of an event are decoded according to
+//! the BMR491's PMBus definitions; use `--verbose` to additionally display
+//! status registers that are zero.
+//!
+//! `humility ibc snapshot` captures the configuration of the power
+//! converter:  every command that the BMR491's PMBus definitions describe
+//! as both readable and writable (with the exception of `PAGE` and
+//! `OPERATION`) is read, and the results are written as JSON to the file
+//! specified with `--output` (`-o`).  To instead compare the current
+//! configuration against a previously captured snapshot, use `--compare`
+//! (`-c`):
+//!
+//! ```console
+//! $ humility ibc snapshot --compare bmr491.json
+//! humility: attached via ST-Link V3
+//! humility: BMR491 is at I2C4, port F, address 0x67
+//! 0x40 VOUT_OV_FAULT_LIMIT       0x6a00 (13.25V) -> 0x6800 (13.00V)
+//! 0x46 IOUT_OC_FAULT_LIMIT       0xf8c0 (48.00A) -> 0xf8f0 (60.00A)
+//! humility: 2 of 37 commands differ from bmr491.json
+//! ```
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{CommandFactory, Parser};
 use colored::Colorize;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::CommandKind;
+use humility_i2c::I2cRetryPolicy;
 use humility_idol::{self as idol, HubrisIdol};
+use pmbus::commands::CommandCode;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
 use zerocopy::{
     byteorder::{BigEndian, U16, U32},
     AsBytes, FromBytes,
@@ -82,7 +127,7 @@ use hif::*;
 use humility::core::Core;
 use humility::hubris::*;
 use humility_cmd::{Archive, Attach, Command, Validate};
-use humility_hiffy::HiffyContext;
+use humility_hiffy::{HiffyContext, HiffyFunction};
 
 #[derive(Parser, Debug)]
 #[clap(name = "ibc", about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -94,23 +139,76 @@ struct IbcArgs {
     )]
     timeout: u32,
 
+    /// specifies the BMR491 (by name) if there is more than one
+    #[clap(long, short, value_name = "device")]
+    device: Option<String>,
+
     #[clap(subcommand)]
     cmd: IbcSubcommand,
 }
 
 #[derive(Parser, Debug)]
 enum IbcSubcommand {
-    /// Prints the auxiliary flash status
+    /// Prints the identity and firmware revision of the device
+    Info,
+    /// Prints the black box log
     BlackBox {
         #[clap(long, short)]
         verbose: bool,
     },
+    /// Saves or compares a snapshot of the device's configuration
+    Snapshot {
+        /// file to which to write the snapshot
+        #[clap(
+            long,
+            short,
+            value_name = "filename",
+            conflicts_with = "compare",
+            required_unless_present = "compare"
+        )]
+        output: Option<String>,
+
+        /// snapshot against which to compare the device's configuration
+        #[clap(long, short, value_name = "filename")]
+        compare: Option<String>,
+    },
+}
+
+/// Commands that identify the device, as displayed by `ibc info`
+const IBC_IDENTITY: &[CommandCode] = &[
+    CommandCode::PMBUS_REVISION,
+    CommandCode::MFR_ID,
+    CommandCode::MFR_MODEL,
+    CommandCode::MFR_REVISION,
+    CommandCode::MFR_LOCATION,
+    CommandCode::MFR_DATE,
+    CommandCode::MFR_SERIAL,
+];
+
+/// A snapshot of the configuration of a BMR491, as written by `ibc snapshot`
+#[derive(Debug, Serialize, Deserialize)]
+struct IbcSnapshot {
+    /// The location of the device at the time of the snapshot
+    device: String,
+
+    /// The value of `VOUT_MODE`, needed to interpret output voltages
+    vout_mode: u8,
+
+    commands: Vec<IbcSnapshotCommand>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IbcSnapshotCommand {
+    code: u8,
+    command: String,
+    data: Vec<u8>,
 }
 
 pub struct IbcHandler<'a> {
     hubris: &'a HubrisArchive,
     core: &'a mut dyn Core,
     context: HiffyContext<'a>,
+    device: Option<String>,
 }
 
 impl<'a> IbcHandler<'a> {
@@ -120,7 +218,313 @@ impl<'a> IbcHandler<'a> {
         hiffy_timeout: u32,
     ) -> Result<Self> {
         let context = HiffyContext::new(hubris, core, hiffy_timeout)?;
-        Ok(Self { hubris, core, context })
+        Ok(Self { hubris, core, context, device: None })
+    }
+
+    ///
+    /// Locates the BMR491 in the manifest, requiring that it be specified
+    /// by name if there is more than one.
+    ///
+    fn locate(&self) -> Result<&'a HubrisI2cDevice> {
+        let found = self
+            .hubris
+            .manifest
+            .i2c_devices
+            .iter()
+            .filter(|d| d.device == "bmr491")
+            .filter(|d| match &self.device {
+                Some(name) => d.name.as_ref() == Some(name),
+                None => true,
+            })
+            .collect::<Vec<_>>();
+
+        match (&found[..], &self.device) {
+            ([], Some(name)) => bail!("no BMR491 named \"{name}\""),
+            ([], None) => bail!("no BMR491 found in the manifest"),
+            ([d], _) => {
+                humility::msg!(
+                    "BMR491 is at I2C{}, port {}, address 0x{:02x}",
+                    d.controller,
+                    d.port.name,
+                    d.address
+                );
+
+                Ok(d)
+            }
+            _ => bail!(
+                "more than one BMR491; specify one with --device: {}",
+                found
+                    .iter()
+                    .map(|d| d.name.as_deref().unwrap_or("<unnamed>"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    ///
+    /// Performs the specified PMBus reads of the device, returning the
+    /// results along with the HIF function (needed to decode errors).
+    ///
+    fn pmbus_read(
+        &mut self,
+        dev: &HubrisI2cDevice,
+        reads: &[(u8, pmbus::Operation)],
+    ) -> Result<(Vec<Result<Vec<u8>, u32>>, HiffyFunction)> {
+        let func = self.context.get_function("I2cRead", 7)?;
+
+        let mut ops = vec![Op::Push(dev.controller), Op::Push(dev.port.index)];
+
+        match (dev.mux, dev.segment) {
+            (Some(mux), Some(segment)) => {
+                ops.push(Op::Push(mux));
+                ops.push(Op::Push(segment));
+            }
+            _ => {
+                ops.push(Op::PushNone);
+                ops.push(Op::PushNone);
+            }
+        }
+
+        ops.push(Op::Push(dev.address));
+
+        for (code, op) in reads {
+            ops.push(Op::Push(*code));
+            ops.push(match op {
+                pmbus::Operation::ReadByte => Op::Push(1),
+                pmbus::Operation::ReadWord => Op::Push(2),
+                pmbus::Operation::ReadWord32 => Op::Push(4),
+                pmbus::Operation::ReadBlock => Op::PushNone,
+                _ => bail!("0x{code:02x} is not a read operation"),
+            });
+            ops.push(Op::Call(func.id));
+            ops.push(Op::DropN(2));
+        }
+
+        ops.push(Op::Done);
+
+        let results = I2cRetryPolicy::default().run(
+            &mut self.context,
+            self.core,
+            &ops,
+            None,
+            &func,
+        )?;
+
+        Ok((results, func))
+    }
+
+    pub fn info(&mut self) -> Result<()> {
+        let dev = self.locate()?;
+        let bmr491 = pmbus::commands::Device::Bmr491;
+        let mut reads = vec![];
+
+        for code in IBC_IDENTITY {
+            let code = *code as u8;
+
+            bmr491.command(code, |cmd| {
+                reads.push((code, cmd.read_op(), cmd.name().to_string()));
+            });
+        }
+
+        let ops = reads.iter().map(|r| (r.0, r.1)).collect::<Vec<_>>();
+        let (results, func) = self.pmbus_read(dev, &ops)?;
+
+        for ((_, _, name), result) in reads.iter().zip(results.iter()) {
+            let value = match result {
+                Ok(data) if data.len() == 1 => format!("0x{:02x}", data[0]),
+                Ok(data) => {
+                    let s = String::from_utf8_lossy(data);
+                    let s = s.trim_end_matches('\0');
+
+                    if s.chars().any(|c| c.is_control() || c == '\u{fffd}') {
+                        format!("{data:x?}")
+                    } else {
+                        s.to_string()
+                    }
+                }
+                Err(err) => format!("<{}>", func.strerror(*err)),
+            };
+
+            println!("{name:>18} => {value}");
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Returns the commands that make up the device's configuration:  those
+    /// that are both readable and writable, less `PAGE` (the BMR491 has but
+    /// one rail) and `OPERATION` (which reflects whether the output is on
+    /// rather than how it is configured).
+    ///
+    fn config_commands() -> Vec<(u8, pmbus::Operation, String)> {
+        use pmbus::Operation::*;
+
+        let bmr491 = pmbus::commands::Device::Bmr491;
+        let mut commands = vec![];
+
+        for code in 0..=255u8 {
+            if code == CommandCode::PAGE as u8
+                || code == CommandCode::OPERATION as u8
+            {
+                continue;
+            }
+
+            bmr491.command(code, |cmd| {
+                let op = match (cmd.write_op(), cmd.read_op()) {
+                    (WriteByte, ReadByte) => ReadByte,
+                    (WriteWord, ReadWord) => ReadWord,
+                    (WriteWord32, ReadWord32) => ReadWord32,
+                    (WriteBlock, ReadBlock) => ReadBlock,
+                    _ => return,
+                };
+
+                commands.push((code, op, cmd.name().to_string()));
+            });
+        }
+
+        commands
+    }
+
+    fn read_config(&mut self, dev: &HubrisI2cDevice) -> Result<IbcSnapshot> {
+        let commands = Self::config_commands();
+
+        let mut ops =
+            vec![(CommandCode::VOUT_MODE as u8, pmbus::Operation::ReadByte)];
+        ops.extend(commands.iter().map(|c| (c.0, c.1)));
+
+        let (results, func) = self.pmbus_read(dev, &ops)?;
+
+        let vout_mode = match &results[0] {
+            Ok(data) if data.len() == 1 => data[0],
+            Ok(data) => bail!("bad VOUT_MODE: {data:x?}"),
+            Err(err) => {
+                bail!("failed to read VOUT_MODE: {}", func.strerror(*err))
+            }
+        };
+
+        //
+        // As with `humility pmbus --save`, commands that can't be read are
+        // taken to not be implemented by the device, and are skipped.
+        //
+        let commands = commands
+            .into_iter()
+            .zip(results.into_iter().skip(1))
+            .filter_map(|((code, _, command), result)| {
+                result.ok().map(|data| IbcSnapshotCommand {
+                    code,
+                    command,
+                    data,
+                })
+            })
+            .collect();
+
+        Ok(IbcSnapshot {
+            device: format!(
+                "I2C{}, port {}, address 0x{:02x}",
+                dev.controller, dev.port.name, dev.address
+            ),
+            vout_mode,
+            commands,
+        })
+    }
+
+    ///
+    /// Formats the value of a configuration command, interpreting it
+    /// according to the BMR491's definitions if possible.
+    ///
+    fn format_config(code: u8, data: &[u8], vout_mode: u8) -> String {
+        let bmr491 = pmbus::commands::Device::Bmr491;
+        let mode = || pmbus::commands::VOUT_MODE::CommandData(vout_mode);
+        let mut interpreted = None;
+
+        let raw = if data.len() <= 4 {
+            let mut raw = String::from("0x");
+            for b in data.iter().rev() {
+                raw += &format!("{b:02x}");
+            }
+            raw
+        } else {
+            format!("{data:x?}")
+        };
+
+        let _ = bmr491.interpret(code, data, mode, |field, value| {
+            if !field.bitfield() {
+                interpreted = Some(format!("{value}"));
+            }
+        });
+
+        match interpreted {
+            Some(value) => format!("{raw} ({value})"),
+            None => raw,
+        }
+    }
+
+    pub fn snapshot(
+        &mut self,
+        output: Option<&str>,
+        compare: Option<&str>,
+    ) -> Result<()> {
+        let dev = self.locate()?;
+        let current = self.read_config(dev)?;
+
+        if let Some(filename) = output {
+            let mut file = std::fs::File::create(filename)?;
+            serde_json::to_writer_pretty(&mut file, &current)?;
+            writeln!(file)?;
+
+            humility::msg!(
+                "saved {} commands to {filename}",
+                current.commands.len()
+            );
+
+            return Ok(());
+        }
+
+        let filename = compare.unwrap();
+        let contents = std::fs::read_to_string(filename)
+            .with_context(|| format!("failed to read {filename}"))?;
+        let snapshot: IbcSnapshot = serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse snapshot {filename}"))?;
+
+        let mut ndiffs = 0;
+
+        for cmd in &snapshot.commands {
+            let was =
+                Self::format_config(cmd.code, &cmd.data, snapshot.vout_mode);
+
+            let is = match current.commands.iter().find(|c| c.code == cmd.code)
+            {
+                Some(c) if c.data == cmd.data => continue,
+                Some(c) => {
+                    Self::format_config(c.code, &c.data, current.vout_mode)
+                }
+                None => "<unreadable>".to_string(),
+            };
+
+            println!("0x{:02x} {:<25} {was} -> {is}", cmd.code, cmd.command);
+            ndiffs += 1;
+        }
+
+        for cmd in &current.commands {
+            if !snapshot.commands.iter().any(|c| c.code == cmd.code) {
+                println!(
+                    "0x{:02x} {:<25} <absent> -> {}",
+                    cmd.code,
+                    cmd.command,
+                    Self::format_config(cmd.code, &cmd.data, current.vout_mode)
+                );
+                ndiffs += 1;
+            }
+        }
+
+        humility::msg!(
+            "{ndiffs} of {} commands differ from {filename}",
+            snapshot.commands.len()
+        );
+
+        Ok(())
     }
 
     pub fn blackbox(&mut self, verbose: bool) -> Result<()> {
@@ -202,16 +606,28 @@ impl<'a> IbcHandler<'a> {
         println!("  EVENT_ID            {:#06x}", e.event_id.get());
         println!("  STATUS_WORD         {:#06x}", e.status_word.get());
         let statuses = [
-            ("STATUS_VOUT", e.status_vout),
-            ("STATUS_IOUT", e.status_iout),
-            ("STATUS_INPUT", e.status_input),
-            ("STATUS_TEMPERATURE", e.status_temperature),
-            ("STATUS_CML", e.status_cml),
-            ("STATUS_OTHER", e.status_other),
-            ("STATUS_MFG", e.status_mfr),
+            ("STATUS_VOUT", CommandCode::STATUS_VOUT as u8, e.status_vout),
+            ("STATUS_IOUT", CommandCode::STATUS_IOUT as u8, e.status_iout),
+            ("STATUS_INPUT", CommandCode::STATUS_INPUT as u8, e.status_input),
+            (
+                "STATUS_TEMPERATURE",
+                CommandCode::STATUS_TEMPERATURE as u8,
+                e.status_temperature,
+            ),
+            ("STATUS_CML", CommandCode::STATUS_CML as u8, e.status_cml),
+            ("STATUS_OTHER", CommandCode::STATUS_OTHER as u8, e.status_other),
+            (
+                "STATUS_MFG",
+                CommandCode::STATUS_MFR_SPECIFIC as u8,
+                e.status_mfr,
+            ),
         ];
 
-        for (name, value) in statuses {
+        let dev = pmbus::commands::Device::Bmr491;
+        let vout_mode_cb =
+            || pmbus::commands::VOUT_MODE::CommandData(vout_mode);
+
+        for (name, code, value) in statuses {
             if value == 0 && !verbose {
                 continue;
             }
@@ -219,9 +635,35 @@ impl<'a> IbcHandler<'a> {
             let v = format!("{:#06x}", value);
             if value == 0 {
                 println!("{}", v.dimmed());
-            } else {
-                println!("{v}");
+                continue;
+            }
+
+            println!("{v}");
+
+            // The manufacturer-specific status is decoded below
+            if code == CommandCode::STATUS_MFR_SPECIFIC as u8 {
+                continue;
             }
+
+            let _ =
+                dev.interpret(code, &[value], vout_mode_cb, |field, val| {
+                    if !field.bitfield() || val.raw() == 0 {
+                        return;
+                    }
+
+                    let (pos, width) = field.bits();
+
+                    if width.0 == 1 {
+                        println!("    b{} = {}", pos.0, field.name());
+                    } else {
+                        println!(
+                            "    b{}:{} = {}",
+                            pos.0 + width.0 - 1,
+                            pos.0,
+                            field.name()
+                        );
+                    }
+                });
         }
         if e.status_word.get() == 0x0001 {
             for (i, flag) in [
@@ -243,10 +685,7 @@ impl<'a> IbcHandler<'a> {
             }
         }
 
-        let dev = pmbus::commands::Device::Bmr491;
         use pmbus::commands::bmr491::CommandCode;
-        let vout_mode_cb =
-            || pmbus::commands::VOUT_MODE::CommandData(vout_mode);
 
         let values = [
             ("V_IN", CommandCode::READ_VIN, e.vin_value.get()),
@@ -328,11 +767,18 @@ fn ibc(context: &mut ExecutionContext) -> Result<()> {
     let subargs = IbcArgs::try_parse_from(subargs)?;
     let hubris = context.archive.as_ref().unwrap();
     let mut worker = IbcHandler::new(hubris, core, subargs.timeout)?;
+    worker.device = subargs.device;
 
     match subargs.cmd {
+        IbcSubcommand::Info => {
+            worker.info()?;
+        }
         IbcSubcommand::BlackBox { verbose } => {
             worker.blackbox(verbose)?;
         }
+        IbcSubcommand::Snapshot { output, compare } => {
+            worker.snapshot(output.as_deref(), compare.as_deref())?;
+        }
     }
     Ok(())
 }
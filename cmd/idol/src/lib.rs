@@ -0,0 +1,996 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility idol`
+//!
+//! `humility idol` works with the Idol interfaces that are described in a
+//! Hubris archive.  With `--generate-client` (`-g`), it emits a host-side
+//! Rust module that exposes each operation of the interface served by the
+//! specified task as a typed function.  The structures and enums that the
+//! interface uses are generated as Rust types from their definitions in the
+//! archive.  Each function calls its operation through HIF (via
+//! `humility_hiffy::hiffy_call`), returning an outer error if the call could
+//! not be made and an inner error if the operation itself failed; this
+//! allows integration tests to be written against firmware without
+//! hand-marshalling arguments or picking apart replies:
+//!
+//! ```console
+//! $ humility -a ./build-sidecar-b.zip idol -g sensor > sensor.rs
+//! $ grep -B1 -A7 "pub struct SensorId" sensor.rs
+//! #[derive(Clone, Debug, Load)]
+//! pub struct SensorId(pub u32);
+//!
+//! pub struct SensorClient<'a, 'b> {
+//!     hubris: &'a HubrisArchive,
+//!     core: &'b mut dyn Core,
+//!     context: &'b mut HiffyContext<'a>,
+//!     task: HubrisTask,
+//! }
+//! $ grep -A17 "pub fn get(" sensor.rs
+//!     pub fn get(
+//!         &mut self,
+//!         id: SensorId,
+//!     ) -> Result<std::result::Result<f32, SensorError>> {
+//!         let r = self.call(
+//!             "get",
+//!             &[
+//!                 ("id", IdolArgument::Scalar(id.0.into())),
+//!             ],
+//!             None,
+//!         )?;
+//!
+//!         Ok(match r {
+//!             Ok(v) => Ok(Load::from_value(&v)?),
+//!             Err(e) => Err(SensorError::from_name(&e)
+//!                 .ok_or_else(|| anyhow!("unknown SensorError: {e}"))?),
+//!         })
+//!     }
+//! ```
+//!
+//! Generated types derive `humility::reflect::Load`, and replies are loaded
+//! as them; a C-like error enum is returned as such.  A type that cannot be
+//! expressed in Rust (e.g., one that contains a pointer or a union) is left
+//! as a `humility::reflect::Value` in a reply, and is passed as a string
+//! (marshalled as it is by `humility hiffy --call`) in an argument.
+//! Operations that take a single lease accept a slice to be read or written;
+//! operations that cannot be called via HIF are noted in the generated
+//! module and skipped.
+//!
+//! To call an operation directly, use the `call` subcommand, specifying the
+//! operation as `interface.operation` followed by its arguments as
+//! `argument=value`.  Arguments are checked against the operation's
+//! definition in the archive and marshalled according to their Idol types
+//! (as with `humility hiffy --call`); the reply is decoded in the same way:
+//!
+//! ```console
+//! $ humility idol call Sensor.get id=12
+//! humility: attached via ST-Link V3
+//! Sensor.get() => 31.25
+//! $ humility idol call Sensor.get sensor=12
+//! humility: attached via ST-Link V3
+//! humility idol failed: Sensor.get has no argument "sensor"; expected Sensor.get(id: SensorId)
+//! ```
+//!
+//! If more than one task serves the interface, the task can be specified
+//! with `--task` (`-t`).  For an operation that takes a lease, `--input`
+//! (`-i`) specifies a file whose contents are to be written to the lease;
+//! for an operation that writes to a lease, `--num` (`-n`) specifies the
+//! number of bytes to read back, which are displayed or, with `--output`
+//! (`-o`), written to a file.
+//!
+
+use ::idol::syntax::{Error, Operation, Reply};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use humility::hubris::*;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Dumper, Validate};
+use humility_hiffy::{hiffy_call, hiffy_format_call, HiffyContext, HiffyLease};
+use humility_idol::{IdolArgument, IdolOperation};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+struct CallArgs {
+    /// operation to call, as interface.operation
+    #[clap(value_name = "interface.operation")]
+    call: String,
+
+    /// arguments to the operation, as argument=value
+    #[clap(value_name = "argument=value")]
+    arguments: Vec<String>,
+
+    /// task serving the interface, if more than one does
+    #[clap(long, short, value_name = "task")]
+    task: Option<String>,
+
+    /// input for an operation that takes a lease
+    #[clap(long, short, value_name = "filename", conflicts_with = "num")]
+    input: Option<String>,
+
+    /// number of bytes to return, when an operation writes to a lease
+    #[clap(long, short, value_name = "nbytes")]
+    num: Option<usize>,
+
+    /// output for an operation that writes to a lease
+    #[clap(long, short, value_name = "filename", requires = "num")]
+    output: Option<String>,
+
+    /// sets timeout
+    #[clap(
+        long, short = 'T', default_value_t = 5000, value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+}
+
+#[derive(Parser, Debug)]
+enum IdolCommand {
+    /// Call an Idol operation served by a task
+    Call(CallArgs),
+}
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "idol", about = env!("CARGO_PKG_DESCRIPTION"),
+    args_conflicts_with_subcommands = true
+)]
+struct IdolArgs {
+    /// generate a Rust client for the interface served by a task
+    #[clap(long, short, value_name = "task")]
+    generate_client: Option<String>,
+
+    #[clap(subcommand)]
+    cmd: Option<IdolCommand>,
+}
+
+//
+// Idol types that are Rust primitives, and can therefore be used as-is.
+//
+const PRIMITIVES: &[&str] = &[
+    "u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64", "f32", "f64", "bool",
+];
+
+fn ident(name: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "as", "box", "break", "const", "continue", "crate", "do", "else",
+        "enum", "extern", "fn", "for", "if", "impl", "in", "let", "loop",
+        "match", "mod", "move", "mut", "pub", "ref", "return", "static",
+        "struct", "trait", "type", "unsafe", "use", "where", "while",
+    ];
+
+    if KEYWORDS.contains(&name) {
+        format!("r#{name}")
+    } else {
+        name.to_string()
+    }
+}
+
+//
+// Determines if a name can be used as-is as a Rust identifier (which rules
+// out, e.g., the names of instances of generic types).
+//
+fn is_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && ident(name) == name
+}
+
+///
+/// How an argument is marshalled by a generated client.
+///
+enum Marshal {
+    /// An unsigned integer, passed as a scalar
+    Scalar,
+    /// A newtype around an unsigned integer, passed as a scalar
+    Newtype,
+    /// A boolean, passed as `true` or `false`
+    Bool,
+    /// A C-like enum, passed as the name of its variant
+    Variant,
+    /// A signed integer or a floating point value, passed as a string
+    Display,
+    /// Anything else, passed as a string to be marshalled by type
+    Str,
+}
+
+///
+/// The Rust types generated for the Idol types of an interface.  Structures
+/// and enums are generated from their definitions in the archive, deriving
+/// `Load` so that they can be loaded from a reply; a type that cannot be
+/// expressed (e.g., one that contains a pointer or a union) is left as a
+/// `Value`.
+///
+struct Types<'a> {
+    hubris: &'a HubrisArchive,
+    module: &'a HubrisModule,
+    /// Definitions by name; a definition is None if it cannot be generated
+    defs: BTreeMap<String, (HubrisGoff, Option<String>)>,
+    /// Names of enums that have only unit variants
+    clike: BTreeSet<String>,
+}
+
+impl<'a> Types<'a> {
+    fn new(hubris: &'a HubrisArchive, module: &'a HubrisModule) -> Self {
+        Self { hubris, module, defs: BTreeMap::new(), clike: BTreeSet::new() }
+    }
+
+    /// Returns the Rust type for an Idol type, as named by the interface
+    fn lookup(&mut self, name: &str) -> Option<String> {
+        let (hubris, module) = (self.hubris, self.module);
+
+        if PRIMITIVES.contains(&name) {
+            Some(name.to_string())
+        } else if let Ok(Some(e)) = module.lookup_enum_byname(hubris, name) {
+            self.enumeration(e)
+        } else if let Ok(Some(s)) = module.lookup_struct_byname(hubris, name) {
+            self.structure(s)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the Rust type for a type in the archive
+    fn goff(&mut self, goff: HubrisGoff) -> Option<String> {
+        let hubris = self.hubris;
+
+        match hubris.lookup_type(goff).ok()? {
+            t @ HubrisType::Base(_) => {
+                let name = t.name(hubris).ok()?;
+                PRIMITIVES.contains(&&*name).then(|| name.to_string())
+            }
+            HubrisType::Struct(s) => self.structure(s),
+            HubrisType::Enum(e) => self.enumeration(e),
+            HubrisType::Array(a) => {
+                let elem = self.goff(a.goff)?;
+                Some(format!("[{elem}; {}]", a.count))
+            }
+            _ => None,
+        }
+    }
+
+    ///
+    /// Returns the fields of a structure (or of the payload of an enum
+    /// variant), either as a tuple or as named fields.
+    ///
+    fn fields(
+        &mut self,
+        s: &HubrisStruct,
+        vis: &str,
+        indent: &str,
+    ) -> Option<String> {
+        let tuple = (0..s.members.len())
+            .map(|i| s.members.iter().find(|m| m.name == format!("__{i}")))
+            .collect::<Option<Vec<_>>>();
+
+        match tuple {
+            Some(members) => {
+                let mut types = vec![];
+
+                for m in members {
+                    types.push(format!("{vis}{}", self.goff(m.goff)?));
+                }
+
+                Some(format!("({})", types.join(", ")))
+            }
+            None => {
+                let mut out = " {\n".to_string();
+
+                for m in &s.members {
+                    if !is_ident(&m.name) {
+                        return None;
+                    }
+
+                    let ty = self.goff(m.goff)?;
+                    out += &format!("{indent}    {vis}{}: {ty},\n", m.name);
+                }
+
+                out += &format!("{indent}}}");
+                Some(out)
+            }
+        }
+    }
+
+    ///
+    /// Checks for a definition of the named type.  If there isn't one, a
+    /// placeholder is recorded (lest we recurse into it) and None is
+    /// returned; otherwise, returns whether the type can be generated.
+    ///
+    fn defined(&mut self, name: &str, goff: HubrisGoff) -> Option<bool> {
+        match self.defs.get(name) {
+            Some((g, def)) => Some(*g == goff && def.is_some()),
+            None => {
+                self.defs.insert(name.to_string(), (goff, Some(String::new())));
+                None
+            }
+        }
+    }
+
+    fn structure(&mut self, s: &HubrisStruct) -> Option<String> {
+        let name = &s.name;
+
+        if !is_ident(name) || s.members.is_empty() {
+            return None;
+        }
+
+        if let Some(ok) = self.defined(name, s.goff) {
+            return ok.then(|| name.clone());
+        }
+
+        let def = self.fields(s, "pub ", "").map(|fields| {
+            let semi = if fields.starts_with('(') { ";" } else { "" };
+            format!(
+                "#[derive(Clone, Debug, Load)]\n\
+                pub struct {name}{fields}{semi}\n"
+            )
+        });
+
+        let ok = def.is_some();
+        self.defs.insert(name.clone(), (s.goff, def));
+        ok.then(|| name.clone())
+    }
+
+    fn enumeration(&mut self, e: &HubrisEnum) -> Option<String> {
+        let name = &e.name;
+
+        //
+        // An Option is loaded as such, provided that its payload can be.
+        //
+        if name.starts_with("Option<") {
+            let some = e.variants.iter().find(|v| v.name == "Some")?;
+            let payload = self.hubris.lookup_struct(some.goff?).ok()?;
+            let inner = payload.newtype()?;
+            return Some(format!("Option<{}>", self.goff(inner)?));
+        }
+
+        if !is_ident(name) || e.variants.is_empty() {
+            return None;
+        }
+
+        if let Some(ok) = self.defined(name, e.goff) {
+            return ok.then(|| name.clone());
+        }
+
+        let def = self.variants(e).map(|(variants, clike)| {
+            if !clike {
+                return format!(
+                    "#[derive(Clone, Debug, Load)]\npub enum {name} {{\n\
+                    {variants}}}\n"
+                );
+            }
+
+            self.clike.insert(name.clone());
+
+            let (mut names, mut from) = (String::new(), String::new());
+
+            for v in &e.variants {
+                let v = &v.name;
+                names += &format!("            Self::{v} => \"{v}\",\n");
+                from += &format!("            \"{v}\" => Some(Self::{v}),\n");
+            }
+
+            format!(
+                "\
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Load)]
+pub enum {name} {{
+{variants}}}
+
+impl {name} {{
+    pub fn name(&self) -> &'static str {{
+        match self {{
+{names}        }}
+    }}
+
+    pub fn from_name(name: &str) -> Option<Self> {{
+        match name {{
+{from}            _ => None,
+        }}
+    }}
+}}
+"
+            )
+        });
+
+        let ok = def.is_some();
+        self.defs.insert(name.clone(), (e.goff, def));
+        ok.then(|| name.clone())
+    }
+
+    ///
+    /// Returns the variants of an enum, and whether they are all unit
+    /// variants.
+    ///
+    fn variants(&mut self, e: &HubrisEnum) -> Option<(String, bool)> {
+        let mut out = String::new();
+        let mut clike = true;
+
+        for v in &e.variants {
+            if !is_ident(&v.name) {
+                return None;
+            }
+
+            let payload = match v.goff {
+                Some(goff) => {
+                    let s = self.hubris.lookup_struct(goff).ok()?;
+
+                    if s.members.is_empty() {
+                        String::new()
+                    } else {
+                        clike = false;
+                        self.fields(s, "", "    ")?
+                    }
+                }
+                None => String::new(),
+            };
+
+            out += &format!("    {}{payload},\n", v.name);
+        }
+
+        Some((out, clike))
+    }
+
+    /// Determines if the named type is a newtype around an unsigned integer
+    fn newtype(&self, name: &str) -> bool {
+        let s = match self.module.lookup_struct_byname(self.hubris, name) {
+            Ok(Some(s)) => s,
+            _ => return false,
+        };
+
+        matches!(
+            s.newtype().map(|g| self.hubris.lookup_type(g)),
+            Some(Ok(HubrisType::Base(HubrisBasetype {
+                encoding: HubrisEncoding::Unsigned,
+                size: 1 | 2 | 4 | 8,
+            })))
+        )
+    }
+
+    /// Returns the Rust type of an argument, and how it is to be marshalled
+    fn argument(&mut self, ty: &str) -> (String, Marshal) {
+        match self.lookup(ty) {
+            Some(t) if matches!(t.as_str(), "u8" | "u16" | "u32" | "u64") => {
+                (t, Marshal::Scalar)
+            }
+            Some(t) if t == "bool" => (t, Marshal::Bool),
+            Some(t) if PRIMITIVES.contains(&t.as_str()) => {
+                (t, Marshal::Display)
+            }
+            Some(t) if self.clike.contains(&t) => (t, Marshal::Variant),
+            Some(t) if self.newtype(ty) => (t, Marshal::Newtype),
+            _ => ("&str".to_string(), Marshal::Str),
+        }
+    }
+
+    /// Returns the definitions of all types that could be generated
+    fn definitions(&self) -> String {
+        self.defs
+            .values()
+            .filter_map(|(_, def)| def.as_deref())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn reply(op: &Operation) -> String {
+    match &op.reply {
+        Reply::Simple(ok) => ok.ty.0.clone(),
+        Reply::Result { ok, err } => {
+            let err = match err {
+                Error::CLike(t) | Error::Complex(t) => t.0.clone(),
+                Error::ServerDeath => "ServerDeath".to_string(),
+            };
+
+            format!("Result<{}, {}>", ok.ty.0, err)
+        }
+    }
+}
+
+fn signature(op: &Operation) -> String {
+    op.args
+        .iter()
+        .map(|(aname, arg)| format!("{}: {}", aname, arg.ty.0))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn generate_op(
+    out: &mut String,
+    types: &mut Types,
+    iface: &str,
+    name: &str,
+    op: &Operation,
+) -> Result<()> {
+    let lease = match op.leases.len() {
+        0 => None,
+        1 => {
+            let (lname, lease) = op.leases.iter().next().unwrap();
+
+            //
+            // A lease that the server reads from is written by us, and
+            // vice versa.
+            //
+            match (lease.read, lease.write) {
+                (true, false) => Some((lname, "&[u8]", "Write")),
+                (false, true) => Some((lname, "&mut [u8]", "Read")),
+                _ => {
+                    writeln!(
+                        out,
+                        "    // {iface}.{name}: skipped (read/write lease)\n"
+                    )?;
+                    return Ok(());
+                }
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "    // {iface}.{name}: skipped (multiple leases)\n"
+            )?;
+            return Ok(());
+        }
+    };
+
+    let mut params = vec![];
+    let mut lets = vec![];
+    let mut args = vec![];
+
+    for (aname, arg) in &op.args {
+        let id = ident(aname);
+        let (ty, marshal) = types.argument(&arg.ty.0);
+        params.push(format!("{id}: {ty}"));
+
+        let arg = match marshal {
+            Marshal::Scalar => format!("IdolArgument::Scalar({id}.into())"),
+            Marshal::Newtype => format!("IdolArgument::Scalar({id}.0.into())"),
+            Marshal::Bool => {
+                lets.push(format!(
+                    "let {id} = if {id} {{ \"true\" }} else {{ \"false\" }};"
+                ));
+                format!("IdolArgument::String({id})")
+            }
+            Marshal::Variant => format!("IdolArgument::String({id}.name())"),
+            Marshal::Display => {
+                lets.push(format!("let {id} = {id}.to_string();"));
+                format!("IdolArgument::String(&{id})")
+            }
+            Marshal::Str => format!("IdolArgument::String({id})"),
+        };
+
+        args.push(format!("(\"{aname}\", {arg})"));
+    }
+
+    let lease = match lease {
+        Some((lname, ty, kind)) => {
+            let id = ident(lname);
+            params.push(format!("{id}: {ty}"));
+            format!("Some(HiffyLease::{kind}({id}))")
+        }
+        None => "None".to_string(),
+    };
+
+    //
+    // Determine what we return:  the reply (loaded as its Rust type) and, if
+    // the operation can fail, its error -- as a C-like enum if we have one,
+    // or as a string if not.
+    //
+    let ok = match &op.reply {
+        Reply::Simple(ok) => &ok.ty.0,
+        Reply::Result { ok, .. } => &ok.ty.0,
+    };
+
+    let (ok, decode) = match ok.as_str() {
+        "()" => ("()".to_string(), "Ok(_) => Ok(())"),
+        ok => match types.lookup(ok) {
+            Some(t) => (t, "Ok(v) => Ok(Load::from_value(&v)?)"),
+            None => ("Value".to_string(), "Ok(v) => Ok(v)"),
+        },
+    };
+
+    let err = match &op.reply {
+        Reply::Simple(_) => None,
+        Reply::Result { err: Error::CLike(t), .. } => {
+            match types.lookup(&t.0) {
+                Some(e) if types.clike.contains(&e) => Some(Some(e)),
+                _ => Some(None),
+            }
+        }
+        Reply::Result { .. } => Some(None),
+    };
+
+    let rval = match &err {
+        None => format!("Result<{ok}>"),
+        Some(Some(e)) => format!("Result<std::result::Result<{ok}, {e}>>"),
+        Some(None) => format!("Result<std::result::Result<{ok}, String>>"),
+    };
+
+    writeln!(
+        out,
+        "    /// `{iface}.{name}({}) -> {}`",
+        signature(op),
+        reply(op)
+    )?;
+    write!(out, "    pub fn {}(\n        &mut self,\n", ident(name))?;
+
+    for param in &params {
+        writeln!(out, "        {param},")?;
+    }
+
+    writeln!(out, "    ) -> {rval} {{")?;
+
+    for l in &lets {
+        writeln!(out, "        {l}")?;
+    }
+
+    if args.is_empty() {
+        writeln!(out, "        let r = self.call(\"{name}\", &[], {lease})?;")?;
+    } else {
+        writeln!(out, "        let r = self.call(")?;
+        writeln!(out, "            \"{name}\",")?;
+        writeln!(out, "            &[")?;
+
+        for arg in &args {
+            writeln!(out, "                {arg},")?;
+        }
+
+        writeln!(out, "            ],")?;
+        writeln!(out, "            {lease},")?;
+        writeln!(out, "        )?;")?;
+    }
+
+    writeln!(out)?;
+
+    match err {
+        None => {
+            writeln!(out, "        match r {{")?;
+            writeln!(out, "            {decode},")?;
+            writeln!(
+                out,
+                "            Err(e) => Err(anyhow!(\"{iface}.{name}: {{e}}\")),"
+            )?;
+            writeln!(out, "        }}")?;
+        }
+        Some(err) => {
+            writeln!(out, "        Ok(match r {{")?;
+            writeln!(out, "            {decode},")?;
+
+            match err {
+                Some(e) => {
+                    writeln!(
+                        out,
+                        "            Err(e) => Err({e}::from_name(&e)"
+                    )?;
+                    writeln!(
+                        out,
+                        "                .ok_or_else(|| \
+                        anyhow!(\"unknown {e}: {{e}}\"))?),"
+                    )?;
+                }
+                None => writeln!(out, "            Err(e) => Err(e),")?,
+            }
+
+            writeln!(out, "        }})")?;
+        }
+    }
+
+    writeln!(out, "    }}\n")?;
+
+    Ok(())
+}
+
+fn generate_client(hubris: &HubrisArchive, task: &str) -> Result<String> {
+    let t = hubris
+        .lookup_task(task)
+        .ok_or_else(|| anyhow!("unknown task \"{task}\""))?;
+
+    let module = hubris.lookup_module(*t)?;
+
+    let iface = match &module.iface {
+        Some(iface) => iface,
+        None => bail!("task \"{task}\" does not serve an Idol interface"),
+    };
+
+    let name = &iface.name;
+    let client = format!("{name}Client");
+    let image = match (&hubris.manifest.name, &hubris.manifest.gitrev) {
+        (Some(name), Some(gitrev)) => format!("{name} ({gitrev})"),
+        (Some(name), None) => name.clone(),
+        _ => "archive".to_string(),
+    };
+
+    //
+    // Generate our operations first, as doing so determines the types that
+    // we need to generate.
+    //
+    let mut types = Types::new(hubris, module);
+    let mut ops = String::new();
+
+    for (opname, op) in &iface.ops {
+        generate_op(&mut ops, &mut types, name, opname, op)?;
+    }
+
+    let mut out = String::new();
+
+    writeln!(out, "//")?;
+    writeln!(
+        out,
+        "// Client for the {name} interface as served by task {task}, \
+        generated by\n// `humility idol --generate-client` from {image}; \
+        do not edit."
+    )?;
+    writeln!(out, "//\n")?;
+
+    writeln!(
+        out,
+        "\
+use anyhow::{{anyhow, Result}};
+use humility::core::Core;
+use humility::hubris::{{HubrisArchive, HubrisTask}};
+use humility::reflect::{{Load, Value}};
+use humility_hiffy::{{hiffy_call, HiffyContext, HiffyLease}};
+use humility_idol::{{IdolArgument, IdolOperation}};
+"
+    )?;
+
+    let definitions = types.definitions();
+
+    if !definitions.is_empty() {
+        writeln!(out, "{definitions}")?;
+    }
+
+    writeln!(
+        out,
+        "\
+pub struct {client}<'a, 'b> {{
+    hubris: &'a HubrisArchive,
+    core: &'b mut dyn Core,
+    context: &'b mut HiffyContext<'a>,
+    task: HubrisTask,
+}}
+
+impl<'a, 'b> {client}<'a, 'b> {{
+    pub fn new(
+        hubris: &'a HubrisArchive,
+        core: &'b mut dyn Core,
+        context: &'b mut HiffyContext<'a>,
+    ) -> Result<Self> {{
+        let task = *hubris
+            .lookup_task(\"{task}\")
+            .ok_or_else(|| anyhow!(\"task \\\"{task}\\\" not found\"))?;
+        Ok(Self {{ hubris, core, context, task }})
+    }}
+
+    fn call(
+        &mut self,
+        op: &str,
+        args: &[(&str, IdolArgument)],
+        lease: Option<HiffyLease>,
+    ) -> Result<std::result::Result<Value, String>> {{
+        let op =
+            IdolOperation::new(self.hubris, \"{name}\", op, Some(&self.task))?;
+        hiffy_call(self.hubris, self.core, self.context, &op, args, lease)
+    }}
+"
+    )?;
+
+    out += &ops;
+
+    //
+    // Remove the blank line that follows our last function.
+    //
+    while out.ends_with('\n') {
+        out.pop();
+    }
+
+    writeln!(out, "\n}}")?;
+
+    Ok(out)
+}
+
+///
+/// Parses arguments given as `argument=value`, checking them against those
+/// of the operation so that a mistake can be met with the operation's
+/// signature rather than a marshalling failure.
+///
+fn call_args<'a>(
+    op: &IdolOperation,
+    arguments: &'a [String],
+) -> Result<Vec<(&'a str, IdolArgument<'a>)>> {
+    let (iface, name) = &op.name;
+    let sig = || format!("{iface}.{name}({})", signature(op.operation));
+    let mut args = vec![];
+
+    for arg in arguments {
+        let Some((aname, value)) = arg.split_once('=') else {
+            bail!("arguments must be argument=value; expected {}", sig());
+        };
+
+        if !op.operation.args.contains_key(aname) {
+            bail!(
+                "{iface}.{name} has no argument \"{aname}\"; expected {}",
+                sig()
+            );
+        }
+
+        if args.iter().any(|(a, _)| *a == aname) {
+            bail!("argument \"{aname}\" is specified more than once");
+        }
+
+        args.push((aname, IdolArgument::String(value)));
+    }
+
+    for aname in op.operation.args.keys() {
+        if !args.iter().any(|(a, _)| *a == aname.as_str()) {
+            bail!("argument \"{aname}\" is not specified; expected {}", sig());
+        }
+    }
+
+    Ok(args)
+}
+
+fn call(context: &mut ExecutionContext, subargs: &CallArgs) -> Result<()> {
+    let hubris = context.archive.as_ref().unwrap();
+    let core = &mut **context.core.as_mut().unwrap();
+
+    let Some((iface, name)) = subargs.call.split_once('.') else {
+        bail!("operation must be interface.operation");
+    };
+
+    let task = match &subargs.task {
+        Some(task) => Some(
+            hubris
+                .lookup_task(task)
+                .ok_or_else(|| anyhow!("unknown task \"{task}\""))?,
+        ),
+        None => None,
+    };
+
+    let op = IdolOperation::new(hubris, iface, name, task)?;
+    let args = call_args(&op, &subargs.arguments)?;
+    let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
+
+    let (result, data) = if let Some(input) = &subargs.input {
+        let input = std::fs::read(input)
+            .with_context(|| format!("failed to read {input}"))?;
+        let lease = Some(HiffyLease::Write(&input));
+        (hiffy_call(hubris, core, &mut context, &op, &args, lease)?, None)
+    } else if let Some(num) = subargs.num {
+        let mut data = vec![0u8; num];
+        let lease = Some(HiffyLease::Read(&mut data));
+        let r = hiffy_call(hubris, core, &mut context, &op, &args, lease)?;
+        (r, Some(data))
+    } else {
+        (hiffy_call(hubris, core, &mut context, &op, &args, None)?, None)
+    };
+
+    println!("{}", hiffy_format_call(hubris, &op, result));
+
+    if let Some(data) = data {
+        if let Some(output) = &subargs.output {
+            std::fs::write(output, &data)
+                .with_context(|| format!("failed to write {output}"))?;
+            println!("wrote {} bytes to {output}", data.len());
+        } else {
+            Dumper::new().dump(&data, 0x0);
+        }
+    }
+
+    Ok(())
+}
+
+fn idol(context: &mut ExecutionContext) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = IdolArgs::try_parse_from(subargs)?;
+
+    match subargs.cmd {
+        Some(IdolCommand::Call(args)) => humility_cmd::attach(
+            context,
+            Attach::LiveOnly,
+            Validate::Booted,
+            |context| call(context, &args),
+        ),
+        None => {
+            let hubris = context.archive.as_ref().unwrap();
+
+            match &subargs.generate_client {
+                Some(task) => print!("{}", generate_client(hubris, task)?),
+                None => bail!("expected --generate-client or call"),
+            }
+
+            Ok(())
+        }
+    }
+}
+
+pub fn init() -> Command {
+    Command {
+        app: IdolArgs::command(),
+        name: "idol",
+        run: idol,
+        kind: CommandKind::Unattached { archive: Archive::Required },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+    use std::process;
+
+    ///
+    /// Generates a client for every interface in one of our test archives,
+    /// and checks that the generated code compiles against our crates.
+    ///
+    #[test]
+    fn test_generated_clients_compile() -> Result<()> {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+        let root = root.canonicalize()?;
+        let archive =
+            root.join("tests/cmd/archives/build-sidecar-b-image-default.zip");
+
+        let mut hubris = HubrisArchive::new()?;
+        hubris.load(archive.to_str().unwrap(), HubrisArchiveDoneness::Cook)?;
+
+        let dir = root.join("target/idol-client-test");
+        std::fs::create_dir_all(dir.join("src"))?;
+
+        let mut lib = String::new();
+
+        for i in 0..hubris.ntasks() {
+            let module = hubris.lookup_module(HubrisTask::Task(i as u32))?;
+
+            if module.iface.is_none() {
+                continue;
+            }
+
+            let name = module.name.replace('-', "_");
+            let client = generate_client(&hubris, &module.name)?;
+            std::fs::write(dir.join(format!("src/{name}.rs")), client)?;
+            writeln!(lib, "#[allow(dead_code)]\nmod {name};")?;
+        }
+
+        assert!(!lib.is_empty(), "no interfaces found in {:?}", archive);
+        std::fs::write(dir.join("src/lib.rs"), lib)?;
+
+        let path = |p: &str| root.join(p).display().to_string();
+
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            format!(
+                "[package]\n\
+                name = \"idol-client-test\"\n\
+                version = \"0.0.0\"\n\
+                edition = \"2021\"\n\n\
+                [workspace]\n\n\
+                [dependencies]\n\
+                anyhow = \"1\"\n\
+                humility = {{ path = \"{}\", package = \"humility-core\" }}\n\
+                humility-hiffy = {{ path = \"{}\" }}\n\
+                humility-idol = {{ path = \"{}\" }}\n",
+                path("humility-core"),
+                path("humility-hiffy"),
+                path("humility-idol"),
+            ),
+        )?;
+
+        //
+        // Use our lockfile so that the generated code is built against the
+        // same dependencies that we are, and a target directory of its own
+        // so that we don't contend with the build that is running us.
+        //
+        std::fs::copy(root.join("Cargo.lock"), dir.join("Cargo.lock"))?;
+
+        let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+
+        let status = process::Command::new(cargo)
+            .args(["check", "--quiet"])
+            .env("CARGO_TARGET_DIR", dir.join("target"))
+            .current_dir(&dir)
+            .status()?;
+
+        assert!(status.success(), "generated clients failed to compile");
+        Ok(())
+    }
+}
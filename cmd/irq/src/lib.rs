@@ -0,0 +1,248 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility irq`
+//!
+//! `humility irq` reads the state of the nested vectored interrupt
+//! controller (NVIC) -- which interrupts are enabled, pending and active,
+//! and their priorities -- and merges it with the routing of interrupts to
+//! tasks specified in the archive.  By default, any interrupt that is owned
+//! by a task or is enabled, pending or active is displayed:
+//!
+//! ```console
+//! $ humility irq
+//! humility: attached via ST-Link V3
+//!  IRQ NAME                 TASK         NOTIFICATION  PRI STATE
+//!   31 i2c1.event           i2c_driver   0x1          0x00 masked
+//!   32 i2c1.error           i2c_driver   0x1          0x00 masked
+//!   33 i2c2.event           i2c_driver   0x2          0x00 enabled
+//!   34 i2c2.error           i2c_driver   0x2          0x00 enabled
+//!   39 usart3               usart_driver 0x1          0x00 enabled,pending
+//!   61 eth                  net          0x1          0x00 enabled
+//!   95 i2c4.event           i2c_driver   0x8          0x00 masked
+//!   96 i2c4.error           i2c_driver   0x8          0x00 masked
+//! humility: 1 interrupt is pending
+//! humility: 4 interrupts owned by tasks are masked
+//! ```
+//!
+//! An interrupt that is owned by a task is masked by the kernel when it
+//! fires, and remains masked until the task re-enables it; an interrupt
+//! that is masked for an extended period of time may therefore indicate a
+//! task that is not servicing it (or has no reason to, e.g. an I2C
+//! controller that is not in use).  Interrupts that are enabled but not
+//! owned by any task are marked as `unowned`.
+//!
+//! To display all interrupts that the NVIC implements, use `--all` (`-a`).
+//! Note that on ARMv6-M targets, the NVIC does not indicate which
+//! interrupts are active.
+//!
+
+use anyhow::Result;
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::msg;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use std::collections::BTreeMap;
+
+#[derive(Parser, Debug)]
+#[clap(name = "irq", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct IrqArgs {
+    /// display all interrupts, including those that are unused
+    #[clap(long, short)]
+    all: bool,
+}
+
+const NVIC_ICTR: u32 = 0xe000_e004;
+const NVIC_ISER: u32 = 0xe000_e100;
+const NVIC_ISPR: u32 = 0xe000_e200;
+const NVIC_IABR: u32 = 0xe000_e300;
+const NVIC_IPR: u32 = 0xe000_e400;
+
+struct IrqState {
+    enabled: bool,
+    pending: bool,
+    active: Option<bool>,
+    priority: u8,
+}
+
+///
+/// Reads the state of every interrupt implemented by the NVIC.  ARMv6-M has
+/// neither an interrupt controller type register (it always has 32 lines)
+/// nor active bit registers.
+///
+fn nvic(core: &mut dyn Core, armv6m: bool) -> Result<Vec<IrqState>> {
+    let lines = if armv6m {
+        32
+    } else {
+        ((core.read_word_32(NVIC_ICTR)? & 0xf) + 1) * 32
+    };
+
+    let mut rval = vec![];
+
+    for word in 0..lines / 32 {
+        let offset = word * 4;
+        let iser = core.read_word_32(NVIC_ISER + offset)?;
+        let ispr = core.read_word_32(NVIC_ISPR + offset)?;
+        let iabr = if armv6m {
+            None
+        } else {
+            Some(core.read_word_32(NVIC_IABR + offset)?)
+        };
+
+        for bit in 0..32 {
+            rval.push(IrqState {
+                enabled: iser & (1 << bit) != 0,
+                pending: ispr & (1 << bit) != 0,
+                active: iabr.map(|iabr| iabr & (1 << bit) != 0),
+                priority: 0,
+            });
+        }
+    }
+
+    //
+    // The priority registers are byte-accessible on ARMv7-M and ARMv8-M,
+    // but only word-accessible on ARMv6-M -- so we always read words.
+    //
+    for word in 0..lines / 4 {
+        let ipr = core.read_word_32(NVIC_IPR + word * 4)?;
+
+        for (i, priority) in ipr.to_le_bytes().iter().enumerate() {
+            rval[(word * 4) as usize + i].priority = *priority;
+        }
+    }
+
+    Ok(rval)
+}
+
+fn irq(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = IrqArgs::try_parse_from(subargs)?;
+    let manifest = &hubris.manifest;
+
+    let armv6m = manifest.target.as_deref() == Some("thumbv6m-none-eabi");
+
+    core.op_start()?;
+    let rval = nvic(core, armv6m);
+    core.op_done()?;
+    let state = rval?;
+
+    let mut owners: BTreeMap<u32, Vec<(&str, u32)>> = BTreeMap::new();
+
+    for (task, irqs) in &manifest.task_irqs {
+        for (mask, irq) in irqs {
+            owners.entry(*irq).or_default().push((task.as_str(), *mask));
+        }
+    }
+
+    let mut names: BTreeMap<u32, String> = BTreeMap::new();
+
+    for (peripheral, irqs) in &manifest.peripheral_irqs {
+        for (name, irq) in irqs {
+            names.insert(*irq, format!("{peripheral}.{name}"));
+        }
+    }
+
+    let mut pending = 0;
+    let mut masked = 0;
+
+    println!(
+        "{:>4} {:20} {:12} {:12} {:>4} STATE",
+        "IRQ", "NAME", "TASK", "NOTIFICATION", "PRI"
+    );
+
+    for (irq, s) in state.iter().enumerate() {
+        let irq = irq as u32;
+        let owned = owners.get(&irq);
+        let active = s.active == Some(true);
+
+        if !subargs.all
+            && owned.is_none()
+            && !s.enabled
+            && !s.pending
+            && !active
+        {
+            continue;
+        }
+
+        let mut flags = vec![];
+
+        match (s.enabled, owned.is_some()) {
+            (true, true) => flags.push("enabled"),
+            (true, false) => flags.push("enabled,unowned"),
+            (false, true) => {
+                flags.push("masked");
+                masked += 1;
+            }
+            (false, false) => {}
+        }
+
+        if s.pending {
+            flags.push("pending");
+            pending += 1;
+        }
+
+        if active {
+            flags.push("active");
+        }
+
+        let name = names.get(&irq).map(String::as_str).unwrap_or("-");
+        let state =
+            if flags.is_empty() { "-".to_string() } else { flags.join(",") };
+
+        match owned {
+            Some(owned) => {
+                for (task, mask) in owned {
+                    println!(
+                        "{:>4} {:20} {:12} {:12} 0x{:02x} {}",
+                        irq,
+                        name,
+                        task,
+                        format!("0x{mask:x}"),
+                        s.priority,
+                        state
+                    );
+                }
+            }
+            None => {
+                println!(
+                    "{:>4} {:20} {:12} {:12} 0x{:02x} {}",
+                    irq, name, "-", "-", s.priority, state
+                );
+            }
+        }
+    }
+
+    if pending > 0 {
+        msg!(
+            "{pending} interrupt{} pending",
+            if pending == 1 { " is" } else { "s are" }
+        );
+    }
+
+    if masked > 0 {
+        msg!(
+            "{masked} interrupt{} owned by tasks {} masked",
+            if masked == 1 { "" } else { "s" },
+            if masked == 1 { "is" } else { "are" }
+        );
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: IrqArgs::command(),
+        name: "irq",
+        run: irq,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -0,0 +1,177 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Capture files for `humility itm --capture` and `humility itm --read`.  A
+//! capture file consists of a single line of JSON describing the capture,
+//! followed by the raw SWO stream as a series of records, each consisting
+//! of the time (in seconds since the start of capture, as a little-endian
+//! `f64`) at which the data was read, the length of the data (as a
+//! little-endian `u32`), and the data itself.
+//!
+
+use anyhow::{bail, Context, Result};
+use humility::timebase::{Timebase, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::time::{Duration, UNIX_EPOCH};
+
+const CAPTURE_FORMAT: &str = "humility-itm";
+const CAPTURE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CaptureHeader {
+    format: String,
+    version: u32,
+
+    /// Trace ID, or None if the TPIU formatter was bypassed
+    pub traceid: Option<u8>,
+
+    /// Image ID of the archive, if any, as a hex string
+    pub image_id: Option<String>,
+
+    /// Kernel ticks at the start of capture, if known
+    ticks: Option<u64>,
+
+    /// Wall clock time at the start of capture, in seconds since the epoch
+    wallclock: Option<f64>,
+}
+
+impl CaptureHeader {
+    pub fn new(
+        traceid: Option<u8>,
+        image_id: Option<&[u8]>,
+        timebase: Option<&Timebase>,
+    ) -> Self {
+        let now = timebase.map(|t| t.now());
+
+        Self {
+            format: CAPTURE_FORMAT.to_string(),
+            version: CAPTURE_VERSION,
+            traceid,
+            image_id: image_id.map(|id| {
+                id.iter().map(|b| format!("{b:02x}")).collect::<String>()
+            }),
+            ticks: now.map(|n| n.ticks),
+            wallclock: now.and_then(|n| n.wallclock).and_then(|w| {
+                w.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs_f64())
+            }),
+        }
+    }
+
+    /// Returns the timebase in effect at the start of capture, if any
+    pub fn timebase(&self) -> Option<Timebase> {
+        self.ticks.map(|ticks| {
+            Timebase::from(Timestamp {
+                ticks,
+                wallclock: self
+                    .wallclock
+                    .map(|w| UNIX_EPOCH + Duration::from_secs_f64(w)),
+            })
+        })
+    }
+}
+
+pub struct CaptureWriter {
+    file: BufWriter<File>,
+    bytes: usize,
+}
+
+impl CaptureWriter {
+    pub fn create(filename: &str, header: &CaptureHeader) -> Result<Self> {
+        let file = File::create(filename)
+            .with_context(|| format!("failed to create {filename}"))?;
+        let mut file = BufWriter::new(file);
+
+        writeln!(file, "{}", serde_json::to_string(header)?)?;
+
+        Ok(Self { file, bytes: 0 })
+    }
+
+    pub fn write(&mut self, time: f64, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        self.file.write_all(&time.to_le_bytes())?;
+        self.file.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.file.write_all(data)?;
+        self.bytes += data.len();
+
+        Ok(())
+    }
+
+    /// Flushes the capture, returning the number of bytes captured
+    pub fn finish(mut self) -> Result<usize> {
+        self.file.flush()?;
+        Ok(self.bytes)
+    }
+}
+
+pub struct CaptureReader {
+    file: BufReader<File>,
+    pub header: CaptureHeader,
+    data: Vec<u8>,
+    ndx: usize,
+    time: f64,
+}
+
+impl CaptureReader {
+    pub fn open(filename: &str) -> Result<Self> {
+        let file = File::open(filename)
+            .with_context(|| format!("failed to open {filename}"))?;
+        let mut file = BufReader::new(file);
+        let mut line = String::new();
+
+        file.read_line(&mut line)?;
+
+        let header: CaptureHeader = match serde_json::from_str(&line) {
+            Ok(header) => header,
+            Err(_) => bail!("{filename} is not an ITM capture file"),
+        };
+
+        if header.format != CAPTURE_FORMAT {
+            bail!("{filename} is not an ITM capture file");
+        }
+
+        if header.version != CAPTURE_VERSION {
+            bail!(
+                "{filename} is version {} capture; expected version {}",
+                header.version,
+                CAPTURE_VERSION
+            );
+        }
+
+        Ok(Self { file, header, data: vec![], ndx: 0, time: 0.0 })
+    }
+
+    /// Returns the next byte in the capture, along with its time
+    pub fn read_byte(&mut self) -> Result<Option<(u8, f64)>> {
+        while self.ndx == self.data.len() {
+            let mut time = [0u8; 8];
+
+            match self.file.read_exact(&mut time) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            let mut len = [0u8; 4];
+            self.file.read_exact(&mut len).context("truncated capture")?;
+
+            self.data = vec![0; u32::from_le_bytes(len) as usize];
+            self.file
+                .read_exact(&mut self.data)
+                .context("truncated capture")?;
+            self.time = f64::from_le_bytes(time);
+            self.ndx = 0;
+        }
+
+        self.ndx += 1;
+        Ok(Some((self.data[self.ndx - 1], self.time)))
+    }
+}
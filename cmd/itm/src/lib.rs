@@ -31,11 +31,94 @@
 //! Task #7 Divide-by-zero
 //! ```
 //!
+//! Because ITM is lossy, `humility itm` keeps a running count of ITM
+//! overflow packets (which the ITM emits when its FIFO fills and data is
+//! dropped), of bytes discarded while resynchronizing, and of gaps in the
+//! TPIU framing.  When ingestion ends (at the end of an ingested file, or
+//! when interrupted with Control-C when attached), these are reported along
+//! with an estimate of the bytes lost and the stimulus ports most affected:
+//!
+//! ```console
+//! humility: ITM: 48211 packets, 190316 bytes on 2 ports
+//! humility: ITM: 37 overflows, 1 resyncs (12 bytes discarded)
+//! humility: ITM: 2 TPIU gaps (31 bytes discarded) in 23905 frames
+//! humility: ITM: estimated 191 bytes lost (0.1% of trace)
+//! humility: ITM: port 0: 35 overflows, ~140 bytes lost (47920 packets, 189164 bytes received)
+//! humility: ITM: port 1: 2 overflows, ~8 bytes lost (291 packets, 1152 bytes received)
+//! ```
+//!
+//! As an overflow indicates only that at least one packet was dropped, each
+//! overflow is assumed to have dropped a single packet of the average size
+//! for the port that last sent data; the estimate is therefore a lower
+//! bound.
+//!
+//! Auxiliary information from the TPIU formatter -- trigger packets, and
+//! the half-word synchronization packets with which it pads frames when
+//! flushed -- is counted rather than treated as data, and reported if
+//! present.  Frames that fail validation once framing has been established
+//! (including any with a reserved source ID) are reported as frame errors;
+//! any frame error, gap or discarded byte indicates that the capture is
+//! incomplete.
+//!
+//! To tune the SWO baud rate (or to see where overflows are coming from),
+//! `--stats` will accumulate statistics on each stimulus port rather than
+//! display packets, reporting them every `--interval` milliseconds (5000 by
+//! default) and again when ingestion ends.  Gaps between packets on a port
+//! that exceed `--idle` milliseconds (10 by default) are considered idle
+//! time; `RATE` is the port's bytes per second over the time that it was
+//! otherwise active, and `ACTIVE` is that time as a percentage of the
+//! interval:
+//!
+//! ```console
+//! $ humility -a /path/to/my/hubris-archive.zip itm -ea --stats
+//! ...
+//! PORT   PACKETS      BYTES OVERFLOWS       RATE   ACTIVE  IDLES     MAXGAP
+//!    0      4913      19402         6      41270     9.4%     37    0.812s
+//!    1        12         48         0          -     0.0%     11    1.104s
+//! ```
+//!
+//! When ingesting from an attached device (or reading a capture),
+//! `--timeline` will prefix each line of output with the kernel's tick count
+//! (milliseconds since boot) and the host's wall clock time (seconds since
+//! the epoch) at which it was ingested.  The same timebase is used by
+//! `humility ringbuf --timeline`, allowing output from both to be
+//! interleaved into a single timeline.
+//!
+//! To capture ITM data on one machine (e.g., a headless test rig) and
+//! analyze it on another, `--capture` will write the raw SWO stream -- along
+//! with the time at which each byte was read, and the timebase (if the
+//! archive allows one to be established) -- to the specified file as it is
+//! ingested from an attached device:
+//!
+//! ```console
+//! $ humility -a /path/to/my/hubris-archive.zip itm -ea --capture out.swo
+//! ...
+//! ^C
+//! humility: captured 190341 bytes to out.swo
+//! ```
+//!
+//! This file can then be decoded with `--read`, which accepts the same
+//! options (`--timeline`, `--chrome-trace` and `--stats`) as ingesting from
+//! an attached device:
+//!
+//! ```console
+//! $ humility -a /path/to/my/hubris-archive.zip itm --read out.swo --timeline
+//! ```
+//!
+//! To explore ITM output interactively, `--chrome-trace` will write each
+//! line of text (or, for ports other than 0 and 1, each packet) as an event
+//! in the Chrome trace event format, which can be loaded into
+//! `chrome://tracing` or Perfetto (https://ui.perfetto.dev).  Each stimulus
+//! port has its own track, and events are timed by when they were ingested
+//! (or, for a Saleae trace file, by the time recorded in the file).
+//!
 
 use anyhow::{bail, Context, Result};
 use clap::{CommandFactory, Parser};
+use humility::chrome::ChromeTrace;
 use humility::core::Core;
 use humility::hubris::*;
+use humility::timebase::Timebase;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{attach_live, CommandKind};
 use humility_cmd::{Archive, Command};
@@ -44,10 +127,17 @@ use humility_cortex::dwt::*;
 use humility_cortex::itm::*;
 use humility_cortex::scs::*;
 use humility_cortex::tpiu::*;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
+use std::sync::atomic::Ordering;
 use std::time::Instant;
 
+mod capture;
+mod stats;
+use capture::{CaptureHeader, CaptureReader, CaptureWriter};
+use stats::ItmActivity;
+
 const ITM_TRACEID_MAX: u8 = 0x7f;
 
 #[derive(Parser, Debug)]
@@ -95,6 +185,40 @@ struct ItmArgs {
     /// reset target
     #[clap(long, short, requires = "attach")]
     reset: bool,
+
+    /// prefix each line with kernel ticks and wall clock time
+    #[clap(long)]
+    timeline: bool,
+
+    /// write output to the specified file in Chrome trace event format
+    #[clap(long, value_name = "filename")]
+    chrome_trace: Option<String>,
+
+    /// report per-port statistics rather than displaying packets
+    #[clap(long, conflicts_with_all = &["timeline", "chrome_trace"])]
+    stats: bool,
+
+    /// with --stats, interval at which to report, in milliseconds
+    #[clap(long, value_name = "ms", default_value_t = 5000)]
+    interval: u64,
+
+    /// with --stats, gap after which a port is considered idle, in
+    /// milliseconds
+    #[clap(long, value_name = "ms", default_value_t = 10)]
+    idle: u64,
+
+    /// when ingesting from attached device, also capture raw stream to file
+    #[clap(long, value_name = "filename", requires = "attach")]
+    capture: Option<String>,
+
+    /// decode a capture made with --capture
+    #[clap(
+        long, value_name = "filename",
+        conflicts_with_all = &[
+            "probe", "enable", "disable", "ingest", "attach", "bypass"
+        ]
+    )]
+    read: Option<String>,
 }
 
 fn itmcmd_probe(core: &mut dyn Core, coreinfo: &CoreInfo) -> Result<()> {
@@ -149,23 +273,99 @@ fn itmcmd_disable(core: &mut dyn Core) -> Result<()> {
     Ok(())
 }
 
+///
+/// Adds an instrumentation packet to a Chrome trace.  Ports 0 and 1 carry
+/// text, which we accumulate into lines; packets on any other port are
+/// added as they are.
+///
+fn itmcmd_chrome(
+    chrome: &mut ChromeTrace,
+    lines: &mut HashMap<u32, String>,
+    packet: &ITMPacket,
+) {
+    if let ITMPayload::Instrumentation { payload, port } = &packet.payload {
+        let track = format!("port {port}");
+
+        if *port > 1 {
+            let name = format!("{:x?}", payload);
+            chrome.instant(&track, &name, packet.time, &[]);
+            return;
+        }
+
+        let line = lines.entry(*port).or_default();
+
+        for p in payload {
+            if *p == b'\n' {
+                chrome.instant(&track, line, packet.time, &[]);
+                line.clear();
+            } else {
+                line.push(*p as char);
+            }
+        }
+    }
+}
+
+fn itmcmd_chrome_write(
+    chrome: Option<ChromeTrace>,
+    subargs: &ItmArgs,
+) -> Result<()> {
+    if let (Some(chrome), Some(filename)) = (chrome, &subargs.chrome_trace) {
+        chrome.write(filename)?;
+        humility::msg!("Chrome trace written to {filename}");
+    }
+
+    Ok(())
+}
+
+///
+/// Accumulates a packet into per-port statistics, reporting them if the
+/// current interval has run its course.
+///
+fn itmcmd_stats(
+    activity: &mut ItmActivity,
+    subargs: &ItmArgs,
+    packet: &ITMPacket,
+) {
+    activity.record(packet);
+
+    if activity.due(subargs.interval as f64 / 1000.0) {
+        activity.report();
+    }
+}
+
 fn itmcmd_ingest(subargs: &ItmArgs, filename: &str) -> Result<()> {
     let file = File::open(filename)?;
     let traceid = if subargs.bypass { None } else { Some(subargs.traceid) };
 
+    let mut chrome =
+        subargs.chrome_trace.as_ref().map(|_| ChromeTrace::new("itm"));
+    let mut lines = HashMap::new();
+
+    let mut activity =
+        subargs.stats.then(|| ItmActivity::new(subargs.idle as f64 / 1000.0));
+
     let process = |packet: &ITMPacket| -> Result<()> {
+        if let Some(ref mut activity) = activity {
+            itmcmd_stats(activity, subargs, packet);
+            return Ok(());
+        }
+
         if let ITMPayload::Instrumentation { payload, .. } = &packet.payload {
             for p in payload {
                 print!("{}", *p as char);
             }
         }
 
+        if let Some(ref mut chrome) = chrome {
+            itmcmd_chrome(chrome, &mut lines, packet);
+        }
+
         Ok(())
     };
 
     let mut rdr = csv::Reader::from_reader(file);
 
-    match rdr.headers() {
+    let stats = match rdr.headers() {
         Ok(_hdr) => {
             type SaleaeTraceRecord = (f64, u8, Option<String>, Option<String>);
             let mut iter = rdr.deserialize();
@@ -205,10 +405,94 @@ fn itmcmd_ingest(subargs: &ItmArgs, filename: &str) -> Result<()> {
                 process,
             )
         }
+    }?;
+
+    if let Some(ref mut activity) = activity {
+        activity.report();
     }
+
+    stats.report();
+
+    itmcmd_chrome_write(chrome, subargs)
+}
+
+///
+/// Decodes an ITM stream from an attached device or a capture file, honoring
+/// `--timeline`, `--chrome-trace` and `--stats`.
+///
+fn itmcmd_decode(
+    subargs: &ItmArgs,
+    traceid: Option<u8>,
+    timebase: Option<Timebase>,
+    readnext: impl FnMut() -> Result<Option<(u8, f64)>>,
+) -> Result<()> {
+    let mut line = String::new();
+
+    let mut chrome =
+        subargs.chrome_trace.as_ref().map(|_| ChromeTrace::new("itm"));
+    let mut lines = HashMap::new();
+
+    let mut activity =
+        subargs.stats.then(|| ItmActivity::new(subargs.idle as f64 / 1000.0));
+
+    let stats = itm_ingest(traceid, readnext, |packet| {
+        if let Some(ref mut activity) = activity {
+            itmcmd_stats(activity, subargs, packet);
+            return Ok(());
+        }
+
+        if let Some(ref mut chrome) = chrome {
+            itmcmd_chrome(chrome, &mut lines, packet);
+        }
+
+        if let ITMPayload::Instrumentation { payload, port } = &packet.payload {
+            let timebase = match timebase {
+                Some(ref timebase) => timebase,
+                None => {
+                    if *port > 1 {
+                        println!("{:x?}", payload);
+                        return Ok(());
+                    }
+
+                    for p in payload {
+                        print!("{}", *p as char);
+                    }
+
+                    return Ok(());
+                }
+            };
+
+            let when = timebase.elapsed(packet.time);
+
+            if *port > 1 {
+                println!("{} {:x?}", when, payload);
+                return Ok(());
+            }
+
+            for p in payload {
+                if *p == b'\n' {
+                    println!("{} {}", when, line);
+                    line.clear();
+                } else {
+                    line.push(*p as char);
+                }
+            }
+        }
+
+        Ok(())
+    })?;
+
+    if let Some(ref mut activity) = activity {
+        activity.report();
+    }
+
+    stats.report();
+
+    itmcmd_chrome_write(chrome, subargs)
 }
 
 fn itmcmd_ingest_attached(
+    hubris: &HubrisArchive,
     core: &mut dyn Core,
     coreinfo: &CoreInfo,
     subargs: &ItmArgs,
@@ -216,41 +500,110 @@ fn itmcmd_ingest_attached(
     let mut bytes: Vec<u8> = vec![];
     let mut ndx = 0;
 
+    //
+    // If we have been asked for a timeline, we anchor our timebase now; the
+    // host time at which we ingest each packet then positions it relative
+    // to the kernel's notion of time.  If we are capturing, we anchor it
+    // regardless (if we can), so that a timeline can be displayed when the
+    // capture is read.
+    //
+    let timebase = if subargs.timeline {
+        if !hubris.loaded() {
+            bail!("must provide an archive to display a timeline");
+        }
+
+        Some(Timebase::new(hubris, core)?)
+    } else if subargs.capture.is_some() && hubris.loaded() {
+        Timebase::new(hubris, core).ok()
+    } else {
+        None
+    };
+
     let traceid = if coreinfo.address(CoreSightComponent::SWO).is_some() {
         None
     } else {
         Some(subargs.traceid)
     };
 
+    let mut capture = match &subargs.capture {
+        Some(filename) => {
+            let header = CaptureHeader::new(
+                traceid,
+                hubris.image_id(),
+                timebase.as_ref(),
+            );
+
+            Some(CaptureWriter::create(filename, &header)?)
+        }
+        None => None,
+    };
+
     let start = Instant::now();
 
-    itm_ingest(
-        traceid,
-        || {
-            while ndx == bytes.len() {
-                bytes = core.read_swv()?;
-                ndx = 0;
+    //
+    // We ingest until we are interrupted, at which point we report on any
+    // data that we know to have been lost.
+    //
+    let done = humility_cmd::interrupted()?;
+
+    let timeline = if subargs.timeline { timebase } else { None };
+
+    itmcmd_decode(subargs, traceid, timeline, || {
+        while ndx == bytes.len() {
+            if done.load(Ordering::SeqCst) {
+                return Ok(None);
             }
-            ndx += 1;
-            Ok(Some((bytes[ndx - 1], start.elapsed().as_secs_f64())))
-        },
-        |packet| {
-            if let ITMPayload::Instrumentation { payload, port } =
-                &packet.payload
-            {
-                if *port > 1 {
-                    println!("{:x?}", payload);
-                    return Ok(());
-                }
 
-                for p in payload {
-                    print!("{}", *p as char);
-                }
+            bytes = core.read_swv()?;
+            ndx = 0;
+
+            if let Some(ref mut capture) = capture {
+                capture.write(start.elapsed().as_secs_f64(), &bytes)?;
             }
+        }
+        ndx += 1;
+        Ok(Some((bytes[ndx - 1], start.elapsed().as_secs_f64())))
+    })?;
+
+    if let (Some(capture), Some(filename)) = (capture, &subargs.capture) {
+        let nbytes = capture.finish()?;
+        humility::msg!("captured {nbytes} bytes to {filename}");
+    }
 
-            Ok(())
-        },
-    )
+    Ok(())
+}
+
+fn itmcmd_read(
+    hubris: &HubrisArchive,
+    subargs: &ItmArgs,
+    filename: &str,
+) -> Result<()> {
+    let mut capture = CaptureReader::open(filename)?;
+    let header = &capture.header;
+
+    if let (Some(id), Some(captured)) = (hubris.image_id(), &header.image_id) {
+        let id = id.iter().map(|b| format!("{b:02x}")).collect::<String>();
+
+        if id != *captured {
+            humility::warn!(
+                "archive image ID ({id}) does not match that of the \
+                capture ({captured})"
+            );
+        }
+    }
+
+    let timebase = if subargs.timeline {
+        match header.timebase() {
+            Some(timebase) => Some(timebase),
+            None => bail!("capture does not contain a timebase"),
+        }
+    } else {
+        None
+    };
+
+    let traceid = header.traceid;
+
+    itmcmd_decode(subargs, traceid, timebase, || capture.read_byte())
 }
 
 fn itmcmd(context: &mut ExecutionContext) -> Result<()> {
@@ -262,10 +615,19 @@ fn itmcmd(context: &mut ExecutionContext) -> Result<()> {
 
     let traceid = subargs.traceid;
 
+    if subargs.timeline && !subargs.attach && subargs.read.is_none() {
+        bail!("--timeline requires --attach or --read");
+    }
+
     if traceid >= ITM_TRACEID_MAX {
         bail!("traceid has a maximum value of {:x}", ITM_TRACEID_MAX);
     }
 
+    if let Some(read) = &subargs.read {
+        return itmcmd_read(hubris, subargs, read)
+            .with_context(|| format!("failed to read {read}"));
+    }
+
     if let Some(ingest) = &subargs.ingest {
         match itmcmd_ingest(subargs, ingest) {
             Err(e) => {
@@ -336,7 +698,7 @@ fn itmcmd(context: &mut ExecutionContext) -> Result<()> {
     }
 
     if rval.is_ok() && subargs.attach {
-        match itmcmd_ingest_attached(core, &coreinfo, subargs) {
+        match itmcmd_ingest_attached(hubris, core, &coreinfo, subargs) {
             Err(e) => {
                 bail!("failed to ingest from attached device: {}", e);
             }
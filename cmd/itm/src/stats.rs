@@ -0,0 +1,161 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Accumulation of per-stimulus port statistics for `humility itm --stats`.
+//! Rather than displaying packets, we count packets, bytes and overflows on
+//! each port, along with the gaps between consecutive packets.  Gaps longer
+//! than an idle threshold are taken to be the port having nothing to say,
+//! and are excluded from the port's active time; the rate over active time
+//! is the rate at which the port demands bandwidth of the SWO.
+//!
+
+use humility_cortex::itm::*;
+use std::collections::BTreeMap;
+
+#[derive(Copy, Clone, Debug, Default)]
+struct PortActivity {
+    packets: usize,
+    bytes: usize,
+    overflows: usize,
+    last: Option<f64>,
+    active: f64,
+    idles: usize,
+    maxgap: f64,
+}
+
+#[derive(Debug)]
+pub struct ItmActivity {
+    idle: f64,
+    start: Option<f64>,
+    now: f64,
+    last: Option<u32>,
+    unattributed: usize,
+    ports: BTreeMap<u32, PortActivity>,
+}
+
+impl ItmActivity {
+    pub fn new(idle: f64) -> Self {
+        Self {
+            idle,
+            start: None,
+            now: 0.0,
+            last: None,
+            unattributed: 0,
+            ports: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the seconds elapsed in the current interval
+    fn elapsed(&self) -> f64 {
+        self.start.map(|start| self.now - start).unwrap_or(0.0)
+    }
+
+    /// Returns true if the current interval has run for `interval` seconds
+    pub fn due(&self, interval: f64) -> bool {
+        self.elapsed() >= interval
+    }
+
+    pub fn record(&mut self, packet: &ITMPacket) {
+        self.start.get_or_insert(packet.time);
+        self.now = packet.time;
+
+        match (&packet.payload, packet.header) {
+            (ITMPayload::Instrumentation { port, payload }, _) => {
+                let p = self.ports.entry(*port).or_default();
+
+                if let Some(last) = p.last {
+                    let gap = packet.time - last;
+
+                    if gap > self.idle {
+                        p.idles += 1;
+                    } else {
+                        p.active += gap;
+                    }
+
+                    if gap > p.maxgap {
+                        p.maxgap = gap;
+                    }
+                }
+
+                p.packets += 1;
+                p.bytes += payload.len();
+                p.last = Some(packet.time);
+                self.last = Some(*port);
+            }
+
+            (_, ITMHeader::Overflow) => match self.last {
+                Some(port) => {
+                    self.ports.entry(port).or_default().overflows += 1
+                }
+                None => self.unattributed += 1,
+            },
+
+            _ => {}
+        }
+    }
+
+    ///
+    /// Reports the statistics accumulated over the current interval, and
+    /// begins a new one.  The time of each port's last packet is retained,
+    /// so a gap that spans two intervals is accounted to the latter.
+    ///
+    pub fn report(&mut self) {
+        let elapsed = self.elapsed();
+
+        println!(
+            "{:>4} {:>9} {:>10} {:>9} {:>10} {:>8} {:>6} {:>10}",
+            "PORT",
+            "PACKETS",
+            "BYTES",
+            "OVERFLOWS",
+            "RATE",
+            "ACTIVE",
+            "IDLES",
+            "MAXGAP"
+        );
+
+        for (port, p) in &self.ports {
+            let rate = if p.active > 0.0 {
+                format!("{:.0}", p.bytes as f64 / p.active)
+            } else {
+                "-".to_string()
+            };
+
+            let active = if elapsed > 0.0 {
+                format!("{:.1}%", (p.active / elapsed) * 100.0)
+            } else {
+                "-".to_string()
+            };
+
+            println!(
+                "{:>4} {:>9} {:>10} {:>9} {:>10} {:>8} {:>6} {:>9.3}s",
+                port,
+                p.packets,
+                p.bytes,
+                p.overflows,
+                rate,
+                active,
+                p.idles,
+                p.maxgap
+            );
+        }
+
+        if self.unattributed != 0 {
+            println!(
+                "{:>4} {:>9} {:>10} {:>9}",
+                "-", "-", "-", self.unattributed
+            );
+        }
+
+        println!();
+
+        self.start = Some(self.now);
+        self.unattributed = 0;
+
+        for p in self.ports.values_mut() {
+            *p = PortActivity { last: p.last, ..Default::default() };
+        }
+    }
+}
@@ -93,6 +93,34 @@
 //!
 //! As with tasks held by `--hold`, use `--release`/`-r` to set the task back to
 //! normal, or `--start`/`-s` to run it once but catch the next fault.
+//! (`--restart` and `--resume` are accepted as synonyms for `--start` and
+//! `--release`, respectively.)
+//!
+//! More than one task may be specified, in which case the request is made of
+//! each in turn.  To see which tasks -- and which task indices, as
+//! conveyed to `jefe` -- a request would affect without making it, use
+//! `--dry-run`/`-n`:
+//!
+//! ```console
+//! $ humility jefe --fault --dry-run ping pong
+//! humility: attached via ST-Link
+//! TASK                 INDEX REQUEST
+//! ping                     8 fault (inject a fault and hold)
+//! pong                     7 fault (inject a fault and hold)
+//! humility: dry run; no requests made
+//! ```
+//!
+//! To see the same and be asked for confirmation before any request is made,
+//! use `--confirm`:
+//!
+//! ```console
+//! $ humility jefe --start --confirm pong
+//! humility: attached via ST-Link
+//! TASK                 INDEX REQUEST
+//! pong                     7 start (restart now)
+//! humility: make this request of jefe? [y/N] y
+//! humility: successfully changed disposition for pong
+//! ```
 
 use anyhow::{anyhow, bail, Result};
 use clap::{CommandFactory, Parser};
@@ -117,7 +145,10 @@ struct JefeArgs {
     fault: bool,
 
     /// start the specified task
-    #[clap(long, short, conflicts_with_all = &["release", "hold"])]
+    #[clap(
+        long, short, visible_alias = "restart",
+        conflicts_with_all = &["release", "hold"]
+    )]
     start: bool,
 
     /// hold the specified task
@@ -125,10 +156,28 @@ struct JefeArgs {
     hold: bool,
 
     /// release the specified task
-    #[clap(long, short)]
+    #[clap(long, short, visible_alias = "resume")]
     release: bool,
 
-    task: String,
+    /// show the tasks that would be affected, but make no requests
+    #[clap(long = "dry-run", short = 'n', conflicts_with = "confirm")]
+    dryrun: bool,
+
+    /// show the tasks that would be affected and ask for confirmation
+    #[clap(long)]
+    confirm: bool,
+
+    #[clap(required = true)]
+    task: Vec<String>,
+}
+
+fn jefe_describe(request: JefeRequest) -> &'static str {
+    match request {
+        JefeRequest::Start => "start (restart now)",
+        JefeRequest::Hold => "hold (do not restart on fault)",
+        JefeRequest::Release => "release (restart on fault)",
+        JefeRequest::Fault => "fault (inject a fault and hold)",
+    }
 }
 
 fn jefe(context: &mut ExecutionContext) -> Result<()> {
@@ -136,7 +185,8 @@ fn jefe(context: &mut ExecutionContext) -> Result<()> {
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
     let hubris = context.archive.as_ref().unwrap();
 
-    let subargs = JefeArgs::try_parse_from(subargs)?;
+    let mut subargs = JefeArgs::try_parse_from(subargs)?;
+    subargs.dryrun |= context.cli.dry_run;
 
     let request = if subargs.fault {
         JefeRequest::Fault
@@ -150,26 +200,60 @@ fn jefe(context: &mut ExecutionContext) -> Result<()> {
         bail!("one of fault, start, hold, or release must be specified");
     };
 
-    let task = hubris
-        .lookup_task(&subargs.task)
-        .ok_or_else(|| anyhow!("couldn't find task {}", subargs.task))?;
+    //
+    // Resolve all of our tasks before making any requests, so we don't
+    // make requests of some tasks only to fail on another.
+    //
+    let mut targets = vec![];
 
-    let id = match task {
-        HubrisTask::Kernel => {
-            bail!("cannot change disposition of kernel");
-        }
-        HubrisTask::Task(id) => {
-            if let Some(id) = NonZeroU32::new(*id) {
-                id
-            } else {
-                bail!("cannot change disposition of supervisor task");
+    for name in &subargs.task {
+        let task = hubris
+            .lookup_task(name)
+            .ok_or_else(|| anyhow!("couldn't find task {}", name))?;
+
+        let id = match task {
+            HubrisTask::Kernel => {
+                bail!("cannot change disposition of kernel");
             }
+            HubrisTask::Task(id) => {
+                if let Some(id) = NonZeroU32::new(*id) {
+                    id
+                } else {
+                    bail!("cannot change disposition of supervisor task");
+                }
+            }
+        };
+
+        targets.push((name, id));
+    }
+
+    if subargs.dryrun || subargs.confirm {
+        println!("{:20} {:>5} REQUEST", "TASK", "INDEX");
+
+        for (name, id) in &targets {
+            println!("{:20} {:>5} {}", name, id, jefe_describe(request));
+        }
+
+        if subargs.dryrun {
+            humility::msg!("dry run; no requests made");
+            return Ok(());
         }
-    };
 
-    send_request(hubris, core, request, id, subargs.timeout)?;
+        let prompt = if targets.len() == 1 {
+            "make this request of jefe?"
+        } else {
+            "make these requests of jefe?"
+        };
 
-    humility::msg!("successfully changed disposition for {}", subargs.task);
+        if !humility_cmd::confirm(prompt)? {
+            bail!("request aborted");
+        }
+    }
+
+    for (name, id) in targets {
+        send_request(hubris, core, request, id, subargs.timeout)?;
+        humility::msg!("successfully changed disposition for {}", name);
+    }
 
     Ok(())
 }
@@ -0,0 +1,1132 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility lpc55pfr`
+//!
+//! `humility lpc55pfr` reads, decodes and writes the pages of the LPC55S6x
+//! protected flash region (PFR) that govern how the part boots:  the
+//! customer manufacturing programmable area (CMPA), which contains the
+//! boot configuration, the secure boot configuration and the hash of the
+//! root keys; and the customer field programmable area (CFPA), which
+//! contains the monotonic firmware versions, the root key revocation
+//! fields and the debug authentication settings.
+//!
+//! To read and decode both pages, use the `read` subcommand:
+//!
+//! ```console
+//! $ humility lpc55pfr read
+//! humility: attached via CMSIS-DAP
+//! ROM bootloader v3.0.0
+//! CMPA at 0x0009e400:
+//!   BOOT_CFG                   0x00000000
+//!     DEFAULT_ISP_MODE         0x0 (auto)
+//!     BOOT_SPEED               0x0 (NMPA)
+//!     BOOT_FAILURE_PIN         0x0
+//!   SPI_FLASH_CFG              0x00000000
+//!   USB_ID                     0x00000000
+//! ...
+//!   SECURE_BOOT_CFG            0xc0000000
+//!     RSA4K                    0x0
+//! ...
+//!     SEC_BOOT_EN              0x3 (enabled)
+//! ...
+//!   ROTKH                      2dd1f8c9e3b0d5f3db4e2a6e0e0b7d1d...
+//! CFPA at 0x0009e000 (ping, version 3; pong is version 2):
+//!   HEADER                     0x00000000
+//!   VERSION                    0x00000003
+//! ...
+//!   ROTKH_REVOKE               0x00000005
+//!     RoTK0_EN                 0x1 (enabled)
+//!     RoTK1_EN                 0x1 (enabled)
+//!     RoTK2_EN                 0x0 (invalid)
+//! ...
+//! ```
+//!
+//! To read just one page, specify it (`cmpa` or `cfpa`); to additionally
+//! dump the raw contents of the page, use `--raw`.  (The CFPA exists as a
+//! pair of pages -- "ping" and "pong" -- of which the one with the higher
+//! version is active; it is the active page that is displayed and modified.)
+//!
+//! To modify a page, use the `write` subcommand, specifying each register
+//! or field to be changed with `--set` (`-s`) as either `REGISTER=value` or
+//! `REGISTER.FIELD=value`.  (Register and field names are not case
+//! sensitive.)  A byte array such as `ROTKH` is set by specifying its
+//! contents in hexadecimal.  Alternatively, the entire contents of a page
+//! can be taken from a file with `--file` (`-f`); `--set` may be combined
+//! with `--file` to modify the page from the file.  A diff of the decoded
+//! page is always displayed, but the page is only written if `--commit` is
+//! specified -- and even then, only after confirmation:
+//!
+//! ```console
+//! $ humility lpc55pfr write cfpa --set rotkh_revoke.rotk1_en=2
+//! humility: attached via CMSIS-DAP
+//!   VERSION                    0x00000003 -> 0x00000004
+//!   ROTKH_REVOKE               0x00000005 -> 0x00000009
+//!     RoTK1_EN                 0x1 (enabled) -> 0x2 (revoked)
+//! humility: dry run; specify --commit to write CFPA
+//! $ humility lpc55pfr write cfpa --set rotkh_revoke.rotk1_en=2 --commit
+//! humility: attached via CMSIS-DAP
+//!   VERSION                    0x00000003 -> 0x00000004
+//!   ROTKH_REVOKE               0x00000005 -> 0x00000009
+//!     RoTK1_EN                 0x1 (enabled) -> 0x2 (revoked)
+//! humility: write CFPA? [y/N] y
+//! humility: wrote CFPA at 0x0009e200; target has been reset
+//! ```
+//!
+//! When writing the CFPA, its version is incremented automatically if it
+//! has not been explicitly set to a greater value.  When setting a field of
+//! a debug authentication register (e.g., `CC_SOCU_PIN`), the inverse value
+//! in the upper half of the register is updated accordingly.
+//!
+//! Pages are written by calling into the ROM's flash API, which requires
+//! that the target be halted and its RAM used as scratch; the target is
+//! therefore reset after a write (including one that fails).  `humility
+//! lpc55pfr` will not write a sealed CMPA (that is, one with a non-zero
+//! `SHA256_DIGEST`), nor will it seal the CMPA.  Note that an incorrect CMPA
+//! can render the part unbootable and (depending on debug settings)
+//! unrecoverable; use caution!
+//!
+
+use anyhow::{bail, Context, Result};
+use clap::{ArgEnum, CommandFactory, Parser};
+use humility::core::Core;
+use humility::msg;
+use humility_arch_arm::ARMRegister;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Dumper, Validate};
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[clap(name = "lpc55pfr", about = env!("CARGO_PKG_DESCRIPTION"))]
+enum Lpc55PfrArgs {
+    /// Read and decode the CMPA and/or CFPA
+    Read {
+        /// page to read (default is both)
+        #[clap(arg_enum)]
+        page: Option<Page>,
+
+        /// additionally dump the raw contents of the page
+        #[clap(long)]
+        raw: bool,
+    },
+    /// Modify the CMPA or CFPA, displaying the change before writing it
+    Write {
+        /// page to write
+        #[clap(arg_enum)]
+        page: Page,
+
+        /// register or field to set, as REGISTER[.FIELD]=value
+        #[clap(
+            long,
+            short,
+            value_name = "register[.field]=value",
+            multiple_occurrences = true
+        )]
+        set: Vec<String>,
+
+        /// file containing the new contents of the page
+        #[clap(long, short, value_name = "file")]
+        file: Option<String>,
+
+        /// actually write the page (otherwise, only display the diff)
+        #[clap(long)]
+        commit: bool,
+    },
+}
+
+#[derive(ArgEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum Page {
+    Cmpa,
+    Cfpa,
+}
+
+impl Page {
+    fn name(&self) -> &'static str {
+        match self {
+            Page::Cmpa => "CMPA",
+            Page::Cfpa => "CFPA",
+        }
+    }
+
+    fn registers(&self) -> &'static [Register] {
+        match self {
+            Page::Cmpa => CMPA_REGISTERS,
+            Page::Cfpa => CFPA_REGISTERS,
+        }
+    }
+}
+
+const CFPA_PING: u32 = 0x0009_e000;
+const CFPA_PONG: u32 = 0x0009_e200;
+const CMPA: u32 = 0x0009_e400;
+const PAGE_SIZE: usize = 512;
+
+/// Offset of the version in the CFPA
+const CFPA_VERSION: usize = 0x04;
+
+/// Offset of the digest that seals the CMPA
+const CMPA_DIGEST: usize = 0x1e0;
+
+///
+/// The ROM API tree, which contains (at offset 0x4) the ROM version, and
+/// (at offset 0x10) a pointer to the flash driver interface.
+///
+const ROM_API_TREE: u32 = 0x1300_10f0;
+const ROM_VERSION: u32 = 0x4;
+const ROM_FLASH_DRIVER: u32 = 0x10;
+
+///
+/// Offsets of the functions that we need within the flash driver interface
+///
+const FLASH_INIT: u32 = 0x04;
+const FFR_INIT: u32 = 0x28;
+const FFR_CUST_FACTORY_PAGE_WRITE: u32 = 0x30;
+const FFR_INFIELD_PAGE_WRITE: u32 = 0x48;
+
+///
+/// Our use of RAM when calling the ROM:  a breakpoint instruction to which
+/// calls return, the flash configuration structure, the page to be written,
+/// and a stack.
+///
+const SCRATCH_BKPT: u32 = 0x2000_0000;
+const SCRATCH_CONFIG: u32 = 0x2000_0100;
+const SCRATCH_CONFIG_SIZE: usize = 0x100;
+const SCRATCH_PAGE: u32 = 0x2000_0200;
+const SCRATCH_STACK: u32 = 0x2000_1000;
+
+const PSR_THUMB: u32 = 1 << 24;
+
+struct Field {
+    name: &'static str,
+    hi: u32,
+    lo: u32,
+    values: &'static [(u32, &'static str)],
+}
+
+const fn field(name: &'static str, hi: u32, lo: u32) -> Field {
+    Field { name, hi, lo, values: &[] }
+}
+
+const fn named(
+    name: &'static str,
+    hi: u32,
+    lo: u32,
+    values: &'static [(u32, &'static str)],
+) -> Field {
+    Field { name, hi, lo, values }
+}
+
+impl Field {
+    fn mask(&self) -> u32 {
+        (((1u64 << (self.hi - self.lo + 1)) - 1) as u32) << self.lo
+    }
+
+    fn get(&self, val: u32) -> u32 {
+        (val & self.mask()) >> self.lo
+    }
+
+    fn format(&self, val: u32) -> String {
+        let val = self.get(val);
+
+        match self.values.iter().find(|(v, _)| *v == val) {
+            Some((_, name)) => format!("0x{val:x} ({name})"),
+            None => format!("0x{val:x}"),
+        }
+    }
+}
+
+///
+/// A register within a page:  either a word (possibly with fields) or an
+/// array of bytes.  Debug authentication registers have their value
+/// duplicated, inverted, in their upper half.
+///
+struct Register {
+    name: &'static str,
+    offset: usize,
+    len: usize,
+    fields: &'static [Field],
+    inverse: bool,
+}
+
+const fn word(name: &'static str, offset: usize) -> Register {
+    Register { name, offset, len: 4, fields: &[], inverse: false }
+}
+
+const fn fields(
+    name: &'static str,
+    offset: usize,
+    fields: &'static [Field],
+) -> Register {
+    Register { name, offset, len: 4, fields, inverse: false }
+}
+
+const fn socu(name: &'static str, offset: usize) -> Register {
+    Register { name, offset, len: 4, fields: SOCU_FIELDS, inverse: true }
+}
+
+const fn bytes(name: &'static str, offset: usize, len: usize) -> Register {
+    Register { name, offset, len, fields: &[], inverse: false }
+}
+
+const ENABLED: &[(u32, &str)] = &[(0, "disabled"), (1, "enabled")];
+
+const SOCU_FIELDS: &[Field] = &[
+    named("NIDEN", 0, 0, ENABLED),
+    named("DBGEN", 1, 1, ENABLED),
+    named("SPNIDEN", 2, 2, ENABLED),
+    named("SPIDEN", 3, 3, ENABLED),
+    named("TAPEN", 4, 4, ENABLED),
+    named("CPU1_DBGEN", 5, 5, ENABLED),
+    named("ISP_CMD_EN", 6, 6, ENABLED),
+    named("FA_CMD_EN", 7, 7, ENABLED),
+    named("ME_CMD_EN", 8, 8, ENABLED),
+    named("CPU1_NIDEN", 9, 9, ENABLED),
+    named("UUID_CHECK", 15, 15, ENABLED),
+    field("INVERSE_VALUE", 31, 16),
+];
+
+const ROTK_EN: &[(u32, &str)] =
+    &[(0, "invalid"), (1, "enabled"), (2, "revoked"), (3, "revoked")];
+
+const CMPA_REGISTERS: &[Register] = &[
+    fields(
+        "BOOT_CFG",
+        0x00,
+        &[
+            named(
+                "DEFAULT_ISP_MODE",
+                6,
+                4,
+                &[
+                    (0, "auto"),
+                    (1, "USB0"),
+                    (2, "UART"),
+                    (3, "SPI"),
+                    (4, "I2C"),
+                    (5, "USB1"),
+                    (7, "disabled"),
+                ],
+            ),
+            named(
+                "BOOT_SPEED",
+                8,
+                7,
+                &[(0, "NMPA"), (1, "96 MHz"), (2, "48 MHz")],
+            ),
+            field("BOOT_FAILURE_PIN", 31, 24),
+        ],
+    ),
+    word("SPI_FLASH_CFG", 0x04),
+    fields(
+        "USB_ID",
+        0x08,
+        &[field("USB_VENDOR_ID", 15, 0), field("USB_PRODUCT_ID", 31, 16)],
+    ),
+    word("SDIO_CFG", 0x0c),
+    socu("CC_SOCU_PIN", 0x10),
+    socu("CC_SOCU_DFLT", 0x14),
+    word("VENDOR_USAGE", 0x18),
+    fields(
+        "SECURE_BOOT_CFG",
+        0x1c,
+        &[
+            named("RSA4K", 1, 0, &[(0, "RSA-2048")]),
+            field("DICE_ENC_NXP_CFG", 3, 2),
+            field("DICE_CUST_CFG", 5, 4),
+            field("SKIP_DICE", 7, 6),
+            named(
+                "TZM_IMAGE_TYPE",
+                9,
+                8,
+                &[(0, "from header"), (1, "disabled"), (2, "enabled")],
+            ),
+            field("BLOCK_SET_KEY", 11, 10),
+            field("BLOCK_ENROLL", 13, 12),
+            field("DICE_INC_SEC_EPOCH", 15, 14),
+            named(
+                "SEC_BOOT_EN",
+                31,
+                30,
+                &[
+                    (0, "disabled"),
+                    (1, "enabled"),
+                    (2, "enabled"),
+                    (3, "enabled"),
+                ],
+            ),
+        ],
+    ),
+    fields(
+        "PRINCE_BASE_ADDR",
+        0x20,
+        &[
+            field("ADDR0_PRG", 3, 0),
+            field("ADDR1_PRG", 7, 4),
+            field("ADDR2_PRG", 11, 8),
+            field("LOCK_REG0", 17, 16),
+            field("LOCK_REG1", 19, 18),
+            field("REG0_ERASE_CHECK_EN", 25, 24),
+            field("REG1_ERASE_CHECK_EN", 27, 26),
+            field("REG2_ERASE_CHECK_EN", 29, 28),
+        ],
+    ),
+    word("PRINCE_SR_0", 0x24),
+    word("PRINCE_SR_1", 0x28),
+    word("PRINCE_SR_2", 0x2c),
+    bytes("ROTKH", 0x50, 32),
+    bytes("CUSTOMER_DEFINED", 0x100, 224),
+    bytes("SHA256_DIGEST", CMPA_DIGEST, 32),
+];
+
+const CFPA_REGISTERS: &[Register] = &[
+    word("HEADER", 0x00),
+    word("VERSION", CFPA_VERSION),
+    word("S_FW_VERSION", 0x08),
+    word("NS_FW_VERSION", 0x0c),
+    word("IMAGE_KEY_REVOKE", 0x10),
+    fields(
+        "ROTKH_REVOKE",
+        0x18,
+        &[
+            named("RoTK0_EN", 1, 0, ROTK_EN),
+            named("RoTK1_EN", 3, 2, ROTK_EN),
+            named("RoTK2_EN", 5, 4, ROTK_EN),
+        ],
+    ),
+    word("VENDOR_USAGE", 0x1c),
+    socu("DCFG_CC_SOCU_NS_PIN", 0x20),
+    socu("DCFG_CC_SOCU_NS_DFLT", 0x24),
+    word("ENABLE_FA_MODE", 0x28),
+    word("CMPA_PROG_IN_PROGRESS", 0x2c),
+    bytes("CUSTOMER_DEFINED", 0x100, 224),
+    bytes("SHA256_DIGEST", 0x1e0, 32),
+];
+
+impl Register {
+    fn value(&self, page: &[u8]) -> u32 {
+        let b = &page[self.offset..self.offset + 4];
+        u32::from_le_bytes(b.try_into().unwrap())
+    }
+
+    fn contents<'a>(&self, page: &'a [u8]) -> &'a [u8] {
+        &page[self.offset..self.offset + self.len]
+    }
+
+    fn format(&self, page: &[u8]) -> String {
+        if self.len == 4 {
+            return format!("0x{:08x}", self.value(page));
+        }
+
+        let contents = self.contents(page);
+
+        if contents.iter().all(|&b| b == 0) {
+            "(all zeroes)".to_string()
+        } else if self.len <= 32 {
+            contents.iter().map(|b| format!("{b:02x}")).collect()
+        } else {
+            let nz = contents.iter().filter(|&&b| b != 0).count();
+            format!("({} bytes, {nz} non-zero)", self.len)
+        }
+    }
+
+    fn lookup_field(&self, name: &str) -> Option<&'static Field> {
+        self.fields.iter().find(|f| f.name.eq_ignore_ascii_case(name))
+    }
+}
+
+fn print_page(page: Page, data: &[u8], raw: bool, addr: u32) {
+    for reg in page.registers() {
+        println!("  {:26} {}", reg.name, reg.format(data));
+
+        if reg.len != 4 {
+            continue;
+        }
+
+        let val = reg.value(data);
+
+        for f in reg.fields {
+            println!("    {:24} {}", f.name, f.format(val));
+        }
+
+        if reg.inverse && val != 0 && (val >> 16) != (!val & 0xffff) {
+            msg!("warning: {} has an invalid inverse value", reg.name);
+        }
+    }
+
+    if raw {
+        println!();
+        Dumper::new().dump(data, addr);
+    }
+}
+
+///
+/// Prints the differences between two pages, returning true if there are
+/// any.
+///
+fn print_diff(page: Page, old: &[u8], new: &[u8]) -> bool {
+    let mut covered = vec![false; PAGE_SIZE];
+    let mut changed = false;
+
+    for reg in page.registers() {
+        covered[reg.offset..reg.offset + reg.len].fill(true);
+
+        if reg.contents(old) == reg.contents(new) {
+            continue;
+        }
+
+        changed = true;
+
+        println!(
+            "  {:26} {} -> {}",
+            reg.name,
+            reg.format(old),
+            reg.format(new)
+        );
+
+        if reg.len != 4 {
+            continue;
+        }
+
+        let (o, n) = (reg.value(old), reg.value(new));
+
+        for f in reg.fields.iter().filter(|f| f.get(o) != f.get(n)) {
+            println!("    {:24} {} -> {}", f.name, f.format(o), f.format(n));
+        }
+    }
+
+    for offset in 0..PAGE_SIZE {
+        if !covered[offset] && old[offset] != new[offset] {
+            println!(
+                "  {:26} 0x{:02x} -> 0x{:02x}",
+                format!("(offset 0x{offset:03x})"),
+                old[offset],
+                new[offset]
+            );
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+///
+/// Applies a setting of the form `REGISTER[.FIELD]=value` to a page.
+///
+fn apply(page: Page, data: &mut [u8], setting: &str) -> Result<()> {
+    let (lhs, rhs) = match setting.split_once('=') {
+        Some((lhs, rhs)) => (lhs, rhs),
+        None => bail!("setting must be of the form register[.field]=value"),
+    };
+
+    let (name, field) = match lhs.split_once('.') {
+        Some((name, field)) => (name, Some(field)),
+        None => (lhs, None),
+    };
+
+    let reg = match page
+        .registers()
+        .iter()
+        .find(|r| r.name.eq_ignore_ascii_case(name))
+    {
+        Some(reg) => reg,
+        None => bail!("unknown {} register \"{name}\"", page.name()),
+    };
+
+    if reg.len != 4 {
+        if field.is_some() {
+            bail!("{} has no fields", reg.name);
+        }
+
+        let hex = rhs.trim_start_matches("0x");
+
+        if hex.len() != reg.len * 2 {
+            bail!(
+                "{} must be specified as {} hex digits",
+                reg.name,
+                reg.len * 2
+            );
+        }
+
+        for (i, b) in
+            data[reg.offset..reg.offset + reg.len].iter_mut().enumerate()
+        {
+            *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .with_context(|| format!("invalid value for {}", reg.name))?;
+        }
+
+        return Ok(());
+    }
+
+    let val = parse_int::parse::<u32>(rhs)
+        .with_context(|| format!("invalid value for {lhs}: \"{rhs}\""))?;
+
+    let mut new = match field {
+        None => val,
+        Some(field) => {
+            let f = match reg.lookup_field(field) {
+                Some(f) => f,
+                None => bail!("{} has no field \"{field}\"", reg.name),
+            };
+
+            if val > f.mask() >> f.lo {
+                bail!("value 0x{val:x} is too large for {}", f.name);
+            }
+
+            (reg.value(data) & !f.mask()) | (val << f.lo)
+        }
+    };
+
+    //
+    // If a field of a debug authentication register has been set, update
+    // the inverse value accordingly.
+    //
+    if reg.inverse
+        && field.map_or(false, |f| !f.eq_ignore_ascii_case("INVERSE_VALUE"))
+    {
+        new = (new & 0xffff) | ((!new & 0xffff) << 16);
+    }
+
+    data[reg.offset..reg.offset + 4].copy_from_slice(&new.to_le_bytes());
+    Ok(())
+}
+
+fn read_page(core: &mut dyn Core, addr: u32) -> Result<Vec<u8>> {
+    let mut data = vec![0u8; PAGE_SIZE];
+
+    core.read_8(addr, &mut data).with_context(|| {
+        format!("failed to read page at 0x{addr:08x} (erased?)")
+    })?;
+
+    Ok(data)
+}
+
+///
+/// Reads the active CFPA, returning its address, its contents, and the
+/// version of the inactive page (if it can be read).
+///
+fn read_cfpa(core: &mut dyn Core) -> Result<(u32, Vec<u8>, Option<u32>)> {
+    match (read_page(core, CFPA_PING), read_page(core, CFPA_PONG)) {
+        (Ok(ping), Ok(pong)) => {
+            if cfpa_version(&pong) > cfpa_version(&ping) {
+                let v = cfpa_version(&ping);
+                Ok((CFPA_PONG, pong, Some(v)))
+            } else {
+                let v = cfpa_version(&pong);
+                Ok((CFPA_PING, ping, Some(v)))
+            }
+        }
+        (Ok(ping), Err(_)) => Ok((CFPA_PING, ping, None)),
+        (Err(_), Ok(pong)) => Ok((CFPA_PONG, pong, None)),
+        (Err(e), Err(_)) => Err(e),
+    }
+}
+
+fn cfpa_version(data: &[u8]) -> u32 {
+    u32::from_le_bytes(data[CFPA_VERSION..CFPA_VERSION + 4].try_into().unwrap())
+}
+
+fn rom_version(core: &mut dyn Core) -> Result<String> {
+    let v = core.read_word_32(ROM_API_TREE + ROM_VERSION)?;
+    Ok(format!("v{}.{}.{}", (v >> 16) & 0xff, (v >> 8) & 0xff, v & 0xff))
+}
+
+///
+/// Our use of the core while calling into the ROM.  The core is held halted
+/// while this exists (other than while a ROM function is executing); the
+/// registers that we clobber are restored -- and the core is reset if we
+/// have written to its RAM, or resumed if we have not -- by
+/// [`RomSession::finish`], or when it is dropped on an error path.
+///
+struct RomSession<'a> {
+    core: &'a mut dyn Core,
+    saved: Vec<(ARMRegister, u32)>,
+    running: bool,
+    clobbered: bool,
+    finished: bool,
+}
+
+impl<'a> RomSession<'a> {
+    fn new(core: &'a mut dyn Core) -> Result<Self> {
+        core.halt()?;
+
+        let mut session = RomSession {
+            core,
+            saved: vec![],
+            running: false,
+            clobbered: false,
+            finished: false,
+        };
+
+        for reg in [
+            ARMRegister::R0,
+            ARMRegister::R1,
+            ARMRegister::R2,
+            ARMRegister::SP,
+            ARMRegister::LR,
+            ARMRegister::PC,
+            ARMRegister::PSR,
+            ARMRegister::SPR,
+        ] {
+            let val = session.core.read_reg(reg)?;
+            session.saved.push((reg, val));
+        }
+
+        Ok(session)
+    }
+
+    ///
+    /// Disables interrupts (by setting PRIMASK), plants our breakpoint, and
+    /// loads the page into RAM.
+    ///
+    fn prepare(&mut self, data: &[u8]) -> Result<()> {
+        let spr = self.core.read_reg(ARMRegister::SPR)?;
+        self.core.write_reg(ARMRegister::SPR, spr | 1)?;
+
+        self.clobbered = true;
+        self.core.write_word_32(SCRATCH_BKPT, 0xbe00_be00)?;
+        self.core.write_8(SCRATCH_CONFIG, &[0u8; SCRATCH_CONFIG_SIZE])?;
+        self.core.write_8(SCRATCH_PAGE, data)
+    }
+
+    ///
+    /// Calls a function in the ROM's flash driver interface, returning to a
+    /// breakpoint in scratch RAM.
+    ///
+    fn call(
+        &mut self,
+        driver: u32,
+        name: &str,
+        offset: u32,
+        args: &[u32],
+    ) -> Result<()> {
+        let core = &mut *self.core;
+        let func = core.read_word_32(driver + offset)?;
+        let regs = [ARMRegister::R0, ARMRegister::R1, ARMRegister::R2];
+
+        for (reg, arg) in regs.iter().zip(args) {
+            core.write_reg(*reg, *arg)?;
+        }
+
+        core.write_reg(ARMRegister::SP, SCRATCH_STACK)?;
+        core.write_reg(ARMRegister::LR, SCRATCH_BKPT | 1)?;
+        core.write_reg(ARMRegister::PC, func & !1)?;
+        core.write_reg(ARMRegister::PSR, PSR_THUMB)?;
+
+        self.running = true;
+        core.run()?;
+
+        core.wait_for_halt(Duration::from_secs(5))
+            .with_context(|| format!("{name} did not return"))?;
+        self.running = false;
+
+        match core.read_reg(ARMRegister::R0)? {
+            0 => Ok(()),
+            status => bail!("{name} failed with status {status}"),
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.finished = true;
+
+        //
+        // If a ROM function failed to return, it is still running; halt it
+        // so that we can restore the core.
+        //
+        if self.running {
+            self.core.halt()?;
+            self.running = false;
+        }
+
+        for (reg, val) in &self.saved {
+            self.core.write_reg(*reg, *val)?;
+        }
+
+        //
+        // Once we have written to RAM, we can't resume whatever was running
+        // and must reset -- which we do by resetting into a halt that we then
+        // balance with our own.
+        //
+        if self.clobbered {
+            self.core.reset_and_halt(Duration::from_secs(2))?;
+        }
+
+        self.core.run()
+    }
+}
+
+impl<'a> Drop for RomSession<'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Err(e) = self.finish() {
+                msg!("failed to restore core after ROM call: {e:?}");
+            }
+        }
+    }
+}
+
+fn rom_write(core: &mut dyn Core, page: Page, data: &[u8]) -> Result<()> {
+    let mut session = RomSession::new(core)?;
+    session.prepare(data)?;
+
+    let driver = session.core.read_word_32(ROM_API_TREE + ROM_FLASH_DRIVER)?;
+    let config = SCRATCH_CONFIG;
+
+    session.call(driver, "FLASH_Init", FLASH_INIT, &[config])?;
+    session.call(driver, "FFR_Init", FFR_INIT, &[config])?;
+
+    match page {
+        Page::Cmpa => session.call(
+            driver,
+            "FFR_CustFactoryPageWrite",
+            FFR_CUST_FACTORY_PAGE_WRITE,
+            &[config, SCRATCH_PAGE, 0],
+        )?,
+        Page::Cfpa => session.call(
+            driver,
+            "FFR_InfieldPageWrite",
+            FFR_INFIELD_PAGE_WRITE,
+            &[config, SCRATCH_PAGE, PAGE_SIZE as u32],
+        )?,
+    }
+
+    session.finish()
+}
+
+fn lpc55pfr_read(
+    core: &mut dyn Core,
+    page: Option<Page>,
+    raw: bool,
+) -> Result<()> {
+    match rom_version(core) {
+        Ok(version) => println!("ROM bootloader {version}"),
+        Err(e) => msg!("failed to read ROM version: {e}"),
+    }
+
+    if page != Some(Page::Cfpa) {
+        let data = read_page(core, CMPA)?;
+        println!("CMPA at 0x{CMPA:08x}:");
+        print_page(Page::Cmpa, &data, raw, CMPA);
+    }
+
+    if page != Some(Page::Cmpa) {
+        let (addr, data, other) = read_cfpa(core)?;
+        let which = if addr == CFPA_PING { "ping" } else { "pong" };
+        let version = cfpa_version(&data);
+
+        print!("CFPA at 0x{addr:08x} ({which}, version {version}");
+
+        match other {
+            Some(v) => {
+                let other = if addr == CFPA_PING { "pong" } else { "ping" };
+                println!("; {other} is version {v}):");
+            }
+            None => println!("):"),
+        }
+
+        print_page(Page::Cfpa, &data, raw, addr);
+    }
+
+    Ok(())
+}
+
+fn lpc55pfr_write(
+    core: &mut dyn Core,
+    page: Page,
+    set: &[String],
+    file: Option<&str>,
+    commit: bool,
+) -> Result<()> {
+    let (addr, old) = match page {
+        Page::Cmpa => (CMPA, read_page(core, CMPA)?),
+        Page::Cfpa => {
+            let (addr, data, _) = read_cfpa(core)?;
+            (addr, data)
+        }
+    };
+
+    let mut new = match file {
+        Some(file) => {
+            let data = std::fs::read(file)
+                .with_context(|| format!("failed to read {file}"))?;
+
+            if data.len() != PAGE_SIZE {
+                bail!("{file} is {} bytes; expected {PAGE_SIZE}", data.len());
+            }
+
+            data
+        }
+        None => old.clone(),
+    };
+
+    if file.is_none() && set.is_empty() {
+        bail!("must specify --set and/or --file");
+    }
+
+    for setting in set {
+        apply(page, &mut new, setting)?;
+    }
+
+    match page {
+        Page::Cmpa => {
+            let digest = CMPA_DIGEST..CMPA_DIGEST + 32;
+
+            if old[digest.clone()].iter().any(|&b| b != 0) {
+                bail!("CMPA is sealed and cannot be rewritten");
+            }
+
+            if new[digest].iter().any(|&b| b != 0) {
+                bail!("refusing to seal CMPA (SHA256_DIGEST must be zero)");
+            }
+        }
+        Page::Cfpa => {
+            if cfpa_version(&new) <= cfpa_version(&old) {
+                let version = cfpa_version(&old) + 1;
+                new[CFPA_VERSION..CFPA_VERSION + 4]
+                    .copy_from_slice(&version.to_le_bytes());
+            }
+        }
+    }
+
+    if !print_diff(page, &old, &new) {
+        msg!("no changes to {}", page.name());
+        return Ok(());
+    }
+
+    if !commit {
+        msg!("dry run; specify --commit to write {}", page.name());
+        return Ok(());
+    }
+
+    if !humility_cmd::confirm(&format!("write {}?", page.name()))? {
+        msg!("{} not written", page.name());
+        return Ok(());
+    }
+
+    rom_write(core, page, &new)?;
+
+    //
+    // Read back what we wrote.  (For the CFPA, the ROM will have written
+    // the inactive page, which is now active.)
+    //
+    let (addr, readback) = match page {
+        Page::Cmpa => (addr, read_page(core, CMPA)?),
+        Page::Cfpa => {
+            let (addr, data, _) = read_cfpa(core)?;
+            (addr, data)
+        }
+    };
+
+    if readback != new {
+        print_diff(page, &new, &readback);
+        bail!(
+            "{} at 0x{addr:08x} does not match what was written",
+            page.name()
+        );
+    }
+
+    msg!("wrote {} at 0x{addr:08x}; target has been reset", page.name());
+
+    Ok(())
+}
+
+fn lpc55pfr(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+
+    let subargs = Lpc55PfrArgs::try_parse_from(subargs)?;
+
+    if let Some(chip) = hubris.chip() {
+        if !chip.starts_with("LPC55") {
+            bail!("lpc55pfr is not supported on {chip}");
+        }
+    }
+
+    match subargs {
+        Lpc55PfrArgs::Read { page, raw } => lpc55pfr_read(core, page, raw),
+        Lpc55PfrArgs::Write { page, set, file, commit } => {
+            lpc55pfr_write(core, page, &set, file.as_deref(), commit)
+        }
+    }
+}
+
+pub fn init() -> Command {
+    Command {
+        app: Lpc55PfrArgs::command(),
+        name: "lpc55pfr",
+        run: lpc55pfr,
+        kind: CommandKind::Attached {
+            archive: Archive::Optional,
+            attach: Attach::LiveOnly,
+            validate: Validate::None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reg(page: Page, name: &str) -> &'static Register {
+        page.registers().iter().find(|r| r.name == name).unwrap()
+    }
+
+    #[test]
+    fn field_decode() {
+        let boot = reg(Page::Cmpa, "BOOT_CFG");
+        let mode = boot.lookup_field("default_isp_mode").unwrap();
+
+        assert_eq!(mode.mask(), 0x70);
+        assert_eq!(mode.get(0x0000_0020), 2);
+        assert_eq!(mode.format(0x0000_0020), "0x2 (UART)");
+        assert_eq!(mode.format(0x0000_0060), "0x6");
+
+        let pin = boot.lookup_field("BOOT_FAILURE_PIN").unwrap();
+        assert_eq!(pin.mask(), 0xff00_0000);
+        assert_eq!(pin.get(0xab00_0000), 0xab);
+
+        assert!(boot.lookup_field("NO_SUCH_FIELD").is_none());
+    }
+
+    #[test]
+    fn register_format() {
+        let mut data = vec![0u8; PAGE_SIZE];
+
+        let version = reg(Page::Cfpa, "VERSION");
+        data[CFPA_VERSION..CFPA_VERSION + 4]
+            .copy_from_slice(&0x1234u32.to_le_bytes());
+        assert_eq!(version.format(&data), "0x00001234");
+        assert_eq!(cfpa_version(&data), 0x1234);
+
+        let rotkh = reg(Page::Cmpa, "ROTKH");
+        assert_eq!(rotkh.format(&data), "(all zeroes)");
+        data[0x50] = 0xde;
+        data[0x6f] = 0xad;
+        assert_eq!(rotkh.format(&data), format!("de{}ad", "00".repeat(30)));
+
+        let custom = reg(Page::Cmpa, "CUSTOMER_DEFINED");
+        data[0x100] = 1;
+        data[0x180] = 2;
+        assert_eq!(custom.format(&data), "(224 bytes, 2 non-zero)");
+    }
+
+    #[test]
+    fn apply_word() {
+        let mut data = vec![0u8; PAGE_SIZE];
+
+        apply(Page::Cfpa, &mut data, "version=0x10").unwrap();
+        assert_eq!(cfpa_version(&data), 0x10);
+
+        apply(Page::Cfpa, &mut data, "VERSION=17").unwrap();
+        assert_eq!(cfpa_version(&data), 17);
+    }
+
+    #[test]
+    fn apply_field() {
+        let mut data = vec![0u8; PAGE_SIZE];
+        let boot = reg(Page::Cmpa, "BOOT_CFG");
+
+        apply(Page::Cmpa, &mut data, "BOOT_CFG=0xff000000").unwrap();
+        apply(Page::Cmpa, &mut data, "boot_cfg.default_isp_mode=7").unwrap();
+        assert_eq!(boot.value(&data), 0xff00_0070);
+
+        apply(Page::Cmpa, &mut data, "BOOT_CFG.BOOT_SPEED=1").unwrap();
+        assert_eq!(boot.value(&data), 0xff00_00f0);
+    }
+
+    #[test]
+    fn apply_inverse() {
+        let mut data = vec![0u8; PAGE_SIZE];
+        let pin = reg(Page::Cmpa, "CC_SOCU_PIN");
+
+        apply(Page::Cmpa, &mut data, "CC_SOCU_PIN.DBGEN=1").unwrap();
+        assert_eq!(pin.value(&data), 0xfffd_0002);
+
+        apply(Page::Cmpa, &mut data, "CC_SOCU_PIN.NIDEN=1").unwrap();
+        assert_eq!(pin.value(&data), 0xfffc_0003);
+
+        //
+        // Setting the inverse value directly (or the whole word) must be
+        // left alone.
+        //
+        apply(Page::Cmpa, &mut data, "CC_SOCU_PIN.INVERSE_VALUE=0").unwrap();
+        assert_eq!(pin.value(&data), 0x0000_0003);
+
+        apply(Page::Cmpa, &mut data, "CC_SOCU_PIN=0x12345678").unwrap();
+        assert_eq!(pin.value(&data), 0x1234_5678);
+    }
+
+    #[test]
+    fn apply_bytes() {
+        let mut data = vec![0u8; PAGE_SIZE];
+        let hash = "00112233445566778899aabbccddeeff".repeat(2);
+
+        apply(Page::Cmpa, &mut data, &format!("ROTKH=0x{hash}")).unwrap();
+        assert_eq!(data[0x50], 0x00);
+        assert_eq!(data[0x51], 0x11);
+        assert_eq!(data[0x6f], 0xff);
+        assert_eq!(reg(Page::Cmpa, "ROTKH").format(&data), hash);
+    }
+
+    #[test]
+    fn apply_invalid() {
+        let mut data = vec![0u8; PAGE_SIZE];
+
+        for setting in [
+            "BOOT_CFG",
+            "NO_SUCH_REGISTER=1",
+            "BOOT_CFG.NO_SUCH_FIELD=1",
+            "BOOT_CFG.DEFAULT_ISP_MODE=8",
+            "BOOT_CFG=banana",
+            "ROTKH.FIELD=00",
+            "ROTKH=0011",
+            &format!("ROTKH={}", "zz".repeat(32)),
+        ] {
+            assert!(
+                apply(Page::Cmpa, &mut data, setting).is_err(),
+                "{setting}"
+            );
+        }
+
+        assert!(data.iter().all(|&b| b == 0));
+
+        //
+        // CMPA registers are not CFPA registers.
+        //
+        assert!(apply(Page::Cfpa, &mut data, "BOOT_CFG=1").is_err());
+    }
+
+    #[test]
+    fn diff() {
+        let old = vec![0u8; PAGE_SIZE];
+        let mut new = old.clone();
+
+        assert!(!print_diff(Page::Cmpa, &old, &new));
+
+        apply(Page::Cmpa, &mut new, "BOOT_CFG.BOOT_SPEED=2").unwrap();
+        assert!(print_diff(Page::Cmpa, &old, &new));
+
+        //
+        // A change outside of any register is still a difference.
+        //
+        let mut new = old.clone();
+        new[0x40] = 1;
+        assert!(print_diff(Page::Cmpa, &old, &new));
+    }
+
+    #[test]
+    fn registers_in_bounds() {
+        for page in [Page::Cmpa, Page::Cfpa] {
+            for reg in page.registers() {
+                assert!(reg.offset + reg.len <= PAGE_SIZE, "{}", reg.name);
+
+                for f in reg.fields {
+                    assert!(f.lo <= f.hi && f.hi < 32, "{}", f.name);
+                }
+            }
+        }
+
+        assert_eq!(reg(Page::Cmpa, "SHA256_DIGEST").offset, CMPA_DIGEST);
+    }
+}
@@ -40,20 +40,227 @@
 //! ```
 //!
 //! `humility manifest` can operate on either an archive or on a dump.
+//!
+//! To extract structured facts from an archive (e.g., from build tooling),
+//! use `--query` (`-q`) with a selector.  A selector is a sequence of
+//! members separated by `.`; a member that is an array can be followed by
+//! `[]` to select from each of its elements, or by an index (e.g., `[3]`)
+//! to select a single element.  The result is always displayed as JSON:
+//!
+//! ```console
+//! $ humility manifest --query tasks[].priority
+//! [0,1,2,2,3,2,2,2,2,3,4,5]
+//! $ humility manifest --query peripherals[].interrupts
+//! [{"irq":31},{"irq":33},{},{"irq":51}]
+//! $ humility manifest --query tasks[2].irqs
+//! [{"irq":31,"notification":1},{"irq":32,"notification":1}]
+//! ```
+//!
+//! The selector `.` selects the entire document, whose members are
+//! `version`, `gitrev`, `board`, `name`, `image`, `target`, `features`,
+//! `kernel`, `tasks`, `regions`, `peripherals`, `i2c_buses`, `i2c_devices`
+//! and `sensors`.
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
+use humility::core::ArchiveCore;
 use humility::hubris::*;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Command, CommandKind};
+use serde_json::{json, Value};
 use std::collections::HashSet;
 
 #[derive(Parser, Debug)]
 #[clap(name = "manifest", about = env!("CARGO_PKG_DESCRIPTION"))]
 struct ManifestArgs {
     /// generate JSON output
-    #[clap(short, long)]
+    #[clap(short, long, conflicts_with = "query")]
     json: bool,
+
+    /// display the part of the manifest identified by a selector as JSON
+    #[clap(short, long, value_name = "selector")]
+    query: Option<String>,
+}
+
+enum Step {
+    Member(String),
+    Each,
+    Index(usize),
+}
+
+fn parse_selector(selector: &str) -> Result<Vec<Step>> {
+    let mut steps = vec![];
+
+    for segment in selector.split('.').filter(|s| !s.is_empty()) {
+        let (member, mut rest) = match segment.find('[') {
+            Some(ndx) => segment.split_at(ndx),
+            None => (segment, ""),
+        };
+
+        if !member.is_empty() {
+            steps.push(Step::Member(member.to_string()));
+        }
+
+        while !rest.is_empty() {
+            let close = match (rest.starts_with('['), rest.find(']')) {
+                (true, Some(close)) => close,
+                _ => bail!("malformed selector \"{selector}\" at \"{rest}\""),
+            };
+
+            steps.push(match &rest[1..close] {
+                "" => Step::Each,
+                ndx => match ndx.parse() {
+                    Ok(ndx) => Step::Index(ndx),
+                    Err(_) => {
+                        bail!("illegal index \"{ndx}\" in \"{selector}\"")
+                    }
+                },
+            });
+
+            rest = &rest[close + 1..];
+        }
+    }
+
+    Ok(steps)
+}
+
+fn select(value: &Value, steps: &[Step]) -> Result<Value> {
+    let (step, rest) = match steps.split_first() {
+        Some(split) => split,
+        None => return Ok(value.clone()),
+    };
+
+    match (step, value) {
+        (Step::Member(m), Value::Object(o)) => match o.get(m) {
+            Some(v) => select(v, rest),
+            None => bail!(
+                "no member \"{m}\"; expected one of: {}",
+                o.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        },
+        (Step::Each, Value::Array(a)) => Ok(Value::Array(
+            a.iter().map(|v| select(v, rest)).collect::<Result<_>>()?,
+        )),
+        (Step::Index(ndx), Value::Array(a)) => match a.get(*ndx) {
+            Some(v) => select(v, rest),
+            None => bail!("index {ndx} out of range (length is {})", a.len()),
+        },
+        (Step::Member(m), Value::Array(_)) => {
+            bail!("cannot select \"{m}\" from an array; use \"[]\"")
+        }
+        (Step::Member(m), _) => bail!("cannot select \"{m}\" from {value}"),
+        (_, _) => bail!("cannot index into {value}: not an array"),
+    }
+}
+
+///
+/// Assembles the facts about an archive into a single JSON document against
+/// which queries are made.
+///
+fn manifest_document(hubris: &HubrisArchive) -> Result<Value> {
+    let manifest = &hubris.manifest;
+    let mut tasks = vec![];
+    let mut kernel = 0;
+
+    for module in hubris.modules() {
+        let id = match module.task {
+            HubrisTask::Task(id) => id,
+            HubrisTask::Kernel => {
+                kernel = module.memsize;
+                continue;
+            }
+        };
+
+        let name = &module.name;
+        let irqs = manifest.task_irqs.get(name).map_or(vec![], |irqs| {
+            irqs.iter()
+                .map(|(n, irq)| json!({ "irq": irq, "notification": n }))
+                .collect()
+        });
+
+        tasks.push(json!({
+            "id": id,
+            "name": name,
+            "priority": manifest.task_priorities.get(name),
+            "size": module.memsize,
+            "features": manifest.task_features.get(name).unwrap_or(&vec![]),
+            "notifications":
+                manifest.task_notifications.get(name).unwrap_or(&vec![]),
+            "irqs": irqs,
+        }));
+    }
+
+    //
+    // The memory map comes from the kernel's task table, which is in flash
+    // and can therefore be read from the archive itself.
+    //
+    let mut core = ArchiveCore::new(hubris)?;
+    let mut regions = vec![];
+
+    for region in hubris.regions(&mut core)?.values() {
+        let attr = &region.attr;
+        let attributes = [
+            ("read", attr.read),
+            ("write", attr.write),
+            ("execute", attr.execute),
+            ("device", attr.device),
+            ("dma", attr.dma),
+            ("external", attr.external),
+        ];
+
+        let tasks = region
+            .tasks
+            .iter()
+            .map(|t| match t {
+                HubrisTask::Kernel => Ok("kernel".to_string()),
+                _ => Ok(hubris.lookup_module(*t)?.name.clone()),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        regions.push(json!({
+            "base": region.base,
+            "size": region.size,
+            "attributes": attributes
+                .iter()
+                .filter(|(_, set)| *set)
+                .map(|(a, _)| a)
+                .collect::<Vec<_>>(),
+            "tasks": tasks,
+        }));
+    }
+
+    let peripherals = manifest
+        .peripherals
+        .iter()
+        .map(|(name, address)| {
+            json!({
+                "name": name,
+                "address": address,
+                "interrupts": manifest
+                    .peripheral_irqs
+                    .get(name)
+                    .cloned()
+                    .unwrap_or_default(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(json!({
+        "version": manifest.version,
+        "gitrev": manifest.gitrev,
+        "board": manifest.board,
+        "name": manifest.name,
+        "image": manifest.image,
+        "target": manifest.target,
+        "features": manifest.features,
+        "kernel": { "size": kernel },
+        "tasks": tasks,
+        "regions": regions,
+        "peripherals": peripherals,
+        "i2c_buses": manifest.i2c_buses,
+        "i2c_devices": manifest.i2c_devices,
+        "sensors": manifest.sensors,
+    }))
 }
 
 #[allow(clippy::print_literal)]
@@ -69,6 +276,13 @@ fn manifestcmd(context: &mut ExecutionContext) -> Result<()> {
         return Ok(());
     }
 
+    if let Some(selector) = &subargs.query {
+        let steps = parse_selector(selector)?;
+        let document = manifest_document(hubris)?;
+        println!("{}", serde_json::to_string(&select(&document, &steps)?)?);
+        return Ok(());
+    }
+
     let print = |what, val| {
         println!("{:>12} => {}", what, val);
     };
@@ -95,6 +309,23 @@ fn manifestcmd(context: &mut ExecutionContext) -> Result<()> {
     print("target", manifest.target.as_deref().unwrap_or("<unknown>"));
     print("features", &manifest.features.join(", "));
 
+    if let Some(metadata) = hubris.dump_metadata() {
+        println!("{:>12} => {} (Unix time)", "dump time", metadata.time);
+
+        if let Some(probe) = &metadata.probe {
+            match &metadata.serial {
+                Some(serial) => {
+                    print("dumped via", &format!("{probe} ({serial})"))
+                }
+                None => print("dumped via", probe),
+            }
+        }
+
+        if let Some(comment) = &metadata.comment {
+            print("comment", comment);
+        }
+    }
+
     let ttl = hubris.modules().fold(0, |ttl, m| ttl + m.memsize);
 
     println!("{:>12} => {}K", "total size", ttl / 1024);
@@ -60,41 +60,217 @@
 //!
 //! (In this case, task 7, `oh_no`, has overflowed its stack -- which
 //! we can see from the `map` output has been sized to only 256 bytes.)
+//!
+//! To see how much of the memory mapped into each task is actually in use,
+//! run `humility map --usage` (`-u`).  For flash, this is the part of each
+//! region loaded from the task's ELF object; for RAM, it is the task's
+//! static data plus the high-water mark of its stack (determined as with
+//! `humility stackmargin`).  Both are shown relative to the memory
+//! allocated to the task, along with the stack's maximum depth and size:
+//!
+//! ```console
+//! $ humility -a ~/hubris/target/demo/dist/build-demo.zip map --usage
+//! humility: attached via OpenOCD
+//! ID TASK                 FLASH   ALLOC   USED     RAM   ALLOC   USED   STACK    SIZE
+//!  0 jefe                 14716   32768  44.9%     800    1024  78.1%     608     768
+//!  1 rcc_driver            3924    8192  47.9%     192    1024  18.8%     176     512
+//!  2 usart_driver          6208   16384  37.9%     288    1024  28.1%     216     512
+//!  3 user_leds             4412   16384  26.9%     216    1024  21.1%     208     512
+//!  4 ping                  3276    8192  40.0%     264     512  51.6%     224     384
+//!  5 pong                  2912    8192  35.5%     220    1024  21.5%     208     512
+//!  6 idle                   128     256  50.0%     104     256  40.6%     104     224
+//!  7 oh_no                 2848    8192  34.8%     256     256 100.0%     240     240
+//!  8 oh_no2                2848    8192  34.8%     112     256  43.8%      96     240
+//! ```
+//!
+//! (Here too, we can see that `oh_no` has exhausted its RAM.)  RAM usage
+//! can only be determined for tasks whose stacks can be read, and is shown
+//! as `-` otherwise -- e.g., for tasks other than the dumped task in a
+//! single-task dump.
+//!
+//! To visualize the address space, use `--render` (`-r`) to render the map
+//! to a file as either SVG or HTML, as determined by the file's extension.
+//! Each 256 MiB segment of the address space that contains regions is
+//! drawn as a bar, with each region shaded by task and the portion of it
+//! in use shaded darker; hovering over a region shows its addresses,
+//! attributes and utilization.
+//! The HTML additionally includes a table of utilization by task:
+//!
+//! ```console
+//! $ humility -a ~/hubris/target/demo/dist/build-demo.zip map -r map.html
+//! humility: attached via OpenOCD
+//! humility: rendered map to map.html
+//! ```
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
-use humility_cli::ExecutionContext;
+use humility::hubris::*;
+use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+mod render;
+mod usage;
 
 #[derive(Parser, Debug)]
 #[clap(name = "map", about = env!("CARGO_PKG_DESCRIPTION"))]
-struct MapArgs {}
+struct MapArgs {
+    /// show flash, RAM and stack utilization by task
+    #[clap(long, short)]
+    usage: bool,
+
+    /// render the map as SVG or HTML, as determined by the file extension
+    #[clap(long, short, value_name = "filename")]
+    render: Option<String>,
+}
+
+type Renderer = fn(
+    &HubrisArchive,
+    &BTreeMap<u32, HubrisRegion>,
+    &usage::Usage,
+) -> Result<String>;
+
+///
+/// Returns the name of the task (or tasks) to which a region is mapped,
+/// along with the name of the peripheral or external region, if any.
+///
+pub(crate) fn region_name(
+    hubris: &HubrisArchive,
+    region: &HubrisRegion,
+) -> Result<String> {
+    let name = {
+        let mut names = vec![];
+
+        for t in &region.tasks {
+            names.push(hubris.lookup_module(*t)?.name.clone());
+        }
+
+        names.join(", ")
+    };
+
+    Ok(if region.attr.device {
+        if let Some(p) = hubris.lookup_peripheral_byaddr(region.base) {
+            format!("[{}] {}", p, name)
+        } else {
+            format!("[??] {}", name)
+        }
+    } else if region.attr.external {
+        if let Some(e) = hubris.lookup_external_byaddr(region.base) {
+            format!("[{}] {}", e, name)
+        } else {
+            format!("[??] {}", name)
+        }
+    } else {
+        name
+    })
+}
+
+fn usage_print(
+    hubris: &HubrisArchive,
+    regions: &BTreeMap<u32, HubrisRegion>,
+    usage: &usage::Usage,
+) -> Result<()> {
+    let used = |used: Option<u32>| match used {
+        Some(used) => format!("{}", used),
+        None => "-".to_string(),
+    };
+
+    let percent = |u: &usage::Utilization| match u.percent() {
+        Some(percent) => format!("{:.1}%", percent),
+        None => "-".to_string(),
+    };
+
+    println!(
+        "{:2} {:18} {:>7} {:>7} {:>6} {:>7} {:>7} {:>6} {:>7} {:>7}",
+        "ID",
+        "TASK",
+        "FLASH",
+        "ALLOC",
+        "USED",
+        "RAM",
+        "ALLOC",
+        "USED",
+        "STACK",
+        "SIZE"
+    );
+
+    for i in 0..hubris.ntasks() {
+        let task = HubrisTask::Task(i as u32);
+        let module = hubris.lookup_module(task)?;
+        let u = usage.task(regions, task);
+
+        println!(
+            "{:2} {:18} {:>7} {:>7} {:>6} {:>7} {:>7} {:>6} {:>7} {:>7}",
+            i,
+            module.name,
+            used(u.flash.used),
+            u.flash.allocated,
+            percent(&u.flash),
+            used(u.ram.used),
+            u.ram.allocated,
+            percent(&u.ram),
+            used(u.stack.map(|s| s.depth)),
+            used(u.stack.map(|s| s.size)),
+        );
+    }
+
+    Ok(())
+}
 
 fn mapcmd(context: &mut ExecutionContext) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = MapArgs::try_parse_from(subargs)?;
+
+    let render = match &subargs.render {
+        Some(filename) => {
+            let ext = Path::new(filename).extension().and_then(|e| e.to_str());
+
+            let renderer: Renderer = match ext {
+                Some("svg") => render::svg,
+                Some("html") | Some("htm") => render::html,
+                _ => bail!("rendered map must be either .svg or .html"),
+            };
+
+            Some((filename, renderer))
+        }
+        None => None,
+    };
+
     let core = &mut **context.core.as_mut().unwrap();
     core.op_start()?;
 
     let hubris = context.archive.as_ref().unwrap();
 
     let regions = hubris.regions(core)?;
+
+    let usage = if subargs.usage || render.is_some() {
+        Some(usage::Usage::new(hubris, core, &regions)?)
+    } else {
+        None
+    };
+
     core.op_done()?;
 
+    if let Some(usage) = &usage {
+        if let Some((filename, renderer)) = render {
+            std::fs::write(filename, renderer(hubris, &regions, usage)?)?;
+            humility::msg!("rendered map to {filename}");
+        }
+
+        if subargs.usage {
+            usage_print(hubris, &regions, usage)?;
+        }
+
+        return Ok(());
+    }
+
     println!(
         "{:10} {:10}   {:10} {:>7} {:6} {:2} TASK",
         "DESC", "LOW", "HIGH", "SIZE", "ATTR", "ID",
     );
 
     for (_, region) in regions.iter() {
-        let name = {
-            let mut names = vec![];
-
-            for t in &region.tasks {
-                names.push(hubris.lookup_module(*t)?.name.clone());
-            }
-
-            names.join(", ")
-        };
-
         println!(
             "{:10} 0x{:08x} - 0x{:08x} {:>7} {}{}{}{}{}{} {:2} {}",
             match region.daddr {
@@ -115,21 +291,7 @@ fn mapcmd(context: &mut ExecutionContext) -> Result<()> {
             if region.attr.dma { "m" } else { "-" },
             if region.attr.external { "e" } else { "-" },
             region.tasks[0].id(),
-            if region.attr.device {
-                if let Some(p) = hubris.lookup_peripheral_byaddr(region.base) {
-                    format!("[{}] {}", p, name)
-                } else {
-                    format!("[??] {}", name)
-                }
-            } else if region.attr.external {
-                if let Some(e) = hubris.lookup_external_byaddr(region.base) {
-                    format!("[{}] {}", e, name)
-                } else {
-                    format!("[??] {}", name)
-                }
-            } else {
-                name.to_string()
-            }
+            region_name(hubris, region)?,
         );
     }
 
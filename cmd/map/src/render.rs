@@ -0,0 +1,308 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Rendering of the memory map as SVG (or as HTML containing the SVG along
+//! with a table of utilization by task) for `humility map --render`.  The
+//! address space is sparse, so each 256 MiB segment of it that contains
+//! regions is drawn as its own bar; within a bar, regions are drawn in
+//! address order with a width proportional to their size (but no narrower
+//! than a minimum), and gaps between them are drawn with a fixed width.
+//! Each region is shaded by task, with the portion of it that is in use
+//! shaded darker; hovering over a region displays its details.
+//!
+
+use crate::usage::{Kind, Usage, Utilization};
+use anyhow::Result;
+use humility::hubris::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const WIDTH: f64 = 1000.0;
+const MARGIN: f64 = 20.0;
+const BAR_HEIGHT: f64 = 40.0;
+const ROW_HEIGHT: f64 = 90.0;
+const GAP_WIDTH: f64 = 12.0;
+const MIN_WIDTH: f64 = 4.0;
+const FONT_WIDTH: f64 = 6.5;
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn size(n: u32) -> String {
+    if n >= 1024 && n % 1024 == 0 {
+        format!("{}KiB", n >> 10)
+    } else {
+        format!("{}", n)
+    }
+}
+
+fn hue(task: HubrisTask) -> u32 {
+    match task {
+        HubrisTask::Kernel => 0,
+        HubrisTask::Task(id) => (id * 137 + 40) % 360,
+    }
+}
+
+fn attrs(region: &HubrisRegion) -> String {
+    [
+        (region.attr.read, 'r'),
+        (region.attr.write, 'w'),
+        (region.attr.execute, 'x'),
+        (region.attr.device, 'd'),
+        (region.attr.dma, 'm'),
+        (region.attr.external, 'e'),
+    ]
+    .iter()
+    .map(|&(set, c)| if set { c } else { '-' })
+    .collect()
+}
+
+fn rect(
+    out: &mut String,
+    (x, y, w): (f64, f64, f64),
+    attrs: &str,
+) -> Result<()> {
+    writeln!(
+        out,
+        "<rect x=\"{x:.1}\" y=\"{y:.1}\" width=\"{w:.1}\" \
+        height=\"{BAR_HEIGHT}\" {attrs}/>"
+    )?;
+
+    Ok(())
+}
+
+fn region(
+    out: &mut String,
+    hubris: &HubrisArchive,
+    region: &HubrisRegion,
+    used: Option<u32>,
+    (x, y, w): (f64, f64, f64),
+) -> Result<()> {
+    let name = crate::region_name(hubris, region)?;
+    let kind = Kind::of(region);
+
+    let (fill, usedfill) = match kind {
+        Kind::Other => ("#d8d8d8".to_string(), "#d8d8d8".to_string()),
+        _ => {
+            let h = hue(region.tasks[0]);
+            (format!("hsl({h}, 55%, 88%)"), format!("hsl({h}, 55%, 60%)"))
+        }
+    };
+
+    let mut title = format!(
+        "{}\n0x{:08x} - 0x{:08x} ({}) {}",
+        name,
+        region.base,
+        region.base + region.size - 1,
+        size(region.size),
+        attrs(region)
+    );
+
+    if let Some(used) = used {
+        write!(
+            title,
+            "\n{} of {} bytes used ({:.1}%)",
+            used,
+            region.size,
+            used as f64 * 100.0 / region.size as f64
+        )?;
+    }
+
+    writeln!(out, "<g>")?;
+    writeln!(out, "<title>{}</title>", escape(&title))?;
+    rect(out, (x, y, w), &format!("fill=\"{fill}\""))?;
+
+    if let Some(used) = used {
+        let uw = w * (used.min(region.size) as f64 / region.size as f64);
+        rect(out, (x, y, uw), &format!("fill=\"{usedfill}\""))?;
+    }
+
+    rect(
+        out,
+        (x, y, w),
+        "fill=\"none\" stroke=\"#404040\" stroke-width=\"0.5\"",
+    )?;
+
+    if w >= (name.len() as f64 + 1.0) * FONT_WIDTH {
+        writeln!(
+            out,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+            x + w / 2.0,
+            y + BAR_HEIGHT / 2.0 + 4.0,
+            escape(&name)
+        )?;
+    }
+
+    writeln!(out, "</g>")?;
+
+    Ok(())
+}
+
+///
+/// Renders the regions as an SVG image.
+///
+pub fn svg(
+    hubris: &HubrisArchive,
+    regions: &BTreeMap<u32, HubrisRegion>,
+    usage: &Usage,
+) -> Result<String> {
+    let mut segments: BTreeMap<u32, Vec<&HubrisRegion>> = BTreeMap::new();
+
+    for region in regions.values() {
+        segments.entry(region.base >> 28).or_default().push(region);
+    }
+
+    let mut body = String::new();
+    let mut width = WIDTH;
+    let mut y = MARGIN;
+
+    for segment in segments.values() {
+        let total: u64 = segment.iter().map(|r| r.size as u64).sum();
+        let scale = WIDTH / total as f64;
+        let first = segment[0];
+        let last = segment[segment.len() - 1];
+
+        writeln!(
+            body,
+            "<text x=\"{MARGIN}\" y=\"{:.1}\" class=\"segment\">\
+            0x{:08x} - 0x{:08x}</text>",
+            y + 14.0,
+            first.base,
+            last.base + last.size - 1,
+        )?;
+
+        let mut x = MARGIN;
+        let mut next = None;
+
+        for r in segment {
+            if next.map(|next| next != r.base).unwrap_or(false) {
+                writeln!(
+                    body,
+                    "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" \
+                    y2=\"{:.1}\" stroke=\"#808080\" \
+                    stroke-dasharray=\"2,2\"/>",
+                    x + 2.0,
+                    y + 24.0 + BAR_HEIGHT / 2.0,
+                    x + GAP_WIDTH - 2.0,
+                    y + 24.0 + BAR_HEIGHT / 2.0,
+                )?;
+
+                x += GAP_WIDTH;
+            }
+
+            let w = (r.size as f64 * scale).max(MIN_WIDTH);
+            let used = usage.regions.get(&r.base).copied().flatten();
+
+            region(&mut body, hubris, r, used, (x, y + 24.0, w))?;
+
+            x += w;
+            next = Some(r.base + r.size);
+        }
+
+        width = width.max(x - MARGIN);
+        y += ROW_HEIGHT;
+    }
+
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" \
+        height=\"{:.0}\" font-family=\"monospace\" font-size=\"11\">",
+        width + MARGIN * 2.0,
+        y - ROW_HEIGHT + BAR_HEIGHT + 24.0 + MARGIN,
+    )?;
+    writeln!(out, "<style>.segment {{ font-weight: bold; }}</style>")?;
+    out.push_str(&body);
+    writeln!(out, "</svg>")?;
+
+    Ok(out)
+}
+
+fn cell(u: &Utilization) -> String {
+    match (u.used, u.percent()) {
+        (Some(used), Some(percent)) => {
+            format!("{} / {} ({:.1}%)", used, u.allocated, percent)
+        }
+        _ => format!("- / {}", u.allocated),
+    }
+}
+
+///
+/// Renders the regions as an HTML page containing the SVG image, followed
+/// by a table of utilization by task.
+///
+pub fn html(
+    hubris: &HubrisArchive,
+    regions: &BTreeMap<u32, HubrisRegion>,
+    usage: &Usage,
+) -> Result<String> {
+    let image = match (&hubris.manifest.name, &hubris.manifest.gitrev) {
+        (Some(name), Some(gitrev)) => format!("{name} ({gitrev})"),
+        (Some(name), None) => name.clone(),
+        _ => "archive".to_string(),
+    };
+
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "\
+<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Memory map of {image}</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; font-family: monospace; }}
+th, td {{ border: 1px solid #c0c0c0; padding: 2px 8px; text-align: right; }}
+td.task {{ text-align: left; }}
+</style>
+</head>
+<body>
+<h1>Memory map of {image}</h1>",
+        image = escape(&image)
+    )?;
+
+    out.push_str(&svg(hubris, regions, usage)?);
+
+    writeln!(out, "<h2>Utilization by task</h2>")?;
+    writeln!(out, "<table>")?;
+    writeln!(
+        out,
+        "<tr><th>ID</th><th>TASK</th><th>FLASH</th><th>RAM</th>\
+        <th>STACK</th></tr>"
+    )?;
+
+    for i in 0..hubris.ntasks() {
+        let task = HubrisTask::Task(i as u32);
+        let module = hubris.lookup_module(task)?;
+        let u = usage.task(regions, task);
+
+        let stack = match u.stack {
+            Some(s) => format!("{} / {}", s.depth, s.size),
+            None => "-".to_string(),
+        };
+
+        writeln!(
+            out,
+            "<tr><td>{}</td><td class=\"task\">{}</td><td>{}</td>\
+            <td>{}</td><td>{}</td></tr>",
+            i,
+            escape(&module.name),
+            cell(&u.flash),
+            cell(&u.ram),
+            stack
+        )?;
+    }
+
+    writeln!(out, "</table>")?;
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")?;
+
+    Ok(out)
+}
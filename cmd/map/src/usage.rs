@@ -0,0 +1,230 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Utilization of mapped memory for `humility map --usage` and `humility
+//! map --render`.  The flash used in a region is the extent of it that was
+//! loaded from the task's ELF object.  The RAM used in a region is the
+//! static data (`.data`, `.bss` and the like) loaded into it, plus -- for
+//! the region containing the task's stack -- the stack's high-water mark,
+//! as determined by [`stack_depth`] (as `humility stackmargin` does).
+//!
+
+use anyhow::Result;
+use humility::core::Core;
+use humility::hubris::*;
+use std::collections::BTreeMap;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Flash,
+    Ram,
+    Other,
+}
+
+impl Kind {
+    pub fn of(region: &HubrisRegion) -> Self {
+        if region.attr.device || region.attr.external {
+            Kind::Other
+        } else if region.attr.write {
+            Kind::Ram
+        } else {
+            Kind::Flash
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Stack {
+    /// Base (lowest address) of the stack
+    pub base: u32,
+
+    /// Size of the stack, from its base to the initial stack pointer
+    pub size: u32,
+
+    /// Maximum depth that the stack has reached
+    pub depth: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Utilization {
+    /// Bytes used, or `None` if unknown
+    pub used: Option<u32>,
+    pub allocated: u32,
+}
+
+impl Default for Utilization {
+    fn default() -> Self {
+        Self { used: Some(0), allocated: 0 }
+    }
+}
+
+impl Utilization {
+    fn add(&mut self, used: Option<u32>, allocated: u32) {
+        self.used = self.used.zip(used).map(|(u, used)| u + used);
+        self.allocated += allocated;
+    }
+
+    pub fn percent(&self) -> Option<f64> {
+        match self.used {
+            Some(used) if self.allocated != 0 => {
+                Some(used as f64 * 100.0 / self.allocated as f64)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TaskUsage {
+    pub flash: Utilization,
+    pub ram: Utilization,
+    pub stack: Option<Stack>,
+}
+
+#[derive(Debug)]
+pub struct Usage {
+    /// Bytes used in each region, by base address; `None` if unknown or if
+    /// the region isn't memory
+    pub regions: BTreeMap<u32, Option<u32>>,
+
+    /// Stack of each task whose stack could be read
+    pub stacks: BTreeMap<HubrisTask, Stack>,
+}
+
+///
+/// Reads the stack of each task.  A task whose stack can't be read (e.g.,
+/// because it isn't in a single-task dump, or because it's the supervisor
+/// and we are attached over the network) is omitted.
+///
+fn stacks(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    regions: &BTreeMap<u32, HubrisRegion>,
+) -> Result<BTreeMap<HubrisTask, Stack>> {
+    let (base, ntasks) = hubris.task_table(core)?;
+    let task = hubris.lookup_struct_byname("Task")?;
+    let taskdesc = hubris.lookup_struct_byname("TaskDesc")?;
+
+    let descriptor = task.lookup_member("descriptor")?.offset as u32;
+    let initial_stack = taskdesc.lookup_member("initial_stack")?.offset as u32;
+
+    let mut stacks = BTreeMap::new();
+
+    for i in 0..ntasks {
+        let t = HubrisTask::Task(i);
+
+        match hubris.task_dump() {
+            Some(dumped) if dumped != t => continue,
+            _ => {}
+        }
+
+        if core.is_net() && i == 0 {
+            continue;
+        }
+
+        let daddr =
+            core.read_word_32(base + i * task.size as u32 + descriptor)?;
+        let initial = core.read_word_32(daddr + initial_stack)?;
+
+        let Some(region) = regions.values().find(|r| {
+            r.tasks.contains(&t)
+                && initial > r.base
+                && initial <= r.base + r.size
+        }) else {
+            continue;
+        };
+
+        let size = initial - region.base;
+        let depth = stack_depth(core, region.base, initial)?;
+
+        stacks.insert(t, Stack { base: region.base, size, depth });
+    }
+
+    Ok(stacks)
+}
+
+impl Usage {
+    pub fn new(
+        hubris: &HubrisArchive,
+        core: &mut dyn Core,
+        regions: &BTreeMap<u32, HubrisRegion>,
+    ) -> Result<Self> {
+        let stacks = stacks(hubris, core, regions)?;
+
+        //
+        // Returns the bytes between lo and hi that were loaded into RAM for
+        // any of the specified tasks.
+        //
+        let statics = |tasks: &[HubrisTask], lo: u32, hi: u32| {
+            hubris
+                .loaded_regions()
+                .filter(|l| l.attr.write && tasks.contains(&l.tasks[0]))
+                .map(|l| hi.min(l.base + l.size).saturating_sub(lo.max(l.base)))
+                .sum::<u32>()
+        };
+
+        let mut usage = BTreeMap::new();
+
+        for (&base, region) in regions {
+            let end = region.base + region.size;
+            let tasks = region.tasks.as_slice();
+
+            let used = match Kind::of(region) {
+                Kind::Flash => Some(region.mapsize),
+                Kind::Other => None,
+                Kind::Ram if tasks.contains(&HubrisTask::Kernel) => None,
+                Kind::Ram => {
+                    //
+                    // We can only account for a task's RAM if we know where
+                    // its stack is: a stack is generally loaded as part of
+                    // the task's RAM, but is only in use to its depth.
+                    //
+                    let stack = tasks
+                        .iter()
+                        .map(|t| stacks.get(t))
+                        .collect::<Option<Vec<_>>>()
+                        .map(|s| s.into_iter().find(|s| s.base == base));
+
+                    match stack {
+                        None => None,
+                        Some(Some(stack)) => Some(
+                            stack.depth
+                                + statics(tasks, base + stack.size, end),
+                        ),
+                        Some(None) => Some(statics(tasks, base, end)),
+                    }
+                }
+            };
+
+            usage.insert(base, used);
+        }
+
+        Ok(Self { regions: usage, stacks })
+    }
+
+    /// Returns the utilization of flash, RAM and stack by the given task
+    pub fn task(
+        &self,
+        regions: &BTreeMap<u32, HubrisRegion>,
+        task: HubrisTask,
+    ) -> TaskUsage {
+        let mut rval = TaskUsage {
+            stack: self.stacks.get(&task).copied(),
+            ..Default::default()
+        };
+
+        for region in regions.values().filter(|r| r.tasks.contains(&task)) {
+            let used = self.regions.get(&region.base).copied().flatten();
+
+            match Kind::of(region) {
+                Kind::Flash => rval.flash.add(used, region.size),
+                Kind::Ram => rval.ram.add(used, region.size),
+                Kind::Other => {}
+            }
+        }
+
+        rval
+    }
+}
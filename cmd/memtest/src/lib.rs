@@ -0,0 +1,372 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility memtest`
+//!
+//! `humility memtest` runs pattern-based tests over a region of target RAM
+//! via the debug probe, reporting each word that fails along with the bits
+//! that differ.  This is useful for qualifying marginal boards and for
+//! diagnosing bit flips that have been reported by ECC counters.  The
+//! patterns are solid zeros, solid ones, a checkerboard and its inverse,
+//! walking ones, and address-in-address and its inverse:
+//!
+//! ```console
+//! $ humility memtest 0x24000000 0x10000
+//! humility: attached via ST-Link V3
+//! humility: testing 0x24000000-0x2400ffff (65536 bytes)
+//! humility: zeros: ok
+//! humility: ones: ok
+//! humility: checkerboard: ok
+//! humility: inverse checkerboard: ok
+//! humility: walking ones: 1 failing word
+//! humility: address: ok
+//! humility: inverse address: ok
+//! ADDRESS     PATTERN                EXPECTED     ACTUAL   BITS
+//! 0x24003a08  walking ones         0x00000004 0x00000404   10
+//! humility: 1 failing word; bits that have failed: 10
+//! ```
+//!
+//! Because testing overwrites memory, the target is halted for the duration
+//! of the test, and the original contents of the region are restored when
+//! the test completes -- or fails, or is interrupted with ^C.  The region must fall within a writable, non-device
+//! memory region of the Hubris image (as displayed by `humility map`); to
+//! test memory outside of these regions, use `--force`.  Note that while
+//! the core is halted, DMA engines can continue to write to memory; a
+//! failure in a buffer that is the target of DMA should be treated with
+//! suspicion.
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::{Core, CORE_MAX_READSIZE};
+use humility::hubris::*;
+use humility::msg;
+use humility::Diagnostic;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Parser, Debug)]
+#[clap(name = "memtest", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct MemtestArgs {
+    /// test memory outside of the image's memory regions
+    #[clap(long, short = 'F')]
+    force: bool,
+
+    /// maximum number of failing words to display
+    #[clap(
+        long, short, default_value_t = 32, value_name = "count",
+        parse(try_from_str = parse_int::parse)
+    )]
+    max: usize,
+
+    /// address of region to test
+    #[clap(parse(try_from_str = parse_int::parse))]
+    address: u32,
+
+    /// length of region to test, in bytes
+    #[clap(parse(try_from_str = parse_int::parse))]
+    length: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Pattern {
+    Zeros,
+    Ones,
+    Checkerboard,
+    InverseCheckerboard,
+    WalkingOnes,
+    Address,
+    InverseAddress,
+}
+
+impl Pattern {
+    fn all() -> [Pattern; 7] {
+        [
+            Pattern::Zeros,
+            Pattern::Ones,
+            Pattern::Checkerboard,
+            Pattern::InverseCheckerboard,
+            Pattern::WalkingOnes,
+            Pattern::Address,
+            Pattern::InverseAddress,
+        ]
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Pattern::Zeros => "zeros",
+            Pattern::Ones => "ones",
+            Pattern::Checkerboard => "checkerboard",
+            Pattern::InverseCheckerboard => "inverse checkerboard",
+            Pattern::WalkingOnes => "walking ones",
+            Pattern::Address => "address",
+            Pattern::InverseAddress => "inverse address",
+        }
+    }
+
+    /// Returns the value of the pattern for the word at `addr`
+    fn value(&self, addr: u32) -> u32 {
+        let ndx = addr / 4;
+
+        match self {
+            Pattern::Zeros => 0,
+            Pattern::Ones => 0xffff_ffff,
+            Pattern::Checkerboard if ndx % 2 == 0 => 0x5555_5555,
+            Pattern::Checkerboard => 0xaaaa_aaaa,
+            Pattern::InverseCheckerboard if ndx % 2 == 0 => 0xaaaa_aaaa,
+            Pattern::InverseCheckerboard => 0x5555_5555,
+            Pattern::WalkingOnes => 1 << (ndx % 32),
+            Pattern::Address => addr,
+            Pattern::InverseAddress => !addr,
+        }
+    }
+}
+
+struct Failure {
+    addr: u32,
+    pattern: Pattern,
+    expected: u32,
+    actual: u32,
+}
+
+fn bits(val: u32) -> String {
+    (0..32)
+        .filter(|b| val & (1 << b) != 0)
+        .map(|b| b.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+///
+/// The saved contents of the region under test.  The target is held halted
+/// while this exists; the contents are restored and the target resumed by
+/// [`Saved::restore`], or when it is dropped on an error path.
+///
+struct Saved<'a> {
+    core: &'a mut dyn Core,
+    base: u32,
+    contents: Vec<u8>,
+    restored: bool,
+}
+
+impl<'a> Saved<'a> {
+    fn new(core: &'a mut dyn Core, base: u32, length: u32) -> Result<Self> {
+        core.halt()?;
+
+        //
+        // Until we have read the contents, there is nothing to restore --
+        // but if the read fails, we still want to resume the target.
+        //
+        let mut saved = Saved { core, base, contents: vec![], restored: false };
+        let mut contents = vec![0u8; length as usize];
+
+        for (i, chunk) in contents.chunks_mut(CORE_MAX_READSIZE).enumerate() {
+            let addr = base + (i * CORE_MAX_READSIZE) as u32;
+            saved.core.read_8(addr, chunk)?;
+        }
+
+        saved.contents = contents;
+        Ok(saved)
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        self.restored = true;
+
+        for (i, chunk) in self.contents.chunks(CORE_MAX_READSIZE).enumerate() {
+            let addr = self.base + (i * CORE_MAX_READSIZE) as u32;
+            self.core.write_8(addr, chunk)?;
+        }
+
+        self.core.run()
+    }
+}
+
+impl<'a> Drop for Saved<'a> {
+    fn drop(&mut self) {
+        if !self.restored {
+            if let Err(e) = self.restore() {
+                msg!("failed to restore 0x{:08x}: {:?}", self.base, e);
+            }
+        }
+    }
+}
+
+///
+/// Writes the pattern over the region and reads it back, returning the
+/// words that do not match.
+///
+fn test(
+    core: &mut dyn Core,
+    base: u32,
+    length: u32,
+    pattern: Pattern,
+    done: &AtomicBool,
+) -> Result<Vec<Failure>> {
+    let mut failures = vec![];
+    let mut offset = 0;
+
+    while offset < length {
+        if done.load(Ordering::SeqCst) {
+            bail!("interrupted");
+        }
+
+        let addr = base + offset;
+        let len = std::cmp::min(length - offset, CORE_MAX_READSIZE as u32);
+
+        let expected = (0..len / 4)
+            .map(|i| pattern.value(addr + i * 4))
+            .collect::<Vec<_>>();
+
+        let buf =
+            expected.iter().flat_map(|w| w.to_le_bytes()).collect::<Vec<_>>();
+
+        core.write_8(addr, &buf)?;
+
+        let mut actual = vec![0u8; buf.len()];
+        core.read_8(addr, &mut actual)?;
+
+        for (i, chunk) in actual.chunks_exact(4).enumerate() {
+            let actual = u32::from_le_bytes(chunk.try_into().unwrap());
+
+            if actual != expected[i] {
+                failures.push(Failure {
+                    addr: addr + i as u32 * 4,
+                    pattern,
+                    expected: expected[i],
+                    actual,
+                });
+            }
+        }
+
+        offset += len;
+    }
+
+    Ok(failures)
+}
+
+fn memtest(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let subargs = MemtestArgs::try_parse_from(subargs)?;
+    let (base, length) = (subargs.address, subargs.length);
+
+    if base % 4 != 0 || length % 4 != 0 {
+        bail!("address and length must be 4-byte aligned");
+    }
+
+    if length == 0 {
+        bail!("length must be non-zero");
+    }
+
+    let end = match base.checked_add(length - 1) {
+        Some(end) => end,
+        None => bail!("region extends beyond the end of the address space"),
+    };
+
+    if !subargs.force {
+        let regions = hubris.regions(core)?;
+
+        let contained = regions.values().any(|r| {
+            r.size > 0
+                && r.attr.write
+                && !r.attr.device
+                && base >= r.base
+                && end <= r.base + (r.size - 1)
+        });
+
+        if !contained {
+            bail!(Diagnostic::error(format!(
+                "0x{:08x}-0x{:08x} is not within a writable memory region \
+                of the image",
+                base, end
+            ))
+            .code("outside-image")
+            .hint("use --force to test it anyway"));
+        }
+    }
+
+    msg!("testing 0x{:08x}-0x{:08x} ({} bytes)", base, end, length);
+
+    //
+    // Save the contents of the region so we can restore them, keeping the
+    // core halted until we have done so.
+    //
+    let done = humility_cmd::interrupted()?;
+    let mut saved = Saved::new(core, base, length)?;
+    let mut failures = vec![];
+
+    for pattern in Pattern::all() {
+        let f = test(&mut *saved.core, base, length, pattern, done)?;
+
+        if f.is_empty() {
+            msg!("{}: ok", pattern.name());
+        } else {
+            msg!(
+                "{}: {} failing word{}",
+                pattern.name(),
+                f.len(),
+                if f.len() == 1 { "" } else { "s" }
+            );
+            failures.extend(f);
+        }
+    }
+
+    saved.restore()?;
+
+    if failures.is_empty() {
+        msg!("no failures");
+        return Ok(());
+    }
+
+    println!(
+        "{:11} {:20} {:>10} {:>10}   BITS",
+        "ADDRESS", "PATTERN", "EXPECTED", "ACTUAL"
+    );
+
+    for f in failures.iter().take(subargs.max) {
+        println!(
+            "0x{:08x}  {:20} 0x{:08x} 0x{:08x}   {}",
+            f.addr,
+            f.pattern.name(),
+            f.expected,
+            f.actual,
+            bits(f.expected ^ f.actual)
+        );
+    }
+
+    if failures.len() > subargs.max {
+        println!("... ({} more)", failures.len() - subargs.max);
+    }
+
+    let failed =
+        failures.iter().fold(0, |acc, f| acc | (f.expected ^ f.actual));
+    let mut words = failures.iter().map(|f| f.addr).collect::<Vec<_>>();
+    words.sort_unstable();
+    words.dedup();
+
+    msg!(
+        "{} failing word{}; bits that have failed: {}",
+        words.len(),
+        if words.len() == 1 { "" } else { "s" },
+        bits(failed)
+    );
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: MemtestArgs::command(),
+        name: "memtest",
+        run: memtest,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
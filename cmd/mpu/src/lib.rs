@@ -0,0 +1,485 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility mpu`
+//!
+//! `humility mpu` reads the configuration of the Memory Protection Unit
+//! from a live system, and maps each enabled MPU region back to the region
+//! descriptor (and task) in the archive that it corresponds to.  Because
+//! the kernel reprograms the MPU on every context switch, the MPU reflects
+//! the regions of the current task (that is, the task that most recently
+//! ran):
+//!
+//! ```console
+//! $ humility mpu
+//! humility: attached via ST-Link V3
+//! MPU: enabled, 8 regions, privileged default map enabled
+//! current task: pong
+//! RGN LOW          HIGH          SIZE PERM ATTR   TASK
+//!   0 0x08020000 - 0x08027fff   32KiB r-x  normal pong
+//!   1 0x24010000 - 0x24013fff   16KiB rw-  normal pong
+//!   2 0x40020000 - 0x400203ff    1KiB rw-  device pong, ping [gpio]
+//!   3 -
+//!   ...
+//! humility: no discrepancies found
+//! ```
+//!
+//! The permissions shown are those of unprivileged (that is, task) code.
+//! In addition to displaying the MPU configuration, the command flags any
+//! discrepancies that it finds:
+//!
+//! - An enabled MPU region that does not correspond to a region of the
+//!   current task, or whose size, permissions or attributes do not match
+//!   its region descriptor
+//!
+//! - A region of the current task that has not been programmed into the MPU
+//!
+//! - Enabled MPU regions that overlap one another
+//!
+//! - A region (of any task) that is both writable and executable, or an
+//!   executable region that does not contain its task's text
+//!
+//! - Region descriptors (of any tasks) that overlap one another
+//!
+//! If any discrepancies are found, they are displayed and the command
+//! exits with a non-zero status.
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::msg;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_cortex::debug::ARMCore;
+use humility_cortex::mpu::*;
+use humility_cortex::scs::CPUID;
+use num_traits::FromPrimitive;
+use std::collections::BTreeMap;
+
+#[derive(Parser, Debug)]
+#[clap(name = "mpu", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct MpuArgs {}
+
+///
+/// An MPU region, as decoded from either the ARMv7-M (and ARMv6-M)
+/// representation or the ARMv8-M one.  Permissions are those for
+/// unprivileged access.
+///
+#[derive(Clone, Debug)]
+struct MpuRegion {
+    region: u32,
+    enabled: bool,
+    base: u32,
+    size: u64,
+    read: bool,
+    write: bool,
+    execute: bool,
+    device: bool,
+    subregions_disabled: u32,
+}
+
+impl MpuRegion {
+    fn limit(&self) -> u64 {
+        self.base as u64 + self.size
+    }
+
+    fn overlaps(&self, other: &MpuRegion) -> bool {
+        (self.base as u64) < other.limit() && (other.base as u64) < self.limit()
+    }
+}
+
+fn mpu_region_v7(region: u32, rbar: MPU_RBAR, rasr: MPU_RASR) -> MpuRegion {
+    let (read, write) = match rasr.ap() {
+        0b010 | 0b110 | 0b111 => (true, false),
+        0b011 => (true, true),
+        _ => (false, false),
+    };
+
+    //
+    // Strongly-ordered and device memory are both indicated by TEX of 0
+    // with C clear; TEX of 0b010 with C and B clear is non-shareable device.
+    //
+    let device =
+        !rasr.c() && (rasr.tex() == 0 || (rasr.tex() == 0b010 && !rasr.b()));
+
+    MpuRegion {
+        region,
+        enabled: rasr.enable(),
+        base: rbar.addr() << 5,
+        size: 1u64 << (rasr.size() + 1),
+        read,
+        write,
+        execute: !rasr.xn(),
+        device,
+        subregions_disabled: rasr.srd(),
+    }
+}
+
+fn mpu_region_v8(
+    region: u32,
+    rbar: MPU_RBAR_V8,
+    rlar: MPU_RLAR,
+    mair: u64,
+) -> MpuRegion {
+    let (read, write) = match rbar.ap() {
+        0b01 => (true, true),
+        0b11 => (true, false),
+        _ => (false, false),
+    };
+
+    let base = rbar.base() << 5;
+    let limit = (rlar.limit() << 5) | 0x1f;
+    let attr = (mair >> (rlar.attrindx() * 8)) & 0xff;
+
+    MpuRegion {
+        region,
+        enabled: rlar.enable(),
+        base,
+        size: limit as u64 - base as u64 + 1,
+        read,
+        write,
+        execute: !rbar.xn(),
+        device: attr & 0xf0 == 0,
+        subregions_disabled: 0,
+    }
+}
+
+///
+/// Reads all MPU regions.  Each region is selected via MPU_RNR, which we
+/// restore when we're done -- but the target must be halted lest the
+/// kernel itself be using it.
+///
+fn mpu_read(core: &mut dyn Core) -> Result<(MPU_CTRL, Vec<MpuRegion>)> {
+    let nregions = MPU_TYPE::read(core)?.dregion();
+
+    if nregions == 0 {
+        bail!("target does not implement an MPU");
+    }
+
+    let armv8m = match ARMCore::from_u32(CPUID::read(core)?.partno()) {
+        Some(part) => part.is_armv8m(),
+        None => false,
+    };
+
+    let mair = if armv8m {
+        let lo: u32 = MPU_MAIR0::read(core)?.into();
+        let hi: u32 = MPU_MAIR1::read(core)?.into();
+        ((hi as u64) << 32) | lo as u64
+    } else {
+        0
+    };
+
+    let ctrl = MPU_CTRL::read(core)?;
+    let rnr = MPU_RNR::read(core)?;
+    let mut regions = vec![];
+
+    for i in 0..nregions {
+        let mut sel = MPU_RNR::from(0);
+        sel.set_region(i);
+        sel.write(core)?;
+
+        let region = if armv8m {
+            mpu_region_v8(
+                i,
+                MPU_RBAR_V8::read(core)?,
+                MPU_RLAR::read(core)?,
+                mair,
+            )
+        } else {
+            mpu_region_v7(i, MPU_RBAR::read(core)?, MPU_RASR::read(core)?)
+        };
+
+        regions.push(region);
+    }
+
+    rnr.write(core)?;
+
+    Ok((ctrl, regions))
+}
+
+fn mpu_size(size: u64) -> String {
+    if size >= 1024 * 1024 {
+        format!("{}MiB", size >> 20)
+    } else if size >= 1024 {
+        format!("{}KiB", size >> 10)
+    } else {
+        format!("{}", size)
+    }
+}
+
+fn mpu_names(hubris: &HubrisArchive, region: &HubrisRegion) -> String {
+    let names = region
+        .tasks
+        .iter()
+        .map(|t| match hubris.lookup_module(*t) {
+            Ok(module) => module.name.clone(),
+            Err(_) => format!("{t}"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    match hubris.lookup_peripheral_byaddr(region.base) {
+        Some(p) if region.attr.device => format!("{names} [{p}]"),
+        _ => names,
+    }
+}
+
+///
+/// Checks that an enabled MPU region matches the region descriptor it
+/// corresponds to, returning a description of each difference.
+///
+fn mpu_compare(mpu: &MpuRegion, region: &HubrisRegion) -> Vec<String> {
+    let mut diffs = vec![];
+    let attr = &region.attr;
+
+    if mpu.size != region.mapsize as u64 {
+        diffs.push(format!(
+            "size is {} in MPU, but {} in descriptor",
+            mpu_size(mpu.size),
+            mpu_size(region.mapsize as u64)
+        ));
+    }
+
+    for (what, mpu, desc) in [
+        ("readable", mpu.read, attr.read || attr.write),
+        ("writable", mpu.write, attr.write),
+        ("executable", mpu.execute, attr.execute),
+        ("device memory", mpu.device, attr.device),
+    ] {
+        if mpu != desc {
+            diffs.push(format!(
+                "{} {what} in MPU, but {} in descriptor",
+                if mpu { "is" } else { "is not" },
+                if desc { "is" } else { "is not" },
+            ));
+        }
+    }
+
+    if mpu.subregions_disabled != 0 {
+        diffs.push(format!(
+            "has subregions disabled (SRD = 0x{:02x})",
+            mpu.subregions_disabled
+        ));
+    }
+
+    diffs
+}
+
+///
+/// Checks the region descriptors of all tasks, independent of the MPU.
+///
+fn mpu_check_descriptors(
+    hubris: &HubrisArchive,
+    regions: &BTreeMap<u32, HubrisRegion>,
+    issues: &mut Vec<String>,
+) {
+    let descs =
+        regions.values().filter(|r| r.daddr.is_some()).collect::<Vec<_>>();
+
+    for region in &descs {
+        if !region.attr.execute {
+            continue;
+        }
+
+        let name = mpu_names(hubris, region);
+
+        if region.attr.write {
+            issues.push(format!(
+                "region at 0x{:08x} ({name}) is writable and executable",
+                region.base
+            ));
+        }
+
+        let end = region.base as u64 + region.mapsize as u64;
+
+        for task in &region.tasks {
+            let module = match hubris.lookup_module(*task) {
+                Ok(module) => module,
+                Err(_) => continue,
+            };
+
+            let text = module.textbase;
+
+            if text < region.base || text as u64 >= end {
+                issues.push(format!(
+                    "region at 0x{:08x} ({name}) is executable, but does \
+                    not contain the text of task {}",
+                    region.base, module.name
+                ));
+            }
+        }
+    }
+
+    for pair in descs.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+
+        if a.base as u64 + a.mapsize as u64 > b.base as u64 {
+            issues.push(format!(
+                "region at 0x{:08x} ({}) overlaps region at 0x{:08x} ({})",
+                a.base,
+                mpu_names(hubris, a),
+                b.base,
+                mpu_names(hubris, b),
+            ));
+        }
+    }
+}
+
+fn mpu(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let _subargs = MpuArgs::try_parse_from(subargs)?;
+
+    core.halt()?;
+
+    let rval = mpu_read(core).and_then(|mpu| {
+        let regions = hubris.regions(core)?;
+        let current = hubris.current_task(core)?;
+        Ok((mpu, regions, current))
+    });
+
+    core.run()?;
+
+    let ((ctrl, mpu), regions, current) = rval?;
+    let mut issues = vec![];
+
+    println!(
+        "MPU: {}, {} regions, privileged default map {}",
+        if ctrl.enable() { "enabled" } else { "disabled" },
+        mpu.len(),
+        if ctrl.privdefena() { "enabled" } else { "disabled" }
+    );
+
+    if !ctrl.enable() {
+        issues.push("MPU is not enabled".to_string());
+    }
+
+    let current = match current {
+        Some(HubrisTask::Task(t)) => {
+            let name = &hubris.lookup_module(HubrisTask::Task(t))?.name;
+            println!("current task: {name}");
+            Some(HubrisTask::Task(t))
+        }
+        _ => {
+            println!("current task: unknown");
+            None
+        }
+    };
+
+    println!(
+        "{:3} {:10}   {:10} {:>7} {:4} {:6} TASK",
+        "RGN", "LOW", "HIGH", "SIZE", "PERM", "ATTR"
+    );
+
+    for m in &mpu {
+        if !m.enabled {
+            println!("{:3} -", m.region);
+            continue;
+        }
+
+        let found = regions.get(&m.base).filter(|r| r.daddr.is_some());
+
+        println!(
+            "{:3} 0x{:08x} - 0x{:08x} {:>7} {}{}{}  {:6} {}",
+            m.region,
+            m.base,
+            m.limit() - 1,
+            mpu_size(m.size),
+            if m.read { "r" } else { "-" },
+            if m.write { "w" } else { "-" },
+            if m.execute { "x" } else { "-" },
+            if m.device { "device" } else { "normal" },
+            match found {
+                Some(region) => mpu_names(hubris, region),
+                None => "-".to_string(),
+            }
+        );
+
+        let what = format!("MPU region {} (0x{:08x})", m.region, m.base);
+
+        match (found, current) {
+            (None, _) => {
+                issues.push(format!("{what} has no region descriptor"));
+            }
+            (Some(region), Some(task)) if !region.tasks.contains(&task) => {
+                issues.push(format!(
+                    "{what} belongs to {}, not the current task",
+                    mpu_names(hubris, region)
+                ));
+            }
+            (Some(region), _) => {
+                for diff in mpu_compare(m, region) {
+                    issues.push(format!("{what} {diff}"));
+                }
+            }
+        }
+
+        if m.write && m.execute {
+            issues.push(format!("{what} is writable and executable"));
+        }
+    }
+
+    let enabled = mpu.iter().filter(|m| m.enabled).collect::<Vec<_>>();
+
+    for (i, a) in enabled.iter().enumerate() {
+        for b in &enabled[i + 1..] {
+            if a.overlaps(b) {
+                issues.push(format!(
+                    "MPU regions {} and {} overlap",
+                    a.region, b.region
+                ));
+            }
+        }
+    }
+
+    if let Some(task) = current {
+        for region in regions.values() {
+            if region.daddr.is_none() || !region.tasks.contains(&task) {
+                continue;
+            }
+
+            if !enabled.iter().any(|m| m.base == region.base) {
+                issues.push(format!(
+                    "region at 0x{:08x} ({}) is not programmed in the MPU",
+                    region.base,
+                    mpu_names(hubris, region)
+                ));
+            }
+        }
+    }
+
+    mpu_check_descriptors(hubris, &regions, &mut issues);
+
+    if issues.is_empty() {
+        msg!("no discrepancies found");
+        return Ok(());
+    }
+
+    for issue in &issues {
+        msg!("{issue}");
+    }
+
+    bail!(
+        "{} discrepanc{} found",
+        issues.len(),
+        if issues.len() == 1 { "y" } else { "ies" }
+    );
+}
+
+pub fn init() -> Command {
+    Command {
+        app: MpuArgs::command(),
+        name: "mpu",
+        run: mpu,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
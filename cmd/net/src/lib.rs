@@ -36,12 +36,56 @@
 //! It is only functional on the fully supported boards listed above.  In
 //! addition, the MAC-side counters are only supported on the VSC8562, not the
 //! VSC8552; this is indicated with `--` in the relevant table positions.
+//!
+//! ### `humility net sockets`
+//! This subcommand reads the socket table of the netstack task (`net`, by
+//! default; use `--task` to specify a different task) directly from target
+//! memory, and prints each socket along with the depths of its receive and
+//! transmit queues, expressed as packets and bytes queued out of the
+//! capacity of the queue:
+//!
+//! ```console
+//! $ humility net sockets
+//! humility: attached via ST-Link V3
+//! SOCKET TYPE  LOCAL          STATE   RX PKTS  RX BYTES TX PKTS  TX BYTES
+//! 0      udp   [::]:7         bound       0/4    0/4096     0/4    0/4096
+//! 1      udp   [::]:998       bound       4/4 1720/4096     0/4    0/4096
+//! 2      udp   [::]:11111     bound       0/4    0/4096     0/4    0/4096
+//! ```
+//!
+//! A receive queue that is full (as socket 1 is, above) indicates that the
+//! task that owns the socket is not keeping up with incoming traffic; any
+//! further packets for the socket will be dropped.
+//!
+//! ### `humility net neighbors`
+//! This subcommand prints the netstack's neighbor cache (that is, its table
+//! of IP to MAC address mappings, as learned via NDP or ARP), along with the
+//! time until each entry expires.
+//!
+//! ### `humility net queues`
+//! This subcommand prints the Ethernet DMA descriptor rings, showing for
+//! each the number of descriptors that are owned by the DMA engine and by
+//! software, and the resulting number of packets that are queued:  for a
+//! receive ring, packets that have been received but not yet processed by
+//! the netstack; for a transmit ring, packets that have not yet been sent.
+//!
+//! ### `humility net stats`
+//! This subcommand prints any counters kept by the netstack task (that is,
+//! variables in the task whose names contain `COUNTERS`), eliding counters
+//! that are zero unless `--verbose` (`-v`) is specified.
+//!
+//! Unlike the other subcommands, `sockets`, `neighbors`, `queues`, and
+//! `stats` do not require the netstack to respond to requests:  the target
+//! is briefly halted while the relevant memory is read.
 use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
 use colored::Colorize;
 
+use humility::core::Core;
+use humility::hubris::*;
 use humility::reflect::*;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
@@ -58,6 +102,18 @@ enum NetCommand {
     Status,
     /// Print the counters
     Counters,
+    /// Print the netstack's sockets and their queue depths
+    Sockets,
+    /// Print the netstack's neighbor cache
+    Neighbors,
+    /// Print the depths of the Ethernet DMA descriptor rings
+    Queues,
+    /// Print the netstack's counters
+    Stats {
+        /// print counters that are zero
+        #[clap(long, short)]
+        verbose: bool,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -70,6 +126,10 @@ struct NetArgs {
     )]
     timeout: u32,
 
+    /// netstack task to inspect from memory
+    #[clap(long, default_value = "net", value_name = "task")]
+    task: String,
+
     #[clap(subcommand)]
     cmd: NetCommand,
 }
@@ -469,6 +529,569 @@ fn net_counters(context: &mut ExecutionContext) -> Result<()> {
     Ok(())
 }
 
+///
+/// Reads the variables of the netstack task, returning each along with its
+/// reflected value.  The target is halted while reading so that the values
+/// are consistent with one another.
+///
+fn net_values(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    task: &str,
+) -> Result<Vec<(String, Value)>> {
+    let mut vars = vec![];
+
+    for (name, var) in hubris.qualified_variables() {
+        let module = hubris.lookup_module(HubrisTask::from(var.goff))?;
+
+        if module.name == task && var.size > 0 {
+            vars.push((name, var));
+        }
+    }
+
+    if vars.is_empty() {
+        bail!("no variables found in task {}", task);
+    }
+
+    let mut bufs = vec![];
+
+    core.halt()?;
+
+    for (_, var) in &vars {
+        let mut buf = vec![0u8; var.size];
+
+        if let Err(e) = core.read_8(var.addr, &mut buf) {
+            core.run()?;
+            return Err(e);
+        }
+
+        bufs.push(buf);
+    }
+
+    core.run()?;
+
+    let mut values = vec![];
+
+    for ((name, var), buf) in vars.iter().zip(bufs.iter()) {
+        let ty = hubris.lookup_type(var.goff)?;
+
+        //
+        // Variables whose types we can't reflect upon can't contain anything
+        // we're looking for; skip them.
+        //
+        if let Ok(value) = load_value(hubris, buf, ty, 0) {
+            values.push((name.to_string(), value));
+        }
+    }
+
+    Ok(values)
+}
+
+///
+/// Calls `f` on the specified value and each value contained within it,
+/// stopping the descent wherever `f` returns true.
+///
+fn net_walk<'a>(value: &'a Value, f: &mut impl FnMut(&'a Value) -> bool) {
+    if f(value) {
+        return;
+    }
+
+    match value {
+        Value::Struct(s) => s.iter().for_each(|(_, v)| net_walk(v, f)),
+        Value::Tuple(t) => t.iter().for_each(|v| net_walk(v, f)),
+        Value::Array(a) => a.iter().for_each(|v| net_walk(v, f)),
+        Value::Enum(e) => {
+            if let Some(v) = e.contents() {
+                net_walk(v, f);
+            }
+        }
+        Value::Ref(r) => net_walk(r.target(), f),
+        Value::Base(_) | Value::Ptr(_) => {}
+    }
+}
+
+fn net_uint(value: &Value) -> Option<u64> {
+    match value.as_base().ok()? {
+        Base::U8(v) => Some(*v as u64),
+        Base::U16(v) => Some(*v as u64),
+        Base::U32(v) => Some(*v as u64),
+        Base::U64(v) => Some(*v),
+        Base::I64(v) if *v >= 0 => Some(*v as u64),
+        Base::Bool(v) => Some(*v as u64),
+        _ => None,
+    }
+}
+
+///
+/// Returns the integers contained within a value, in order.  This allows us
+/// to treat (e.g.) an `AtomicU32` as a `u32`, and an `EthernetAddress` (or
+/// an `IpAddress` of either flavor) as an array of bytes.
+///
+fn net_words(value: &Value) -> Vec<u64> {
+    let mut words = vec![];
+
+    net_walk(value, &mut |v| {
+        if let Some(w) = net_uint(v) {
+            words.push(w);
+        }
+        false
+    });
+
+    words
+}
+
+fn net_ip(value: &Value) -> String {
+    let bytes = net_words(value).iter().map(|&b| b as u8).collect::<Vec<_>>();
+
+    match bytes.len() {
+        4 => {
+            Ipv4Addr::from(<[u8; 4]>::try_from(&bytes[..]).unwrap()).to_string()
+        }
+        16 => format!(
+            "[{}]",
+            Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[..]).unwrap())
+        ),
+        _ => "*".to_string(),
+    }
+}
+
+fn net_mac(value: &Value) -> String {
+    net_words(value)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+///
+/// Formats a `smoltcp` endpoint, which is a structure consisting of an
+/// address (which may be optional) and a port.
+///
+fn net_endpoint(value: &Value) -> String {
+    let s = match value.as_struct() {
+        Ok(s) if s.check_members(&["addr", "port"]).is_ok() => s,
+        _ => return "-".to_string(),
+    };
+
+    let port = net_uint(&s["port"]).unwrap_or(0);
+
+    let addr = match &s["addr"] {
+        Value::Enum(e) if e.disc() == "None" => "*".to_string(),
+        addr => net_ip(addr),
+    };
+
+    format!("{addr}:{port}")
+}
+
+struct NetQueue {
+    packets: Option<(u64, u64)>,
+    bytes: (u64, u64),
+}
+
+///
+/// Returns the occupancy and capacity of a `smoltcp` `RingBuffer`.
+///
+fn net_ring(value: &Value) -> Option<(u64, u64)> {
+    let s = value.as_struct().ok()?;
+    s.check_members(&["storage", "read_at", "length"]).ok()?;
+
+    let length = net_uint(&s["length"])?;
+    let mut capacity = None;
+
+    //
+    // The storage is a `ManagedSlice`, which is either a borrowed slice or
+    // (with an allocator) a vector; either way, we want its length.
+    //
+    net_walk(&s["storage"], &mut |v| match v.as_struct() {
+        Ok(s) if s.name().starts_with('&') => {
+            capacity = s
+                .iter()
+                .find(|(n, _)| *n == "length")
+                .and_then(|m| net_uint(m.1));
+            true
+        }
+        Ok(s) if s.name().starts_with("Vec") => {
+            capacity =
+                s.iter().find(|(n, _)| *n == "len").and_then(|m| net_uint(m.1));
+            true
+        }
+        _ => false,
+    });
+
+    Some((length, capacity?))
+}
+
+///
+/// Returns the depth of a socket buffer, which is either a `PacketBuffer`
+/// (consisting of a ring of packet metadata and a ring of payload) or a
+/// `RingBuffer` of bytes.
+///
+fn net_queue(value: &Value) -> Option<NetQueue> {
+    let s = value.as_struct().ok()?;
+
+    if s.check_members(&["metadata_ring", "payload_ring"]).is_ok() {
+        Some(NetQueue {
+            packets: Some(net_ring(&s["metadata_ring"])?),
+            bytes: net_ring(&s["payload_ring"])?,
+        })
+    } else {
+        Some(NetQueue { packets: None, bytes: net_ring(value)? })
+    }
+}
+
+struct NetSocket {
+    kind: String,
+    local: String,
+    state: String,
+    rx: Option<NetQueue>,
+    tx: Option<NetQueue>,
+}
+
+///
+/// Finds sockets within a value, taking the kind of each socket from the
+/// variant of the enclosing `Socket` enum (e.g., `Socket::Udp`).
+///
+fn net_find_sockets(value: &Value, kind: &str, sockets: &mut Vec<NetSocket>) {
+    match value {
+        Value::Struct(s)
+            if s.check_members(&["rx_buffer", "tx_buffer"]).is_ok() =>
+        {
+            let member = |name| s.iter().find(|(n, _)| *n == name);
+
+            let local = ["endpoint", "listen_endpoint", "local_endpoint"]
+                .iter()
+                .find_map(|name| member(*name))
+                .map(|(_, v)| net_endpoint(v))
+                .unwrap_or_else(|| "-".to_string());
+
+            let state = match member("state") {
+                Some((_, Value::Enum(e))) => e.disc().to_lowercase(),
+                _ if local.ends_with(":0") || local == "-" => {
+                    "unbound".to_string()
+                }
+                _ => "bound".to_string(),
+            };
+
+            sockets.push(NetSocket {
+                kind: kind.to_lowercase(),
+                local,
+                state,
+                rx: net_queue(&s["rx_buffer"]),
+                tx: net_queue(&s["tx_buffer"]),
+            });
+        }
+        Value::Struct(s) => {
+            s.iter().for_each(|(_, v)| net_find_sockets(v, kind, sockets))
+        }
+        Value::Tuple(t) => {
+            t.iter().for_each(|v| net_find_sockets(v, kind, sockets))
+        }
+        Value::Array(a) => {
+            a.iter().for_each(|v| net_find_sockets(v, kind, sockets))
+        }
+        Value::Enum(e) => {
+            if let Some(v) = e.contents() {
+                let kind = match e.disc() {
+                    "Some" | "None" => kind,
+                    disc => disc,
+                };
+
+                net_find_sockets(v, kind, sockets);
+            }
+        }
+        Value::Ref(_) | Value::Base(_) | Value::Ptr(_) => {}
+    }
+}
+
+fn net_sockets(context: &mut ExecutionContext, task: &str) -> Result<()> {
+    let hubris = context.archive.as_ref().unwrap();
+    let core = &mut **context.core.as_mut().unwrap();
+
+    let values = net_values(hubris, core, task)?;
+    let mut sockets = vec![];
+
+    for (_, value) in &values {
+        net_find_sockets(value, "?", &mut sockets);
+    }
+
+    if sockets.is_empty() {
+        bail!("no sockets found in task {}", task);
+    }
+
+    let depth = |depth: Option<(u64, u64)>, width: usize| match depth {
+        Some((n, capacity)) => {
+            let out = format!("{:>width$}", format!("{n}/{capacity}"));
+
+            if n > 0 && n == capacity {
+                out.red()
+            } else {
+                out.normal()
+            }
+        }
+        None => format!("{:>width$}", "-").normal(),
+    };
+
+    println!(
+        "{:6} {:5} {:14} {:7} {:>7} {:>9} {:>7} {:>9}",
+        "SOCKET",
+        "TYPE",
+        "LOCAL",
+        "STATE",
+        "RX PKTS",
+        "RX BYTES",
+        "TX PKTS",
+        "TX BYTES"
+    );
+
+    for (i, socket) in sockets.iter().enumerate() {
+        let (rx_pkts, rx_bytes) = match &socket.rx {
+            Some(q) => (depth(q.packets, 7), depth(Some(q.bytes), 9)),
+            None => (depth(None, 7), depth(None, 9)),
+        };
+
+        let (tx_pkts, tx_bytes) = match &socket.tx {
+            Some(q) => (depth(q.packets, 7), depth(Some(q.bytes), 9)),
+            None => (depth(None, 7), depth(None, 9)),
+        };
+
+        println!(
+            "{:<6} {:5} {:14} {:7} {} {} {} {}",
+            i,
+            socket.kind,
+            socket.local,
+            socket.state,
+            rx_pkts,
+            rx_bytes,
+            tx_pkts,
+            tx_bytes
+        );
+    }
+
+    Ok(())
+}
+
+fn net_neighbors(context: &mut ExecutionContext, task: &str) -> Result<()> {
+    let hubris = context.archive.as_ref().unwrap();
+    let core = &mut **context.core.as_mut().unwrap();
+
+    let values = net_values(hubris, core, task)?;
+    let now = hubris.ticks(core)?;
+    let mut neighbors = vec![];
+
+    //
+    // The neighbor cache consists of (address, neighbor) tuples, regardless
+    // of whether it is stored in a map or in an array of options.
+    //
+    for (_, value) in &values {
+        net_walk(value, &mut |v| {
+            let t = match v.as_tuple() {
+                Ok(t) if t.len() == 2 => t,
+                _ => return false,
+            };
+
+            let neighbor = match t[1].as_struct() {
+                Ok(s) if s.name() == "Neighbor" => s,
+                _ => return false,
+            };
+
+            let (hw, expires) = match (
+                neighbor.iter().find(|(n, _)| *n == "hardware_addr"),
+                neighbor.iter().find(|(n, _)| *n == "expires_at"),
+            ) {
+                (Some((_, hw)), Some((_, expires))) => (hw, expires),
+                _ => return false,
+            };
+
+            //
+            // An `Instant` is in microseconds in later versions of smoltcp,
+            // and in milliseconds in earlier ones.
+            //
+            let expires = match expires.as_struct() {
+                Ok(s) => s.iter().next().and_then(|(n, v)| {
+                    net_uint(v)
+                        .map(|t| if n == "micros" { t / 1000 } else { t })
+                }),
+                Err(_) => None,
+            };
+
+            neighbors.push((net_ip(&t[0]), net_mac(hw), expires));
+            true
+        });
+    }
+
+    if neighbors.is_empty() {
+        humility::msg!("neighbor cache is empty");
+        return Ok(());
+    }
+
+    println!("{:40} {:17} EXPIRES", "ADDRESS", "MAC");
+
+    for (ip, mac, expires) in &neighbors {
+        let expires = match expires {
+            Some(t) if *t >= now => format!("{}ms", t - now),
+            Some(t) => format!("{}ms ago", now - t).red().to_string(),
+            None => "-".to_string(),
+        };
+
+        println!(
+            "{:40} {:17} {}",
+            ip.trim_start_matches('[').trim_end_matches(']'),
+            mac,
+            expires
+        );
+    }
+
+    Ok(())
+}
+
+fn net_queues(context: &mut ExecutionContext, task: &str) -> Result<()> {
+    let hubris = context.archive.as_ref().unwrap();
+    let core = &mut **context.core.as_mut().unwrap();
+
+    let values = net_values(hubris, core, task)?;
+    let mut rings = vec![];
+
+    //
+    // A DMA descriptor consists of four words, the last of which has the
+    // OWN bit (bit 31) set when the descriptor belongs to the DMA engine.
+    //
+    for (name, value) in &values {
+        let descs = match value.as_array() {
+            Ok(a) if !a.is_empty() => a,
+            _ => continue,
+        };
+
+        match descs[0].as_struct() {
+            Ok(s) if s.name().ends_with("Desc") => {}
+            _ => continue,
+        }
+
+        let mut dma = 0;
+
+        for desc in descs.iter() {
+            let words = net_words(desc);
+
+            if words.len() != 4 {
+                continue;
+            }
+
+            if words[3] & (1 << 31) != 0 {
+                dma += 1;
+            }
+        }
+
+        rings.push((name.as_str(), descs.len(), dma));
+    }
+
+    if rings.is_empty() {
+        bail!("no descriptor rings found in task {}", task);
+    }
+
+    println!(
+        "{:36} {:>5} {:>5} {:>5} {:>6}",
+        "RING", "DESCS", "DMA", "SW", "QUEUED"
+    );
+
+    for (name, total, dma) in rings {
+        let sw = total - dma;
+
+        //
+        // For a receive ring, descriptors owned by software are packets that
+        // have been received but not yet processed; for a transmit ring,
+        // descriptors owned by the DMA engine are packets not yet sent.
+        //
+        let base = name.rsplit("::").next().unwrap_or(name).to_lowercase();
+
+        let queued = if base.starts_with("rx") {
+            sw.to_string()
+        } else if base.starts_with("tx") {
+            dma.to_string()
+        } else {
+            "-".to_string()
+        };
+
+        println!("{:36} {:>5} {:>5} {:>5} {:>6}", name, total, dma, sw, queued);
+    }
+
+    Ok(())
+}
+
+///
+/// Flattens a value into its named integer leaves.  Structures with a single
+/// member (e.g., an `AtomicU32`) are treated as their member.
+///
+fn net_leaves(value: &Value, path: &str, leaves: &mut Vec<(String, u64)>) {
+    let child = |name: &str| {
+        if path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{path}.{name}")
+        }
+    };
+
+    match value {
+        Value::Struct(s) if s.len() == 1 => {
+            s.iter().for_each(|(_, v)| net_leaves(v, path, leaves))
+        }
+        Value::Struct(s) => {
+            s.iter().for_each(|(n, v)| net_leaves(v, &child(n), leaves))
+        }
+        Value::Array(a) => a
+            .iter()
+            .enumerate()
+            .for_each(|(i, v)| net_leaves(v, &format!("{path}[{i}]"), leaves)),
+        Value::Tuple(t) => t
+            .iter()
+            .enumerate()
+            .for_each(|(i, v)| net_leaves(v, &child(&i.to_string()), leaves)),
+        _ => {
+            if let Some(w) = net_uint(value) {
+                leaves.push((path.to_string(), w));
+            }
+        }
+    }
+}
+
+fn net_stats(
+    context: &mut ExecutionContext,
+    task: &str,
+    verbose: bool,
+) -> Result<()> {
+    let hubris = context.archive.as_ref().unwrap();
+    let core = &mut **context.core.as_mut().unwrap();
+
+    let values = net_values(hubris, core, task)?;
+    let mut found = false;
+
+    for (name, value) in &values {
+        if !name.to_uppercase().contains("COUNTERS") {
+            continue;
+        }
+
+        found = true;
+
+        let mut leaves = vec![];
+        net_leaves(value, "", &mut leaves);
+
+        println!("{}:", name.bold());
+
+        for (path, count) in &leaves {
+            if *count == 0 && !verbose {
+                continue;
+            }
+
+            let path = if path.is_empty() { name.as_str() } else { path };
+            println!("    {:50} {:>10}", path, count);
+        }
+    }
+
+    if !found {
+        bail!("no counters found in task {}", task);
+    }
+
+    Ok(())
+}
+
 fn net(context: &mut ExecutionContext) -> Result<()> {
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
     let subargs = NetArgs::try_parse_from(subargs)?;
@@ -478,6 +1101,12 @@ fn net(context: &mut ExecutionContext) -> Result<()> {
         NetCommand::Ip => net_ip(context)?,
         NetCommand::Status => net_status(context)?,
         NetCommand::Counters => net_counters(context)?,
+        NetCommand::Sockets => net_sockets(context, &subargs.task)?,
+        NetCommand::Neighbors => net_neighbors(context, &subargs.task)?,
+        NetCommand::Queues => net_queues(context, &subargs.task)?,
+        NetCommand::Stats { verbose } => {
+            net_stats(context, &subargs.task, verbose)?
+        }
     }
     Ok(())
 }
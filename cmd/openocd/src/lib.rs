@@ -120,7 +120,7 @@ fn openocd(context: &mut ExecutionContext) -> Result<()> {
     }
 
     // Run OpenOCD, ignoring Ctrl-C (so it can handle them)
-    ctrlc::set_handler(|| {}).expect("Error setting Ctrl-C handler");
+    humility_cmd::interrupted()?;
     let status = cmd.status()?;
 
     // Then, check on the OpenOCD status.
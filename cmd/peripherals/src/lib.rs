@@ -0,0 +1,616 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility peripherals`
+//!
+//! `humility peripherals` uses a CMSIS-SVD description of the target's
+//! microcontroller to read and decode its peripheral registers.  With no
+//! arguments, it lists the peripherals that the SVD describes; given the
+//! name of a peripheral, it reads each of its registers and decodes them
+//! field by field:
+//!
+//! ```console
+//! $ humility peripherals i2c3
+//! humility: attached via ST-Link V3
+//! I2C3 (Inter-integrated circuit) at 0x40005c00
+//!   CR1          0x40005c00 = 0x00000001
+//!     PE                [0] = 0x1 Enabled
+//!     TXIE              [1] = 0x0 Disabled
+//!     RXIE              [2] = 0x0 Disabled
+//!     ...
+//!     DNF            [11:8] = 0x0 NoFilter
+//!     ...
+//!   CR2          0x40005c04 = 0x00000000
+//!     SADD            [9:0] = 0x0
+//!     ...
+//! ```
+//!
+//! To display only some of a peripheral's registers, use `--register`
+//! (`-r`).  Registers that cannot be read (because they are write-only, or
+//! because reading them has side-effects) are not read.
+//!
+//! To display only those registers (and fields) that differ from their
+//! reset values, use `--diff` (`-d`):
+//!
+//! ```console
+//! $ humility peripherals i2c3 --diff
+//! humility: attached via ST-Link V3
+//! I2C3 (Inter-integrated circuit) at 0x40005c00
+//!   CR1          0x40005c00 = 0x00000001 (reset 0x00000000)
+//!     PE                [0] = 0x1 Enabled (reset 0x0 Disabled)
+//!   TIMINGR      0x40005c10 = 0x10c0ecff (reset 0x00000000)
+//!     SCLL            [7:0] = 0xff (reset 0x0)
+//!     ...
+//! ```
+//!
+//! To watch a peripheral for changes, use `--watch` (`-w`).  The registers
+//! are read every `--interval` (`-i`) milliseconds (1000 by default), and
+//! any change to a register or field is displayed as it is seen:
+//!
+//! ```console
+//! $ humility peripherals i2c3 --watch --register isr
+//! humility: attached via ST-Link V3
+//! humility: watching I2C3 (^C to stop)
+//!    2.004s ISR: 0x00000001 -> 0x00000003
+//!    2.004s ISR.TXIS: 0x0 -> 0x1 Set
+//! ```
+//!
+//! The SVD file is taken from the Hubris archive if it contains one.
+//! Otherwise, if the `HUMILITY_SVD_PATH` environment variable names a
+//! directory, the SVD file in that directory whose name is the longest
+//! prefix of the archive's chip (e.g., `STM32H753.svd` for a chip of
+//! `STM32H753ZITx`) is used.  An SVD file can also be specified directly
+//! with `--svd`, in which case no archive is needed.
+//!
+
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::HubrisArchive;
+use humility::msg;
+use humility::Diagnostic;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+use svd_parser::svd::{
+    Access, Device, FieldInfo, PeripheralInfo, RegisterCluster, RegisterInfo,
+    RegisterProperties,
+};
+
+#[derive(Parser, Debug)]
+#[clap(name = "peripherals", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct PeripheralsArgs {
+    /// SVD file describing the microcontroller
+    #[clap(long, value_name = "file")]
+    svd: Option<String>,
+
+    /// registers to display (all registers by default)
+    #[clap(
+        long,
+        short,
+        value_name = "register",
+        use_value_delimiter = true,
+        requires = "peripheral"
+    )]
+    register: Vec<String>,
+
+    /// display only registers and fields that differ from their reset values
+    #[clap(long, short, requires = "peripheral", conflicts_with = "watch")]
+    diff: bool,
+
+    /// watch registers for changes
+    #[clap(long, short, requires = "peripheral")]
+    watch: bool,
+
+    /// interval at which to read registers when watching, in milliseconds
+    #[clap(
+        long, short, default_value_t = 1000, value_name = "ms",
+        requires = "watch", parse(try_from_str = parse_int::parse)
+    )]
+    interval: u64,
+
+    /// peripheral to display
+    peripheral: Option<String>,
+}
+
+///
+/// A register, flattened out of the SVD:  clusters are folded into the
+/// register's name and address, and register properties are inherited from
+/// the enclosing cluster, peripheral and device.
+///
+struct Register<'a> {
+    name: String,
+    addr: u32,
+    size: u32,
+    reset: Option<(u64, u64)>,
+    readable: bool,
+    info: &'a RegisterInfo,
+}
+
+///
+/// Register properties, as inherited from each level of the SVD hierarchy
+///
+#[derive(Copy, Clone, Default)]
+struct Properties {
+    size: Option<u32>,
+    access: Option<Access>,
+    reset_value: Option<u64>,
+    reset_mask: Option<u64>,
+}
+
+impl Properties {
+    fn inherit(&self, props: &RegisterProperties) -> Self {
+        Self {
+            size: props.size.or(self.size),
+            access: props.access.or(self.access),
+            reset_value: props.reset_value.or(self.reset_value),
+            reset_mask: props.reset_mask.or(self.reset_mask),
+        }
+    }
+}
+
+impl<'a> Register<'a> {
+    fn mask(&self) -> u64 {
+        if self.size >= 64 {
+            u64::MAX
+        } else {
+            (1u64 << self.size) - 1
+        }
+    }
+
+    fn format(&self, val: u64) -> String {
+        format!("0x{:0width$x}", val, width = (self.size as usize + 3) / 4)
+    }
+
+    fn reset(&self) -> Option<u64> {
+        self.reset.map(|(value, mask)| value & mask & self.mask())
+    }
+
+    ///
+    /// Returns true if the specified value differs from the reset value in
+    /// the bits that the reset value defines.  (A register with no reset
+    /// value is never considered to differ.)
+    ///
+    fn differs(&self, val: u64, lo: u32, width: u32) -> bool {
+        match self.reset {
+            Some((value, mask)) => {
+                let m = field_mask(lo, width) & mask;
+                (val & m) != (value & m)
+            }
+            None => false,
+        }
+    }
+}
+
+fn field_mask(lo: u32, width: u32) -> u64 {
+    if width >= 64 {
+        u64::MAX
+    } else {
+        ((1u64 << width) - 1) << lo
+    }
+}
+
+fn field_value(field: &FieldInfo, val: u64) -> u64 {
+    let (lo, width) = (field.bit_range.offset, field.bit_range.width);
+    (val & field_mask(lo, width)) >> lo
+}
+
+///
+/// Returns the field's value along with the name of its enumerated value,
+/// if it has one.
+///
+fn field_format(field: &FieldInfo, val: u64) -> String {
+    let name = field
+        .enumerated_values
+        .iter()
+        .flat_map(|e| e.values.iter())
+        .find(|v| v.value == Some(val))
+        .map(|v| format!(" {}", v.name))
+        .unwrap_or_default();
+
+    format!("0x{:x}{}", val, name)
+}
+
+fn field_bits(field: &FieldInfo) -> String {
+    let (lo, width) = (field.bit_range.offset, field.bit_range.width);
+
+    if width == 1 {
+        format!("[{lo}]")
+    } else {
+        format!("[{}:{lo}]", lo + width - 1)
+    }
+}
+
+fn peripherals_flatten<'a>(
+    children: &'a [RegisterCluster],
+    base: u64,
+    prefix: &str,
+    props: Properties,
+    rval: &mut Vec<Register<'a>>,
+) {
+    for child in children {
+        match child {
+            RegisterCluster::Register(register) => {
+                let props = props.inherit(&register.properties);
+
+                let readable = register.read_action.is_none()
+                    && !matches!(
+                        props.access,
+                        Some(Access::WriteOnly) | Some(Access::WriteOnce)
+                    );
+
+                rval.push(Register {
+                    name: format!("{prefix}{}", register.name),
+                    addr: (base + register.address_offset as u64) as u32,
+                    size: props.size.unwrap_or(32),
+                    reset: props
+                        .reset_value
+                        .map(|v| (v, props.reset_mask.unwrap_or(u64::MAX))),
+                    readable,
+                    info: register,
+                });
+            }
+            RegisterCluster::Cluster(cluster) => {
+                peripherals_flatten(
+                    &cluster.children,
+                    base + cluster.address_offset as u64,
+                    &format!("{prefix}{}.", cluster.name),
+                    props.inherit(&cluster.default_register_properties),
+                    rval,
+                );
+            }
+        }
+    }
+}
+
+///
+/// Returns the SVD file in the specified directory whose name is the
+/// longest prefix of the specified chip, if any.
+///
+fn peripherals_svd_path(dir: &Path, chip: &str) -> Result<Option<String>> {
+    let chip = chip.to_lowercase();
+    let mut best: Option<(usize, String)> = None;
+
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read {}", dir.display()))?;
+
+    for entry in entries {
+        let path = entry?.path();
+
+        if path.extension().map_or(true, |ext| ext != "svd") {
+            continue;
+        }
+
+        let stem = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(stem) => stem.to_lowercase(),
+            None => continue,
+        };
+
+        if chip.starts_with(&stem)
+            && best.as_ref().map_or(true, |(len, _)| stem.len() > *len)
+        {
+            best = Some((stem.len(), path.display().to_string()));
+        }
+    }
+
+    Ok(best.map(|(_, path)| path))
+}
+
+fn peripherals_svd(
+    hubris: &HubrisArchive,
+    subargs: &PeripheralsArgs,
+) -> Result<Device> {
+    let (name, contents) = if let Some(ref svd) = subargs.svd {
+        (svd.clone(), std::fs::read(svd)?)
+    } else if !hubris.loaded() {
+        bail!("must provide a Hubris archive or an SVD file (via --svd)");
+    } else if let Some(svd) = hubris.read_svd()? {
+        svd
+    } else {
+        let chip = match hubris.chip() {
+            Some(chip) => chip,
+            None => {
+                bail!(Diagnostic::error("archive does not specify its chip")
+                    .code("no-svd")
+                    .hint("use --svd to specify an SVD file"))
+            }
+        };
+
+        let path = match std::env::var_os("HUMILITY_SVD_PATH") {
+            Some(dir) => peripherals_svd_path(Path::new(&dir), &chip)?,
+            None => None,
+        };
+
+        match path {
+            Some(path) => {
+                let contents = std::fs::read(&path)?;
+                (path, contents)
+            }
+            None => {
+                bail!(Diagnostic::error(format!(
+                    "archive does not contain an SVD file and none found \
+                    for {chip} in HUMILITY_SVD_PATH"
+                ))
+                .code("no-svd")
+                .hint("use --svd to specify an SVD file"));
+            }
+        }
+    };
+
+    let contents = String::from_utf8(contents)
+        .with_context(|| format!("{name} is not valid UTF-8"))?;
+
+    let config = svd_parser::Config::default().expand(true);
+
+    svd_parser::parse_with_config(&contents, &config)
+        .with_context(|| format!("failed to parse SVD file {name}"))
+}
+
+///
+/// SVD descriptions often span lines; we want them on one.
+///
+fn peripherals_description(description: &str) -> String {
+    description.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn peripherals_list(device: &Device) {
+    let mut peripherals =
+        device.peripherals.iter().map(|p| &**p).collect::<Vec<_>>();
+
+    peripherals.sort_by_key(|p| p.base_address);
+
+    println!("{:16} {:10} DESCRIPTION", "NAME", "BASE");
+
+    for p in peripherals {
+        let description = p
+            .description
+            .as_deref()
+            .map(peripherals_description)
+            .unwrap_or_default();
+
+        println!("{:16} 0x{:08x} {}", p.name, p.base_address, description);
+    }
+}
+
+fn peripherals_read(core: &mut dyn Core, register: &Register) -> Result<u64> {
+    //
+    // Peripheral registers generally require word accesses, so we read the
+    // containing word (or words) rather than the register's bytes.
+    //
+    let aligned = register.addr & !0b11;
+    let shift = (register.addr - aligned) * 8;
+
+    let val = if register.size > 32 {
+        let lo = core.read_word_32(aligned)? as u64;
+        let hi = core.read_word_32(aligned + 4)? as u64;
+        (hi << 32) | lo
+    } else {
+        core.read_word_32(aligned)? as u64
+    };
+
+    Ok((val >> shift) & register.mask())
+}
+
+fn peripherals_print(register: &Register, val: u64, diff: bool) {
+    if diff && !register.differs(val, 0, register.size) {
+        return;
+    }
+
+    print!(
+        "  {:12} 0x{:08x} = {}",
+        register.name,
+        register.addr,
+        register.format(val)
+    );
+
+    match register.reset() {
+        Some(reset) if diff => println!(" (reset {})", register.format(reset)),
+        _ => println!(),
+    }
+
+    for field in register.info.fields() {
+        let (lo, width) = (field.bit_range.offset, field.bit_range.width);
+
+        if diff && !register.differs(val, lo, width) {
+            continue;
+        }
+
+        print!(
+            "    {:12} {:>8} = {}",
+            field.name,
+            field_bits(field),
+            field_format(field, field_value(field, val))
+        );
+
+        match register.reset() {
+            Some(reset) if diff => println!(
+                " (reset {})",
+                field_format(field, field_value(field, reset))
+            ),
+            _ => println!(),
+        }
+    }
+}
+
+fn peripherals_watch(
+    core: &mut dyn Core,
+    peripheral: &PeripheralInfo,
+    registers: &[Register],
+    interval: u64,
+) -> Result<()> {
+    let done = humility_cmd::interrupted()?;
+
+    let read = |core: &mut dyn Core| -> Result<Vec<u64>> {
+        core.op_start()?;
+
+        let vals = registers
+            .iter()
+            .map(|r| peripherals_read(core, r))
+            .collect::<Result<Vec<_>>>();
+
+        core.op_done()?;
+        vals
+    };
+
+    let mut last = read(core)?;
+
+    for (register, val) in registers.iter().zip(last.iter()) {
+        peripherals_print(register, *val, false);
+    }
+
+    msg!("watching {} (^C to stop)", peripheral.name);
+    let start = Instant::now();
+
+    while !done.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(interval));
+
+        let vals = read(core)?;
+        let elapsed = start.elapsed().as_secs_f64();
+
+        for ((register, old), new) in registers.iter().zip(&last).zip(&vals) {
+            if old == new {
+                continue;
+            }
+
+            println!(
+                "{:9.3}s {}: {} -> {}",
+                elapsed,
+                register.name,
+                register.format(*old),
+                register.format(*new)
+            );
+
+            for field in register.info.fields() {
+                let (old, new) =
+                    (field_value(field, *old), field_value(field, *new));
+
+                if old != new {
+                    println!(
+                        "{:9.3}s {}.{}: {} -> {}",
+                        elapsed,
+                        register.name,
+                        field.name,
+                        field_format(field, old),
+                        field_format(field, new)
+                    );
+                }
+            }
+        }
+
+        last = vals;
+    }
+
+    Ok(())
+}
+
+fn peripherals(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = PeripheralsArgs::try_parse_from(subargs)?;
+
+    let device = peripherals_svd(hubris, &subargs)?;
+
+    let name = match subargs.peripheral {
+        Some(ref name) => name,
+        None => {
+            peripherals_list(&device);
+            return Ok(());
+        }
+    };
+
+    let peripheral = match device
+        .peripherals
+        .iter()
+        .find(|p| p.name.eq_ignore_ascii_case(name))
+    {
+        Some(peripheral) => peripheral,
+        None => bail!("no peripheral \"{name}\" in SVD for {}", device.name),
+    };
+
+    let props = Properties::default()
+        .inherit(&device.default_register_properties)
+        .inherit(&peripheral.default_register_properties);
+
+    let mut registers = vec![];
+
+    if let Some(ref children) = peripheral.registers {
+        peripherals_flatten(
+            children,
+            peripheral.base_address,
+            "",
+            props,
+            &mut registers,
+        );
+    }
+
+    for name in &subargs.register {
+        if !registers.iter().any(|r| r.name.eq_ignore_ascii_case(name)) {
+            bail!("no register \"{name}\" in {}", peripheral.name);
+        }
+    }
+
+    registers.retain(|r| {
+        subargs.register.is_empty()
+            || subargs.register.iter().any(|n| r.name.eq_ignore_ascii_case(n))
+    });
+
+    registers.sort_by_key(|r| r.addr);
+
+    let (readable, unreadable): (Vec<_>, Vec<_>) =
+        registers.into_iter().partition(|r| r.readable);
+
+    for r in &unreadable {
+        msg!("not reading {}: write-only or read has side-effects", r.name);
+    }
+
+    if subargs.watch {
+        return peripherals_watch(
+            core,
+            peripheral,
+            &readable,
+            subargs.interval,
+        );
+    }
+
+    print!("{}", peripheral.name);
+
+    if let Some(ref d) = peripheral.description {
+        print!(" ({})", peripherals_description(d));
+    }
+
+    println!(" at 0x{:08x}", peripheral.base_address);
+
+    core.op_start()?;
+
+    let vals = readable
+        .iter()
+        .map(|r| peripherals_read(core, r))
+        .collect::<Result<Vec<_>>>();
+
+    core.op_done()?;
+
+    for (register, val) in readable.iter().zip(vals?) {
+        peripherals_print(register, val, subargs.diff);
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: PeripheralsArgs::command(),
+        name: "peripherals",
+        run: peripherals,
+        kind: CommandKind::Attached {
+            archive: Archive::Optional,
+            attach: Attach::LiveOnly,
+            validate: Validate::None,
+        },
+    }
+}
@@ -172,6 +172,93 @@
 //! has the added advantage of displaying all power rails in the systemn, not
 //! just PMBus devices.)
 //!
+//! To monitor telemetry over time, use `--monitor` with a sampling interval in
+//! milliseconds.  By default, this will monitor `READ_VOUT`, `READ_IOUT`,
+//! `READ_TEMPERATURE_1` and `STATUS_WORD` on every PMBus rail in the system,
+//! redrawing the table in place with each sample; rails can be specified with
+//! `--rail` (`-r`) and commands with `--command` (`-C`):
+//!
+//! ```console
+//! $ humility pmbus --monitor 1000 -r VDD_VCORE,VDD_MEM_ABCD
+//! humility: attached via ST-Link V3
+//! DEVICE      RAIL                     READ_VOUT       READ_IOUT READ_TEMPERATURE_1     STATUS_WORD
+//! raa229618   VDD_VCORE                   1.181V         53.200A        40.000°C          0x0000
+//! raa229618   VDD_MEM_ABCD                1.224V         28.600A        41.000°C          0x0000
+//! ```
+//!
+//! To instead emit a stream of comma-separated values (one line per rail per
+//! sample, prefixed with the time in seconds since monitoring began), also
+//! specify `--csv`:
+//!
+//! ```console
+//! $ humility pmbus --monitor 1000 -r VDD_VCORE --csv
+//! humility: attached via ST-Link V3
+//! TIME,DEVICE,RAIL,READ_VOUT,READ_IOUT,READ_TEMPERATURE_1,STATUS_WORD
+//! 0.052,raa229618,VDD_VCORE,1.181V,53.200A,40.000°C,0x0000
+//! 1.104,raa229618,VDD_VCORE,1.182V,52.800A,40.000°C,0x0000
+//! ```
+//!
+//! To preserve the tuning of a device (e.g., before reflashing a voltage
+//! regulator or swapping a board), use `--save` to capture the standard PMBus
+//! configuration commands (output voltage, limits, fault responses, timing and
+//! fan configuration) of the device (or of the rail specified with `--rail`)
+//! into a JSON file:
+//!
+//! ```console
+//! $ humility pmbus -d raa229618 --save vcore.json
+//! humility: attached via ST-Link V3
+//! humility: saved 126 commands from I2C3, port H, dev 0x5c to vcore.json
+//! ```
+//!
+//! This snapshot can later be reapplied with `--restore`.  Only commands whose
+//! values differ from the snapshot are written, and each write is verified by
+//! reading the command back; `--dry-run` (`-n`) will show what would be written
+//! without writing it:
+//!
+//! ```console
+//! $ humility pmbus -d raa229618 --restore vcore.json
+//! humility: attached via ST-Link V3
+//! humility: I2C3, port H, dev 0x5c, rail VDD_VCORE: restored 2/2 commands (61 unchanged)
+//! humility: I2C3, port H, dev 0x5c, rail VDD_MEM_ABCD: all 63 commands already match
+//! ```
+//!
+//! Manufacturer-specific commands are neither saved nor restored:  they include
+//! data ports and indirect memory access for which a read may have side
+//! effects and a write may modify device memory.  Nor are `OPERATION` and
+//! `WRITE_PROTECT`, as restoring them could turn a rail on or off or prevent
+//! other commands from being written.  A snapshot taken with one driver will not be restored to a device
+//! using a different driver unless `--force` (`-F`) is specified.
+//!
+//! To catch transient faults as they happen on boards that wire a bus's
+//! SMBALERT# line to a GPIO visible to the SP, use `--alert` to specify that
+//! pin (as `port:pin`) along with the bus.  `humility pmbus` will sample the
+//! pin (every 10 milliseconds by default; see `--alert-interval`) and, when it
+//! asserts, read from the SMBus Alert Response Address to identify the alerting
+//! device and immediately dump the status registers on each of its rails:
+//!
+//! ```console
+//! $ humility pmbus --alert E:10 -b mid
+//! humility: attached via ST-Link V3
+//! humility: waiting for SMBALERT# on I2C3, port H
+//! 12.481s: I2C3, port H, dev 0x5c (raa229618) responded to ARA
+//! rail 0 (VDD_VCORE):
+//! 0x79 STATUS_WORD               0x0802
+//! 0x7a STATUS_VOUT               0x00
+//! 0x7b STATUS_IOUT               0x00
+//! 0x7c STATUS_INPUT              0x00
+//! 0x7d STATUS_TEMPERATURE        0x00
+//! 0x7e STATUS_CML                0x02
+//! 0x80 STATUS_MFR_SPECIFIC       0x00
+//! rail 1 (VDD_MEM_ABCD):
+//! 0x79 STATUS_WORD               0x0000
+//! ...
+//! ```
+//!
+//! Use `--verbose` to decode the fields of each status register.  Because
+//! reading from the Alert Response Address requires raw I2C, alerts can only be
+//! captured with the `i2c` agent.  Status registers are not cleared; if
+//! multiple devices are alerting, each will be dumped in turn.
+//!
 //! `humility pmbus` can use two different mechanisms to perform PMBus actions,
 //! selected by the `--agent` command-line argument.
 //!
@@ -189,21 +276,26 @@
 
 use colored::Colorize;
 use humility::hubris::*;
-use humility::{core::Core, warn};
+use humility::{core::Core, msg, warn, Diagnostic};
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
 use humility_hiffy::*;
-use humility_i2c::I2cArgs;
+use humility_i2c::{I2cArgs, I2cRetryPolicy};
 use humility_idol::{HubrisIdol, IdolArgument, IdolOperation};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{CommandFactory, Parser};
 use hif::*;
 use indexmap::IndexMap;
 use pmbus::commands::*;
 use pmbus::*;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
 use std::collections::{BTreeMap, HashMap};
 use std::fmt::Write;
+use std::io::Write as _;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Parser, Debug)]
 #[clap(name = "pmbus", about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -300,6 +392,59 @@ struct PmbusArgs {
     /// agent to use when executing PMBus operations
     #[clap(long, arg_enum, default_value_t=Agent::Auto)]
     agent: Agent,
+
+    /// monitor telemetry on the specified rails (or all rails), sampling
+    /// at the specified interval
+    #[clap(
+        long, value_name = "interval_ms",
+        conflicts_with_all = &["list", "summarize", "writes", "commandhelp"],
+        parse(try_from_str = parse_int::parse)
+    )]
+    monitor: Option<u64>,
+
+    /// when monitoring, emit comma-separated values rather than a table
+    #[clap(long, requires = "monitor")]
+    csv: bool,
+
+    /// save the writable state of the specified device (or rail) to a file
+    #[clap(
+        long, value_name = "file",
+        conflicts_with_all = &[
+            "list", "summarize", "writes", "commandhelp", "monitor",
+            "commands", "restore"
+        ]
+    )]
+    save: Option<String>,
+
+    /// restore the writable state of the specified device (or rail) from a
+    /// file saved with --save, verifying each write
+    #[clap(
+        long, value_name = "file",
+        conflicts_with_all = &[
+            "list", "summarize", "writes", "commandhelp", "monitor",
+            "commands"
+        ]
+    )]
+    restore: Option<String>,
+
+    /// wait for SMBALERT# to assert on the specified GPIO pin, then use the
+    /// Alert Response Address to identify each alerting device on the
+    /// specified bus and dump its status registers
+    #[clap(
+        long, value_name = "port:pin",
+        conflicts_with_all = &[
+            "list", "summarize", "writes", "commandhelp", "monitor",
+            "commands", "save", "restore", "device", "rail"
+        ]
+    )]
+    alert: Option<String>,
+
+    /// when waiting for SMBALERT#, the interval at which to sample its pin
+    #[clap(
+        long, value_name = "interval_ms", default_value_t = 10,
+        parse(try_from_str = parse_int::parse)
+    )]
+    alert_interval: u64,
 }
 
 #[derive(clap::ArgEnum, Clone, Debug)]
@@ -966,10 +1111,612 @@ fn summarize(
             );
         }
 
-        base += calls.len();
-    }
+        base += calls.len();
+    }
+
+    Ok(())
+}
+
+fn monitor_value(
+    driver: &pmbus::Device,
+    code: u8,
+    val: &[u8],
+    mode: VOutModeCommandData,
+) -> String {
+    let mut interpreted = false;
+    let mut str = String::new();
+
+    let err = driver.interpret(
+        code,
+        val,
+        || mode,
+        |field, value| {
+            if !field.bitfield() {
+                write!(&mut str, "{}", value).unwrap();
+                interpreted = true;
+            }
+        },
+    );
+
+    if let Err(err) = err {
+        return format!("{:?}", err);
+    }
+
+    if !interpreted {
+        write!(&mut str, "0x").unwrap();
+        for i in (0..val.len()).rev() {
+            write!(&mut str, "{:02x}", val[i]).unwrap();
+        }
+    }
+
+    str
+}
+
+fn monitor(
+    subargs: &PmbusArgs,
+    hubris: &HubrisArchive,
+    worker: &mut dyn PmbusWorker,
+    interval: u64,
+) -> Result<()> {
+    let page = CommandCode::PAGE as u8;
+    let vout_mode = CommandCode::VOUT_MODE as u8;
+    let (all, bycode) = all_commands(pmbus::Device::Common);
+
+    let commands = match subargs.commands {
+        Some(ref cmds) => cmds
+            .iter()
+            .map(|cmd| match all.get(cmd) {
+                Some(code) => Ok(*code),
+                None => bail!("unrecognized command {}", cmd),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => vec![
+            CommandCode::READ_VOUT as u8,
+            CommandCode::READ_IOUT as u8,
+            CommandCode::READ_TEMPERATURE_1 as u8,
+            CommandCode::STATUS_WORD as u8,
+        ],
+    };
+
+    //
+    // Determine the rails that we're going to monitor:  either those that
+    // have been explicitly specified, all of the rails on a specified
+    // device, or (by default) every PMBus rail in the system.
+    //
+    let hargs = match (&subargs.rail, &subargs.device) {
+        (None, Some(_)) => Some(I2cArgs::parse(
+            hubris,
+            &subargs.bus,
+            subargs.controller,
+            &subargs.port,
+            &subargs.mux,
+            &subargs.device,
+        )?),
+        _ => None,
+    };
+
+    let mut targets = vec![];
+
+    for device in &hubris.manifest.i2c_devices {
+        if let HubrisI2cDeviceClass::Pmbus { rails } = &device.class {
+            if let Some(ref hargs) = hargs {
+                if !hargs.matches_device(device) {
+                    continue;
+                }
+            }
+
+            let driver = match pmbus::Device::from_str(&device.device) {
+                Some(device) => device,
+                None => pmbus::Device::Common,
+            };
+
+            for (rnum, rail) in rails.iter().enumerate() {
+                if let Some(ref names) = subargs.rail {
+                    if !names.iter().any(|n| *n == rail.name) {
+                        continue;
+                    }
+                }
+
+                let rnum =
+                    if rails.len() > 1 { Some(rnum as u8) } else { None };
+                targets.push((device, driver, rnum, &rail.name));
+            }
+        }
+    }
+
+    if let Some(ref names) = subargs.rail {
+        for name in names {
+            if !targets.iter().any(|(_, _, _, rail)| *rail == name) {
+                bail!("rail {} not found", name);
+            }
+        }
+    }
+
+    if targets.is_empty() {
+        bail!("no PMBus rails found to monitor");
+    }
+
+    let headers = commands
+        .iter()
+        .map(|code| bycode.get(code).unwrap().as_str())
+        .collect::<Vec<_>>();
+
+    let width = 15;
+    let redraw = !subargs.csv && atty::is(atty::Stream::Stdout);
+    let started = Instant::now();
+    let mut first = true;
+
+    if subargs.csv {
+        println!("TIME,DEVICE,RAIL,{}", headers.join(","));
+    }
+
+    loop {
+        let mut work = vec![];
+
+        for (device, driver, rnum, _) in &targets {
+            let mut calls = vec![];
+
+            worker.begin_device(&I2cArgs::from_device(device))?;
+
+            if let Some(rnum) = rnum {
+                worker.select_rail(*rnum);
+                calls.push(page);
+            }
+
+            for &code in std::iter::once(&vout_mode).chain(commands.iter()) {
+                driver.command(code, |cmd| {
+                    let op = cmd.read_op();
+                    if matches!(
+                        op,
+                        pmbus::Operation::ReadByte
+                            | pmbus::Operation::ReadWord
+                            | pmbus::Operation::ReadWord32
+                            | pmbus::Operation::ReadBlock
+                    ) {
+                        worker.read(code, op);
+                        calls.push(code);
+                    }
+                });
+            }
+
+            worker.end_device();
+            work.push(calls);
+        }
+
+        let results = worker.run()?;
+        let elapsed = started.elapsed().as_secs_f64();
+        let mut base = 0;
+
+        if !subargs.csv {
+            if redraw && !first {
+                print!("\x1b[{}A", targets.len() + 1);
+            }
+
+            print!("{:11} {:18}", "DEVICE".bold(), "RAIL".bold());
+
+            for header in &headers {
+                print!(" {:>width$}", header.bold(), width = width);
+            }
+
+            println!();
+        }
+
+        for ((device, driver, _, rail), calls) in targets.iter().zip(&work) {
+            let results = &results[base..base + calls.len()];
+            base += calls.len();
+
+            //
+            // Our rail has been selected if we had to select it; determine
+            // our VOUT_MODE, if we can.  If either of these fail, we can't
+            // meaningfully interpret the rail's values.
+            //
+            let mut mode = None;
+            let mut ok = true;
+
+            for (code, result) in calls.iter().zip(results) {
+                match (*code, result) {
+                    (c, Err(_)) if c == page => ok = false,
+                    (c, Ok(val)) if c == vout_mode => {
+                        mode = VOUT_MODE::CommandData::from_slice(val);
+                    }
+                    _ => {}
+                }
+            }
+
+            let values = commands
+                .iter()
+                .map(|code| {
+                    let ndx = calls.iter().position(|c| c == code)?;
+
+                    match (&results[ndx], mode) {
+                        (Ok(val), Some(mode)) if ok => {
+                            Some(monitor_value(driver, *code, val, mode))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            if subargs.csv {
+                println!(
+                    "{:.3},{},{},{}",
+                    elapsed,
+                    device.device,
+                    rail,
+                    values
+                        .iter()
+                        .map(|v| v.as_deref().unwrap_or(""))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                );
+            } else {
+                print!("{:11} {:18}", device.device, rail);
+
+                for value in &values {
+                    print!(
+                        " {:>width$}",
+                        value.as_deref().unwrap_or("-"),
+                        width = width
+                    );
+                }
+
+                //
+                // Clear to the end of the line, lest a shorter value leave
+                // detritus from a longer one.
+                //
+                if redraw {
+                    print!("\x1b[K");
+                }
+
+                println!();
+            }
+        }
+
+        if !redraw && !subargs.csv {
+            println!();
+        }
+
+        first = false;
+        std::io::stdout().flush()?;
+        thread::sleep(Duration::from_millis(interval));
+    }
+}
+
+///
+/// The SMBus Alert Response Address:  a read from this address is answered
+/// by the alerting device with the lowest address, which then (per SMBus)
+/// deasserts SMBALERT#.
+///
+const ALERT_RESPONSE_ADDRESS: u8 = 0x0c;
+
+///
+/// Returns true if SMBALERT# (which is active low) is asserted on the
+/// specified pin.
+///
+fn alert_asserted(
+    worker: &mut I2cWorker,
+    gpio_input: &HiffyFunction,
+    port: u16,
+    pin: u8,
+) -> Result<bool> {
+    let ops =
+        [Op::Push16(port), Op::Call(gpio_input.id), Op::DropN(1), Op::Done];
+
+    let results = worker.context.run(worker.core, &ops, None)?;
+
+    match &results[0] {
+        Err(code) => {
+            bail!("failed to read alert pin: {}", gpio_input.strerror(*code))
+        }
+        Ok(val) if val.len() >= 2 => {
+            let v = u16::from_le_bytes([val[0], val[1]]);
+            Ok((v >> pin) & 1 == 0)
+        }
+        Ok(val) => bail!("short read of alert pin: {:x?}", val),
+    }
+}
+
+///
+/// Performs an ARA read on the specified bus, returning the address of the
+/// responding device (if any).
+///
+fn alert_respond(worker: &mut I2cWorker, bus: &I2cArgs) -> Result<Option<u8>> {
+    let mut ops = vec![Op::Push(bus.controller), Op::Push(bus.port.index)];
+
+    if let Some((mux, segment)) = bus.mux {
+        ops.push(Op::Push(mux));
+        ops.push(Op::Push(segment));
+    } else {
+        ops.push(Op::PushNone);
+        ops.push(Op::PushNone);
+    }
+
+    ops.push(Op::Push(ALERT_RESPONSE_ADDRESS));
+    ops.push(Op::PushNone);
+    ops.push(Op::Push(1));
+    ops.push(Op::Call(worker.read_func.id));
+    ops.push(Op::DropN(7));
+    ops.push(Op::Done);
+
+    let results = worker.context.run(worker.core, &ops, None)?;
+
+    //
+    // The responding device sends its address in the upper seven bits; the
+    // low bit is unspecified.  If no device responds, the ARA read is not
+    // acknowledged; any other error is a failure to perform the read.
+    //
+    match &results[0] {
+        Ok(val) if val.len() == 1 => Ok(Some(val[0] >> 1)),
+        Ok(val) => bail!("unexpected response to ARA read: {:x?}", val),
+        Err(code) => match worker.read_func.strerror(*code) {
+            err if err == "NoDevice" => Ok(None),
+            err => bail!("ARA read failed: {}", err),
+        },
+    }
+}
+
+///
+/// Dumps the status registers of every rail on an alerting device.
+///
+fn alert_dump(
+    subargs: &PmbusArgs,
+    worker: &mut I2cWorker,
+    hargs: &I2cArgs,
+    driver: pmbus::Device,
+    rails: &[HubrisPmbusRail],
+) -> Result<()> {
+    let page = CommandCode::PAGE as u8;
+    let vout_mode = CommandCode::VOUT_MODE as u8;
+
+    let status = [
+        CommandCode::STATUS_WORD as u8,
+        CommandCode::STATUS_VOUT as u8,
+        CommandCode::STATUS_IOUT as u8,
+        CommandCode::STATUS_INPUT as u8,
+        CommandCode::STATUS_TEMPERATURE as u8,
+        CommandCode::STATUS_CML as u8,
+        CommandCode::STATUS_OTHER as u8,
+        CommandCode::STATUS_MFR_SPECIFIC as u8,
+        CommandCode::STATUS_FANS_1_2 as u8,
+        CommandCode::STATUS_FANS_3_4 as u8,
+    ];
+
+    let rnums = if rails.len() > 1 {
+        (0..rails.len()).map(|rnum| Some(rnum as u8)).collect::<Vec<_>>()
+    } else {
+        vec![None]
+    };
+
+    let mut work = vec![];
+
+    worker.begin_device(hargs)?;
+
+    for rnum in &rnums {
+        let mut calls = vec![];
+
+        if let Some(rnum) = rnum {
+            worker.select_rail(*rnum);
+            calls.push(page);
+        }
+
+        for &code in std::iter::once(&vout_mode).chain(status.iter()) {
+            driver.command(code, |cmd| {
+                let op = cmd.read_op();
+                if matches!(
+                    op,
+                    pmbus::Operation::ReadByte
+                        | pmbus::Operation::ReadWord
+                        | pmbus::Operation::ReadWord32
+                        | pmbus::Operation::ReadBlock
+                ) {
+                    worker.read(code, op);
+                    calls.push(code);
+                }
+            });
+        }
+
+        work.push(calls);
+    }
+
+    worker.end_device();
+
+    let results = worker.run()?;
+    let mut base = 0;
+
+    //
+    // A placeholder for VOUT_MODE, used only to determine whether a result
+    // requires VOUT_MODE to be interpreted.
+    //
+    let unknown = VOUT_MODE::CommandData::from_slice(&[0]).unwrap();
+
+    for (rnum, calls) in rnums.iter().zip(&work) {
+        let results = &results[base..base + calls.len()];
+        base += calls.len();
+
+        if let Some(rnum) = rnum {
+            println!("rail {} ({}):", rnum, rails[*rnum as usize].name);
+        }
+
+        let mut mode = None;
+
+        for (&code, result) in calls.iter().zip(results) {
+            match (code, result) {
+                (c, Err(err)) if c == page => {
+                    println!(
+                        "couldn't set rail: {}",
+                        worker.decode_write_err(*err)
+                    );
+                    break;
+                }
+                (c, _) if c == page => {}
+                (c, Ok(val)) if c == vout_mode => {
+                    mode = VOUT_MODE::CommandData::from_slice(val);
+                }
+                (c, Err(_)) if c == vout_mode => {}
+                (code, result) => {
+                    //
+                    // If we couldn't read VOUT_MODE, determine up front
+                    // whether interpreting this result requires it.
+                    //
+                    if let (None, Ok(val)) = (mode, result) {
+                        let needed = Cell::new(false);
+
+                        let _ = driver.interpret(
+                            code,
+                            val,
+                            || {
+                                needed.set(true);
+                                unknown
+                            },
+                            |_, _| {},
+                        );
+
+                        if needed.get() {
+                            bail!(
+                                "VOUT_MODE not available to interpret \
+                                command 0x{:02x}",
+                                code
+                            );
+                        }
+                    }
+
+                    let mut r = Ok(());
+
+                    driver.command(code, |cmd| {
+                        r = print_result(
+                            subargs,
+                            driver,
+                            code,
+                            || mode.unwrap_or(unknown),
+                            cmd,
+                            result,
+                            &*worker,
+                        );
+                    });
+
+                    r?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn alert(
+    subargs: &PmbusArgs,
+    hubris: &HubrisArchive,
+    worker: &mut I2cWorker,
+    pin: &str,
+) -> Result<()> {
+    let gpio_input = worker.context.get_function("GpioInput", 1)?;
+
+    let Some((port, pin)) = pin.split_once(':') else {
+        bail!("expected alert pin to be specified as port:pin");
+    };
+
+    let port = gpio_input.lookup_argument(hubris, "port", 0, port)?;
+
+    let pin = match parse_int::parse::<u8>(pin) {
+        Ok(pin) if pin < 16 => pin,
+        _ => bail!("invalid pin {}", pin),
+    };
+
+    let bus = I2cArgs::parse(
+        hubris,
+        &subargs.bus,
+        subargs.controller,
+        &subargs.port,
+        &subargs.mux,
+        &None,
+    )?;
+
+    let interval = Duration::from_millis(subargs.alert_interval);
+    let started = Instant::now();
+
+    //
+    // A device that answers the ARA but fails to deassert SMBALERT# would
+    // otherwise have its status dumped in a tight loop; we only dump each
+    // device once until the line deasserts.
+    //
+    let mut responded: Vec<u8> = vec![];
+    let mut unanswered = false;
+
+    msg!("waiting for SMBALERT# on {bus}");
+
+    loop {
+        if !alert_asserted(worker, &gpio_input, port, pin)? {
+            responded.clear();
+            unanswered = false;
+            thread::sleep(interval);
+            continue;
+        }
+
+        let elapsed = started.elapsed().as_secs_f64();
+
+        let Some(address) = alert_respond(worker, &bus)? else {
+            if !unanswered {
+                warn!(
+                    "{elapsed:.3}s: SMBALERT# asserted, but no device \
+                    responded to ARA"
+                );
+                unanswered = true;
+            }
+
+            thread::sleep(interval);
+            continue;
+        };
+
+        if responded.contains(&address) {
+            thread::sleep(interval);
+            continue;
+        }
+
+        responded.push(address);
+
+        let device = hubris
+            .manifest
+            .i2c_devices
+            .iter()
+            .find(|d| bus.matches_device(d) && d.address == address);
+
+        let (hargs, driver, rails) = match device {
+            Some(device) => {
+                let driver = pmbus::Device::from_str(&device.device)
+                    .unwrap_or(pmbus::Device::Common);
+
+                let rails = match &device.class {
+                    HubrisI2cDeviceClass::Pmbus { rails } => rails.as_slice(),
+                    _ => &[],
+                };
+
+                (I2cArgs::from_device(device), driver, rails)
+            }
+            None => {
+                let hargs = I2cArgs {
+                    controller: bus.controller,
+                    port: bus.port,
+                    mux: bus.mux,
+                    device: None,
+                    address: Some(address),
+                    class: bus.class,
+                };
 
-    Ok(())
+                (hargs, pmbus::Device::Common, &[][..])
+            }
+        };
+
+        println!(
+            "{:.3}s: {} ({}) responded to ARA",
+            elapsed,
+            hargs,
+            hargs.device.as_deref().unwrap_or("unknown device")
+        );
+
+        alert_dump(subargs, worker, &hargs, driver, rails)?;
+        println!();
+        std::io::stdout().flush()?;
+    }
 }
 
 fn find_rail<'a>(
@@ -1401,6 +2148,614 @@ fn writes(
     Ok(())
 }
 
+/// A snapshot of the writable state of a PMBus device, as captured by
+/// `--save` and reapplied by `--restore`
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    /// The PMBus driver used to interpret the device, if not the common one
+    driver: Option<String>,
+
+    /// The location of the device at the time of the snapshot
+    device: String,
+
+    rails: Vec<SnapshotRail>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotRail {
+    rail: Option<u8>,
+    name: Option<String>,
+    commands: Vec<SnapshotCommand>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotCommand {
+    code: u8,
+    command: String,
+    data: Vec<u8>,
+}
+
+/// The device (and its rails) that is the subject of a snapshot
+struct SnapshotTarget<'a> {
+    hargs: I2cArgs<'a>,
+    driver: Option<String>,
+    device: pmbus::Device,
+    rails: Vec<(Option<u8>, Option<String>)>,
+}
+
+impl<'a> SnapshotTarget<'a> {
+    fn new(subargs: &PmbusArgs, hubris: &'a HubrisArchive) -> Result<Self> {
+        let (hargs, rail) = match (&subargs.rail, &subargs.device) {
+            (Some(rails), None) => {
+                if rails.len() > 1 {
+                    bail!("cannot specify more than one rail");
+                }
+
+                find_rail(hubris, &rails[0])?
+            }
+
+            (_, _) => (
+                I2cArgs::parse(
+                    hubris,
+                    &subargs.bus,
+                    subargs.controller,
+                    &subargs.port,
+                    &subargs.mux,
+                    &subargs.device,
+                )?,
+                None,
+            ),
+        };
+
+        if hargs.address.is_none() {
+            bail!("must specify a device or a rail");
+        }
+
+        if let Some(driver) = &subargs.driver {
+            if pmbus::Device::from_str(driver).is_none() {
+                bail!(Diagnostic::error(format!(
+                    "unknown device \"{}\"",
+                    driver
+                ))
+                .code("unknown-driver")
+                .hint("use -l (\"--list\") to list devices and drivers"));
+            }
+        }
+
+        let driver = subargs
+            .driver
+            .as_ref()
+            .or(hargs.device.as_ref())
+            .filter(|driver| pmbus::Device::from_str(driver).is_some())
+            .cloned();
+
+        let device = match &driver {
+            Some(driver) => pmbus::Device::from_str(driver).unwrap(),
+            None => pmbus::Device::Common,
+        };
+
+        let rails = match hargs.class {
+            HubrisI2cDeviceClass::Pmbus { rails } => rails.as_slice(),
+            _ if subargs.force => &[],
+            _ => {
+                bail!(Diagnostic::error("not a recognized PMBus device")
+                    .code("not-pmbus")
+                    .hint("use -F (\"--force\") to force")
+                    .hint("use -D (\"--driver\") to specify a driver"));
+            }
+        };
+
+        //
+        // If we were given both a device and a rail, the rail can be
+        // specified by name or by number.
+        //
+        let rail = match (rail, &subargs.rail) {
+            (None, Some(r)) if rails.len() > 1 => {
+                let r = &r[0];
+
+                match rails.iter().position(|rail| rail.name == *r) {
+                    Some(rnum) => Some(rnum as u8),
+                    None => match parse_int::parse::<u8>(r) {
+                        Ok(rnum) if (rnum as usize) < rails.len() => Some(rnum),
+                        _ => bail!("invalid rail {}", r),
+                    },
+                }
+            }
+            (rail, _) => rail,
+        };
+
+        let rails = match rail {
+            Some(rnum) => {
+                vec![(Some(rnum), Some(rails[rnum as usize].name.clone()))]
+            }
+            None if rails.len() > 1 => rails
+                .iter()
+                .enumerate()
+                .map(|(rnum, r)| (Some(rnum as u8), Some(r.name.clone())))
+                .collect(),
+            None => vec![(None, rails.first().map(|r| r.name.clone()))],
+        };
+
+        Ok(Self { hargs, driver, device, rails })
+    }
+
+    fn label(&self, rail: &Option<String>) -> String {
+        match rail {
+            Some(name) => format!("{}, rail {}", self.hargs, name),
+            None => format!("{}", self.hargs),
+        }
+    }
+}
+
+///
+/// The commands that are saved and restored:  the standard PMBus
+/// configuration commands (output voltage, limits, fault responses, timing
+/// and fan configuration).  Manufacturer-specific commands are deliberately
+/// absent, as these include data ports and indirect memory access (e.g.,
+/// DMAADDR, DMAFIX and DMASEQ on Renesas parts) for which a read can have side
+/// effects and a write can modify arbitrary device memory.  `PAGE` is handled
+/// by rail selection; `OPERATION` and `WRITE_PROTECT` are absent as restoring
+/// them could respectively turn a rail on or off or prevent the remaining
+/// commands from being restored.
+///
+const SNAPSHOT_COMMANDS: &[CommandCode] = &[
+    CommandCode::ON_OFF_CONFIG,
+    CommandCode::VOUT_MODE,
+    CommandCode::VOUT_COMMAND,
+    CommandCode::VOUT_TRIM,
+    CommandCode::VOUT_CAL_OFFSET,
+    CommandCode::VOUT_MAX,
+    CommandCode::VOUT_MARGIN_HIGH,
+    CommandCode::VOUT_MARGIN_LOW,
+    CommandCode::VOUT_TRANSITION_RATE,
+    CommandCode::VOUT_DROOP,
+    CommandCode::VOUT_SCALE_LOOP,
+    CommandCode::VOUT_SCALE_MONITOR,
+    CommandCode::VOUT_MIN,
+    CommandCode::POUT_MAX,
+    CommandCode::MAX_DUTY,
+    CommandCode::FREQUENCY_SWITCH,
+    CommandCode::POWER_MODE,
+    CommandCode::VIN_ON,
+    CommandCode::VIN_OFF,
+    CommandCode::INTERLEAVE,
+    CommandCode::IOUT_CAL_GAIN,
+    CommandCode::IOUT_CAL_OFFSET,
+    CommandCode::FAN_CONFIG_1_2,
+    CommandCode::FAN_COMMAND_1,
+    CommandCode::FAN_COMMAND_2,
+    CommandCode::FAN_CONFIG_3_4,
+    CommandCode::FAN_COMMAND_3,
+    CommandCode::FAN_COMMAND_4,
+    CommandCode::VOUT_OV_FAULT_LIMIT,
+    CommandCode::VOUT_OV_FAULT_RESPONSE,
+    CommandCode::VOUT_OV_WARN_LIMIT,
+    CommandCode::VOUT_UV_WARN_LIMIT,
+    CommandCode::VOUT_UV_FAULT_LIMIT,
+    CommandCode::VOUT_UV_FAULT_RESPONSE,
+    CommandCode::IOUT_OC_FAULT_LIMIT,
+    CommandCode::IOUT_OC_FAULT_RESPONSE,
+    CommandCode::IOUT_OC_LV_FAULT_LIMIT,
+    CommandCode::IOUT_OC_LV_FAULT_RESPONSE,
+    CommandCode::IOUT_OC_WARN_LIMIT,
+    CommandCode::IOUT_UC_FAULT_LIMIT,
+    CommandCode::IOUT_UC_FAULT_RESPONSE,
+    CommandCode::OT_FAULT_LIMIT,
+    CommandCode::OT_FAULT_RESPONSE,
+    CommandCode::OT_WARN_LIMIT,
+    CommandCode::UT_WARN_LIMIT,
+    CommandCode::UT_FAULT_LIMIT,
+    CommandCode::UT_FAULT_RESPONSE,
+    CommandCode::VIN_OV_FAULT_LIMIT,
+    CommandCode::VIN_OV_FAULT_RESPONSE,
+    CommandCode::VIN_OV_WARN_LIMIT,
+    CommandCode::VIN_UV_WARN_LIMIT,
+    CommandCode::VIN_UV_FAULT_LIMIT,
+    CommandCode::VIN_UV_FAULT_RESPONSE,
+    CommandCode::IIN_OC_FAULT_LIMIT,
+    CommandCode::IIN_OC_FAULT_RESPONSE,
+    CommandCode::IIN_OC_WARN_LIMIT,
+    CommandCode::POWER_GOOD_ON,
+    CommandCode::POWER_GOOD_OFF,
+    CommandCode::TON_DELAY,
+    CommandCode::TON_RISE,
+    CommandCode::TON_MAX_FAULT_LIMIT,
+    CommandCode::TON_MAX_FAULT_RESPONSE,
+    CommandCode::TOFF_DELAY,
+    CommandCode::TOFF_FALL,
+    CommandCode::TOFF_MAX_WARN_LIMIT,
+    CommandCode::POUT_OP_FAULT_LIMIT,
+    CommandCode::POUT_OP_FAULT_RESPONSE,
+    CommandCode::POUT_OP_WARN_LIMIT,
+    CommandCode::PIN_OP_WARN_LIMIT,
+];
+
+///
+/// Returns the read operation that captures the state of the specified
+/// command, if that command is in [`SNAPSHOT_COMMANDS`] and is both readable
+/// and writable on the device.
+///
+fn snapshot_op(code: u8, cmd: &dyn pmbus::Command) -> Option<pmbus::Operation> {
+    use pmbus::Operation::*;
+
+    if !SNAPSHOT_COMMANDS.iter().any(|&c| c as u8 == code) {
+        return None;
+    }
+
+    match (cmd.write_op(), cmd.read_op()) {
+        (WriteByte, ReadByte) => Some(ReadByte),
+        (WriteWord, ReadWord) => Some(ReadWord),
+        (WriteWord32, ReadWord32) => Some(ReadWord32),
+        (WriteBlock, ReadBlock) => Some(ReadBlock),
+        _ => None,
+    }
+}
+
+fn snapshot_bytes(data: &[u8]) -> String {
+    data.iter().map(|b| format!("0x{:02x}", b)).collect::<Vec<_>>().join(",")
+}
+
+fn save(
+    subargs: &PmbusArgs,
+    hubris: &HubrisArchive,
+    worker: &mut dyn PmbusWorker,
+    filename: &str,
+) -> Result<()> {
+    let target = SnapshotTarget::new(subargs, hubris)?;
+    let mut writable = vec![];
+
+    for code in 0..=255u8 {
+        target.device.command(code, |cmd| {
+            if let Some(op) = snapshot_op(code, cmd) {
+                writable.push((code, cmd.name().to_string(), op));
+            }
+        });
+    }
+
+    let mut snapshot = Snapshot {
+        driver: target.driver.clone(),
+        device: target.hargs.to_string(),
+        rails: vec![],
+    };
+
+    let mut total = 0;
+
+    for (rail, name) in &target.rails {
+        worker.begin_device(&target.hargs)?;
+
+        if let Some(rnum) = rail {
+            worker.select_rail(*rnum);
+        }
+
+        for (code, _, op) in &writable {
+            worker.read(*code, *op);
+        }
+
+        worker.end_device();
+
+        let results = worker.run()?;
+        let label = target.label(name);
+
+        let results = if rail.is_some() {
+            if let Err(code) = results[0] {
+                bail!(
+                    "{label}: failed to set rail: {}",
+                    worker.decode_write_err(code)
+                );
+            }
+
+            &results[1..]
+        } else {
+            &results[..]
+        };
+
+        let mut commands = vec![];
+
+        //
+        // Devices will generally not implement every command that their
+        // driver knows about; commands that can't be read are not part of
+        // the device's state, and are skipped.
+        //
+        for ((code, command, _), result) in writable.iter().zip(results) {
+            match result {
+                Ok(data) => commands.push(SnapshotCommand {
+                    code: *code,
+                    command: command.clone(),
+                    data: data.clone(),
+                }),
+                Err(err) if subargs.errors => {
+                    humility::msg!(
+                        "{label}: skipping {command}: {}",
+                        worker.decode_read_err(*err)
+                    );
+                }
+                Err(_) => {}
+            }
+        }
+
+        total += commands.len();
+
+        snapshot.rails.push(SnapshotRail {
+            rail: *rail,
+            name: name.clone(),
+            commands,
+        });
+    }
+
+    let mut file = std::fs::File::create(filename)?;
+    serde_json::to_writer_pretty(&mut file, &snapshot)?;
+    writeln!(file)?;
+
+    humility::msg!(
+        "saved {total} commands from {} to {filename}",
+        target.hargs
+    );
+
+    Ok(())
+}
+
+fn restore(
+    subargs: &PmbusArgs,
+    hubris: &HubrisArchive,
+    worker: &mut dyn PmbusWorker,
+    filename: &str,
+) -> Result<()> {
+    let contents = std::fs::read_to_string(filename)
+        .with_context(|| format!("failed to read {filename}"))?;
+    let snapshot: Snapshot = serde_json::from_str(&contents)
+        .with_context(|| format!("failed to parse snapshot {filename}"))?;
+
+    let target = SnapshotTarget::new(subargs, hubris)?;
+    let device = target.device;
+
+    if snapshot.driver != target.driver && !subargs.force {
+        bail!(Diagnostic::error(format!(
+            "snapshot of {} was taken with driver {}, but {} uses {}",
+            snapshot.device,
+            snapshot.driver.as_deref().unwrap_or("common"),
+            target.hargs,
+            target.driver.as_deref().unwrap_or("common"),
+        ))
+        .code("snapshot-mismatch")
+        .hint("use -F (\"--force\") to restore anyway"));
+    }
+
+    let mut failures = 0;
+
+    for (rail, name) in &target.rails {
+        let label = target.label(name);
+
+        let srail = match snapshot.rails.iter().find(|r| r.rail == *rail) {
+            Some(srail) => srail,
+            None => {
+                warn!("{label}: not found in snapshot; skipping");
+                continue;
+            }
+        };
+
+        //
+        // Make sure that every command in the snapshot means the same thing
+        // to this device, and determine the size of each.
+        //
+        let mut ops = vec![];
+
+        for scmd in &srail.commands {
+            let mut op = None;
+
+            device.command(scmd.code, |cmd| {
+                if cmd.name() == scmd.command {
+                    op = snapshot_op(scmd.code, cmd);
+                }
+            });
+
+            match op {
+                Some(op) => ops.push(op),
+                None => {
+                    bail!(
+                        "{label}: {} (0x{:02x}) is not writable state \
+                        on this device",
+                        scmd.command,
+                        scmd.code
+                    );
+                }
+            }
+        }
+
+        let select = |worker: &mut dyn PmbusWorker| -> Result<()> {
+            worker.begin_device(&target.hargs)?;
+
+            if let Some(rnum) = rail {
+                worker.select_rail(*rnum);
+            }
+
+            Ok(())
+        };
+
+        let check = |worker: &dyn PmbusWorker,
+                     results: Vec<Result<Vec<u8>, u32>>|
+         -> Result<Vec<Result<Vec<u8>, u32>>> {
+            let mut results = results;
+
+            if rail.is_some() {
+                if let Err(code) = results[0] {
+                    bail!(
+                        "{label}: failed to set rail: {}",
+                        worker.decode_write_err(code)
+                    );
+                }
+
+                results.remove(0);
+            }
+
+            Ok(results)
+        };
+
+        //
+        // First, read the current state, so we only write what differs.
+        //
+        select(worker)?;
+
+        for (scmd, op) in srail.commands.iter().zip(&ops) {
+            worker.read(scmd.code, *op);
+        }
+
+        worker.end_device();
+
+        let results = worker.run()?;
+        let current = check(worker, results)?;
+
+        let mut changes = vec![];
+
+        for ((scmd, op), cur) in srail.commands.iter().zip(&ops).zip(&current) {
+            match cur {
+                Ok(val) if *val == scmd.data => continue,
+                Ok(val) => {
+                    if subargs.dryrun || subargs.verbose {
+                        humility::msg!(
+                            "{label}: {}: {} -> {}",
+                            scmd.command,
+                            snapshot_bytes(val),
+                            snapshot_bytes(&scmd.data)
+                        );
+                    }
+                }
+                Err(_) => {
+                    if subargs.dryrun || subargs.verbose {
+                        humility::msg!(
+                            "{label}: {}: <unreadable> -> {}",
+                            scmd.command,
+                            snapshot_bytes(&scmd.data)
+                        );
+                    }
+                }
+            }
+
+            let write = match (op, scmd.data.len()) {
+                (pmbus::Operation::ReadByte, 1) => {
+                    WriteOp::SetByte(scmd.data[0])
+                }
+                (pmbus::Operation::ReadWord, 2) => WriteOp::SetWord(
+                    u16::from_le_bytes(scmd.data[..2].try_into().unwrap()),
+                ),
+                (pmbus::Operation::ReadWord32, 4) => WriteOp::SetWord32(
+                    u32::from_le_bytes(scmd.data[..4].try_into().unwrap()),
+                ),
+                (pmbus::Operation::ReadBlock, _) => {
+                    if !worker.can_write_block() {
+                        warn!(
+                            "{label}: cannot restore block command {} \
+                            with this agent; skipping",
+                            scmd.command
+                        );
+                        failures += 1;
+                        continue;
+                    }
+
+                    WriteOp::SetBlock(scmd.data.clone())
+                }
+                (_, len) => {
+                    bail!(
+                        "{label}: {} has bad length {len} in snapshot",
+                        scmd.command
+                    );
+                }
+            };
+
+            changes.push((scmd, op, write));
+        }
+
+        let unchanged = srail.commands.len() - changes.len();
+
+        if subargs.dryrun {
+            humility::msg!(
+                "{label}: would write {} commands ({unchanged} unchanged)",
+                changes.len()
+            );
+            continue;
+        }
+
+        if changes.is_empty() {
+            humility::msg!("{label}: all {unchanged} commands already match");
+            continue;
+        }
+
+        //
+        // Now perform our writes...
+        //
+        select(worker)?;
+
+        for (scmd, _, write) in &changes {
+            worker.write(scmd.code, write);
+        }
+
+        worker.end_device();
+
+        let results = worker.run()?;
+        let written = check(worker, results)?;
+
+        //
+        // ...and read back everything we wrote to verify it.
+        //
+        select(worker)?;
+
+        for (scmd, op, _) in &changes {
+            worker.read(scmd.code, **op);
+        }
+
+        worker.end_device();
+
+        let results = worker.run()?;
+        let readback = check(worker, results)?;
+        let mut restored = 0;
+
+        for (((scmd, _, _), w), r) in
+            changes.iter().zip(&written).zip(&readback)
+        {
+            match (w, r) {
+                (Err(code), _) => {
+                    warn!(
+                        "{label}: failed to write {}: {}",
+                        scmd.command,
+                        worker.decode_write_err(*code)
+                    );
+                    failures += 1;
+                }
+                (Ok(_), Err(code)) => {
+                    warn!(
+                        "{label}: failed to verify {}: {}",
+                        scmd.command,
+                        worker.decode_read_err(*code)
+                    );
+                    failures += 1;
+                }
+                (Ok(_), Ok(val)) if *val != scmd.data => {
+                    warn!(
+                        "{label}: {} reads back as {}, expected {}",
+                        scmd.command,
+                        snapshot_bytes(val),
+                        snapshot_bytes(&scmd.data)
+                    );
+                    failures += 1;
+                }
+                (Ok(_), Ok(_)) => {
+                    restored += 1;
+                }
+            }
+        }
+
+        humility::msg!(
+            "{label}: restored {restored}/{} commands ({unchanged} unchanged)",
+            changes.len()
+        );
+    }
+
+    if failures > 0 {
+        bail!("failed to restore {failures} command(s) from {filename}");
+    }
+
+    Ok(())
+}
+
 /// Functions necessary to perform PMBus work
 trait PmbusWorker {
     /// Called to set up function calls to point to the given device
@@ -1425,6 +2780,9 @@ trait PmbusWorker {
 
     fn decode_read_err(&self, code: u32) -> String;
     fn decode_write_err(&self, code: u32) -> String;
+
+    /// Indicates whether this worker can perform block writes
+    fn can_write_block(&self) -> bool;
 }
 
 struct I2cWorker<'a> {
@@ -1500,7 +2858,14 @@ impl PmbusWorker for I2cWorker<'_> {
     fn run(&mut self) -> Result<Vec<Result<Vec<u8>, u32>>> {
         self.ops.push(Op::Done);
         let ops = std::mem::take(&mut self.ops);
-        self.context.run(self.core, &ops, None)
+
+        //
+        // Programs that select a page and then read can be retried; any
+        // program with another write is run exactly once.
+        //
+        I2cRetryPolicy::default()
+            .idempotent(None, Some(CommandCode::PAGE as u8))
+            .run(&mut self.context, self.core, &ops, None, &self.read_func)
     }
 
     fn decode_read_err(&self, code: u32) -> String {
@@ -1511,6 +2876,10 @@ impl PmbusWorker for I2cWorker<'_> {
         self.read_func.strerror(code)
     }
 
+    fn can_write_block(&self) -> bool {
+        true
+    }
+
     fn write(&mut self, code: u8, op: &WriteOp) {
         match op {
             WriteOp::SetBlock(payload) => {
@@ -1835,6 +3204,11 @@ impl PmbusWorker for IdolWorker<'_> {
         self.write_byte.strerror(code)
     }
 
+    fn can_write_block(&self) -> bool {
+        // We have no way of encoding a block as an Idol argument
+        false
+    }
+
     fn run(&mut self) -> Result<Vec<Result<Vec<u8>, u32>>> {
         self.ops.push(Op::Done);
         let ops = std::mem::take(&mut self.ops);
@@ -1878,7 +3252,8 @@ impl PmbusWorker for IdolWorker<'_> {
 fn pmbus(context: &mut ExecutionContext) -> Result<()> {
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
     let hubris = context.archive.as_ref().unwrap();
-    let subargs = PmbusArgs::try_parse_from(subargs)?;
+    let mut subargs = PmbusArgs::try_parse_from(subargs)?;
+    subargs.dryrun |= context.cli.dry_run;
 
     if subargs.list {
         println!(
@@ -1921,6 +3296,23 @@ fn pmbus(context: &mut ExecutionContext) -> Result<()> {
 
     let timeout = subargs.timeout;
 
+    //
+    // Alert capture requires raw I2C (to read from the Alert Response
+    // Address), so it can only be done with the I2C agent.
+    //
+    if let Some(ref pin) = subargs.alert {
+        if core.is_net() {
+            bail!("cannot capture alerts over the network");
+        }
+
+        if let Agent::Idol = subargs.agent {
+            bail!("cannot capture alerts with the idol agent");
+        }
+
+        let mut worker = I2cWorker::new(hubris, core, timeout)?;
+        return alert(&subargs, hubris, &mut worker, pin);
+    }
+
     // Pick an implementation based on our flags and core state
     let mut worker: Box<dyn PmbusWorker> = match subargs.agent {
         Agent::Auto => {
@@ -1949,6 +3341,18 @@ fn pmbus(context: &mut ExecutionContext) -> Result<()> {
         }
     };
 
+    if let Some(interval) = subargs.monitor {
+        return monitor(&subargs, hubris, worker.as_mut(), interval);
+    }
+
+    if let Some(ref filename) = subargs.save {
+        return save(&subargs, hubris, worker.as_mut(), filename);
+    }
+
+    if let Some(ref filename) = subargs.restore {
+        return restore(&subargs, hubris, worker.as_mut(), filename);
+    }
+
     if subargs.summarize {
         summarize(&subargs, hubris, worker.as_mut())?;
         return Ok(());
@@ -1990,7 +3394,12 @@ fn pmbus_main(
         match pmbus::Device::from_str(driver) {
             Some(device) => device,
             None => {
-                bail!("unknown device \"{}\"", driver);
+                bail!(Diagnostic::error(format!(
+                    "unknown device \"{}\"",
+                    driver
+                ))
+                .code("unknown-driver")
+                .hint("use -l (\"--list\") to list devices and drivers"));
             }
         }
     } else if let Some(driver) = &hargs.device {
@@ -2046,7 +3455,10 @@ fn pmbus_main(
         HubrisI2cDeviceClass::Pmbus { rails } => Some(rails),
         _ => {
             if !subargs.force {
-                bail!("not a recognized PMBus device; -F to force");
+                bail!(Diagnostic::error("not a recognized PMBus device")
+                    .code("not-pmbus")
+                    .hint("use -F (\"--force\") to force")
+                    .hint("use -D (\"--driver\") to specify a driver"));
             } else {
                 None
             }
@@ -2068,11 +3480,12 @@ fn pmbus_main(
             } else if let Ok(code) = parse_int::parse::<u8>(cmd) {
                 run[code as usize] = true;
             } else {
-                bail!(
-                    "unrecognized PMBus command {}; \
-                     use -H for command help",
+                bail!(Diagnostic::error(format!(
+                    "unrecognized PMBus command {}",
                     cmd
-                );
+                ))
+                .code("unknown-command")
+                .hint("use -H for command help"));
             }
         }
     }
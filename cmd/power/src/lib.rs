@@ -13,9 +13,40 @@
 //! displayed.  Some rails can determine current by output phase; to display
 //! these, use the `--phase-current` option.
 //!
+//! To get a summary of the health of the entire power tree, use `--status`
+//! (`-s`).  In addition to the sensor values, this reads the status and
+//! commanded output voltage directly from the controller of every PMBus rail
+//! in the system (including rails without sensors), computes output power,
+//! and highlights any anomalies:  rails that are off, rails that have faults
+//! latched, and rails whose output voltage deviates from the commanded
+//! voltage by more than a tolerance (5% by default; this can be changed with
+//! `--tolerance`).  Any latched faults are listed beneath their rail:
+//!
+//! ```console
+//! $ humility power --status
+//! humility: attached via ST-Link V3
+//! RAIL                               VOUT     IOUT     POUT      VIN      IIN     TEMP STATUS
+//! V12_SYS_A2                       11.995   19.250  230.904   53.625        -   35.750 ok
+//! V1P8_SYS_A2                       1.797    3.316    5.959        -        -   34.750 ok
+//! V3P3_SYS_A0                       3.309    1.457    4.821        -        -   36.250 ok
+//! VDDCR_SOC                         0.890   18.200   16.198        -        -   42.000 ok
+//! VDD_MEM_ABCD                      1.224   28.600   35.006        -        -   41.000 ok
+//! VDD_MEM_EFGH                      0.000    0.000    0.000        -        -   43.000 off
+//! VDD_VCORE                         1.181   53.200   62.829        -        -   40.000 ok
+//! VPP_ABCD                          2.291    0.400    0.916        -        -   40.000 vout -8.4%, 1 fault
+//!                                   |
+//!                                   +--- Output voltage fault
+//! ```
+//!
+//! As `--status` reads PMBus devices directly, it requires a debugger
+//! connection.  Note that a rail that has been deliberately margined will be
+//! flagged as out of tolerance, as the deviation is measured against
+//! `VOUT_COMMAND`.
+//!
 
 use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
+use colored::Colorize;
 use hif::*;
 use humility::core::Core;
 use humility::hubris::*;
@@ -24,6 +55,7 @@ use humility_cmd::CommandKind;
 use humility_cmd::{Archive, Attach, Command, Validate};
 use humility_hiffy::*;
 use humility_idol::{self as idol, HubrisIdol};
+use humility_pmbus::RailStatus;
 use std::collections::BTreeMap;
 
 #[derive(Parser, Debug)]
@@ -39,6 +71,19 @@ struct PowerArgs {
     /// get phase current where available
     #[clap(long)]
     phase_current: bool,
+
+    /// read PMBus status of every rail, highlighting anomalies
+    #[clap(long, short)]
+    status: bool,
+
+    /// tolerance for output voltage deviation, in percent
+    #[clap(
+        long,
+        default_value_t = 5.0,
+        value_name = "percent",
+        requires = "status"
+    )]
+    tolerance: f32,
 }
 
 struct Device<'a> {
@@ -51,6 +96,100 @@ struct Device<'a> {
     temperature: Option<usize>,
     phases: Option<&'a Vec<u8>>,
     phase_currents: Option<Vec<Option<f32>>>,
+    status: Option<RailStatus>,
+}
+
+impl<'a> Device<'a> {
+    fn new(name: &'a str, phases: Option<&'a Vec<u8>>) -> Self {
+        Self {
+            name,
+            rail: None,
+            voltage: None,
+            current: None,
+            input_voltage: None,
+            input_current: None,
+            temperature: None,
+            phases,
+            phase_currents: None,
+            status: None,
+        }
+    }
+}
+
+///
+/// Summarizes the status of a rail, highlighting any anomalies
+///
+fn rail_summary(status: &RailStatus, tolerance: f32) -> String {
+    if let Some(error) = &status.error {
+        return error.red().to_string();
+    }
+
+    let mut anomalies = vec![];
+
+    if status.off {
+        anomalies.push("off".yellow().to_string());
+    }
+
+    if !status.off {
+        if let Some(deviation) = status.deviation() {
+            if deviation.abs() > tolerance {
+                anomalies
+                    .push(format!("vout {deviation:+.1}%").red().to_string());
+            }
+        }
+    }
+
+    match status.faults.len() {
+        0 => {}
+        1 => anomalies.push("1 fault".red().to_string()),
+        n => anomalies.push(format!("{n} faults").red().to_string()),
+    }
+
+    if anomalies.is_empty() {
+        "ok".green().to_string()
+    } else {
+        anomalies.join(", ")
+    }
+}
+
+///
+/// Reads the status of every rail that is backed by a PMBus device.
+///
+fn rail_status(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    devices: &mut BTreeMap<(&String, HubrisSensorDevice), Device>,
+) -> Result<()> {
+    let mut rails = vec![];
+    let mut names = vec![];
+
+    for ((_, sensor_device), device) in devices.iter() {
+        let HubrisSensorDevice::I2c(i) = sensor_device else {
+            continue;
+        };
+
+        let d = &hubris.manifest.i2c_devices[*i];
+
+        let HubrisI2cDeviceClass::Pmbus { rails: all } = &d.class else {
+            continue;
+        };
+
+        if let Some(rnum) = all.iter().position(|r| r.name == device.name) {
+            rails.push((d, rnum));
+            names.push(device.name);
+        }
+    }
+
+    let statuses = humility_pmbus::rail_status(core, context, &rails)?;
+    let mut statuses =
+        names.into_iter().zip(statuses).collect::<BTreeMap<_, _>>();
+
+    for device in devices.values_mut() {
+        device.status = statuses.remove(device.name);
+    }
+
+    Ok(())
 }
 
 fn phase_currents(
@@ -118,6 +257,10 @@ fn power(context: &mut ExecutionContext) -> Result<()> {
 
     let subargs = PowerArgs::try_parse_from(subargs)?;
 
+    if subargs.status && core.is_net() {
+        bail!("--status requires a debugger connection");
+    }
+
     let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
     let mut ops = vec![];
     let op = hubris.get_idol_command("Sensor.get")?;
@@ -154,28 +297,28 @@ fn power(context: &mut ExecutionContext) -> Result<()> {
             }
 
             let key = (&s.name, s.device.clone());
-            if devices
-                .insert(
-                    key,
-                    Device {
-                        name: &s.name,
-                        rail: None,
-                        voltage: None,
-                        current: None,
-                        input_voltage: None,
-                        input_current: None,
-                        temperature: None,
-                        phases,
-                        phase_currents: None,
-                    },
-                )
-                .is_some()
-            {
+            if devices.insert(key, Device::new(&s.name, phases)).is_some() {
                 bail!("Duplicate voltage sensor: {s:?}");
             }
         }
     }
 
+    //
+    // If we have been asked for status, we want every PMBus rail -- even
+    // those that don't have sensors.
+    //
+    if subargs.status {
+        for (i, d) in hubris.manifest.i2c_devices.iter().enumerate() {
+            if let HubrisI2cDeviceClass::Pmbus { rails } = &d.class {
+                for r in rails {
+                    devices
+                        .entry((&r.name, HubrisSensorDevice::I2c(i)))
+                        .or_insert_with(|| Device::new(&r.name, None));
+                }
+            }
+        }
+    }
+
     let mut ndx = 0;
 
     for (i, s) in hubris.manifest.sensors.iter().enumerate() {
@@ -230,10 +373,19 @@ fn power(context: &mut ExecutionContext) -> Result<()> {
         phase_currents(hubris, core, &mut context, &mut devices)?;
     }
 
-    println!(
-        "{:30} {:>8} {:>8} {:>8} {:>8} {:>8}",
-        "RAIL", "VOUT", "IOUT", "VIN", "IIN", "TEMP"
-    );
+    if subargs.status {
+        rail_status(hubris, core, &mut context, &mut devices)?;
+
+        println!(
+            "{:30} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} STATUS",
+            "RAIL", "VOUT", "IOUT", "POUT", "VIN", "IIN", "TEMP"
+        );
+    } else {
+        println!(
+            "{:30} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            "RAIL", "VOUT", "IOUT", "VIN", "IIN", "TEMP"
+        );
+    }
 
     let no = "-";
     let err = "x";
@@ -261,12 +413,42 @@ fn power(context: &mut ExecutionContext) -> Result<()> {
         p(d.voltage);
         p(d.current);
 
+        if subargs.status {
+            match (d.voltage, d.current) {
+                (Some(v), Some(i)) => match (rval[v], rval[i]) {
+                    (Some(v), Some(i)) => print!(" {:>8.3}", v * i),
+                    _ => print!(" {err:>8}"),
+                },
+                _ => print!(" {no:>8}"),
+            }
+        }
+
         p(d.input_voltage);
         p(d.input_current);
         p(d.temperature);
 
+        if subargs.status {
+            match &d.status {
+                Some(status) => {
+                    print!(" {}", rail_summary(status, subargs.tolerance))
+                }
+                None => print!(" {no}"),
+            }
+        }
+
         println!();
 
+        if let Some(status) = &d.status {
+            if let Some((first, rest)) = status.faults.split_first() {
+                println!("{:35}|", "");
+                println!("{:35}+--- {}", "", first);
+
+                for fault in rest {
+                    println!("{:35}     {}", "", fault);
+                }
+            }
+        }
+
         if let Some(phase_currents) = &d.phase_currents {
             if phase_currents.len() > 1 {
                 for (index, value) in phase_currents.iter().enumerate() {
@@ -278,7 +460,7 @@ fn power(context: &mut ExecutionContext) -> Result<()> {
                         print!("{name:30} {no:>8} {err:>8}");
                     }
 
-                    for _ in 0..3 {
+                    for _ in 0..if subargs.status { 4 } else { 3 } {
                         print!(" {no:>8}");
                     }
 
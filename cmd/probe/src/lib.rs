@@ -85,6 +85,49 @@
 //! humility:          PSP => 0x20001ba8
 //! humility:          SPR => 0x7000000
 //! ```
+//!
+//! `humility probe` can also serve the attached core to other instances of
+//! Humility via `--serve`, which takes an address to bind to or merely a port,
+//! in which case only the loopback interface is bound.  Another machine can
+//! then operate on the target by specifying a probe of the form
+//! `remote:host[:port]` -- most safely by forwarding the port over SSH:
+//!
+//! ```console
+//! lab0$ humility probe --serve 7878
+//! humility: attached via ST-Link V3
+//! humility: serving STLink V3, VID 0483, PID 374e on 127.0.0.1:7878
+//! ...
+//! laptop$ ssh -N -L 7878:localhost:7878 lab0 &
+//! laptop$ humility -a build-gimlet.zip -p remote:localhost tasks
+//! humility: attached via STLink V3, VID 0483, PID 374e on localhost:7878
+//! ...
+//! ```
+//!
+//! The server is unauthenticated:  anyone who can connect to it can read and
+//! write the target's memory and registers, halt and reset it, and flash it.
+//! To listen on other interfaces, specify the address explicitly (e.g.,
+//! `--serve 0.0.0.0:7878`), and only do so on a trusted network.  As serving
+//! the target allows it to be modified, `probe` is a mutating command, and is
+//! recorded in the audit log when the server exits.
+//!
+//! The server services one client at a time until it is interrupted with ^C;
+//! should a client disconnect with the target halted, the target is resumed.
+//!
+//! On a target with multiple cores (e.g., an STM32H7 dual core part), the
+//! core to probe can be selected with `humility --core`; the index of the
+//! probed core is then displayed:
+//!
+//! ```console
+//! $ humility --core 1 probe
+//! humility: attached via ST-Link V3
+//! humility: operating on core 1 (Armv7em)
+//! humility:        probe => STLink V3, VID 0483, PID 374e
+//! humility: probe serial => 003700303137511139383538
+//! humility:         core => Cortex-M4
+//! humility:   core index => 1 (of 2 cores)
+//! ...
+//! ```
+//!
 
 use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
@@ -103,6 +146,12 @@ struct ProbeArgs {
     /// display environment variable for this probe
     #[clap(long, short)]
     environment: bool,
+
+    /// rather than probing, serve the attached core to remote Humility
+    /// instances at the specified address (or port, on the loopback
+    /// interface); there is no authentication
+    #[clap(long, value_name = "address", conflicts_with = "environment")]
+    serve: Option<String>,
 }
 
 #[rustfmt::skip::macros(format)]
@@ -112,6 +161,16 @@ fn probecmd(context: &mut ExecutionContext) -> Result<()> {
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
     let subargs = ProbeArgs::try_parse_from(subargs)?;
 
+    if let Some(addr) = &subargs.serve {
+        let addr = match addr.parse::<u16>() {
+            Ok(port) => format!("127.0.0.1:{port}"),
+            Err(_) => addr.clone(),
+        };
+
+        let done = humility_cmd::interrupted()?;
+        return humility::remote::serve(core, &addr, done);
+    }
+
     use num_traits::FromPrimitive;
     let mut status = vec![];
 
@@ -159,6 +218,13 @@ fn probecmd(context: &mut ExecutionContext) -> Result<()> {
     //
     print("core", corename(part));
 
+    if core.ncores() > 1 {
+        print(
+            "core index",
+            format!("{} (of {} cores)", core.core_index(), core.ncores()),
+        );
+    }
+
     let m = &coreinfo.manufacturer;
 
     print(
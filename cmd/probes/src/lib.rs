@@ -0,0 +1,233 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility probes`
+//!
+//! `humility probes` lists the USB debug probes attached to this machine,
+//! along with their serial numbers and firmware versions:
+//!
+//! ```console
+//! $ humility probes
+//! PROBE VID:PID   SERIAL                   FIRMWARE NAME         ALIAS
+//! usb-0 0483:374e 002A00174741500520383733 1.0.0    STLink V3    gimlet-3
+//! usb-1 0483:374e 000D00184741500520383733 1.0.0    STLink V3    -
+//! usb-2 1fc9:0143 OQ0TC1GJZS3BC            1.0.0    CMSIS-DAP    -
+//! ```
+//!
+//! If an environment has been specified (via `--environment` or the
+//! `HUMILITY_ENVIRONMENT` environment variable), the target (if any) that
+//! uses each probe is also shown.  To determine what each probe is actually
+//! attached to, use `--identify` (`-i`), which will attach to each probe in
+//! turn and report the core (and its manufacturer) that it finds:
+//!
+//! ```console
+//! $ humility probes list --identify
+//! PROBE VID:PID   SERIAL                   FIRMWARE NAME         ALIAS    CORE
+//! usb-0 0483:374e 002A00174741500520383733 1.0.0    STLink V3    gimlet-3 Cortex-M7 (STMicroelectronics)
+//! usb-1 0483:374e 000D00184741500520383733 1.0.0    STLink V3    -        -
+//! usb-2 1fc9:0143 OQ0TC1GJZS3BC            1.0.0    CMSIS-DAP    -        Cortex-M33 (NXP (founded by Philips))
+//! ```
+//!
+//! A probe that cannot be attached to (e.g., because it isn't connected to
+//! a powered target, or because it is in use) has its core shown as `-`.
+//!
+//! Probes can be given names with `humility probes alias`; a name can then
+//! be used anywhere that a probe is expected (e.g., with `--probe` or in an
+//! environment file).  The probe to be named can be specified by its index,
+//! by its serial number, or in any other form accepted by `--probe`:
+//!
+//! ```console
+//! $ humility probes alias gimlet-3 usb-0
+//! humility: gimlet-3 is now an alias for 0483:374e:002A00174741500520383733
+//! $ humility -a build-gimlet.zip -p gimlet-3 tasks
+//! ```
+//!
+//! Aliases are kept in `~/.humility_probes`, and can be removed with
+//! `humility probes unalias`.
+//!
+
+use anyhow::Result;
+use clap::{CommandFactory, Parser};
+use humility::hubris::HubrisArchive;
+use humility::probes::ProbeListing;
+use humility::{msg, Output};
+use humility_cli::{Environment, ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Command, CommandKind};
+use humility_cortex::debug::corename;
+use humility_cortex::scs::CoreInfo;
+use std::collections::BTreeMap;
+
+#[derive(Parser, Debug)]
+enum ProbesCommand {
+    /// List attached probes (the default)
+    List {
+        /// attach to each probe to identify its core
+        #[clap(long, short)]
+        identify: bool,
+    },
+    /// Name a probe
+    Alias {
+        /// name for the probe
+        name: String,
+        /// probe to name, as an index, a serial number or any other probe
+        probe: String,
+    },
+    /// Remove a probe's name
+    Unalias {
+        /// name to remove
+        name: String,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[clap(name = "probes", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct ProbesArgs {
+    #[clap(subcommand)]
+    cmd: Option<ProbesCommand>,
+}
+
+///
+/// Attaches to the specified probe and returns a description of the core
+/// found there.  We don't want the messages that attaching generates, so
+/// we suppress them while we do it.
+///
+fn probes_identify(listing: &ProbeListing) -> Option<String> {
+    let output = humility::output();
+    humility::set_output(Output::Silent);
+
+    let rval = HubrisArchive::new().ok().and_then(|hubris| {
+        let mut core =
+            humility::core::attach_to_chip(&listing.specifier(), &hubris, None)
+                .ok()?;
+
+        let coreinfo = CoreInfo::read(core.as_mut()).ok()?;
+
+        Some(match coreinfo.manufacturer.get() {
+            Some(m) => format!("{} ({m})", corename(coreinfo.part)),
+            None => corename(coreinfo.part),
+        })
+    });
+
+    humility::set_output(output);
+    rval
+}
+
+///
+/// Returns true if the specified probe (as it appears in an environment or
+/// an alias) refers to the listed probe.
+///
+fn probes_match(probe: &str, listing: &ProbeListing) -> bool {
+    probe == listing.probe
+        || listing.serial.is_some()
+            && probe.eq_ignore_ascii_case(&listing.specifier())
+}
+
+fn probes_list(context: &ExecutionContext, identify: bool) -> Result<()> {
+    let listings = humility::probes::list();
+
+    if listings.is_empty() {
+        msg!("no probes found");
+        return Ok(());
+    }
+
+    let aliases = humility::probes::aliases()?;
+
+    //
+    // If we have an environment, we also want to know which target (if any)
+    // each probe belongs to; the probe for a target may itself be an alias.
+    //
+    let targets = match context.cli.environment {
+        Some(ref env) => Environment::probes(env)?
+            .into_iter()
+            .map(|(target, probe)| {
+                let probe = aliases.get(&probe).cloned().unwrap_or(probe);
+                (target, probe)
+            })
+            .collect::<BTreeMap<_, _>>(),
+        None => BTreeMap::new(),
+    };
+
+    let find = |map: &BTreeMap<String, String>, listing: &ProbeListing| {
+        let found = map
+            .iter()
+            .filter(|(_, probe)| probes_match(probe, listing))
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+
+        if found.is_empty() {
+            "-".to_string()
+        } else {
+            found.join(",")
+        }
+    };
+
+    let env = context.cli.environment.is_some();
+
+    print!(
+        "{:5} {:9} {:24} {:8} {:12} {:8}",
+        "PROBE", "VID:PID", "SERIAL", "FIRMWARE", "NAME", "ALIAS"
+    );
+
+    if env {
+        print!(" {:12}", "TARGET");
+    }
+
+    println!("{}", if identify { " CORE" } else { "" });
+
+    for listing in &listings {
+        print!(
+            "{:5} {:04x}:{:04x} {:24} {:8} {:12} {:8}",
+            listing.probe,
+            listing.vendor_id,
+            listing.product_id,
+            listing.serial.as_deref().unwrap_or("-"),
+            listing.firmware.as_deref().unwrap_or("-"),
+            listing.name,
+            find(&aliases, listing),
+        );
+
+        if env {
+            print!(" {:12}", find(&targets, listing));
+        }
+
+        if identify {
+            print!(" {}", probes_identify(listing).as_deref().unwrap_or("-"));
+        }
+
+        println!();
+    }
+
+    Ok(())
+}
+
+fn probes(context: &mut ExecutionContext) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = ProbesArgs::try_parse_from(subargs)?;
+
+    match subargs.cmd {
+        None => probes_list(context, false),
+        Some(ProbesCommand::List { identify }) => {
+            probes_list(context, identify)
+        }
+        Some(ProbesCommand::Alias { name, probe }) => {
+            let probe = humility::probes::alias(&name, &probe)?;
+            msg!("{name} is now an alias for {probe}");
+            Ok(())
+        }
+        Some(ProbesCommand::Unalias { name }) => {
+            humility::probes::unalias(&name)?;
+            msg!("removed alias {name}");
+            Ok(())
+        }
+    }
+}
+
+pub fn init() -> Command {
+    Command {
+        app: ProbesArgs::command(),
+        name: "probes",
+        run: probes,
+        kind: CommandKind::Unattached { archive: Archive::Ignored },
+    }
+}
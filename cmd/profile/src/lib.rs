@@ -0,0 +1,372 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility profile`
+//!
+//! `humility profile` profiles the target by having the Data Watchpoint and
+//! Trace (DWT) unit periodically sample the program counter, sending each
+//! sample out SWO via the ITM.  Samples are collected for the specified
+//! duration (5 seconds by default) and then resolved against the symbol
+//! tables of the kernel and every task, yielding a flat profile:
+//!
+//! ```console
+//! $ humility profile --duration 10
+//! humility: attached via ST-Link V3
+//! humility: sampling PC every 16384 cycles (24414 Hz) for 10 seconds
+//! humility: ITM synchronization packet found at offset 6
+//! humility: 243912 samples (201744 while sleeping)
+//!  SAMPLES      % TASK         FUNCTION
+//!   201744  82.7% -            <sleeping>
+//!    19921   8.2% idle         idle::main
+//!     9310   3.8% net          smoltcp::iface::InterfaceInner::process_ipv6
+//!     4187   1.7% kernel       kern::arch::arm_m::pendsv_entry
+//!     2870   1.2% net          task_net::main
+//! ...
+//! ```
+//!
+//! The sampling period can be specified in CPU cycles with `--period`
+//! (`-p`), and must be a multiple of 64 cycles up to 1024 cycles, or a
+//! multiple of 1024 cycles up to 16384 cycles.  Shorter periods yield more
+//! samples, but are more likely to overrun SWO and result in lost samples.
+//! The number of functions displayed can be specified with `--limit`
+//! (`-l`).
+//!
+//! Because the DWT samples only the program counter, the call graph that
+//! can be derived from its samples is limited to the task and to any
+//! functions that have been inlined at the sampled address; to see this
+//! call graph, use `--graph` (`-g`):
+//!
+//! ```console
+//! $ humility profile --graph
+//! ...
+//!     9310   3.8% net          smoltcp::iface::InterfaceInner::process_ipv6
+//!     6127   2.5%                smoltcp::wire::ipv6::Packet::check_len
+//!     2004   0.8%                smoltcp::iface::InterfaceInner::process_udp
+//! ...
+//! ```
+//!
+//! To generate a flamegraph, use `--flamegraph` to specify a file to which
+//! folded stacks should be written, and then process that file with a tool
+//! like [inferno](https://github.com/jonhoo/inferno):
+//!
+//! ```console
+//! $ humility profile --flamegraph profile.folded
+//! ...
+//! $ inferno-flamegraph < profile.folded > profile.svg
+//! ```
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::msg;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_cortex::dwt::*;
+use humility_cortex::itm::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+#[derive(Parser, Debug)]
+#[clap(name = "profile", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct ProfileArgs {
+    /// duration of profiling, in seconds
+    #[clap(long, short, default_value_t = 5.0, value_name = "seconds")]
+    duration: f64,
+
+    /// sampling period, in CPU cycles
+    #[clap(
+        long, short, default_value_t = 16384, value_name = "cycles",
+        parse(try_from_str = parse_int::parse)
+    )]
+    period: u32,
+
+    /// number of functions to display
+    #[clap(long, short, default_value_t = 30, value_name = "count")]
+    limit: usize,
+
+    /// display the inlined functions within each function
+    #[clap(long, short)]
+    graph: bool,
+
+    /// write folded stacks to the specified file
+    #[clap(long, value_name = "filename")]
+    flamegraph: Option<String>,
+}
+
+///
+/// The DWT hardware source that periodic PC samples are sent from.
+///
+const DWT_SOURCE_PC_SAMPLE: u32 = 2;
+
+///
+/// Returns the POSTCNT tap and reload value that correspond to a sampling
+/// period: the counter counts down every 64 cycles (or every 1024 cycles,
+/// if the tap is set), and a sample is taken when it reaches zero.
+///
+fn postcnt(period: u32) -> Result<(bool, u32)> {
+    match period {
+        64..=1024 if period % 64 == 0 => Ok((false, period / 64 - 1)),
+        2048..=16384 if period % 1024 == 0 => Ok((true, period / 1024 - 1)),
+        _ => bail!(
+            "period must be a multiple of 64 cycles up to 1024 cycles, \
+            or a multiple of 1024 cycles up to 16384 cycles"
+        ),
+    }
+}
+
+fn profile_enable(core: &mut dyn Core, period: u32) -> Result<()> {
+    let (tap, reset) = postcnt(period)?;
+
+    let mut tcr = ITM_TCR::read(core)?;
+    tcr.set_dwt_enable(true);
+    tcr.write(core)?;
+
+    let mut ctrl = DWT_CTRL::read(core)?;
+    ctrl.set_postcnt_tap(tap);
+    ctrl.set_postcnt_reset(reset);
+    ctrl.set_pc_sampling_enabled(true);
+    ctrl.write(core)?;
+
+    Ok(())
+}
+
+fn profile_disable(core: &mut dyn Core) -> Result<()> {
+    let mut ctrl = DWT_CTRL::read(core)?;
+    ctrl.set_pc_sampling_enabled(false);
+    ctrl.write(core)?;
+
+    let mut tcr = ITM_TCR::read(core)?;
+    tcr.set_dwt_enable(false);
+    tcr.write(core)?;
+
+    Ok(())
+}
+
+///
+/// Collects PC samples until our duration has elapsed (or we are
+/// interrupted), returning the number of samples taken at each address
+/// along with the number of samples taken while sleeping.
+///
+fn profile_ingest(
+    core: &mut dyn Core,
+    traceid: Option<u8>,
+    duration: Duration,
+) -> Result<(HashMap<u32, u64>, u64)> {
+    let mut pcs: HashMap<u32, u64> = HashMap::new();
+    let mut sleeping = 0;
+    let mut bytes: Vec<u8> = vec![];
+    let mut ndx = 0;
+
+    let done = humility_cmd::interrupted()?;
+
+    let start = Instant::now();
+
+    let stats = itm_ingest(
+        traceid,
+        || {
+            while ndx == bytes.len() {
+                if done.load(Ordering::SeqCst) || start.elapsed() >= duration {
+                    return Ok(None);
+                }
+
+                bytes = core.read_swv()?;
+                ndx = 0;
+            }
+            ndx += 1;
+            Ok(Some((bytes[ndx - 1], start.elapsed().as_secs_f64())))
+        },
+        |packet| {
+            if let ITMPayload::Hardware { source, payload, len } =
+                &packet.payload
+            {
+                //
+                // A sample taken while the core is sleeping is a single
+                // byte; otherwise, it is the 32-bit PC.
+                //
+                if *source == DWT_SOURCE_PC_SAMPLE {
+                    if *len == 4 {
+                        *pcs.entry(u32::from_le_bytes(*payload))
+                            .or_default() += 1;
+                    } else {
+                        sleeping += 1;
+                    }
+                }
+            }
+
+            Ok(())
+        },
+    )?;
+
+    if stats.overflows > 0 {
+        msg!(
+            "ITM overflowed {} times; samples were lost \
+            (consider a longer --period)",
+            stats.overflows
+        );
+    }
+
+    Ok((pcs, sleeping))
+}
+
+#[derive(Default)]
+struct Function<'a> {
+    samples: u64,
+    inlined: BTreeMap<Vec<&'a str>, u64>,
+}
+
+fn profile(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = ProfileArgs::try_parse_from(subargs)?;
+
+    postcnt(subargs.period)?;
+
+    if subargs.duration <= 0.0 {
+        bail!("duration must be positive");
+    }
+
+    if DWT_CTRL::read(core)?.no_trace_sampling() {
+        bail!("DWT on target does not support PC sampling");
+    }
+
+    //
+    // We don't want any instrumentation packets, so we enable ITM with all
+    // stimulus ports disabled.
+    //
+    let traceid = itm_enable_ingest(core, hubris, 0)?;
+
+    match hubris.clock(core)? {
+        Some(khz) => msg!(
+            "sampling PC every {} cycles ({} Hz) for {} seconds",
+            subargs.period,
+            khz as u64 * 1000 / subargs.period as u64,
+            subargs.duration
+        ),
+        None => msg!(
+            "sampling PC every {} cycles for {} seconds",
+            subargs.period,
+            subargs.duration
+        ),
+    }
+
+    profile_enable(core, subargs.period)?;
+
+    let rval = profile_ingest(
+        core,
+        traceid,
+        Duration::from_secs_f64(subargs.duration),
+    );
+
+    profile_disable(core)?;
+
+    let (pcs, sleeping) = rval?;
+    let total = pcs.values().sum::<u64>() + sleeping;
+
+    if total == 0 {
+        bail!("no samples received; is SWO connected?");
+    }
+
+    msg!("{total} samples ({sleeping} while sleeping)");
+
+    let mut functions: HashMap<(&str, &str), Function> = HashMap::new();
+
+    for (pc, count) in &pcs {
+        let task = hubris.instr_mod(*pc).unwrap_or("-");
+
+        let (func, inlined) = match hubris.instr_sym(*pc) {
+            Some((name, base)) => (
+                name,
+                hubris
+                    .instr_inlined(*pc, base)
+                    .iter()
+                    .map(|i| i.name)
+                    .collect::<Vec<_>>(),
+            ),
+            None => ("<unknown>", vec![]),
+        };
+
+        let f = functions.entry((task, func)).or_default();
+        f.samples += count;
+
+        if !inlined.is_empty() {
+            *f.inlined.entry(inlined).or_default() += count;
+        }
+    }
+
+    if sleeping > 0 {
+        functions.entry(("-", "<sleeping>")).or_default().samples = sleeping;
+    }
+
+    let mut sorted = functions.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| b.1.samples.cmp(&a.1.samples).then(a.0.cmp(b.0)));
+
+    let pct = |count: u64| count as f64 * 100.0 / total as f64;
+
+    println!("{:>8} {:>6} {:12} FUNCTION", "SAMPLES", "%", "TASK");
+
+    for ((task, func), f) in sorted.iter().take(subargs.limit) {
+        println!(
+            "{:>8} {:>5.1}% {:12} {}",
+            f.samples,
+            pct(f.samples),
+            task,
+            func
+        );
+
+        if !subargs.graph {
+            continue;
+        }
+
+        let mut inlined = f.inlined.iter().collect::<Vec<_>>();
+        inlined.sort_by(|a, b| b.1.cmp(a.1));
+
+        for (chain, count) in inlined {
+            println!(
+                "{:>8} {:>5.1}% {:12}   {}",
+                count,
+                pct(*count),
+                "",
+                chain.join(" > ")
+            );
+        }
+    }
+
+    if let Some(ref filename) = subargs.flamegraph {
+        let mut file = File::create(filename)?;
+
+        for ((task, func), f) in &sorted {
+            let inlined = f.inlined.values().sum::<u64>();
+
+            if f.samples > inlined {
+                writeln!(file, "{task};{func} {}", f.samples - inlined)?;
+            }
+
+            for (chain, count) in &f.inlined {
+                writeln!(file, "{task};{func};{} {count}", chain.join(";"))?;
+            }
+        }
+
+        msg!("folded stacks written to {filename}");
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: ProfileArgs::command(),
+        name: "profile",
+        run: profile,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -81,10 +81,63 @@
 //! internal bookkeeping.  To override this, use the `--write-sector0` flag;
 //! this is required for erases and writes that would otherwise modify sector 0,
 //! as well as bulk erase.
+//!
+//! To program QSPI flash as part of flashing an archive, see the `--qspi`
+//! option to `humility flash`.
+//!
+//! For flash that contains a littlefs filesystem, files can be manipulated
+//! directly:  `--ls` lists a directory, `--get` copies a file out of the
+//! filesystem, `--put` copies a file into it, and `--rm` removes a file.
+//! The filesystem is assumed to start at the address given with
+//! `--address` (`-a`), defaulting to 0:
+//!
+//! ```console
+//! $ humility qspi -a 0x1000000 --ls /
+//! humility: attached via ST-Link V3
+//! TYPE       SIZE NAME
+//! dir           - config/
+//! file       4096 boot.log
+//! $ humility qspi -a 0x1000000 --get /boot.log ./boot.log
+//! humility: attached via ST-Link V3
+//! humility: read /boot.log (4096 bytes) to ./boot.log
+//! ```
+//!
+//! Only the blocks that are needed are read from the device.  Modifications
+//! are made by appending to the existing metadata of the directory; if that
+//! metadata is full (or if the operation would require creating a
+//! directory), the operation fails without modifying flash.  Only files can
+//! be removed.  When writing or removing a file, only the appended metadata
+//! and any newly allocated blocks are programmed (and verified); a newly
+//! allocated block is erased first if it consists of whole sectors, and is
+//! otherwise taken only from blocks that are already erased.  Commits are
+//! aligned to the alignment of the existing commits, or to the program size
+//! of the device if it is given with `--prog-size`.
+//!
+//! Because the filesystem must not be modified underneath us, writing or
+//! removing a file requires the task that owns the filesystem to be given
+//! with `--owner` -- and to be held at a fault (e.g., with `humility jefe
+//! --hold` followed by `humility jefe --fault`):
+//!
+//! ```console
+//! $ humility jefe --hold fs
+//! $ humility jefe --fault fs
+//! $ humility qspi -a 0x1000000 --owner fs --put ./config.txt /config.txt
+//! humility: attached via ST-Link V3
+//! humility: wrote ./config.txt (117 bytes) to /config.txt
+//! humility: programmed 261 bytes in 3 ranges
+//! $ humility jefe --release fs
+//! ```
+//!
+//! To operate instead on an image of the filesystem in a file (e.g., one
+//! read with `--readfile`), use `--image`; any modifications are written
+//! back to the file.
 
 use humility::core::Core;
+use humility::hubris::{HubrisArchive, HubrisTask};
+use humility::reflect::{self, Load};
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Dumper, Validate};
+use humility_doppel::{SchedState, Task, TaskState};
 use humility_hiffy::*;
 use humility_idol::{HubrisIdol, IdolArgument};
 use sha2::{Digest, Sha256};
@@ -102,6 +155,9 @@ use hif::*;
 use indicatif::{HumanBytes, HumanDuration};
 use indicatif::{ProgressBar, ProgressStyle};
 
+mod littlefs;
+use littlefs::Littlefs;
+
 #[derive(Parser, Debug)]
 #[clap(
     name = "qspi", about = env!("CARGO_PKG_DESCRIPTION"),
@@ -191,6 +247,49 @@ struct QspiArgs {
     /// persistently selects a storage slot
     #[clap(long, group = "command")]
     set_persistent_slot: Option<u8>,
+
+    /// list a directory in a littlefs filesystem at the address
+    #[clap(long, value_name = "path", group = "command")]
+    ls: Option<String>,
+
+    /// copy a file out of a littlefs filesystem
+    #[clap(
+        long, group = "command", number_of_values = 2,
+        value_names = &["path", "filename"]
+    )]
+    get: Option<Vec<String>>,
+
+    /// copy a file into a littlefs filesystem
+    #[clap(
+        long, group = "command", number_of_values = 2,
+        value_names = &["filename", "path"]
+    )]
+    put: Option<Vec<String>>,
+
+    /// remove a file from a littlefs filesystem
+    #[clap(long, value_name = "path", group = "command")]
+    rm: Option<String>,
+
+    /// operate on a littlefs filesystem in a file rather than in flash
+    #[clap(long, value_name = "filename")]
+    image: Option<String>,
+
+    /// task that owns the littlefs filesystem, which must be held
+    #[clap(long, value_name = "task", conflicts_with = "image")]
+    owner: Option<String>,
+
+    /// program size of the flash underlying a littlefs filesystem
+    #[clap(long, value_name = "bytes", parse(try_from_str = parse_int::parse))]
+    prog_size: Option<u32>,
+}
+
+impl QspiArgs {
+    fn filesystem(&self) -> bool {
+        self.ls.is_some()
+            || self.get.is_some()
+            || self.put.is_some()
+            || self.rm.is_some()
+    }
 }
 
 struct QspiDevice {
@@ -198,6 +297,9 @@ struct QspiDevice {
     sector_size: u32,
 }
 
+const SECTOR_SIZE: u32 = 64 * 1024;
+const BLOCK_SIZE: u32 = 256; // Conflating flash block size with hubris scratch buffer.
+
 fn optional_nbytes<'a>(
     core: &'a mut dyn Core,
     context: &'a mut HiffyContext,
@@ -474,16 +576,571 @@ fn write(
     }
 }
 
-fn qspi(context: &mut ExecutionContext) -> Result<()> {
+///
+/// Programs exactly the specified bytes at the specified address, which need
+/// not be aligned.  Unlike [`write`], this never programs beyond the end of
+/// the data, so it may be used to program into a partially programmed page.
+///
+fn program(
+    device: &QspiDevice,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    addr: u32,
+    data: &[u8],
+) -> Result<()> {
+    let qspi_page_program =
+        if let Ok(f) = context.get_function("QspiPageProgramSector0", 3) {
+            f
+        } else {
+            context.get_function("QspiPageProgram", 3)?
+        };
+
+    //
+    // A page program can't cross a page boundary, so we split our data at
+    // page boundaries, and then batch as many as fit in our data and text.
+    //
+    let mut pieces = vec![];
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let a = addr + offset as u32;
+        let len = usize::min(
+            (device.block_size - (a % device.block_size)) as usize,
+            data.len() - offset,
+        );
+
+        pieces.push((a, offset..offset + len));
+        offset += len;
+    }
+
+    let op_bytes_per_program = 24;
+    let max_calls = usize::max(context.text_size() / op_bytes_per_program, 1);
+    let data_size = context.data_size();
+
+    let mut pieces = pieces.as_slice();
+
+    while !pieces.is_empty() {
+        let start = pieces[0].1.start;
+        let mut ops = vec![];
+        let mut n = 0;
+
+        while n < pieces.len()
+            && n < max_calls
+            && pieces[n].1.end - start <= data_size
+        {
+            let (a, range) = &pieces[n];
+            ops.push(Op::Push32(*a));
+            ops.push(Op::Push32((range.start - start) as u32));
+            ops.push(Op::Push32(range.len() as u32));
+            ops.push(Op::Call(qspi_page_program.id));
+            ops.push(Op::DropN(3));
+            n += 1;
+        }
+
+        if n == 0 {
+            bail!("data size of {data_size} bytes is too small to program");
+        }
+
+        ops.push(Op::Done);
+
+        let end = pieces[n - 1].1.end;
+        let results =
+            context.run(core, ops.as_slice(), Some(&data[start..end]))?;
+
+        for (i, result) in results.iter().enumerate() {
+            if let Err(err) = result {
+                bail!(
+                    "failed to program 0x{:x}: {}",
+                    pieces[i].0,
+                    qspi_page_program.strerror(*err),
+                );
+            }
+        }
+
+        pieces = &pieces[n..];
+    }
+
+    Ok(())
+}
+
+///
+/// Verifies an image written at `start` by comparing the hash of each
+/// sector (as computed by the target) to the hash of the image.
+///
+fn verify_hashes(
+    device: &QspiDevice,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    image: &[u8],
+    start: u32,
+) -> Result<()> {
+    let qspi_hash = context.get_function("QspiHash", 2)?;
+    let len = image.len() as u32;
+    let started = Instant::now();
+
+    let bar = ProgressBar::new((len - start).into());
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("humility: verifying [{bar:30}] {bytes}/{total_bytes}"),
+    );
+
+    let sectors =
+        (start..len).step_by(device.sector_size as usize).collect::<Vec<u32>>();
+    let mut mismatches = vec![];
+
+    for batch in sectors.chunks(8) {
+        let mut ops = vec![];
+
+        for &addr in batch {
+            ops.push(Op::Push32(addr));
+            ops.push(Op::Push32(u32::min(device.sector_size, len - addr)));
+            ops.push(Op::Call(qspi_hash.id));
+        }
+
+        ops.push(Op::Done);
+
+        let results = context.run(core, ops.as_slice(), None)?;
+
+        for (&addr, result) in batch.iter().zip(results.iter()) {
+            let end = u32::min(addr + device.sector_size, len);
+
+            match result {
+                Err(err) => {
+                    bail!(
+                        "failed to hash 0x{:x}: {}",
+                        addr,
+                        qspi_hash.strerror(*err)
+                    );
+                }
+                Ok(hash) => {
+                    let sum =
+                        Sha256::digest(&image[addr as usize..end as usize]);
+
+                    if !sum.iter().eq(hash.iter()) {
+                        mismatches.push(addr);
+                    }
+                }
+            }
+
+            bar.set_position((end - start).into());
+        }
+    }
+
+    bar.finish_and_clear();
+
+    if !mismatches.is_empty() {
+        for addr in &mismatches {
+            humility::msg!("sector at 0x{addr:x} failed to verify");
+        }
+
+        bail!("{} sector(s) failed to verify", mismatches.len());
+    }
+
+    humility::msg!(
+        "verified {} in {}",
+        HumanBytes((len - start).into()),
+        HumanDuration(started.elapsed())
+    );
+
+    Ok(())
+}
+
+///
+/// Erases QSPI flash, writes the specified image to it and then verifies
+/// it; this is used by `humility flash --qspi`.  Unless `write_sector0` is
+/// set, sector 0 is not written, and must therefore be empty in the image.
+///
+pub fn qspi_write_image(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    image: &[u8],
+    write_sector0: bool,
+    timeout: u32,
+) -> Result<()> {
+    let mut context = HiffyContext::new(hubris, core, timeout)?;
+    let device =
+        QspiDevice { block_size: BLOCK_SIZE, sector_size: SECTOR_SIZE };
+    let len = image.len() as u32;
+
+    let start = if write_sector0 {
+        0
+    } else {
+        let sector0 = &image[..usize::min(image.len(), SECTOR_SIZE as usize)];
+
+        if sector0.iter().any(|c| *c != 0xFF) {
+            bail!("cannot skip sector 0 with non-empty bytes");
+        }
+
+        humility::msg!("skipping sector 0");
+        SECTOR_SIZE
+    };
+
+    if start >= len {
+        bail!("image contains nothing to write");
+    }
+
+    let sectors =
+        (start..len).step_by(SECTOR_SIZE as usize).collect::<Vec<u32>>();
+
+    erase(&device, core, &mut context, &sectors)?;
+
+    let started = Instant::now();
+    let bar = ProgressBar::new((len - start).into());
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("humility: flashing [{bar:30}] {bytes}/{total_bytes}"),
+    );
+
+    let mut offset = start as usize;
+
+    write(&device, core, &mut context, start, len - start, |buf| {
+        buf.copy_from_slice(&image[offset..offset + buf.len()]);
+        offset += buf.len();
+        bar.set_position((offset - start as usize) as u64);
+        Ok(())
+    })?;
+
+    bar.finish_and_clear();
+
+    humility::msg!(
+        "flashed {} in {}",
+        HumanBytes((len - start).into()),
+        HumanDuration(started.elapsed())
+    );
+
+    verify_hashes(&device, core, &mut context, image, start)
+}
+
+///
+/// Reads a region of flash into the specified buffer.
+///
+fn read_range(
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    addr: u32,
+    buf: &mut [u8],
+) -> Result<()> {
+    let qspi_read = context.get_function("QspiRead", 2)?;
+
+    //
+    // As with --readfile, we read in units of the scratch size, batching
+    // as many reads as will fit in the return stack.
+    //
+    let chunk = context.scratch_size();
+    let batch = chunk * usize::max(context.rstack_size() / (chunk + 1), 1);
+
+    for (i, reads) in buf.chunks_mut(batch).enumerate() {
+        let base = addr + (i * batch) as u32;
+        let mut ops = vec![];
+
+        for (j, c) in reads.chunks(chunk).enumerate() {
+            ops.push(Op::Push32(base + (j * chunk) as u32));
+            ops.push(Op::Push32(c.len() as u32));
+            ops.push(Op::Call(qspi_read.id));
+        }
+
+        ops.push(Op::Done);
+
+        let results = context.run(core, ops.as_slice(), None)?;
+
+        for (j, (c, result)) in
+            reads.chunks_mut(chunk).zip(results.iter()).enumerate()
+        {
+            let a = base + (j * chunk) as u32;
+
+            match result {
+                Ok(data) if data.len() == c.len() => c.copy_from_slice(data),
+                Ok(data) => bail!("short read at 0x{a:x}: {}", data.len()),
+                Err(err) => {
+                    bail!(
+                        "failed to read 0x{a:x}: {}",
+                        qspi_read.strerror(*err)
+                    )
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// An owned copy of a [`littlefs::Program`], which outlives the filesystem
+/// (and therefore the borrow of the device that it reads from).
+///
+struct FsProgram {
+    block: u32,
+    offset: u32,
+    data: Vec<u8>,
+    erase: bool,
+}
+
+///
+/// Performs the specified filesystem operation, returning the ranges (if
+/// any) that must be programmed to effect it, in order.
+///
+fn qspi_fs(lfs: &mut Littlefs, subargs: &QspiArgs) -> Result<Vec<FsProgram>> {
+    if let Some(ref path) = subargs.ls {
+        let mut entries = lfs.list(path)?;
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        println!("{:<4} {:>10} NAME", "TYPE", "SIZE");
+
+        for e in entries {
+            if e.dir {
+                println!("{:<4} {:>10} {}/", "dir", "-", e.name);
+            } else {
+                println!("{:<4} {:>10} {}", "file", e.size, e.name);
+            }
+        }
+    } else if let Some(ref get) = subargs.get {
+        let contents = lfs.read(&get[0])?;
+        fs::write(&get[1], &contents)?;
+        humility::msg!(
+            "read {} ({} bytes) to {}",
+            get[0],
+            contents.len(),
+            get[1]
+        );
+    } else if let Some(ref put) = subargs.put {
+        let contents = fs::read(&put[0])?;
+        lfs.write(&put[1], &contents)?;
+        humility::msg!(
+            "wrote {} ({} bytes) to {}",
+            put[0],
+            contents.len(),
+            put[1]
+        );
+    } else if let Some(ref path) = subargs.rm {
+        lfs.remove(path)?;
+        humility::msg!("removed {path}");
+    }
+
+    Ok(lfs
+        .programs()
+        .map(|p| FsProgram {
+            block: p.block,
+            offset: p.offset,
+            data: p.data.to_vec(),
+            erase: p.erase,
+        })
+        .collect())
+}
+
+///
+/// Performs a filesystem operation on an image in a file, writing any
+/// modifications back to the file.
+///
+fn qspi_fs_image(subargs: &QspiArgs, filename: &str) -> Result<()> {
+    let mut image = fs::read(filename)?;
+    let base = subargs.addr.unwrap_or(0);
+
+    let (block_size, programs) = {
+        let mut lfs = Littlefs::mount(|offset, buf| {
+            let offset = base + offset as usize;
+
+            match image.get(offset..offset + buf.len()) {
+                Some(contents) => buf.copy_from_slice(contents),
+                None => {
+                    bail!("{filename} is too small to contain 0x{offset:x}")
+                }
+            }
+
+            Ok(())
+        })?;
+
+        lfs.prog_size = subargs.prog_size;
+        (lfs.block_size as usize, qspi_fs(&mut lfs, subargs)?)
+    };
+
+    if !programs.is_empty() {
+        for p in programs {
+            let block = base + p.block as usize * block_size;
+
+            if p.erase {
+                image[block..block + block_size].fill(0xff);
+            }
+
+            let offset = block + p.offset as usize;
+            image[offset..offset + p.data.len()].copy_from_slice(&p.data);
+        }
+
+        fs::write(filename, &image)?;
+    }
+
+    Ok(())
+}
+
+///
+/// Checks that the task that owns the filesystem is held -- that is, that
+/// it is either stopped or faulted -- so it can't modify the filesystem
+/// underneath us.
+///
+fn qspi_fs_owner(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    owner: &str,
+) -> Result<()> {
+    let id = match hubris.lookup_task(owner) {
+        Some(HubrisTask::Task(id)) => *id,
+        Some(HubrisTask::Kernel) => bail!("the kernel cannot own flash"),
+        None => bail!("couldn't find task {owner}"),
+    };
+
+    let (base, task_count) = hubris.task_table(core)?;
+
+    if id >= task_count {
+        bail!("task {owner} is not in the task table");
+    }
+
+    let task_t = hubris.lookup_struct_byname("Task")?;
+    let mut buf = vec![0; task_t.size];
+    core.read_8(base + id * task_t.size as u32, &mut buf)?;
+
+    let value = reflect::load(hubris, &buf, task_t, 0)?;
+
+    match Task::from_value(&value)?.state {
+        TaskState::Healthy(SchedState::Stopped) | TaskState::Faulted { .. } => {
+            Ok(())
+        }
+        _ => bail!(
+            "task {owner} must be stopped or held at a fault \
+            to modify its filesystem"
+        ),
+    }
+}
+
+///
+/// Performs a filesystem operation on flash, programming only what the
+/// operation has modified:  newly allocated blocks are erased and then
+/// programmed, while appends to metadata are programmed into space that is
+/// already erased.
+///
+fn qspi_fs_attached(
+    context: &mut ExecutionContext,
+    subargs: &QspiArgs,
+) -> Result<()> {
     let core = &mut **context.core.as_mut().unwrap();
-    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
     let hubris = context.archive.as_ref().unwrap();
+    let base = subargs.addr.unwrap_or(0) as u32;
+
+    let modifying = subargs.put.is_some() || subargs.rm.is_some();
+
+    match subargs.owner {
+        Some(ref owner) => qspi_fs_owner(hubris, core, owner)?,
+        None if modifying => {
+            bail!("must specify the task that owns the filesystem (--owner)")
+        }
+        None => {}
+    }
 
-    let subargs = QspiArgs::try_parse_from(subargs)?;
     let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
 
-    const SECTOR_SIZE: u32 = 64 * 1024;
-    const BLOCK_SIZE: u32 = 256; // Conflating flash block size with hubris scratch buffer.
+    let (block_size, programs) = {
+        let mut lfs = Littlefs::mount(|offset, buf| {
+            read_range(core, &mut context, base + offset, buf)
+        })?;
+
+        //
+        // We can only erase a block if it consists of entire sectors;
+        // otherwise, the filesystem must allocate blocks that are already
+        // erased.
+        //
+        lfs.prog_size = subargs.prog_size;
+        lfs.erasable =
+            base % SECTOR_SIZE == 0 && lfs.block_size % SECTOR_SIZE == 0;
+
+        (lfs.block_size, qspi_fs(&mut lfs, subargs)?)
+    };
+
+    if programs.is_empty() {
+        return Ok(());
+    }
+
+    if !subargs.write_sector0 {
+        for p in &programs {
+            let block = base + p.block * block_size;
+            let start = if p.erase { block } else { block + p.offset };
+
+            if start < SECTOR_SIZE {
+                bail!("cannot write to sector 0 without --write-sector0 flag");
+            }
+        }
+    }
+
+    let device =
+        QspiDevice { block_size: BLOCK_SIZE, sector_size: SECTOR_SIZE };
+
+    let mut nbytes = 0;
+
+    for p in &programs {
+        let block = base + p.block * block_size;
+        let addr = block + p.offset;
+
+        if p.erase {
+            let sectors = (block..block + block_size)
+                .step_by(SECTOR_SIZE as usize)
+                .collect::<Vec<_>>();
+
+            erase(&device, core, &mut context, &sectors)?;
+        }
+
+        program(&device, core, &mut context, addr, &p.data)?;
+
+        let mut found = vec![0u8; p.data.len()];
+        read_range(core, &mut context, addr, &mut found)?;
+
+        if found != p.data {
+            bail!("program of 0x{addr:x} failed to verify");
+        }
+
+        nbytes += p.data.len();
+    }
+
+    humility::msg!(
+        "programmed {} in {} range{}",
+        HumanBytes(nbytes as u64),
+        programs.len(),
+        if programs.len() != 1 { "s" } else { "" }
+    );
+
+    Ok(())
+}
+
+fn qspi(context: &mut ExecutionContext) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = QspiArgs::try_parse_from(subargs)?;
+
+    if let Some(ref image) = subargs.image {
+        if !subargs.filesystem() {
+            bail!("--image can only be used with a filesystem operation");
+        }
+
+        return qspi_fs_image(&subargs, image);
+    }
+
+    humility_cmd::attach(
+        context,
+        Attach::LiveOnly,
+        Validate::Booted,
+        |context| {
+            if subargs.filesystem() {
+                qspi_fs_attached(context, &subargs)
+            } else {
+                qspi_attached(context, &subargs)
+            }
+        },
+    )
+}
+
+fn qspi_attached(
+    context: &mut ExecutionContext,
+    subargs: &QspiArgs,
+) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
 
     let device =
         QspiDevice { block_size: BLOCK_SIZE, sector_size: SECTOR_SIZE };
@@ -575,7 +1232,7 @@ fn qspi(context: &mut ExecutionContext) -> Result<()> {
         ops.push(Op::Push32(arr.len() as u32));
         ops.push(Op::Call(qspi_page_program.id));
         (Some(arr), qspi_page_program)
-    } else if let Some(filename) = subargs.writefile {
+    } else if let Some(ref filename) = subargs.writefile {
         let qspi_page_program = if subargs.verify {
             context.get_function("QspiVerify", 3)?
         } else if subargs.write_sector0 {
@@ -591,7 +1248,7 @@ fn qspi(context: &mut ExecutionContext) -> Result<()> {
             context.get_function("QspiPageProgram", 3)?
         };
 
-        let filelen = fs::metadata(filename.clone())?.len() as u32;
+        let filelen = fs::metadata(filename)?.len() as u32;
 
         if !subargs.verify {
             let start_addr =
@@ -732,7 +1389,7 @@ fn qspi(context: &mut ExecutionContext) -> Result<()> {
         }
 
         return Ok(());
-    } else if let Some(filename) = subargs.readfile {
+    } else if let Some(ref filename) = subargs.readfile {
         // Address(default=0) and n-bytes(default=size of part-address) are
         // optional.
         let qspi_read_id = context.get_function("QspiReadId", 0)?;
@@ -843,8 +1500,8 @@ fn qspi(context: &mut ExecutionContext) -> Result<()> {
         );
 
         return Ok(());
-    } else if let Some(filename) = subargs.diffwrite {
-        let filelen = fs::metadata(filename.clone())?.len() as u32;
+    } else if let Some(ref filename) = subargs.diffwrite {
+        let filelen = fs::metadata(filename)?.len() as u32;
         let qspi_hash = context.get_function("QspiHash", 2)?;
         let started = Instant::now();
 
@@ -921,7 +1578,7 @@ fn qspi(context: &mut ExecutionContext) -> Result<()> {
         let mut bufs: Vec<Vec<u8>> = vec![];
         let mut nbytes = 0;
 
-        deltas(&device, &filename, &sums, |offset, buf| {
+        deltas(&device, filename, &sums, |offset, buf| {
             sectors.push(offset);
             bufs.push(buf.to_vec());
             nbytes += buf.len();
@@ -1160,10 +1817,6 @@ pub fn init() -> Command {
         app: QspiArgs::command(),
         name: "qspi",
         run: qspi,
-        kind: CommandKind::Attached {
-            archive: Archive::Required,
-            attach: Attach::LiveOnly,
-            validate: Validate::Booted,
-        },
+        kind: CommandKind::Unattached { archive: Archive::Required },
     }
 }
@@ -0,0 +1,1179 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! A minimal implementation of the on-disk format of littlefs (version 2),
+//! sufficient to list directories, read files, and write and remove files.
+//! This operates on an image of the filesystem that is read on demand, one
+//! block at a time; modifications are made in memory, and it is up to the
+//! caller to program them (see [`Littlefs::programs`]).
+//!
+//! Modifications are made by appending a commit to an existing metadata
+//! pair; we never compact a metadata pair, create a directory or split a
+//! directory across metadata pairs.  If a modification would require any of
+//! these -- or if the filesystem has pending global state (that is, an
+//! interrupted move or orphaned metadata pairs) -- we fail rather than
+//! attempt it; such a filesystem can still be modified by the target itself.
+//!
+
+use anyhow::{bail, Result};
+use std::collections::{btree_map, BTreeMap, BTreeSet};
+use std::ops::Range;
+
+const TYPE_REG: u16 = 0x001;
+const TYPE_DIR: u16 = 0x002;
+const TYPE_SUPERBLOCK: u16 = 0x0ff;
+const TYPE_DIRSTRUCT: u16 = 0x200;
+const TYPE_INLINESTRUCT: u16 = 0x201;
+const TYPE_CTZSTRUCT: u16 = 0x202;
+const TYPE_CREATE: u16 = 0x401;
+const TYPE_DELETE: u16 = 0x4ff;
+const TYPE_CRC: u16 = 0x500;
+const TYPE_FCRC: u16 = 0x5ff;
+const TYPE_TAIL: u16 = 0x600;
+const TYPE_MOVESTATE: u16 = 0x7ff;
+
+const NULL_PAIR: [u32; 2] = [0xffff_ffff, 0xffff_ffff];
+
+///
+/// The CRC used by littlefs is CRC-32 without the final inversion.
+///
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for b in data {
+        crc ^= *b as u32;
+
+        for _ in 0..8 {
+            crc =
+                if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+
+    crc
+}
+
+fn le32(buf: &[u8]) -> u32 {
+    u32::from_le_bytes(buf[..4].try_into().unwrap())
+}
+
+fn be32(buf: &[u8]) -> u32 {
+    u32::from_be_bytes(buf[..4].try_into().unwrap())
+}
+
+fn mktag(kind: u16, id: u16, size: u32) -> u32 {
+    (kind as u32) << 20 | (id as u32) << 10 | size
+}
+
+fn tag_type(tag: u32) -> u16 {
+    ((tag >> 20) & 0x7ff) as u16
+}
+
+fn tag_id(tag: u32) -> u16 {
+    ((tag >> 10) & 0x3ff) as u16
+}
+
+fn tag_size(tag: u32) -> usize {
+    (tag & 0x3ff) as usize
+}
+
+///
+/// The size of a tag and its data; deleted attributes (which have a size of
+/// 0x3ff) have no data.
+///
+fn tag_dsize(tag: u32) -> usize {
+    match tag_size(tag) {
+        0x3ff => 4,
+        size => 4 + size,
+    }
+}
+
+///
+/// The number of pointers at the start of a block in a CTZ skip-list
+///
+fn ctz_pointers(index: u32) -> usize {
+    match index {
+        0 => 0,
+        _ => index.trailing_zeros() as usize + 1,
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Struct {
+    Dir([u32; 2]),
+    Inline(Vec<u8>),
+    Ctz { head: u32, size: u32 },
+}
+
+#[derive(Clone, Debug, Default)]
+struct Entry {
+    kind: u16,
+    name: String,
+    data: Option<Struct>,
+}
+
+///
+/// The state of a metadata pair, as of its last valid commit
+///
+#[derive(Clone, Debug)]
+struct Mdir {
+    /// the blocks in the pair, with the active block first
+    pair: [u32; 2],
+
+    /// offset of the end of the last commit in the active block
+    off: usize,
+
+    /// the tag against which the next tag will be XOR'd
+    ptag: u32,
+
+    /// an alignment that is a multiple of the program size
+    align: usize,
+
+    entries: Vec<Entry>,
+    tail: Option<([u32; 2], bool)>,
+
+    /// this pair's contribution to the global state
+    gdelta: [u32; 3],
+}
+
+impl Mdir {
+    fn find(&self, name: &str) -> Option<(u16, &Entry)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .find(|(_, e)| is_file_or_dir(e) && e.name == name)
+            .map(|(id, e)| (id as u16, e))
+    }
+}
+
+fn is_file_or_dir(entry: &Entry) -> bool {
+    entry.kind == TYPE_REG || entry.kind == TYPE_DIR
+}
+
+#[derive(Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub dir: bool,
+    pub size: u32,
+}
+
+///
+/// A range of a block to be programmed.  If `erase` is set, the block is
+/// newly allocated and must be erased before it is programmed; otherwise,
+/// the range is known to be erased, and the rest of the block must be left
+/// as it is.
+///
+#[derive(Debug)]
+pub struct Program<'b> {
+    pub block: u32,
+    pub offset: u32,
+    pub data: &'b [u8],
+    pub erase: bool,
+}
+
+type Reader<'a> = Box<dyn FnMut(u32, &mut [u8]) -> Result<()> + 'a>;
+
+pub struct Littlefs<'a> {
+    read: Reader<'a>,
+    blocks: BTreeMap<u32, Vec<u8>>,
+    programs: Vec<(u32, usize, usize, bool)>,
+    gstate: [u32; 3],
+    pub block_size: u32,
+    pub block_count: u32,
+    name_max: u32,
+
+    /// The program size of the device.  If not set, commits are aligned to
+    /// the alignment of the existing commits in the metadata pair, which is
+    /// necessarily a multiple of the program size.
+    pub prog_size: Option<u32>,
+
+    /// Whether a single block can be erased.  If not (that is, if the erase
+    /// size of the device is larger than the block size), newly allocated
+    /// blocks are only taken from those that are already erased.
+    pub erasable: bool,
+}
+
+impl<'a> Littlefs<'a> {
+    ///
+    /// Mounts a filesystem, given a function to read the image at a given
+    /// offset.  The geometry is discovered from the superblock, which is
+    /// in the first commit of block 0.
+    ///
+    pub fn mount(
+        mut read: impl FnMut(u32, &mut [u8]) -> Result<()> + 'a,
+    ) -> Result<Self> {
+        let mut buf = vec![0u8; 512];
+        read(0, &mut buf)?;
+
+        let mut off = 4;
+        let mut ptag = 0xffff_ffff;
+        let mut found = false;
+        let mut geometry = None;
+
+        while off + 4 <= buf.len() {
+            let tag = be32(&buf[off..]) ^ ptag;
+
+            if tag & 0x8000_0000 != 0 || tag_type(tag) & 0x780 == TYPE_CRC {
+                break;
+            }
+
+            let data = &buf[(off + 4).min(buf.len())
+                ..(off + tag_dsize(tag)).min(buf.len())];
+
+            match tag_type(tag) {
+                TYPE_SUPERBLOCK => found = data == b"littlefs",
+                TYPE_INLINESTRUCT if found && tag_id(tag) == 0 => {
+                    if data.len() >= 12 {
+                        geometry = Some((le32(data), le32(&data[4..])));
+                    }
+                    break;
+                }
+                _ => {}
+            }
+
+            ptag = tag;
+            off += tag_dsize(tag);
+        }
+
+        let (version, block_size) = match geometry {
+            Some(geometry) => geometry,
+            None => bail!("no littlefs superblock found"),
+        };
+
+        if version >> 16 != 2 {
+            bail!("unsupported littlefs version {:x}", version);
+        }
+
+        if block_size < 128 || !block_size.is_power_of_two() {
+            bail!("invalid littlefs block size {block_size}");
+        }
+
+        let mut fs = Littlefs {
+            read: Box::new(read),
+            blocks: BTreeMap::new(),
+            programs: vec![],
+            gstate: [0; 3],
+            block_size,
+            block_count: 2,
+            name_max: 255,
+            prog_size: None,
+            erasable: true,
+        };
+
+        //
+        // Now that we know the block size, fetch the root metadata pair to
+        // get the superblock as of the latest commit.
+        //
+        let root = fs.fetch([0, 1])?;
+
+        match root.entries.first() {
+            Some(Entry {
+                kind: TYPE_SUPERBLOCK,
+                data: Some(Struct::Inline(sb)),
+                ..
+            }) if sb.len() >= 16 => {
+                fs.block_count = le32(&sb[8..]);
+
+                if le32(&sb[12..]) != 0 {
+                    fs.name_max = le32(&sb[12..]);
+                }
+            }
+            _ => bail!("littlefs superblock is corrupt"),
+        }
+
+        //
+        // The global state is the XOR of the deltas in every metadata pair,
+        // all of which are linked from the root via their tails.
+        //
+        let mut gstate = [0; 3];
+
+        for mdir in fs.mdirs()? {
+            for (g, d) in gstate.iter_mut().zip(mdir.gdelta) {
+                *g ^= d;
+            }
+        }
+
+        //
+        // Orphans are indicated by the valid bit, with the orphan count
+        // itself being biased by one.
+        //
+        gstate[0] = gstate[0].wrapping_add(gstate[0] >> 31);
+        fs.gstate = gstate;
+
+        Ok(fs)
+    }
+
+    ///
+    /// Returns every metadata pair in the filesystem.
+    ///
+    fn mdirs(&mut self) -> Result<Vec<Mdir>> {
+        let mut rval = vec![];
+        let mut seen = BTreeSet::new();
+        let mut pair = Some([0, 1]);
+
+        while let Some(next) = pair {
+            if !seen.insert(next[0]) {
+                bail!("metadata pairs have a cycle at {}", next[0]);
+            }
+
+            let mdir = self.fetch(next)?;
+            pair = mdir.tail.map(|(pair, _)| pair);
+            rval.push(mdir);
+        }
+
+        Ok(rval)
+    }
+
+    ///
+    /// Returns the ID of the entry in the specified metadata pair that has
+    /// a pending move (if any).  Until the move is completed by the target,
+    /// such an entry also exists at its destination, and is ignored here.
+    ///
+    fn moving(&self, pair: [u32; 2]) -> Option<usize> {
+        let [tag, a, b] = self.gstate;
+
+        if (tag >> 20) & 0x700 != 0 && (pair == [a, b] || pair == [b, a]) {
+            Some(tag_id(tag) as usize)
+        } else {
+            None
+        }
+    }
+
+    ///
+    /// Fails if there is pending global state:  a move that has not been
+    /// completed, or orphaned metadata pairs.  Either requires repair that
+    /// the target will perform on its next modification.
+    ///
+    fn check_gstate(&self) -> Result<()> {
+        let [tag, _, _] = self.gstate;
+
+        if (tag >> 20) & 0x700 != 0 {
+            bail!(
+                "filesystem has a pending move; it must be repaired by the \
+                target"
+            );
+        }
+
+        if tag_size(tag) & 0x1ff != 0 {
+            bail!("filesystem has orphans; it must be repaired by the target");
+        }
+
+        Ok(())
+    }
+
+    fn block(&mut self, block: u32) -> Result<&[u8]> {
+        if block >= self.block_count {
+            bail!("block {block} is out of range");
+        }
+
+        if let btree_map::Entry::Vacant(e) = self.blocks.entry(block) {
+            let mut buf = vec![0u8; self.block_size as usize];
+            (self.read)(block * self.block_size, &mut buf)?;
+            e.insert(buf);
+        }
+
+        Ok(&self.blocks[&block])
+    }
+
+    ///
+    /// Updates the contents of a block, recording the range that must be
+    /// programmed (and whether the block must be erased first).
+    ///
+    fn update(
+        &mut self,
+        block: u32,
+        contents: Vec<u8>,
+        range: Range<usize>,
+        erase: bool,
+    ) {
+        self.blocks.insert(block, contents);
+        self.programs.push((block, range.start, range.len(), erase));
+    }
+
+    ///
+    /// Returns the ranges that must be programmed to effect our
+    /// modifications, in the order in which they must be programmed.
+    ///
+    pub fn programs(&self) -> impl Iterator<Item = Program<'_>> {
+        self.programs.iter().map(|&(block, offset, len, erase)| Program {
+            block,
+            offset: offset as u32,
+            data: &self.blocks[&block][offset..offset + len],
+            erase,
+        })
+    }
+
+    ///
+    /// Replays the commits in a metadata block, returning the state as of
+    /// the last commit with a valid CRC (if any).
+    ///
+    fn fetch_block(&mut self, pair: [u32; 2]) -> Result<Option<Mdir>> {
+        let data = self.block(pair[0])?.to_vec();
+
+        let mut off = 4;
+        let mut ptag = 0xffff_ffff;
+        let mut crc = crc32(0xffff_ffff, &data[..4]);
+        let mut entries: Vec<Entry> = vec![];
+        let mut tail = None;
+        let mut gdelta = [0; 3];
+        let mut rval: Option<Mdir> = None;
+        let mut align = 0;
+
+        while off + 4 <= data.len() {
+            let tag = be32(&data[off..]) ^ ptag;
+
+            if tag & 0x8000_0000 != 0 || off + tag_dsize(tag) > data.len() {
+                break;
+            }
+
+            crc = crc32(crc, &data[off..off + 4]);
+            ptag = tag;
+
+            let kind = tag_type(tag);
+
+            if kind & 0x780 == TYPE_CRC {
+                if off + 8 > data.len() || le32(&data[off + 4..]) != crc {
+                    break;
+                }
+
+                ptag ^= (kind as u32 & 1) << 31;
+                off += tag_dsize(tag);
+                align = gcd(align, off);
+
+                rval = Some(Mdir {
+                    pair,
+                    off,
+                    ptag,
+                    align,
+                    entries: entries.clone(),
+                    tail,
+                    gdelta,
+                });
+
+                crc = 0xffff_ffff;
+                continue;
+            }
+
+            let payload = &data[off + 4..off + tag_dsize(tag)];
+            crc = crc32(crc, payload);
+
+            let id = tag_id(tag) as usize;
+
+            match kind {
+                TYPE_CREATE => {
+                    if id > entries.len() {
+                        entries.resize(id, Entry::default());
+                    }
+
+                    entries.insert(id, Entry::default());
+                }
+                TYPE_DELETE => {
+                    if id < entries.len() {
+                        entries.remove(id);
+                    }
+                }
+                _ if kind & 0x700 == 0 || kind & 0x700 == TYPE_DIRSTRUCT => {
+                    if id >= entries.len() {
+                        entries.resize(id + 1, Entry::default());
+                    }
+
+                    let entry = &mut entries[id];
+                    let deleted = tag_size(tag) == 0x3ff;
+
+                    match kind {
+                        _ if kind & 0x700 == 0 => {
+                            entry.kind = kind;
+                            entry.name =
+                                String::from_utf8_lossy(payload).to_string();
+                        }
+                        _ if deleted => entry.data = None,
+                        TYPE_DIRSTRUCT if payload.len() >= 8 => {
+                            entry.data = Some(Struct::Dir([
+                                le32(payload),
+                                le32(&payload[4..]),
+                            ]));
+                        }
+                        TYPE_INLINESTRUCT => {
+                            entry.data = Some(Struct::Inline(payload.to_vec()));
+                        }
+                        TYPE_CTZSTRUCT if payload.len() >= 8 => {
+                            entry.data = Some(Struct::Ctz {
+                                head: le32(payload),
+                                size: le32(&payload[4..]),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                TYPE_MOVESTATE if payload.len() >= 12 => {
+                    gdelta = [
+                        le32(payload),
+                        le32(&payload[4..]),
+                        le32(&payload[8..]),
+                    ];
+                }
+                _ if kind & 0x700 == TYPE_TAIL && payload.len() >= 8 => {
+                    let next = [le32(payload), le32(&payload[4..])];
+
+                    tail = if next.contains(&NULL_PAIR[0]) {
+                        None
+                    } else {
+                        Some((next, kind & 1 != 0))
+                    };
+                }
+                _ => {}
+            }
+
+            off += tag_dsize(tag);
+        }
+
+        Ok(rval)
+    }
+
+    ///
+    /// Fetches a metadata pair, preferring the block with the more recent
+    /// revision count.
+    ///
+    fn fetch(&mut self, pair: [u32; 2]) -> Result<Mdir> {
+        let revs = [le32(self.block(pair[0])?), le32(self.block(pair[1])?)];
+
+        let order = if (revs[1].wrapping_sub(revs[0]) as i32) > 0 {
+            [[pair[1], pair[0]], pair]
+        } else {
+            [pair, [pair[1], pair[0]]]
+        };
+
+        for pair in order {
+            if let Some(mut mdir) = self.fetch_block(pair)? {
+                if let Some(id) = self.moving(mdir.pair) {
+                    if let Some(entry) = mdir.entries.get_mut(id) {
+                        *entry = Entry::default();
+                    }
+                }
+
+                return Ok(mdir);
+            }
+        }
+
+        bail!("metadata pair {{{}, {}}} is corrupt", pair[0], pair[1]);
+    }
+
+    ///
+    /// Returns the metadata pairs that make up a directory.
+    ///
+    fn dir(&mut self, pair: [u32; 2]) -> Result<Vec<Mdir>> {
+        let mut rval = vec![];
+        let mut pair = pair;
+
+        loop {
+            let mdir = self.fetch(pair)?;
+            let tail = mdir.tail;
+            rval.push(mdir);
+
+            match tail {
+                Some((next, true)) => pair = next,
+                _ => break,
+            }
+
+            if rval.len() > self.block_count as usize {
+                bail!("directory has a cycle at {{{}, {}}}", pair[0], pair[1]);
+            }
+        }
+
+        Ok(rval)
+    }
+
+    ///
+    /// Returns the metadata pairs of the directory at the specified path.
+    ///
+    fn lookup_dir(&mut self, path: &[&str]) -> Result<Vec<Mdir>> {
+        let mut dir = self.dir([0, 1])?;
+
+        for (i, name) in path.iter().enumerate() {
+            let entry = dir.iter().find_map(|m| m.find(name)).map(|(_, e)| e);
+
+            dir = match entry {
+                Some(Entry { data: Some(Struct::Dir(pair)), .. }) => {
+                    self.dir(*pair)?
+                }
+                Some(_) => {
+                    bail!("/{} is not a directory", path[..=i].join("/"))
+                }
+                None => bail!("/{} not found", path[..=i].join("/")),
+            };
+        }
+
+        Ok(dir)
+    }
+
+    ///
+    /// Returns the metadata pair containing the entry at the specified path,
+    /// along with the entry's ID.
+    ///
+    fn lookup(&mut self, path: &str) -> Result<(Mdir, u16)> {
+        let (dir, name) = split(path)?;
+
+        for mdir in self.lookup_dir(&dir)? {
+            if let Some((id, _)) = mdir.find(name) {
+                return Ok((mdir, id));
+            }
+        }
+
+        bail!("{} not found", path);
+    }
+
+    pub fn list(&mut self, path: &str) -> Result<Vec<DirEntry>> {
+        let path = components(path);
+        let mut rval = vec![];
+
+        for mdir in self.lookup_dir(&path)? {
+            for entry in mdir.entries.iter().filter(|e| is_file_or_dir(e)) {
+                rval.push(DirEntry {
+                    name: entry.name.clone(),
+                    dir: entry.kind == TYPE_DIR,
+                    size: match &entry.data {
+                        Some(Struct::Inline(data)) => data.len() as u32,
+                        Some(Struct::Ctz { size, .. }) => *size,
+                        _ => 0,
+                    },
+                });
+            }
+        }
+
+        Ok(rval)
+    }
+
+    ///
+    /// Returns the blocks of a CTZ skip-list, in file order.
+    ///
+    fn ctz_blocks(&mut self, head: u32, size: u32) -> Result<Vec<u32>> {
+        if size == 0 {
+            return Ok(vec![]);
+        }
+
+        //
+        // Determine the index of the last block; each block holds the block
+        // size less the pointers at its start.
+        //
+        let b = self.block_size - 8;
+        let mut index = (size - 1) / b;
+
+        if index != 0 {
+            index = (size - 1 - 4 * ((index - 1).count_ones() + 2)) / b;
+        }
+
+        let mut rval = vec![head];
+        let mut block = head;
+
+        while index > 0 {
+            block = le32(self.block(block)?);
+            rval.push(block);
+            index -= 1;
+        }
+
+        rval.reverse();
+        Ok(rval)
+    }
+
+    pub fn read(&mut self, path: &str) -> Result<Vec<u8>> {
+        let (mdir, id) = self.lookup(path)?;
+        let entry = &mdir.entries[id as usize];
+
+        match &entry.data {
+            _ if entry.kind == TYPE_DIR => bail!("{path} is a directory"),
+            Some(Struct::Inline(data)) => Ok(data.clone()),
+            Some(Struct::Ctz { head, size }) => {
+                let mut rval = Vec::with_capacity(*size as usize);
+
+                for (i, b) in self.ctz_blocks(*head, *size)?.iter().enumerate()
+                {
+                    let start = ctz_pointers(i as u32) * 4;
+                    let remaining = *size as usize - rval.len();
+                    let data = &self.block(*b)?[start..];
+                    rval.extend(&data[..data.len().min(remaining)]);
+                }
+
+                Ok(rval)
+            }
+            _ => bail!("{path} has no data"),
+        }
+    }
+
+    ///
+    /// Returns the set of blocks in use by walking every metadata pair (all
+    /// of which are linked from the root via their tails) and the CTZ
+    /// skip-lists of every file.
+    ///
+    fn used(&mut self) -> Result<BTreeSet<u32>> {
+        let mut used = BTreeSet::new();
+
+        for mdir in self.mdirs()? {
+            used.extend(mdir.pair);
+
+            for entry in &mdir.entries {
+                if let Some(Struct::Ctz { head, size }) = entry.data {
+                    used.extend(self.ctz_blocks(head, size)?);
+                }
+            }
+        }
+
+        Ok(used)
+    }
+
+    ///
+    /// Allocates a free block.  If blocks can't be erased individually, we
+    /// only allocate blocks that are already erased.
+    ///
+    fn alloc(&mut self, used: &mut BTreeSet<u32>) -> Result<u32> {
+        for block in 0..self.block_count {
+            if used.contains(&block) {
+                continue;
+            }
+
+            if self.erasable || self.block(block)?.iter().all(|b| *b == 0xff) {
+                used.insert(block);
+                return Ok(block);
+            }
+        }
+
+        if self.erasable {
+            bail!("filesystem is full");
+        } else {
+            bail!("filesystem has no free blocks that are erased");
+        }
+    }
+
+    ///
+    /// Writes the contents of a file into newly allocated blocks as a CTZ
+    /// skip-list, returning the head.
+    ///
+    fn write_ctz(&mut self, contents: &[u8]) -> Result<u32> {
+        let mut used = self.used()?;
+        let mut blocks: Vec<u32> = vec![];
+        let mut offset = 0;
+
+        while offset < contents.len() {
+            let index = blocks.len() as u32;
+            let block = self.alloc(&mut used)?;
+
+            let mut buf = vec![0xffu8; self.block_size as usize];
+            let npointers = ctz_pointers(index);
+
+            //
+            // The pointers in block n point to blocks n - 2^i.
+            //
+            for i in 0..npointers {
+                let target = blocks[(index - (1 << i)) as usize];
+                buf[i * 4..i * 4 + 4].copy_from_slice(&target.to_le_bytes());
+            }
+
+            let len = (buf.len() - npointers * 4).min(contents.len() - offset);
+            buf[npointers * 4..npointers * 4 + len]
+                .copy_from_slice(&contents[offset..offset + len]);
+
+            let range = 0..buf.len();
+            self.update(block, buf, range, self.erasable);
+            blocks.push(block);
+            offset += len;
+        }
+
+        Ok(*blocks.last().unwrap())
+    }
+
+    ///
+    /// Appends a commit with the specified attributes to a metadata pair.
+    ///
+    fn commit(&mut self, mdir: &Mdir, attrs: &[(u32, Vec<u8>)]) -> Result<()> {
+        self.check_gstate()?;
+
+        let block = mdir.pair[0];
+        let mut data = self.block(block)?.to_vec();
+        let start = mdir.off;
+        let mut off = mdir.off;
+        let mut ptag = mdir.ptag;
+
+        let align = match self.prog_size {
+            Some(prog) if start % prog as usize != 0 => {
+                bail!(
+                    "commits in metadata pair {{{}, {}}} are not aligned to \
+                    a program size of {prog}",
+                    mdir.pair[0],
+                    mdir.pair[1]
+                );
+            }
+            Some(prog) => prog as usize,
+            None => mdir.align,
+        };
+
+        //
+        // The first commit in a block includes the revision count in its CRC.
+        //
+        let mut crc = match off {
+            4 => crc32(0xffff_ffff, &data[..4]),
+            _ => 0xffff_ffff,
+        };
+
+        //
+        // As littlefs does, we leave room after our attributes for the
+        // commit CRC, preceded by a CRC of the (erased) program unit that
+        // follows the commit; the latter allows the target to know that it
+        // can append to the pair without compacting it.
+        //
+        let len: usize = attrs.iter().map(|(_, d)| 4 + d.len()).sum();
+        let end =
+            ((off + len + 20).min(data.len()) + align - 1) / align * align;
+
+        if off + len + 8 > data.len() || end > data.len() {
+            bail!(
+                "metadata pair {{{}, {}}} is full; it must be compacted \
+                by the target",
+                mdir.pair[0],
+                mdir.pair[1]
+            );
+        }
+
+        if data[off..(end + align).min(data.len())].iter().any(|b| *b != 0xff) {
+            bail!("metadata block {} is not erased at offset {}", block, off);
+        }
+
+        for (tag, payload) in attrs {
+            let raw = ((tag & 0x7fff_ffff) ^ ptag).to_be_bytes();
+            data[off..off + 4].copy_from_slice(&raw);
+            data[off + 4..off + 4 + payload.len()].copy_from_slice(payload);
+
+            crc = crc32(crc, &raw);
+            crc = crc32(crc, payload);
+            ptag = tag & 0x7fff_ffff;
+            off += 4 + payload.len();
+        }
+
+        //
+        // Pad out the commit with CRC tags; the low bit of the type of each
+        // indicates the sense of the valid bit in the tag that follows.  The
+        // padding itself is left unprogrammed.
+        //
+        let mut programmed = off;
+
+        while off < end {
+            let noff = off + 4 + (end - off - 4).min(0x3fe);
+            let noff = if noff < end { noff.min(end - 20) } else { noff };
+            let mut perturb = 0xff;
+
+            if noff >= end && noff + align <= data.len() {
+                let tag = mktag(TYPE_FCRC, 0x3ff, 8);
+                let fcrc = crc32(0xffff_ffff, &data[noff..noff + align]);
+
+                data[off..off + 4].copy_from_slice(&(tag ^ ptag).to_be_bytes());
+                data[off + 4..off + 8]
+                    .copy_from_slice(&(align as u32).to_le_bytes());
+                data[off + 8..off + 12].copy_from_slice(&fcrc.to_le_bytes());
+
+                crc = crc32(crc, &data[off..off + 12]);
+                ptag = tag;
+                off += 12;
+                perturb = data[noff];
+            }
+
+            let reset = (!perturb >> 7) as u32;
+            let tag =
+                mktag(TYPE_CRC + reset as u16, 0x3ff, (noff - off - 4) as u32);
+            let raw = (tag ^ ptag).to_be_bytes();
+
+            crc = crc32(crc, &raw);
+            data[off..off + 4].copy_from_slice(&raw);
+            data[off + 4..off + 8].copy_from_slice(&crc.to_le_bytes());
+
+            programmed = off + 8;
+            off = noff;
+            ptag = tag ^ (reset << 31);
+            crc = 0xffff_ffff;
+        }
+
+        self.update(block, data, start..programmed, false);
+
+        Ok(())
+    }
+
+    ///
+    /// Writes a file, replacing it if it already exists.
+    ///
+    pub fn write(&mut self, path: &str, contents: &[u8]) -> Result<()> {
+        self.check_gstate()?;
+
+        let (dir, name) = split(path)?;
+
+        if name.len() > self.name_max as usize {
+            bail!("name of {path} exceeds {} bytes", self.name_max);
+        }
+
+        let dir = self.lookup_dir(&dir)?;
+
+        let (mdir, id, mut attrs) = match dir
+            .iter()
+            .find_map(|m| m.find(name).map(|(id, e)| (m, id, e)))
+        {
+            Some((_, _, e)) if e.kind == TYPE_DIR => {
+                bail!("{path} is a directory")
+            }
+            Some((mdir, id, _)) => (mdir, id, vec![]),
+            None => {
+                let mdir = dir.last().unwrap();
+                let id = mdir.entries.len() as u16;
+
+                if id >= 0x3ff {
+                    bail!("directory containing {path} is full");
+                }
+
+                let attrs = vec![
+                    (mktag(TYPE_CREATE, id, 0), vec![]),
+                    (
+                        mktag(TYPE_REG, id, name.len() as u32),
+                        name.as_bytes().to_vec(),
+                    ),
+                ];
+
+                (mdir, id, attrs)
+            }
+        };
+
+        //
+        // Empty files are inlined; everything else is written to its own
+        // blocks to avoid exceeding the cache size of the target.
+        //
+        if contents.is_empty() {
+            attrs.push((mktag(TYPE_INLINESTRUCT, id, 0), vec![]));
+        } else {
+            let head = self.write_ctz(contents)?;
+            let mut data = head.to_le_bytes().to_vec();
+            data.extend((contents.len() as u32).to_le_bytes());
+            attrs.push((mktag(TYPE_CTZSTRUCT, id, 8), data));
+        }
+
+        let mdir = mdir.clone();
+        self.commit(&mdir, &attrs)?;
+
+        if self.read(path)? != contents {
+            bail!("{path} failed to read back after writing");
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Removes a file.  Directories cannot be removed, as that would
+    /// require unlinking their metadata pairs.
+    ///
+    pub fn remove(&mut self, path: &str) -> Result<()> {
+        let (mdir, id) = self.lookup(path)?;
+
+        if mdir.entries[id as usize].kind == TYPE_DIR {
+            bail!("{path} is a directory; only files can be removed");
+        }
+
+        self.commit(&mdir, &[(mktag(TYPE_DELETE, id, 0), vec![])])?;
+
+        if self.lookup(path).is_ok() {
+            bail!("{path} still exists after removal");
+        }
+
+        Ok(())
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".").collect()
+}
+
+fn split(path: &str) -> Result<(Vec<&str>, &str)> {
+    let mut path = components(path);
+
+    match path.pop() {
+        Some(name) => Ok((path, name)),
+        None => bail!("expected a path to a file"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    //
+    // These images were made by littlefs 2.9 itself, with 512-byte blocks
+    // and a program size of 16.  Each contains /hello.txt (13 bytes, and
+    // therefore inlined), /big.bin (2000 bytes) and /config/a (700 bytes),
+    // the contents of each being given by `pattern`.  The second image has
+    // a move of /hello.txt to /config/hello.txt that was interrupted, and
+    // the third has an orphan from an interrupted removal of /config.
+    //
+    const IMAGE: &[u8] = include_bytes!("../testdata/littlefs.img");
+    const IMAGE_MOVE: &[u8] = include_bytes!("../testdata/littlefs-move.img");
+    const IMAGE_ORPHAN: &[u8] =
+        include_bytes!("../testdata/littlefs-orphan.img");
+
+    fn pattern(len: usize, mul: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i * mul) % 251) as u8).collect()
+    }
+
+    fn mount(image: &[u8]) -> Littlefs {
+        Littlefs::mount(move |offset, buf| {
+            let offset = offset as usize;
+            buf.copy_from_slice(&image[offset..offset + buf.len()]);
+            Ok(())
+        })
+        .unwrap()
+    }
+
+    fn names(lfs: &mut Littlefs, path: &str) -> Vec<String> {
+        let mut names = lfs
+            .list(path)
+            .unwrap()
+            .into_iter()
+            .map(|e| if e.dir { format!("{}/", e.name) } else { e.name })
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    ///
+    /// Performs the specified modifications, returning the resulting image
+    /// -- and asserting that we only program bytes that are erased (or that
+    /// we are erasing).
+    ///
+    fn modify(
+        image: &[u8],
+        f: impl FnOnce(&mut Littlefs) -> Result<()>,
+    ) -> Result<Vec<u8>> {
+        let mut lfs = mount(image);
+        f(&mut lfs)?;
+
+        let block_size = lfs.block_size as usize;
+        let mut rval = image.to_vec();
+
+        for p in lfs.programs() {
+            let base = p.block as usize * block_size;
+
+            if p.erase {
+                rval[base..base + block_size].fill(0xff);
+            }
+
+            let start = base + p.offset as usize;
+            let dest = &mut rval[start..start + p.data.len()];
+
+            assert!(dest.iter().all(|b| *b == 0xff), "{p:?} is not erased");
+            dest.copy_from_slice(p.data);
+        }
+
+        Ok(rval)
+    }
+
+    #[test]
+    fn test_littlefs_read() {
+        let mut lfs = mount(IMAGE);
+        assert_eq!(lfs.block_size, 512);
+        assert_eq!(lfs.block_count, 32);
+
+        assert_eq!(names(&mut lfs, "/"), ["big.bin", "config/", "hello.txt"]);
+        assert_eq!(names(&mut lfs, "/config"), ["a"]);
+
+        assert_eq!(lfs.read("/hello.txt").unwrap(), pattern(13, 7));
+        assert_eq!(lfs.read("/big.bin").unwrap(), pattern(2000, 3));
+        assert_eq!(lfs.read("config/a").unwrap(), pattern(700, 5));
+
+        assert!(lfs.read("/config").is_err());
+        assert!(lfs.read("/missing").is_err());
+        assert_eq!(lfs.programs().count(), 0);
+    }
+
+    #[test]
+    fn test_littlefs_write() {
+        let image = modify(IMAGE, |lfs| {
+            lfs.write("/new.bin", &pattern(1500, 13))?;
+            lfs.write("/config/a", &pattern(900, 17))?;
+            lfs.write("/empty", &[])?;
+            lfs.remove("/big.bin")
+        })
+        .unwrap();
+
+        let mut lfs = mount(&image);
+
+        assert_eq!(
+            names(&mut lfs, "/"),
+            ["config/", "empty", "hello.txt", "new.bin"]
+        );
+
+        assert_eq!(lfs.read("/hello.txt").unwrap(), pattern(13, 7));
+        assert_eq!(lfs.read("/new.bin").unwrap(), pattern(1500, 13));
+        assert_eq!(lfs.read("/config/a").unwrap(), pattern(900, 17));
+        assert_eq!(lfs.read("/empty").unwrap(), []);
+
+        //
+        // And we must be able to modify what we have modified.
+        //
+        let image =
+            modify(&image, |lfs| lfs.write("/new.bin", b"again")).unwrap();
+        assert_eq!(mount(&image).read("/new.bin").unwrap(), b"again");
+    }
+
+    #[test]
+    fn test_littlefs_commit() {
+        let mut lfs = mount(IMAGE);
+        lfs.remove("/hello.txt").unwrap();
+
+        //
+        // A removal is a single commit, appended to the root pair at a
+        // program boundary, without erasing it.
+        //
+        let programs = lfs.programs().collect::<Vec<_>>();
+        assert_eq!(programs.len(), 1);
+        assert!(!programs[0].erase);
+        assert!(programs[0].block < 2);
+        assert_eq!(programs[0].offset % 16, 0);
+
+        let mut lfs = mount(IMAGE);
+        lfs.prog_size = Some(16);
+        lfs.remove("/hello.txt").unwrap();
+
+        let mut lfs = mount(IMAGE);
+        lfs.prog_size = Some(24);
+        assert!(lfs.remove("/hello.txt").is_err());
+    }
+
+    #[test]
+    fn test_littlefs_erasable() {
+        let image = modify(IMAGE, |lfs| {
+            lfs.erasable = false;
+            lfs.write("/new.bin", &pattern(1500, 13))?;
+            assert!(lfs.programs().all(|p| !p.erase));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(mount(&image).read("/new.bin").unwrap(), pattern(1500, 13));
+    }
+
+    #[test]
+    fn test_littlefs_full() {
+        let mut lfs = mount(IMAGE);
+        assert!(lfs.write("/huge.bin", &pattern(32 * 512, 1)).is_err());
+    }
+
+    #[test]
+    fn test_littlefs_gstate() {
+        let mut lfs = mount(IMAGE_MOVE);
+        assert_eq!(names(&mut lfs, "/"), ["big.bin", "config/"]);
+        assert_eq!(names(&mut lfs, "/config"), ["a", "hello.txt"]);
+        assert_eq!(lfs.read("/config/hello.txt").unwrap(), pattern(13, 7));
+        assert!(lfs.write("/new", b"new").is_err());
+        assert!(lfs.remove("/big.bin").is_err());
+
+        let mut lfs = mount(IMAGE_ORPHAN);
+        assert_eq!(names(&mut lfs, "/"), ["big.bin", "hello.txt"]);
+        assert!(lfs.write("/new", b"new").is_err());
+    }
+}
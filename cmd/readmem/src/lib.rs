@@ -114,6 +114,25 @@
 //! 0x20004b6c | 0x00000000
 //! ```
 //!
+//! To watch a region of memory for changes, use `--watch` (`-W`), specifying an
+//! interval in milliseconds.  The region is re-read at the specified interval
+//! and redisplayed in place, with any bytes (or half-words or words, with `-H`
+//! or `-w`) that have changed since the previous sample highlighted.  This is
+//! useful for catching stray writes to a peripheral or to a shared buffer.  To
+//! additionally log every change, use `--log` to specify a file; each line of
+//! the log has the wall clock time at which a change was seen, the address, and
+//! the old and new values:
+//!
+//! ```console
+//! $ humility readmem -w -W 100 --log changes.log 0x30020000 0x20
+//! humility: attached via ST-Link V3
+//! ...
+//! ^C
+//! $ cat changes.log
+//! 1665001234.517 0x30020008 0x00000000 -> 0x0000001f
+//! 1665001234.619 0x30020008 0x0000001f -> 0x00000020
+//! ```
+//!
 
 use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
@@ -121,6 +140,10 @@ use humility::hubris::*;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Dumper, Validate};
 use std::convert::TryInto;
+use std::fs::File;
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 //
 // We allow the size to be specified as a number (e.g., with an optional `0x`
@@ -154,6 +177,17 @@ struct ReadmemArgs {
     #[clap(long, short)]
     symbol: bool,
 
+    /// re-read the region at the specified interval, highlighting changes
+    #[clap(
+        long, short = 'W', value_name = "ms", conflicts_with = "symbol",
+        parse(try_from_str = parse_int::parse)
+    )]
+    watch: Option<u64>,
+
+    /// when watching, log each change (with a timestamp) to a file
+    #[clap(long, value_name = "filename", requires = "watch")]
+    log: Option<String>,
+
     /// address to read
     address: String,
 
@@ -240,6 +274,81 @@ fn readmem(context: &mut ExecutionContext) -> Result<()> {
     dumper.size = size;
     dumper.dump(&bytes, addr);
 
+    if let Some(interval) = subargs.watch {
+        if core.is_dump() {
+            bail!("can only watch memory on a live system");
+        }
+
+        let mut log = match subargs.log {
+            Some(ref filename) => Some(File::create(filename)?),
+            None => None,
+        };
+
+        let interval = Duration::from_millis(interval);
+        let mut prev = bytes;
+
+        //
+        // The number of lines that our dump occupies, including the header.
+        //
+        let offs = addr as usize & (dumper.width - 1);
+        let lines = (offs + length + dumper.width - 1) / dumper.width + 1;
+
+        loop {
+            thread::sleep(interval);
+
+            let mut bytes = vec![0u8; length];
+            core.read_8(addr, &mut bytes)?;
+
+            print!("\x1b[{}A", lines);
+            dumper.dump_changes(&bytes, addr, &prev);
+
+            if let Some(ref mut log) = log {
+                readmem_log(log, addr, size, &prev, &bytes)?;
+            }
+
+            prev = bytes;
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Logs each unit that has changed between samples, along with the wall
+/// clock time at which the change was seen.
+///
+fn readmem_log(
+    log: &mut File,
+    addr: u32,
+    size: usize,
+    prev: &[u8],
+    bytes: &[u8],
+) -> Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let val = |b: &[u8]| match size {
+        1 => u32::from(b[0]),
+        2 => u32::from(u16::from_le_bytes(b.try_into().unwrap())),
+        _ => u32::from_le_bytes(b.try_into().unwrap()),
+    };
+
+    for (offs, (p, b)) in
+        prev.chunks_exact(size).zip(bytes.chunks_exact(size)).enumerate()
+    {
+        if p != b {
+            writeln!(
+                log,
+                "{}.{:03} 0x{:08x} 0x{:0width$x} -> 0x{:0width$x}",
+                now.as_secs(),
+                now.subsec_millis(),
+                addr + (offs * size) as u32,
+                val(p),
+                val(b),
+                width = size * 2
+            )?;
+        }
+    }
+
+    log.flush()?;
     Ok(())
 }
 
@@ -35,14 +35,49 @@
 //!     })
 //! ```
 //!
+//! By default, pointers are displayed as addresses.  To follow pointers (and to
+//! read the contents of slices and string slices), use the `--follow` (`-f`)
+//! option.  The levels of nesting that are displayed can be limited with
+//! `--depth` (`-D`), with contents beyond that depth being elided; when
+//! following pointers, the depth also limits the pointers that are followed
+//! (and is 8 by default):
+//!
+//! ```console
+//! $ humility readvar --follow --depth 3 CURRENT_TASK_PTR
+//! humility: attached via ST-Link
+//! CURRENT_TASK_PTR (0x20000018) = Some(NonNull<kern::task::Task> {
+//!         pointer: 0x20000558 -> Task {
+//!             save: SavedState { .. },
+//!             priority: Priority(0x0),
+//!             state: Healthy(Runnable),
+//!             timer: TimerState { .. },
+//!             generation: Generation(0x0),
+//!             notifications: 0x0,
+//!             notification_mask: 0x0,
+//!             region_table: &[&kern::descs::RegionDesc] { .. },
+//!             descriptor: 0x8004a2c (&kern::descs::TaskDesc)
+//!         }
+//!     })
+//! ```
+//!
+//! Pointers that are null, that would form a cycle, or that cannot be read are
+//! displayed as addresses, as are pointers to (and slices of) more than 16 KiB.
+//!
 
 use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
 use humility::core::Core;
 use humility::hubris::*;
+use humility::reflect::{self, Format};
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
 
+//
+// When following pointers, the number of levels of nesting that we will
+// follow them through if a depth has not been specified.
+//
+const FOLLOW_DEPTH: usize = 8;
+
 #[derive(Parser, Debug)]
 #[clap(name = "readvar", about = env!("CARGO_PKG_DESCRIPTION"))]
 struct ReadvarArgs {
@@ -59,6 +94,17 @@ struct ReadvarArgs {
     #[clap(long, short)]
     list: bool,
 
+    /// follow pointers and read the contents of slices
+    #[clap(long, short)]
+    follow: bool,
+
+    /// levels of nesting to display
+    #[clap(
+        long, short = 'D', value_name = "levels",
+        parse(try_from_str = parse_int::parse)
+    )]
+    depth: Option<usize>,
+
     /// leave target halted
     #[clap(long)]
     leave_halted: bool,
@@ -80,6 +126,14 @@ fn readvar_dump(
     core.halt()?;
     core.read_8(variable.addr, buf.as_mut_slice())?;
 
+    let ty = hubris.lookup_type(variable.goff)?;
+    let mut value = reflect::load_value(hubris, &buf, ty, 0)?;
+
+    if subargs.follow {
+        let depth = subargs.depth.unwrap_or(FOLLOW_DEPTH);
+        value = reflect::follow(hubris, core, &value, depth);
+    }
+
     if !subargs.leave_halted {
         core.run()?;
     }
@@ -90,9 +144,13 @@ fn readvar_dump(
         newline: true,
         hex,
         interpret_as_c_string: subargs.as_c_string,
+        depth: subargs.depth,
         ..HubrisPrintFormat::default()
     };
-    let dumped = hubris.printfmt(&buf, variable.goff, fmt)?;
+
+    let mut out = vec![];
+    value.format(hubris, fmt, &mut out)?;
+    let dumped = String::from_utf8(out)?;
 
     println!("{} (0x{:08x}) = {}", name, variable.addr, dumped);
 
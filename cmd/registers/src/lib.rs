@@ -53,6 +53,11 @@
 //! FPSCR = 0x00000000
 //! ```
 //!
+//! On a RISC-V target (for which the chip must be specified with `--chip`),
+//! the general purpose registers (by their ABI names), the PC and the
+//! machine-mode CSRs are displayed; stack backtraces and floating point
+//! registers are not supported.
+//!
 //! If an archive is provided or if displaying registers from a dump, the
 //! symbol that corresponds to register's value (if any) is displayed, e.g.:
 //!
@@ -131,12 +136,14 @@
 
 use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
+use humility::arch::{Arch, ArchKind, ArchRegisterField};
+use humility::core::Core;
 use humility::hubris::*;
-use humility_arch_arm::{ARMRegister, ARMRegisterField};
+use humility_arch_arm::ARMRegister;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
 use humility_cortex::debug::*;
-use num_traits::FromPrimitive;
+use num_traits::{FromPrimitive, ToPrimitive};
 use std::collections::BTreeMap;
 
 #[derive(Parser, Debug)]
@@ -155,9 +162,9 @@ struct RegistersArgs {
     fp: bool,
 }
 
-fn print_reg(reg: ARMRegister, val: u32, fields: &[ARMRegisterField]) {
-    print!("{:>5} = 0x{:08x} <- ", reg, val);
-    let indent = 5 + "= 0x00000000 <- ".len();
+fn print_reg(name: &str, width: usize, val: u32, fields: &[ArchRegisterField]) {
+    print!("{:>width$} = 0x{:08x} <- ", name, val);
+    let indent = width + "= 0x00000000 <- ".len();
 
     for i in (0..32).step_by(4).rev() {
         print!("{:04b}", (val >> i) & 0b1111);
@@ -169,7 +176,7 @@ fn print_reg(reg: ARMRegister, val: u32, fields: &[ARMRegisterField]) {
         }
     }
 
-    fn print_bars(f: &[ARMRegisterField], elbow: bool) {
+    fn print_bars(f: &[ArchRegisterField], elbow: bool) {
         let mut pos = 32;
 
         for i in 0..f.len() {
@@ -232,6 +239,37 @@ fn print_reg(reg: ARMRegister, val: u32, fields: &[ARMRegisterField]) {
     println!();
 }
 
+///
+/// Displays the registers of an architecture other than ARM, for which we
+/// have neither floating point registers nor stack unwinding.
+///
+fn registers_arch(core: &mut dyn Core) -> Result<()> {
+    let arch = core.arch();
+
+    for reg in arch.registers() {
+        let val = match core.read_reg_id(reg.id) {
+            Ok(val) => val,
+            Err(_) => {
+                continue;
+            }
+        };
+
+        if let Some(fields) = arch.fields(reg.id) {
+            print_reg(&reg.name, 8, val, &fields);
+            continue;
+        }
+
+        match arch.describe(reg.id, val) {
+            Some(desc) => {
+                println!("{:>8} = 0x{:08x} ({})", reg.name, val, desc)
+            }
+            None => println!("{:>8} = 0x{:08x}", reg.name, val),
+        }
+    }
+
+    Ok(())
+}
+
 fn registers(context: &mut ExecutionContext) -> Result<()> {
     let core = &mut **context.core.as_mut().unwrap();
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
@@ -239,6 +277,21 @@ fn registers(context: &mut ExecutionContext) -> Result<()> {
     let mut regs = BTreeMap::new();
     let hubris = context.archive.as_ref().unwrap();
 
+    if core.arch().kind() != ArchKind::Arm {
+        if subargs.stack || subargs.fp {
+            bail!(
+                "--stack and --floating-point are not supported on {}",
+                core.arch().name()
+            );
+        }
+
+        core.halt()?;
+        let rval = registers_arch(core);
+        core.run()?;
+
+        return rval;
+    }
+
     if subargs.fp && !core.is_dump() {
         let mvfr = MVFR0::read(core)?;
 
@@ -299,8 +352,9 @@ fn registers(context: &mut ExecutionContext) -> Result<()> {
     for (reg, val) in regs.iter() {
         let val = *val;
 
-        if let Some(fields) = reg.fields() {
-            print_reg(*reg, val, &fields);
+        if let Some(fields) = humility::arch::ARM.fields(reg.to_u16().unwrap())
+        {
+            print_reg(&reg.to_string(), 5, val, &fields);
             continue;
         }
 
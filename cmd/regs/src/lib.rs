@@ -0,0 +1,413 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility regs`
+//!
+//! `humility regs` reads and writes individual registers, either of the
+//! core or of a particular task.  Registers are specified by name; a
+//! register to be written is followed by `=` and the value to write:
+//!
+//! ```console
+//! $ humility regs pc lr primask
+//! humility: attached via ST-Link V3
+//!        PC = 0x08004236
+//!        LR = 0x0800414f
+//!   PRIMASK = 0x0
+//! $ humility regs r0=0x1f basepri=0x20
+//! humility: attached via ST-Link V3
+//!        R0 = 0x00000000 -> 0x0000001f
+//!   BASEPRI = 0x0 -> 0x20
+//! ```
+//!
+//! In addition to the general purpose registers (`r0` through `r12`, `sp`,
+//! `lr` and `pc`), the following may be specified:  `psr` (or `xpsr`),
+//! `msp`, `psp`, `control`, `primask`, `basepri`, `faultmask`, and -- on
+//! targets with floating point -- `fpscr` and `s0` through `s31`.  Register
+//! names are not case sensitive.  If no registers are specified, all
+//! general purpose and special registers are displayed.  The target is
+//! halted while registers are read or written, and is then allowed to run.
+//!
+//! To operate on the registers of a task, specify the task with `--task`
+//! (`-t`).  If the task is not executing (as is almost always the case when
+//! the target is halted), its registers are those that the kernel has saved
+//! on its behalf:  R4 through R11 from the task's saved state, and the
+//! remaining registers from the exception frame on its stack.  Writing a
+//! register of such a task modifies its saved state, and therefore takes
+//! effect when the task is next scheduled:
+//!
+//! ```console
+//! $ humility regs --task ping r4 pc
+//! humility: attached via ST-Link V3
+//!        R4 = 0x00000003
+//!        PC = 0x0802a1c6
+//! $ humility regs --task ping r4=0
+//! humility: attached via ST-Link V3
+//!        R4 = 0x00000003 -> 0x00000000
+//! ```
+//!
+//! Registers that the kernel does not save on a task's behalf (e.g.,
+//! `control` or `msp`) cannot be accessed for a task that is not executing.
+//! If the specified task is executing, its registers are those of the core.
+//! Registers can be read from a dump (with or without `--task`), but
+//! can only be written on a live system.
+//!
+//! On a RISC-V target, registers are named as they are by the ABI (e.g.,
+//! `ra`, `sp`, `a0`), along with `pc` and the machine-mode CSRs (e.g.,
+//! `mstatus`, `mcause`, `mepc`); `--task` is not supported.
+//!
+
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use humility::arch::ArchKind;
+use humility::core::Core;
+use humility::hubris::*;
+use humility::msg;
+use humility_arch_arm::ARMRegister;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use num_traits::FromPrimitive;
+use std::collections::BTreeMap;
+
+#[derive(Parser, Debug)]
+#[clap(name = "regs", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct RegsArgs {
+    /// task whose registers are to be read or written
+    #[clap(long, short, value_name = "task")]
+    task: Option<String>,
+
+    /// registers to read, or to write as register=value
+    #[clap(value_name = "register[=value]")]
+    registers: Vec<String>,
+}
+
+///
+/// The fields of the special-purpose register (SPR) that we allow to be
+/// named, as (name, high bit, low bit).
+///
+const SPR_FIELDS: &[(&str, u32, u32)] = &[
+    ("CONTROL", 31, 24),
+    ("FAULTMASK", 16, 16),
+    ("BASEPRI", 15, 8),
+    ("PRIMASK", 0, 0),
+];
+
+///
+/// A register (or a field of one) specified by name
+///
+struct Reg {
+    name: String,
+    reg: ARMRegister,
+    field: Option<(u32, u32)>,
+}
+
+impl Reg {
+    fn from_name(name: &str) -> Result<Self> {
+        let name = name.to_uppercase();
+
+        if let Some((_, hi, lo)) = SPR_FIELDS.iter().find(|f| f.0 == name) {
+            return Ok(Self {
+                name,
+                reg: ARMRegister::SPR,
+                field: Some((*hi, *lo)),
+            });
+        }
+
+        let lookup = if name == "XPSR" { "PSR" } else { name.as_str() };
+
+        let reg = (0..=ARMRegister::max())
+            .filter_map(ARMRegister::from_u16)
+            .find(|r| *r != ARMRegister::SPR && format!("{:?}", r) == lookup);
+
+        match reg {
+            Some(reg) => Ok(Self { name, reg, field: None }),
+            None => bail!("unknown register \"{}\"", name.to_lowercase()),
+        }
+    }
+
+    fn mask(&self) -> u32 {
+        match self.field {
+            Some((hi, lo)) => (((1u64 << (hi - lo + 1)) - 1) as u32) << lo,
+            None => 0xffff_ffff,
+        }
+    }
+
+    /// Extracts our value from the value of the underlying register
+    fn get(&self, val: u32) -> u32 {
+        match self.field {
+            Some((_, lo)) => (val & self.mask()) >> lo,
+            None => val,
+        }
+    }
+
+    /// Returns the value of the underlying register with ours set
+    fn set(&self, val: u32, new: u32) -> Result<u32> {
+        match self.field {
+            Some((_, lo)) => {
+                if new > self.mask() >> lo {
+                    bail!("value 0x{new:x} is too large for {}", self.name);
+                }
+
+                Ok((val & !self.mask()) | (new << lo))
+            }
+            None => Ok(new),
+        }
+    }
+
+    fn format(&self, val: u32) -> String {
+        match self.field {
+            Some(_) => format!("0x{:x}", val),
+            None => format!("0x{:08x}", val),
+        }
+    }
+}
+
+///
+/// The registers of the context that we are operating on:  either those of
+/// the core, or those that the kernel has saved for a task.
+///
+enum RegsContext {
+    Core,
+    Saved {
+        regs: BTreeMap<ARMRegister, u32>,
+        addrs: BTreeMap<ARMRegister, u32>,
+    },
+}
+
+impl RegsContext {
+    fn read(&self, core: &mut dyn Core, reg: &Reg) -> Result<u32> {
+        match self {
+            RegsContext::Core => core
+                .read_reg(reg.reg)
+                .with_context(|| format!("failed to read {}", reg.name)),
+            RegsContext::Saved { regs, .. } => match regs.get(&reg.reg) {
+                Some(val) => Ok(*val),
+                None => bail!("{} is not saved for a task", reg.name),
+            },
+        }
+    }
+
+    fn write(&self, core: &mut dyn Core, reg: &Reg, val: u32) -> Result<()> {
+        match self {
+            RegsContext::Core => core
+                .write_reg(reg.reg, val)
+                .with_context(|| format!("failed to write {}", reg.name)),
+            RegsContext::Saved { addrs, .. } => match addrs.get(&reg.reg) {
+                Some(addr) => core.write_word_32(*addr, val),
+                None => bail!("{} cannot be written for a task", reg.name),
+            },
+        }
+    }
+}
+
+///
+/// Returns all registers that can be read in the specified context.
+///
+fn regs_all(context: &RegsContext, core: &mut dyn Core) -> Vec<(Reg, u32)> {
+    let mut rval = vec![];
+
+    for i in 0..=ARMRegister::max() {
+        let reg = match ARMRegister::from_u16(i) {
+            Some(reg) if !reg.is_floating_point() => reg,
+            _ => continue,
+        };
+
+        let regs = if reg == ARMRegister::SPR {
+            SPR_FIELDS
+                .iter()
+                .map(|(name, hi, lo)| Reg {
+                    name: name.to_string(),
+                    reg,
+                    field: Some((*hi, *lo)),
+                })
+                .collect::<Vec<_>>()
+        } else {
+            vec![Reg { name: format!("{:?}", reg), reg, field: None }]
+        };
+
+        for r in regs {
+            if let Ok(val) = context.read(core, &r) {
+                let val = r.get(val);
+                rval.push((r, val));
+            }
+        }
+    }
+
+    rval
+}
+
+///
+/// Parses a register to read (`register`) or write (`register=value`).
+///
+fn regs_parse(arg: &str) -> Result<(&str, Option<u32>)> {
+    match arg.split_once('=') {
+        Some((name, val)) => {
+            let val = parse_int::parse::<u32>(val).with_context(|| {
+                format!("invalid value for {name}: \"{val}\"")
+            })?;
+
+            Ok((name, Some(val)))
+        }
+        None => Ok((arg, None)),
+    }
+}
+
+///
+/// Reads and writes the registers of an architecture other than ARM, as
+/// named by the architecture.
+///
+fn regs_arch(core: &mut dyn Core, subargs: &RegsArgs) -> Result<()> {
+    let arch = core.arch();
+
+    if subargs.registers.is_empty() {
+        for reg in arch.registers() {
+            if let Ok(val) = core.read_reg_id(reg.id) {
+                println!("{:>9} = 0x{:08x}", reg.name, val);
+            }
+        }
+
+        return Ok(());
+    }
+
+    let mut ops = vec![];
+
+    for arg in &subargs.registers {
+        let (name, val) = regs_parse(arg)?;
+
+        match arch.lookup(name) {
+            Some(reg) => ops.push((reg, val)),
+            None => bail!("unknown register \"{}\"", name.to_lowercase()),
+        }
+    }
+
+    for (reg, val) in &ops {
+        let current = core
+            .read_reg_id(reg.id)
+            .with_context(|| format!("failed to read {}", reg.name))?;
+
+        match val {
+            None => println!("{:>9} = 0x{:08x}", reg.name, current),
+            Some(val) => {
+                core.write_reg_id(reg.id, *val)
+                    .with_context(|| format!("failed to write {}", reg.name))?;
+
+                println!(
+                    "{:>9} = 0x{:08x} -> 0x{:08x}",
+                    reg.name, current, val
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn regs_run(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &RegsArgs,
+) -> Result<()> {
+    let mut ops = vec![];
+
+    for arg in &subargs.registers {
+        let (name, val) = regs_parse(arg)?;
+        ops.push((Reg::from_name(name)?, val));
+    }
+
+    if core.is_dump() && ops.iter().any(|(_, val)| val.is_some()) {
+        bail!("registers cannot be written in a dump");
+    }
+
+    let context = match &subargs.task {
+        Some(name) => {
+            let task = match hubris.lookup_task(name) {
+                Some(task) => *task,
+                None => bail!("unknown task \"{name}\""),
+            };
+
+            if hubris.task_in_userland(core, task)? {
+                msg!("task {name} is executing; using registers of the core");
+                RegsContext::Core
+            } else {
+                RegsContext::Saved {
+                    regs: hubris.registers(core, task)?,
+                    addrs: hubris.saved_registers(core, task)?,
+                }
+            }
+        }
+        None => RegsContext::Core,
+    };
+
+    if ops.is_empty() {
+        for (reg, val) in regs_all(&context, core) {
+            println!("{:>9} = {}", reg.name, reg.format(val));
+        }
+
+        return Ok(());
+    }
+
+    for (reg, val) in &ops {
+        let current = context.read(core, reg)?;
+
+        match val {
+            None => {
+                println!("{:>9} = {}", reg.name, reg.format(reg.get(current)));
+            }
+            Some(val) => {
+                context.write(core, reg, reg.set(current, *val)?)?;
+
+                println!(
+                    "{:>9} = {} -> {}",
+                    reg.name,
+                    reg.format(reg.get(current)),
+                    reg.format(*val)
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn regs(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = RegsArgs::try_parse_from(subargs)?;
+
+    if core.arch().kind() != ArchKind::Arm {
+        if subargs.task.is_some() {
+            bail!("tasks are not supported on {}", core.arch().name());
+        }
+
+        core.halt()?;
+        let rval = regs_arch(core, &subargs);
+        core.run()?;
+
+        return rval;
+    }
+
+    if subargs.task.is_some() && !hubris.loaded() {
+        bail!("must provide a Hubris archive or dump to specify a task");
+    }
+
+    core.halt()?;
+    let rval = regs_run(hubris, core, &subargs);
+    core.run()?;
+
+    rval
+}
+
+pub fn init() -> Command {
+    Command {
+        app: RegsArgs::command(),
+        name: "regs",
+        run: regs,
+        kind: CommandKind::Attached {
+            archive: Archive::Optional,
+            attach: Attach::Any,
+            validate: Validate::None,
+        },
+    }
+}
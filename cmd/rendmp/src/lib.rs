@@ -29,6 +29,44 @@
 //! humility: RAA229618 at I2C3, port H, dev 0x5a has CRC 0x00000000
 //! ```
 //!
+//! To report the CRC and number of remaining slots for every Renesas device in
+//! the manifest, add the `--all` option:
+//!
+//! ```console
+//! $ humility rendmp --crc --all
+//! humility: attached via ST-Link V3
+//! DEVICE     LOCATION                                CRC SLOTS
+//! RAA229618  I2C3, port H, dev 0x5a           0x841f35a5    27
+//! RAA229618  I2C3, port H, dev 0x5b           0x1e6d8c32    27
+//! ISL68224   I2C3, port H, dev 0x5c           0x0f2c65d4    26
+//! ```
+//!
+//! To audit a fleet of targets running the same image, list their IP addresses
+//! (each optionally preceded by a name for the target) in a file and specify it
+//! with `--fleet`.  Each target is attached to over the network in turn, and
+//! any device whose CRC is not consistent across the fleet is called out:
+//!
+//! ```console
+//! $ cat fleet.txt
+//! # rack 3
+//! BRM42220014 fe80::0c1d:9aff:fe64:b8c2%en0
+//! BRM42220017 fe80::0c1d:deff:fef0:d922%en0
+//! $ humility rendmp --crc --all --fleet fleet.txt
+//! humility: connecting to fe80::0c1d:9aff:fe64:b8c2%en0
+//! humility: connecting to fe80::0c1d:deff:fef0:d922%en0
+//! TARGET           DEVICE     LOCATION                                CRC SLOTS
+//! BRM42220014      RAA229618  I2C3, port H, dev 0x5a           0x841f35a5    27
+//!                  RAA229618  I2C3, port H, dev 0x5b           0x1e6d8c32    27
+//!                  ISL68224   I2C3, port H, dev 0x5c           0x0f2c65d4    26
+//! BRM42220017      RAA229618  I2C3, port H, dev 0x5a           0x841f35a5    27
+//!                  RAA229618  I2C3, port H, dev 0x5b           0x1e6d8c32    27
+//!                  ISL68224   I2C3, port H, dev 0x5c           0x6e0312b9    25
+//! humility: WARNING: ISL68224 at I2C3, port H, dev 0x5c has differing CRCs: 0x0f2c65d4 (1), 0x6e0312b9 (1)
+//! ```
+//!
+//! For consumption by other tooling, the `--csv` option emits these results as
+//! CSV, with one row per device per target.
+//!
 //! To flash a part (that is, to program its one-time programmable
 //! non-volatile memory), specify the HEX file as generated by the
 //! Renesas PowerNavigator.  Note that this file specifies the address
@@ -61,6 +99,29 @@
 //! humility: image CRC (0x841f35a5) matches OTP CRC
 //! ```
 //!
+//! The Rust payload for a configuration can also be generated from a text
+//! file exported by Power Navigator, specifying the device's driver and the
+//! file with `--ingest` (`-i`).  Each PMBus write in the file is validated
+//! against the definition of its command:  payloads that are the wrong size
+//! or can't be interpreted are flagged, as are output voltage settings that
+//! are inconsistent with one another:
+//!
+//! ```console
+//! $ humility rendmp -D raa229618 -i ./vddcr.txt > raa229618.rs
+//! humility: WARNING: line 212: rail 1: VOUT_UV_WARN_LIMIT (0.95V) is below VOUT_UV_FAULT_LIMIT (1.05V)
+//! humility: WARNING: ./vddcr.txt: 1 suspicious value(s) found
+//! ```
+//!
+//! To generate the Rust payload for a configuration (that is, the
+//! `*_payload` function that firmware uses to apply the configuration) from
+//! the same HEX image that is used to flash it, specify the image and the
+//! `--generate` (`-g`) option; no target is needed:
+//!
+//! ```console
+//! $ humility rendmp -g --flash ./raa229618-0x5a.hex > raa229618.rs
+//! humility: generating payload for RAA229618 at 0x5a from 646 records
+//! ```
+//!
 //! The Renesas voltage regulators include a black box which stores fault
 //! information.  This can be queried using the `--blackbox` subcommand,
 //! specifying a device (I2C) address to pick a specific power converter:
@@ -140,11 +201,11 @@
 
 use humility::hubris::*;
 use humility::reflect::{Base, Value};
-use humility::warn;
+use humility::{warn, Diagnostic};
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
 use humility_hiffy::*;
-use humility_i2c::I2cArgs;
+use humility_i2c::{I2cArgs, I2cRetryPolicy};
 use humility_idol::{HubrisIdol, IdolOperation};
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -201,6 +262,24 @@ struct RendmpArgs {
     #[clap(long, group = "subcommand")]
     crc: bool,
 
+    /// with --crc, report the CRC and slot count of every Renesas device
+    #[clap(
+        long,
+        requires = "crc",
+        conflicts_with_all = &[
+            "rail", "driver", "bus", "controller", "port", "mux", "device"
+        ]
+    )]
+    all: bool,
+
+    /// with --all, audit each target (by IP address) listed in a file
+    #[clap(long, value_name = "filename", requires = "all")]
+    fleet: Option<String>,
+
+    /// with --all, emit results as CSV
+    #[clap(long, requires = "all")]
+    csv: bool,
+
     /// reads the contents of a Renesas power converter black box
     #[clap(long, group = "subcommand")]
     blackbox: bool,
@@ -228,6 +307,13 @@ struct RendmpArgs {
     /// check the OTP CRC against the image CRC
     #[clap(long, short = 'C', requires = "flash")]
     check: bool,
+
+    /// rather than flashing, generate the Rust payload for the image
+    #[clap(
+        long, short = 'g', requires = "flash",
+        conflicts_with_all = &["dryrun", "force", "check"]
+    )]
+    generate: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -497,6 +583,7 @@ enum RendmpHexRecordKind {
 #[allow(dead_code)]
 struct RendmpHex {
     device: RendmpDevice,
+    address: u8,
     ic_device_id: [u8; 4],
     ic_device_rev: [u8; 4],
     crc: u32,
@@ -504,12 +591,21 @@ struct RendmpHex {
 }
 
 impl RendmpHex {
-    fn from_file(filename: &str, address: u8) -> Result<Self> {
+    ///
+    /// Reads a HEX file, checking that it specifies the given address (if
+    /// any).
+    ///
+    fn from_file(filename: &str, address: Option<u8>) -> Result<Self> {
         let file = fs::File::open(filename)?;
-        let lines = BufReader::new(file).lines();
+        Self::parse(BufReader::new(file), address)
+    }
+
+    fn parse(input: impl BufRead, address: Option<u8>) -> Result<Self> {
+        let lines = input.lines();
 
         let mut data = vec![];
         let mut headers = vec![];
+        let mut image = None;
 
         //
         // The IC_DEVICE_ID and IC_DEVICE_REV are (inexplicably?) big-endian in
@@ -581,12 +677,29 @@ impl RendmpHex {
             // it seems much more likely that someone is trying to flash the
             // wrong device -- and we want to preserve this as a check.
             //
-            if vals[2] >> 1 != address {
-                bail!(
-                    "image specifies address to be 0x{:x}; can't flash 0x{:x}",
-                    vals[2] >> 1,
-                    address
-                );
+            if let Some(address) = address {
+                if vals[2] >> 1 != address {
+                    bail!(
+                        "image specifies address to be 0x{:x}; \
+                        can't flash 0x{:x}",
+                        vals[2] >> 1,
+                        address
+                    );
+                }
+            }
+
+            match image {
+                None => image = Some(vals[2] >> 1),
+                Some(image) if image != vals[2] >> 1 => {
+                    bail!(
+                        "image specifies address 0x{:x} on line {}, \
+                        but 0x{:x} on prior lines",
+                        vals[2] >> 1,
+                        l,
+                        image
+                    );
+                }
+                Some(_) => {}
             }
 
             let payload = vals[3..reclen + 1].to_vec();
@@ -627,6 +740,7 @@ impl RendmpHex {
 
         Ok(Self {
             device,
+            address: image.unwrap(),
             ic_device_id,
             ic_device_rev: flip_word(&headers[1][1..], "IC_DEVICE_REV")?,
             crc: u32::from_le_bytes(crc.try_into().unwrap()),
@@ -664,7 +778,7 @@ struct Packet<'a> {
 }
 
 fn rendmp_gen(
-    _subargs: &RendmpArgs,
+    source: &str,
     device: &pmbus::Device,
     packets: &[Packet],
     commands: &HashMap<String, (u8, pmbus::Operation, pmbus::Operation)>,
@@ -676,8 +790,8 @@ fn rendmp_gen(
 
 ///
 /// Iterate over a configuration payload for a Renesas {} digital multiphase
-/// PWM controller.  This code was generated by "humility rendmp -g" given
-/// a .txt dump from running Renesas configuration software.
+/// PWM controller.  This code was generated by "humility rendmp" given
+/// {}.
 ///
 #[rustfmt::skip]
 pub fn {}_payload<E>(
@@ -686,6 +800,7 @@ pub fn {}_payload<E>(
 
     const PAYLOAD: &[&[u8]] = &["##,
         device.name(),
+        source,
         device.name(),
     );
 
@@ -756,6 +871,229 @@ pub fn {}_payload<E>(
     Ok(())
 }
 
+///
+/// Commands whose values are in terms of VOUT, in the order in which their
+/// values are expected to fall (lowest first).
+///
+const RENDMP_VOUT_ORDER: &[u8] = &[
+    pmbus::CommandCode::VOUT_UV_FAULT_LIMIT as u8,
+    pmbus::CommandCode::VOUT_UV_WARN_LIMIT as u8,
+    pmbus::CommandCode::VOUT_MARGIN_LOW as u8,
+    pmbus::CommandCode::VOUT_COMMAND as u8,
+    pmbus::CommandCode::VOUT_MARGIN_HIGH as u8,
+    pmbus::CommandCode::VOUT_OV_WARN_LIMIT as u8,
+    pmbus::CommandCode::VOUT_OV_FAULT_LIMIT as u8,
+];
+
+///
+/// Commands whose values must not exceed VOUT_MAX
+///
+const RENDMP_VOUT_CAPPED: &[u8] = &[
+    pmbus::CommandCode::VOUT_COMMAND as u8,
+    pmbus::CommandCode::VOUT_MARGIN_HIGH as u8,
+];
+
+///
+/// Interprets a value written to a PMBus command, returning its scalar value
+/// (if it has one).  As with other consumers of the pmbus crate, we take the
+/// leading numeric portion of the value's representation.
+///
+fn rendmp_ingest_value(
+    device: &pmbus::Device,
+    code: u8,
+    payload: &[u8],
+    mode: Option<u8>,
+) -> Result<Option<f32>, String> {
+    let vmode = || pmbus::commands::VOUT_MODE::CommandData(mode.unwrap_or(0));
+    let mut rval = None;
+
+    device
+        .interpret(code, payload, vmode, |field, value| {
+            if !field.bitfield() {
+                let s = value.to_string();
+                let end = s
+                    .find(|c: char| {
+                        !(c.is_ascii_digit() || c == '.' || c == '-')
+                    })
+                    .unwrap_or(s.len());
+                rval = s[..end].parse().ok();
+            }
+        })
+        .map_err(|e| format!("{e:?}"))?;
+
+    Ok(rval)
+}
+
+///
+/// Validates the PMBus writes in an ingested configuration against the
+/// pmbus crate's definition of the device, warning on any value that seems
+/// suspicious:  payloads that are the wrong size for their command or that
+/// cannot be interpreted, writes to commands that can't be written, and
+/// output voltage settings that are inconsistent with one another (e.g., an
+/// undervoltage limit above the commanded voltage).  Returns the number of
+/// warnings.
+///
+fn rendmp_ingest_validate(
+    device: &pmbus::Device,
+    packets: &[Packet],
+    linenos: &[usize],
+) -> usize {
+    let page = pmbus::CommandCode::PAGE as u8;
+    let vout_mode = pmbus::CommandCode::VOUT_MODE as u8;
+    let mut nwarnings = 0;
+
+    let mut suspicious = |lineno: usize, msg: String| {
+        warn!("line {lineno}: {msg}");
+        nwarnings += 1;
+    };
+
+    //
+    // Our first pass finds the VOUT_MODE for each rail, as we need it to
+    // interpret any values that are in terms of VOUT.
+    //
+    let mut modes = HashMap::new();
+    let mut rail = 0;
+
+    for packet in packets {
+        match (packet.address, &packet.payload[..]) {
+            (Address::Pmbus(code, _), [val]) if code == page => rail = *val,
+            (Address::Pmbus(code, _), [val]) if code == vout_mode => {
+                modes.insert(rail, *val);
+            }
+            _ => {}
+        }
+    }
+
+    let vout_max = pmbus::CommandCode::VOUT_MAX as u8;
+    let mut vouts: BTreeMap<u8, BTreeMap<u8, (&str, f32, usize)>> =
+        BTreeMap::new();
+    let mut unvalidated = false;
+    let mut rail = 0;
+
+    for (packet, lineno) in packets.iter().zip(linenos.iter().copied()) {
+        let (code, name) = match packet.address {
+            Address::Pmbus(code, name) => (code, name),
+            Address::Dma(_) => continue,
+        };
+
+        let payload = &packet.payload;
+
+        if code == page && payload.len() == 1 {
+            rail = payload[0];
+        }
+
+        let mut write = None;
+        device.command(code, |cmd| write = Some(cmd.write_op()));
+
+        let expected = match write {
+            None => continue,
+            Some(pmbus::Operation::SendByte) => Some(0),
+            Some(pmbus::Operation::WriteByte) => Some(1),
+            Some(pmbus::Operation::WriteWord) => Some(2),
+            Some(pmbus::Operation::WriteWord32) => Some(4),
+            Some(pmbus::Operation::WriteBlock) => None,
+            Some(op) => {
+                suspicious(
+                    lineno,
+                    format!("{name} cannot be written ({op:?})"),
+                );
+                continue;
+            }
+        };
+
+        if let Some(expected) = expected {
+            if payload.len() != expected {
+                suspicious(
+                    lineno,
+                    format!(
+                        "{name} expects {expected} byte(s), \
+                        found {}: {payload:x?}",
+                        payload.len()
+                    ),
+                );
+                continue;
+            }
+        }
+
+        let mode = modes.get(&rail).copied();
+        let vout = RENDMP_VOUT_ORDER.contains(&code) || code == vout_max;
+
+        if vout && mode.is_none() {
+            unvalidated = true;
+            continue;
+        }
+
+        match rendmp_ingest_value(device, code, payload, mode) {
+            Err(err) => {
+                suspicious(
+                    lineno,
+                    format!("{name} value {payload:x?} is invalid: {err}"),
+                );
+            }
+            Ok(Some(value)) if vout => {
+                if value < 0.0 {
+                    suspicious(lineno, format!("{name} is negative ({value})"));
+                }
+
+                vouts
+                    .entry(rail)
+                    .or_default()
+                    .insert(code, (name, value, lineno));
+            }
+            Ok(_) => {}
+        }
+    }
+
+    //
+    // Now check that the output voltage settings for each rail are
+    // consistent with one another.
+    //
+    for (rail, vouts) in &vouts {
+        let mut prev: Option<(&str, f32)> = None;
+
+        for code in RENDMP_VOUT_ORDER {
+            let Some(&(name, value, lineno)) = vouts.get(code) else {
+                continue;
+            };
+
+            if let Some((pname, pvalue)) = prev {
+                if pvalue > value {
+                    suspicious(
+                        lineno,
+                        format!(
+                            "rail {rail}: {name} ({value}V) is below \
+                            {pname} ({pvalue}V)"
+                        ),
+                    );
+                }
+            }
+
+            if let Some(&(_, max, _)) = vouts.get(&vout_max) {
+                if RENDMP_VOUT_CAPPED.contains(code) && value > max {
+                    suspicious(
+                        lineno,
+                        format!(
+                            "rail {rail}: {name} ({value}V) exceeds \
+                            VOUT_MAX ({max}V)"
+                        ),
+                    );
+                }
+            }
+
+            prev = Some((name, value));
+        }
+    }
+
+    if unvalidated {
+        humility::msg!(
+            "VOUT_MODE not specified for all rails; \
+            some VOUT values have not been validated"
+        );
+    }
+
+    nwarnings
+}
+
 fn rendmp_ingest(subargs: &RendmpArgs) -> Result<()> {
     let filename = subargs.ingest.as_ref().unwrap();
     let file = fs::File::open(filename)?;
@@ -763,6 +1101,7 @@ fn rendmp_ingest(subargs: &RendmpArgs) -> Result<()> {
 
     let mut allcmds = HashMap::new();
     let mut packets = vec![];
+    let mut linenos = vec![];
 
     let device = if let Some(driver) = &subargs.dev.driver {
         match pmbus::Device::from_str(driver) {
@@ -848,9 +1187,18 @@ fn rendmp_ingest(subargs: &RendmpArgs) -> Result<()> {
             }
         } else {
             match parse_int::parse::<u8>(address) {
-                Ok(paddr) => {
-                    Address::Pmbus(paddr, allcmds.get(&paddr).unwrap())
-                }
+                Ok(paddr) => match allcmds.get(&paddr) {
+                    Some(name) => Address::Pmbus(paddr, name),
+                    None => {
+                        warn!(
+                            "line {}: 0x{:02x} is not a known {} command",
+                            lineno,
+                            paddr,
+                            device.name()
+                        );
+                        Address::Pmbus(paddr, "<unknown>")
+                    }
+                },
                 Err(_) => {
                     bail!("bad PMBus address on line {}: {}", lineno, address);
                 }
@@ -858,6 +1206,13 @@ fn rendmp_ingest(subargs: &RendmpArgs) -> Result<()> {
         };
 
         packets.push(Packet { address, payload });
+        linenos.push(lineno);
+    }
+
+    let nwarnings = rendmp_ingest_validate(&device, &packets, &linenos);
+
+    if nwarnings != 0 {
+        warn!("{filename}: {nwarnings} suspicious value(s) found");
     }
 
     packets.push(Packet {
@@ -866,11 +1221,104 @@ fn rendmp_ingest(subargs: &RendmpArgs) -> Result<()> {
     });
 
     let commands = all_commands(device);
-    rendmp_gen(subargs, &device, &packets, &commands)?;
+    rendmp_gen(
+        "a .txt dump from running Renesas configuration software",
+        &device,
+        &packets,
+        &commands,
+    )?;
 
     Ok(())
 }
 
+///
+/// Generates the Rust payload from a HEX image.  Each data record in the
+/// image is a PMBus write (a command code followed by its data); a write to
+/// DMAADDR followed by a write to DMAFIX is a DMA write.
+///
+fn rendmp_generate(subargs: &RendmpArgs) -> Result<()> {
+    let filename = subargs.flash.as_ref().unwrap();
+    let hex = RendmpHex::from_file(filename, None)?;
+
+    let driver = hex.device.to_string().to_lowercase();
+
+    let device = match pmbus::Device::from_str(&driver) {
+        Some(device) => device,
+        None => bail!("image is for {}, which is not a PMBus device", driver),
+    };
+
+    if let Some(driver) = &subargs.dev.driver {
+        match pmbus::Device::from_str(driver) {
+            Some(d) if d.name() == device.name() => {}
+            _ => bail!("image is for {}, not {}", hex.device, driver),
+        }
+    }
+
+    let mut names = HashMap::new();
+
+    for code in 0..=0xffu8 {
+        device.command(code, |cmd| {
+            names.insert(code, cmd.name());
+        });
+    }
+
+    let commands = all_commands(device);
+    let code = |name| commands.get(name).map(|(code, _, _)| *code);
+    let (dmaaddr, dmafix) = (code("DMAADDR"), code("DMAFIX"));
+
+    let mut packets = vec![];
+    let mut records = hex.data.iter().enumerate().peekable();
+
+    while let Some((ndx, record)) = records.next() {
+        let (code, payload) = match record.split_first() {
+            Some((code, payload)) => (*code, payload),
+            None => bail!("empty data record {}", ndx),
+        };
+
+        if Some(code) == dmaaddr && payload.len() == 2 {
+            if let Some((_, next)) = records.peek() {
+                if next.first().copied() == dmafix {
+                    let addr = u16::from_le_bytes([payload[0], payload[1]]);
+
+                    packets.push(Packet {
+                        address: Address::Dma(addr),
+                        payload: next[1..].to_vec(),
+                    });
+
+                    records.next();
+                    continue;
+                }
+            }
+        }
+
+        let name = match names.get(&code) {
+            Some(name) => name,
+            None => bail!("unknown command 0x{:02x} in record {}", code, ndx),
+        };
+
+        packets.push(Packet {
+            address: Address::Pmbus(code, name),
+            payload: payload.to_vec(),
+        });
+    }
+
+    humility::msg!(
+        "generating payload for {} at 0x{:x} from {} records",
+        hex.device,
+        hex.address,
+        hex.data.len()
+    );
+
+    rendmp_gen(
+        &format!(
+            "the HEX image \"{filename}\" from Renesas configuration software"
+        ),
+        &device,
+        &packets,
+        &commands,
+    )
+}
+
 /// A device which supports open-pin detection and other advanced debug
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum SupportedDevice {
@@ -967,12 +1415,12 @@ fn check_addr(
 }
 
 fn rendmp_blackbox(
-    subargs: RendmpArgs,
+    subargs: &RendmpArgs,
     hubris: &HubrisArchive,
     core: &mut dyn humility::core::Core,
     context: &mut HiffyContext,
 ) -> Result<()> {
-    let (addr, _dev) = check_addr(&subargs, hubris)?;
+    let (addr, _dev) = check_addr(subargs, hubris)?;
     let op = hubris.get_idol_command("Power.rendmp_blackbox_dump")?;
     let value =
         hiffy_call(hubris, core, context, &op, &[("addr", addr.into())], None)?;
@@ -2021,40 +2469,345 @@ fn restore_default_config<'a>(
     Ok(())
 }
 
-fn rendmp(context: &mut ExecutionContext) -> Result<()> {
-    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
-    let hubris = context.archive.as_mut().unwrap();
+/// The OTP state of a single Renesas device, as determined by `--crc --all`
+struct Audit<'a> {
+    device: RendmpDevice,
+    hargs: I2cArgs<'a>,
+    /// The OTP CRC and number of NVM slots remaining, or a read error
+    result: Result<(u32, u32), String>,
+}
 
-    let core = &mut **context.core.as_mut().unwrap();
+///
+/// Reads the OTP CRC and the number of remaining NVM slots from every
+/// Renesas device in the manifest.  Errors reading an individual device
+/// are recorded in its `Audit` rather than failing the entire audit.
+///
+fn rendmp_audit<'a>(
+    hubris: &'a HubrisArchive,
+    core: &mut dyn humility::core::Core,
+    timeout: u32,
+) -> Result<Vec<Audit<'a>>> {
+    let mut context = HiffyContext::new(hubris, core, timeout)?;
+    let i2c_read = context.get_function("I2cRead", 7)?;
+    let i2c_write = context.get_function("I2cWrite", 8)?;
+    let mut audits = vec![];
+
+    for device in &hubris.manifest.i2c_devices {
+        let Ok(d) = RendmpDevice::from_str(&device.device) else {
+            continue;
+        };
 
-    let subargs = RendmpArgs::try_parse_from(subargs)?;
+        let Some(driver) = pmbus::Device::from_str(&device.device) else {
+            continue;
+        };
+
+        let all = all_commands(driver);
+
+        let (Some(&(dmaaddr, ..)), Some(&(dmaseq, ..))) =
+            (all.get("DMAADDR"), all.get("DMASEQ"))
+        else {
+            continue;
+        };
+
+        let retry = I2cRetryPolicy::default().idempotent(None, Some(dmaaddr));
+        let hargs = I2cArgs::from_device(device);
+        let mut ops =
+            vec![Op::Push(hargs.controller), Op::Push(hargs.port.index)];
+
+        if let Some(mux) = hargs.mux {
+            ops.push(Op::Push(mux.0));
+            ops.push(Op::Push(mux.1));
+        } else {
+            ops.push(Op::PushNone);
+            ops.push(Op::PushNone);
+        }
+
+        ops.push(Op::Push(device.address));
+
+        for addr in [d.slot_addr(), d.crc_addr()] {
+            ops.push(Op::Push(dmaaddr));
+            ops.push(Op::Push(addr[0]));
+            ops.push(Op::Push(addr[1]));
+            ops.push(Op::Push(2));
+            ops.push(Op::Call(i2c_write.id));
+            ops.push(Op::DropN(4));
+
+            ops.push(Op::Push(dmaseq));
+            ops.push(Op::Push(4));
+            ops.push(Op::Call(i2c_read.id));
+            ops.push(Op::DropN(2));
+        }
+
+        ops.push(Op::Done);
+
+        let results =
+            retry.run(&mut context, core, ops.as_slice(), None, &i2c_read)?;
+
+        let word = |ndx: usize, what| match (&results[ndx - 1], &results[ndx]) {
+            (Err(err), _) | (_, Err(err)) => Err(format!(
+                "failed to read {what}: {}",
+                i2c_read.strerror(*err)
+            )),
+            (_, Ok(val)) if val.len() == 4 => {
+                Ok(u32::from_le_bytes(val[0..4].try_into().unwrap()))
+            }
+            (_, Ok(val)) => Err(format!("bad length on {what}: {val:x?}")),
+        };
+
+        let result = word(3, "CRC")
+            .and_then(|crc| Ok((crc, word(1, "available slots")?)));
+
+        audits.push(Audit { device: d, hargs, result });
+    }
+
+    if audits.is_empty() {
+        bail!("no Renesas devices found in the manifest");
+    }
+
+    Ok(audits)
+}
+
+///
+/// Prints the results of `--crc --all`, either for our attached target or
+/// (with `--fleet`) for each target in a fleet.
+///
+fn rendmp_audit_print(
+    subargs: &RendmpArgs,
+    results: &[(Option<String>, Result<Vec<Audit>>)],
+) -> Result<()> {
+    let fleet = results.iter().any(|(target, _)| target.is_some());
+
+    if subargs.csv {
+        println!("target,device,controller,port,mux,address,crc,slots,error");
+
+        for (target, audits) in results {
+            let target = target.as_deref().unwrap_or("");
+
+            let audits = match audits {
+                Ok(audits) => audits,
+                Err(err) => {
+                    println!("{target},,,,,,,,\"{err}\"");
+                    continue;
+                }
+            };
+
+            for audit in audits {
+                let h = &audit.hargs;
+
+                print!(
+                    "{target},{},{},{},{},0x{:02x},",
+                    audit.device,
+                    h.controller,
+                    h.port.name,
+                    h.mux.map(|(m, s)| format!("{m}:{s}")).unwrap_or_default(),
+                    h.address.unwrap_or_default(),
+                );
+
+                match &audit.result {
+                    Ok((crc, slots)) => println!("0x{crc:08x},{slots},"),
+                    Err(err) => println!(",,\"{err}\""),
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    if fleet {
+        print!("{:16} ", "TARGET");
+    }
+
+    println!("{:10} {:32} {:>10} {:>5}", "DEVICE", "LOCATION", "CRC", "SLOTS");
+
+    //
+    // As we go, we track the CRCs that we see at each location; for a
+    // fleet, we want to call out any location at which they differ.
+    //
+    let mut crcs: BTreeMap<String, BTreeMap<u32, usize>> = BTreeMap::new();
+
+    for (target, audits) in results {
+        if fleet {
+            print!("{:16} ", target.as_deref().unwrap_or(""));
+        }
+
+        let audits = match audits {
+            Ok(audits) => audits,
+            Err(err) => {
+                println!("{}", format!("<failed: {err}>").red());
+                continue;
+            }
+        };
+
+        for (ndx, audit) in audits.iter().enumerate() {
+            if fleet && ndx > 0 {
+                print!("{:16} ", "");
+            }
+
+            let device = audit.device.to_string();
+            let location = audit.hargs.to_string();
+            print!("{device:10} {location:32} ");
+
+            match &audit.result {
+                Ok((crc, slots)) => {
+                    println!("0x{crc:08x} {slots:>5}");
+
+                    *crcs
+                        .entry(format!("{device} at {location}"))
+                        .or_default()
+                        .entry(*crc)
+                        .or_default() += 1;
+                }
+                Err(err) => println!("{}", err.red()),
+            }
+        }
+    }
+
+    if fleet {
+        for (device, crcs) in crcs.iter().filter(|(_, crcs)| crcs.len() > 1) {
+            let summary = crcs
+                .iter()
+                .map(|(crc, count)| format!("0x{crc:08x} ({count})"))
+                .collect::<Vec<_>>();
+
+            warn!("{device} has differing CRCs: {}", summary.join(", "));
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Audits the Renesas devices on each target listed in the specified file.
+/// Each line of the file consists of an IP address, optionally preceded
+/// by a name for the target; `#` denotes a comment.  All targets are
+/// expected to be running the image in our archive.
+///
+fn rendmp_fleet(
+    context: &mut ExecutionContext,
+    subargs: &RendmpArgs,
+    filename: &str,
+) -> Result<()> {
+    let cli = &context.cli;
+
+    if cli.probe.is_some() || cli.ip.is_some() || cli.dump.is_some() {
+        bail!("cannot specify a probe, IP address, or dump with --fleet");
+    }
+
+    let hubris = context.archive.as_ref().unwrap();
+    let timeout = Duration::from_millis(cli.timeout as u64);
+
+    let contents = fs::read_to_string(filename)
+        .with_context(|| format!("failed to read {filename}"))?;
+
+    let mut results = vec![];
+
+    for (lineno, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let (name, ip) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [ip] => (ip, ip),
+            [name, ip] => (name, ip),
+            _ => {
+                bail!("{filename}:{}: malformed target \"{line}\"", lineno + 1)
+            }
+        };
+
+        let audit = humility_net_core::attach_net(ip, hubris, timeout)
+            .and_then(|mut core| {
+                hubris.validate(&mut *core, HubrisValidate::Booted)?;
+                rendmp_audit(hubris, &mut *core, subargs.timeout)
+            });
+
+        if let Err(ref err) = audit {
+            warn!("failed to audit {name}: {err:?}");
+        }
+
+        results.push((Some(name.to_string()), audit));
+    }
+
+    if results.is_empty() {
+        bail!("no targets found in {filename}");
+    }
+
+    rendmp_audit_print(subargs, &results)
+}
+
+fn rendmp(context: &mut ExecutionContext) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let mut subargs = RendmpArgs::try_parse_from(subargs)?;
 
     // Workaround for clap#4707
     if subargs.flash.is_none() {
+        let requires_flash = |opt: &str| {
+            Diagnostic::error(format!("{opt} requires --flash"))
+                .code("requires-flash")
+                .hint("specify the image to flash with --flash")
+        };
+
         if subargs.dryrun {
-            bail!("--dryrun requires --flash");
+            bail!(requires_flash("--dryrun"));
         } else if subargs.force {
-            bail!("--force requires --flash");
+            bail!(requires_flash("--force"));
         } else if subargs.check {
-            bail!("--check requires --flash");
+            bail!(requires_flash("--check"));
+        } else if subargs.generate {
+            bail!(requires_flash("--generate"));
         }
     }
 
+    subargs.dryrun |= context.cli.dry_run;
+
     if subargs.ingest.is_some() {
         return rendmp_ingest(&subargs);
     }
 
+    if subargs.generate {
+        return rendmp_generate(&subargs);
+    }
+
+    //
+    // If we have been given a list of targets, we attach to each of them
+    // in turn over the network; otherwise, we attach to our (sole) target.
+    //
+    if let Some(ref fleet) = subargs.fleet {
+        return rendmp_fleet(context, &subargs, fleet);
+    }
+
+    humility_cmd::attach(
+        context,
+        Attach::LiveOnly,
+        Validate::Booted,
+        |context| rendmp_attached(context, &subargs),
+    )
+}
+
+fn rendmp_attached(
+    context: &mut ExecutionContext,
+    subargs: &RendmpArgs,
+) -> Result<()> {
+    let hubris = context.archive.as_mut().unwrap();
+    let core = &mut **context.core.as_mut().unwrap();
+
+    if subargs.all {
+        let audits = rendmp_audit(hubris, core, subargs.timeout)?;
+        return rendmp_audit_print(subargs, &[(None, Ok(audits))]);
+    }
+
     let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
     if subargs.blackbox {
         return rendmp_blackbox(subargs, hubris, core, &mut context);
     } else if subargs.open_pin {
-        return rendmp_open_pin(&subargs, hubris, core, &mut context);
+        return rendmp_open_pin(subargs, hubris, core, &mut context);
     } else if subargs.phase_check {
         // Bail out early if the arguments are invalid
-        let _ = check_addr(&subargs, hubris)?;
-        let out = rendmp_phase_check(&subargs, hubris, core, &mut context);
+        let _ = check_addr(subargs, hubris)?;
+        let out = rendmp_phase_check(subargs, hubris, core, &mut context);
         if let Err(e) =
-            restore_default_config(&subargs, hubris, core, &mut context)
+            restore_default_config(subargs, hubris, core, &mut context)
                 .context("failed to restore default cfg")
         {
             if out.is_err() {
@@ -2164,6 +2917,14 @@ fn rendmp(context: &mut ExecutionContext) -> Result<()> {
         }
     };
 
+    //
+    // Setting the DMA address is idempotent, so programs that set it and
+    // then read from it can be safely retried in the face of transient
+    // errors.  Note that we do not retry the reads of a memory dump, as the
+    // device's DMA address auto-increments between them.
+    //
+    let retry = I2cRetryPolicy::default().idempotent(None, Some(dmaaddr));
+
     let dmaseq = match all.get("DMASEQ") {
         Some((code, read, _)) => {
             if *read != pmbus::Operation::ReadWord32 {
@@ -2216,7 +2977,8 @@ fn rendmp(context: &mut ExecutionContext) -> Result<()> {
         dmaread_ops(&mut ops, d.crc_addr(), 4);
 
         ops.push(Op::Done);
-        let results = context.run(core, ops.as_slice(), None)?;
+        let results =
+            retry.run(&mut context, core, ops.as_slice(), None, &i2c_read)?;
 
         let crc = word_result(&results[1], "CRC")?;
         humility::msg!("{d} at {hargs} has CRC 0x{crc:<08x}");
@@ -2232,7 +2994,8 @@ fn rendmp(context: &mut ExecutionContext) -> Result<()> {
         dmaread_ops(&mut ops, d.crc_addr(), 4);
 
         ops.push(Op::Done);
-        let results = context.run(core, ops.as_slice(), None)?;
+        let results =
+            retry.run(&mut context, core, ops.as_slice(), None, &i2c_read)?;
 
         let nslots = word_result(&results[1], "available slots")?;
         humility::msg!("{d} at {hargs} has {nslots} slots available");
@@ -2241,7 +3004,7 @@ fn rendmp(context: &mut ExecutionContext) -> Result<()> {
     }
 
     if let Some(ref flash) = subargs.flash {
-        let hex = RendmpHex::from_file(flash, address)?;
+        let hex = RendmpHex::from_file(flash, Some(address))?;
 
         //
         // We first need to validate that the IC_DEVICE_ID matches.  The
@@ -2272,7 +3035,8 @@ fn rendmp(context: &mut ExecutionContext) -> Result<()> {
         dmaread_ops(&mut ops, hex.device.crc_addr(), 4);
 
         ops.push(Op::Done);
-        let results = context.run(core, ops.as_slice(), None)?;
+        let results =
+            retry.run(&mut context, core, ops.as_slice(), None, &i2c_read)?;
 
         match &results[0] {
             Err(err) => {
@@ -2337,7 +3101,9 @@ fn rendmp(context: &mut ExecutionContext) -> Result<()> {
             }
 
             if !subargs.force {
-                bail!("{msg}; use --force to force");
+                bail!(Diagnostic::error(msg)
+                    .code("crc-matches")
+                    .hint("use --force to flash anyway"));
             } else {
                 humility::msg!("{msg}; flashing anyway");
             }
@@ -2439,7 +3205,13 @@ fn rendmp(context: &mut ExecutionContext) -> Result<()> {
             dmaread_ops(&mut ops, hex.device.bank_status_addr(), 8);
             ops.push(Op::Done);
 
-            let results = context.run(core, ops.as_slice(), None)?;
+            let results = retry.run(
+                &mut context,
+                core,
+                ops.as_slice(),
+                None,
+                &i2c_read,
+            )?;
 
             let status = match &results[1] {
                 Err(err) => {
@@ -2604,10 +3376,141 @@ pub fn init() -> Command {
         app: RendmpArgs::command(),
         name: "rendmp",
         run: rendmp,
-        kind: CommandKind::Attached {
-            archive: Archive::Required,
-            attach: Attach::LiveOnly,
-            validate: Validate::Booted,
-        },
+        kind: CommandKind::Unattached { archive: Archive::Required },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(kind: u8, addr: u8, payload: &[u8]) -> String {
+        let mut vals = vec![kind, payload.len() as u8 + 2, addr << 1];
+        vals.extend_from_slice(payload);
+        vals.push(0xff);
+        vals.iter().map(|v| format!("{v:02X}")).collect()
+    }
+
+    //
+    // Synthesizes an image for the given device ID:  IC_DEVICE_ID and
+    // IC_DEVICE_REV headers followed by enough data records to fill out
+    // the image, with the CRC at the device's CRC line.
+    //
+    fn image(id: u8, addr: u8, crc: u32) -> Vec<String> {
+        let device = RendmpDevice::from_id(id).unwrap();
+        let mut lines = vec![
+            record(0x49, addr, &[0xad, 0x00, 0x49, id, 0xd2]),
+            record(0x49, addr, &[0xae, 0x06, 0x00, 0x00, 0x00]),
+        ];
+
+        for l in lines.len() + 1..=device.lines() {
+            if l == device.crc_line() {
+                let mut payload = vec![0xe7];
+                payload.extend_from_slice(&crc.to_le_bytes());
+                lines.push(record(0, addr, &payload));
+            } else {
+                lines.push(record(0, addr, &[0xc7, l as u8, (l >> 8) as u8]));
+            }
+        }
+
+        lines
+    }
+
+    fn parse(lines: &[String], address: Option<u8>) -> Result<RendmpHex> {
+        RendmpHex::parse(lines.join("\n").as_bytes(), address)
+    }
+
+    fn parse_err(lines: &[String], address: Option<u8>) -> String {
+        match parse(lines, address) {
+            Ok(_) => panic!("parse unexpectedly succeeded"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_gen2() {
+        let hex = parse(&image(0x52, 0x5c, 0xdeadbeef), Some(0x5c)).unwrap();
+
+        assert_eq!(hex.device.to_string(), "ISL68224");
+        assert_eq!(hex.address, 0x5c);
+        assert_eq!(hex.ic_device_id, [0xd2, 0x52, 0x49, 0x00]);
+        assert_eq!(hex.ic_device_rev, [0x00, 0x00, 0x00, 0x06]);
+        assert_eq!(hex.crc, 0xdeadbeef);
+        assert_eq!(hex.data.len(), 290 + 358 - 2);
+        assert_eq!(hex.data[0], [0xc7, 3, 0]);
+    }
+
+    #[test]
+    fn parse_gen2p5() {
+        let hex = parse(&image(0x99, 0x5a, 0x1234_5678), None).unwrap();
+
+        assert_eq!(hex.device.to_string(), "RAA229618");
+        assert_eq!(hex.address, 0x5a);
+        assert_eq!(hex.crc, 0x1234_5678);
+        assert_eq!(hex.data.len(), 273 + 309 - 2);
+    }
+
+    #[test]
+    fn parse_comments() {
+        let mut lines = image(0x52, 0x5c, 0);
+        lines.insert(0, "# generated by a test".to_string());
+        lines.insert(5, String::new());
+
+        let hex = parse(&lines, Some(0x5c)).unwrap();
+        assert_eq!(hex.data.len(), 290 + 358 - 2);
+    }
+
+    #[test]
+    fn parse_wrong_address() {
+        let err = parse_err(&image(0x52, 0x5c, 0), Some(0x5a));
+        assert_eq!(err, "image specifies address to be 0x5c; can't flash 0x5a");
+
+        let mut lines = image(0x52, 0x5c, 0);
+        lines[10] = record(0, 0x5a, &[0xc7, 0, 0]);
+        let err = parse_err(&lines, None);
+        assert!(err.contains("0x5a on line 11"), "{err}");
+    }
+
+    #[test]
+    fn parse_malformed() {
+        let base = image(0x52, 0x5c, 0);
+
+        let mut lines = base.clone();
+        lines[2].pop();
+        assert!(parse_err(&lines, None).starts_with("short hex input"));
+
+        let mut lines = base.clone();
+        lines[2].replace_range(4..6, "ZZ");
+        let err = parse_err(&lines, None);
+        assert_eq!(err, "bad hex value on line 3 in column 5: ZZ");
+
+        let mut lines = base.clone();
+        lines[2].replace_range(0..2, "01");
+        assert_eq!(parse_err(&lines, None), "bad record kind 0x1 on line 3");
+
+        let mut lines = base.clone();
+        lines[2].replace_range(2..4, "09");
+        assert_eq!(parse_err(&lines, None), "bad record length 9 on line 3");
+
+        let mut lines = base.clone();
+        lines[2] = "00".to_string();
+        assert_eq!(parse_err(&lines, None), "short hex input on line 3");
+    }
+
+    #[test]
+    fn parse_bad_image() {
+        let mut lines = image(0x52, 0x5c, 0);
+        lines.pop();
+        assert_eq!(
+            parse_err(&lines, None),
+            "expected 648 total lines, found 647"
+        );
+
+        let lines = image(0x52, 0x5c, 0)[2..].to_vec();
+        assert_eq!(parse_err(&lines, None), "insufficient headers found");
+
+        let mut lines = image(0x52, 0x5c, 0);
+        lines[0] = record(0x49, 0x5c, &[0xad, 0x00, 0x49, 0x01, 0xd2]);
+        assert_eq!(parse_err(&lines, None), "unknown device id 0x1");
     }
 }
@@ -49,9 +49,15 @@
 //! in the background; your code is still running in the background while you
 //! use the repl!
 //!
+//! The target is attached to once, and the archive is loaded once, when the
+//! repl starts; each command within the repl makes use of them, making short
+//! commands considerably faster than they would be if run individually.
+//!
+//! Pressing tab completes subcommands and their options, as well as the names
+//! of tasks, variables and power rails found in the archive (e.g., for use
+//! with `readvar`, `ringbuf` or `power`).
+//!
 //! Finally, as you can see, `quit` will quit the repl. There is also a
 //! `history` command, which will show you recent commands you've put into the
-//! prompt.
-//!
-//! The repl is still very early days! We'll be adding more features in the
-//! future.
+//! prompt.  History is saved in `~/.humility_history`, and is therefore
+//! available from one repl session to the next.
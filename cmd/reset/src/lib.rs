@@ -34,7 +34,7 @@ fn reset(context: &mut ExecutionContext) -> Result<()> {
         None => "auto",
     };
 
-    let mut c = if subargs.soft_reset || subargs.halt {
+    let c = if subargs.soft_reset || subargs.halt {
         let chip = hubris.chip().ok_or_else(|| {
             anyhow::anyhow!(
                 "Need a chip to do a soft reset or halt after reset"
@@ -45,6 +45,8 @@ fn reset(context: &mut ExecutionContext) -> Result<()> {
         humility::core::attach_to_probe(probe)?
     };
 
+    let mut c = humility_cmd::dry_run(&context.cli, c);
+
     let r = if subargs.halt {
         c.reset_and_halt(std::time::Duration::from_secs(2))
     } else {
@@ -50,6 +50,82 @@
 //! ...
 //! ```
 //!
+//! Ring buffer entries are not themselves timestamped, but `--timeline` will
+//! display the kernel's tick count and (for a live target) the host's wall
+//! clock time at which each ring buffer was read; every entry in the buffer
+//! was recorded at or before this time.  This is the same timebase used by
+//! `humility itm --timeline`.
+//!
+//! To continuously display new entries as they are recorded (in the manner of
+//! `tail -f`), use `--follow` (`-f`).  The ring buffers are polled, and only
+//! entries that have not been seen before are printed; an entry that has been
+//! repeated since it was last printed is printed again with its updated count.
+//! Polls that find no new entries cause the interval between polls to back off
+//! (up to 16 times the interval specified with `--interval`, which defaults to
+//! 100 milliseconds) to limit traffic on the debug bus.  If a ring buffer wraps
+//! between polls, a warning is displayed, as entries will have been missed.
+//!
+//! To display the ring buffers of several tasks as a single stream, use
+//! `--merge` (`-m`).  The ring buffers are read with the target halted, and
+//! their entries are merged from oldest to newest.  As ring buffer entries are
+//! not timestamped, entries are ordered by their age within their own ring
+//! buffer (that is, by the number of entries -- counting repeats -- that were
+//! subsequently recorded in the same buffer):  entries from a single ring
+//! buffer are always in order, but the order of entries from different ring
+//! buffers is only approximate, and a note to this effect is displayed.
+//!
+//! ```console
+//! $ humility ringbuf -m -F "task=thermal task=sensor"
+//! humility: attached via ST-Link V3
+//! humility: entries are merged by age; order across ring buffers is approximate
+//! TASK         BUFFER                   NDX LINE      GEN    COUNT PAYLOAD
+//! thermal      task_thermal::__RINGBUF   12  269        3        1 Pwm(0x1e)
+//! sensor       task_sensor::__RINGBUF    40   98        7        1 Post(0x4)
+//! thermal      task_thermal::__RINGBUF   13  231        3        1 Running
+//! ```
+//!
+//! Entries can be filtered with `--filter` (`-F`), which takes a
+//! whitespace-separated list of clauses of the form `key=value` or
+//! `key!=value`.  The key is one of `task` or `buffer` (which match by
+//! substring), or `variant` (which matches the name of the variant of an enum
+//! payload exactly).  An entry is displayed if it matches at least one `=`
+//! clause for each key that has them, and matches none of the `!=` clauses.
+//! For example, to display every entry in the `i2c_driver` and `spd` tasks
+//! other than `Idle` entries:
+//!
+//! ```console
+//! $ humility ringbuf -F "task=i2c_driver task=spd variant!=Idle"
+//! ```
+//!
+//! To post-process ring buffers, use `--json` (`-j`) to emit entries as JSON.
+//! Each entry names its task and ring buffer, and its payload is fully decoded:
+//! structs become objects, and enums become either the name of the variant or
+//! (for a variant with contents) an object with the variant name as its key.
+//! Each entry also has an `age`:  the number of entries (counting repeats)
+//! recorded in its ring buffer after it, by which merged entries are ordered.
+//! `--json` can be combined with `--merge` and `--filter`:
+//!
+//! ```console
+//! $ humility ringbuf -j -m -F "variant=Read" ksz
+//! [
+//!   {
+//!     "age": 5,
+//!     "buffer": "ksz8463::__RINGBUF",
+//!     "count": 1,
+//!     "generation": 89,
+//!     "index": 2,
+//!     "line": 134,
+//!     "payload": {
+//!       "Read": [
+//!         "IADR5",
+//!         16384
+//!       ]
+//!     },
+//!     "task": "net"
+//!   },
+//! ...
+//! ```
+//!
 //! See the [`ringbuf`
 //! documentation](https://github.com/oxidecomputer/hubris/blob/master/lib/ringbuf/src/lib.rs) for more details.
 
@@ -57,10 +133,14 @@ use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
 use humility::core::Core;
 use humility::hubris::*;
-use humility::reflect::{self, Format, Load, Value};
+use humility::reflect::Value;
+use humility::timebase::Timebase;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
-use humility_doppel::{Ringbuf, StaticCell};
+use humility_doppel::{Ringbuf, RingbufEntry};
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[clap(name = "ringbuf", about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -71,69 +151,486 @@ struct RingbufArgs {
     /// print full errors
     #[clap(long, short)]
     verbose: bool,
+    /// display the kernel ticks and wall clock time of each read
+    #[clap(long, conflicts_with = "list")]
+    timeline: bool,
+    /// poll for and print new entries
+    #[clap(long, short, conflicts_with_all = &["list", "timeline"])]
+    follow: bool,
+    /// minimum interval between polls when following
+    #[clap(
+        long, short, default_value_t = 100, value_name = "ms",
+        requires = "follow", parse(try_from_str = parse_int::parse)
+    )]
+    interval: u64,
+    /// merge ring buffers into a single stream, ordered from oldest entry
+    #[clap(long, short, conflicts_with_all = &["list", "follow"])]
+    merge: bool,
+    /// display only entries that match the expression, e.g.
+    /// "task=i2c_driver variant!=Idle"
+    #[clap(long, short = 'F', value_name = "expr", conflicts_with = "list")]
+    filter: Option<RingbufFilter>,
+    /// display entries as JSON, with payloads fully decoded
+    #[clap(
+        long, short,
+        conflicts_with_all = &["list", "follow", "timeline"]
+    )]
+    json: bool,
     /// print only a single ringbuffer by substring of name
     #[clap(conflicts_with = "list")]
     name: Option<String>,
 }
 
-fn ringbuf_dump(
+///
+/// A ring buffer to be read:  the name of its task, its name, its variable
+/// and its definition.
+///
+type RingbufSource<'a> =
+    (&'a str, &'a str, &'a HubrisVariable, &'a HubrisStruct);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FilterKey {
+    Task,
+    Buffer,
+    Variant,
+}
+
+#[derive(Clone, Debug)]
+struct FilterClause {
+    key: FilterKey,
+    negated: bool,
+    value: String,
+}
+
+///
+/// An expression that selects ring buffer entries, consisting of
+/// whitespace-separated clauses of the form `key=value` or `key!=value`,
+/// where `key` is one of `task`, `buffer` or `variant`.  Tasks and buffers
+/// match by substring; a variant (that is, the name of the variant of an
+/// enum payload) must match exactly.  An entry matches if, for each key
+/// that has `=` clauses, it matches at least one of them -- and it matches
+/// none of the `!=` clauses.
+///
+#[derive(Clone, Debug)]
+struct RingbufFilter {
+    clauses: Vec<FilterClause>,
+}
+
+impl std::str::FromStr for RingbufFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut clauses = vec![];
+
+        for clause in s.split_whitespace() {
+            let (key, negated, value) = match clause.split_once("!=") {
+                Some((key, value)) => (key, true, value),
+                None => match clause.split_once('=') {
+                    Some((key, value)) => (key, false, value),
+                    None => bail!(
+                        "invalid clause \"{clause}\"; \
+                        expected key=value or key!=value"
+                    ),
+                },
+            };
+
+            let key = match key {
+                "task" => FilterKey::Task,
+                "buffer" => FilterKey::Buffer,
+                "variant" => FilterKey::Variant,
+                _ => bail!(
+                    "invalid key \"{key}\"; \
+                    expected \"task\", \"buffer\" or \"variant\""
+                ),
+            };
+
+            if value.is_empty() {
+                bail!("clause \"{clause}\" is missing a value");
+            }
+
+            clauses.push(FilterClause { key, negated, value: value.into() });
+        }
+
+        if clauses.is_empty() {
+            bail!("filter expression is empty");
+        }
+
+        Ok(Self { clauses })
+    }
+}
+
+impl RingbufFilter {
+    fn check(
+        &self,
+        keys: &[FilterKey],
+        matched: impl Fn(&FilterClause) -> bool,
+    ) -> bool {
+        let clauses = self.clauses.iter().filter(|c| keys.contains(&c.key));
+
+        keys.iter().all(|&key| {
+            let mut positive =
+                clauses.clone().filter(|c| c.key == key && !c.negated);
+
+            positive.clone().next().is_none() || positive.any(&matched)
+        }) && !clauses.clone().any(|c| c.negated && matched(c))
+    }
+
+    /// Determines if entries in the specified ring buffer can match
+    fn selects(&self, task: &str, buffer: &str) -> bool {
+        self.check(&[FilterKey::Task, FilterKey::Buffer], |c| match c.key {
+            FilterKey::Task => task.contains(&c.value),
+            FilterKey::Buffer => buffer.contains(&c.value),
+            FilterKey::Variant => false,
+        })
+    }
+
+    /// Determines if the specified entry matches
+    fn matches(&self, task: &str, buffer: &str, payload: &Value) -> bool {
+        let variant = match payload {
+            Value::Enum(e) => Some(e.disc()),
+            _ => None,
+        };
+
+        self.check(
+            &[FilterKey::Task, FilterKey::Buffer, FilterKey::Variant],
+            |c| match c.key {
+                FilterKey::Task => task.contains(&c.value),
+                FilterKey::Buffer => buffer.contains(&c.value),
+                FilterKey::Variant => variant == Some(c.value.as_str()),
+            },
+        )
+    }
+}
+
+fn ringbuf_read(
     hubris: &HubrisArchive,
     core: &mut dyn Core,
     definition: &HubrisStruct,
     ringbuf_var: &HubrisVariable,
-) -> Result<()> {
+    timeline: bool,
+) -> Result<Ringbuf> {
     let mut buf: Vec<u8> = vec![];
     buf.resize_with(ringbuf_var.size, Default::default);
 
     core.halt()?;
     core.read_8(ringbuf_var.addr, buf.as_mut_slice())?;
-    core.run()?;
 
-    // There are two possible shapes of ringbufs, depending on the age of the
-    // firmware.
-    // - Raw Ringbuf that is not wrapped by anything.
-    // - Safe Ringbuf that is inside a StaticCell.
     //
-    // Here we will attempt to handle them both -- first raw, then fallback.
-    let ringbuf_val: Value =
-        Value::Struct(reflect::load_struct(hubris, &buf, definition, 0)?);
+    // Ring buffer entries are not themselves timestamped, but we can note
+    // the time at which the buffer was read:  every entry was recorded at
+    // or before this time.
+    //
+    let timebase = if timeline {
+        match Timebase::new(hubris, core) {
+            Ok(timebase) => Some(timebase),
+            Err(e) => {
+                core.run()?;
+                return Err(e);
+            }
+        }
+    } else {
+        None
+    };
 
-    let ringbuf: Ringbuf = Ringbuf::from_value(&ringbuf_val).or_else(|_e| {
-        let cell: StaticCell = StaticCell::from_value(&ringbuf_val)?;
-        Ringbuf::from_value(&cell.cell.value)
-    })?;
+    core.run()?;
 
-    let ndx = if let Some(x) = ringbuf.last {
-        x as usize
-    } else {
+    if let Some(timebase) = timebase {
+        humility::msg!("read at {}", timebase.now());
+    }
+
+    Ringbuf::load(hubris, definition, &buf)
+}
+
+///
+/// An entry from a ring buffer, along with the task and ring buffer that
+/// contain it.
+///
+struct RingbufRecord<'a> {
+    task: &'a str,
+    buffer: &'a str,
+    slot: usize,
+    entry: RingbufEntry,
+    /// The number of entries (counting repeats) that were recorded in the
+    /// same ring buffer after this one
+    age: u64,
+}
+
+impl RingbufRecord<'_> {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "age": self.age,
+            "task": self.task,
+            "buffer": self.buffer,
+            "index": self.slot,
+            "line": self.entry.line,
+            "generation": self.entry.generation,
+            "count": self.entry.count,
+            "payload": self.entry.payload.to_json(),
+        })
+    }
+}
+
+///
+/// Returns the entries of a ring buffer that match the filter (if any),
+/// from oldest to newest.
+///
+fn ringbuf_records<'a>(
+    task: &'a str,
+    buffer: &'a str,
+    ringbuf: &Ringbuf,
+    filter: Option<&RingbufFilter>,
+) -> Vec<RingbufRecord<'a>> {
+    let mut records = vec![];
+    let mut age = 0;
+
+    for slot in ringbuf.slots().into_iter().rev() {
+        let entry = &ringbuf.buffer[slot];
+
+        if filter.map_or(true, |f| f.matches(task, buffer, &entry.payload)) {
+            records.push(RingbufRecord {
+                task,
+                buffer,
+                slot,
+                entry: entry.clone(),
+                age,
+            });
+        }
+
+        age += entry.count as u64;
+    }
+
+    records.reverse();
+    records
+}
+
+fn ringbuf_dump(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    (task, buffer, ringbuf_var, definition): RingbufSource,
+    timeline: bool,
+    filter: Option<&RingbufFilter>,
+) -> Result<()> {
+    let ringbuf =
+        ringbuf_read(hubris, core, definition, ringbuf_var, timeline)?;
+
+    let records = ringbuf_records(task, buffer, &ringbuf, filter);
+
+    if records.is_empty() {
         return Ok(());
+    }
+
+    println!("{}", RingbufEntry::HEADER);
+
+    for r in &records {
+        println!("{}", r.entry.display(hubris, r.slot)?);
+    }
+
+    Ok(())
+}
+
+///
+/// Reads the specified ring buffers with the target halted, so that they
+/// represent a single moment in time.  A ring buffer that cannot be loaded
+/// is reported and skipped.
+///
+fn ringbuf_snapshot(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    sources: &[RingbufSource],
+    timeline: bool,
+) -> Result<Vec<Option<Ringbuf>>> {
+    let mut bufs = vec![];
+
+    core.halt()?;
+
+    for (_, _, var, _) in sources {
+        let mut buf = vec![0u8; var.size];
+
+        if let Err(e) = core.read_8(var.addr, &mut buf) {
+            core.run()?;
+            return Err(e);
+        }
+
+        bufs.push(buf);
+    }
+
+    let timebase = if timeline {
+        match Timebase::new(hubris, core) {
+            Ok(timebase) => Some(timebase),
+            Err(e) => {
+                core.run()?;
+                return Err(e);
+            }
+        }
+    } else {
+        None
     };
 
-    let fmt = HubrisPrintFormat { hex: true, ..HubrisPrintFormat::default() };
+    core.run()?;
 
-    println!("{:>4} {:>4} {:>8} {:>8} PAYLOAD", "NDX", "LINE", "GEN", "COUNT",);
+    if let Some(timebase) = timebase {
+        humility::msg!("read at {}", timebase.now());
+    }
 
-    for i in 0..ringbuf.buffer.len() {
-        let slot = (ndx + i + 1) % ringbuf.buffer.len();
-        let entry = &ringbuf.buffer[slot];
+    Ok(sources
+        .iter()
+        .zip(bufs.iter())
+        .map(|((_, buffer, _, def), buf)| {
+            match Ringbuf::load(hubris, def, buf) {
+                Ok(ringbuf) => Some(ringbuf),
+                Err(e) => {
+                    humility::msg!("ringbuf {buffer} load failed: {e}");
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
+///
+/// Displays entries from all of the specified ring buffers, either in a
+/// single stream (if merging) or grouped by ring buffer.  Entries carry no
+/// timestamps, so a merged stream is ordered by each entry's age:  the
+/// number of entries (counting repeats) that were recorded in its ring
+/// buffer after it.  The order is therefore exact within a ring buffer, but
+/// only approximate across ring buffers.
+///
+fn ringbuf_aggregate(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    sources: &[RingbufSource],
+    subargs: &RingbufArgs,
+) -> Result<()> {
+    let ringbufs = ringbuf_snapshot(hubris, core, sources, subargs.timeline)?;
+    let mut records = vec![];
 
-        if entry.generation == 0 {
-            continue;
+    for ((task, buffer, _, _), ringbuf) in sources.iter().zip(ringbufs.iter()) {
+        if let Some(ringbuf) = ringbuf {
+            let filter = subargs.filter.as_ref();
+            records.extend(ringbuf_records(task, buffer, ringbuf, filter));
         }
+    }
 
-        let mut dumped = vec![];
-        entry.payload.format(hubris, fmt, &mut dumped)?;
-        let dumped = String::from_utf8(dumped)?;
+    if subargs.merge {
+        records.sort_by_key(|r| std::cmp::Reverse(r.age));
 
+        if sources.len() > 1 {
+            humility::msg!(
+                "entries are merged by age; order across ring buffers is \
+                approximate"
+            );
+        }
+    }
+
+    if subargs.json {
+        let json = records.iter().map(|r| r.to_json()).collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:12} {:24} {:>4} {:>4} {:>8} {:>8} PAYLOAD",
+        "TASK", "BUFFER", "NDX", "LINE", "GEN", "COUNT",
+    );
+
+    for r in &records {
         println!(
-            "{:4} {:4} {:8} {:8} {}",
-            slot, entry.line, entry.generation, entry.count, dumped
+            "{:12} {:24} {}",
+            r.task,
+            r.buffer,
+            r.entry.display(hubris, r.slot)?
         );
     }
 
     Ok(())
 }
 
+///
+/// Polls the specified ring buffers, printing only those entries that have
+/// not been seen before.  An entry is identified by its generation and slot;
+/// an entry that has been seen but whose count has since increased (that
+/// is, a repeated entry) is printed again.  If polls find nothing new, we
+/// back off exponentially (up to 16 times the specified interval) to limit
+/// traffic on the debug bus.
+///
+fn ringbuf_follow(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    ringbufs: &[RingbufSource],
+    interval: Duration,
+    filter: Option<&RingbufFilter>,
+) -> Result<()> {
+    let mut last: HashMap<u32, (u16, usize, u32)> = HashMap::new();
+    let mut delay = interval;
+    let prefix = ringbufs.len() > 1;
+
+    if prefix {
+        print!("{:30} ", "BUFFER");
+    }
+
+    println!("{}", RingbufEntry::HEADER);
+
+    loop {
+        let mut found = false;
+
+        for &(task, name, var, def) in ringbufs {
+            let ringbuf = ringbuf_read(hubris, core, def, var, false)?;
+            let seen = last.get(&var.addr).copied();
+
+            //
+            // If the newest entry that we have seen has been overwritten,
+            // the buffer has wrapped since our last poll, and we may have
+            // missed entries.
+            //
+            if let Some((generation, slot, _)) = seen {
+                if ringbuf.buffer[slot].generation != generation {
+                    humility::warn!("{name}: buffer wrapped; entries missed");
+                }
+            }
+
+            for slot in ringbuf.slots() {
+                let entry = &ringbuf.buffer[slot];
+                let key = (entry.generation, slot);
+
+                let new = match seen {
+                    None => true,
+                    Some((generation, s, count)) => {
+                        key > (generation, s)
+                            || (key == (generation, s) && entry.count > count)
+                    }
+                };
+
+                if !new {
+                    continue;
+                }
+
+                last.insert(var.addr, (entry.generation, slot, entry.count));
+
+                if !filter
+                    .map_or(true, |f| f.matches(task, name, &entry.payload))
+                {
+                    continue;
+                }
+
+                if prefix {
+                    print!("{:30} ", name);
+                }
+
+                println!("{}", entry.display(hubris, slot)?);
+                found = true;
+            }
+        }
+
+        delay = if found {
+            interval
+        } else {
+            std::cmp::min(delay * 2, interval * 16)
+        };
+
+        thread::sleep(delay);
+    }
+}
+
 fn taskname<'a>(
     hubris: &'a HubrisArchive,
     variable: &'a HubrisVariable,
@@ -175,17 +672,25 @@ fn ringbuf(context: &mut ExecutionContext) -> Result<()> {
         };
 
         if let Some(ref name) = subargs.name {
-            if v.0.contains(name) || taskname(hubris, v.1)?.contains(name) {
-                ringbufs.push((v, def));
+            if !v.0.contains(name) && !taskname(hubris, v.1)?.contains(name) {
+                continue;
             }
-        } else {
-            ringbufs.push((v, def));
         }
+
+        if let Some(ref filter) = subargs.filter {
+            if !filter.selects(taskname(hubris, v.1)?, v.0) {
+                continue;
+            }
+        }
+
+        ringbufs.push((v, def));
     }
 
     if ringbufs.is_empty() {
         if let Some(name) = subargs.name {
             bail!("no ring buffer name contains \"{}\" (-l to list)", name);
+        } else if subargs.filter.is_some() {
+            bail!("no ring buffers match filter (-l to list)");
         } else {
             bail!("no ring buffers found");
         }
@@ -205,6 +710,36 @@ fn ringbuf(context: &mut ExecutionContext) -> Result<()> {
         return Ok(());
     }
 
+    if subargs.follow || subargs.merge || subargs.json {
+        let mut sources = vec![];
+
+        for (v, def) in &ringbufs {
+            match def {
+                Some(def) => sources.push((
+                    taskname(hubris, v.1).unwrap_or("???"),
+                    v.0,
+                    v.1,
+                    *def,
+                )),
+                None => {
+                    humility::msg!("could not look up type: {:?}", v.1.goff)
+                }
+            }
+        }
+
+        if !subargs.follow {
+            return ringbuf_aggregate(hubris, core, &sources, &subargs);
+        }
+
+        if core.is_dump() {
+            bail!("can only follow ring buffers on a live system");
+        }
+
+        let interval = Duration::from_millis(subargs.interval);
+        let filter = subargs.filter.as_ref();
+        return ringbuf_follow(hubris, core, &sources, interval, filter);
+    }
+
     for (v, def) in ringbufs {
         // Try not to use `?` here, because it causes one bad ringbuf to make
         // them all unavailable.
@@ -214,7 +749,13 @@ fn ringbuf(context: &mut ExecutionContext) -> Result<()> {
             taskname(hubris, v.1).unwrap_or("???")
         );
         if let Some(def) = def {
-            if let Err(e) = ringbuf_dump(hubris, core, def, v.1) {
+            let task = taskname(hubris, v.1).unwrap_or("???");
+            let source = (task, v.0, v.1, def);
+            let filter = subargs.filter.as_ref();
+
+            if let Err(e) =
+                ringbuf_dump(hubris, core, source, subargs.timeline, filter)
+            {
                 if subargs.verbose {
                     humility::msg!("ringbuf dump failed: {e:?}");
                 } else {
@@ -0,0 +1,437 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Passive decoding of SP-to-RoT protocol (sprot) messages, as found in a
+//! captured SPI trace or in a ring buffer of frames.
+//!
+//! Each message consists of a four byte header -- the protocol (a `u8`),
+//! the message type (a `u8`) and the length of the payload (a
+//! little-endian `u16`) -- followed by the payload and a little-endian
+//! CRC-16/XMODEM over the header and the payload.  Between messages, the
+//! bus carries padding:  zeros (the `Ignore` protocol) from a side that has
+//! nothing to send, or a busy indicator from a RoT that is not ready.
+//!
+
+use anyhow::{bail, Context, Result};
+use humility::core::Core;
+use humility::hubris::HubrisArchive;
+use humility::reflect::{self, Base, Load, Value};
+use humility_doppel::{Ringbuf, StaticCell};
+use std::path::Path;
+
+const SPROT_IGNORE: u8 = 0x00;
+const SPROT_V1: u8 = 0x01;
+const SPROT_BUSY: u8 = 0xb2;
+const SPROT_IDLE: u8 = 0xff;
+
+const SPROT_HEADER_SIZE: usize = 4;
+const SPROT_CRC_SIZE: usize = 2;
+
+/// The largest payload we will believe a header that claims it
+const SPROT_MAX_PAYLOAD: usize = 4096;
+
+/// The number of payload bytes displayed for a payload we can't decode
+const SPROT_PAYLOAD_DISPLAY: usize = 16;
+
+const SPROT_MSGTYPES: &[(u8, &str)] = &[
+    (0, "Invalid"),
+    (1, "ErrorRsp"),
+    (2, "EchoReq"),
+    (3, "EchoRsp"),
+    (4, "StatusReq"),
+    (5, "StatusRsp"),
+    (6, "SprocketsReq"),
+    (7, "SprocketsRsp"),
+    (8, "SinkReq"),
+    (9, "SinkRsp"),
+    (10, "UpdBlockSizeReq"),
+    (11, "UpdBlockSizeRsp"),
+    (12, "UpdPrepImageUpdateReq"),
+    (13, "UpdPrepImageUpdateRsp"),
+    (14, "UpdWriteOneBlockReq"),
+    (15, "UpdWriteOneBlockRsp"),
+    (16, "UpdAbortUpdateReq"),
+    (17, "UpdAbortUpdateRsp"),
+    (18, "UpdFinishImageUpdateReq"),
+    (19, "UpdFinishImageUpdateRsp"),
+    (20, "UpdCurrentVersionReq"),
+    (21, "UpdCurrentVersionRsp"),
+    (22, "PulseCsReq"),
+    (23, "PulseCsRsp"),
+];
+
+const SPROT_ERRORS: &[(u8, &str)] = &[
+    (1, "FlowError"),
+    (2, "InvalidCrc"),
+    (3, "UnsupportedProtocol"),
+    (4, "BadMessageType"),
+    (5, "BadMessageLength"),
+    (6, "CannotAssertCSn"),
+    (7, "RotNotReady"),
+    (8, "RspTimeout"),
+    (9, "RotBusy"),
+    (10, "RxOverrun"),
+    (11, "TxUnderrun"),
+    (12, "Deserialization"),
+    (13, "Serialization"),
+    (14, "UpdateError"),
+];
+
+fn lookup(table: &[(u8, &'static str)], val: u8) -> Option<&'static str> {
+    table.iter().find(|(v, _)| *v == val).map(|(_, name)| *name)
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = crc_any::CRCu16::crc16xmodem();
+    crc.digest(data);
+    crc.get_crc()
+}
+
+///
+/// Something found in a stream of bytes:  a message, an indication that the
+/// RoT was busy, or bytes that could not be decoded.
+///
+#[derive(Debug)]
+enum SprotItem {
+    Message { msgtype: u8, payload: Vec<u8>, crc_ok: bool },
+    Busy,
+    Truncated { msgtype: u8, len: usize, have: usize },
+    Garbage(Vec<u8>),
+}
+
+fn sprot_items(bytes: &[u8]) -> Vec<SprotItem> {
+    let mut items = vec![];
+    let mut garbage = vec![];
+    let mut offs = 0;
+
+    while offs < bytes.len() {
+        let rest = &bytes[offs..];
+
+        match rest[0] {
+            SPROT_IGNORE | SPROT_IDLE => {
+                offs += 1;
+                continue;
+            }
+            SPROT_BUSY => {
+                items.push(SprotItem::Busy);
+
+                //
+                // A busy RoT fills its response with the busy indicator; we
+                // only want to report it once.
+                //
+                offs += rest.iter().take_while(|&&b| b == SPROT_BUSY).count();
+                continue;
+            }
+            SPROT_V1 if rest.len() >= SPROT_HEADER_SIZE => {
+                let msgtype = rest[1];
+                let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+
+                if len <= SPROT_MAX_PAYLOAD {
+                    if !garbage.is_empty() {
+                        items.push(SprotItem::Garbage(std::mem::take(
+                            &mut garbage,
+                        )));
+                    }
+
+                    let total = SPROT_HEADER_SIZE + len + SPROT_CRC_SIZE;
+
+                    if rest.len() < total {
+                        items.push(SprotItem::Truncated {
+                            msgtype,
+                            len,
+                            have: rest.len() - SPROT_HEADER_SIZE,
+                        });
+                        break;
+                    }
+
+                    let body = &rest[..SPROT_HEADER_SIZE + len];
+                    let crc =
+                        u16::from_le_bytes([rest[total - 2], rest[total - 1]]);
+
+                    items.push(SprotItem::Message {
+                        msgtype,
+                        payload: body[SPROT_HEADER_SIZE..].to_vec(),
+                        crc_ok: crc16(body) == crc,
+                    });
+
+                    offs += total;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+
+        garbage.push(rest[0]);
+        offs += 1;
+    }
+
+    if !garbage.is_empty() {
+        items.push(SprotItem::Garbage(garbage));
+    }
+
+    items
+}
+
+fn sprot_bytes(bytes: &[u8]) -> String {
+    let shown = bytes
+        .iter()
+        .take(SPROT_PAYLOAD_DISPLAY)
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if bytes.len() > SPROT_PAYLOAD_DISPLAY {
+        format!("{shown} ...")
+    } else {
+        shown
+    }
+}
+
+fn sprot_u32(payload: &[u8], offs: usize) -> Option<u32> {
+    let bytes = payload.get(offs..offs + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+///
+/// Decodes the payload of a message, to the degree that we can.
+///
+fn sprot_payload(msgtype: u8, payload: &[u8]) -> String {
+    let decoded = match lookup(SPROT_MSGTYPES, msgtype) {
+        Some("ErrorRsp") => {
+            payload.first().map(|&e| match lookup(SPROT_ERRORS, e) {
+                Some(name) => format!("error {name}"),
+                None => format!("error <unknown 0x{e:x}>"),
+            })
+        }
+        Some("SinkReq") | Some("SinkRsp") => payload
+            .get(0..2)
+            .map(|s| format!("seq {}", u16::from_le_bytes([s[0], s[1]]))),
+        Some("UpdBlockSizeRsp") => {
+            sprot_u32(payload, 0).map(|s| format!("block size {s}"))
+        }
+        Some("UpdWriteOneBlockReq") => sprot_u32(payload, 0)
+            .map(|b| format!("block {b}, {} bytes", payload.len() - 4)),
+        Some("EchoReq") | Some("EchoRsp") => Some(sprot_bytes(payload)),
+        _ => None,
+    };
+
+    match decoded {
+        Some(decoded) => decoded,
+        None if payload.is_empty() => String::new(),
+        None => sprot_bytes(payload),
+    }
+}
+
+///
+/// Prints the items found in a stream of bytes, labelled by the frame or
+/// transaction in which they were found and their direction.
+///
+fn sprot_print(label: &str, dir: &str, bytes: &[u8]) {
+    for item in sprot_items(bytes) {
+        let (what, len, crc, payload) = match item {
+            SprotItem::Message { msgtype, payload, crc_ok } => (
+                lookup(SPROT_MSGTYPES, msgtype)
+                    .map_or(format!("<type 0x{msgtype:x}>"), str::to_string),
+                payload.len().to_string(),
+                if crc_ok { "ok" } else { "BAD" },
+                sprot_payload(msgtype, &payload),
+            ),
+            SprotItem::Busy => {
+                ("<busy>".to_string(), "-".to_string(), "-", String::new())
+            }
+            SprotItem::Truncated { msgtype, len, have } => (
+                lookup(SPROT_MSGTYPES, msgtype)
+                    .map_or(format!("<type 0x{msgtype:x}>"), str::to_string),
+                len.to_string(),
+                "-",
+                format!("truncated: {have} of {len} bytes present"),
+            ),
+            SprotItem::Garbage(bytes) => (
+                "<garbage>".to_string(),
+                bytes.len().to_string(),
+                "-",
+                sprot_bytes(&bytes),
+            ),
+        };
+
+        println!(
+            "{:>6} {:<8} {:<24} {:>5} {:<4} {}",
+            label, dir, what, len, crc, payload
+        );
+    }
+}
+
+fn sprot_header() {
+    println!(
+        "{:>6} {:<8} {:<24} {:>5} {:<4} {}",
+        "FRAME", "DIR", "TYPE", "LEN", "CRC", "PAYLOAD"
+    );
+}
+
+///
+/// Decodes a captured SPI trace, as exported (e.g., by a logic analyzer) in
+/// CSV form.  The trace must have `MOSI` and `MISO` columns; if it has a
+/// `Packet ID` column, rows that share a packet ID are a single transaction
+/// (that is, were sent with chip select asserted), and each transaction is
+/// decoded separately.
+///
+pub fn decode_trace(path: &Path) -> Result<()> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    let mut lines = contents.lines();
+
+    let header = match lines.next() {
+        Some(header) => header
+            .split(',')
+            .map(|c| c.trim().trim_matches('"').to_lowercase())
+            .collect::<Vec<_>>(),
+        None => bail!("{} is empty", path.display()),
+    };
+
+    let column = |name: &str| header.iter().position(|c| c == name);
+
+    let (mosi, miso) = match (column("mosi"), column("miso")) {
+        (Some(mosi), Some(miso)) => (mosi, miso),
+        _ => bail!("trace must have MOSI and MISO columns"),
+    };
+
+    let packet = column("packet id");
+
+    let byte = |row: &[&str], col: usize, lineno: usize| -> Result<u8> {
+        let val = row.get(col).map(|v| v.trim().trim_matches('"'));
+
+        match val {
+            Some(v) => parse_int::parse::<u8>(v)
+                .with_context(|| format!("bad byte \"{v}\" on line {lineno}")),
+            None => bail!("missing column on line {lineno}"),
+        }
+    };
+
+    let mut transactions: Vec<(String, Vec<u8>, Vec<u8>)> = vec![];
+
+    for (lineno, line) in lines.enumerate().map(|(i, l)| (i + 2, l)) {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let row = line.split(',').collect::<Vec<_>>();
+        let id = packet
+            .and_then(|p| row.get(p))
+            .map_or(String::new(), |id| id.trim().to_string());
+
+        let (tx, rx) = (byte(&row, mosi, lineno)?, byte(&row, miso, lineno)?);
+
+        match transactions.last_mut() {
+            Some((last, mosi, miso)) if *last == id => {
+                mosi.push(tx);
+                miso.push(rx);
+            }
+            _ => transactions.push((id, vec![tx], vec![rx])),
+        }
+    }
+
+    sprot_header();
+
+    for (ndx, (_, mosi, miso)) in transactions.iter().enumerate() {
+        let label = ndx.to_string();
+        sprot_print(&label, "SP->RoT", mosi);
+        sprot_print(&label, "RoT->SP", miso);
+    }
+
+    Ok(())
+}
+
+///
+/// Finds the bytes in the payload of a ring buffer entry, which we take to
+/// be the first array of bytes found within it.
+///
+fn ringbuf_bytes(val: &Value) -> Option<Vec<u8>> {
+    match val {
+        Value::Array(a) if !a.is_empty() => {
+            let bytes = a
+                .iter()
+                .map(|v| v.as_base().ok().and_then(Base::as_u8))
+                .collect::<Option<Vec<u8>>>();
+
+            bytes.or_else(|| a.iter().find_map(ringbuf_bytes))
+        }
+        Value::Enum(e) => e.contents().and_then(ringbuf_bytes),
+        Value::Struct(s) => s.iter().find_map(|(_, v)| ringbuf_bytes(v)),
+        Value::Tuple(t) => t.iter().find_map(ringbuf_bytes),
+        _ => None,
+    }
+}
+
+///
+/// Decodes frames that have been recorded in a ring buffer.  Each entry that
+/// contains an array of bytes is decoded as a frame; its direction is taken
+/// to be the name of the entry's variant.
+///
+pub fn decode_ringbuf(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    name: &str,
+) -> Result<()> {
+    let found = hubris
+        .qualified_variables()
+        .filter(|(n, _)| n.contains(name))
+        .filter_map(|(n, v)| Some((n, v, hubris.lookup_struct(v.goff).ok()?)))
+        .filter(|(_, _, def)| def.name.contains("Ringbuf"))
+        .collect::<Vec<_>>();
+
+    let (vname, var, def) = match found.len() {
+        1 => found[0],
+        0 => bail!("no ring buffer name contains \"{name}\""),
+        _ => bail!(
+            "\"{name}\" matches multiple ring buffers: {}",
+            found.iter().map(|f| f.0).collect::<Vec<_>>().join(", ")
+        ),
+    };
+
+    let mut buf = vec![0u8; var.size];
+
+    core.halt()?;
+    let rval = core.read_8(var.addr, &mut buf);
+    core.run()?;
+    rval?;
+
+    let val = Value::Struct(reflect::load_struct(hubris, &buf, def, 0)?);
+
+    let ringbuf = Ringbuf::from_value(&val).or_else(|_| {
+        let cell = StaticCell::from_value(&val)?;
+        Ringbuf::from_value(&cell.cell.value)
+    })?;
+
+    humility::msg!("decoding frames in ring buffer {vname}");
+
+    let slots = match ringbuf.last {
+        Some(last) => {
+            let len = ringbuf.buffer.len();
+            (0..len)
+                .map(|i| (last as usize + i + 1) % len)
+                .filter(|&slot| ringbuf.buffer[slot].generation != 0)
+                .collect::<Vec<_>>()
+        }
+        None => vec![],
+    };
+
+    sprot_header();
+
+    for slot in slots {
+        let entry = &ringbuf.buffer[slot];
+
+        if let Some(bytes) = ringbuf_bytes(&entry.payload) {
+            let dir = match &entry.payload {
+                Value::Enum(e) => e.disc().to_string(),
+                _ => "-".to_string(),
+            };
+
+            sprot_print(&slot.to_string(), &dir, &bytes);
+        }
+    }
+
+    Ok(())
+}
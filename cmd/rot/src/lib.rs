@@ -0,0 +1,460 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility rot`
+//!
+//! `humility rot` queries the Root of Trust (RoT) by way of the SP.  The SP
+//! speaks to the RoT over SPI using the SP-to-RoT protocol (sprot), which is
+//! exposed on the SP as the `SpRot` Idol interface; `humility rot` makes its
+//! requests of that interface via Hiffy, and decodes the responses.
+//!
+//! `humility rot info` displays the status of sprot, the RoT's boot
+//! selection (the active image slot, along with any persistent or transient
+//! preference for a slot), the digest of the image in each slot, and the
+//! versioning information in the caboose of each slot's image:
+//!
+//! ```console
+//! $ humility rot info
+//! humility: attached via ST-Link V3
+//! humility: sprot status:
+//!                  rot => RotStatus { version: 0x3, min_version: 0x2, ... }
+//!           rx_crc_err => 0x0
+//!           rx_timeout => 0x0
+//! humility: boot selection:
+//!                              active => A
+//!          persistent_boot_preference => A
+//!  pending_persistent_boot_preference => -
+//!           transient_boot_preference => -
+//!              slot_a_sha3_256_digest => 5b3a1c...e40d
+//!              slot_b_sha3_256_digest => 93cd07...1f6a
+//! humility: caboose of slot A:
+//! GITC => 2b2b9f4a2b5dd7c71f0b4c3a3a84b3c5f3ab0cfd
+//! BORD => oxide-rot-1
+//! NAME => oxide-rot-1
+//! VERS => 1.0.2
+//! humility: caboose of slot B:
+//! GITC => 9f1c1a2e4ba6e8c8e02d5b8e0b7e1f43c6c8a0e2
+//! BORD => oxide-rot-1
+//! NAME => oxide-rot-1
+//! VERS => 1.0.1
+//! ```
+//!
+//! Members of the RoT's responses that are options are displayed as `-` when
+//! absent; byte arrays (e.g., digests) are displayed in hex.
+//!
+//! `humility rot attest` displays the RoT's attestation state:  its
+//! certificate chain (from the alias certificate to the root) and the log
+//! of measurements that the RoT has recorded:
+//!
+//! ```console
+//! $ humility rot attest
+//! humility: attached via ST-Link V3
+//! humility: certificate chain (4 certificates):
+//!    0 => CN=alias (523 bytes), issued by CN=deviceid
+//!    1 => CN=deviceid (563 bytes), issued by CN=BRM42220004
+//!    2 => CN=BRM42220004 (612 bytes), issued by CN=intermediate
+//!    3 => CN=intermediate (598 bytes), issued by CN=root
+//! humility: measurement log (2 measurements):
+//!    0 => sha3-256 0f3b7c...91ae
+//!    1 => sha3-256 8d64a2...c03b
+//! ```
+//!
+//! To extract the certificates (e.g., to verify the chain with other tools),
+//! use `--certs` to specify a directory into which each is written in DER
+//! form, as `cert-<index>.der`.
+//!
+//! `humility rot decode` is a protocol analyzer:  rather than making
+//! requests of the RoT, it decodes sprot messages that have already been
+//! exchanged, displaying the type of each message, its length, the validity
+//! of its CRC and (to the degree that it can be decoded) its payload.  The
+//! messages can be taken from a captured SPI trace (`--trace`), exported
+//! from a logic analyzer as CSV with `MOSI` and `MISO` columns; if the trace
+//! has a `Packet ID` column, each packet is decoded as a transaction:
+//!
+//! ```console
+//! $ humility rot decode --trace sprot.csv
+//!  FRAME DIR      TYPE                       LEN CRC  PAYLOAD
+//!      0 SP->RoT  StatusReq                    0 ok
+//!      1 RoT->SP  StatusRsp                   36 ok   01 00 00 00 9c 6f ...
+//!      2 SP->RoT  SinkReq                    512 ok   seq 0
+//!      3 RoT->SP  <busy>                       - -
+//!      4 SP->RoT  SinkReq                    512 BAD  seq 1
+//!      5 RoT->SP  ErrorRsp                     1 ok   error InvalidCrc
+//! ```
+//!
+//! Alternatively, messages can be taken from a ring buffer (`--ringbuf`)
+//! that records frames, in which case each entry that contains an array of
+//! bytes is decoded, and the direction of each is the name of the entry's
+//! variant.
+//!
+//! Note that a signed attestation (that is, a signature over the log and a
+//! caller-provided nonce) requires leases in both directions, which Hiffy
+//! does not support; it therefore cannot be obtained with `humility rot`.
+//!
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::HubrisArchive;
+use humility::msg;
+use humility::reflect::{Base, Value};
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_hiffy::*;
+use humility_idol::{HubrisIdol, IdolArgument};
+use std::path::PathBuf;
+
+mod decode;
+
+#[derive(Parser, Debug)]
+enum RotCommand {
+    /// Display sprot status, boot selection and image versions
+    Info,
+    /// Display the certificate chain and measurement log
+    Attest {
+        /// write the certificate chain (in DER form) into this directory
+        #[clap(long, value_name = "dir")]
+        certs: Option<PathBuf>,
+    },
+    /// Decode sprot messages in a captured SPI trace or a ring buffer
+    Decode {
+        /// captured SPI trace, as CSV with MOSI and MISO columns
+        #[clap(
+            long,
+            value_name = "file",
+            conflicts_with = "ringbuf",
+            required_unless_present = "ringbuf"
+        )]
+        trace: Option<PathBuf>,
+
+        /// ring buffer of sprot frames
+        #[clap(long, value_name = "name")]
+        ringbuf: Option<String>,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[clap(name = "rot", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct RotArgs {
+    /// sets timeout
+    #[clap(
+        long, short = 'T', default_value_t = 5000, value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+
+    #[clap(subcommand)]
+    cmd: RotCommand,
+}
+
+/// The size of each read of a buffer (e.g., a certificate) from the RoT
+const ROT_READ_CHUNK: usize = 256;
+
+/// The image slots of the RoT
+const ROT_SLOTS: [&str; 2] = ["A", "B"];
+
+/// The hubpack encoding of a SHA3-256 measurement:  a variant tag of 0,
+/// followed by the digest
+const ROT_MEASUREMENT_SHA3_256: u8 = 0;
+const ROT_MEASUREMENT_SIZE: usize = 1 + 32;
+
+struct RotHandler<'a> {
+    hubris: &'a HubrisArchive,
+    core: &'a mut dyn Core,
+    context: HiffyContext<'a>,
+}
+
+impl<'a> RotHandler<'a> {
+    fn call(
+        &mut self,
+        name: &str,
+        args: &[(&str, IdolArgument)],
+        lease: Option<HiffyLease>,
+    ) -> Result<Value> {
+        let op = self
+            .hubris
+            .get_idol_command(&format!("SpRot.{name}"))
+            .with_context(|| format!("RoT does not support {name}"))?;
+
+        match hiffy_call(
+            self.hubris,
+            self.core,
+            &mut self.context,
+            &op,
+            args,
+            lease,
+        )? {
+            Ok(val) => Ok(val),
+            Err(e) => bail!("SpRot.{name} failed: {e}"),
+        }
+    }
+
+    fn call_u32(
+        &mut self,
+        name: &str,
+        args: &[(&str, IdolArgument)],
+    ) -> Result<u32> {
+        let val = self.call(name, args, None)?;
+
+        val.as_base()?
+            .as_u32()
+            .ok_or_else(|| anyhow!("SpRot.{name} returned {val:?}"))
+    }
+
+    ///
+    /// Reads a buffer of the specified length from the RoT in chunks, given
+    /// an operation that takes the offset of the chunk (along with any
+    /// additional arguments).
+    ///
+    fn read(
+        &mut self,
+        name: &str,
+        args: &[(&str, IdolArgument)],
+        len: usize,
+    ) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; len];
+
+        for (i, chunk) in buf.chunks_mut(ROT_READ_CHUNK).enumerate() {
+            let offset =
+                ("offset", IdolArgument::Scalar((i * ROT_READ_CHUNK) as u64));
+
+            let mut all = args.to_vec();
+            all.push(offset);
+            self.call(name, &all, Some(HiffyLease::Read(chunk)))?;
+        }
+
+        Ok(buf)
+    }
+
+    fn format(&self, val: &Value) -> String {
+        rot_format(self.hubris, val)
+    }
+}
+
+///
+/// Formats a value from a response, displaying options as their contents
+/// (or `-` if absent), unit variants by their name and byte arrays in hex.
+/// Anything else is displayed as Hiffy would display it.
+///
+fn rot_format(hubris: &HubrisArchive, val: &Value) -> String {
+    match val {
+        Value::Enum(e) => match e.as_option() {
+            Ok(Some(val)) => rot_format(hubris, val),
+            Ok(None) => "-".to_string(),
+            Err(_) if e.contents().is_none() => e.disc().to_string(),
+            Err(_) => hiffy_format_result(hubris, Ok(val.clone())),
+        },
+        Value::Array(a) => {
+            let bytes = a
+                .iter()
+                .map(|v| v.as_base().ok().and_then(Base::as_u8))
+                .collect::<Option<Vec<u8>>>();
+
+            match bytes {
+                Some(bytes) if !bytes.is_empty() => rot_hex(&bytes),
+                _ => hiffy_format_result(hubris, Ok(val.clone())),
+            }
+        }
+        _ => hiffy_format_result(hubris, Ok(val.clone())),
+    }
+}
+
+fn rot_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+///
+/// Prints the members of a response, right-aligning their names.
+///
+fn rot_print(handler: &RotHandler, what: &str, val: &Value) -> Result<()> {
+    msg!("{what}:");
+
+    let s = val.as_struct()?;
+    let width = s.iter().map(|(name, _)| name.len()).max().unwrap_or(0) + 1;
+
+    for (name, val) in s.iter() {
+        println!("{:>width$} => {}", name, handler.format(val));
+    }
+
+    Ok(())
+}
+
+fn rot_caboose(handler: &mut RotHandler, slot: &str) -> Result<()> {
+    let args = [
+        ("component", IdolArgument::String("Hubris")),
+        ("slot", IdolArgument::String(slot)),
+    ];
+
+    let size = handler.call_u32("caboose_size", &args)?;
+    let data = handler.read("read_caboose_region", &args, size as usize)?;
+
+    msg!("caboose of slot {slot}:");
+
+    let mut reader = tlvc::TlvcReader::begin(&data[..])
+        .map_err(|e| anyhow!("failed to read caboose: {e:?}"))?;
+
+    loop {
+        match reader.next() {
+            Ok(Some(chunk)) => {
+                let tag = String::from_utf8_lossy(&chunk.header().tag);
+                let mut body = vec![0u8; chunk.len() as usize];
+
+                chunk
+                    .read_exact(0, &mut body)
+                    .map_err(|e| anyhow!("failed to read record: {e:?}"))?;
+
+                match std::str::from_utf8(&body) {
+                    Ok(s) if s.chars().all(|c| !c.is_control()) => {
+                        println!("{tag} => {s}");
+                    }
+                    _ => println!("{tag} => {body:x?}"),
+                }
+            }
+            Ok(None) => break,
+            Err(e) => bail!("malformed caboose: {e:?}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn rot_info(handler: &mut RotHandler) -> Result<()> {
+    let status = handler.call("status", &[], None)?;
+    rot_print(handler, "sprot status", &status)?;
+
+    let info = handler.call("rot_boot_info", &[], None)?;
+    rot_print(handler, "boot selection", &info)?;
+
+    for slot in ROT_SLOTS {
+        //
+        // An empty (or corrupt) slot won't have a caboose; we don't want to
+        // fail to display the other slot because of it.
+        //
+        if let Err(e) = rot_caboose(handler, slot) {
+            msg!("caboose of slot {slot}: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Decodes the measurement log, which is hubpack-encoded as a count of
+/// measurements followed by an array of them.
+///
+fn rot_measurements(log: &[u8]) -> Result<Vec<String>> {
+    if log.len() < 4 {
+        bail!("measurement log is too short ({} bytes)", log.len());
+    }
+
+    let count = u32::from_le_bytes(log[0..4].try_into().unwrap()) as usize;
+    let entries = &log[4..];
+
+    if count * ROT_MEASUREMENT_SIZE > entries.len() {
+        bail!(
+            "measurement log has {count} measurements, but only \
+            {} bytes of measurements",
+            entries.len()
+        );
+    }
+
+    entries
+        .chunks_exact(ROT_MEASUREMENT_SIZE)
+        .take(count)
+        .map(|m| match m[0] {
+            ROT_MEASUREMENT_SHA3_256 => {
+                Ok(format!("sha3-256 {}", rot_hex(&m[1..])))
+            }
+            tag => bail!("unknown measurement type {tag}"),
+        })
+        .collect()
+}
+
+fn rot_attest(handler: &mut RotHandler, certs: Option<&PathBuf>) -> Result<()> {
+    use x509_parser::prelude::*;
+
+    let ncerts = handler.call_u32("cert_chain_len", &[])?;
+    msg!("certificate chain ({ncerts} certificates):");
+
+    for index in 0..ncerts {
+        let arg = [("index", IdolArgument::Scalar(index as u64))];
+        let len = handler.call_u32("cert_len", &arg)?;
+        let der = handler.read("cert", &arg, len as usize)?;
+
+        match X509Certificate::from_der(&der) {
+            Ok((_, cert)) => println!(
+                "{index:>4} => {} ({len} bytes), issued by {}",
+                cert.subject(),
+                cert.issuer()
+            ),
+            Err(e) => println!("{index:>4} => <invalid certificate: {e}>"),
+        }
+
+        if let Some(dir) = certs {
+            let path = dir.join(format!("cert-{index}.der"));
+            std::fs::write(&path, &der).with_context(|| {
+                format!("failed to write {}", path.display())
+            })?;
+        }
+    }
+
+    let len = handler.call_u32("log_len", &[])?;
+    let log = handler.read("log", &[], len as usize)?;
+    let measurements = rot_measurements(&log)?;
+
+    msg!("measurement log ({} measurements):", measurements.len());
+
+    for (index, m) in measurements.iter().enumerate() {
+        println!("{index:>4} => {m}");
+    }
+
+    Ok(())
+}
+
+fn rot(context: &mut ExecutionContext) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = RotArgs::try_parse_from(subargs)?;
+
+    let (attach, validate) = match subargs.cmd {
+        RotCommand::Decode { trace: Some(ref trace), .. } => {
+            return decode::decode_trace(trace);
+        }
+        RotCommand::Decode { .. } => (Attach::Any, Validate::Match),
+        _ => (Attach::LiveOnly, Validate::Booted),
+    };
+
+    if !context.archive.as_ref().unwrap().loaded() {
+        bail!("must provide a Hubris archive or dump");
+    }
+
+    humility_cmd::attach(context, attach, validate, |context| {
+        let hubris = context.archive.as_ref().unwrap();
+        let core = &mut **context.core.as_mut().unwrap();
+
+        if let RotCommand::Decode { ringbuf: Some(ref name), .. } = subargs.cmd
+        {
+            return decode::decode_ringbuf(hubris, core, name);
+        }
+
+        let context = HiffyContext::new(hubris, core, subargs.timeout)?;
+        let mut handler = RotHandler { hubris, core, context };
+
+        match subargs.cmd {
+            RotCommand::Info => rot_info(&mut handler),
+            RotCommand::Attest { ref certs } => {
+                rot_attest(&mut handler, certs.as_ref())
+            }
+            RotCommand::Decode { .. } => unreachable!(),
+        }
+    })
+}
+
+pub fn init() -> Command {
+    Command {
+        app: RotArgs::command(),
+        name: "rot",
+        run: rot,
+        kind: CommandKind::Unattached { archive: Archive::Optional },
+    }
+}
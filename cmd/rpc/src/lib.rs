@@ -461,14 +461,30 @@ fn rpc_call(
     args: &[(&str, idol::IdolArgument)],
     ips: Vec<String>,
     timeout: u32,
+    dry_run: bool,
 ) -> Result<()> {
     let timeout = Duration::from_millis(u64::from(timeout));
 
+    //
+    // Our calls don't go through a core, so we must report them ourselves
+    // in a dry run.
+    //
+    if dry_run {
+        for ip in &ips {
+            humility::msg!("would call {}.{} via {ip}", op.name.0, op.name.1);
+        }
+
+        bail!(humility::core::DryRun);
+    }
+
     for ip in &ips {
         let mut client = RpcClient::new(hubris, ip, timeout)?;
         let result = client.call(op, args)?;
-        print!("{:25} ", ip);
-        humility_hiffy::hiffy_print_result(hubris, op, result)?;
+        println!(
+            "{:25} {}",
+            ip,
+            humility_hiffy::hiffy_format_call(hubris, op, result)
+        );
     }
 
     Ok(())
@@ -535,7 +551,14 @@ fn rpc_run(context: &mut ExecutionContext) -> Result<()> {
         };
 
         let op = idol::IdolOperation::new(hubris, func[0], func[1], task)?;
-        rpc_call(hubris, &op, &args, ips, subargs.timeout)?;
+        rpc_call(
+            hubris,
+            &op,
+            &args,
+            ips,
+            subargs.timeout,
+            context.cli.dry_run,
+        )?;
 
         return Ok(());
     }
@@ -0,0 +1,272 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility rtt`
+//!
+//! `humility rtt` streams output from a target via SEGGER's Real-Time
+//! Transfer (RTT) protocol, in which the target writes into ring buffers in
+//! its own RAM that the debugger reads from (and, for input, writes into).
+//! Because RTT requires nothing beyond memory access, it can be used on
+//! targets that do not have SWO wired out.
+//!
+//! The RTT control block is located via its symbol (`_SEGGER_RTT`) in the
+//! archive, or can be specified explicitly with `--address` (`-a`).  To
+//! list the channels -- "up" channels carry data from the target, and
+//! "down" channels carry data to it -- use `--list` (`-l`):
+//!
+//! ```console
+//! $ humility rtt --list
+//! humility: attached via ST-Link V3
+//! humility: RTT control block at 0x24000490
+//! DIR  CH NAME             SIZE MODE
+//! up    0 Terminal         1024 no-block-skip
+//! up    1 log              4096 no-block-skip
+//! down  0 Terminal           16 no-block-skip
+//! ```
+//!
+//! By default, `humility rtt` streams up channel 0 to stdout until killed.
+//! Other channels can be specified with `--channel` (`-c`); if multiple
+//! channels are specified, each line of output is prefixed with the name of
+//! its channel:
+//!
+//! ```console
+//! $ humility rtt -c 0,1
+//! humility: attached via ST-Link V3
+//! humility: RTT control block at 0x24000490
+//! Terminal: sequencer: A2 power good
+//! log: 1023: thermal loop running
+//! ...
+//! ```
+//!
+//! To use RTT as an interactive console, use `--input` (`-i`) to specify a
+//! down channel to which lines read from stdin will be written.  Channels
+//! are polled at the interval specified by `--poll-interval` (`-p`) in
+//! milliseconds, or immediately if data was found.
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use humility::msg;
+use humility::rtt;
+use humility::Diagnostic;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use std::io::{BufRead, Write};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[clap(name = "rtt", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct RttArgs {
+    /// address of the RTT control block
+    #[clap(
+        long, short, value_name = "address",
+        parse(try_from_str = parse_int::parse)
+    )]
+    address: Option<u32>,
+
+    /// list up and down channels
+    #[clap(long, short, conflicts_with_all = &["channel", "input"])]
+    list: bool,
+
+    /// up channel(s) to stream
+    #[clap(
+        long,
+        short,
+        value_name = "channel",
+        use_value_delimiter = true,
+        multiple_occurrences = true
+    )]
+    channel: Vec<usize>,
+
+    /// down channel to which lines from stdin are to be written
+    #[clap(long, short, value_name = "channel")]
+    input: Option<usize>,
+
+    /// interval at which channels are polled, in milliseconds
+    #[clap(long, short, default_value_t = 10, value_name = "ms")]
+    poll_interval: u64,
+}
+
+///
+/// Emits data from an up channel.  If we are streaming a single channel,
+/// its data is emitted as is; otherwise, we emit only complete lines, each
+/// prefixed with the name of its channel.
+///
+fn rtt_emit(
+    prefix: Option<&str>,
+    partial: &mut Vec<u8>,
+    data: &[u8],
+) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+
+    match prefix {
+        None => out.write_all(data)?,
+        Some(prefix) => {
+            partial.extend_from_slice(data);
+
+            while let Some(pos) = partial.iter().position(|&c| c == b'\n') {
+                let line = partial.drain(..=pos).collect::<Vec<_>>();
+                write!(out, "{prefix}: ")?;
+                out.write_all(&line)?;
+            }
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+fn rtt(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = RttArgs::try_parse_from(subargs)?;
+
+    let addr = rtt::address(hubris, subargs.address)?;
+
+    core.op_start()?;
+    let cb = rtt::RttControlBlock::read(core, addr);
+    core.op_done()?;
+    let cb = cb?;
+
+    msg!("RTT control block at 0x{addr:x}");
+
+    if subargs.list {
+        println!("{:4} {:>2} {:15} {:>5} MODE", "DIR", "CH", "NAME", "SIZE");
+
+        for (dir, buffers) in [("up", &cb.up), ("down", &cb.down)] {
+            for (ndx, buffer) in buffers.iter().enumerate() {
+                println!(
+                    "{:4} {:>2} {:15} {:>5} {}",
+                    dir,
+                    ndx,
+                    buffer.name,
+                    buffer.size,
+                    buffer.mode()
+                );
+            }
+        }
+
+        return Ok(());
+    }
+
+    let channels = if subargs.channel.is_empty() {
+        vec![0]
+    } else {
+        subargs.channel.clone()
+    };
+
+    for &channel in &channels {
+        if channel >= cb.up.len() {
+            bail!(Diagnostic::error(format!(
+                "up channel {channel} does not exist"
+            ))
+            .code("no-such-channel")
+            .hint("use --list to list channels"));
+        }
+    }
+
+    let input = match subargs.input {
+        Some(channel) if channel >= cb.down.len() => {
+            bail!(Diagnostic::error(format!(
+                "down channel {channel} does not exist"
+            ))
+            .code("no-such-channel")
+            .hint("use --list to list channels"));
+        }
+        Some(channel) => Some(&cb.down[channel]),
+        None => None,
+    };
+
+    let prefixes = channels
+        .iter()
+        .map(|&channel| match cb.up[channel].name.as_str() {
+            "" => format!("{channel}"),
+            name => name.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut partials = vec![vec![]; channels.len()];
+
+    let done = humility_cmd::interrupted()?;
+
+    //
+    // If we have been asked for input, we read lines from stdin in their
+    // own thread, and write them to the down channel as space allows.
+    //
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    let mut pending = vec![];
+
+    if input.is_some() {
+        thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if tx.send(format!("{line}\n").into_bytes()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+
+    let interval = Duration::from_millis(subargs.poll_interval);
+
+    while !done.load(Ordering::SeqCst) {
+        let mut found = false;
+
+        core.op_start()?;
+
+        for (ndx, &channel) in channels.iter().enumerate() {
+            let data = cb.up[channel].drain(core)?;
+
+            if !data.is_empty() {
+                let prefix = match channels.len() {
+                    1 => None,
+                    _ => Some(prefixes[ndx].as_str()),
+                };
+
+                rtt_emit(prefix, &mut partials[ndx], &data)?;
+                found = true;
+            }
+        }
+
+        if let Some(down) = input {
+            while let Ok(line) = rx.try_recv() {
+                pending.extend(line);
+            }
+
+            if !pending.is_empty() {
+                let written = down.fill(core, &pending)?;
+                pending.drain(..written);
+            }
+        }
+
+        core.op_done()?;
+
+        if !found {
+            thread::sleep(interval);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: RttArgs::command(),
+        name: "rtt",
+        run: rtt,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -88,8 +88,25 @@
 //! can be retrieved by using the `--cpuid` option and specifying a
 //! desired target thread; full MCA information can similarly be retrieved
 //! using the `--mca` option and specifyin a desired thread.
+//!
+//! The host CPU's temperature can be read via its sideband temperature
+//! sensor interface (SB-TSI) by using the `--temp` option, which will
+//! display the temperature of each SB-TSI device in the manifest along
+//! with its high and low alert thresholds and any active alerts:
+//!
+//! ```console
+//! % humility sbrmi --temp
+//! DEVICE                   TEMP     HIGH      LOW ALERT
+//! 3H:0x4c                42.625   70.000    0.000 -
+//! ```
+//!
+//! The SB-RMI registers themselves -- including the inbound and outbound
+//! mailbox message registers and the software interrupt register -- can be
+//! displayed with the `--registers` option.  This can be useful to
+//! understand the state of a mailbox command that has failed or hung
+//! without requiring the cooperation of the host operating system.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use clap::{ArgGroup, CommandFactory, Parser};
 use colored::Colorize;
 use hif::*;
@@ -133,6 +150,14 @@ struct SbrmiArgs {
     /// display MCA registers for target thread
     #[clap(long, short, group = "command", requires = "thread")]
     mca: bool,
+
+    /// display host CPU temperature via SB-TSI
+    #[clap(long, group = "command")]
+    temp: bool,
+
+    /// display SB-RMI registers, including the mailbox registers
+    #[clap(long, short, group = "command")]
+    registers: bool,
 }
 
 fn call_cpuid(
@@ -518,6 +543,256 @@ fn mca(
     Ok(())
 }
 
+///
+/// Returns the I2C devices in the manifest of the specified type.
+///
+fn i2c_devices<'a>(
+    hubris: &'a HubrisArchive,
+    device: &str,
+) -> Result<Vec<&'a HubrisI2cDevice>> {
+    let devices = hubris
+        .manifest
+        .i2c_devices
+        .iter()
+        .filter(|d| d.device == device)
+        .collect::<Vec<_>>();
+
+    if devices.is_empty() {
+        bail!("no {device} devices found in the manifest");
+    }
+
+    Ok(devices)
+}
+
+///
+/// Reads the specified single-byte registers from an I2C device, in order.
+///
+fn i2c_read(
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    device: &HubrisI2cDevice,
+    registers: &[u8],
+) -> Result<Vec<Result<u8, String>>> {
+    let func = context.get_function("I2cRead", 7)?;
+    let mut ops =
+        vec![Op::Push(device.controller), Op::Push(device.port.index)];
+
+    match (device.mux, device.segment) {
+        (Some(mux), Some(segment)) => {
+            ops.push(Op::Push(mux));
+            ops.push(Op::Push(segment));
+        }
+        _ => {
+            ops.push(Op::PushNone);
+            ops.push(Op::PushNone);
+        }
+    }
+
+    ops.push(Op::Push(device.address));
+
+    for &register in registers {
+        ops.push(Op::Push(register));
+        ops.push(Op::Push(1));
+        ops.push(Op::Call(func.id));
+        ops.push(Op::DropN(2));
+    }
+
+    ops.push(Op::DropN(5));
+    ops.push(Op::Done);
+
+    let results = context.run(core, ops.as_slice(), None)?;
+
+    Ok(results
+        .iter()
+        .map(|r| match r {
+            Ok(val) if val.len() == 1 => Ok(val[0]),
+            Ok(val) => Err(format!("short read: {val:x?}")),
+            Err(code) => Err(func.strerror(*code)),
+        })
+        .collect())
+}
+
+//
+// SB-TSI registers, as defined by AMD's SB-TSI specification.  Temperatures
+// are expressed as an integer part and a decimal part, the latter in the
+// upper three bits in units of 0.125 degrees C.
+//
+const SBTSI_TEMP_INT: u8 = 0x01;
+const SBTSI_STATUS: u8 = 0x02;
+const SBTSI_CONFIG: u8 = 0x03;
+const SBTSI_TEMP_HIGH_INT: u8 = 0x07;
+const SBTSI_TEMP_LOW_INT: u8 = 0x08;
+const SBTSI_TEMP_DEC: u8 = 0x10;
+const SBTSI_TEMP_HIGH_DEC: u8 = 0x13;
+const SBTSI_TEMP_LOW_DEC: u8 = 0x14;
+
+/// If set in the config register, the decimal part latches the integer part
+const SBTSI_CONFIG_READ_ORDER: u8 = 1 << 5;
+
+const SBTSI_STATUS_TEMP_HIGH_ALERT: u8 = 1 << 4;
+const SBTSI_STATUS_TEMP_LOW_ALERT: u8 = 1 << 3;
+
+fn sbtsi_temp(int: u8, dec: u8) -> f32 {
+    int as f32 + (dec >> 5) as f32 * 0.125
+}
+
+fn temp(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+) -> Result<()> {
+    let devices = i2c_devices(hubris, "sbtsi")?;
+
+    println!("{:20} {:>8} {:>8} {:>8} ALERT", "DEVICE", "TEMP", "HIGH", "LOW");
+
+    for device in devices {
+        let location = format!(
+            "{}{}:0x{:02x}",
+            device.controller, device.port.name, device.address
+        );
+
+        //
+        // Reading one half of the temperature latches the other half; which
+        // half does so is determined by the config register.
+        //
+        let config =
+            match i2c_read(core, context, device, &[SBTSI_CONFIG])?.remove(0) {
+                Ok(config) => config,
+                Err(err) => {
+                    println!("{:20} {}", location, err);
+                    continue;
+                }
+            };
+
+        let (first, second) = if config & SBTSI_CONFIG_READ_ORDER != 0 {
+            (SBTSI_TEMP_DEC, SBTSI_TEMP_INT)
+        } else {
+            (SBTSI_TEMP_INT, SBTSI_TEMP_DEC)
+        };
+
+        let registers = [
+            first,
+            second,
+            SBTSI_TEMP_HIGH_INT,
+            SBTSI_TEMP_HIGH_DEC,
+            SBTSI_TEMP_LOW_INT,
+            SBTSI_TEMP_LOW_DEC,
+            SBTSI_STATUS,
+        ];
+
+        let results = i2c_read(core, context, device, &registers)?;
+
+        let mut values = HashMap::new();
+
+        for (register, result) in registers.iter().zip(results) {
+            match result {
+                Ok(val) => {
+                    values.insert(*register, val);
+                }
+                Err(err) => {
+                    println!(
+                        "{:20} register 0x{:02x}: {}",
+                        location, register, err
+                    );
+                }
+            }
+        }
+
+        let degrees = |int, dec| match (values.get(&int), values.get(&dec)) {
+            (Some(&int), Some(&dec)) => format!("{:.3}", sbtsi_temp(int, dec)),
+            _ => "-".to_string(),
+        };
+
+        let alert = match values.get(&SBTSI_STATUS) {
+            Some(&status) => {
+                let mut alerts = vec![];
+
+                if status & SBTSI_STATUS_TEMP_HIGH_ALERT != 0 {
+                    alerts.push("high");
+                }
+
+                if status & SBTSI_STATUS_TEMP_LOW_ALERT != 0 {
+                    alerts.push("low");
+                }
+
+                if alerts.is_empty() {
+                    "-".to_string()
+                } else {
+                    alerts.join(",")
+                }
+            }
+            None => "?".to_string(),
+        };
+
+        println!(
+            "{:20} {:>8} {:>8} {:>8} {}",
+            location,
+            degrees(SBTSI_TEMP_INT, SBTSI_TEMP_DEC),
+            degrees(SBTSI_TEMP_HIGH_INT, SBTSI_TEMP_HIGH_DEC),
+            degrees(SBTSI_TEMP_LOW_INT, SBTSI_TEMP_LOW_DEC),
+            alert,
+        );
+    }
+
+    Ok(())
+}
+
+///
+/// Returns the SB-RMI registers that we display, as defined by AMD's APML
+/// specification:  the control and status registers, followed by the
+/// outbound and inbound mailbox message registers and the software
+/// interrupt register used to signal the mailbox.
+///
+fn sbrmi_registers() -> Vec<(u8, String)> {
+    let mut registers = vec![
+        (0x00, "Revision".to_string()),
+        (0x01, "Control".to_string()),
+        (0x02, "Status".to_string()),
+        (0x03, "ReadSize".to_string()),
+    ];
+
+    for i in 0..8 {
+        registers.push((0x30 + i, format!("OutBndMsg_inst{i}")));
+    }
+
+    for i in 0..8 {
+        registers.push((0x38 + i, format!("InBndMsg_inst{i}")));
+    }
+
+    registers.push((0x40, "SoftwareInterrupt".to_string()));
+    registers
+}
+
+fn registers(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+) -> Result<()> {
+    let devices = i2c_devices(hubris, "sbrmi")?;
+    let registers = sbrmi_registers();
+    let addrs = registers.iter().map(|(addr, _)| *addr).collect::<Vec<_>>();
+
+    for device in devices {
+        println!(
+            "SB-RMI at I2C{}, port {}, dev 0x{:02x}:",
+            device.controller, device.port.name, device.address
+        );
+
+        let results = i2c_read(core, context, device, &addrs)?;
+
+        for ((addr, name), result) in registers.iter().zip(results) {
+            match result {
+                Ok(val) => println!("    0x{addr:02x} {name:20} 0x{val:02x}"),
+                Err(err) => println!("    0x{addr:02x} {name:20} {err}"),
+            }
+        }
+
+        println!();
+    }
+
+    Ok(())
+}
+
 fn sbrmi(context: &mut ExecutionContext) -> Result<()> {
     let core = &mut **context.core.as_mut().unwrap();
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
@@ -529,6 +804,14 @@ fn sbrmi(context: &mut ExecutionContext) -> Result<()> {
         return cpuid(hubris, core, &mut context, subargs.thread);
     }
 
+    if subargs.temp {
+        return temp(hubris, core, &mut context);
+    }
+
+    if subargs.registers {
+        return registers(hubris, core, &mut context);
+    }
+
     let mut ops = vec![];
 
     let nthreads = hubris.get_idol_command("Sbrmi.nthreads")?;
@@ -0,0 +1,496 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility script`
+//!
+//! `humility script` runs a script written in
+//! [Rhai](https://rhai.rs/book/) against the attached target, allowing
+//! multi-step procedures (e.g., board bring-up or test) to be automated
+//! without writing a new subcommand.  In addition to the Rhai language and
+//! its standard library, scripts have access to the following functions:
+//!
+//! - `read_u8(addr)`, `read_u16(addr)`, `read_u32(addr)`: read an integer
+//!   from target memory
+//! - `read(addr, nbytes)`: read a blob of bytes from target memory
+//! - `symbol(name)`: return the address of the named variable or function
+//! - `readvar(name)`: read and return the value of the named variable
+//! - `call(operation, args)`: make the Idol call `operation` (e.g.,
+//!   `"Sensor.get"`) via Hiffy with the arguments in the object map `args`,
+//!   returning the result (or throwing the error)
+//! - `i2c_read(device, register, nbytes)`: read `nbytes` from `register` on
+//!   the I2C device named `device` in the archive, returning a blob;
+//!   `register` may be `()` to read without a register
+//! - `i2c_read(bus, address, register, nbytes)`: like `i2c_read`, but to the
+//!   device at `address` on the named bus
+//! - `i2c_write(device, register, bytes)`, `i2c_write(bus, address,
+//!   register, bytes)`: write the blob `bytes` to `register`
+//! - `sleep(ms)`: sleep for the specified number of milliseconds
+//!
+//! Values returned from target memory or from Idol calls are converted into
+//! their Rhai equivalents: structures become object maps, arrays and tuples
+//! become arrays (though a tuple struct with a single member becomes its
+//! member), and enumerations become their variant name (or, if the variant
+//! has contents, an object map from the variant name to its contents).  Any
+//! arguments following the script are available to it in the `ARGS`
+//! array.  For example, given the following script in `temps.rhai`:
+//!
+//! ```text
+//! let limit = if ARGS.is_empty() { 50.0 } else { parse_float(ARGS[0]) };
+//!
+//! for id in 0..3 {
+//!     let t = call("Sensor.get", #{ id: id });
+//!     print(`sensor ${id}: ${t}`);
+//!
+//!     if t > limit {
+//!         throw `sensor ${id} too hot`;
+//!     }
+//! }
+//! ```
+//!
+//! The script can be run with:
+//!
+//! ```console
+//! $ humility script temps.rhai 45
+//! humility: attached via ST-Link V3
+//! sensor 0: 28.5625
+//! sensor 1: 29.1875
+//! sensor 2: 47.875
+//! humility script failed: Runtime error: sensor 2 too hot (line 8, position 9)
+//! ```
+//!
+//! A script that throws an exception (or otherwise fails) causes
+//! `humility script` to exit with a non-zero status.  Scripts cannot write
+//! to target memory directly.
+//!
+
+use anyhow::{anyhow, bail, Result};
+use clap::{CommandFactory, Parser};
+use hif::*;
+use humility::core::Core;
+use humility::hubris::*;
+use humility::reflect::{self, Base, Value};
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_hiffy::*;
+use humility_i2c::I2cArgs;
+use humility_idol::{HubrisIdol, IdolArgument};
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, Scope};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[clap(name = "script", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct ScriptArgs {
+    /// sets Hiffy timeout
+    #[clap(
+        long, short = 'T', default_value_t = 5000, value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+
+    /// script to run
+    script: String,
+
+    /// arguments to the script
+    #[clap(multiple_values = true, allow_hyphen_values = true)]
+    args: Vec<String>,
+}
+
+type ScriptResult<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+fn script_error(err: anyhow::Error) -> Box<EvalAltResult> {
+    format!("{err:#}").into()
+}
+
+///
+/// Converts a value read from the target into its Rhai equivalent.
+///
+fn script_value(value: &Value) -> Dynamic {
+    match value {
+        Value::Base(base) => match *base {
+            Base::I8(v) => Dynamic::from_int(v.into()),
+            Base::I16(v) => Dynamic::from_int(v.into()),
+            Base::I32(v) => Dynamic::from_int(v.into()),
+            Base::I64(v) => Dynamic::from_int(v),
+            Base::I128(v) => Dynamic::from_int(v as i64),
+            Base::U0 => Dynamic::UNIT,
+            Base::U8(v) => Dynamic::from_int(v.into()),
+            Base::U16(v) => Dynamic::from_int(v.into()),
+            Base::U32(v) => Dynamic::from_int(v.into()),
+            Base::U64(v) => Dynamic::from_int(v as i64),
+            Base::U128(v) => Dynamic::from_int(v as i64),
+            Base::Bool(v) => Dynamic::from_bool(v),
+            Base::F32(v) => Dynamic::from_float(v.into()),
+            Base::F64(v) => Dynamic::from_float(v),
+        },
+        Value::Enum(e) => match e.contents() {
+            Some(contents) => {
+                let mut map = rhai::Map::new();
+                map.insert(e.disc().into(), script_value(contents));
+                map.into()
+            }
+            None => e.disc().into(),
+        },
+        Value::Struct(s) => s
+            .iter()
+            .map(|(name, v)| (name.into(), script_value(v)))
+            .collect::<rhai::Map>()
+            .into(),
+        Value::Tuple(t) if t.len() == 1 => script_value(&t[0]),
+        Value::Tuple(t) => t.iter().map(script_value).collect::<Array>().into(),
+        Value::Array(a) => a.iter().map(script_value).collect::<Array>().into(),
+        Value::Ptr(p) => Dynamic::from_int(p.addr().into()),
+        Value::Ref(r) => script_value(r.target()),
+    }
+}
+
+fn script_u8(val: i64, what: &str) -> Result<u8> {
+    u8::try_from(val).map_err(|_| anyhow!("invalid {what} {val}"))
+}
+
+fn script_register(register: &Dynamic) -> Result<Option<u8>> {
+    if register.is_unit() {
+        return Ok(None);
+    }
+
+    match register.as_int() {
+        Ok(val) => Ok(Some(script_u8(val, "register")?)),
+        Err(ty) => bail!("register must be an integer or (), not {ty}"),
+    }
+}
+
+///
+/// The state that is shared by all of the functions that we make available
+/// to a script.  We take ownership of the core and the archive for the
+/// duration of the script, and give them back when it has completed.
+///
+struct ScriptState {
+    core: Box<dyn Core>,
+    hubris: HubrisArchive,
+    timeout: u32,
+}
+
+impl ScriptState {
+    fn read(&mut self, addr: i64, nbytes: i64) -> Result<Blob> {
+        let addr = u32::try_from(addr)
+            .map_err(|_| anyhow!("invalid address {addr:#x}"))?;
+        let mut buf = vec![0u8; usize::try_from(nbytes)?];
+
+        self.core.op_start()?;
+        let rval = self.core.read_8(addr, &mut buf);
+        self.core.op_done()?;
+        rval?;
+
+        Ok(buf)
+    }
+
+    fn read_int(&mut self, addr: i64, nbytes: i64) -> Result<i64> {
+        let buf = self.read(addr, nbytes)?;
+        let mut val = [0u8; 8];
+        val[..buf.len()].copy_from_slice(&buf);
+
+        Ok(i64::from_le_bytes(val))
+    }
+
+    fn symbol(&self, name: &str) -> Result<i64> {
+        if let Ok(variable) = self.hubris.lookup_variable(name) {
+            return Ok(variable.addr.into());
+        }
+
+        let syms = self.hubris.lookup_symbols(name);
+
+        if let Some((_, addr, _)) = syms.iter().find(|(n, _, _)| *n == name) {
+            return Ok((*addr).into());
+        }
+
+        match syms.as_slice() {
+            [] => bail!("symbol {name} not found"),
+            [(_, addr, _)] => Ok((*addr).into()),
+            _ => bail!("{name} is ambiguous"),
+        }
+    }
+
+    fn readvar(&mut self, name: &str) -> Result<Dynamic> {
+        let variable = self.hubris.lookup_variable(name)?;
+        let (addr, size, goff) = (variable.addr, variable.size, variable.goff);
+
+        let buf = self.read(addr.into(), size as i64)?;
+        let ty = self.hubris.lookup_type(goff)?;
+
+        Ok(script_value(&reflect::load_value(&self.hubris, &buf, ty, 0)?))
+    }
+
+    fn call(&mut self, name: &str, args: &rhai::Map) -> Result<Dynamic> {
+        let op = self.hubris.get_idol_command(name)?;
+        let mut scalars = vec![];
+        let mut strings = vec![];
+
+        for (arg, val) in args {
+            if let Ok(v) = val.as_int() {
+                scalars.push((arg.as_str(), v as u64));
+            } else if let Ok(v) = val.as_bool() {
+                scalars.push((arg.as_str(), v.into()));
+            } else if val.is_string() {
+                strings.push((arg.as_str(), val.to_string()));
+            } else {
+                bail!(
+                    "argument {arg} has unsupported type {}",
+                    val.type_name()
+                );
+            }
+        }
+
+        let args = scalars
+            .iter()
+            .map(|(arg, v)| (*arg, IdolArgument::Scalar(*v)))
+            .chain(
+                strings.iter().map(|(arg, v)| (*arg, IdolArgument::String(v))),
+            )
+            .collect::<Vec<_>>();
+
+        let core = self.core.as_mut();
+        let mut context = HiffyContext::new(&self.hubris, core, self.timeout)?;
+
+        match hiffy_call(&self.hubris, core, &mut context, &op, &args, None)? {
+            Ok(val) => Ok(script_value(&val)),
+            Err(err) => bail!("{name}() => Err({err})"),
+        }
+    }
+
+    ///
+    /// Performs an I2C read or write, returning any bytes read.  A device is
+    /// either named, or specified by its bus and address.
+    ///
+    fn i2c(
+        &mut self,
+        device: &str,
+        address: Option<i64>,
+        register: &Dynamic,
+        write: Option<&Blob>,
+        nbytes: i64,
+    ) -> Result<Blob> {
+        let hubris = &self.hubris;
+
+        let hargs = match address {
+            Some(address) => I2cArgs::parse(
+                hubris,
+                &Some(device.to_string()),
+                None,
+                &None,
+                &None,
+                &Some(script_u8(address, "address")?.to_string()),
+            )?,
+            None => I2cArgs::parse(
+                hubris,
+                &None,
+                None,
+                &None,
+                &None,
+                &Some(device.to_string()),
+            )?,
+        };
+
+        let register = script_register(register)?;
+        let core = self.core.as_mut();
+        let mut context = HiffyContext::new(hubris, core, self.timeout)?;
+
+        let func = match write {
+            Some(_) => context.get_function("I2cWrite", 8)?,
+            None => context.get_function("I2cRead", 7)?,
+        };
+
+        let mut ops = vec![Op::Push(hargs.controller)];
+        ops.push(Op::Push(hargs.port.index));
+
+        match hargs.mux {
+            Some((mux, segment)) => {
+                ops.push(Op::Push(mux));
+                ops.push(Op::Push(segment));
+            }
+            None => {
+                ops.push(Op::PushNone);
+                ops.push(Op::PushNone);
+            }
+        }
+
+        ops.push(Op::Push(hargs.address.unwrap()));
+
+        match register {
+            Some(register) => ops.push(Op::Push(register)),
+            None => ops.push(Op::PushNone),
+        }
+
+        match write {
+            Some(bytes) => {
+                for b in bytes {
+                    ops.push(Op::Push(*b));
+                }
+
+                ops.push(Op::Push32(bytes.len() as u32));
+            }
+            None => ops.push(Op::Push(script_u8(nbytes, "length")?)),
+        }
+
+        ops.push(Op::Call(func.id));
+        ops.push(Op::Done);
+
+        match context.run(core, ops.as_slice(), None)?.pop() {
+            Some(Ok(bytes)) => Ok(if write.is_some() { vec![] } else { bytes }),
+            Some(Err(err)) => bail!(
+                "I2C operation on {hargs} failed: {}",
+                func.errmap
+                    .get(&err)
+                    .cloned()
+                    .unwrap_or_else(|| err.to_string())
+            ),
+            None => bail!("no result from I2C operation on {hargs}"),
+        }
+    }
+}
+
+///
+/// Registers the functions that we make available to scripts.  Each
+/// function borrows the shared state for the duration of its call.
+///
+fn script_engine(state: &Rc<RefCell<ScriptState>>) -> Engine {
+    let mut engine = Engine::new();
+
+    for (name, nbytes) in [("read_u8", 1), ("read_u16", 2), ("read_u32", 4)] {
+        let s = state.clone();
+        engine.register_fn(name, move |addr: i64| -> ScriptResult<i64> {
+            s.borrow_mut().read_int(addr, nbytes).map_err(script_error)
+        });
+    }
+
+    let s = state.clone();
+    engine.register_fn("read", move |addr: i64, nbytes: i64| {
+        s.borrow_mut().read(addr, nbytes).map_err(script_error)
+    });
+
+    let s = state.clone();
+    engine.register_fn("symbol", move |name: &str| {
+        s.borrow().symbol(name).map_err(script_error)
+    });
+
+    let s = state.clone();
+    engine.register_fn("readvar", move |name: &str| {
+        s.borrow_mut().readvar(name).map_err(script_error)
+    });
+
+    let s = state.clone();
+    engine.register_fn("call", move |name: &str, args: rhai::Map| {
+        s.borrow_mut().call(name, &args).map_err(script_error)
+    });
+
+    let s = state.clone();
+    engine.register_fn(
+        "i2c_read",
+        move |device: &str, register: Dynamic, nbytes: i64| {
+            s.borrow_mut()
+                .i2c(device, None, &register, None, nbytes)
+                .map_err(script_error)
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "i2c_read",
+        move |bus: &str, address: i64, register: Dynamic, nbytes: i64| {
+            s.borrow_mut()
+                .i2c(bus, Some(address), &register, None, nbytes)
+                .map_err(script_error)
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "i2c_write",
+        move |device: &str, register: Dynamic, bytes: Blob| {
+            s.borrow_mut()
+                .i2c(device, None, &register, Some(&bytes), 0)
+                .map(|_| ())
+                .map_err(script_error)
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "i2c_write",
+        move |bus: &str, address: i64, register: Dynamic, bytes: Blob| {
+            s.borrow_mut()
+                .i2c(bus, Some(address), &register, Some(&bytes), 0)
+                .map(|_| ())
+                .map_err(script_error)
+        },
+    );
+
+    engine.register_fn("sleep", |ms: i64| {
+        thread::sleep(Duration::from_millis(ms.max(0) as u64));
+    });
+
+    engine
+}
+
+fn script(context: &mut ExecutionContext) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = ScriptArgs::try_parse_from(subargs)?;
+
+    let source = std::fs::read_to_string(&subargs.script)
+        .map_err(|e| anyhow!("failed to read {}: {e}", subargs.script))?;
+
+    let state = Rc::new(RefCell::new(ScriptState {
+        core: context.core.take().unwrap(),
+        hubris: context.archive.take().unwrap(),
+        timeout: subargs.timeout,
+    }));
+
+    let engine = script_engine(&state);
+    let mut scope = Scope::new();
+
+    scope.push_constant(
+        "ARGS",
+        subargs
+            .args
+            .iter()
+            .map(|a| Dynamic::from(a.clone()))
+            .collect::<Array>(),
+    );
+
+    let rval = engine.run_with_scope(&mut scope, &source);
+
+    //
+    // Now that the script has completed, our engine is the only other
+    // holder of our state; drop it to give the core and archive back.
+    //
+    drop(engine);
+
+    let state = match Rc::try_unwrap(state) {
+        Ok(state) => state.into_inner(),
+        Err(_) => bail!("script state is still in use"),
+    };
+
+    context.core = Some(state.core);
+    context.archive = Some(state.hubris);
+
+    if let Err(err) = rval {
+        bail!("{}", err);
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: ScriptArgs::command(),
+        name: "script",
+        run: script,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -0,0 +1,405 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Correlation of the sensor values cached by the sensor task with those
+//! read directly from the hardware.  A value that disagrees with the
+//! hardware indicates a problem with the sensor (or with its conversion); a
+//! value that agrees but is old indicates that the firmware has stopped
+//! polling it.
+//!
+
+use anyhow::Result;
+use hif::*;
+use humility::core::Core;
+use humility::hubris::*;
+use humility::reflect::Base;
+use humility_hiffy::*;
+use humility_i2c::I2cRetryPolicy;
+use humility_idol::{self as idol, HubrisIdol};
+use pmbus::commands::CommandCode;
+
+///
+/// How a sensor's value is read directly from its device.  Only devices
+/// whose values can be read and converted without any configuration are
+/// supported.  In particular, because `humility sensors` is safe for
+/// operators, we never write to a device -- and therefore can't read a rail
+/// of a PMBus device that has more than one, as that requires setting its
+/// PAGE (and thereby changing the page that firmware will find selected).
+///
+#[derive(Debug)]
+enum HardwareRead {
+    Pmbus { driver: pmbus::Device, code: u8 },
+    Tmp117,
+    Sbtsi,
+    NvmeBmc,
+}
+
+impl HardwareRead {
+    fn from_sensor(
+        hubris: &HubrisArchive,
+        ndx: usize,
+    ) -> Result<Self, &'static str> {
+        let sensor = &hubris.manifest.sensors[ndx];
+
+        let HubrisSensorDevice::I2c(d) = sensor.device else {
+            return Err("unsupported");
+        };
+
+        let device = &hubris.manifest.i2c_devices[d];
+
+        match device.device.as_str() {
+            "tmp117" => return Ok(HardwareRead::Tmp117),
+            "sbtsi" => return Ok(HardwareRead::Sbtsi),
+            "nvme_bmc" | "m2_hp_only" => return Ok(HardwareRead::NvmeBmc),
+            _ => {}
+        }
+
+        let HubrisI2cDeviceClass::Pmbus { rails } = &device.class else {
+            return Err("unsupported");
+        };
+
+        if rails.len() > 1 {
+            return Err("paged (unsupported)");
+        }
+
+        let code = match sensor.kind {
+            HubrisSensorKind::Temperature => CommandCode::READ_TEMPERATURE_1,
+            HubrisSensorKind::Power => CommandCode::READ_POUT,
+            HubrisSensorKind::Current => CommandCode::READ_IOUT,
+            HubrisSensorKind::Voltage => CommandCode::READ_VOUT,
+            HubrisSensorKind::InputCurrent => CommandCode::READ_IIN,
+            HubrisSensorKind::InputVoltage => CommandCode::READ_VIN,
+            HubrisSensorKind::Speed => CommandCode::READ_FAN_SPEED_1,
+        };
+
+        let driver = pmbus::Device::from_str(&device.device)
+            .unwrap_or(pmbus::Device::Common);
+
+        Ok(HardwareRead::Pmbus { driver, code: code as u8 })
+    }
+
+    ///
+    /// Appends the operations to read the value, returning the number of
+    /// results that they will produce.
+    ///
+    fn ops(&self, ops: &mut Vec<Op>, read: &HiffyFunction) -> usize {
+        let mut nresults = 0;
+
+        let mut push_read = |reg: u8, nbytes: Option<u8>| {
+            ops.push(Op::Push(reg));
+
+            match nbytes {
+                Some(nbytes) => ops.push(Op::Push(nbytes)),
+                None => ops.push(Op::PushNone),
+            }
+
+            ops.push(Op::Call(read.id));
+            ops.push(Op::DropN(2));
+            nresults += 1;
+        };
+
+        match self {
+            HardwareRead::Pmbus { code, .. } => {
+                push_read(CommandCode::VOUT_MODE as u8, Some(1));
+                push_read(*code, Some(2));
+            }
+            HardwareRead::Tmp117 => push_read(0x00, Some(2)),
+            HardwareRead::Sbtsi => {
+                //
+                // Reading the integer part latches the decimal part.
+                //
+                push_read(0x01, Some(1));
+                push_read(0x10, Some(1));
+            }
+            HardwareRead::NvmeBmc => push_read(0x00, None),
+        }
+
+        nresults
+    }
+
+    fn value(
+        &self,
+        results: &[Result<Vec<u8>, u32>],
+        read: &HiffyFunction,
+    ) -> Result<f32, String> {
+        let mut results = results.iter();
+
+        let mut next = || match results.next() {
+            Some(Ok(val)) => Ok(val.as_slice()),
+            Some(Err(err)) => Err(read.strerror(*err)),
+            None => Err("missing result".to_string()),
+        };
+
+        match self {
+            HardwareRead::Pmbus { driver, code, .. } => {
+                let mode = match next()? {
+                    [mode] => *mode,
+                    _ => 0,
+                };
+
+                let val = next()?;
+                pmbus_value(driver, *code, val, mode)
+                    .ok_or_else(|| format!("uninterpretable {val:x?}"))
+            }
+            HardwareRead::Tmp117 => match next()? {
+                [hi, lo] => Ok(i16::from_be_bytes([*hi, *lo]) as f32 / 128.0),
+                val => Err(format!("bad read {val:x?}")),
+            },
+            HardwareRead::Sbtsi => match (next()?, next()?) {
+                ([int], [dec]) => Ok(*int as f32 + (*dec >> 5) as f32 * 0.125),
+                (int, dec) => Err(format!("bad read {int:x?} {dec:x?}")),
+            },
+            HardwareRead::NvmeBmc => match next()? {
+                [_, _, temp, ..] if *temp <= 0x7f || *temp >= 0xc4 => {
+                    Ok(*temp as i8 as f32)
+                }
+                [_, _, temp, ..] => Err(format!("no data (0x{temp:02x})")),
+                val => Err(format!("bad read {val:x?}")),
+            },
+        }
+    }
+}
+
+///
+/// Interprets a PMBus value according to its device, returning it as a
+/// scalar.  The pmbus crate offers no generic way of getting at a value's
+/// units, so we take the leading numeric portion of its representation.
+///
+fn pmbus_value(
+    driver: &pmbus::Device,
+    code: u8,
+    val: &[u8],
+    mode: u8,
+) -> Option<f32> {
+    let mode = || pmbus::commands::VOUT_MODE::CommandData(mode);
+    let mut rval = None;
+
+    let _ = driver.interpret(code, val, mode, |field, value| {
+        if !field.bitfield() {
+            let s = value.to_string();
+            let end = s
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+                .unwrap_or(s.len());
+            rval = s[..end].parse().ok();
+        }
+    });
+
+    rval
+}
+
+///
+/// Reads the specified sensors from both the sensor task and the hardware,
+/// and displays the values side by side along with any discrepancies.
+///
+pub fn correlate(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    sensors: &[(usize, &HubrisSensor)],
+    tolerance: f32,
+    stale: u64,
+) -> Result<()> {
+    //
+    // Older sensor tasks don't record the time of a reading; for these, we
+    // can still correlate values, but we can't say anything about age.
+    //
+    let (op, timestamped) = match hubris.get_idol_command("Sensor.get_reading")
+    {
+        Ok(op) => (op, true),
+        Err(_) => {
+            humility::warn!(
+                "sensor task does not provide readings with timestamps; \
+                staleness cannot be determined"
+            );
+            (hubris.get_idol_command("Sensor.get")?, false)
+        }
+    };
+
+    let mut firmware = vec![];
+
+    for s in sensors.chunks(100) {
+        let mut ops = vec![];
+
+        for (i, _) in s {
+            let payload =
+                op.payload(&[("id", idol::IdolArgument::Scalar(*i as u64))])?;
+            context.idol_call_ops(&op, &payload, &mut ops)?;
+        }
+
+        ops.push(Op::Done);
+
+        for r in context.run(core, ops.as_slice(), None)? {
+            let reading = match hiffy_decode(hubris, &op, r)? {
+                Ok(val) if timestamped => {
+                    let s = val.as_struct()?;
+
+                    match (s["value"].as_base()?, s["timestamp"].as_base()?) {
+                        (Base::F32(val), ts) => Ok((*val, ts.as_u64())),
+                        _ => Err(format!("unexpected reading {val:?}")),
+                    }
+                }
+                Ok(val) => match val.as_base()? {
+                    Base::F32(val) => Ok((*val, None)),
+                    _ => Err(format!("unexpected value {val:?}")),
+                },
+                Err(err) => Err(err),
+            };
+
+            firmware.push(reading);
+        }
+    }
+
+    let now = hubris.ticks(core)?;
+
+    let read = context.get_function("I2cRead", 7)?;
+
+    let mut ops = vec![];
+    let mut reads = vec![];
+
+    for (i, _) in sensors {
+        let hw = match HardwareRead::from_sensor(hubris, *i) {
+            Ok(hw) => hw,
+            Err(reason) => {
+                reads.push(Err(reason));
+                continue;
+            }
+        };
+
+        let HubrisSensorDevice::I2c(d) = hubris.manifest.sensors[*i].device
+        else {
+            unreachable!();
+        };
+
+        let device = &hubris.manifest.i2c_devices[d];
+
+        ops.push(Op::Push(device.controller));
+        ops.push(Op::Push(device.port.index));
+
+        match (device.mux, device.segment) {
+            (Some(mux), Some(segment)) => {
+                ops.push(Op::Push(mux));
+                ops.push(Op::Push(segment));
+            }
+            _ => {
+                ops.push(Op::PushNone);
+                ops.push(Op::PushNone);
+            }
+        }
+
+        ops.push(Op::Push(device.address));
+        let nresults = hw.ops(&mut ops, &read);
+        ops.push(Op::DropN(5));
+
+        reads.push(Ok((hw, nresults)));
+    }
+
+    ops.push(Op::Done);
+
+    let results = if reads.iter().any(Result::is_ok) {
+        I2cRetryPolicy::default().run(context, core, &ops, None, &read)?
+    } else {
+        vec![]
+    };
+
+    println!(
+        "{:20} {:13} {:>12} {:>8} {:>12} {:>8} STATUS",
+        "NAME", "KIND", "FIRMWARE", "AGE", "HARDWARE", "DELTA"
+    );
+
+    let mut results = results.as_slice();
+    let (mut nstale, mut nmismatched) = (0, 0);
+
+    for (((_, s), fw), hw) in sensors.iter().zip(&firmware).zip(&reads) {
+        let mut status = vec![];
+
+        let hw = match hw {
+            Ok((hw, nresults)) => {
+                let (these, rest) = results.split_at(*nresults);
+                results = rest;
+                hw.value(these, &read)
+            }
+            Err(reason) => Err(reason.to_string()),
+        };
+
+        let (fwval, age) = match fw {
+            Ok((val, ts)) => {
+                (format!("{val:.2}"), ts.map(|ts| now.saturating_sub(ts)))
+            }
+            Err(err) => {
+                status.push(format!("firmware: {err}"));
+                ("-".to_string(), None)
+            }
+        };
+
+        let hwval = match &hw {
+            Ok(val) => format!("{val:.2}"),
+            Err(err) => {
+                status.push(format!("hardware: {err}"));
+                "-".to_string()
+            }
+        };
+
+        if let Some(age) = age {
+            if age > stale {
+                status.push("stale".to_string());
+                nstale += 1;
+            }
+        }
+
+        let delta = match (fw, &hw) {
+            (Ok((fw, _)), Ok(hw)) => {
+                let delta = fw - hw;
+
+                //
+                // We express the discrepancy relative to the hardware value,
+                // unless that value is zero.
+                //
+                let relative = if *hw != 0.0 {
+                    (delta / hw).abs() * 100.0
+                } else {
+                    delta.abs() * 100.0
+                };
+
+                if relative > tolerance {
+                    status.push("mismatch".to_string());
+                    nmismatched += 1;
+                }
+
+                format!("{delta:+.2}")
+            }
+            _ => "-".to_string(),
+        };
+
+        println!(
+            "{:20} {:13} {fwval:>12} {:>8} {hwval:>12} {delta:>8} {}",
+            s.name,
+            s.kind.to_string(),
+            match age {
+                Some(age) => format!("{age}ms"),
+                None => "-".to_string(),
+            },
+            if status.is_empty() {
+                "ok".to_string()
+            } else {
+                status.join(", ")
+            }
+        );
+    }
+
+    if nstale != 0 {
+        humility::warn!(
+            "{nstale} sensor(s) not updated by firmware in more than {stale}ms"
+        );
+    }
+
+    if nmismatched != 0 {
+        humility::warn!(
+            "{nmismatched} sensor(s) differ from hardware by more than \
+            {tolerance}%"
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,277 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Export of sensor readings in the Prometheus text exposition format or
+//! the InfluxDB line protocol, either to stdout or to an HTTP endpoint
+//! (e.g., a Prometheus Pushgateway or an InfluxDB write API).
+//!
+
+use anyhow::{bail, Context, Result};
+use humility::hubris::*;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(clap::ArgEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Prometheus,
+    Influx,
+}
+
+/// An HTTP endpoint to which we push metrics
+#[derive(Debug)]
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn from_url(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            bail!("push URL \"{url}\" must be of the form http://host/path");
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(ndx) => (&rest[..ndx], &rest[ndx..]),
+            None => (rest, "/"),
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse::<u16>()
+                    .with_context(|| format!("bad port in \"{url}\""))?,
+            ),
+            _ => (authority, 80),
+        };
+
+        if host.is_empty() {
+            bail!("push URL \"{url}\" is missing a host");
+        }
+
+        Ok(Self { host: host.to_string(), port, path: path.to_string() })
+    }
+
+    fn post(&self, body: &str) -> Result<()> {
+        let mut stream = TcpStream::connect((
+            self.host.trim_start_matches('[').trim_end_matches(']'),
+            self.port,
+        ))
+        .with_context(|| format!("failed to connect to {}", self.host))?;
+
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\n\
+            Host: {}:{}\r\n\
+            Content-Type: text/plain; version=0.0.4\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\r\n{body}",
+            self.path,
+            self.host,
+            self.port,
+            body.len(),
+        )?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+
+        let status = response.lines().next().unwrap_or_default();
+
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => bail!("push to {}{} failed: {status}", self.host, self.path),
+        }
+    }
+}
+
+pub struct Exporter<'a> {
+    hubris: &'a HubrisArchive,
+    format: ExportFormat,
+    endpoint: Option<Endpoint>,
+}
+
+/// Returns the metric name (including units) for a kind of sensor
+fn metric(kind: HubrisSensorKind) -> &'static str {
+    match kind {
+        HubrisSensorKind::Temperature => "temperature_celsius",
+        HubrisSensorKind::Power => "power_watts",
+        HubrisSensorKind::Current => "current_amperes",
+        HubrisSensorKind::Voltage => "voltage_volts",
+        HubrisSensorKind::InputCurrent => "input_current_amperes",
+        HubrisSensorKind::InputVoltage => "input_voltage_volts",
+        HubrisSensorKind::Speed => "speed_rpm",
+    }
+}
+
+impl<'a> Exporter<'a> {
+    pub fn new(
+        hubris: &'a HubrisArchive,
+        format: ExportFormat,
+        push: Option<&str>,
+    ) -> Result<Self> {
+        let endpoint = push.map(Endpoint::from_url).transpose()?;
+        Ok(Self { hubris, format, endpoint })
+    }
+
+    ///
+    /// Returns the labels for a sensor, as derived from the manifest:  the
+    /// sensor's name and kind, the board, and the device (including its
+    /// location, for I2C devices).
+    ///
+    fn labels(&self, sensor: &HubrisSensor) -> Vec<(&'static str, String)> {
+        let manifest = &self.hubris.manifest;
+        let mut labels = vec![
+            ("sensor", sensor.name.clone()),
+            ("kind", sensor.kind.to_string().to_string()),
+        ];
+
+        if let Some(board) = &manifest.board {
+            labels.push(("board", board.clone()));
+        }
+
+        match &sensor.device {
+            HubrisSensorDevice::I2c(ndx) => {
+                let device = &manifest.i2c_devices[*ndx];
+                labels.push(("device", device.device.clone()));
+
+                if let Some(name) = &device.name {
+                    labels.push(("device_name", name.clone()));
+                }
+
+                let mut bus =
+                    format!("{}{}", device.controller, device.port.name);
+
+                if let (Some(mux), Some(segment)) = (device.mux, device.segment)
+                {
+                    write!(bus, ":{mux}:{segment}").unwrap();
+                }
+
+                labels.push(("bus", bus));
+                labels.push(("address", format!("{:#04x}", device.address)));
+            }
+            HubrisSensorDevice::Other(device, _) => {
+                labels.push(("device", device.clone()));
+            }
+        }
+
+        labels
+    }
+
+    fn prometheus(
+        &self,
+        sensors: &[(usize, &HubrisSensor)],
+        values: &[Option<f32>],
+    ) -> String {
+        let escape = |s: &str| {
+            s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+        };
+
+        let mut out = String::new();
+        let mut kinds = vec![];
+
+        for (_, s) in sensors {
+            if !kinds.contains(&s.kind) {
+                kinds.push(s.kind);
+            }
+        }
+
+        //
+        // Each metric family must be contiguous, so we emit each kind of
+        // sensor in turn.
+        //
+        for kind in kinds {
+            let name = format!("hubris_sensor_{}", metric(kind));
+            writeln!(out, "# TYPE {name} gauge").unwrap();
+
+            for ((_, s), value) in sensors.iter().zip(values) {
+                if s.kind != kind {
+                    continue;
+                }
+
+                let Some(value) = value else {
+                    continue;
+                };
+
+                let labels = self
+                    .labels(s)
+                    .iter()
+                    .map(|(k, v)| format!("{k}=\"{}\"", escape(v)))
+                    .collect::<Vec<_>>();
+
+                writeln!(out, "{name}{{{}}} {value}", labels.join(","))
+                    .unwrap();
+            }
+        }
+
+        out
+    }
+
+    fn influx(
+        &self,
+        sensors: &[(usize, &HubrisSensor)],
+        values: &[Option<f32>],
+    ) -> Result<String> {
+        let escape = |s: &str| {
+            s.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let mut out = String::new();
+
+        for ((_, s), value) in sensors.iter().zip(values) {
+            let Some(value) = value else {
+                continue;
+            };
+
+            let tags = self
+                .labels(s)
+                .iter()
+                .map(|(k, v)| format!("{k}={}", escape(v)))
+                .collect::<Vec<_>>();
+
+            writeln!(
+                out,
+                "hubris_sensor,{} {}={value} {now}",
+                tags.join(","),
+                metric(s.kind),
+            )
+            .unwrap();
+        }
+
+        Ok(out)
+    }
+
+    ///
+    /// Emits one set of readings, either to stdout or to our endpoint.
+    /// Sensors that could not be read are omitted.
+    ///
+    pub fn emit(
+        &self,
+        sensors: &[(usize, &HubrisSensor)],
+        values: &[Option<f32>],
+    ) -> Result<()> {
+        let body = match self.format {
+            ExportFormat::Prometheus => self.prometheus(sensors, values),
+            ExportFormat::Influx => self.influx(sensors, values)?,
+        };
+
+        match &self.endpoint {
+            Some(endpoint) => endpoint.post(&body),
+            None => {
+                print!("{body}");
+
+                if self.format == ExportFormat::Prometheus {
+                    println!();
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
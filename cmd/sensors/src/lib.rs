@@ -22,10 +22,78 @@
 //! option.  To print values as a table with individual sensors as columns,
 //! `--tabular`.  In its default output (with one sensor per row), error
 //! counts are also displayed.
+//!
+//! To export readings for consumption by a time-series database, use the
+//! `--export` option, specifying either `prometheus` (for the Prometheus text
+//! exposition format) or `influx` (for the InfluxDB line protocol).  Each
+//! reading is labelled with the sensor's name and kind, the board, and the
+//! device that provides it, as found in the manifest:
+//!
+//! ```console
+//! $ humility sensors --export prometheus -t temp -d tmp117
+//! humility: attached via ST-Link V3
+//! # TYPE hubris_sensor_temperature_celsius gauge
+//! hubris_sensor_temperature_celsius{sensor="Southwest",kind="temp",board="gimlet-c",device="tmp117",device_name="Southwest",bus="2F",address="0x48"} 26.5625
+//! hubris_sensor_temperature_celsius{sensor="South",kind="temp",board="gimlet-c",device="tmp117",device_name="South",bus="2F",address="0x49"} 27.2265625
+//! hubris_sensor_temperature_celsius{sensor="Southeast",kind="temp",board="gimlet-c",device="tmp117",device_name="Southeast",bus="2F",address="0x4a"} 26.3515625
+//! ```
+//!
+//! Readings are written to stdout by default; to instead push them to an HTTP
+//! endpoint (e.g., a Prometheus Pushgateway or the InfluxDB write API), specify
+//! its URL with `--push`.  Combined with `--sleep`, this allows for
+//! long-running telemetry collection; a failure to push is reported but does
+//! not end the export:
+//!
+//! ```console
+//! $ humility sensors --export influx --sleep \
+//!     --push "http://influx:8086/api/v2/write?org=lab&bucket=bringup&precision=ns"
+//! ```
+//!
+//! To view readings over time in a trace viewer, use `--chrome-trace` to
+//! write them to a file in the Chrome trace event format, which can be
+//! loaded into `chrome://tracing` or Perfetto (https://ui.perfetto.dev).
+//! Each sensor appears as a counter track; when used with `--sleep`, the
+//! file is written when the command is interrupted:
+//!
+//! ```console
+//! $ humility sensors --sleep -t temp --chrome-trace temps.json
+//! ...
+//! ^Chumility: Chrome trace written to temps.json
+//! ```
+//!
+//! To determine whether a suspicious value is due to the sensor itself or to
+//! the firmware no longer updating it, use `--correlate`, which reads each
+//! sensor's most recent value (and its age) from the `sensor` task, reads the
+//! same value directly from the device, and reports any discrepancy:
+//!
+//! ```console
+//! $ humility sensors --correlate -t temp
+//! humility: attached via ST-Link V3
+//! NAME                 KIND              FIRMWARE      AGE     HARDWARE    DELTA STATUS
+//! Southwest            temp                 26.56    412ms        26.56    +0.00 ok
+//! South                temp                 27.23    412ms        27.25    -0.02 ok
+//! Southeast            temp                 31.80  93811ms        26.35    +5.45 stale, mismatch
+//! CPU                  temp                 46.12    412ms        46.12    +0.00 ok
+//! U2_N0                temp                 37.00    412ms            -        - hardware: NoDevice
+//! ```
+//!
+//! A value that differs from the hardware by more than a tolerance (5% by
+//! default; this can be changed with `--tolerance`) is flagged as a
+//! mismatch, and a value that the `sensor` task hasn't updated in more than
+//! 5 seconds (changed with `--stale`, in milliseconds) is flagged as stale.
+//! Values are read directly only from PMBus devices and from devices that
+//! require no configuration to read (e.g., TMP117 and SB-TSI temperature
+//! sensors); for other devices, the hardware value is displayed as
+//! unsupported.  Devices are only read, never written, so the rails of a
+//! PMBus device that has more than one (and would therefore require its
+//! PAGE to be set) are also unsupported.  As the hardware values are read
+//! directly, `--correlate` requires a debugger connection.
+//!
 
 use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
 use hif::*;
+use humility::chrome::ChromeTrace;
 use humility::core::Core;
 use humility::hubris::*;
 use humility_cli::{ExecutionContext, Subcommand};
@@ -34,9 +102,14 @@ use humility_hiffy::*;
 use humility_idol::{self as idol, HubrisIdol};
 use itertools::izip;
 use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::{Duration, Instant};
 
+mod correlate;
+mod export;
+use export::{ExportFormat, Exporter};
+
 #[derive(Parser, Debug)]
 #[clap(name = "sensors", about = env!("CARGO_PKG_DESCRIPTION"))]
 struct SensorsArgs {
@@ -59,6 +132,48 @@ struct SensorsArgs {
     #[clap(long, conflicts_with = "list")]
     tabular: bool,
 
+    /// export readings as Prometheus exposition or InfluxDB line protocol
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "format",
+        conflicts_with_all = &["list", "tabular"]
+    )]
+    export: Option<ExportFormat>,
+
+    /// push exported readings to the specified HTTP endpoint
+    #[clap(long, value_name = "url", requires = "export")]
+    push: Option<String>,
+
+    /// write readings to the specified file in Chrome trace event format
+    #[clap(long, value_name = "filename", conflicts_with = "list")]
+    chrome_trace: Option<String>,
+
+    /// compare sensor task values with values read from hardware
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "list", "sleep", "tabular", "export", "chrome_trace"
+        ]
+    )]
+    correlate: bool,
+
+    /// percentage by which a value may differ from hardware
+    #[clap(
+        long,
+        value_name = "percent",
+        default_value_t = 5.0,
+        requires = "correlate"
+    )]
+    tolerance: f32,
+
+    /// age beyond which a sensor task value is considered stale
+    #[clap(
+        long, value_name = "ms", default_value_t = 5000,
+        requires = "correlate", parse(try_from_str = parse_int::parse)
+    )]
+    stale: u64,
+
     /// restrict sensors by type of sensor
     #[clap(
         long,
@@ -154,30 +269,12 @@ fn list(
     Ok(())
 }
 
-fn print(
-    hubris: &HubrisArchive,
-    core: &mut dyn Core,
-    subargs: &SensorsArgs,
-    context: &mut HiffyContext,
+fn select<'a>(
+    hubris: &'a HubrisArchive,
     types: &Option<HashSet<HubrisSensorKind>>,
     devices: &Option<HashSet<&String>>,
     named: &Option<HashSet<&String>>,
-) -> Result<()> {
-    let mut all_ops = vec![];
-    let mut err_ops = vec![];
-    let nerrbits = 32;
-    let op = hubris.get_idol_command("Sensor.get")?;
-
-    let ok = hubris.lookup_basetype(op.ok)?;
-
-    if (ok.encoding, ok.size) != (HubrisEncoding::Float, 4) {
-        bail!("expected return value of Sensor.get() to be a float");
-    }
-
-    if hubris.manifest.sensors.is_empty() {
-        bail!("no sensors found");
-    }
-
+) -> Vec<(usize, &'a HubrisSensor)> {
     let mut sensors = vec![];
 
     for (i, s) in hubris.manifest.sensors.iter().enumerate() {
@@ -206,6 +303,35 @@ fn print(
         sensors.push((i, s));
     }
 
+    sensors
+}
+
+fn print(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &SensorsArgs,
+    context: &mut HiffyContext,
+    types: &Option<HashSet<HubrisSensorKind>>,
+    devices: &Option<HashSet<&String>>,
+    named: &Option<HashSet<&String>>,
+) -> Result<()> {
+    let mut all_ops = vec![];
+    let mut err_ops = vec![];
+    let nerrbits = 32;
+    let op = hubris.get_idol_command("Sensor.get")?;
+
+    let ok = hubris.lookup_basetype(op.ok)?;
+
+    if (ok.encoding, ok.size) != (HubrisEncoding::Float, 4) {
+        bail!("expected return value of Sensor.get() to be a float");
+    }
+
+    if hubris.manifest.sensors.is_empty() {
+        bail!("no sensors found");
+    }
+
+    let sensors = select(hubris, types, devices, named);
+
     for s in sensors.chunks(100) {
         let mut ops = vec![];
 
@@ -242,6 +368,13 @@ fn print(
         }
     }
 
+    let exporter = match subargs.export {
+        Some(format) => {
+            Some(Exporter::new(hubris, format, subargs.push.as_deref())?)
+        }
+        None => None,
+    };
+
     if subargs.tabular {
         for (_, s) in &sensors {
             print!(" {:>12}", s.name.to_uppercase());
@@ -256,6 +389,23 @@ fn print(
         println!();
     }
 
+    //
+    // If we are writing a Chrome trace while reading continuously, we stop
+    // when interrupted so that we have an opportunity to write it out.
+    //
+    let mut chrome =
+        subargs.chrome_trace.as_ref().map(|_| ChromeTrace::new("sensors"));
+
+    let never = AtomicBool::new(false);
+
+    let done = if chrome.is_some() && subargs.sleep {
+        humility_cmd::interrupted()?
+    } else {
+        &never
+    };
+
+    let begin = Instant::now();
+
     loop {
         let start = Instant::now();
         let mut rval = vec![];
@@ -289,7 +439,30 @@ fn print(
             errs = vec![None; rval.len()];
         }
 
-        if subargs.tabular {
+        if let Some(ref mut chrome) = chrome {
+            let time = (start - begin).as_secs_f64();
+
+            for ((_, s), val) in sensors.iter().zip(&rval) {
+                if let Some(val) = val {
+                    let kind = s.kind.to_string();
+                    let name = format!("{} ({})", s.name, kind);
+                    chrome.counter(&name, time, &[("value", *val as f64)]);
+                }
+            }
+        }
+
+        if let Some(ref exporter) = exporter {
+            //
+            // If we are pushing readings, a failure to push shouldn't end a
+            // long-running export; we warn and try again on the next pass.
+            //
+            match exporter.emit(&sensors, &rval) {
+                Err(err) if subargs.sleep && subargs.push.is_some() => {
+                    humility::warn!("{err:#}");
+                }
+                result => result?,
+            }
+        } else if subargs.tabular {
             for val in rval {
                 if let Some(val) = val {
                     print!(" {:>12.2}", val);
@@ -358,6 +531,15 @@ fn print(
         if elapsed < 1000 {
             thread::sleep(Duration::from_millis(1000 - elapsed));
         }
+
+        if done.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+
+    if let (Some(chrome), Some(filename)) = (chrome, &subargs.chrome_trace) {
+        chrome.write(filename)?;
+        humility::msg!("Chrome trace written to {filename}");
     }
 
     Ok(())
@@ -444,6 +626,23 @@ fn sensors(context: &mut ExecutionContext) -> Result<()> {
 
     let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
 
+    if subargs.correlate {
+        if hubris.manifest.sensors.is_empty() {
+            bail!("no sensors found");
+        }
+
+        let sensors = select(hubris, &types, &devices, &named);
+
+        return correlate::correlate(
+            hubris,
+            core,
+            &mut context,
+            &sensors,
+            subargs.tolerance,
+            subargs.stale,
+        );
+    }
+
     print(hubris, core, &subargs, &mut context, &types, &devices, &named)?;
 
     Ok(())
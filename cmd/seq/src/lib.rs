@@ -0,0 +1,341 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility seq`
+//!
+//! `humility seq` reads and decodes the register space of the power
+//! sequencer FPGA:  the states of its power state machines, its fault
+//! (interrupt flag) bits, and the enables and power-good readbacks of the
+//! rails that it controls.  The FPGA is accessed via the SPI device that the
+//! application TOML names `sequencer` (another device can be specified with
+//! `--device`/`-D`), with the transfers performed by the SPI task:
+//!
+//! ```console
+//! $ humility seq
+//! humility: attached via ST-Link V3
+//! humility: sequencer is device 0 on spi2 (spi2_driver)
+//! ADDR NAME              VALUE DECODED
+//! 0x00 ID0                0x01
+//! 0x01 ID1                0xde
+//! 0x02 ID2                0xaa
+//! 0x03 ID3                0x55
+//! 0x04 CS0                0x00
+//! 0x05 CS1                0x00
+//! 0x06 CS2                0x00
+//! 0x07 CS3                0xff
+//! 0x08 SCRATCHPAD         0x06
+//! 0x09 IFR                0x00
+//! 0x0a IER                0x00
+//! 0x0b STATUS             0x07 FANPWROK | A1PWROK | A0PWROK
+//! 0x0c EARLY_PWR_CTRL     0x01 FANPWREN
+//! 0x0d PWR_CTRL           0x03 A1PWREN | A0A_EN
+//! 0x0e NIC_CTRL           0x00
+//! 0x0f A1SMSTATUS         0x04 DONE
+//! 0x10 A0SMSTATUS         0x06 DONE
+//! 0x11 NICSMSTATUS        0x00 IDLE
+//! ...
+//! ```
+//!
+//! Any set fault bits are additionally called out.  Note that this
+//! register map is that of the Gimlet sequencer; the identification
+//! registers are checked, and a warning is issued if they don't match.
+//!
+//! To catch transient states (e.g., a fault during power-on that is
+//! subsequently cleared), use `--watch` (`-w`), which repeatedly reads the
+//! registers and displays each change as it is observed, along with the
+//! time since the watch began:
+//!
+//! ```console
+//! $ humility seq --watch
+//! humility: attached via ST-Link V3
+//! humility: sequencer is device 0 on spi2 (spi2_driver)
+//! humility: watching sequencer registers; ^C to stop
+//!     0.000s A1SMSTATUS       0x04 DONE
+//!     0.000s A0SMSTATUS       0x00 IDLE
+//!     0.000s NICSMSTATUS      0x00 IDLE
+//!     1.215s A0SMSTATUS       0x00 IDLE -> 0x01 DDR_BULK_EN
+//!     1.322s A0SMSTATUS       0x01 DDR_BULK_EN -> 0x03 GROUPB_EN
+//!     1.322s A0_RBKS_1        0x00 -> 0x3f VPP_ABCD_EN | ...
+//!     1.429s IFR              0x00 -> 0x08 A0MAPO
+//! humility: fault: A0MAPO
+//! ```
+//!
+//! The polling interval can be adjusted with `--interval` (in
+//! milliseconds); the watch continues until interrupted.
+//!
+
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use hif::*;
+use humility::core::Core;
+use humility::hubris::*;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_hiffy::*;
+
+mod regs;
+
+use regs::*;
+
+/// The FPGA command byte to read registers
+const SEQ_CMD_READ: u8 = 0x1;
+
+#[derive(Parser, Debug)]
+#[clap(name = "seq", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct SeqArgs {
+    /// sets timeout
+    #[clap(
+        long, short = 'T', default_value_t = 5000, value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+
+    /// SPI device (by name) of the sequencer FPGA
+    #[clap(
+        long,
+        short = 'D',
+        value_name = "device",
+        default_value = "sequencer"
+    )]
+    device: String,
+
+    /// repeatedly read registers, displaying changes
+    #[clap(long, short)]
+    watch: bool,
+
+    /// interval between reads when watching
+    #[clap(
+        long, short, value_name = "ms", default_value_t = 100,
+        requires = "watch", parse(try_from_str = parse_int::parse)
+    )]
+    interval: u64,
+}
+
+struct SeqReader<'a> {
+    context: HiffyContext<'a>,
+    ops: Vec<Op>,
+    data: Vec<u8>,
+}
+
+impl<'a> SeqReader<'a> {
+    fn new(
+        hubris: &'a HubrisArchive,
+        core: &mut dyn Core,
+        subargs: &SeqArgs,
+    ) -> Result<Self> {
+        let device = hubris
+            .manifest
+            .spi_devices
+            .iter()
+            .find(|d| d.name == subargs.device);
+
+        let device = match device {
+            Some(device) => device,
+            None => {
+                bail!(
+                    "no SPI device named \"{}\" (use \"humility spi --list\" \
+                    to list devices)",
+                    subargs.device
+                );
+            }
+        };
+
+        let task = cmd_spi::spi_task(hubris, Some(device.controller))?;
+
+        humility::msg!(
+            "{} is device {} on spi{} ({})",
+            device.name,
+            device.index,
+            device.controller,
+            hubris.lookup_module(task)?.name
+        );
+
+        let context = HiffyContext::new(hubris, core, subargs.timeout)?;
+        let spi_read = context.get_function("SpiRead", 4)?;
+
+        let last = SEQ_REGISTERS.iter().map(|r| r.addr).max().unwrap();
+
+        //
+        // A read consists of the read command and the (big-endian) address
+        // of the first register; the registers are shifted in after these
+        // three bytes.
+        //
+        let mut data = vec![SEQ_CMD_READ];
+        data.extend_from_slice(&0u16.to_be_bytes());
+
+        let ops = vec![
+            Op::Push32(task.task()),
+            Op::Push(device.index),
+            Op::Push32(data.len() as u32),
+            Op::Push32((data.len() + last as usize + 1) as u32),
+            Op::Call(spi_read.id),
+            Op::Done,
+        ];
+
+        Ok(Self { context, ops, data })
+    }
+
+    fn read(&mut self, core: &mut dyn Core) -> Result<Vec<u8>> {
+        let results = self.context.run(core, &self.ops, Some(&self.data))?;
+
+        match &results[0] {
+            Ok(buf) if buf.len() >= self.data.len() => {
+                Ok(buf[self.data.len()..].to_vec())
+            }
+            Ok(buf) => bail!("short read: {buf:x?}"),
+            Err(err) => bail!("SPI read failed: {err}"),
+        }
+    }
+}
+
+fn seq_value(reg: &SeqRegister, regs: &[u8]) -> Option<u8> {
+    regs.get(reg.addr as usize).copied()
+}
+
+fn seq_faults(regs: &[u8]) -> Vec<String> {
+    SEQ_REGISTERS
+        .iter()
+        .filter(|r| r.fault)
+        .filter_map(|r| match seq_value(r, regs) {
+            Some(val) if val != 0 => Some(r.decode(val)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn seq_check_id(regs: &[u8]) {
+    if regs.get(..SEQ_ID.len()) != Some(&SEQ_ID[..]) {
+        humility::warn!(
+            "identification registers are {:x?}, not {:x?}; \
+            register decoding may be incorrect",
+            regs.get(..SEQ_ID.len()).unwrap_or(regs),
+            SEQ_ID
+        );
+    }
+}
+
+fn seq_print(regs: &[u8]) {
+    println!("{:4} {:<16} {:>6} DECODED", "ADDR", "NAME", "VALUE");
+
+    for reg in SEQ_REGISTERS {
+        if let Some(val) = seq_value(reg, regs) {
+            println!(
+                "0x{:02x} {:<16}   0x{:02x} {}",
+                reg.addr,
+                reg.name,
+                val,
+                reg.decode(val)
+            );
+        }
+    }
+
+    for fault in seq_faults(regs) {
+        humility::msg!("fault: {fault}");
+    }
+}
+
+fn seq_watch(
+    reader: &mut SeqReader,
+    core: &mut dyn Core,
+    interval: u64,
+) -> Result<()> {
+    let done = humility_cmd::interrupted()?;
+
+    humility::msg!("watching sequencer registers; ^C to stop");
+
+    let start = Instant::now();
+    let mut last = reader.read(core)?;
+
+    seq_check_id(&last);
+
+    //
+    // Start by displaying the state machines, which are the registers
+    // whose changes are most likely to be of interest.
+    //
+    for reg in SEQ_REGISTERS {
+        if let (SeqRegisterKind::State(_), Some(val)) =
+            (reg.kind, seq_value(reg, &last))
+        {
+            println!(
+                "{:>9.3}s {:<16} 0x{val:02x} {}",
+                0.0,
+                reg.name,
+                reg.decode(val)
+            );
+        }
+    }
+
+    for fault in seq_faults(&last) {
+        humility::msg!("fault: {fault}");
+    }
+
+    while !done.load(Ordering::SeqCst) {
+        std::thread::sleep(Duration::from_millis(interval));
+
+        let regs = reader.read(core)?;
+        let elapsed = start.elapsed().as_secs_f64();
+
+        for reg in SEQ_REGISTERS {
+            let (was, is) = match (seq_value(reg, &last), seq_value(reg, &regs))
+            {
+                (Some(was), Some(is)) if was != is => (was, is),
+                _ => continue,
+            };
+
+            let (wdecoded, idecoded) = (reg.decode(was), reg.decode(is));
+
+            println!(
+                "{elapsed:>9.3}s {:<16} 0x{was:02x}{}{} -> 0x{is:02x} {}",
+                reg.name,
+                if wdecoded.is_empty() { "" } else { " " },
+                wdecoded,
+                idecoded,
+            );
+
+            if reg.fault && is & !was != 0 {
+                humility::msg!("fault: {}", reg.decode(is & !was));
+            }
+        }
+
+        last = regs;
+    }
+
+    Ok(())
+}
+
+fn seq(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = SeqArgs::try_parse_from(subargs)?;
+
+    let mut reader = SeqReader::new(hubris, core, &subargs)?;
+
+    if subargs.watch {
+        return seq_watch(&mut reader, core, subargs.interval);
+    }
+
+    let regs = reader.read(core)?;
+    seq_check_id(&regs);
+    seq_print(&regs);
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: SeqArgs::command(),
+        name: "seq",
+        run: seq,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
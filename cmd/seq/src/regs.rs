@@ -0,0 +1,246 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! The register map of the power sequencer FPGA.  Registers are a byte
+//! wide, and are addressed by a 16-bit address; the map is contiguous from
+//! address 0, allowing the entire register space to be read in a single
+//! transfer.
+//!
+
+#[derive(Copy, Clone, Debug)]
+pub enum SeqRegisterKind {
+    /// A value that is displayed without further decoding
+    Raw,
+
+    /// A set of single-bit fields, named by bit position
+    Bits(&'static [(u8, &'static str)]),
+
+    /// The state of a state machine, named by value
+    State(&'static [(u8, &'static str)]),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SeqRegister {
+    pub name: &'static str,
+    pub addr: u16,
+    pub kind: SeqRegisterKind,
+
+    /// Set bits in this register denote faults
+    pub fault: bool,
+}
+
+const fn raw(name: &'static str, addr: u16) -> SeqRegister {
+    SeqRegister { name, addr, kind: SeqRegisterKind::Raw, fault: false }
+}
+
+const fn bits(
+    name: &'static str,
+    addr: u16,
+    fields: &'static [(u8, &'static str)],
+) -> SeqRegister {
+    SeqRegister {
+        name,
+        addr,
+        kind: SeqRegisterKind::Bits(fields),
+        fault: false,
+    }
+}
+
+const fn faults(
+    name: &'static str,
+    addr: u16,
+    fields: &'static [(u8, &'static str)],
+) -> SeqRegister {
+    SeqRegister { name, addr, kind: SeqRegisterKind::Bits(fields), fault: true }
+}
+
+const fn state(
+    name: &'static str,
+    addr: u16,
+    states: &'static [(u8, &'static str)],
+) -> SeqRegister {
+    SeqRegister {
+        name,
+        addr,
+        kind: SeqRegisterKind::State(states),
+        fault: false,
+    }
+}
+
+/// The value of the identification registers, `ID0` through `ID3`
+pub const SEQ_ID: [u8; 4] = [0x01, 0xde, 0xaa, 0x55];
+
+const SEQ_IFR: &[(u8, &str)] = &[
+    (0, "FANFAULT"),
+    (1, "THERMTRIP"),
+    (2, "A1MAPO"),
+    (3, "A0MAPO"),
+    (4, "NICMAPO"),
+    (5, "AMD_PWROK_FEDGE"),
+    (6, "AMD_RSTN_FEDGE"),
+];
+
+const SEQ_STATUS: &[(u8, &str)] =
+    &[(0, "FANPWROK"), (1, "A1PWROK"), (2, "A0PWROK"), (3, "NICPWROK")];
+
+const SEQ_EARLY_PWR_CTRL: &[(u8, &str)] =
+    &[(0, "FANPWREN"), (1, "FANHP_RESTART"), (2, "EARLY_PWR_EN")];
+
+const SEQ_PWR_CTRL: &[(u8, &str)] = &[
+    (0, "A1PWREN"),
+    (1, "A0A_EN"),
+    (2, "A0C_DIS"),
+    (3, "NICPWREN"),
+    (4, "CLR_FAULTS"),
+];
+
+const SEQ_NIC_CTRL: &[(u8, &str)] =
+    &[(0, "CLD_RST"), (1, "COMPHY_RST"), (2, "NIC_PERST")];
+
+const SEQ_A1SM: &[(u8, &str)] = &[
+    (0, "IDLE"),
+    (1, "ENABLE"),
+    (2, "WAITPG"),
+    (3, "DELAY"),
+    (4, "DONE"),
+    (5, "MAPO"),
+];
+
+const SEQ_A0SM: &[(u8, &str)] = &[
+    (0, "IDLE"),
+    (1, "DDR_BULK_EN"),
+    (2, "IGNORE_SP"),
+    (3, "GROUPB_EN"),
+    (4, "GROUPB_PG_AND_WAIT"),
+    (5, "GROUPC_PG_AND_WAIT"),
+    (6, "DONE"),
+    (7, "SAFE_DISABLE"),
+    (8, "MAPO"),
+];
+
+const SEQ_NICSM: &[(u8, &str)] = &[
+    (0, "IDLE"),
+    (1, "POWER_UP"),
+    (2, "RESET_RELEASE"),
+    (3, "DONE"),
+    (4, "MAPO"),
+];
+
+const SEQ_EARLY_RBKS: &[(u8, &str)] =
+    &[(0, "FAN_PG"), (1, "V3P3_SP_PG"), (2, "V1P2_SP_PG")];
+
+const SEQ_A1_RBKS: &[(u8, &str)] = &[
+    (0, "V3P3_S5_EN"),
+    (1, "V1P8_S5_EN"),
+    (2, "V1P1_S5_EN"),
+    (3, "V0P9_S5_EN"),
+    (4, "V3P3_S5_PG"),
+    (5, "V1P8_S5_PG"),
+    (6, "V1P1_S5_PG"),
+    (7, "V0P9_S5_PG"),
+];
+
+const SEQ_A0_RBKS_1: &[(u8, &str)] = &[
+    (0, "VPP_ABCD_EN"),
+    (1, "VPP_EFGH_EN"),
+    (2, "VDD_MEM_ABCD_EN"),
+    (3, "VDD_MEM_EFGH_EN"),
+    (4, "VTT_ABCD_EN"),
+    (5, "VTT_EFGH_EN"),
+    (6, "V3P3_S0_EN"),
+    (7, "V1P8_S0_EN"),
+];
+
+const SEQ_A0_RBKS_2: &[(u8, &str)] = &[
+    (0, "VDDCR_CPU_EN"),
+    (1, "VDDCR_SOC_EN"),
+    (2, "V0P9_S0_EN"),
+    (3, "V1P1_S0_EN"),
+    (4, "VDDCR_CPU_PG"),
+    (5, "VDDCR_SOC_PG"),
+    (6, "V0P9_S0_PG"),
+    (7, "V1P1_S0_PG"),
+];
+
+const SEQ_NIC_RBKS: &[(u8, &str)] = &[
+    (0, "V1P5_NIC_EN"),
+    (1, "V1P2_NIC_EN"),
+    (2, "V1P1_NIC_EN"),
+    (3, "V0P96_NIC_EN"),
+    (4, "V1P5_NIC_PG"),
+    (5, "V1P2_NIC_PG"),
+    (6, "V1P1_NIC_PG"),
+    (7, "V0P96_NIC_PG"),
+];
+
+const SEQ_AMD_STATUS: &[(u8, &str)] = &[
+    (0, "PWROK"),
+    (1, "RESET_L"),
+    (2, "THERMTRIP_L"),
+    (3, "SLP_S3_L"),
+    (4, "SLP_S5_L"),
+    (5, "PWRGD_OUT"),
+];
+
+pub const SEQ_REGISTERS: &[SeqRegister] = &[
+    raw("ID0", 0x00),
+    raw("ID1", 0x01),
+    raw("ID2", 0x02),
+    raw("ID3", 0x03),
+    raw("CS0", 0x04),
+    raw("CS1", 0x05),
+    raw("CS2", 0x06),
+    raw("CS3", 0x07),
+    raw("SCRATCHPAD", 0x08),
+    faults("IFR", 0x09, SEQ_IFR),
+    bits("IER", 0x0a, SEQ_IFR),
+    bits("STATUS", 0x0b, SEQ_STATUS),
+    bits("EARLY_PWR_CTRL", 0x0c, SEQ_EARLY_PWR_CTRL),
+    bits("PWR_CTRL", 0x0d, SEQ_PWR_CTRL),
+    bits("NIC_CTRL", 0x0e, SEQ_NIC_CTRL),
+    state("A1SMSTATUS", 0x0f, SEQ_A1SM),
+    state("A0SMSTATUS", 0x10, SEQ_A0SM),
+    state("NICSMSTATUS", 0x11, SEQ_NICSM),
+    bits("EARLY_RBKS", 0x12, SEQ_EARLY_RBKS),
+    bits("A1_RBKS", 0x13, SEQ_A1_RBKS),
+    bits("A0_RBKS_1", 0x14, SEQ_A0_RBKS_1),
+    bits("A0_RBKS_2", 0x15, SEQ_A0_RBKS_2),
+    bits("NIC_RBKS", 0x16, SEQ_NIC_RBKS),
+    bits("AMD_STATUS", 0x17, SEQ_AMD_STATUS),
+];
+
+impl SeqRegister {
+    ///
+    /// Decodes a value of this register:  the names of set bits for a
+    /// register of bits, or the name of the state for a state machine.
+    ///
+    pub fn decode(&self, val: u8) -> String {
+        match self.kind {
+            SeqRegisterKind::Raw => String::new(),
+            SeqRegisterKind::Bits(fields) => {
+                let mut set = vec![];
+
+                for bit in 0..8 {
+                    if val & (1 << bit) == 0 {
+                        continue;
+                    }
+
+                    match fields.iter().find(|(b, _)| *b == bit) {
+                        Some((_, name)) => set.push(name.to_string()),
+                        None => set.push(format!("bit{bit}")),
+                    }
+                }
+
+                set.join(" | ")
+            }
+            SeqRegisterKind::State(states) => {
+                match states.iter().find(|(v, _)| *v == val) {
+                    Some((_, name)) => name.to_string(),
+                    None => format!("<unknown state {val}>"),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,505 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility signature`
+//!
+//! `humility signature` verifies the signature of a signed image, as used
+//! by the LPC55 (and therefore by root-of-trust images).  Such an image
+//! contains a certificate block consisting of a chain of X.509 certificates
+//! and a table of the hashes of up to four root keys, and is followed by an
+//! RSA signature made by the key of the last certificate in the chain.
+//! `humility signature` checks that the chain is valid, that its root is in
+//! the root key table, and that the signature is valid, reporting the
+//! signer:
+//!
+//! ```console
+//! $ humility -a build-rot-carrier-image-a.zip signature
+//!        image => img/final.bin
+//!         type => signed XIP image (0x4)
+//!       length => 97280 bytes (signed), 256 bytes (signature)
+//!   cert[0]    => CN=Oxide RoT Root 1 (RSA-2048, self-signed, valid)
+//!   cert[1]    => CN=Oxide RoT Signer 1 (RSA-2048, valid)
+//!   rkh[0]     => 4c1d5a...b0e3 (root of chain)
+//!   rkh[1]     => 91a06e...17f2
+//!   rkh[2]     => 00000000...0000
+//!   rkh[3]     => 00000000...0000
+//!         rkth => 2dd1f8c9e3b0d5f3db4e2a6e0e0b7d1d86c8a3b2d1d6ad0f8b4f14da4a5e7c22
+//!       signer => CN=Oxide RoT Signer 1
+//!    signature => valid
+//! ```
+//!
+//! By default, the image is `img/final.bin` from the archive; to verify an
+//! image in a file, use `--file` (`-f`).  To verify the image as it exists
+//! in the flash of the attached target, use `--target`; the image is read
+//! from the base of flash as determined by the archive, or from the address
+//! specified with `--address`.
+//!
+//! To check that an image has been signed by a particular key, specify the
+//! key (as a PEM- or DER-encoded public key or certificate) with `--key`
+//! (`-k`); this option may be repeated.  The root of the certificate chain
+//! will be compared against each specified key, and verification will fail
+//! if it matches none of them:
+//!
+//! ```console
+//! $ humility signature --file image.bin --key rot-root-1.pem
+//! ...
+//!   key[0]     => rot-root-1.pem (matches root of chain)
+//!       signer => CN=Oxide RoT Signer 1
+//!    signature => valid
+//! ```
+//!
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::msg;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Command, CommandKind};
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use x509_parser::prelude::*;
+use x509_parser::public_key::PublicKey;
+
+#[derive(Parser, Debug)]
+#[clap(name = "signature", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct SignatureArgs {
+    /// public key or certificate (PEM or DER) that must be the root
+    #[clap(long, short, value_name = "file", multiple_occurrences = true)]
+    key: Vec<String>,
+
+    /// verify the signed image in the specified file
+    #[clap(long, short, value_name = "file", conflicts_with = "target")]
+    file: Option<String>,
+
+    /// verify the signed image in the flash of the attached target
+    #[clap(long)]
+    target: bool,
+
+    /// address of the image on the target
+    #[clap(
+        long, value_name = "address", requires = "target",
+        parse(try_from_str = parse_int::parse)
+    )]
+    address: Option<u32>,
+}
+
+/// Offset of the image length in the image header
+const IMAGE_LENGTH_OFFSET: usize = 0x20;
+
+/// Offset of the image type in the image header
+const IMAGE_TYPE_OFFSET: usize = 0x24;
+
+/// Offset of the offset of the certificate block in the image header
+const IMAGE_CERT_OFFSET: usize = 0x28;
+
+/// Image type of a signed XIP image
+const IMAGE_TYPE_SIGNED_XIP: u32 = 0x4;
+
+/// Magic at the start of a certificate block header
+const CERT_MAGIC: &[u8; 4] = b"cert";
+
+/// Size of a certificate block header
+const CERT_HEADER_SIZE: usize = 32;
+
+/// Number of entries in the root key table
+const RKH_COUNT: usize = 4;
+
+/// Size of an entry in the root key table (a SHA-256 hash)
+const RKH_SIZE: usize = 32;
+
+/// Largest signature that we expect (that of an RSA-4096 key)
+const MAX_SIGNATURE_SIZE: usize = 512;
+
+/// Size of the LPC55's flash, which bounds the size of a signed image
+const FLASH_SIZE: usize = 640 * 1024;
+
+fn word(buf: &[u8], offset: usize) -> Result<u32> {
+    match buf.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_le_bytes(b.try_into().unwrap())),
+        None => bail!("image truncated at offset {offset:#x}"),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+///
+/// The certificate block header, which follows the image proper.
+///
+struct CertHeader {
+    /// Length of the signed portion of the image, including the
+    /// certificate block but not the signature
+    total_length: usize,
+    count: usize,
+    table_length: usize,
+    size: usize,
+}
+
+impl CertHeader {
+    fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() < CERT_HEADER_SIZE || &buf[0..4] != CERT_MAGIC {
+            bail!("certificate block header not found");
+        }
+
+        Ok(Self {
+            size: word(buf, 0x8)? as usize,
+            total_length: word(buf, 0x14)? as usize,
+            count: word(buf, 0x18)? as usize,
+            table_length: word(buf, 0x1c)? as usize,
+        })
+    }
+}
+
+///
+/// Returns the hash of an RSA public key as it appears in the root key
+/// table:  the SHA-256 of its modulus and exponent, each big-endian and
+/// without leading zeroes.
+///
+fn rsa_key_hash(modulus: &[u8], exponent: &[u8]) -> [u8; RKH_SIZE] {
+    let strip = |b: &[u8]| {
+        let nz = b.iter().position(|&b| b != 0).unwrap_or(b.len());
+        b[nz..].to_vec()
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(strip(modulus));
+    hasher.update(strip(exponent));
+    hasher.finalize().into()
+}
+
+fn rsa_key(spki: &SubjectPublicKeyInfo) -> Result<(RsaPublicKey, [u8; 32])> {
+    match spki.parsed() {
+        Ok(PublicKey::RSA(key)) => {
+            let rsa = RsaPublicKey::new(
+                BigUint::from_bytes_be(key.modulus),
+                BigUint::from_bytes_be(key.exponent),
+            )?;
+
+            Ok((rsa, rsa_key_hash(key.modulus, key.exponent)))
+        }
+        Ok(_) => bail!("public key is not an RSA key"),
+        Err(e) => bail!("failed to parse public key: {e}"),
+    }
+}
+
+///
+/// Loads a key specified by the user, returning its hash.  The key may be
+/// a public key or a certificate, and either PEM- or DER-encoded.
+///
+fn load_key(filename: &str) -> Result<[u8; 32]> {
+    let contents = std::fs::read(filename)
+        .with_context(|| format!("failed to read {filename}"))?;
+
+    let der = match x509_parser::pem::parse_x509_pem(&contents) {
+        Ok((_, pem)) => pem.contents,
+        Err(_) => contents,
+    };
+
+    if let Ok((_, cert)) = parse_x509_certificate(&der) {
+        return Ok(rsa_key(cert.public_key())?.1);
+    }
+
+    match SubjectPublicKeyInfo::from_der(&der) {
+        Ok((_, spki)) => Ok(rsa_key(&spki)?.1),
+        Err(_) => bail!("{filename} is not a public key or certificate"),
+    }
+}
+
+///
+/// Reads a signed image, given a function to read a range of it.  We read
+/// the image header to find the certificate block header, and then read the
+/// signed portion of the image -- which in turn tells us the size of the
+/// signature that follows it.
+///
+fn read_image(
+    mut read: impl FnMut(usize, usize) -> Result<Vec<u8>>,
+) -> Result<Vec<u8>> {
+    //
+    // The lengths and offsets that we read are taken from the image itself,
+    // so we refuse any read that would extend beyond the end of flash rather
+    // than trust them.
+    //
+    let mut read = |offset: usize, len: usize| {
+        let end = offset.saturating_add(len);
+
+        if end > FLASH_SIZE {
+            bail!(
+                "image extends to offset {end:#x}, beyond the end of flash \
+                ({FLASH_SIZE:#x} bytes)"
+            );
+        }
+
+        read(offset, len)
+    };
+
+    let header = read(0, IMAGE_CERT_OFFSET + 4)?;
+    let image_type = word(&header, IMAGE_TYPE_OFFSET)? & 0xff;
+
+    if image_type != IMAGE_TYPE_SIGNED_XIP {
+        bail!("image is not signed (image type is {image_type:#x})");
+    }
+
+    let offset = word(&header, IMAGE_CERT_OFFSET)? as usize;
+    let cert = CertHeader::from_bytes(&read(offset, CERT_HEADER_SIZE)?)?;
+
+    let mut image = read(0, cert.total_length)?;
+
+    let table = image
+        .get(offset + cert.size..offset + cert.size + cert.table_length)
+        .ok_or_else(|| anyhow!("certificate table exceeds image"))?;
+
+    let certs = parse_certs(table, cert.count)?;
+
+    let nbytes = match certs.last() {
+        Some(leaf) => rsa_key(leaf.public_key())?.0.size(),
+        None => bail!("certificate block contains no certificates"),
+    };
+
+    if nbytes > MAX_SIGNATURE_SIZE {
+        bail!("signature of {nbytes} bytes exceeds maximum");
+    }
+
+    image.extend(read(cert.total_length, nbytes)?);
+
+    Ok(image)
+}
+
+fn parse_certs(table: &[u8], count: usize) -> Result<Vec<X509Certificate>> {
+    let mut certs = vec![];
+    let mut offset = 0;
+
+    for i in 0..count {
+        let len = word(table, offset)? as usize;
+
+        let der = table
+            .get(offset + 4..offset + 4 + len)
+            .ok_or_else(|| anyhow!("certificate {i} exceeds table"))?;
+
+        let (_, cert) = parse_x509_certificate(der)
+            .map_err(|e| anyhow!("failed to parse certificate {i}: {e}"))?;
+
+        certs.push(cert);
+        offset += 4 + ((len + 3) & !3);
+    }
+
+    Ok(certs)
+}
+
+fn verify(image: &[u8], keys: &[(String, [u8; 32])]) -> Result<()> {
+    let print = |what: &str, val: &str| println!("{:>12} => {}", what, val);
+
+    let image_type = word(image, IMAGE_TYPE_OFFSET)?;
+    let offset = word(image, IMAGE_CERT_OFFSET)? as usize;
+    let cert = CertHeader::from_bytes(&image[offset..])?;
+    let nbytes = image.len() - cert.total_length;
+
+    print("type", &format!("signed XIP image ({:#x})", image_type & 0xff));
+
+    print(
+        "length",
+        &format!(
+            "{} bytes (signed), {nbytes} bytes (signature)",
+            cert.total_length
+        ),
+    );
+
+    if word(image, IMAGE_LENGTH_OFFSET)? as usize > image.len() {
+        msg!("warning: image length in header exceeds image");
+    }
+
+    //
+    // Walk the certificate chain, verifying each certificate against its
+    // issuer (and the root against itself).
+    //
+    let table =
+        &image[offset + cert.size..offset + cert.size + cert.table_length];
+    let certs = parse_certs(table, cert.count)?;
+    let mut valid = true;
+
+    for (i, c) in certs.iter().enumerate() {
+        let issuer = if i == 0 { c } else { &certs[i - 1] };
+        let (key, _) = rsa_key(c.public_key())?;
+
+        let result = c.verify_signature(Some(issuer.public_key()));
+        valid &= result.is_ok();
+
+        print(
+            &format!("cert[{i}]    "),
+            &format!(
+                "{} (RSA-{}{}, {})",
+                c.subject(),
+                key.size() * 8,
+                if i == 0 { ", self-signed" } else { "" },
+                match result {
+                    Ok(_) => "valid".to_string(),
+                    Err(e) => format!("INVALID: {e}"),
+                }
+            ),
+        );
+    }
+
+    //
+    // The root key table follows the certificate table; the root of the
+    // chain must be one of its entries.
+    //
+    let (_, root) = rsa_key(certs[0].public_key())?;
+    let rkh_base = offset + cert.size + cert.table_length;
+
+    let rkh = image
+        .get(rkh_base..rkh_base + RKH_COUNT * RKH_SIZE)
+        .ok_or_else(|| anyhow!("root key table exceeds image"))?;
+
+    let mut found = false;
+
+    for (i, entry) in rkh.chunks(RKH_SIZE).enumerate() {
+        let matches = entry == root;
+        found |= matches;
+
+        print(
+            &format!("rkh[{i}]     "),
+            &format!(
+                "{}{}",
+                hex(entry),
+                if matches { " (root of chain)" } else { "" }
+            ),
+        );
+    }
+
+    print("rkth", &hex(&Sha256::digest(rkh)));
+
+    if !found {
+        msg!("root of certificate chain is not in root key table");
+        valid = false;
+    }
+
+    for (i, (name, hash)) in keys.iter().enumerate() {
+        print(
+            &format!("key[{i}]     "),
+            &format!(
+                "{name}{}",
+                if *hash == root { " (matches root of chain)" } else { "" }
+            ),
+        );
+    }
+
+    if !keys.is_empty() && !keys.iter().any(|(_, hash)| *hash == root) {
+        msg!("root of certificate chain matches none of the specified keys");
+        valid = false;
+    }
+
+    //
+    // Finally, verify the signature over the signed portion of the image
+    // with the key of the last certificate in the chain.
+    //
+    let leaf = certs.last().unwrap();
+    let (key, _) = rsa_key(leaf.public_key())?;
+    let digest = Sha256::digest(&image[..cert.total_length]);
+    let signature = &image[cert.total_length..];
+
+    print("signer", &leaf.subject().to_string());
+
+    match key.verify(Pkcs1v15Sign::new::<Sha256>(), &digest, signature) {
+        Ok(_) => print("signature", "valid"),
+        Err(e) => {
+            print("signature", &format!("INVALID: {e}"));
+            valid = false;
+        }
+    }
+
+    if !valid {
+        bail!("image failed verification");
+    }
+
+    Ok(())
+}
+
+fn signature(context: &mut ExecutionContext) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = SignatureArgs::try_parse_from(subargs)?;
+    let hubris = context.archive.as_ref().unwrap();
+
+    let keys = subargs
+        .key
+        .iter()
+        .map(|k| Ok((k.clone(), load_key(k)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let image = if let Some(ref file) = subargs.file {
+        let contents = std::fs::read(file)
+            .with_context(|| format!("failed to read {file}"))?;
+
+        println!("{:>12} => {}", "image", file);
+
+        read_image(|offset, len| match contents.get(offset..offset + len) {
+            Some(buf) => Ok(buf.to_vec()),
+            None => bail!("image truncated at offset {:#x}", offset + len),
+        })?
+    } else if subargs.target {
+        let base = match subargs.address {
+            Some(address) => address,
+            None => {
+                if !hubris.loaded() {
+                    bail!("must specify an archive or --address");
+                }
+
+                let map = HubrisFlashMap::new(hubris)?;
+
+                match map.regions.keys().next() {
+                    Some(base) => *base,
+                    None => bail!("archive contains no flash"),
+                }
+            }
+        };
+
+        let mut c = humility_cmd::attach_live(&context.cli, hubris)?;
+        let core = c.as_mut();
+
+        println!("{:>12} => target flash at {base:#x}", "image");
+
+        core.op_start()?;
+
+        let rval = read_image(|offset, len| {
+            let mut buf = vec![0u8; len];
+
+            for (i, chunk) in buf.chunks_mut(1024).enumerate() {
+                let addr = base + (offset + i * 1024) as u32;
+                core.read_8(addr, chunk)?;
+            }
+
+            Ok(buf)
+        });
+
+        core.op_done()?;
+        rval?
+    } else {
+        if !hubris.loaded() {
+            bail!("must specify an archive, --file, or --target");
+        }
+
+        let contents = hubris
+            .read_file("img/final.bin")?
+            .ok_or_else(|| anyhow!("could not find final.bin in archive"))?;
+
+        println!("{:>12} => img/final.bin", "image");
+
+        read_image(|offset, len| match contents.get(offset..offset + len) {
+            Some(buf) => Ok(buf.to_vec()),
+            None => bail!("image truncated at offset {:#x}", offset + len),
+        })?
+    };
+
+    verify(&image, &keys)
+}
+
+pub fn init() -> Command {
+    Command {
+        app: SignatureArgs::command(),
+        name: "signature",
+        run: signature,
+        kind: CommandKind::Unattached { archive: Archive::Optional },
+    }
+}
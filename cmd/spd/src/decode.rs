@@ -0,0 +1,656 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Symbolic decoding of DDR4 (JESD21-C Annex L) and DDR5 (JESD400-5) SPD
+//! contents.  Only the fields of general interest are decoded:  the module
+//! type and organization, the timings that determine its speed grade, and
+//! the manufacturing information.
+//!
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::fmt;
+
+/// Data rates of JEDEC speed bins, in MT/s
+const SPEED_BINS: &[u32] = &[
+    1600, 1866, 2133, 2400, 2666, 2933, 3200, 3600, 4000, 4400, 4800, 5200,
+    5600, 6000, 6400, 6800, 7200, 7600, 8000, 8400, 8800,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum MemoryType {
+    DDR4,
+    DDR5,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Timings {
+    /// Minimum clock cycle time, in picoseconds
+    pub tck_ps: u32,
+    /// Minimum CAS latency time, in picoseconds
+    pub taa_ps: u32,
+    /// Minimum RAS to CAS delay, in picoseconds
+    pub trcd_ps: u32,
+    /// Minimum row precharge delay, in picoseconds
+    pub trp_ps: u32,
+    /// Minimum active to precharge delay, in picoseconds
+    pub tras_ps: u32,
+    /// Minimum active to active/refresh delay, in picoseconds
+    pub trc_ps: u32,
+}
+
+impl Timings {
+    /// Returns the data rate in MT/s, snapped to the nearest speed bin
+    pub fn speed(&self) -> u32 {
+        if self.tck_ps == 0 {
+            return 0;
+        }
+
+        let rate = 2_000_000 / self.tck_ps;
+
+        SPEED_BINS
+            .iter()
+            .copied()
+            .min_by_key(|&bin| (bin as i64 - rate as i64).abs())
+            .filter(|&bin| (bin as i64 - rate as i64).abs() < 50)
+            .unwrap_or(rate)
+    }
+
+    /// Returns the number of clocks for the given time at the minimum
+    /// clock cycle time
+    fn clocks(&self, ps: u32) -> u32 {
+        if self.tck_ps == 0 {
+            return 0;
+        }
+
+        //
+        // JEDEC rounding:  allow for a small guard band before rounding up,
+        // to accommodate times that are specified as truncated values.
+        //
+        (ps * 1000 / self.tck_ps + 974) / 1000
+    }
+
+    /// Returns CL-tRCD-tRP in clocks
+    pub fn latencies(&self) -> (u32, u32, u32) {
+        (
+            self.clocks(self.taa_ps),
+            self.clocks(self.trcd_ps),
+            self.clocks(self.trp_ps),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Manufacturing {
+    pub manufacturer: Option<String>,
+    pub dram_manufacturer: Option<String>,
+    pub part: String,
+    pub serial: String,
+    pub location: u8,
+    pub year: u16,
+    pub week: u8,
+    pub revision: u8,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Spd {
+    pub memory_type: MemoryType,
+    pub spd_revision: String,
+    pub module_type: String,
+    /// Capacity of the module, in MiB
+    pub capacity_mib: u64,
+    /// Density of each SDRAM die, in Mbit
+    pub sdram_density_mbit: u32,
+    pub sdram_width: u32,
+    pub dies_per_package: u32,
+    pub ranks: u32,
+    pub bus_width: u32,
+    pub ecc_width: u32,
+    pub speed: u32,
+    pub timings: Timings,
+    /// Manufacturing information, if present in the data
+    pub manufacturing: Option<Manufacturing>,
+}
+
+fn from_bcd(val: u8) -> u8 {
+    (val >> 4) * 10 + (val & 0xf)
+}
+
+fn jedec(lsb: u8, msb: u8) -> Option<String> {
+    jep106::JEP106Code::new(lsb & 0x7f, msb & 0x7f).get().map(String::from)
+}
+
+fn ascii(buf: &[u8]) -> String {
+    String::from_utf8_lossy(buf).trim_end_matches(['\0', ' ']).to_string()
+}
+
+/// Medium and fine timebase time for DDR4, in picoseconds
+fn ddr4_time(mtb: u32, ftb: u8) -> u32 {
+    (mtb as i64 * 125 + ftb as i8 as i64) as u32
+}
+
+fn ddr4_module_type(val: u8) -> &'static str {
+    match val & 0xf {
+        0b0001 => "RDIMM",
+        0b0010 => "UDIMM",
+        0b0011 => "SO-DIMM",
+        0b0100 => "LRDIMM",
+        0b0101 => "Mini-RDIMM",
+        0b0110 => "Mini-UDIMM",
+        0b1000 => "72b-SO-RDIMM",
+        0b1001 => "72b-SO-UDIMM",
+        0b1100 => "16b-SO-DIMM",
+        0b1101 => "32b-SO-DIMM",
+        _ => "unknown",
+    }
+}
+
+fn ddr5_module_type(val: u8) -> &'static str {
+    match val & 0xf {
+        0b0001 => "RDIMM",
+        0b0010 => "UDIMM",
+        0b0011 => "SO-DIMM",
+        0b0100 => "LRDIMM",
+        0b0111 => "MRDIMM",
+        0b1010 => "DDIMM",
+        0b1011 => "Solder down",
+        _ => "unknown",
+    }
+}
+
+fn decode_ddr4(buf: &[u8]) -> Result<Spd> {
+    if buf.len() < 256 {
+        bail!("DDR4 SPD is short ({} bytes)", buf.len());
+    }
+
+    //
+    // Byte 4: SDRAM density and banks
+    //
+    let sdram_density_mbit = match buf[4] & 0xf {
+        n @ 0b0000..=0b0111 => 256 << n,
+        0b1000 => 12 * 1024,
+        0b1001 => 24 * 1024,
+        n => bail!("invalid DDR4 SDRAM density {n:#x}"),
+    };
+
+    //
+    // Byte 6: primary SDRAM package type, including die count
+    //
+    let dies_per_package = ((buf[6] >> 4) & 0b111) as u32 + 1;
+    let multiload = buf[6] & 0b11 == 0b10;
+
+    //
+    // Byte 12: module organization; byte 13: bus width
+    //
+    let sdram_width = 4 << (buf[12] & 0b111);
+    let ranks = ((buf[12] >> 3) & 0b111) as u32 + 1;
+    let bus_width = 8 << (buf[13] & 0b111);
+    let ecc_width = if (buf[13] >> 3) & 0b11 == 0b01 { 8 } else { 0 };
+
+    //
+    // Each rank of a 3DS (multi-load) package consists of multiple logical
+    // ranks, each of which contributes to capacity.
+    //
+    let logical = if multiload { dies_per_package } else { 1 };
+
+    let capacity_mib = (sdram_density_mbit as u64 / 8)
+        * (bus_width / sdram_width) as u64
+        * (ranks * logical) as u64;
+
+    //
+    // Bytes 18-29 are in units of the medium timebase (125 ps) with fine
+    // timebase (1 ps) corrections in bytes 120-125.
+    //
+    if buf[17] != 0 {
+        bail!("unsupported DDR4 timebases {:#x}", buf[17]);
+    }
+
+    let timings = Timings {
+        tck_ps: ddr4_time(buf[18] as u32, buf[125]),
+        taa_ps: ddr4_time(buf[24] as u32, buf[123]),
+        trcd_ps: ddr4_time(buf[25] as u32, buf[122]),
+        trp_ps: ddr4_time(buf[26] as u32, buf[121]),
+        tras_ps: ddr4_time(((buf[27] as u32 & 0xf) << 8) | buf[28] as u32, 0),
+        trc_ps: ddr4_time(
+            ((buf[27] as u32 >> 4) << 8) | buf[29] as u32,
+            buf[120],
+        ),
+    };
+
+    //
+    // Manufacturing information is in the upper half of the SPD.
+    //
+    let manufacturing = if buf.len() >= 352 {
+        let m = &buf[320..352];
+
+        Some(Manufacturing {
+            manufacturer: jedec(m[0], m[1]),
+            location: m[2],
+            year: 2000 + from_bcd(m[3]) as u16,
+            week: from_bcd(m[4]),
+            serial: format!("{:02x}{:02x}{:02x}{:02x}", m[5], m[6], m[7], m[8]),
+            part: ascii(&m[9..29]),
+            revision: m[29],
+            dram_manufacturer: jedec(m[30], m[31]),
+        })
+    } else {
+        None
+    };
+
+    Ok(Spd {
+        memory_type: MemoryType::DDR4,
+        spd_revision: format!("{}.{}", buf[1] >> 4, buf[1] & 0xf),
+        module_type: ddr4_module_type(buf[3]).to_string(),
+        capacity_mib,
+        sdram_density_mbit,
+        sdram_width,
+        dies_per_package,
+        ranks,
+        bus_width,
+        ecc_width,
+        speed: timings.speed(),
+        timings,
+        manufacturing,
+    })
+}
+
+fn decode_ddr5(buf: &[u8]) -> Result<Spd> {
+    if buf.len() < 256 {
+        bail!("DDR5 SPD is short ({} bytes)", buf.len());
+    }
+
+    let word = |offs: usize| u16::from_le_bytes([buf[offs], buf[offs + 1]]);
+
+    //
+    // Byte 4: first SDRAM density and package
+    //
+    let sdram_density_mbit = 1024
+        * match buf[4] & 0b1_1111 {
+            0b00001 => 4,
+            0b00010 => 8,
+            0b00011 => 12,
+            0b00100 => 16,
+            0b00101 => 24,
+            0b00110 => 32,
+            0b00111 => 48,
+            0b01000 => 64,
+            n => bail!("invalid DDR5 SDRAM density {n:#x}"),
+        };
+
+    let dies_per_package = match buf[4] >> 5 {
+        0b000 => 1,
+        0b010 => 2,
+        0b011 => 4,
+        0b100 => 8,
+        0b101 => 16,
+        n => bail!("invalid DDR5 die count {n:#x}"),
+    };
+
+    //
+    // Byte 6: first SDRAM I/O width; byte 234: module organization; byte
+    // 235: memory channel bus width
+    //
+    let sdram_width = 4 << (buf[6] >> 5);
+    let ranks = ((buf[234] >> 3) & 0b111) as u32 + 1;
+    let subchannel_width = 8 << (buf[235] & 0b111);
+    let subchannels = ((buf[235] >> 5) & 0b11) as u32 + 1;
+    let ecc_width = match (buf[235] >> 3) & 0b11 {
+        0b01 => 4,
+        0b10 => 8,
+        _ => 0,
+    } * subchannels;
+    let bus_width = subchannel_width * subchannels;
+
+    let capacity_mib = (sdram_density_mbit as u64 / 8)
+        * dies_per_package as u64
+        * (bus_width / sdram_width) as u64
+        * ranks as u64;
+
+    //
+    // DDR5 timings are in picoseconds.
+    //
+    let timings = Timings {
+        tck_ps: word(20) as u32,
+        taa_ps: word(30) as u32,
+        trcd_ps: word(32) as u32,
+        trp_ps: word(34) as u32,
+        tras_ps: word(36) as u32,
+        trc_ps: word(38) as u32,
+    };
+
+    //
+    // Manufacturing information is in bytes 512-639, which we will only
+    // have if we have the full SPD.
+    //
+    let manufacturing = if buf.len() >= 554 {
+        let m = &buf[512..554];
+
+        Some(Manufacturing {
+            manufacturer: jedec(m[0], m[1]),
+            location: m[2],
+            year: 2000 + from_bcd(m[3]) as u16,
+            week: from_bcd(m[4]),
+            serial: format!("{:02x}{:02x}{:02x}{:02x}", m[5], m[6], m[7], m[8]),
+            part: ascii(&m[9..39]),
+            revision: m[39],
+            dram_manufacturer: jedec(m[40], m[41]),
+        })
+    } else {
+        None
+    };
+
+    Ok(Spd {
+        memory_type: MemoryType::DDR5,
+        spd_revision: format!("{}.{}", buf[1] >> 4, buf[1] & 0xf),
+        module_type: ddr5_module_type(buf[3]).to_string(),
+        capacity_mib,
+        sdram_density_mbit,
+        sdram_width,
+        dies_per_package,
+        ranks,
+        bus_width,
+        ecc_width,
+        speed: timings.speed(),
+        timings,
+        manufacturing,
+    })
+}
+
+/// Decodes SPD contents, as determined by the DRAM device type in byte 2
+pub fn decode(buf: &[u8]) -> Result<Spd> {
+    if buf.len() < 3 {
+        bail!("SPD is short ({} bytes)", buf.len());
+    }
+
+    match buf[2] {
+        0x0c => decode_ddr4(buf),
+        0x12 => decode_ddr5(buf),
+        kind => bail!("unsupported DRAM device type {kind:#x}"),
+    }
+}
+
+impl fmt::Display for Spd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let capacity = if self.capacity_mib >= 1024 {
+            format!("{} GiB", self.capacity_mib / 1024)
+        } else {
+            format!("{} MiB", self.capacity_mib)
+        };
+
+        let (cl, trcd, trp) = self.timings.latencies();
+        let density = if self.sdram_density_mbit >= 1024 {
+            format!("{} Gbit", self.sdram_density_mbit / 1024)
+        } else {
+            format!("{} Mbit", self.sdram_density_mbit)
+        };
+
+        let ns = |ps: u32| format!("{:.3} ns", ps as f64 / 1000.0);
+
+        writeln!(
+            f,
+            "     {:<18} {:?}-{} {} (SPD revision {})",
+            "type",
+            self.memory_type,
+            self.speed,
+            self.module_type,
+            self.spd_revision
+        )?;
+
+        writeln!(f, "     {:<18} {capacity}", "capacity")?;
+
+        writeln!(
+            f,
+            "     {:<18} {} rank{}, x{} {density} SDRAM{}",
+            "organization",
+            self.ranks,
+            if self.ranks == 1 { "" } else { "s" },
+            self.sdram_width,
+            if self.dies_per_package > 1 {
+                format!(" ({} dies per package)", self.dies_per_package)
+            } else {
+                "".to_string()
+            }
+        )?;
+
+        writeln!(
+            f,
+            "     {:<18} {} bits{}",
+            "bus width",
+            self.bus_width,
+            if self.ecc_width > 0 {
+                format!(" + {} bits ECC", self.ecc_width)
+            } else {
+                "".to_string()
+            }
+        )?;
+
+        writeln!(f, "     {:<18} {cl}-{trcd}-{trp}", "CL-tRCD-tRP")?;
+        writeln!(f, "     {:<18} {}", "tCKmin", ns(self.timings.tck_ps))?;
+        writeln!(f, "     {:<18} {}", "tAAmin", ns(self.timings.taa_ps))?;
+        writeln!(f, "     {:<18} {}", "tRCDmin", ns(self.timings.trcd_ps))?;
+        writeln!(f, "     {:<18} {}", "tRPmin", ns(self.timings.trp_ps))?;
+        writeln!(f, "     {:<18} {}", "tRASmin", ns(self.timings.tras_ps))?;
+        writeln!(f, "     {:<18} {}", "tRCmin", ns(self.timings.trc_ps))?;
+
+        match &self.manufacturing {
+            Some(m) => {
+                let unknown = "<unknown>";
+
+                writeln!(
+                    f,
+                    "     {:<18} {}",
+                    "manufacturer",
+                    m.manufacturer.as_deref().unwrap_or(unknown)
+                )?;
+                writeln!(
+                    f,
+                    "     {:<18} {}",
+                    "DRAM manufacturer",
+                    m.dram_manufacturer.as_deref().unwrap_or(unknown)
+                )?;
+                writeln!(f, "     {:<18} {}", "part", m.part)?;
+                writeln!(f, "     {:<18} {:#04x}", "revision", m.revision)?;
+                writeln!(f, "     {:<18} {}", "serial", m.serial)?;
+                writeln!(
+                    f,
+                    "     {:<18} week {} of {}, location {}",
+                    "manufactured", m.week, m.year, m.location
+                )?;
+            }
+            None => {
+                writeln!(f, "     {:<18} <not present>", "manufacturing")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn manufacturing(m: &mut [u8], jedec: (u8, u8), part: &str) {
+        m[0] = jedec.0;
+        m[1] = jedec.1;
+        m[2] = 0x03;
+        m[3] = 0x21;
+        m[4] = 0x14;
+        m[5..9].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        m[9..9 + part.len()].copy_from_slice(part.as_bytes());
+    }
+
+    //
+    // A 16 GiB DDR4-3200 RDIMM (single rank of x4 8 Gbit SDRAM, with ECC),
+    // with timings of 22-22-22.
+    //
+    fn ddr4() -> Vec<u8> {
+        let mut buf = vec![0u8; 384];
+        buf[1] = 0x12;
+        buf[2] = 0x0c;
+        buf[3] = 0x01;
+        buf[4] = 0x85;
+        buf[12] = 0x00;
+        buf[13] = 0x0b;
+        buf[18] = 5;
+        buf[24] = 110;
+        buf[25] = 110;
+        buf[26] = 110;
+        buf[27] = 0x11;
+        buf[28] = 0x00;
+        buf[29] = 0x6e;
+        buf[125] = 0;
+
+        let m = &mut buf[320..352];
+        manufacturing(m, (0x80, 0xce), "M393A2K40DB3-CWE    ");
+        m[29] = 0x41;
+        m[30] = 0x80;
+        m[31] = 0xce;
+
+        buf
+    }
+
+    //
+    // A 32 GiB DDR5-4800 RDIMM (two ranks of x8 16 Gbit SDRAM, with two
+    // 32-bit subchannels each with 4 bits of ECC), with timings of 40-40-40.
+    //
+    fn ddr5() -> Vec<u8> {
+        let mut buf = vec![0u8; 1024];
+        buf[1] = 0x10;
+        buf[2] = 0x12;
+        buf[3] = 0x01;
+        buf[4] = 0x04;
+        buf[6] = 0x20;
+        buf[234] = 0x08;
+        buf[235] = 0x2a;
+
+        for (offs, ps) in
+            [(20, 416), (30, 16640), (32, 16640), (34, 16640), (36, 32000)]
+        {
+            buf[offs..offs + 2].copy_from_slice(&(ps as u16).to_le_bytes());
+        }
+
+        buf[38..40].copy_from_slice(&48640u16.to_le_bytes());
+
+        let m = &mut buf[512..554];
+        manufacturing(m, (0x80, 0x2c), "MTC20F2085S1RC48BA1");
+        m[40] = 0x80;
+        m[41] = 0x2c;
+
+        buf
+    }
+
+    #[test]
+    fn decode_ddr4() {
+        let spd = decode(&ddr4()).unwrap();
+
+        assert_eq!(spd.memory_type, MemoryType::DDR4);
+        assert_eq!(spd.spd_revision, "1.2");
+        assert_eq!(spd.module_type, "RDIMM");
+        assert_eq!(spd.capacity_mib, 16 * 1024);
+        assert_eq!(spd.sdram_density_mbit, 8 * 1024);
+        assert_eq!(spd.sdram_width, 4);
+        assert_eq!(spd.ranks, 1);
+        assert_eq!((spd.bus_width, spd.ecc_width), (64, 8));
+        assert_eq!(spd.speed, 3200);
+        assert_eq!(spd.timings.latencies(), (22, 22, 22));
+        assert_eq!(spd.timings.tras_ps, 32000);
+        assert_eq!(spd.timings.trc_ps, 45750);
+
+        let m = spd.manufacturing.unwrap();
+        assert_eq!(m.manufacturer.as_deref(), Some("Samsung"));
+        assert_eq!(m.dram_manufacturer.as_deref(), Some("Samsung"));
+        assert_eq!(m.part, "M393A2K40DB3-CWE");
+        assert_eq!(m.serial, "12345678");
+        assert_eq!((m.year, m.week, m.location), (2021, 14, 3));
+    }
+
+    #[test]
+    fn decode_ddr4_fine_timebase() {
+        //
+        // DDR4-2666 has a tCK of 750 ps:  6 * 125 ps, less 0 ps -- but
+        // DDR4-2933 has a tCK of 682 ps, or 6 * 125 ps, less 68 ps.
+        //
+        let mut buf = ddr4();
+        buf[18] = 6;
+        buf[125] = -68i8 as u8;
+
+        let spd = decode(&buf).unwrap();
+        assert_eq!(spd.timings.tck_ps, 682);
+        assert_eq!(spd.speed, 2933);
+    }
+
+    #[test]
+    fn decode_ddr4_3ds() {
+        //
+        // A 3DS package of four dies, each of which is a logical rank.
+        //
+        let mut buf = ddr4();
+        buf[6] = 0b1011_0010;
+
+        let spd = decode(&buf).unwrap();
+        assert_eq!(spd.dies_per_package, 4);
+        assert_eq!(spd.capacity_mib, 64 * 1024);
+    }
+
+    #[test]
+    fn decode_ddr5() {
+        let spd = decode(&ddr5()).unwrap();
+
+        assert_eq!(spd.memory_type, MemoryType::DDR5);
+        assert_eq!(spd.spd_revision, "1.0");
+        assert_eq!(spd.module_type, "RDIMM");
+        assert_eq!(spd.capacity_mib, 32 * 1024);
+        assert_eq!(spd.sdram_density_mbit, 16 * 1024);
+        assert_eq!(spd.sdram_width, 8);
+        assert_eq!(spd.ranks, 2);
+        assert_eq!((spd.bus_width, spd.ecc_width), (64, 8));
+        assert_eq!(spd.speed, 4800);
+        assert_eq!(spd.timings.latencies(), (40, 40, 40));
+
+        let m = spd.manufacturing.unwrap();
+        assert_eq!(m.manufacturer.as_deref(), Some("Micron Technology"));
+        assert_eq!(m.part, "MTC20F2085S1RC48BA1");
+    }
+
+    #[test]
+    fn decode_without_manufacturing() {
+        let spd = decode(&ddr4()[..256]).unwrap();
+        assert!(spd.manufacturing.is_none());
+
+        let spd = decode(&ddr5()[..512]).unwrap();
+        assert!(spd.manufacturing.is_none());
+    }
+
+    #[test]
+    fn decode_invalid() {
+        assert!(decode(&[0x23, 0x12]).is_err());
+        assert!(decode(&ddr4()[..128]).is_err());
+
+        let mut buf = ddr4();
+        buf[2] = 0x0b;
+        assert!(decode(&buf).is_err());
+
+        let mut buf = ddr4();
+        buf[4] = 0x0f;
+        assert!(decode(&buf).is_err());
+
+        let mut buf = ddr5();
+        buf[4] = 0x1f;
+        assert!(decode(&buf).is_err());
+    }
+
+    #[test]
+    fn display() {
+        let out = decode(&ddr4()).unwrap().to_string();
+
+        assert!(out.contains("DDR4-3200 RDIMM (SPD revision 1.2)"));
+        assert!(out.contains("16 GiB"));
+        assert!(out.contains("1 rank, x4 8 Gbit SDRAM"));
+        assert!(out.contains("64 bits + 8 bits ECC"));
+        assert!(out.contains("22-22-22"));
+        assert!(out.contains("week 14 of 2021, location 3"));
+    }
+}
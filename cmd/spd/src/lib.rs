@@ -96,6 +96,43 @@
 //!     0x1f0 |  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 | ................
 //! ```
 //!
+//! To decode the JEDEC-defined fields of the SPD -- module type, organization,
+//! timings and manufacturing information -- rather than simply displaying them
+//! in hex, use the `--decode` (`-D`) option.  Both DDR4 and DDR5 SPDs are
+//! supported:
+//!
+//! ```console
+//! % humility spd --bus mid --address 5 --decode
+//! humility: attached via ST-Link V3
+//! ADDR MANUFACTURER              PART                 WEEK YEAR
+//!    5 Micron Technology         36ASF8G72PZ-3G2E1      44 2021
+//!    |
+//!      type               DDR4-3200 RDIMM (SPD revision 1.2)
+//!      capacity           64 GiB
+//!      organization       2 ranks, x4 16 Gbit SDRAM
+//!      bus width          64 bits + 8 bits ECC
+//!      CL-tRCD-tRP        22-22-22
+//!      tCKmin             0.625 ns
+//!      tAAmin             13.750 ns
+//!      tRCDmin            13.750 ns
+//!      tRPmin             13.750 ns
+//!      tRASmin            32.000 ns
+//!      tRCmin             45.750 ns
+//!      manufacturer       Micron Technology
+//!      DRAM manufacturer  Micron Technology
+//!      part               36ASF8G72PZ-3G2E1
+//!      revision           0x31
+//!      serial             3252c57e
+//!      manufactured       week 44 of 2021, location 6
+//! ```
+//!
+//! Note that the manufacturing information of a DDR5 SPD lies beyond the first
+//! 512 bytes, and is therefore not displayed for DDR5 devices.
+//!
+//! To instead emit the decoded contents as JSON (e.g., for consumption by other
+//! tooling), use the `--json` option.  Like `--decode`, this can be used both
+//! on cached data and when reading devices directly.
+//!
 //! To dump a given SPD to a file, additionally provide the `--output` (`-o`)
 //! option and specify a desired output file:
 //!
@@ -109,8 +146,9 @@
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
 use humility_hiffy::*;
-use humility_i2c::I2cArgs;
+use humility_i2c::{I2cArgs, I2cRetryPolicy};
 use humility_log::msg;
+use serde::Serialize;
 use std::fs::File;
 use std::io::Write;
 use std::str;
@@ -119,6 +157,8 @@ use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
 use hif::*;
 
+mod decode;
+
 #[derive(Parser, Debug)]
 #[clap(name = "spd", about = env!("CARGO_PKG_DESCRIPTION"))]
 struct SpdArgs {
@@ -168,10 +208,27 @@ struct SpdArgs {
         conflicts_with = "verbose"
     )]
     output: Option<String>,
+
+    /// decode SPD contents symbolically
+    #[clap(long, short = 'D', conflicts_with = "output")]
+    decode: bool,
+
+    /// emit decoded SPD contents as JSON
+    #[clap(long, conflicts_with_all = &["verbose", "output", "decode"])]
+    json: bool,
 }
 
 const SPD_SIZE: usize = 512;
 
+#[derive(Serialize)]
+struct DecodedSpd {
+    address: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spd: Option<decode::Spd>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
 fn from_bcd(val: u8) -> u8 {
     (val >> 4) * 10 + (val & 0xf)
 }
@@ -181,6 +238,7 @@ fn dump_spd(
     addr: u8,
     buf: &[u8],
     header: bool,
+    decoded: &mut Vec<DecodedSpd>,
 ) -> Result<()> {
     use spd::Offset;
 
@@ -207,12 +265,23 @@ fn dump_spd(
         return Ok(());
     }
 
+    if subargs.json {
+        let (spd, error) = match decode::decode(buf) {
+            Ok(spd) => (Some(spd), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+
+        decoded.push(DecodedSpd { address: addr, spd, error });
+        return Ok(());
+    }
+
     let part = str::from_utf8(
         &buf[Offset::PartNumberBase.to_usize()
             ..=Offset::PartNumberLimit.to_usize()],
     );
 
-    if header || subargs.address.is_some() || subargs.verbose {
+    if header || subargs.address.is_some() || subargs.verbose || subargs.decode
+    {
         println!(
             "{:4} {:25} {:20} {:4} {:4}",
             "ADDR", "MANUFACTURER", "PART", "WEEK", "YEAR"
@@ -228,6 +297,19 @@ fn dump_spd(
         2000 + (year as u16),
     );
 
+    if subargs.decode {
+        println!("   |");
+
+        match decode::decode(buf) {
+            Ok(spd) => print!("{spd}"),
+            Err(err) => println!("     <failed to decode: {err}>"),
+        }
+
+        if !subargs.verbose {
+            println!();
+        }
+    }
+
     if !subargs.verbose {
         return Ok(());
     }
@@ -266,6 +348,14 @@ fn dump_spd(
     Ok(())
 }
 
+fn print_json(subargs: &SpdArgs, decoded: &[DecodedSpd]) -> Result<()> {
+    if subargs.json {
+        println!("{}", serde_json::to_string_pretty(decoded)?);
+    }
+
+    Ok(())
+}
+
 // Assumes that we already have pushed on the stack our controller/port/mux
 fn set_page(ops: &mut Vec<Op>, i2c_write: &HiffyFunction, page: u8) {
     let dev =
@@ -320,6 +410,7 @@ fn spd(context: &mut ExecutionContext) -> Result<()> {
         rval?;
 
         let mut header = true;
+        let mut decoded = vec![];
 
         for addr in 0..nspd {
             let offs = addr * SPD_SIZE;
@@ -329,7 +420,7 @@ fn spd(context: &mut ExecutionContext) -> Result<()> {
                 continue;
             }
 
-            dump_spd(&subargs, addr as u8, data, header)?;
+            dump_spd(&subargs, addr as u8, data, header, &mut decoded)?;
             header = false;
         }
 
@@ -337,7 +428,7 @@ fn spd(context: &mut ExecutionContext) -> Result<()> {
             msg!("all SPD data is empty");
         }
 
-        return Ok(());
+        return print_json(&subargs, &decoded);
     }
 
     if core.is_dump() {
@@ -393,8 +484,18 @@ fn spd(context: &mut ExecutionContext) -> Result<()> {
 
     ops.push(Op::Done);
 
-    let results = context.run(core, ops.as_slice(), None)?;
+    //
+    // Selecting a page is idempotent, so our programs (which select a page
+    // and then read) can be retried.
+    //
+    let retry = [0, 1].iter().fold(I2cRetryPolicy::default(), |retry, &p| {
+        let page = spd::Function::PageAddress(spd::Page(p));
+        retry.idempotent(page.to_device_code(), None)
+    });
+    let results =
+        retry.run(&mut context, core, ops.as_slice(), None, &i2c_read)?;
     let mut header = true;
+    let mut decoded = vec![];
 
     if let Err(err) = results[0] {
         bail!("failed to set page to 0: {}", i2c_write.strerror(err));
@@ -451,7 +552,13 @@ fn spd(context: &mut ExecutionContext) -> Result<()> {
 
             ops.push(Op::Done);
 
-            let results = context.run(core, ops.as_slice(), None)?;
+            let results = retry.run(
+                &mut context,
+                core,
+                ops.as_slice(),
+                None,
+                &i2c_read,
+            )?;
 
             //
             // If that succeeded, we'll have four buffers that should add up
@@ -474,12 +581,12 @@ fn spd(context: &mut ExecutionContext) -> Result<()> {
                 bail!("bad SPD length ({} bytes): {results:?}", buf.len());
             }
 
-            dump_spd(&subargs, addr, &buf, header)?;
+            dump_spd(&subargs, addr, &buf, header, &mut decoded)?;
             header = false;
         }
     }
 
-    Ok(())
+    print_json(&subargs, &decoded)
 }
 
 pub fn init() -> Command {
@@ -31,6 +31,36 @@
 //! 0x00000010 | 00 00 00 00 ff ff ff 06 12 00 00 00 06          | .............
 //! ```
 //!
+//! Note that a read is a full-duplex transfer:  the bytes specified with
+//! `--write` are shifted out as the first bytes are shifted in.  Bytes to
+//! write can also be taken from a file with `--write-file`, and bytes read
+//! can be written to a file (rather than displayed) with `--output` (`-o`).
+//! To write and display words rather than bytes, use `--word-size` to
+//! specify the number of bytes in each word; words are shifted most
+//! significant byte first.
+//!
+//! To list the SPI controllers and the devices attached to each of them (as
+//! defined in the application TOML), use `--list` (`-l`):
+//!
+//! ```console
+//! $ humility spi --list
+//! CONTROLLER TASK             DEVICE NAME             MUX        DESCRIPTION
+//! spi2       spi2_driver           0 ksz8463          port_i     -
+//! spi4       spi4_driver           0 local_flash      port_e     -
+//! ```
+//!
+//! The device can be specified via `--device` (`-D`) either by its index or
+//! by its name; if specified by name, the peripheral is determined from the
+//! application TOML:
+//!
+//! ```console
+//! $ humility spi -D ksz8463 --write 0x1,0x0,0x0 --read -n 4 --discard 3
+//! humility: attached to 0483:374e:003C00174741500520383733 via ST-Link V3
+//! humility: SPI master is spi2_driver
+//!              \/  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f
+//! 0x00000000 | 01                                              | .
+//! ```
+//!
 
 use humility::hubris::*;
 use humility_cli::{ExecutionContext, Subcommand};
@@ -38,6 +68,7 @@ use humility_cmd::{Archive, Attach, Command, CommandKind, Dumper, Validate};
 use humility_hiffy::*;
 
 use std::convert::TryInto;
+use std::fs;
 use std::str;
 
 use anyhow::{bail, Result};
@@ -96,9 +127,25 @@ struct SpiArgs {
     #[clap(long, short, value_name = "nbytes", requires = "read")]
     discard: Option<usize>,
 
-    /// device open which to operate
+    /// device (by index or by name) on which to operate
     #[clap(long, short = 'D', value_name = "device")]
     device: Option<String>,
+
+    /// list SPI controllers and their devices
+    #[clap(long, short, conflicts_with_all = &["write", "read", "write_file"])]
+    list: bool,
+
+    /// file containing bytes to write
+    #[clap(long, value_name = "filename", conflicts_with = "write")]
+    write_file: Option<String>,
+
+    /// file to which to write bytes read
+    #[clap(long, short, value_name = "filename", requires = "read")]
+    output: Option<String>,
+
+    /// size of words to write and display, in bytes
+    #[clap(long, value_name = "bytes", conflicts_with = "word")]
+    word_size: Option<usize>,
 }
 
 /// Looks up which Hubris task is associated with SPI (accepting a peripheral
@@ -170,18 +217,163 @@ pub fn spi_task(
     Ok(task)
 }
 
+fn spi_list(hubris: &HubrisArchive) -> Result<()> {
+    let devices = &hubris.manifest.spi_devices;
+    let mut controllers = vec![];
+
+    for peripheral in 0..9 {
+        let task = spi_task(hubris, Some(peripheral)).ok();
+
+        if task.is_some() || devices.iter().any(|d| d.controller == peripheral)
+        {
+            controllers.push((peripheral, task));
+        }
+    }
+
+    if controllers.is_empty() {
+        bail!("no SPI controllers found");
+    }
+
+    println!(
+        "{:<10} {:<16} {:>6} {:<16} {:<10} DESCRIPTION",
+        "CONTROLLER", "TASK", "DEVICE", "NAME", "MUX"
+    );
+
+    for (peripheral, task) in controllers {
+        let controller = format!("spi{peripheral}");
+
+        let task = match task {
+            Some(task) => hubris.lookup_module(task)?.name.clone(),
+            None => "-".to_string(),
+        };
+
+        let mut found = false;
+
+        for d in devices.iter().filter(|d| d.controller == peripheral) {
+            println!(
+                "{:<10} {:<16} {:>6} {:<16} {:<10} {}",
+                controller,
+                task,
+                d.index,
+                d.name,
+                d.mux.as_deref().unwrap_or("-"),
+                d.description.as_deref().unwrap_or("-"),
+            );
+
+            found = true;
+        }
+
+        if !found {
+            println!(
+                "{:<10} {:<16} {:>6} {:<16} {:<10} -",
+                controller, task, "-", "-", "-"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Resolves the specified device -- which can be an index or the name of a
+/// device in the manifest -- into a peripheral (if known) and an index.
+///
+fn spi_device(
+    hubris: &HubrisArchive,
+    peripheral: Option<u8>,
+    device: Option<&str>,
+) -> Result<(Option<u8>, u8)> {
+    let device = match device {
+        Some(device) => device,
+        None => return Ok((peripheral, 0)),
+    };
+
+    if let Ok(index) = parse_int::parse::<u8>(device) {
+        return Ok((peripheral, index));
+    }
+
+    let found = hubris
+        .manifest
+        .spi_devices
+        .iter()
+        .filter(|d| d.name == device)
+        .filter(|d| peripheral.map_or(true, |p| p == d.controller))
+        .collect::<Vec<_>>();
+
+    match found[..] {
+        [] => bail!("illegal device {device}"),
+        [d] => Ok((Some(d.controller), d.index)),
+        _ => bail!("device {device} is on more than one SPI peripheral"),
+    }
+}
+
+///
+/// Parses comma-separated words of the specified size, returning their
+/// bytes in the order in which they are shifted out (that is, most
+/// significant byte first).
+///
+fn spi_words(write: &str, size: usize) -> Result<Vec<u8>> {
+    let mut arr = vec![];
+
+    for word in write.split(',') {
+        if size == 1 {
+            match parse_int::parse::<u8>(word) {
+                Ok(val) => arr.push(val),
+                Err(_) => bail!("invalid byte {word}"),
+            }
+
+            continue;
+        }
+
+        match parse_int::parse::<u32>(word) {
+            Ok(val) if size == 4 || val >> (size * 8) == 0 => {
+                arr.extend_from_slice(&val.to_be_bytes()[4 - size..]);
+            }
+            _ => bail!("invalid {size}-byte word {word}"),
+        }
+    }
+
+    Ok(arr)
+}
+
 fn spi(context: &mut ExecutionContext) -> Result<()> {
-    let core = &mut **context.core.as_mut().unwrap();
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = SpiArgs::try_parse_from(subargs)?;
+
+    if subargs.list {
+        return spi_list(context.archive.as_ref().unwrap());
+    }
+
+    humility_cmd::attach(
+        context,
+        Attach::LiveOnly,
+        Validate::Booted,
+        |context| spi_attached(context, &subargs),
+    )
+}
+
+fn spi_attached(
+    context: &mut ExecutionContext,
+    subargs: &SpiArgs,
+) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
     let hubris = context.archive.as_ref().unwrap();
 
-    let subargs = SpiArgs::try_parse_from(subargs)?;
     let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
 
     let spi_read = context.get_function("SpiRead", 4)?;
     let spi_write = context.get_function("SpiWrite", 3)?;
 
-    let task = spi_task(hubris, subargs.peripheral)?;
+    let word_size = subargs.word_size.unwrap_or(1);
+
+    if ![1, 2, 4].contains(&word_size) {
+        bail!("word size must be 1, 2 or 4 bytes");
+    }
+
+    let (peripheral, device) =
+        spi_device(hubris, subargs.peripheral, subargs.device.as_deref())?;
+
+    let task = spi_task(hubris, peripheral)?;
     let mut ops = vec![];
 
     if let HubrisTask::Task(task) = task {
@@ -190,31 +382,25 @@ fn spi(context: &mut ExecutionContext) -> Result<()> {
         bail!("SPI task cannot be the kernel");
     }
 
-    if let Some(device) = subargs.device {
-        if let Ok(device) = parse_int::parse::<u8>(&device) {
-            ops.push(Op::Push(device));
-        } else {
-            bail!("illegal device {device}");
-        }
-    } else {
-        // Device 0
-        ops.push(Op::Push(0));
-    }
+    ops.push(Op::Push(device));
 
     humility::msg!("SPI master is {}", hubris.lookup_module(task)?.name);
 
     let mut addr = 0;
 
-    let data = if let Some(ref write) = subargs.write {
-        let bytes: Vec<&str> = write.split(',').collect();
-        let mut arr = vec![];
+    let write = match (&subargs.write, &subargs.write_file) {
+        (Some(write), _) => Some(spi_words(write, word_size)?),
+        (None, Some(filename)) => Some(fs::read(filename)?),
+        (None, None) => None,
+    };
 
-        for byte in &bytes {
-            if let Ok(val) = parse_int::parse::<u8>(byte) {
-                arr.push(val);
-            } else {
-                bail!("invalid byte {byte}")
-            }
+    let data = if let Some(arr) = write {
+        if arr.len() > context.data_size() {
+            bail!(
+                "cannot write {} bytes; maximum is {}",
+                arr.len(),
+                context.data_size()
+            );
         }
 
         if let Some(size) = subargs.littleendian_address {
@@ -299,9 +485,37 @@ fn spi(context: &mut ExecutionContext) -> Result<()> {
                 bail!("short read: {:x?}", results);
             }
 
+            let results = &results[discard..];
+
+            if let Some(ref filename) = subargs.output {
+                fs::write(filename, results)?;
+                humility::msg!("wrote {} bytes to {filename}", results.len());
+                return Ok(());
+            }
+
             let mut dumper = Dumper::new();
-            dumper.size = if subargs.word { 4 } else { 1 };
-            dumper.dump(&results[discard..], addr);
+
+            if word_size > 1 {
+                if results.len() % word_size != 0 {
+                    bail!("read is not a multiple of the word size");
+                }
+
+                //
+                // The dumper displays words as little-endian; swap each word
+                // so that it is displayed in the order it was shifted in.
+                //
+                let swapped = results
+                    .chunks(word_size)
+                    .flat_map(|w| w.iter().rev().copied())
+                    .collect::<Vec<u8>>();
+
+                dumper.size = word_size;
+                dumper.ascii = false;
+                dumper.dump(&swapped, addr);
+            } else {
+                dumper.size = if subargs.word { 4 } else { 1 };
+                dumper.dump(results, addr);
+            }
 
             return Ok(());
         }
@@ -317,10 +531,6 @@ pub fn init() -> Command {
         app: SpiArgs::command(),
         name: "spi",
         run: spi,
-        kind: CommandKind::Attached {
-            archive: Archive::Required,
-            attach: Attach::LiveOnly,
-            validate: Validate::Booted,
-        },
+        kind: CommandKind::Unattached { archive: Archive::Required },
     }
 }
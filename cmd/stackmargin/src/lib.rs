@@ -115,22 +115,8 @@ fn stackmargin(context: &mut ExecutionContext) -> Result<()> {
             )
         }
 
-        let size = (initial - region.base) as usize;
-        let mut stack: Vec<u8> = vec![];
-        stack.resize_with(size, Default::default);
-        core.read_8(region.base, stack.as_mut_slice())?;
-
-        let mut o = 0;
-
-        let depth = loop {
-            let c = u32::from_le_bytes(stack[o..o + 4].try_into().unwrap());
-
-            if c != 0xbaddcafe || o + 4 >= size {
-                break size - o;
-            }
-
-            o += 4;
-        };
+        let size = initial - region.base;
+        let depth = stack_depth(core, region.base, initial)?;
 
         println!("{:2} {:18} 0x{:<8x} {:10} {:10} {:10}",
             i, module.name, region.base,
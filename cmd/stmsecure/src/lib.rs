@@ -26,8 +26,45 @@
 //! ```text
 //! humility stmsecure bank-swap
 //! ```
+//!
+//! To read and decode the option bytes, use `option-bytes`:
+//!
+//! ```console
+//! $ humility stmsecure option-bytes
+//! humility: attached via ST-Link V3
+//!   BOR_LEV          0x0 (off)
+//!   IWDG1_SW         0x1 (software)
+//!   NRST_STOP_D1     0x1
+//!   NRST_STBY_D1     0x1
+//!   RDP              0xaa (level 0)
+//!   FZ_IWDG_STOP     0x1
+//!   FZ_IWDG_SDBY     0x1
+//!   ST_RAM_SIZE      0x3
+//!   SECURITY         0x0 (disabled)
+//!   IO_HSLV          0x0
+//!   SWAP_BANK        0x0 (not swapped)
+//!   BOOT_ADD0        0x800 (0x08000000)
+//!   BOOT_ADD1        0x1ff0 (0x1ff00000)
+//!   WRPSN1           0xff (no sectors protected)
+//! ```
+//!
+//! Option bytes can be programmed with `set-option-bytes`, specifying each
+//! as `field=value`.  The change is displayed and must be confirmed before
+//! it is programmed:
+//!
+//! ```console
+//! $ humility stmsecure set-option-bytes bor_lev=3
+//! humility: attached via ST-Link V3
+//!   BOR_LEV          0x0 (off) -> 0x3 (level 3, 2.7V)
+//! humility: program option bytes? [y/N] y
+//! done.
+//! ```
+//!
+//! `RDP` and `SECURITY` cannot be programmed this way; use `set-rdp`,
+//! `unset-rdp`, `set-secure-bit` and `unset-secure-bit`.  A change to
+//! `SWAP_BANK` takes effect on the next reset.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{CommandFactory, Parser};
 use humility::core::Core;
 use humility_arch_arm::ARMRegister;
@@ -49,6 +86,39 @@ const FLASH_OPTSR_CUR: u32 = 0x5200_201C;
 const FLASH_OPTSR_PRG: u32 = 0x5200_2020;
 const FLASH_SCAR_CUR1: u32 = 0x5200_2030;
 const FLASH_SCAR_PRG1: u32 = 0x5200_2034;
+const FLASH_WPSN_CUR1: u32 = 0x5200_2038;
+const FLASH_WPSN_PRG1: u32 = 0x5200_203C;
+const FLASH_BOOT_CUR: u32 = 0x5200_2040;
+const FLASH_BOOT_PRG: u32 = 0x5200_2044;
+
+/// Set in FLASH_OPTSR_CUR if an option byte change failed
+const FLASH_OPTSR_OPTCHANGEERR: u32 = 1 << 30;
+
+///
+/// An option byte field, as (name, current register, program register,
+/// high bit, low bit).
+///
+type OptionField = (&'static str, u32, u32, u32, u32);
+
+const OPTION_FIELDS: &[OptionField] = &[
+    ("BOR_LEV", FLASH_OPTSR_CUR, FLASH_OPTSR_PRG, 3, 2),
+    ("IWDG1_SW", FLASH_OPTSR_CUR, FLASH_OPTSR_PRG, 4, 4),
+    ("NRST_STOP_D1", FLASH_OPTSR_CUR, FLASH_OPTSR_PRG, 6, 6),
+    ("NRST_STBY_D1", FLASH_OPTSR_CUR, FLASH_OPTSR_PRG, 7, 7),
+    ("RDP", FLASH_OPTSR_CUR, FLASH_OPTSR_PRG, 15, 8),
+    ("FZ_IWDG_STOP", FLASH_OPTSR_CUR, FLASH_OPTSR_PRG, 17, 17),
+    ("FZ_IWDG_SDBY", FLASH_OPTSR_CUR, FLASH_OPTSR_PRG, 18, 18),
+    ("ST_RAM_SIZE", FLASH_OPTSR_CUR, FLASH_OPTSR_PRG, 20, 19),
+    ("SECURITY", FLASH_OPTSR_CUR, FLASH_OPTSR_PRG, 21, 21),
+    ("IO_HSLV", FLASH_OPTSR_CUR, FLASH_OPTSR_PRG, 29, 29),
+    ("SWAP_BANK", FLASH_OPTSR_CUR, FLASH_OPTSR_PRG, 31, 31),
+    ("BOOT_ADD0", FLASH_BOOT_CUR, FLASH_BOOT_PRG, 15, 0),
+    ("BOOT_ADD1", FLASH_BOOT_CUR, FLASH_BOOT_PRG, 31, 16),
+    ("WRPSN1", FLASH_WPSN_CUR1, FLASH_WPSN_PRG1, 7, 0),
+];
+
+/// Option bytes that have their own subcommands
+const OPTION_RESERVED: &[&str] = &["RDP", "SECURITY"];
 
 #[derive(Parser, Debug)]
 #[clap(name = "stmsecure", about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -83,6 +153,13 @@ enum StmSecureArgs {
     /// Swap the flash banks (Bank 1 -> Bank 2 or Bank 2 -> Bank 1)
     /// !!! Make sure secure regions are appropriately programmed !!!
     SwapBanks,
+    /// Read and decode the option bytes
+    OptionBytes,
+    /// Program option bytes, specified as field=value (e.g., bor_lev=3)
+    SetOptionBytes {
+        #[clap(value_name = "field=value", required = true)]
+        settings: Vec<String>,
+    },
 }
 
 fn stmsecure_unlock_flash(core: &mut dyn Core) -> Result<()> {
@@ -293,6 +370,149 @@ fn stmsecure_swapbanks(core: &mut dyn Core) -> Result<()> {
     Ok(())
 }
 
+fn option_mask((_, _, _, hi, lo): &OptionField) -> u32 {
+    (((1u64 << (hi - lo + 1)) - 1) as u32) << lo
+}
+
+fn option_get(field: &OptionField, val: u32) -> u32 {
+    (val & option_mask(field)) >> field.4
+}
+
+fn option_format(name: &str, val: u32) -> String {
+    let desc = match (name, val) {
+        ("BOR_LEV", 0) => "off".to_string(),
+        ("BOR_LEV", 1) => "level 1, 2.1V".to_string(),
+        ("BOR_LEV", 2) => "level 2, 2.4V".to_string(),
+        ("BOR_LEV", 3) => "level 3, 2.7V".to_string(),
+        ("IWDG1_SW", 0) => "hardware".to_string(),
+        ("IWDG1_SW", 1) => "software".to_string(),
+        ("RDP", 0xaa) => "level 0".to_string(),
+        ("RDP", 0xcc) => "level 2".to_string(),
+        ("RDP", _) => "level 1".to_string(),
+        ("SECURITY", 0) => "disabled".to_string(),
+        ("SECURITY", _) => "enabled".to_string(),
+        ("SWAP_BANK", 0) => "not swapped".to_string(),
+        ("SWAP_BANK", _) => "swapped".to_string(),
+        ("BOOT_ADD0" | "BOOT_ADD1", _) => format!("0x{:08x}", val << 16),
+        ("WRPSN1", 0xff) => "no sectors protected".to_string(),
+        ("WRPSN1", _) => {
+            format!("{} sectors protected", val.count_zeros() - 24)
+        }
+        _ => return format!("0x{:x}", val),
+    };
+
+    format!("0x{:x} ({})", val, desc)
+}
+
+fn stmsecure_option_read(core: &mut dyn Core) -> Result<Vec<(u32, u32)>> {
+    let mut rval: Vec<(u32, u32)> = vec![];
+
+    for (_, cur, _, _, _) in OPTION_FIELDS {
+        if !rval.iter().any(|(addr, _)| addr == cur) {
+            rval.push((*cur, core.read_word_32(*cur)?));
+        }
+    }
+
+    Ok(rval)
+}
+
+fn stmsecure_option_bytes(core: &mut dyn Core) -> Result<()> {
+    let regs = stmsecure_option_read(core)?;
+
+    for field in OPTION_FIELDS {
+        let (_, val) = regs.iter().find(|(addr, _)| *addr == field.1).unwrap();
+        let val = option_get(field, *val);
+        println!("  {:16} {}", field.0, option_format(field.0, val));
+    }
+
+    Ok(())
+}
+
+fn stmsecure_set_option_bytes(
+    core: &mut dyn Core,
+    settings: &[String],
+) -> Result<()> {
+    let current = stmsecure_option_read(core)?;
+    let mut regs = current.clone();
+
+    for setting in settings {
+        let (name, val) = match setting.split_once('=') {
+            Some((name, val)) => (name.to_uppercase(), val),
+            None => bail!("option byte must be specified as field=value"),
+        };
+
+        let field = match OPTION_FIELDS.iter().find(|f| f.0 == name) {
+            Some(field) => field,
+            None => bail!("unknown option byte \"{}\"", name.to_lowercase()),
+        };
+
+        if OPTION_RESERVED.contains(&field.0) {
+            bail!("{} cannot be set with set-option-bytes", field.0);
+        }
+
+        let val = parse_int::parse::<u32>(val).with_context(|| {
+            format!("invalid value for {}: \"{val}\"", field.0)
+        })?;
+
+        if val > option_mask(field) >> field.4 {
+            bail!("value 0x{:x} is too large for {}", val, field.0);
+        }
+
+        let reg = regs.iter_mut().find(|(addr, _)| *addr == field.1).unwrap();
+        reg.1 = (reg.1 & !option_mask(field)) | (val << field.4);
+    }
+
+    let mut changed = false;
+
+    for field in OPTION_FIELDS {
+        let find = |regs: &[(u32, u32)]| {
+            let (_, val) =
+                regs.iter().find(|(addr, _)| *addr == field.1).unwrap();
+            option_get(field, *val)
+        };
+
+        let (old, new) = (find(&current), find(&regs));
+
+        if old != new {
+            println!(
+                "  {:16} {} -> {}",
+                field.0,
+                option_format(field.0, old),
+                option_format(field.0, new)
+            );
+            changed = true;
+        }
+    }
+
+    if !changed {
+        println!("no change to option bytes.");
+        return Ok(());
+    }
+
+    if !humility_cmd::confirm("program option bytes?")? {
+        println!("not programming option bytes.");
+        return Ok(());
+    }
+
+    stmsecure_unlock_option(core)?;
+
+    for ((cur, old), (_, new)) in current.iter().zip(regs.iter()) {
+        if old != new {
+            let prg = OPTION_FIELDS.iter().find(|f| f.1 == *cur).unwrap().2;
+            core.write_word_32(prg, *new)?;
+        }
+    }
+
+    stmsecure_commit_option(core)?;
+
+    if core.read_word_32(FLASH_OPTSR_CUR)? & FLASH_OPTSR_OPTCHANGEERR != 0 {
+        bail!("option byte change failed (OPTCHANGEERR is set)");
+    }
+
+    println!("done.");
+    Ok(())
+}
+
 #[rustfmt::skip::macros(format)]
 fn stmsecure(context: &mut ExecutionContext) -> Result<()> {
     let core = &mut **context.core.as_mut().unwrap();
@@ -311,6 +531,10 @@ fn stmsecure(context: &mut ExecutionContext) -> Result<()> {
         StmSecureArgs::SetRDP => stmsecure_rdpset(core),
         StmSecureArgs::UnsetRDP => stmsecure_rdpunset(core),
         StmSecureArgs::SwapBanks => stmsecure_swapbanks(core),
+        StmSecureArgs::OptionBytes => stmsecure_option_bytes(core),
+        StmSecureArgs::SetOptionBytes { settings } => {
+            stmsecure_set_option_bytes(core, &settings)
+        }
     }
 }
 
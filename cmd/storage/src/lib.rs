@@ -0,0 +1,278 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility storage`
+//!
+//! `humility storage` displays the identification and health of the
+//! storage devices that are visible to the SP.  Currently, these are NVMe
+//! drives that implement the NVMe Basic Management Command over their
+//! sideband I2C interface (that is, devices of type `nvme_bmc` or
+//! `m2_hp_only` in the manifest); for each such drive, its PCI vendor ID,
+//! its serial number, its composite temperature, the percentage of its
+//! rated life that has been used, and its status are displayed:
+//!
+//! ```console
+//! $ humility storage
+//! humility: attached via ST-Link V3
+//! NAME         DEVICE       VENDOR SERIAL                 TEMP  USED STATUS
+//! U2_N0        nvme_bmc     0x1344 21303182C4F1            37C    3% ready
+//! U2_N1        nvme_bmc     0x1344 21303182C50A            36C   87% ready
+//! U2_N2        nvme_bmc          - -                         -     - absent
+//! M2_A         m2_hp_only   0x1b96 A0630A6E                41C    0% ready
+//! humility: WARNING: U2_N1: drive life used (87%) exceeds threshold (80%)
+//! ```
+//!
+//! A drive that has used more of its rated life than the threshold
+//! specified via `--threshold` (80% by default) is flagged, as is any drive
+//! indicating a SMART critical warning (e.g., that its available spare has
+//! fallen below its threshold, or that its media has been placed in
+//! read-only mode).  Removable drives that do not respond are displayed as
+//! absent.  To display a single drive, specify its name with `--device`
+//! (`-d`).
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use hif::*;
+use humility::hubris::*;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_hiffy::*;
+use humility_i2c::I2cRetryPolicy;
+
+#[derive(Parser, Debug)]
+#[clap(name = "storage", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct StorageArgs {
+    /// sets timeout
+    #[clap(
+        long, short = 'T', default_value_t = 5000, value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+
+    /// display only the specified device
+    #[clap(long, short, value_name = "device")]
+    device: Option<String>,
+
+    /// percentage of drive life used above which a drive is flagged
+    #[clap(
+        long, value_name = "percent", default_value_t = 80,
+        parse(try_from_str = parse_int::parse)
+    )]
+    threshold: u8,
+}
+
+/// NVMe Basic Management Command:  status flags, SMART warnings, composite
+/// temperature and percentage of drive life used
+const NVME_BMC_STATUS: u8 = 0x00;
+
+/// NVMe Basic Management Command:  PCI vendor ID and serial number
+const NVME_BMC_IDENT: u8 = 0x08;
+
+/// Status flags: drive is not ready
+const NVME_BMC_STATUS_NOT_READY: u8 = 1 << 6;
+
+/// Status flags: drive is functional
+const NVME_BMC_STATUS_FUNCTIONAL: u8 = 1 << 5;
+
+/// Status flags: drive does not require a reset
+const NVME_BMC_STATUS_RESET_NOT_REQUIRED: u8 = 1 << 4;
+
+///
+/// SMART critical warnings, by bit position.  Note that these are active
+/// low:  a bit that is clear indicates the warning.
+///
+const NVME_BMC_SMART_WARNINGS: &[&str] = &[
+    "available spare below threshold",
+    "temperature outside of threshold",
+    "reliability degraded",
+    "media in read-only mode",
+    "volatile memory backup failed",
+    "persistent memory region in read-only mode",
+];
+
+fn storage_temp(val: u8) -> String {
+    match val {
+        0x7f => ">=127C".to_string(),
+        0x80 => "-".to_string(),
+        0x81 => "failed".to_string(),
+        0x82..=0xc3 => format!("<0x{val:02x}>"),
+        0xc4 => "<=-60C".to_string(),
+        _ => format!("{}C", val as i8),
+    }
+}
+
+fn storage_status(flags: u8) -> &'static str {
+    if flags & NVME_BMC_STATUS_FUNCTIONAL == 0 {
+        "failed"
+    } else if flags & NVME_BMC_STATUS_NOT_READY != 0 {
+        "not ready"
+    } else if flags & NVME_BMC_STATUS_RESET_NOT_REQUIRED == 0 {
+        "reset required"
+    } else {
+        "ready"
+    }
+}
+
+fn storage_ident(buf: &[u8]) -> (String, String) {
+    if buf.len() < 2 {
+        return ("-".to_string(), "-".to_string());
+    }
+
+    let vendor = u16::from_be_bytes([buf[0], buf[1]]);
+
+    let serial = String::from_utf8_lossy(&buf[2..])
+        .trim_end_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string();
+
+    (format!("0x{vendor:04x}"), serial)
+}
+
+fn storage(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = StorageArgs::try_parse_from(subargs)?;
+
+    let devices = hubris
+        .manifest
+        .i2c_devices
+        .iter()
+        .filter(|d| d.device == "nvme_bmc" || d.device == "m2_hp_only")
+        .filter(|d| match &subargs.device {
+            Some(name) => d.name.as_ref() == Some(name),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+
+    if devices.is_empty() {
+        match &subargs.device {
+            Some(name) => bail!("no storage device named {name}"),
+            None => bail!("no storage devices found in the manifest"),
+        }
+    }
+
+    let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
+    let read = context.get_function("I2cRead", 7)?;
+
+    let mut ops = vec![];
+
+    for dev in &devices {
+        ops.push(Op::Push(dev.controller));
+        ops.push(Op::Push(dev.port.index));
+
+        match (dev.mux, dev.segment) {
+            (Some(mux), Some(segment)) => {
+                ops.push(Op::Push(mux));
+                ops.push(Op::Push(segment));
+            }
+            _ => {
+                ops.push(Op::PushNone);
+                ops.push(Op::PushNone);
+            }
+        }
+
+        ops.push(Op::Push(dev.address));
+
+        //
+        // Both commands are SMBus block reads.
+        //
+        for code in [NVME_BMC_STATUS, NVME_BMC_IDENT] {
+            ops.push(Op::Push(code));
+            ops.push(Op::PushNone);
+            ops.push(Op::Call(read.id));
+            ops.push(Op::DropN(2));
+        }
+
+        ops.push(Op::DropN(5));
+    }
+
+    ops.push(Op::Done);
+
+    let results =
+        I2cRetryPolicy::default().run(&mut context, core, &ops, None, &read)?;
+
+    println!(
+        "{:12} {:12} {:>6} {:20} {:>6} {:>5} STATUS",
+        "NAME", "DEVICE", "VENDOR", "SERIAL", "TEMP", "USED"
+    );
+
+    let mut flagged = vec![];
+
+    for (dev, results) in devices.iter().zip(results.chunks(2)) {
+        let name = dev.name.as_deref().unwrap_or(&dev.description);
+
+        let status = match &results[0] {
+            Ok(buf) if buf.len() >= 4 => buf,
+            Ok(buf) => {
+                humility::warn!("{name}: short status read: {buf:x?}");
+                continue;
+            }
+            Err(err) => {
+                let err = read.strerror(*err);
+
+                let status = if dev.removable && err == "NoDevice" {
+                    "absent".to_string()
+                } else {
+                    format!("error: {err}")
+                };
+
+                println!(
+                    "{name:12} {:12} {:>6} {:20} {:>6} {:>5} {status}",
+                    dev.device, "-", "-", "-", "-"
+                );
+                continue;
+            }
+        };
+
+        let (vendor, serial) = match &results[1] {
+            Ok(buf) => storage_ident(buf),
+            Err(_) => ("-".to_string(), "-".to_string()),
+        };
+
+        let (flags, smart, temp, used) =
+            (status[0], status[1], status[2], status[3]);
+
+        println!(
+            "{name:12} {:12} {vendor:>6} {serial:20} {:>6} {:>4}% {}",
+            dev.device,
+            storage_temp(temp),
+            used,
+            storage_status(flags),
+        );
+
+        if used > subargs.threshold {
+            flagged.push(format!(
+                "{name}: drive life used ({used}%) exceeds threshold ({}%)",
+                subargs.threshold
+            ));
+        }
+
+        for (bit, warning) in NVME_BMC_SMART_WARNINGS.iter().enumerate() {
+            if smart & (1 << bit) == 0 {
+                flagged.push(format!("{name}: SMART warning: {warning}"));
+            }
+        }
+    }
+
+    for flag in flagged {
+        humility::warn!("{flag}");
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: StorageArgs::command(),
+        name: "storage",
+        run: storage,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -0,0 +1,237 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility symbols`
+//!
+//! `humility symbols` searches the symbol tables of the kernel and of every
+//! task in the archive, displaying the address, size, containing task and
+//! section of each matching symbol.  Symbols are matched by their demangled
+//! names; by default, a pattern matches any symbol that contains it
+//! (ignoring case):
+//!
+//! ```console
+//! $ humility symbols ringbuf
+//! TASK         ADDR         SIZE SECTION  SYMBOL
+//! jefe         0x24000400    196 .bss     task_jefe::__RINGBUF
+//! thermal      0x24038000   1216 .bss     task_thermal::__RINGBUF
+//! i2c_driver   0x2403c000    388 .bss     drv_stm32xx_i2c::__RINGBUF
+//! ```
+//!
+//! If the pattern contains `*` or `?`, it is instead treated as a glob that
+//! must match the entire symbol name:
+//!
+//! ```console
+//! $ humility symbols 'task_thermal::*::run*'
+//! TASK         ADDR         SIZE SECTION  SYMBOL
+//! thermal      0x080a11f8    742 .text    task_thermal::control::run_control
+//! thermal      0x080a14e0    356 .text    task_thermal::bsp::run_fans
+//! ```
+//!
+//! To match with a regular expression, use `--regex` (`-r`).
+//!
+//! To find the symbol that contains a particular address (e.g., a faulting
+//! PC or an address that has appeared in a register), use `--containing`:
+//!
+//! ```console
+//! $ humility symbols --containing 0x080a0e22
+//! TASK         ADDR         SIZE SECTION  SYMBOL
+//! thermal      0x080a0d0c   1260 .text    task_thermal::main
+//! ```
+//!
+//! `humility symbols` does not connect to a target.
+//!
+
+use anyhow::{anyhow, bail, Result};
+use clap::{CommandFactory, Parser};
+use goblin::elf::{section_header, sym};
+use humility::hubris::*;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Command, CommandKind};
+use regex::Regex;
+use rustc_demangle::demangle;
+
+#[derive(Parser, Debug)]
+#[clap(name = "symbols", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct SymbolsArgs {
+    /// treat the pattern as a regular expression
+    #[clap(long, short)]
+    regex: bool,
+
+    /// display only symbols that contain the specified address
+    #[clap(
+        long, short, value_name = "address",
+        parse(try_from_str = parse_int::parse)
+    )]
+    containing: Option<u32>,
+
+    /// pattern to match against demangled symbol names
+    #[clap(required_unless_present = "containing")]
+    pattern: Option<String>,
+}
+
+struct Symbol {
+    object: String,
+    addr: u32,
+    size: u32,
+    section: String,
+    name: String,
+}
+
+///
+/// Returns a regular expression for the specified pattern: a pattern
+/// containing glob metacharacters must match the entire name, while any
+/// other pattern can match any part of it.
+///
+fn matcher(pattern: &str, regex: bool) -> Result<Regex> {
+    let re = if regex {
+        pattern.to_string()
+    } else if pattern.contains(|c| c == '*' || c == '?') {
+        let mut re = String::from("^");
+
+        for c in pattern.chars() {
+            match c {
+                '*' => re.push_str(".*"),
+                '?' => re.push('.'),
+                c => re.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+
+        re.push('$');
+        re
+    } else {
+        format!("(?i){}", regex::escape(pattern))
+    };
+
+    Regex::new(&re).map_err(|e| anyhow!("bad pattern \"{pattern}\": {e}"))
+}
+
+fn symbols(
+    hubris: &HubrisArchive,
+    object: &str,
+    file: &str,
+    rval: &mut Vec<Symbol>,
+) -> Result<()> {
+    let contents = match hubris.read_file(file)? {
+        Some(contents) => contents,
+        None => bail!("archive is missing {file}"),
+    };
+
+    let elf = goblin::elf::Elf::parse(&contents)
+        .map_err(|e| anyhow!("failed to parse {file}: {e}"))?;
+
+    for s in elf.syms.iter() {
+        if s.st_name == 0
+            || s.st_size == 0
+            || s.st_type() == sym::STT_SECTION
+            || s.st_type() == sym::STT_FILE
+        {
+            continue;
+        }
+
+        //
+        // As with loading the archive, we only care about symbols in
+        // allocated sections.
+        //
+        let sh = match elf.section_headers.get(s.st_shndx) {
+            Some(sh)
+                if sh.sh_flags & u64::from(section_header::SHF_ALLOC) != 0 =>
+            {
+                sh
+            }
+            _ => continue,
+        };
+
+        let name = match elf.strtab.get(s.st_name) {
+            Some(Ok(name)) => name,
+            _ => bail!("bad symbol in {file}: {}", s.st_name),
+        };
+
+        let section = match elf.shdr_strtab.get(sh.sh_name) {
+            Some(Ok(section)) => section.to_string(),
+            _ => "-".to_string(),
+        };
+
+        //
+        // Clear the Thumb bit from functions.
+        //
+        let addr = if s.is_function() {
+            s.st_value as u32 & !1
+        } else {
+            s.st_value as u32
+        };
+
+        rval.push(Symbol {
+            object: object.to_string(),
+            addr,
+            size: s.st_size as u32,
+            section,
+            name: format!("{:#}", demangle(name)),
+        });
+    }
+
+    Ok(())
+}
+
+fn symbolscmd(context: &mut ExecutionContext) -> Result<()> {
+    let hubris = context.archive.as_ref().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = SymbolsArgs::try_parse_from(subargs)?;
+
+    let re = match &subargs.pattern {
+        Some(pattern) => Some(matcher(pattern, subargs.regex)?),
+        None => None,
+    };
+
+    let mut all = vec![];
+
+    for module in hubris.modules() {
+        let file = match module.task {
+            HubrisTask::Kernel => "elf/kernel".to_string(),
+            HubrisTask::Task(_) => format!("elf/task/{}", module.name),
+        };
+
+        symbols(hubris, &module.name, &file, &mut all)?;
+    }
+
+    let mut found = all
+        .iter()
+        .filter(|s| match subargs.containing {
+            Some(addr) => addr >= s.addr && addr - s.addr < s.size,
+            None => true,
+        })
+        .filter(|s| match &re {
+            Some(re) => re.is_match(&s.name),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+
+    if found.is_empty() {
+        bail!("no matching symbols found");
+    }
+
+    found.sort_by(|a, b| (a.addr, &a.name).cmp(&(b.addr, &b.name)));
+
+    println!(
+        "{:<12} {:<10} {:>6} {:<8} SYMBOL",
+        "TASK", "ADDR", "SIZE", "SECTION"
+    );
+
+    for s in found {
+        println!(
+            "{:<12} 0x{:08x} {:>6} {:<8} {}",
+            s.object, s.addr, s.size, s.section, s.name
+        );
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: SymbolsArgs::command(),
+        name: "symbols",
+        run: symbolscmd,
+        kind: CommandKind::Unattached { archive: Archive::Required },
+    }
+}
@@ -642,7 +642,7 @@ fn test_ingest(
     );
 
     match rval {
-        Ok(_) => rval,
+        Ok(_) => Ok(()),
         Err(err) => {
             testrun.report(output, &wire.borrow(), Some(&err))?;
             Err(err)
@@ -0,0 +1,412 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility thermal`
+//!
+//! `humility thermal` displays the state of thermal control in one place:
+//! the mode of the `thermal` task's control loop, the fan PWM that it is
+//! driving, and the most recent reading of each fan speed and temperature
+//! sensor.  Sensors for which the `sensor` task has no reading (missing) or
+//! whose most recent reading was an error (failed) are flagged as such:
+//!
+//! ```console
+//! $ humility thermal
+//! humility: attached via ST-Link V3
+//! humility: thermal mode is Auto (Running), margin 0.00°C, fan PWM 35%
+//! SENSOR                     KIND  DEVICE        VALUE  ERRS STATUS
+//! Southwest                  temp  tmp117      26.56°C     0 ok
+//! South                      temp  tmp117      27.23°C     0 ok
+//! Southeast                  temp  tmp117      26.35°C     0 ok
+//! U.2 Sharkfin A VPD         temp  tmp451            -     4 DeviceNotPresent
+//! Fan 0 southeast           speed  max31790   5214 RPM     0 ok
+//! Fan 1 northeast           speed  max31790   5190 RPM     0 ok
+//! Fan 2 south               speed  max31790          -     0 missing
+//! humility: 2 sensors failed or missing
+//! ```
+//!
+//! Readings are taken from the memory of the `sensor` task, and can
+//! therefore be displayed from a dump as well as a live system.  On a live
+//! system, the mode, control state and margin are retrieved from the
+//! `thermal` task via its `Thermal` Idol interface; from a dump (or if the
+//! interface is unavailable), they are instead taken from the most recent
+//! corresponding entries in the `thermal` task's ring buffer, as is the fan
+//! PWM.  To show only temperatures or only fans, use `--temps` or `--fans`.
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::reflect::{self, Base, Load, Value};
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_doppel::{Ringbuf, StaticCell};
+use humility_hiffy::*;
+use humility_idol::HubrisIdol;
+
+#[derive(Parser, Debug)]
+#[clap(name = "thermal", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct ThermalArgs {
+    /// sets timeout
+    #[clap(
+        long, short = 'T', default_value_t = 5000, value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+
+    /// show only temperature sensors
+    #[clap(long, conflicts_with = "fans")]
+    temps: bool,
+
+    /// show only fans
+    #[clap(long)]
+    fans: bool,
+}
+
+///
+/// The state of the control loop, as reported by the `thermal` task.
+///
+#[derive(Default)]
+struct ThermalState {
+    mode: Option<String>,
+    auto_state: Option<String>,
+    margin: Option<f32>,
+    pwm: Option<u8>,
+}
+
+///
+/// The most recent reading of a sensor, as recorded by the `sensor` task.
+///
+enum SensorReading {
+    Data(f32),
+    Error(String),
+    Missing,
+}
+
+///
+/// Strips the wrappers (`MaybeUninit`, `ManuallyDrop`) from a value.
+///
+fn unwrap_value(val: &Value) -> &Value {
+    match val {
+        Value::Struct(s)
+            if s.len() == 1 && s.iter().any(|(n, _)| n == "value") =>
+        {
+            unwrap_value(&s["value"])
+        }
+        _ => val,
+    }
+}
+
+///
+/// Returns the first field of an enum variant's contents, if any.
+///
+fn variant_field(val: &Value) -> Option<&Value> {
+    match val {
+        Value::Tuple(t) => t.first(),
+        Value::Struct(s) => s.iter().next().map(|(_, v)| v),
+        _ => Some(val),
+    }
+}
+
+fn disc(val: &Value) -> Option<String> {
+    match val {
+        Value::Enum(e) => Some(e.disc().to_string()),
+        _ => None,
+    }
+}
+
+///
+/// Loads an array that is a static in the `sensor` task.
+///
+fn sensor_array(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    name: &str,
+) -> Result<Vec<Value>> {
+    let var = match hubris.lookup_variables(name) {
+        Ok(vars) if vars.len() == 1 => &vars[0],
+        Ok(_) => bail!("more than one {name}?"),
+        Err(_) => bail!("{name} not found; is there a sensor task?"),
+    };
+
+    let mut buf = vec![0u8; var.size];
+    core.read_8(var.addr, &mut buf)?;
+
+    let val =
+        reflect::load_value(hubris, &buf, hubris.lookup_type(var.goff)?, 0)?;
+
+    match unwrap_value(&val) {
+        Value::Array(a) => Ok(a.to_vec()),
+        _ => bail!("{name} is not an array"),
+    }
+}
+
+fn sensor_readings(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+) -> Result<Vec<(SensorReading, Option<u32>)>> {
+    core.halt()?;
+
+    let arrays = ["LAST_READING", "DATA_VALUE", "ERR_VALUE"]
+        .iter()
+        .map(|name| sensor_array(hubris, core, name))
+        .collect::<Result<Vec<_>>>();
+
+    //
+    // Older sensor tasks don't count errors; we don't insist on it.
+    //
+    let nerrors = sensor_array(hubris, core, "NERRORS").ok();
+
+    core.run()?;
+
+    let arrays = arrays?;
+    let (last, data, err) = (&arrays[0], &arrays[1], &arrays[2]);
+
+    let mut readings = vec![];
+
+    for (i, last) in last.iter().enumerate() {
+        let reading = match last {
+            Value::Enum(e) => match e.as_option()?.and_then(disc).as_deref() {
+                Some("Data") => match unwrap_value(&data[i]) {
+                    Value::Base(Base::F32(f)) => SensorReading::Data(*f),
+                    v => bail!("unexpected sensor value {v:?}"),
+                },
+                Some("Error") => SensorReading::Error(
+                    disc(unwrap_value(&err[i]))
+                        .unwrap_or_else(|| "<unknown>".to_string()),
+                ),
+                _ => SensorReading::Missing,
+            },
+            v => bail!("unexpected reading {v:?}"),
+        };
+
+        let nerrors = nerrors
+            .as_ref()
+            .and_then(|n| n.get(i))
+            .and_then(|v| v.as_base().ok())
+            .and_then(Base::as_u32);
+
+        readings.push((reading, nerrors));
+    }
+
+    Ok(readings)
+}
+
+///
+/// Determines the state of the control loop from the most recent entries in
+/// the `thermal` task's ring buffer.
+///
+fn thermal_ringbuf(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+) -> Result<ThermalState> {
+    let mut state = ThermalState::default();
+
+    let (var, def) = match hubris
+        .qualified_variables()
+        .find(|(n, _)| n.contains("thermal") && n.ends_with("__RINGBUF"))
+    {
+        Some((_, var)) => (var, hubris.lookup_struct(var.goff)?),
+        None => return Ok(state),
+    };
+
+    let mut buf = vec![0u8; var.size];
+
+    core.halt()?;
+    let rval = core.read_8(var.addr, &mut buf);
+    core.run()?;
+    rval?;
+
+    let val = Value::Struct(reflect::load_struct(hubris, &buf, def, 0)?);
+
+    let ringbuf = Ringbuf::from_value(&val).or_else(|_| {
+        let cell = StaticCell::from_value(&val)?;
+        Ringbuf::from_value(&cell.cell.value)
+    })?;
+
+    let last = match ringbuf.last {
+        Some(last) => last as usize,
+        None => return Ok(state),
+    };
+
+    let len = ringbuf.buffer.len();
+
+    //
+    // Walk the ring buffer from oldest to newest, such that the most recent
+    // entry of each kind prevails.
+    //
+    for slot in (0..len).map(|i| (last + i + 1) % len) {
+        let entry = &ringbuf.buffer[slot];
+
+        if entry.generation == 0 {
+            continue;
+        }
+
+        let Value::Enum(e) = &entry.payload else {
+            continue;
+        };
+
+        let field = e.contents().and_then(variant_field);
+
+        match (e.disc(), field) {
+            ("ThermalMode", Some(v)) => state.mode = disc(v),
+            ("AutoState", Some(v)) => state.auto_state = disc(v),
+            ("ControlPwm", Some(Value::Base(b))) => state.pwm = b.as_u8(),
+            _ => {}
+        }
+    }
+
+    Ok(state)
+}
+
+///
+/// Queries the `thermal` task for the state of its control loop, taking
+/// whatever the ring buffer indicates for anything we can't query.
+///
+fn thermal_state(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &ThermalArgs,
+) -> Result<ThermalState> {
+    let mut state = thermal_ringbuf(hubris, core)?;
+
+    if core.is_dump() || hubris.get_idol_command("Thermal.get_mode").is_err() {
+        return Ok(state);
+    }
+
+    let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
+
+    let mut call = |name: &str| -> Result<Option<Value>> {
+        let op = match hubris.get_idol_command(name) {
+            Ok(op) => op,
+            Err(_) => return Ok(None),
+        };
+
+        Ok(hiffy_call(hubris, core, &mut context, &op, &[], None)?.ok())
+    };
+
+    if let Some(mode) = call("Thermal.get_mode")? {
+        state.mode = disc(&mode);
+    }
+
+    if let Some(auto_state) = call("Thermal.get_auto_state")? {
+        state.auto_state = disc(&auto_state);
+    }
+
+    if let Some(Value::Base(Base::F32(margin))) = call("Thermal.get_margin")? {
+        state.margin = Some(margin);
+    }
+
+    Ok(state)
+}
+
+fn thermal(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+
+    let subargs = ThermalArgs::try_parse_from(subargs)?;
+
+    if hubris.manifest.sensors.is_empty() {
+        bail!("no sensors found");
+    }
+
+    let state = thermal_state(hubris, core, &subargs)?;
+    let readings = sensor_readings(hubris, core)?;
+
+    let mut summary = match &state.mode {
+        Some(mode) => format!("thermal mode is {mode}"),
+        None => "thermal mode is unknown".to_string(),
+    };
+
+    if let Some(auto_state) = &state.auto_state {
+        summary.push_str(&format!(" ({auto_state})"));
+    }
+
+    if let Some(margin) = state.margin {
+        summary.push_str(&format!(", margin {margin:.2}°C"));
+    }
+
+    if let Some(pwm) = state.pwm {
+        summary.push_str(&format!(", fan PWM {pwm}%"));
+    }
+
+    humility::msg!("{summary}");
+
+    println!(
+        "{:<26} {:>5}  {:<10} {:>9} {:>5} {}",
+        "SENSOR", "KIND", "DEVICE", "VALUE", "ERRS", "STATUS"
+    );
+
+    let mut bad = 0;
+
+    for (i, s) in hubris.manifest.sensors.iter().enumerate() {
+        let unit = match s.kind {
+            HubrisSensorKind::Temperature if !subargs.fans => "°C",
+            HubrisSensorKind::Speed if !subargs.temps => " RPM",
+            _ => continue,
+        };
+
+        let device = match &s.device {
+            HubrisSensorDevice::I2c(d) => {
+                &hubris.manifest.i2c_devices[*d].device
+            }
+            HubrisSensorDevice::Other(d, _) => d,
+        };
+
+        let (value, status) = match readings.get(i).map(|r| &r.0) {
+            Some(SensorReading::Data(v)) if unit == "°C" => {
+                (format!("{v:.2}{unit}"), "ok".to_string())
+            }
+            Some(SensorReading::Data(v)) => {
+                (format!("{v:.0}{unit}"), "ok".to_string())
+            }
+            Some(SensorReading::Error(e)) => ("-".to_string(), e.clone()),
+            Some(SensorReading::Missing) | None => {
+                ("-".to_string(), "missing".to_string())
+            }
+        };
+
+        if status != "ok" {
+            bad += 1;
+        }
+
+        let errs = match readings.get(i).and_then(|r| r.1) {
+            Some(n) => n.to_string(),
+            None => "-".to_string(),
+        };
+
+        println!(
+            "{:<26} {:>5}  {:<10} {:>9} {:>5} {}",
+            s.name,
+            s.kind.to_string(),
+            device,
+            value,
+            errs,
+            status
+        );
+    }
+
+    if bad > 0 {
+        humility::msg!(
+            "{bad} sensor{} failed or missing",
+            if bad == 1 { "" } else { "s" }
+        );
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: ThermalArgs::command(),
+        name: "thermal",
+        run: thermal,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::Any,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -0,0 +1,532 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility trace`
+//!
+//! `humility trace` records a timeline of the system calls made by tasks
+//! (including IPC sends, receives and replies, and posted notifications)
+//! along with the interrupts that the kernel delivers to tasks as
+//! notifications.  It does this without any instrumentation in the
+//! firmware by setting breakpoints at the kernel's system call and
+//! interrupt entry points, and decoding the arguments to each system call
+//! from the registers of the calling task:
+//!
+//! ```console
+//! $ humility trace
+//! humility: attached via ST-Link V3
+//! humility: tracing (^C to stop)
+//!       TIME TASK         OPERATION    DETAIL
+//!   0.000000 hiffy        send         -> i2c_driver op=1 out=12 in=4 leases=2
+//!   0.004018 i2c_driver   borrow_read  from hiffy lease=0 len=4
+//!   0.007702 kernel       irq 33       -> i2c_driver notification=0x2
+//!   0.011390 i2c_driver   irq_control  mask=0x2 enable
+//!   0.015113 i2c_driver   reply        -> hiffy code=0 len=0
+//!   0.018845 i2c_driver   recv         mask=0x2
+//!   0.022490 hiffy        recv         mask=0x1
+//! ^Chumility: 7 events traced
+//! ```
+//!
+//! Events can be filtered by task with `--task` (`-t`) and by operation
+//! with `--op` (`-o`), both of which can be specified multiple times; an
+//! operation is either the name of a system call (e.g., `send`) or `irq`.
+//! Events that involve a task (e.g., a send to it) match that task.  By
+//! default, tracing continues until interrupted; to stop after a number of
+//! events, use `--count` (`-n`).
+//!
+//! Because every system call and interrupt halts the target, tracing slows
+//! the system dramatically; times (which are in seconds, as measured by
+//! the host) are useful for ordering rather than for measuring latency, and
+//! tasks with timeouts may behave differently when traced.
+//!
+//! To explore a trace interactively, use `--chrome-trace` to write the
+//! traced events to a file in the Chrome trace event format, which can be
+//! loaded into `chrome://tracing` or Perfetto (https://ui.perfetto.dev).
+//! Each task (and the kernel) has its own track; each send that is replied
+//! to appears as a slice on the sending task's track that spans the time
+//! between the send and the reply.
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use humility::chrome::ChromeTrace;
+use humility::core::Core;
+use humility::hubris::*;
+use humility::msg;
+use humility_arch_arm::ARMRegister;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_cortex::debug::{DFSR, DHCSR, FP_COMP0, FP_CTRL};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Parser, Debug)]
+#[clap(name = "trace", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct TraceArgs {
+    /// only display events involving the specified task
+    #[clap(long, short, value_name = "task", multiple_occurrences = true)]
+    task: Vec<String>,
+
+    /// only display the specified system call (or irq)
+    #[clap(long, short, value_name = "operation", multiple_occurrences = true)]
+    op: Vec<String>,
+
+    /// number of events to display before exiting
+    #[clap(
+        long = "count", short = 'n', value_name = "events",
+        parse(try_from_str = parse_int::parse)
+    )]
+    count: Option<usize>,
+
+    /// write events to the specified file in Chrome trace event format
+    #[clap(long, value_name = "filename")]
+    chrome_trace: Option<String>,
+}
+
+//
+// System calls, in the order of the kernel's `Sysnum` enum; this is used
+// only if the enum can't be found in the kernel's debug information.
+//
+const SYSCALLS: &[&str] = &[
+    "send",
+    "recv",
+    "reply",
+    "set_timer",
+    "borrow_read",
+    "borrow_write",
+    "borrow_info",
+    "irq_control",
+    "panic",
+    "get_timer",
+    "refresh_task_id",
+    "post",
+    "reply_fault",
+    "irq_status",
+];
+
+///
+/// Mask of the task index within a task ID; the remaining bits are the
+/// generation.
+///
+const TASK_ID_INDEX_MASK: u32 = 0x3ff;
+
+struct Event {
+    time: f64,
+    task: String,
+    op: String,
+    peer: Option<String>,
+    detail: String,
+}
+
+///
+/// Returns the names of system calls by number, preferring the kernel's
+/// own notion of them.
+///
+fn trace_syscalls(hubris: &HubrisArchive) -> BTreeMap<u32, String> {
+    let snake = |name: &str| {
+        let mut rval = String::new();
+
+        for (i, c) in name.chars().enumerate() {
+            if c.is_ascii_uppercase() && i != 0 {
+                rval.push('_');
+            }
+
+            rval.push(c.to_ascii_lowercase());
+        }
+
+        rval
+    };
+
+    let sysnum = hubris
+        .lookup_module(HubrisTask::Kernel)
+        .ok()
+        .and_then(|kernel| kernel.lookup_enum_byname(hubris, "Sysnum").ok())
+        .flatten();
+
+    match sysnum {
+        Some(sysnum) => sysnum
+            .variants
+            .iter()
+            .filter_map(|v| Some((v.tag? as u32, snake(&v.name))))
+            .collect(),
+        None => SYSCALLS
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (i as u32, name.to_string()))
+            .collect(),
+    }
+}
+
+fn trace_task(hubris: &HubrisArchive, id: u32) -> String {
+    if id & 0xffff == 0xffff {
+        return "kernel".to_string();
+    }
+
+    let index = id & TASK_ID_INDEX_MASK;
+
+    match hubris.lookup_module(HubrisTask::Task(index)) {
+        Ok(module) => module.name.clone(),
+        Err(_) => format!("task{index}"),
+    }
+}
+
+fn trace_breakpoint(hubris: &HubrisArchive, name: &str) -> Result<u32> {
+    match hubris.lookup_symbols(name).iter().find(|(n, _, _)| *n == name) {
+        Some((_, addr, _)) => Ok(addr & !1),
+        None => bail!("could not find {name} in kernel"),
+    }
+}
+
+///
+/// Decodes a system call from the registers of the calling task.
+///
+fn trace_syscall(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    syscalls: &BTreeMap<u32, String>,
+) -> Result<(String, Option<String>, String)> {
+    let mut r = [0u32; 8];
+
+    for (i, reg) in [
+        ARMRegister::R4,
+        ARMRegister::R5,
+        ARMRegister::R6,
+        ARMRegister::R7,
+        ARMRegister::R8,
+        ARMRegister::R9,
+        ARMRegister::R10,
+        ARMRegister::R11,
+    ]
+    .iter()
+    .enumerate()
+    {
+        r[i] = core.read_reg(*reg)?;
+    }
+
+    let op = match syscalls.get(&r[7]) {
+        Some(name) => name.clone(),
+        None => format!("syscall {}", r[7]),
+    };
+
+    let task = |id| Some(trace_task(hubris, id));
+
+    Ok(match op.as_str() {
+        "send" => (
+            op,
+            task(r[0] >> 16),
+            format!(
+                "op={} out={} in={} leases={}",
+                r[0] & 0xffff,
+                r[2],
+                r[4],
+                r[6]
+            ),
+        ),
+        "recv" => (op, None, format!("mask=0x{:x}", r[2])),
+        "reply" => (op, task(r[0]), format!("code={} len={}", r[1], r[3])),
+        "reply_fault" => (op, task(r[0]), format!("reason={}", r[1])),
+        "post" => (op, task(r[0]), format!("bits=0x{:x}", r[1])),
+        "borrow_read" | "borrow_write" => {
+            (op, task(r[0]), format!("lease={} len={}", r[1], r[3]))
+        }
+        "borrow_info" => (op, task(r[0]), format!("lease={}", r[1])),
+        "set_timer" if r[0] != 0 => (
+            op,
+            None,
+            format!(
+                "deadline={} notification=0x{:x}",
+                (r[2] as u64) << 32 | r[1] as u64,
+                r[3]
+            ),
+        ),
+        "set_timer" => (op, None, "disabled".to_string()),
+        "irq_control" => (
+            op,
+            None,
+            format!(
+                "mask=0x{:x} {}",
+                r[0],
+                if r[1] != 0 { "enable" } else { "disable" }
+            ),
+        ),
+        _ => (op, None, String::new()),
+    })
+}
+
+fn trace_event(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    syscalls: &BTreeMap<u32, String>,
+    svcall: bool,
+    time: f64,
+) -> Result<Event> {
+    if svcall {
+        let task = match hubris.current_task(core)? {
+            Some(task) => hubris.lookup_module(task)?.name.clone(),
+            None => "-".to_string(),
+        };
+
+        let (op, peer, detail) = trace_syscall(hubris, core, syscalls)?;
+
+        return Ok(Event { time, task, op, peer, detail });
+    }
+
+    //
+    // For an interrupt, the exception number is in the IPSR; find the task
+    // that it is routed to.
+    //
+    let irq = (core.read_reg(ARMRegister::PSR)? & 0x1ff).saturating_sub(16);
+
+    let owner = hubris.manifest.task_irqs.iter().find_map(|(task, irqs)| {
+        irqs.iter().find(|(_, i)| *i == irq).map(|(mask, _)| (task, *mask))
+    });
+
+    Ok(Event {
+        time,
+        task: "kernel".to_string(),
+        op: format!("irq {irq}"),
+        peer: owner.map(|(task, _)| task.clone()),
+        detail: match owner {
+            Some((_, mask)) => format!("notification=0x{mask:x}"),
+            None => "unowned".to_string(),
+        },
+    })
+}
+
+fn trace_matches(event: &Event, subargs: &TraceArgs) -> bool {
+    let task = subargs.task.is_empty()
+        || subargs
+            .task
+            .iter()
+            .any(|t| *t == event.task || event.peer.as_ref() == Some(t));
+
+    let op = subargs.op.is_empty()
+        || subargs.op.iter().any(|o| {
+            *o == event.op || (o == "irq" && event.op.starts_with("irq "))
+        });
+
+    task && op
+}
+
+///
+/// Adds an event to a Chrome trace.  A send is recorded when it is made,
+/// and turned into a slice on the sender's track when its reply is seen.
+///
+fn trace_chrome(
+    chrome: &mut ChromeTrace,
+    sends: &mut HashMap<String, (f64, String)>,
+    event: &Event,
+) {
+    let mut args = vec![("detail", event.detail.clone())];
+
+    if let Some(ref peer) = event.peer {
+        args.push(("peer", peer.clone()));
+    }
+
+    chrome.instant(&event.task, &event.op, event.time, &args);
+
+    match (event.op.as_str(), &event.peer) {
+        ("send", Some(peer)) => {
+            sends.insert(event.task.clone(), (event.time, peer.clone()));
+        }
+        ("reply" | "reply_fault", Some(peer)) => {
+            if let Some((start, server)) = sends.remove(peer) {
+                if server == event.task {
+                    chrome.slice(
+                        peer,
+                        &format!("send to {server}"),
+                        start,
+                        event.time,
+                        &[("reply", event.detail.clone())],
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn trace_run(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    points: &[u32],
+    subargs: &TraceArgs,
+    done: &AtomicBool,
+    mut chrome: Option<&mut ChromeTrace>,
+) -> Result<usize> {
+    let syscalls = trace_syscalls(hubris);
+    let start = Instant::now();
+    let mut traced = 0;
+    let mut sends = HashMap::new();
+
+    println!("{:>10} {:12} {:12} DETAIL", "TIME", "TASK", "OPERATION");
+
+    core.run()?;
+
+    loop {
+        if done.load(Ordering::SeqCst) {
+            return Ok(traced);
+        }
+
+        if !DHCSR::read(core)?.halted() {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        let time = start.elapsed().as_secs_f64();
+
+        //
+        // The DFSR bits are write-one-to-clear; clear what we have seen.
+        //
+        let dfsr = DFSR::read(core)?;
+        dfsr.write(core)?;
+
+        let pc = core.read_reg(ARMRegister::PC)? & !1;
+
+        let ndx = match points.iter().position(|&p| p == pc) {
+            Some(ndx) if dfsr.breakpoint() => ndx,
+            _ => bail!(
+                "target halted for another reason (DFSR = 0x{:x})",
+                u32::from(dfsr)
+            ),
+        };
+
+        let event = trace_event(hubris, core, &syscalls, ndx == 0, time)?;
+
+        if trace_matches(&event, subargs) {
+            println!(
+                "{:10.6} {:12} {:12} {}{}",
+                event.time,
+                event.task,
+                event.op,
+                match event.peer {
+                    Some(ref peer) => format!("-> {peer} "),
+                    None => String::new(),
+                },
+                event.detail
+            );
+
+            if let Some(ref mut chrome) = chrome {
+                trace_chrome(chrome, &mut sends, &event);
+            }
+
+            traced += 1;
+
+            if Some(traced) == subargs.count {
+                return Ok(traced);
+            }
+        }
+
+        //
+        // A breakpoint halts before the instruction executes; to continue,
+        // we must step over it with its comparator disabled.
+        //
+        let comp = FP_COMP0 + ndx as u32 * 4;
+        let val = core.read_word_32(comp)?;
+        core.write_word_32(comp, 0)?;
+        core.step()?;
+        core.write_word_32(comp, val)?;
+
+        core.run()?;
+    }
+}
+
+fn trace(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+    let subargs = TraceArgs::try_parse_from(subargs)?;
+
+    if subargs.count == Some(0) {
+        bail!("count must be at least 1");
+    }
+
+    for task in &subargs.task {
+        if hubris.lookup_task(task).is_none() && task != "kernel" {
+            bail!("no such task: {task}");
+        }
+    }
+
+    //
+    // Our first breakpoint is the system call entry point; our second is
+    // the handler for all interrupts.
+    //
+    let points = [
+        trace_breakpoint(hubris, "SVCall")?,
+        trace_breakpoint(hubris, "DefaultHandler")?,
+    ];
+
+    let done = humility_cmd::interrupted()?;
+
+    core.halt()?;
+
+    let mut ctrl = FP_CTRL::read(core)?;
+
+    if (ctrl.num_code() as usize) < points.len() {
+        core.run()?;
+        bail!("tracing requires {} breakpoints", points.len());
+    }
+
+    ctrl.set_key(true);
+    ctrl.set_enable(true);
+    ctrl.write(core)?;
+
+    for (i, addr) in points.iter().enumerate() {
+        let val = ctrl.comparator(*addr)?;
+        core.write_word_32(FP_COMP0 + i as u32 * 4, val)?;
+    }
+
+    //
+    // If we are writing a Chrome trace, create the kernel's track and then
+    // a track for each task, so that they appear in task order.
+    //
+    let mut chrome = subargs.chrome_trace.as_ref().map(|_| {
+        let mut chrome = ChromeTrace::new("hubris");
+        chrome.track("kernel");
+
+        for i in 0..hubris.ntasks() {
+            chrome.track(&trace_task(hubris, i as u32));
+        }
+
+        chrome
+    });
+
+    msg!("tracing (^C to stop)");
+
+    let rval =
+        trace_run(hubris, core, &points, &subargs, done, chrome.as_mut());
+
+    //
+    // Regardless of how we got here, disarm our breakpoints and make sure
+    // that the target is running.
+    //
+    for i in 0..points.len() {
+        core.write_word_32(FP_COMP0 + i as u32 * 4, 0)?;
+    }
+
+    core.run()?;
+
+    msg!("{} events traced", rval?);
+
+    if let (Some(chrome), Some(filename)) = (chrome, &subargs.chrome_trace) {
+        chrome.write(filename)?;
+        msg!("Chrome trace written to {filename}");
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: TraceArgs::command(),
+        name: "trace",
+        run: trace,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
@@ -15,7 +15,44 @@
 //! humility: Update done.
 //! ```
 //!
+//! The image to be written can also be specified as a Hubris build archive,
+//! in which case its flash image (`img/final.bin`) is written.  The board
+//! of the archive must match the board of the attached archive; to write an
+//! image for a different board, use `--force`.  The SHA-256 digest of the
+//! image is displayed before it is written:
+//!
+//! ```console
+//! $ humility update build-gimlet-c-image-default.zip
+//! humility: attached via ST-Link V3
+//! humility: writing img/final.bin from build-gimlet-c-image-default.zip
+//! humility: image is 1048576 bytes, SHA-256 9f3c...be21
+//! humility: Starting update using an update block size of 1024
+//! humility: (Erase may take a moment)
+//! humility: Comitting update
+//! humility: Update done.
+//! ```
+//!
+//! To verify the written image, use `--verify` to specify the address at
+//! which the update target is mapped (e.g., 0x08100000 for the second bank
+//! of an STM32H7); once written, the image will be read back from that
+//! address and its digest compared to the digest of the image.  Verification
+//! requires that the memory be readable by the attached core, and therefore
+//! generally requires a debug probe.
+//!
+//! To reboot into the new image once the update has been committed, use
+//! `--reset`.  This uses the update server's `reset` operation if it has
+//! one, `Jefe.request_reset` if not, and finally resets via the debug probe
+//! if neither are available.
+//!
+//! Note that update blocks are delivered as leases to the update server;
+//! as `udprpc` does not support leases, images cannot be written over the
+//! network with `humility update`.  Over the network, an update must be
+//! delivered via the management network (e.g., with `faux-mgs`).
+//!
 
+use humility::core::{Core, CORE_MAX_READSIZE};
+use humility::hubris::{HubrisArchive, HubrisArchiveDoneness};
+use humility::Diagnostic;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::CommandKind;
 use humility_cmd::{Archive, Attach, Command, Validate};
@@ -25,8 +62,9 @@ use humility_log::msg;
 
 use std::path::PathBuf;
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{CommandFactory, Parser};
+use sha2::{Digest, Sha256};
 
 use indicatif::{ProgressBar, ProgressStyle};
 
@@ -46,22 +84,166 @@ struct UpdateArgs {
     #[clap(long)]
     /// Don't commit the image, only write
     skip_commit: bool,
-    /// path to binary to write
+    /// Write an archive's image even if its board doesn't match
+    #[clap(long)]
+    force: bool,
+    /// Verify the written image by reading it back from this address
+    #[clap(
+        long, value_name = "address",
+        parse(try_from_str = parse_int::parse)
+    )]
+    verify: Option<u32>,
+    /// Reset into the new image once the update is committed
+    #[clap(long, conflicts_with = "skip_commit")]
+    reset: bool,
+    /// path to binary (or Hubris archive) to write
     path: PathBuf,
 }
 
+fn digest(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+///
+/// Reads the image to be written.  If the specified file is a Hubris
+/// archive, the image is its flash image, and the archive must be for the
+/// same board as the attached archive unless we have been told otherwise.
+///
+fn update_image(
+    hubris: &HubrisArchive,
+    subargs: &UpdateArgs,
+) -> Result<Vec<u8>> {
+    let contents = std::fs::read(&subargs.path)?;
+
+    if !contents.starts_with(b"PK\x03\x04") {
+        return Ok(contents);
+    }
+
+    let path = subargs.path.display().to_string();
+    let mut archive = HubrisArchive::new()?;
+    archive
+        .load(&path, HubrisArchiveDoneness::Raw)
+        .with_context(|| format!("failed to load archive {path}"))?;
+
+    let board = match archive.read_file("app.toml")? {
+        Some(app) => toml::from_slice::<toml::Value>(&app)?
+            .get("board")
+            .and_then(|b| b.as_str())
+            .map(str::to_string),
+        None => None,
+    };
+
+    if board != hubris.manifest.board && !subargs.force {
+        bail!(Diagnostic::error(format!(
+            "{path} is for board {}, but attached archive is for {}",
+            board.as_deref().unwrap_or("<unknown>"),
+            hubris.manifest.board.as_deref().unwrap_or("<unknown>"),
+        ))
+        .code("board-mismatch")
+        .hint("use --force to write it anyway"));
+    }
+
+    msg!("writing img/final.bin from {path}");
+
+    archive
+        .read_file("img/final.bin")?
+        .ok_or_else(|| anyhow!("{path} does not contain img/final.bin"))
+}
+
+fn update_verify(core: &mut dyn Core, addr: u32, image: &[u8]) -> Result<()> {
+    let mut buf = vec![0u8; image.len()];
+
+    for (i, chunk) in buf.chunks_mut(CORE_MAX_READSIZE).enumerate() {
+        core.read_8(addr + (i * CORE_MAX_READSIZE) as u32, chunk)
+            .with_context(|| {
+                format!("failed to read back image at 0x{addr:08x}")
+            })?;
+    }
+
+    let (expected, actual) = (digest(image), digest(&buf));
+
+    if expected != actual {
+        let offset = image.iter().zip(buf.iter()).position(|(a, b)| a != b);
+
+        bail!(
+            "verification failed: image at 0x{:08x} has SHA-256 {}, \
+            expected {} (first difference at offset 0x{:x})",
+            addr,
+            actual,
+            expected,
+            offset.unwrap_or(0)
+        );
+    }
+
+    msg!("verified image at 0x{addr:08x}");
+    Ok(())
+}
+
+///
+/// Resets the target into the new image, preferring the update server's
+/// own reset operation.  As the target resets out from under the call, we
+/// don't expect a reply.
+///
+fn update_reset(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+) -> Result<()> {
+    let op = hubris
+        .get_idol_command("Update.reset")
+        .or_else(|_| hubris.get_idol_command("Jefe.request_reset"));
+
+    match op {
+        Ok(op) => {
+            msg!("resetting via {}.{}", op.name.0, op.name.1);
+
+            if let Ok(Err(e)) =
+                hiffy_call(hubris, core, context, &op, &[], None)
+            {
+                bail!("Hiffy error resetting {}", e);
+            }
+        }
+        Err(_) => {
+            msg!("resetting via probe");
+            core.reset()?;
+        }
+    }
+
+    Ok(())
+}
+
 fn update(context: &mut ExecutionContext) -> Result<()> {
     let core = &mut **context.core.as_mut().unwrap();
     let hubris = context.archive.as_ref().unwrap();
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
 
     let subargs = UpdateArgs::try_parse_from(subargs)?;
+
+    if core.is_net() {
+        bail!(Diagnostic::error(
+            "cannot write an update over the network: update blocks are \
+            delivered as leases, which udprpc does not support"
+        )
+        .code("requires-probe")
+        .hint("use a debug probe")
+        .hint("update via the management network (e.g., faux-mgs)"));
+    }
+
+    let binary_contents = update_image(hubris, &subargs)?;
+
+    msg!(
+        "image is {} bytes, SHA-256 {}",
+        binary_contents.len(),
+        digest(&binary_contents)
+    );
+
     let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
 
     let start = hubris.get_idol_command("Update.prep_image_update")?;
     let write = hubris.get_idol_command("Update.write_one_block")?;
     let finish = hubris.get_idol_command("Update.finish_image_update")?;
     let block_size = hubris.get_idol_command("Update.block_size")?;
+    let abort = hubris.get_idol_command("Update.abort_update").ok();
 
     let blk_size =
         match hiffy_call(hubris, core, &mut context, &block_size, &[], None)? {
@@ -71,9 +253,12 @@ fn update(context: &mut ExecutionContext) -> Result<()> {
             Err(e) => bail!("Hiffy error getting block size {}", e),
         };
 
+    if blk_size == 0 {
+        bail!("update server reported an update block size of 0");
+    }
+
     msg!("Starting update using an update block size of {blk_size}");
     msg!("(Erase may take a moment)");
-    let binary_contents = std::fs::read(&subargs.path)?;
 
     // Modern SP images don't accept a image_target argument, because they
     // always update the alternate image.  Older SP images _also_ always update
@@ -107,17 +292,30 @@ fn update(context: &mut ExecutionContext) -> Result<()> {
     for (i, c) in binary_contents.chunks(blk_size as usize).enumerate() {
         bar.set_position((i * (blk_size as usize)) as u64);
 
-        match hiffy_call(
+        let err = match hiffy_call(
             hubris,
             core,
             &mut context,
             &write,
             &[("block_num", IdolArgument::Scalar(i as u64))],
             Some(HiffyLease::Write(c)),
-        )? {
-            Ok(_) => (),
-            Err(e) => bail!("Hiffy error writing block #{} {}", i, e),
+        ) {
+            Ok(Ok(_)) => continue,
+            Ok(Err(e)) => anyhow!("Hiffy error writing block #{} {}", i, e),
+            Err(e) => e.context(format!("failed to write block #{i}")),
+        };
+
+        //
+        // Don't leave the update server with a partially written image.
+        //
+        bar.finish_and_clear();
+
+        if let Some(abort) = &abort {
+            msg!("aborting update");
+            let _ = hiffy_call(hubris, core, &mut context, abort, &[], None);
         }
+
+        return Err(err);
     }
 
     bar.finish_and_clear();
@@ -131,7 +329,17 @@ fn update(context: &mut ExecutionContext) -> Result<()> {
             Err(e) => bail!("Hiffy error committing update {}", e),
         }
     }
+
+    if let Some(addr) = subargs.verify {
+        update_verify(core, addr, &binary_contents)?;
+    }
+
     msg!("Update done.");
+
+    if subargs.reset {
+        update_reset(hubris, core, &mut context)?;
+    }
+
     Ok(())
 }
 
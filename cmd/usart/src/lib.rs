@@ -0,0 +1,121 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility usart`
+//!
+//! `humility usart` opens an interactive console on a UART attached to the SP,
+//! by way of a Hubris task that exposes it via an Idol interface with
+//! `uart_read` and `uart_write` operations (e.g., `ControlPlaneAgent`),
+//! obviating the need for a separate serial cable.  Data is polled from the SP
+//! at the interval specified by `--poll-interval` (`-p`), or immediately when
+//! more is pending.
+//!
+//! If more than one such interface is present in the archive, the interface
+//! must be specified with `--interface` (`-i`); use `--list` (`-l`) to list the
+//! candidate interfaces:
+//!
+//! ```console
+//! % humility usart --list
+//! ControlPlaneAgent
+//! % humility usart
+//! humility: attached via ST-Link V3
+//! humility: attached to ControlPlaneAgent; exit with Ctrl-A Ctrl-X
+//! ```
+//!
+//! By default, the terminal is put in raw mode, and an escape character (Ctrl-A
+//! by default; change it with `--escape` (`-e`), e.g. `-e t` for Ctrl-T)
+//! introduces commands:  the escape character followed by Ctrl-X exits, the
+//! escape character followed by itself sends the escape character, and the
+//! escape character followed by Ctrl-H displays help.  With `--no-raw`, the
+//! terminal is left in cooked mode and the console exits on end-of-file.
+//!
+//! To record everything read from the UART, specify a file with `--log`; data
+//! is appended to the file as it is received.  If the interface has a notion of
+//! which client owns the UART (as `ControlPlaneAgent` does), ownership is
+//! returned to the SP on exit.
+//!
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+
+#[cfg(not(windows))]
+mod posix;
+
+#[cfg(not(windows))]
+use posix::usart;
+
+#[cfg(windows)]
+fn usart(_context: &mut humility_cli::ExecutionContext) -> Result<()> {
+    bail!("the usart subcommand is not available on Windows")
+}
+
+#[derive(Parser, Debug)]
+#[clap(name = "usart", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct UsartArgs {
+    /// sets timeout
+    #[clap(
+        long,
+        short = 'T',
+        default_value_t = 5000,
+        value_name = "timeout_ms"
+    )]
+    timeout: u32,
+
+    /// frequency of polling the SP for new data
+    #[clap(
+        long,
+        short,
+        default_value_t = 100,
+        value_name = "poll_interval_ms"
+    )]
+    poll_interval: u32,
+
+    /// Idol interface to use, which must have `uart_read` and `uart_write`
+    /// operations
+    #[clap(long, short, value_name = "interface")]
+    interface: Option<String>,
+
+    /// list interfaces that can be used as a console and exit
+    #[clap(long, short, conflicts_with = "interface")]
+    list: bool,
+
+    /// do not put the terminal in raw mode
+    #[clap(long)]
+    no_raw: bool,
+
+    /// escape character, as a letter denoting a control character
+    #[clap(
+        long, short, default_value = "a",
+        parse(try_from_str = parse_escape)
+    )]
+    escape: u8,
+
+    /// record all data read from the UART to the specified file
+    #[clap(long, value_name = "filename")]
+    log: Option<PathBuf>,
+}
+
+fn parse_escape(s: &str) -> Result<u8> {
+    match s.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Ok(c.to_ascii_uppercase() - b'@'),
+        _ => bail!("escape character must be a single letter"),
+    }
+}
+
+pub fn init() -> Command {
+    Command {
+        app: UsartArgs::command(),
+        name: "usart",
+        run: usart,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
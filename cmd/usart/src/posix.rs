@@ -0,0 +1,368 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::time::Duration;
+use std::{io, thread};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use crossbeam_channel::{select, Sender};
+
+use humility::core::Core;
+use humility::hubris::HubrisArchive;
+use humility::msg;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::UnrawTermiosGuard;
+use humility_hiffy::{HiffyContext, HiffyLease};
+use humility_idol::{IdolArgument, IdolOperation};
+
+use super::UsartArgs;
+
+const BUF_SIZE: usize = 256;
+
+///
+/// Returns the names of all Idol interfaces that can serve as a console:
+/// those that have both `uart_read` and `uart_write` operations.
+///
+fn interfaces(hubris: &HubrisArchive) -> Vec<&str> {
+    hubris
+        .modules()
+        .filter_map(|m| m.iface.as_ref())
+        .filter(|iface| {
+            iface.ops.contains_key("uart_read")
+                && iface.ops.contains_key("uart_write")
+        })
+        .map(|iface| iface.name.as_str())
+        .collect()
+}
+
+///
+/// Performs an Idol call, returning its result as a count of bytes.
+///
+fn call(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    op: &IdolOperation,
+    args: &[(&str, IdolArgument)],
+    lease: Option<HiffyLease>,
+) -> Result<usize> {
+    let value =
+        humility_hiffy::hiffy_call(hubris, core, context, op, args, lease)?;
+
+    let v = match value {
+        Ok(v) => v,
+        Err(e) => bail!("Got Hiffy error: {e}"),
+    };
+
+    Ok(v.as_base().ok().and_then(|v| v.as_u32()).unwrap_or(0) as usize)
+}
+
+struct UsartConsole<'a> {
+    hubris: &'a HubrisArchive,
+    core: &'a mut dyn Core,
+    context: HiffyContext<'a>,
+    read: IdolOperation<'a>,
+    write: IdolOperation<'a>,
+    detach: Option<IdolOperation<'a>>,
+    poll_interval: Duration,
+}
+
+impl<'a> UsartConsole<'a> {
+    fn new(
+        hubris: &'a HubrisArchive,
+        core: &'a mut dyn Core,
+        interface: &str,
+        subargs: &UsartArgs,
+    ) -> Result<Self> {
+        let context = HiffyContext::new(hubris, core, subargs.timeout)?;
+        let read = IdolOperation::new(hubris, interface, "uart_read", None)?;
+        let write = IdolOperation::new(hubris, interface, "uart_write", None)?;
+
+        //
+        // If the interface has a notion of which client owns the UART (as
+        // the control plane agent does), we will hand it back on exit.
+        //
+        let detach = IdolOperation::new(
+            hubris,
+            interface,
+            "set_humility_uart_client",
+            None,
+        )
+        .ok();
+
+        Ok(Self {
+            hubris,
+            core,
+            context,
+            read,
+            write,
+            detach,
+            poll_interval: Duration::from_millis(u64::from(
+                subargs.poll_interval,
+            )),
+        })
+    }
+
+    fn uart_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = buf.len().min(self.context.rdata_size());
+
+        call(
+            self.hubris,
+            self.core,
+            &mut self.context,
+            &self.read,
+            &[],
+            Some(HiffyLease::Read(&mut buf[..len])),
+        )
+    }
+
+    fn uart_write(&mut self, buf: &[u8]) -> Result<usize> {
+        let len = buf.len().min(BUF_SIZE).min(self.context.data_size());
+
+        call(
+            self.hubris,
+            self.core,
+            &mut self.context,
+            &self.write,
+            &[],
+            Some(HiffyLease::Write(&buf[..len])),
+        )
+    }
+
+    fn detach(&mut self) -> Result<()> {
+        if let Some(op) = &self.detach {
+            call(
+                self.hubris,
+                self.core,
+                &mut self.context,
+                op,
+                &[("attach", IdolArgument::String("false"))],
+                None,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn run(
+        &mut self,
+        raw: bool,
+        escape: u8,
+        mut log: Option<File>,
+    ) -> Result<()> {
+        let _guard = if raw {
+            Some(UnrawTermiosGuard::make_stdout_raw()?)
+        } else {
+            None
+        };
+
+        let (stdin_tx, stdin_rx) = crossbeam_channel::unbounded();
+        thread::spawn(move || stdin_reader(raw, escape, stdin_tx));
+
+        let mut rx_buf = vec![0; BUF_SIZE];
+        let mut tx_buf = Vec::new();
+        let mut stdout = io::stdout().lock();
+
+        let result = 'outer: loop {
+            if !tx_buf.is_empty() {
+                match self.uart_write(&tx_buf) {
+                    Ok(nwritten) => {
+                        tx_buf.drain(0..nwritten.min(tx_buf.len()));
+                    }
+                    Err(err) => break Err(err),
+                }
+            }
+
+            let nread = match self.uart_read(&mut rx_buf) {
+                Ok(nread) => nread.min(rx_buf.len()),
+                Err(err) => break Err(err),
+            };
+
+            if nread > 0 {
+                let data = &rx_buf[..nread];
+
+                if let Some(log) = log.as_mut() {
+                    if let Err(err) = log
+                        .write_all(data)
+                        .and_then(|()| log.flush())
+                        .context("error writing to logfile")
+                    {
+                        break Err(err);
+                    }
+                }
+
+                if let Err(err) = stdout
+                    .write_all(data)
+                    .and_then(|()| stdout.flush())
+                    .context("error writing to stdout")
+                {
+                    break Err(err);
+                }
+            }
+
+            //
+            // If our read filled our buffer or we still have data to send,
+            // don't wait for the poll interval to try again.
+            //
+            let timeout = if nread == rx_buf.len() || !tx_buf.is_empty() {
+                Duration::from_millis(1)
+            } else {
+                self.poll_interval
+            };
+
+            let timeout = crossbeam_channel::after(timeout);
+
+            loop {
+                select! {
+                    recv(stdin_rx) -> data => {
+                        match data {
+                            Ok(mut data) => tx_buf.append(&mut data),
+                            Err(_) => break 'outer Ok(()),
+                        }
+                    },
+                    recv(timeout) -> _ => break,
+                }
+            }
+        };
+
+        //
+        // Even if we failed, we want to attempt to hand the UART back.
+        //
+        let detached = self.detach();
+        result.and(detached)
+    }
+}
+
+fn help(escape: u8) {
+    let c = (escape + b'@') as char;
+
+    eprint!(
+        "\r\n*** humility usart: escape is Ctrl-{c}\r\n\
+        ***   Ctrl-{c} Ctrl-X   exit\r\n\
+        ***   Ctrl-{c} Ctrl-{c}   send Ctrl-{c}\r\n\
+        ***   Ctrl-{c} Ctrl-H   show this help\r\n"
+    );
+}
+
+///
+/// Reads from stdin, handling our escape sequences:  the escape character
+/// followed by Ctrl-X exits; the escape character followed by itself sends
+/// the escape character; the escape character followed by Ctrl-H (or `?`)
+/// displays help.  Other escape sequences are discarded.
+///
+fn stdin_reader(raw: bool, escape: u8, tx: Sender<Vec<u8>>) {
+    const CTRL_H: u8 = b'\x08';
+    const CTRL_X: u8 = b'\x18';
+
+    let mut stdin = io::stdin().lock();
+    let mut buf = vec![0; 1024];
+    let mut escaped = false;
+
+    loop {
+        let n = match stdin.read(&mut buf[..]) {
+            Ok(0) => return,
+            Ok(n) => n,
+            Err(err) => panic!("error reading from stdin: {err}"),
+        };
+
+        if !raw {
+            _ = tx.send(buf[..n].to_vec());
+            continue;
+        }
+
+        let mut data = Vec::with_capacity(n);
+
+        for &b in &buf[..n] {
+            if !escaped {
+                if b == escape {
+                    escaped = true;
+                } else {
+                    data.push(b);
+                }
+
+                continue;
+            }
+
+            escaped = false;
+
+            match b {
+                CTRL_X | b'x' | b'X' => {
+                    _ = tx.send(data);
+                    return;
+                }
+                CTRL_H | b'h' | b'?' => help(escape),
+                _ if b == escape => data.push(b),
+                _ => {}
+            }
+        }
+
+        _ = tx.send(data);
+    }
+}
+
+pub(super) fn usart(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = UsartArgs::try_parse_from(subargs)?;
+    let hubris = context.archive.as_ref().unwrap();
+    let candidates = interfaces(hubris);
+
+    if subargs.list {
+        if candidates.is_empty() {
+            msg!("no interfaces with uart_read and uart_write found");
+        }
+
+        for interface in candidates {
+            println!("{interface}");
+        }
+
+        return Ok(());
+    }
+
+    if context.is_interactive {
+        bail!("`usart` cannot be used from the REPL");
+    }
+
+    let interface = match (&subargs.interface, candidates.as_slice()) {
+        (Some(interface), _) => interface.as_str(),
+        (None, [interface]) => *interface,
+        (None, []) => {
+            bail!("no interfaces with uart_read and uart_write found");
+        }
+        (None, _) => {
+            bail!(
+                "multiple UART interfaces found ({}); specify one with -i",
+                candidates.join(", ")
+            );
+        }
+    };
+
+    let log = subargs
+        .log
+        .as_ref()
+        .map(|path| {
+            File::options()
+                .append(true)
+                .create(true)
+                .open(path)
+                .with_context(|| format!("failed to open {}", path.display()))
+        })
+        .transpose()?;
+
+    let raw = !subargs.no_raw;
+    let mut console = UsartConsole::new(hubris, core, interface, &subargs)
+        .with_context(|| format!("failed to open console on {interface}"))?;
+
+    if raw {
+        let c = (subargs.escape + b'@') as char;
+        msg!("attached to {interface}; exit with Ctrl-{c} Ctrl-X");
+    } else {
+        msg!("attached to {interface}; exit with Ctrl-D");
+    }
+
+    console.run(raw, subargs.escape, log)
+}
@@ -41,11 +41,64 @@
 //! 25 validated    4 F  -   0x67 bmr491        Intermediate bus converter
 //! ```
 //!
+//! To instead run a suite of board health checks (e.g., as part of
+//! manufacturing test), use `--suite` (`-s`).  In addition to validating
+//! every device (or every device specified via the options above), this
+//! checks that every PMBus rail is free of latched faults and is within
+//! tolerance of its commanded output voltage, that the CPU clock matches
+//! the clock that the kernel was built for, and that no task has faulted.
+//! Each item checked is reported as passing, failing or skipped (e.g., a
+//! rail that is off, or a clock that cannot be determined):
+//!
+//! ```console
+//! $ humility validate --suite
+//! humility: attached via ST-Link V3
+//! CHECK   RESULT ITEM                                     DETAIL
+//! devices PASS   0: tmp117 Southwest                      validated
+//! devices PASS   3: pca9545 (U.2 ABCD mux)                present
+//! devices FAIL   13: sbtsi (CPU temperature sensor)       absent (device is not present)
+//! ...
+//! rails   PASS   V3P3_SYS_A0                              3.309V (commanded 3.300V)
+//! rails   SKIP   VDD_MEM_EFGH                             0.000V (commanded 1.200V) (rail is off)
+//! rails   FAIL   VPP_ABCD                                 2.291V (commanded 2.500V) (output voltage deviates by -8.4%)
+//! clocks  PASS   cpu                                      400.000 MHz
+//! tasks   PASS   jefe                                     Runnable
+//! tasks   FAIL   spi2_driver                              Panic (task has faulted: Panic)
+//! ...
+//! humility: 61 passed, 3 failed, 4 skipped
+//! humility: 3 check(s) failed
+//! ```
+//!
+//! If any check fails, the command fails.  The checks to be run can be
+//! specified with a checklist file via `--checklist`; only checks that
+//! appear in the checklist are run, and each can be configured:
+//!
+//! ```toml
+//! [devices]
+//! optional = ["sbtsi", "U2_N0"]   # devices (by type or name) that may be absent
+//!
+//! [rails]
+//! tolerance = 3.0                 # in percent; 5.0 by default
+//! on = ["V3P3_SYS_A0"]            # rails that must be on
+//! skip = ["VDD_MEM_EFGH"]         # rails not to check
+//!
+//! [clocks]
+//! oscillator = 8000000            # external oscillator, in Hz
+//!
+//! [tasks]
+//! skip = ["hiffy"]                # tasks not to check
+//! ```
+//!
+//! Removable devices may always be absent.  To additionally write the
+//! results as JUnit XML (with each check as a test suite, and each item
+//! checked as a test case), specify a file with `--junit`.
+//!
 
-use anyhow::{anyhow, Result};
+use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser};
 use colored::Colorize;
 use hif::*;
+use humility::core::Core;
 use humility::hubris::*;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
@@ -53,6 +106,8 @@ use humility_hiffy::HiffyContext;
 use humility_i2c::I2cArgs;
 use humility_idol::{self as idol, HubrisIdol};
 
+mod suite;
+
 #[derive(Parser, Debug)]
 #[clap(name = "validate", about = env!("CARGO_PKG_DESCRIPTION"))]
 struct ValidateArgs {
@@ -96,6 +151,18 @@ struct ValidateArgs {
         conflicts_with_all = &["port", "controller", "bus", "device"]
     )]
     id: Option<usize>,
+
+    /// run the full suite of board health checks
+    #[clap(long, short, conflicts_with = "list")]
+    suite: bool,
+
+    /// run the checks specified in the given checklist
+    #[clap(long, value_name = "file", conflicts_with = "list")]
+    checklist: Option<String>,
+
+    /// write the results of the checks as JUnit XML to the given file
+    #[clap(long, value_name = "file", conflicts_with = "list")]
+    junit: Option<String>,
 }
 
 fn list(hubris: &HubrisArchive, hargs: &Option<I2cArgs>) -> Result<()> {
@@ -131,6 +198,76 @@ fn list(hubris: &HubrisArchive, hargs: &Option<I2cArgs>) -> Result<()> {
 
     Ok(())
 }
+///
+/// The result of validating a device (or the error in doing so):  either
+/// the name of a variant, or (if the result does not correspond to a
+/// variant) a formatted value.
+///
+pub(crate) enum DeviceValidation {
+    Variant(String),
+    Value(String),
+}
+
+///
+/// Validates the specified devices via the `validate` task.
+///
+pub(crate) fn validate_i2c(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    devices: &[(usize, &HubrisI2cDevice)],
+) -> Result<Vec<Result<DeviceValidation, DeviceValidation>>> {
+    let op = hubris.get_idol_command("Validate.validate_i2c")?;
+    let mut ops = vec![];
+
+    for (ndx, _) in devices {
+        let payload =
+            op.payload(&[("index", idol::IdolArgument::Scalar(*ndx as u64))])?;
+        context.idol_call_ops(&op, &payload, &mut ops)?;
+    }
+
+    ops.push(Op::Done);
+
+    let results = context.run(core, ops.as_slice(), None)?;
+
+    let fmt = HubrisPrintFormat {
+        newline: false,
+        hex: true,
+        ..HubrisPrintFormat::default()
+    };
+
+    let ok = hubris.lookup_enum(op.ok)?;
+
+    let err = match op.error {
+        idol::IdolError::CLike(err) => err,
+        _ => bail!("unexpected error type {:?}", op.error),
+    };
+
+    results
+        .iter()
+        .map(|result| {
+            Ok(match result {
+                Ok(val) => match ok.lookup_variant_by_tag(val[0].into()) {
+                    Some(variant) => {
+                        Ok(DeviceValidation::Variant(variant.name.clone()))
+                    }
+                    None => Ok(DeviceValidation::Value(
+                        hubris.printfmt(val, op.ok, fmt)?,
+                    )),
+                },
+                Err(e) => match err.lookup_variant_by_tag(*e as u64) {
+                    Some(variant) => {
+                        Err(DeviceValidation::Variant(variant.name.clone()))
+                    }
+                    None => {
+                        Err(DeviceValidation::Value(format!("Err(0x{:x?})", e)))
+                    }
+                },
+            })
+        })
+        .collect()
+}
+
 fn validate(context: &mut ExecutionContext) -> Result<()> {
     let core = &mut **context.core.as_mut().unwrap();
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
@@ -155,10 +292,6 @@ fn validate(context: &mut ExecutionContext) -> Result<()> {
         return Ok(());
     }
 
-    let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
-    let op = hubris.get_idol_command("Validate.validate_i2c")?;
-    let mut ops = vec![];
-
     let mut devices = vec![];
 
     for (ndx, device) in hubris.manifest.i2c_devices.iter().enumerate() {
@@ -179,66 +312,57 @@ fn validate(context: &mut ExecutionContext) -> Result<()> {
         }
 
         devices.push((ndx, device));
-
-        let payload =
-            op.payload(&[("index", idol::IdolArgument::Scalar(ndx as u64))])?;
-        context.idol_call_ops(&op, &payload, &mut ops)?;
     }
 
-    ops.push(Op::Done);
+    let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
 
-    let results = context.run(core, ops.as_slice(), None)?;
+    if subargs.suite || subargs.checklist.is_some() || subargs.junit.is_some() {
+        let checklist = match &subargs.checklist {
+            Some(filename) => suite::Checklist::from_file(filename)?,
+            None => suite::Checklist::default(),
+        };
 
-    let fmt = HubrisPrintFormat {
-        newline: false,
-        hex: true,
-        ..HubrisPrintFormat::default()
-    };
+        return suite::run(
+            hubris,
+            core,
+            &mut context,
+            &devices,
+            &checklist,
+            subargs.junit.as_deref(),
+        );
+    }
+
+    let results = validate_i2c(hubris, core, &mut context, &devices)?;
 
     println!(
         "{:2} {:11} {:>2} {:2} {:3} {:4} {:13} DESCRIPTION",
         "ID", "VALIDATION", "C", "P", "MUX", "ADDR", "DEVICE"
     );
 
-    let ok = hubris.lookup_enum(op.ok)?;
-
-    for (rndx, (ndx, device)) in devices.iter().enumerate() {
-        let result = match &results[rndx] {
-            Ok(val) => {
-                if let Some(variant) = ok.lookup_variant_by_tag(val[0].into()) {
-                    Ok(match variant.name.as_str() {
-                        "Present" => "present".yellow(),
-                        "Validated" => "validated".green(),
-                        _ => format!("<{}>", variant.name).cyan(),
-                    })
-                } else {
-                    Ok(hubris.printfmt(val, op.ok, fmt)?.white())
+    for ((ndx, device), result) in devices.iter().zip(results) {
+        let result = match result {
+            Ok(DeviceValidation::Variant(name)) => match name.as_str() {
+                "Present" => "present".yellow(),
+                "Validated" => "validated".green(),
+                _ => format!("<{}>", name).cyan(),
+            },
+            Ok(DeviceValidation::Value(val)) => val.white(),
+            Err(DeviceValidation::Variant(name)) => match name.as_str() {
+                "NotPresent" => {
+                    if device.removable {
+                        "removed".blue()
+                    } else {
+                        "absent".red()
+                    }
                 }
-            }
-            Err(e) => {
-                if let idol::IdolError::CLike(err) = op.error {
-                    Ok(match err.lookup_variant_by_tag(*e as u64) {
-                        Some(variant) => match variant.name.as_str() {
-                            "NotPresent" => {
-                                if device.removable {
-                                    "removed".blue()
-                                } else {
-                                    "absent".red()
-                                }
-                            }
-                            "BadValidation" => "failed".red(),
-                            "DeviceTimeout" => "timeout".red(),
-                            "DeviceError" => "error".red(),
-                            "Unavailable" => "unavailable".yellow(),
-                            _ => format!("<{}>", variant.name).red(),
-                        },
-                        None => format!("Err(0x{:x?})", e).red(),
-                    })
-                } else {
-                    Err(anyhow!("unexpected error type {:?}", op.error))
-                }
-            }
-        }?;
+                "BadValidation" => "failed".red(),
+                "DeviceTimeout" => "timeout".red(),
+                "DeviceError" => "error".red(),
+                "Unavailable" => "unavailable".yellow(),
+                _ => format!("<{}>", name).red(),
+            },
+            Err(DeviceValidation::Value(val)) => val.red(),
+        };
 
         let mux = match (device.mux, device.segment) {
             (Some(m), Some(s)) => format!("{}:{}", m, s),
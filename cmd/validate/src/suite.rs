@@ -0,0 +1,476 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! A suite of board health checks:  I2C devices, PMBus rails, the CPU
+//! clock and tasks.  Which checks are run (and how) is described by a
+//! checklist; the results are reported as a pass/fail report and,
+//! optionally, as JUnit XML for consumption by manufacturing test.
+//!
+
+use crate::{validate_i2c, DeviceValidation};
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use humility::core::Core;
+use humility::hubris::*;
+use humility::reflect;
+use humility_doppel::{Task, TaskState};
+use humility_hiffy::HiffyContext;
+use serde::Deserialize;
+use std::fmt::Write as _;
+
+///
+/// The checks to be run.  A check that is absent from a checklist file is
+/// not run; the default checklist runs every check with its defaults.
+///
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Checklist {
+    devices: Option<DevicesCheck>,
+    rails: Option<RailsCheck>,
+    clocks: Option<ClocksCheck>,
+    tasks: Option<TasksCheck>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DevicesCheck {
+    /// Devices (by name or by device type) that may be absent
+    #[serde(default)]
+    optional: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RailsCheck {
+    /// Permitted deviation from the commanded output voltage, in percent
+    #[serde(default = "RailsCheck::default_tolerance")]
+    tolerance: f32,
+
+    /// Rails that must be on
+    #[serde(default)]
+    on: Vec<String>,
+
+    /// Rails that are not to be checked
+    #[serde(default)]
+    skip: Vec<String>,
+}
+
+impl RailsCheck {
+    fn default_tolerance() -> f32 {
+        5.0
+    }
+}
+
+impl Default for RailsCheck {
+    fn default() -> Self {
+        Self { tolerance: Self::default_tolerance(), on: vec![], skip: vec![] }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClocksCheck {
+    /// Frequency of the external oscillator, in Hz
+    oscillator: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TasksCheck {
+    /// Tasks that are not to be checked
+    #[serde(default)]
+    skip: Vec<String>,
+}
+
+impl Default for Checklist {
+    fn default() -> Self {
+        Self {
+            devices: Some(DevicesCheck::default()),
+            rails: Some(RailsCheck::default()),
+            clocks: Some(ClocksCheck::default()),
+            tasks: Some(TasksCheck::default()),
+        }
+    }
+}
+
+impl Checklist {
+    pub fn from_file(filename: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(filename)
+            .with_context(|| format!("failed to read {filename}"))?;
+
+        toml::from_str(&contents)
+            .with_context(|| format!("failed to parse checklist {filename}"))
+    }
+}
+
+enum Verdict {
+    Pass,
+    Fail(String),
+    Skip(String),
+}
+
+struct Outcome {
+    check: &'static str,
+    item: String,
+    detail: String,
+    verdict: Verdict,
+}
+
+impl Outcome {
+    fn new(check: &'static str, item: String, detail: String) -> Self {
+        Self { check, item, detail, verdict: Verdict::Pass }
+    }
+
+    fn fail(mut self, reason: String) -> Self {
+        self.verdict = Verdict::Fail(reason);
+        self
+    }
+
+    fn skip(mut self, reason: String) -> Self {
+        self.verdict = Verdict::Skip(reason);
+        self
+    }
+}
+
+fn check_devices(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    devices: &[(usize, &HubrisI2cDevice)],
+    check: &DevicesCheck,
+) -> Result<Vec<Outcome>> {
+    let results = validate_i2c(hubris, core, context, devices)?;
+    let mut outcomes = vec![];
+
+    for ((ndx, device), result) in devices.iter().zip(results) {
+        let item = match &device.name {
+            Some(name) => format!("{ndx}: {} {name}", device.device),
+            None => {
+                format!("{ndx}: {} ({})", device.device, device.description)
+            }
+        };
+
+        let optional = device.removable
+            || check.optional.iter().any(|o| {
+                *o == device.device || Some(o) == device.name.as_ref()
+            });
+
+        let outcome = match result {
+            Ok(DeviceValidation::Variant(name)) => {
+                Outcome::new("devices", item, name.to_lowercase())
+            }
+            Ok(DeviceValidation::Value(val)) => {
+                Outcome::new("devices", item, val)
+            }
+            Err(DeviceValidation::Variant(name)) if name == "NotPresent" => {
+                if optional {
+                    Outcome::new("devices", item, "absent".to_string())
+                } else {
+                    Outcome::new("devices", item, "absent".to_string())
+                        .fail("device is not present".to_string())
+                }
+            }
+            Err(DeviceValidation::Variant(name))
+            | Err(DeviceValidation::Value(name)) => {
+                Outcome::new("devices", item, name.clone())
+                    .fail(format!("validation failed: {name}"))
+            }
+        };
+
+        outcomes.push(outcome);
+    }
+
+    Ok(outcomes)
+}
+
+fn check_rails(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    check: &RailsCheck,
+) -> Result<Vec<Outcome>> {
+    let mut rails = vec![];
+    let mut names = vec![];
+
+    for device in &hubris.manifest.i2c_devices {
+        let HubrisI2cDeviceClass::Pmbus { rails: all } = &device.class else {
+            continue;
+        };
+
+        for (rnum, rail) in all.iter().enumerate() {
+            if !check.skip.contains(&rail.name) {
+                rails.push((device, rnum));
+                names.push(&rail.name);
+            }
+        }
+    }
+
+    let statuses = humility_pmbus::rail_status(core, context, &rails)?;
+    let mut outcomes = vec![];
+
+    for (name, status) in names.into_iter().zip(statuses) {
+        if let Some(error) = &status.error {
+            outcomes.push(
+                Outcome::new("rails", name.clone(), "-".to_string())
+                    .fail(format!("rail could not be read: {error}")),
+            );
+            continue;
+        }
+
+        let detail = match (status.vout, status.vout_command) {
+            (Some(vout), Some(command)) => {
+                format!("{vout:.3}V (commanded {command:.3}V)")
+            }
+            (Some(vout), None) => format!("{vout:.3}V"),
+            _ => "-".to_string(),
+        };
+
+        let outcome = Outcome::new("rails", name.clone(), detail);
+
+        outcomes.push(if !status.faults.is_empty() {
+            let faults = status.faults.join(", ");
+            outcome.fail(format!("faults latched: {faults}"))
+        } else if status.off {
+            if check.on.contains(name) {
+                outcome.fail("rail is off".to_string())
+            } else {
+                outcome.skip("rail is off".to_string())
+            }
+        } else {
+            match status.deviation() {
+                Some(pct) if pct.abs() > check.tolerance => outcome
+                    .fail(format!("output voltage deviates by {pct:+.1}%")),
+                Some(_) => outcome,
+                None => outcome.fail("output voltage unknown".to_string()),
+            }
+        });
+    }
+
+    Ok(outcomes)
+}
+
+fn check_clocks(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    check: &ClocksCheck,
+) -> Result<Vec<Outcome>> {
+    let oscillator = check.oscillator.map(|hz| hz as f64);
+    let mhz = |hz: f64| format!("{:.3} MHz", hz / 1_000_000.0);
+
+    let cpu = cmd_clocks::cpu_clock(hubris, core, oscillator)?;
+    let expected = cmd_clocks::kernel_clock(hubris, core)?;
+
+    let outcome = Outcome::new(
+        "clocks",
+        "cpu".to_string(),
+        cpu.map_or_else(|| "-".to_string(), mhz),
+    );
+
+    Ok(vec![match (cpu, expected) {
+        (_, None) => {
+            outcome.skip("archive does not specify a kernel clock".to_string())
+        }
+        (None, _) => outcome.skip(
+            "CPU clock could not be determined; specify oscillator".to_string(),
+        ),
+        (Some(cpu), Some(expected)) if (cpu - expected).abs() < 1000.0 => {
+            outcome
+        }
+        (Some(_), Some(expected)) => outcome.fail(format!(
+            "CPU clock does not match kernel clock of {}",
+            mhz(expected)
+        )),
+    }])
+}
+
+fn check_tasks(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    check: &TasksCheck,
+) -> Result<Vec<Outcome>> {
+    let base = core.read_word_32(hubris.lookup_symword("TASK_TABLE_BASE")?)?;
+    let count =
+        core.read_word_32(hubris.lookup_symword("TASK_TABLE_SIZE")?)? as usize;
+    let task_t = hubris.lookup_struct_byname("Task")?;
+
+    let mut taskblock = vec![0; task_t.size * count];
+    core.read_8(base, &mut taskblock)?;
+
+    let mut outcomes = vec![];
+
+    for i in 0..count {
+        let name = &hubris.lookup_module(HubrisTask::Task(i as u32))?.name;
+
+        if check.skip.contains(name) {
+            continue;
+        }
+
+        let task: Task =
+            reflect::load(hubris, &taskblock, task_t, i * task_t.size)?;
+
+        let outcome = Outcome::new("tasks", name.clone(), String::new());
+
+        outcomes.push(match task.state {
+            TaskState::Faulted { fault, .. } => {
+                let fault = format!("{fault:?}");
+                Outcome { detail: fault.clone(), ..outcome }
+                    .fail(format!("task has faulted: {fault}"))
+            }
+            TaskState::Healthy(state) => {
+                Outcome { detail: format!("{state:?}"), ..outcome }
+            }
+        });
+    }
+
+    Ok(outcomes)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn junit(outcomes: &[Outcome]) -> String {
+    let mut out = String::new();
+    let failures = |o: &[&Outcome]| {
+        o.iter().filter(|o| matches!(o.verdict, Verdict::Fail(_))).count()
+    };
+
+    let all = outcomes.iter().collect::<Vec<_>>();
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(
+        out,
+        r#"<testsuites name="humility validate" tests="{}" failures="{}">"#,
+        all.len(),
+        failures(&all)
+    )
+    .unwrap();
+
+    let mut checks = outcomes.iter().map(|o| o.check).collect::<Vec<_>>();
+    checks.dedup();
+
+    for check in checks {
+        let these =
+            outcomes.iter().filter(|o| o.check == check).collect::<Vec<_>>();
+
+        writeln!(
+            out,
+            r#"  <testsuite name="{check}" tests="{}" failures="{}">"#,
+            these.len(),
+            failures(&these)
+        )
+        .unwrap();
+
+        for o in these {
+            let name = xml_escape(&o.item);
+
+            match &o.verdict {
+                Verdict::Pass => {
+                    writeln!(
+                        out,
+                        r#"    <testcase classname="{check}" name="{name}"/>"#
+                    )
+                    .unwrap();
+                }
+                Verdict::Fail(reason) | Verdict::Skip(reason) => {
+                    let element = match o.verdict {
+                        Verdict::Fail(_) => "failure",
+                        _ => "skipped",
+                    };
+
+                    writeln!(
+                        out,
+                        r#"    <testcase classname="{check}" name="{name}">"#
+                    )
+                    .unwrap();
+                    writeln!(
+                        out,
+                        r#"      <{element} message="{}"/>"#,
+                        xml_escape(reason)
+                    )
+                    .unwrap();
+                    writeln!(out, "    </testcase>").unwrap();
+                }
+            }
+        }
+
+        writeln!(out, "  </testsuite>").unwrap();
+    }
+
+    writeln!(out, "</testsuites>").unwrap();
+    out
+}
+
+pub fn run(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    devices: &[(usize, &HubrisI2cDevice)],
+    checklist: &Checklist,
+    junit_file: Option<&str>,
+) -> Result<()> {
+    let mut outcomes = vec![];
+
+    if let Some(check) = &checklist.devices {
+        outcomes.extend(check_devices(hubris, core, context, devices, check)?);
+    }
+
+    if let Some(check) = &checklist.rails {
+        outcomes.extend(check_rails(hubris, core, context, check)?);
+    }
+
+    if let Some(check) = &checklist.clocks {
+        outcomes.extend(check_clocks(hubris, core, check)?);
+    }
+
+    if let Some(check) = &checklist.tasks {
+        outcomes.extend(check_tasks(hubris, core, check)?);
+    }
+
+    println!("{:7} {:6} {:40} DETAIL", "CHECK", "RESULT", "ITEM");
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+
+    for o in &outcomes {
+        let (result, reason) = match &o.verdict {
+            Verdict::Pass => {
+                passed += 1;
+                ("PASS".green(), None)
+            }
+            Verdict::Fail(reason) => {
+                failed += 1;
+                ("FAIL".red(), Some(reason))
+            }
+            Verdict::Skip(reason) => {
+                skipped += 1;
+                ("SKIP".yellow(), Some(reason))
+            }
+        };
+
+        print!("{:7} {:6} {:40} {}", o.check, result, o.item, o.detail);
+
+        match reason {
+            Some(reason) => println!(" ({reason})"),
+            None => println!(),
+        }
+    }
+
+    if let Some(filename) = junit_file {
+        std::fs::write(filename, junit(&outcomes))
+            .with_context(|| format!("failed to write {filename}"))?;
+        humility::msg!("JUnit report written to {filename}");
+    }
+
+    humility::msg!("{passed} passed, {failed} failed, {skipped} skipped");
+
+    if failed != 0 {
+        bail!("{failed} check(s) failed");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,287 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility verify`
+//!
+//! `humility verify` reads the contents of flash from the target and
+//! compares them to the image in the archive, answering the question of
+//! what is actually running on a board.  It first compares the image ID
+//! on the target with that of the archive, and then compares every byte of
+//! the image, reporting the regions that differ along with the task (and
+//! symbol, if any) that each region falls within:
+//!
+//! ```console
+//! $ humility verify
+//! humility: attached via ST-Link V3
+//! humility: image ID on target matches archive
+//! humility: 2 regions differ from archive:
+//! ADDRESS        SIZE OBJECT       SYMBOL
+//! 0x08010c38       12 jefe         task_jefe::main+0x170
+//! 0x0805e000     4096 net          -
+//! Error: flash contents do not match archive
+//! ```
+//!
+//! If the image ID on the target doesn't match the archive, the target is
+//! running a different build.  To determine which one, specify other
+//! archives with `--candidate` (`-c`); each candidate's image ID will be
+//! compared to the target's:
+//!
+//! ```console
+//! $ humility verify -c gimlet-c-v1.0.2.zip -c gimlet-c-v1.0.3.zip
+//! humility: attached via ST-Link V3
+//! humility: image ID on target does not match archive
+//! humility: target image ID:  [e2, 13, 7f, 8c, 3a, 90, d1, 22]
+//! humility: archive image ID: [5b, 0e, 41, 2d, 6c, 7a, 1f, 93]
+//! humility: target matches image ID of gimlet-c-v1.0.2.zip
+//! Error: target is not running the image in the archive
+//! ```
+//!
+//! To compare the contents of flash even if the image ID doesn't match,
+//! use `--force` (`-f`).
+//!
+
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::msg;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use indicatif::{ProgressBar, ProgressStyle};
+
+#[derive(Parser, Debug)]
+#[clap(name = "verify", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct VerifyArgs {
+    /// other archive that the target may be running
+    #[clap(long, short, value_name = "archive", multiple_occurrences = true)]
+    candidate: Vec<String>,
+
+    /// compare flash contents even if the image ID does not match
+    #[clap(long, short)]
+    force: bool,
+}
+
+///
+/// Mismatches that are within this many bytes of one another are reported
+/// as a single region.
+///
+const MISMATCH_GAP: u32 = 16;
+
+///
+/// Returns the image ID of another archive.  Rather than fully loading the
+/// archive, we pull the image ID out of the kernel's symbol table.
+///
+fn candidate_image_id(path: &str) -> Result<Option<Vec<u8>>> {
+    let mut archive = HubrisArchive::new()?;
+
+    archive
+        .load(path, HubrisArchiveDoneness::Raw)
+        .with_context(|| format!("failed to load {path}"))?;
+
+    let kernel = archive
+        .read_file("elf/kernel")?
+        .with_context(|| format!("{path} does not contain a kernel"))?;
+
+    let elf = goblin::elf::Elf::parse(&kernel)
+        .with_context(|| format!("failed to parse kernel in {path}"))?;
+
+    for sym in elf.syms.iter() {
+        if !matches!(elf.strtab.get(sym.st_name), Some(Ok("HUBRIS_IMAGE_ID"))) {
+            continue;
+        }
+
+        let sec = match elf.section_headers.get(sym.st_shndx) {
+            Some(sec) => sec,
+            None => return Ok(None),
+        };
+
+        let offset = (sym.st_value - sec.sh_addr + sec.sh_offset) as usize;
+        let len = sym.st_size as usize;
+
+        return Ok(kernel.get(offset..offset + len).map(|id| id.to_vec()));
+    }
+
+    Ok(None)
+}
+
+fn image_id(core: &mut dyn Core, hubris: &HubrisArchive) -> Result<Vec<u8>> {
+    let (addr, len) = match (hubris.image_id_addr(), hubris.image_id()) {
+        (Some(addr), Some(id)) => (addr, id.len()),
+        _ => bail!("archive does not contain an image ID"),
+    };
+
+    let mut id = vec![0; len];
+    core.read_8(addr, &mut id)
+        .with_context(|| format!("failed to read image ID at 0x{addr:x}"))?;
+
+    Ok(id)
+}
+
+///
+/// Returns the regions of flash that differ from the archive's image as a
+/// vector of address/length tuples.
+///
+fn mismatches(
+    core: &mut dyn Core,
+    hubris: &HubrisArchive,
+) -> Result<Vec<(u32, u32)>> {
+    use goblin::elf::program_header::PT_LOAD;
+
+    let contents = hubris
+        .read_file("img/final.elf")?
+        .context("could not find final.elf in archive")?;
+
+    let elf = goblin::elf::Elf::parse(&contents)
+        .context("failed to parse final.elf")?;
+
+    //
+    // As with `humility flash --verify`, we check every loadable program
+    // header with initialized data at its physical address.
+    //
+    let phdrs = elf
+        .program_headers
+        .iter()
+        .filter(|h| h.p_type == PT_LOAD && h.p_filesz > 0)
+        .map(|h| {
+            let offset = h.p_offset as usize;
+            let size = h.p_filesz as usize;
+            (h.p_paddr as u32, &contents[offset..offset + size])
+        })
+        .collect::<Vec<_>>();
+
+    let total: usize = phdrs.iter().map(|(_, chunk)| chunk.len()).sum();
+
+    let bar = ProgressBar::new(total as u64);
+    bar.set_style(
+        ProgressStyle::default_bar().template(
+            "humility: reading flash [{bar:30}] {bytes}/{total_bytes}",
+        ),
+    );
+
+    let mut rval: Vec<(u32, u32)> = vec![];
+    let mut buffer = vec![0; 1024];
+    let mut verified = 0;
+
+    for (paddr, expected) in phdrs {
+        for (i, chunk) in expected.chunks(buffer.len()).enumerate() {
+            let addr = paddr + (i * buffer.len()) as u32;
+            let found = &mut buffer[..chunk.len()];
+
+            core.read_8(addr, found)?;
+
+            for (offs, _) in chunk
+                .iter()
+                .zip(found.iter())
+                .enumerate()
+                .filter(|(_, (e, f))| e != f)
+            {
+                let addr = addr + offs as u32;
+
+                match rval.last_mut() {
+                    Some((base, len))
+                        if addr >= *base + *len
+                            && addr - (*base + *len) < MISMATCH_GAP =>
+                    {
+                        *len = addr - *base + 1;
+                    }
+                    _ => rval.push((addr, 1)),
+                }
+            }
+
+            verified += chunk.len();
+            bar.set_position(verified as u64);
+        }
+    }
+
+    bar.finish_and_clear();
+
+    Ok(rval)
+}
+
+fn verify(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let subargs = VerifyArgs::try_parse_from(subargs)?;
+
+    let id = image_id(core, hubris)?;
+    let expected = hubris.image_id().unwrap();
+
+    if id == expected {
+        msg!("image ID on target matches archive");
+    } else {
+        msg!("image ID on target does not match archive");
+        msg!("target image ID:  {:x?}", id);
+        msg!("archive image ID: {:x?}", expected);
+
+        for candidate in &subargs.candidate {
+            if candidate_image_id(candidate)?.as_ref() == Some(&id) {
+                msg!("target matches image ID of {candidate}");
+            }
+        }
+
+        if !subargs.force {
+            bail!("target is not running the image in the archive");
+        }
+    }
+
+    let regions = mismatches(core, hubris)?;
+
+    if regions.is_empty() {
+        msg!("flash contents match archive");
+        return Ok(());
+    }
+
+    msg!(
+        "{} region{} differ{} from archive:",
+        regions.len(),
+        if regions.len() != 1 { "s" } else { "" },
+        if regions.len() != 1 { "" } else { "s" },
+    );
+
+    //
+    // Differences outside of program text are attributed to the task whose
+    // region contains them.
+    //
+    let owners = hubris.regions(core)?;
+
+    println!("{:10} {:>8} {:12} SYMBOL", "ADDRESS", "SIZE", "OBJECT");
+
+    for (addr, len) in regions {
+        let owner = match hubris.instr_mod(addr) {
+            Some(module) => module.to_string(),
+            None => match owners.range(..=addr).next_back() {
+                Some((_, r)) if addr - r.base < r.size => match r.tasks[..] {
+                    [HubrisTask::Kernel] => "kernel".to_string(),
+                    [task] => hubris.lookup_module(task)?.name.clone(),
+                    _ => "-".to_string(),
+                },
+                _ => "-".to_string(),
+            },
+        };
+
+        let sym = match hubris.instr_sym(addr) {
+            Some((name, base)) if base == addr => name.to_string(),
+            Some((name, base)) => format!("{}+0x{:x}", name, addr - base),
+            None => "-".to_string(),
+        };
+
+        println!("0x{:08x} {:>8} {:12} {}", addr, len, owner, sym);
+    }
+
+    bail!("flash contents do not match archive");
+}
+
+pub fn init() -> Command {
+    Command {
+        app: VerifyArgs::command(),
+        name: "verify",
+        run: verify,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::None,
+        },
+    }
+}
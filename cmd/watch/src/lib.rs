@@ -0,0 +1,872 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility watch`
+//!
+//! `humility watch` sets hardware breakpoints (via the Flash Patch and
+//! Breakpoint unit) and watchpoints (via the Data Watchpoint and Trace unit),
+//! allows the target to run, and reports when one of them is hit.  A
+//! breakpoint is specified with `--break` (`-b`) as either a function or an
+//! address; a watchpoint is specified with `--write` (`-w`), `--read`
+//! (`-r`), or `--access` (`-a`) as either an address or a variable, which
+//! may be followed by a path of structure members and array indices.  When
+//! a breakpoint or watchpoint is hit, the task that hit it is identified,
+//! and its registers and stack are displayed:
+//!
+//! ```console
+//! $ humility watch -w thermal::LIMIT
+//! humility: attached via ST-Link V3
+//! humility: watchpoint 0: write to task_thermal::LIMIT (0x2400c2a8, 2 bytes)
+//! humility: waiting for 1 hit (^C to stop)
+//! humility: hit 1: watchpoint 0 in task thermal
+//! task_thermal::LIMIT = 0x1f4
+//!    R0 = 0x000001f4    R1 = 0x2400c2a8    R2 = 0x00000000    R3 = 0x00000001
+//!    R4 = 0x2400c290    R5 = 0x00000002    R6 = 0x2400c3f0    R7 = 0x2400c270
+//!    R8 = 0x00000000    R9 = 0x00000000   R10 = 0x00000000   R11 = 0x00000000
+//!   R12 = 0x00000000    SP = 0x2400c268    LR = 0x08041a0f    PC = 0x08041a2e
+//!   PSR = 0x61000000
+//!       |
+//!       +--->  0x2400c270 0x08041a2e task_thermal::control::set_threshold
+//!              0x2400c3f8 0x08041c6a task_thermal::main
+//! ```
+//!
+//! By default, the command exits after the first hit; to wait for more hits,
+//! use `--count` (`-n`).  A watchpoint halts the target after the access
+//! that triggered it, so the program counter will typically be at (or just
+//! beyond) the instruction that follows the access; the stack will
+//! similarly reflect the state after the access.  Breakpoints and
+//! watchpoints are cleared when the command exits (including when it is
+//! interrupted), and the target is left running unless `--leave-halted` is
+//! specified.
+//!
+//! The number of breakpoints and watchpoints is limited by the number of
+//! comparators that the microcontroller implements.  On ARMv8-M (e.g.,
+//! Cortex-M33), a watchpoint can cover at most an aligned word; on ARMv7-M,
+//! it can cover any naturally aligned power-of-two region.  If a watched
+//! variable does not fit exactly into such a region, the smallest region
+//! that contains it is watched (and a message is displayed to that effect).
+//!
+//! ### Timing
+//!
+//! To measure the execution time of a function without any instrumentation
+//! in the firmware, use `--time` (`-t`).  This sets a breakpoint at the
+//! entry of the function and at each of its exits (that is, its return
+//! instructions and any branches out of it), and reads the DWT cycle
+//! counter at each.  The function is timed for the number of invocations
+//! specified with `--count` (`-n`), after which the minimum, average and
+//! maximum number of cycles are displayed:
+//!
+//! ```console
+//! $ humility watch --time drv_stm32h7_spi::Spi::exchange -n 100
+//! humility: attached via ST-Link V3
+//! humility: timing drv_stm32h7_spi::Spi::exchange at 0x080281d4 (3 exits)
+//! humility: waiting for 100 invocations (^C to stop)
+//! INVOCATIONS        MIN        AVG        MAX
+//!         100       4127       4310       9842
+//! humility: at 400.000 MHz: min 10.32us, avg 10.78us, max 24.60us
+//! ```
+//!
+//! An invocation is timed from its entry to its exit in the same task (or
+//! in the kernel), and includes any time spent in interrupt handlers or in
+//! other tasks that preempted it.  Because each breakpoint halts the
+//! target and is then stepped over, each measurement may be off by a
+//! handful of cycles.
+//!
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::msg;
+use humility_arch_arm::ARMRegister;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use humility_cortex::debug::{ARMCore, DEMCR, DFSR, DHCSR, FP_COMP0, FP_CTRL};
+use humility_cortex::dwt::{
+    DWT_COMP0, DWT_COMP_STRIDE, DWT_CTRL, DWT_CYCCNT, DWT_FUNCTION0, DWT_MASK0,
+};
+use humility_cortex::scs::CPUID;
+use num_traits::FromPrimitive;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[clap(name = "watch", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct WatchArgs {
+    /// set a breakpoint at a function or address
+    #[clap(
+        long = "break",
+        short,
+        value_name = "function|address",
+        multiple_occurrences = true
+    )]
+    breakpoint: Vec<String>,
+
+    /// set a watchpoint on writes to a variable or address
+    #[clap(
+        long,
+        short,
+        value_name = "variable|address",
+        multiple_occurrences = true
+    )]
+    write: Vec<String>,
+
+    /// set a watchpoint on reads from a variable or address
+    #[clap(
+        long,
+        short,
+        value_name = "variable|address",
+        multiple_occurrences = true
+    )]
+    read: Vec<String>,
+
+    /// set a watchpoint on reads from or writes to a variable or address
+    #[clap(
+        long,
+        short,
+        value_name = "variable|address",
+        multiple_occurrences = true
+    )]
+    access: Vec<String>,
+
+    /// size of region to watch at an address, in bytes
+    #[clap(
+        long, short, value_name = "bytes", default_value_t = 4,
+        parse(try_from_str = parse_int::parse)
+    )]
+    size: u32,
+
+    /// number of hits to report before exiting
+    #[clap(
+        long = "count", short = 'n', value_name = "hits", default_value_t = 1,
+        parse(try_from_str = parse_int::parse)
+    )]
+    count: usize,
+
+    /// show source line numbers in stack
+    #[clap(long, short)]
+    line: bool,
+
+    /// leave the target halted after the last hit
+    #[clap(long)]
+    leave_halted: bool,
+
+    /// measure the execution time of a function, in cycles
+    #[clap(
+        long, short, value_name = "function",
+        conflicts_with_all = &["breakpoint", "write", "read", "access"]
+    )]
+    time: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Break,
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Clone, Debug)]
+struct Point {
+    kind: Kind,
+    name: String,
+    addr: u32,
+    size: u32,
+    goff: Option<HubrisGoff>,
+
+    /// Index of our comparator in the FPB or the DWT
+    comparator: u32,
+
+    /// Address and number of low bits ignored by our DWT comparator
+    watched: (u32, u32),
+}
+
+impl Point {
+    fn describe(&self) -> String {
+        let what = match self.kind {
+            Kind::Break => {
+                return format!("{} at 0x{:08x}", self.name, self.addr)
+            }
+            Kind::Read => "read from",
+            Kind::Write => "write to",
+            Kind::Access => "access to",
+        };
+
+        format!(
+            "{} {} (0x{:08x}, {} byte{})",
+            what,
+            self.name,
+            self.addr,
+            self.size,
+            if self.size == 1 { "" } else { "s" }
+        )
+    }
+
+    fn label(&self) -> String {
+        match self.kind {
+            Kind::Break => format!("breakpoint {}", self.comparator),
+            _ => format!("watchpoint {}", self.comparator),
+        }
+    }
+}
+
+///
+/// Looks up a function, returning its name, address and size.
+///
+fn watch_function(
+    hubris: &HubrisArchive,
+    target: &str,
+) -> Result<(String, u32, u32)> {
+    let mut syms = hubris.lookup_symbols(target);
+    syms.dedup_by_key(|(_, addr, _)| *addr & !1);
+
+    match syms.len() {
+        0 => bail!("function {target} not found"),
+        1 => Ok((syms[0].0.to_string(), syms[0].1 & !1, syms[0].2)),
+        _ => {
+            let names = syms
+                .iter()
+                .map(|(n, _, _)| n.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            bail!("{target} is ambiguous; could be any of: {names}");
+        }
+    }
+}
+
+fn watch_breakpoint(hubris: &HubrisArchive, target: &str) -> Result<Point> {
+    let (name, addr) = match parse_int::parse::<u32>(target) {
+        Ok(addr) => (format!("0x{addr:08x}"), addr),
+        Err(_) => {
+            let (name, addr, _) = watch_function(hubris, target)?;
+            (name, addr)
+        }
+    };
+
+    Ok(Point {
+        kind: Kind::Break,
+        name,
+        addr,
+        size: 2,
+        goff: None,
+        comparator: 0,
+        watched: (addr, 0),
+    })
+}
+
+///
+/// Returns breakpoints at the entry of a function and at each of its exits:
+/// its return instructions, and any branches to outside of it (i.e., tail
+/// calls).
+///
+fn watch_timed(hubris: &HubrisArchive, target: &str) -> Result<Vec<Point>> {
+    let (name, base, size) = watch_function(hubris, target)?;
+    let mut points = vec![watch_breakpoint(hubris, &format!("{base:#x}"))?];
+    let mut addr = base;
+
+    points[0].name = name.clone();
+
+    while addr < base + size {
+        let exit = match hubris.instr_target(addr) {
+            Some(HubrisTarget::Return) => true,
+            Some(HubrisTarget::Direct(t)) => t < base || t >= base + size,
+            _ => false,
+        };
+
+        if exit {
+            let mut p = watch_breakpoint(hubris, &format!("{addr:#x}"))?;
+            p.name = format!("{}+0x{:x}", name, addr - base);
+            points.push(p);
+        }
+
+        //
+        // If we don't have an instruction here, it's likely a literal pool;
+        // skip it a halfword at a time.
+        //
+        addr += hubris.instr_len(addr).unwrap_or(2);
+    }
+
+    if points.len() == 1 {
+        bail!("could not find any exits from {name}");
+    }
+
+    Ok(points)
+}
+
+fn watch_watchpoint(
+    hubris: &HubrisArchive,
+    kind: Kind,
+    target: &str,
+    size: u32,
+) -> Result<Point> {
+    let (name, addr, size, goff) = match parse_int::parse::<u32>(target) {
+        Ok(addr) => (format!("0x{addr:08x}"), addr, size, None),
+        Err(_) => {
+            let (name, v) = hubris.lookup_variable_path(target)?;
+            (name, v.addr, v.size as u32, Some(v.goff))
+        }
+    };
+
+    if size == 0 {
+        bail!("cannot watch {name}: it has a size of zero");
+    }
+
+    //
+    // Determine the smallest naturally aligned power-of-two region that
+    // contains the entire target.
+    //
+    let last = match addr.checked_add(size - 1) {
+        Some(last) => last,
+        None => bail!("watched region extends beyond the address space"),
+    };
+
+    let mut bits = 0;
+
+    while bits < 31 && (addr >> bits) != (last >> bits) {
+        bits += 1;
+    }
+
+    let base = (addr >> bits) << bits;
+
+    Ok(Point {
+        kind,
+        name,
+        addr,
+        size,
+        goff,
+        comparator: 0,
+        watched: (base, bits),
+    })
+}
+
+///
+/// Arms our breakpoints and watchpoints, assigning each a comparator.
+///
+fn watch_arm(core: &mut dyn Core, points: &mut [Point]) -> Result<()> {
+    let armv8m = match ARMCore::from_u32(CPUID::read(core)?.partno()) {
+        Some(part) => part.is_armv8m(),
+        None => false,
+    };
+
+    let nbreak = points.iter().filter(|p| p.kind == Kind::Break).count();
+    let nwatch = points.len() - nbreak;
+
+    if nbreak > 0 {
+        let mut ctrl = FP_CTRL::read(core)?;
+        let ncomp = ctrl.num_code() as usize;
+
+        if nbreak > ncomp {
+            bail!("{nbreak} breakpoints specified; only {ncomp} available");
+        }
+
+        ctrl.set_key(true);
+        ctrl.set_enable(true);
+        ctrl.write(core)?;
+
+        for (i, p) in
+            points.iter_mut().filter(|p| p.kind == Kind::Break).enumerate()
+        {
+            p.comparator = i as u32;
+            let val = ctrl.comparator(p.addr)?;
+            core.write_word_32(FP_COMP0 + p.comparator * 4, val)?;
+        }
+    }
+
+    if nwatch > 0 {
+        let mut demcr = DEMCR::read(core)?;
+        demcr.set_trcena(true);
+        demcr.write(core)?;
+
+        let ncomp = DWT_CTRL::read(core)?.num_comparators() as usize;
+
+        if nwatch > ncomp {
+            bail!("{nwatch} watchpoints specified; only {ncomp} available");
+        }
+
+        for (i, p) in
+            points.iter_mut().filter(|p| p.kind != Kind::Break).enumerate()
+        {
+            p.comparator = i as u32;
+
+            let (base, bits) = p.watched;
+            let offs = p.comparator * DWT_COMP_STRIDE;
+
+            if base != p.addr || (1u64 << bits) != p.size as u64 {
+                msg!(
+                    "{}: watching 0x{:08x}-0x{:08x} to cover {}",
+                    p.label(),
+                    base,
+                    base as u64 + (1u64 << bits) - 1,
+                    p.name
+                );
+            }
+
+            //
+            // On ARMv8-M, the comparator matches the size of the access as
+            // specified by DATAVSIZE, and the function must specify the
+            // action (a debug event); on ARMv7-M (and ARMv6-M), the mask
+            // specifies the number of low address bits to ignore.
+            //
+            let function = if armv8m {
+                if bits > 2 {
+                    bail!(
+                        "cannot watch {} bytes at 0x{:08x}: ARMv8-M \
+                        watchpoints can cover at most an aligned word; \
+                        consider watching a member instead",
+                        p.size,
+                        p.addr
+                    );
+                }
+
+                let r#match = match p.kind {
+                    Kind::Access => 0b0100,
+                    Kind::Write => 0b0101,
+                    Kind::Read => 0b0110,
+                    Kind::Break => unreachable!(),
+                };
+
+                (bits << 10) | (0b01 << 4) | r#match
+            } else {
+                core.write_word_32(DWT_MASK0 + offs, bits)?;
+
+                if core.read_word_32(DWT_MASK0 + offs)? != bits {
+                    bail!(
+                        "cannot watch {} bytes at 0x{:08x}: watched region \
+                        exceeds the maximum supported by the DWT",
+                        p.size,
+                        p.addr
+                    );
+                }
+
+                match p.kind {
+                    Kind::Read => 0b0101,
+                    Kind::Write => 0b0110,
+                    Kind::Access => 0b0111,
+                    Kind::Break => unreachable!(),
+                }
+            };
+
+            core.write_word_32(DWT_COMP0 + offs, base)?;
+            core.write_word_32(DWT_FUNCTION0 + offs, function)?;
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Disarms our breakpoints and watchpoints.  This is called whenever we
+/// exit, to assure that we don't leave the target with them armed.
+///
+fn watch_disarm(core: &mut dyn Core, points: &[Point]) -> Result<()> {
+    for p in points {
+        match p.kind {
+            Kind::Break => {
+                core.write_word_32(FP_COMP0 + p.comparator * 4, 0)?;
+            }
+            _ => {
+                let offs = p.comparator * DWT_COMP_STRIDE;
+                core.write_word_32(DWT_FUNCTION0 + offs, 0)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Returns the task that the target is halted in:  if we are in handler mode
+/// (that is, the exception number in the IPSR is non-zero), we are in the
+/// kernel; otherwise, we are in the current task.
+///
+fn watch_task(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    psr: u32,
+) -> Result<HubrisTask> {
+    Ok(if psr & 0x1ff != 0 {
+        HubrisTask::Kernel
+    } else {
+        hubris.current_task(core)?.unwrap_or(HubrisTask::Kernel)
+    })
+}
+
+///
+/// Steps over a breakpoint:  a breakpoint halts before the instruction
+/// executes, so to continue we must step with its comparator disabled.
+///
+fn watch_step(core: &mut dyn Core, p: &Point) -> Result<()> {
+    if p.kind == Kind::Break {
+        let comp = FP_COMP0 + p.comparator * 4;
+        let val = core.read_word_32(comp)?;
+        core.write_word_32(comp, 0)?;
+        core.step()?;
+        core.write_word_32(comp, val)?;
+    }
+
+    Ok(())
+}
+
+fn watch_report(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    regions: &BTreeMap<u32, HubrisRegion>,
+    point: &Point,
+    hit: usize,
+    subargs: &WatchArgs,
+) -> Result<()> {
+    let mut regs = BTreeMap::new();
+
+    for i in 0..=ARMRegister::max() {
+        if let Some(reg) = ARMRegister::from_u16(i) {
+            if reg.is_floating_point() {
+                continue;
+            }
+
+            if let Ok(val) = core.read_reg(reg) {
+                regs.insert(reg, val);
+            }
+        }
+    }
+
+    let psr = regs.get(&ARMRegister::PSR).copied().unwrap_or(0);
+    let task = watch_task(hubris, core, psr)?;
+
+    let name = match task {
+        HubrisTask::Kernel => "kernel".to_string(),
+        _ => match hubris.lookup_module(task) {
+            Ok(module) => format!("task {}", module.name),
+            Err(_) => format!("task {task}"),
+        },
+    };
+
+    msg!("hit {}: {} in {}", hit, point.label(), name);
+
+    if let Some(goff) = point.goff {
+        let mut buf = vec![0u8; point.size as usize];
+        core.read_8(point.addr, &mut buf)?;
+        println!("{} = {}", point.name, hubris.print(&buf, goff)?);
+    }
+
+    humility_stack::print_registers(&regs);
+
+    let printer = humility_stack::StackPrinter {
+        indent: 6,
+        line: subargs.line,
+        ..Default::default()
+    };
+
+    printer.print_from_registers(hubris, core, regions, task, &regs);
+
+    Ok(())
+}
+
+///
+/// Waits for the running target to hit a breakpoint or watchpoint (or for
+/// us to be interrupted), returning the index of the point that was hit, if
+/// any.  Either way, the target is halted on return -- and that halt is
+/// accounted for with the core, so it can be balanced by a subsequent run.
+///
+fn watch_wait(
+    core: &mut dyn Core,
+    points: &[Point],
+    done: &AtomicBool,
+) -> Result<Option<usize>> {
+    loop {
+        if done.load(Ordering::SeqCst) {
+            core.halt()?;
+            return Ok(None);
+        }
+
+        match DHCSR::read(core) {
+            Ok(dhcsr) if dhcsr.halted() => break,
+            Ok(_) => {}
+            Err(e) => {
+                core.halt()?;
+                return Err(e);
+            }
+        }
+
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    //
+    // The target halted itself; let the core know, lest our next run be
+    // unbalanced.
+    //
+    core.wait_for_halt(Duration::from_secs(1))?;
+
+    //
+    // The DFSR bits are write-one-to-clear; clear what we have seen.
+    //
+    let dfsr = DFSR::read(core)?;
+    dfsr.write(core)?;
+
+    if dfsr.breakpoint() {
+        let pc = core.read_reg(ARMRegister::PC)? & !1;
+
+        let hit =
+            points.iter().position(|p| p.kind == Kind::Break && p.addr == pc);
+
+        if hit.is_some() {
+            return Ok(hit);
+        }
+    }
+
+    if dfsr.watchpoint() {
+        for (i, p) in points.iter().enumerate() {
+            if p.kind == Kind::Break {
+                continue;
+            }
+
+            //
+            // The MATCHED bit is cleared on read.
+            //
+            let offs = p.comparator * DWT_COMP_STRIDE;
+            let function = core.read_word_32(DWT_FUNCTION0 + offs)?;
+
+            if function & (1 << 24) != 0 {
+                return Ok(Some(i));
+            }
+        }
+    }
+
+    bail!("target halted for another reason (DFSR = 0x{:x})", u32::from(dfsr));
+}
+
+fn watch_run(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    points: &[Point],
+    subargs: &WatchArgs,
+    done: &AtomicBool,
+) -> Result<bool> {
+    let regions = hubris.regions(core)?;
+
+    msg!(
+        "waiting for {} hit{} (^C to stop)",
+        subargs.count,
+        if subargs.count == 1 { "" } else { "s" }
+    );
+
+    core.run()?;
+
+    for hit in 1..=subargs.count {
+        let ndx = match watch_wait(core, points, done)? {
+            Some(ndx) => ndx,
+            None => {
+                msg!("interrupted after {} hit(s)", hit - 1);
+                return Ok(false);
+            }
+        };
+
+        watch_report(hubris, core, &regions, &points[ndx], hit, subargs)?;
+
+        if hit == subargs.count {
+            break;
+        }
+
+        watch_step(core, &points[ndx])?;
+        core.run()?;
+    }
+
+    Ok(true)
+}
+
+///
+/// Times invocations of a function, where the first point is the function's
+/// entry and the remaining points are its exits.
+///
+fn watch_time(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    points: &[Point],
+    subargs: &WatchArgs,
+    done: &AtomicBool,
+) -> Result<bool> {
+    let mut demcr = DEMCR::read(core)?;
+    demcr.set_trcena(true);
+    demcr.write(core)?;
+
+    let mut ctrl = DWT_CTRL::read(core)?;
+
+    if ctrl.no_cycle_counter() {
+        bail!("DWT on target does not have a cycle counter");
+    }
+
+    ctrl.set_cyccnt_enabled(true);
+    ctrl.write(core)?;
+
+    msg!(
+        "waiting for {} invocation{} (^C to stop)",
+        subargs.count,
+        if subargs.count == 1 { "" } else { "s" }
+    );
+
+    let mut cycles: Vec<u32> = vec![];
+    let mut entered = None;
+
+    core.run()?;
+
+    let completed = loop {
+        let ndx = match watch_wait(core, points, done)? {
+            Some(ndx) => ndx,
+            None => {
+                msg!("interrupted after {} invocation(s)", cycles.len());
+                break false;
+            }
+        };
+
+        let cyccnt = core.read_word_32(DWT_CYCCNT)?;
+        let psr = core.read_reg(ARMRegister::PSR)?;
+        let task = watch_task(hubris, core, psr)?;
+
+        //
+        // If we are already within an invocation, an entry is either
+        // recursion or another task calling the function; either way, we
+        // continue to time the outermost invocation.  Similarly, we ignore
+        // any exit from a different task.
+        //
+        match (ndx, entered) {
+            (0, None) => entered = Some((task, cyccnt)),
+            (0, Some(_)) => {}
+            (_, Some((t, start))) if t == task => {
+                cycles.push(cyccnt.wrapping_sub(start));
+                entered = None;
+            }
+            (_, _) => {}
+        }
+
+        if cycles.len() == subargs.count {
+            break true;
+        }
+
+        watch_step(core, &points[ndx])?;
+        core.run()?;
+    };
+
+    if cycles.is_empty() {
+        return Ok(completed);
+    }
+
+    let min = *cycles.iter().min().unwrap();
+    let max = *cycles.iter().max().unwrap();
+    let avg =
+        cycles.iter().map(|&c| c as u64).sum::<u64>() / cycles.len() as u64;
+
+    println!("{:>11} {:>10} {:>10} {:>10}", "INVOCATIONS", "MIN", "AVG", "MAX");
+    println!("{:>11} {:>10} {:>10} {:>10}", cycles.len(), min, avg, max);
+
+    if let Some(khz) = hubris.clock(core)? {
+        let us = |c: u64| c as f64 * 1000.0 / khz as f64;
+
+        msg!(
+            "at {:.3} MHz: min {:.2}us, avg {:.2}us, max {:.2}us",
+            khz as f64 / 1000.0,
+            us(min as u64),
+            us(avg),
+            us(max as u64)
+        );
+    }
+
+    Ok(completed)
+}
+
+fn watch(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let subargs = WatchArgs::try_parse_from(subargs)?;
+    let mut points = match subargs.time {
+        Some(ref function) => watch_timed(hubris, function)?,
+        None => vec![],
+    };
+
+    for target in &subargs.breakpoint {
+        points.push(watch_breakpoint(hubris, target)?);
+    }
+
+    for (kind, targets) in [
+        (Kind::Write, &subargs.write),
+        (Kind::Read, &subargs.read),
+        (Kind::Access, &subargs.access),
+    ] {
+        for target in targets {
+            points.push(watch_watchpoint(hubris, kind, target, subargs.size)?);
+        }
+    }
+
+    if points.is_empty() {
+        bail!("must specify at least one breakpoint or watchpoint");
+    }
+
+    if subargs.count == 0 {
+        bail!("count must be at least 1");
+    }
+
+    let done = humility_cmd::interrupted()?;
+
+    //
+    // Arm our comparators with the target halted so that nothing is hit
+    // before we're ready to report it.
+    //
+    core.halt()?;
+
+    if let Err(e) = watch_arm(core, &mut points) {
+        watch_disarm(core, &points)?;
+        core.run()?;
+        return Err(e);
+    }
+
+    let rval = if subargs.time.is_some() {
+        msg!(
+            "timing {} ({} exit{})",
+            points[0].describe(),
+            points.len() - 1,
+            if points.len() == 2 { "" } else { "s" }
+        );
+
+        watch_time(hubris, core, &points, &subargs, done)
+    } else {
+        for p in &points {
+            msg!("{}: {}", p.label(), p.describe());
+        }
+
+        watch_run(hubris, core, &points, &subargs, done)
+    };
+
+    //
+    // Regardless of how we got here, disarm everything -- and if the target
+    // isn't to be left halted, make sure it's running.
+    //
+    watch_disarm(core, &points)?;
+
+    match rval {
+        Ok(true) if subargs.leave_halted => msg!("leaving target halted"),
+        Ok(_) => core.run()?,
+        Err(e) => {
+            core.run()?;
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: WatchArgs::command(),
+        name: "watch",
+        run: watch,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
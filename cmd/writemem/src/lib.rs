@@ -0,0 +1,387 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility writemem`
+//!
+//! `humility writemem` writes a value to target memory.  The memory to be
+//! written can be specified as a variable (as listed by `humility readvar
+//! -l`), optionally followed by a path of structure members and array
+//! indices; the value is parsed according to the type of the specified
+//! variable or member:
+//!
+//! ```console
+//! $ humility writemem thermal::CONFIG.threshold 500
+//! humility: attached via ST-Link V3
+//! humility: task_thermal::CONFIG.threshold (0x2400c2a8, 2 bytes) = 400
+//! humility: write 500 (0x01f4) to task_thermal::CONFIG.threshold? [y/N] y
+//! humility: task_thermal::CONFIG.threshold (0x2400c2a8, 2 bytes) = 500
+//! ```
+//!
+//! Variables are matched as they are by `humility readvar`; a variable may
+//! also be qualified with the name of the task that contains it.  Unsigned
+//! and signed integers, booleans, floating point values and pointers are
+//! written as such; an enum without data can be written by specifying the
+//! name of its variant:
+//!
+//! ```console
+//! $ humility writemem -y thermal::CONFIG.mode Manual
+//! humility: attached via ST-Link V3
+//! humility: task_thermal::CONFIG.mode (0x2400c2ac, 1 byte) = Auto
+//! humility: task_thermal::CONFIG.mode (0x2400c2ac, 1 byte) = Manual
+//! ```
+//!
+//! Alternatively, an address can be specified along with the size of the
+//! value to be written (via `--size`, which defaults to 4 bytes):
+//!
+//! ```console
+//! $ humility writemem -y --size 2 0x2400c2a8 0x190
+//! humility: attached via ST-Link V3
+//! humility: 0x2400c2a8 (2 bytes) = 0x01f4
+//! humility: 0x2400c2a8 (2 bytes) = 0x0190
+//! ```
+//!
+//! Before writing, the current value is displayed and confirmation is
+//! requested; `--yes` (`-y`) skips the confirmation.  The target is halted
+//! for the duration of the write, and the written memory is read back to
+//! verify that the write took effect.  Memory that is not within a writable
+//! region of the Hubris image (e.g., flash), or that is within a device or
+//! external region (where a write may have side effects), will not be
+//! written unless `--force` (`-F`) is specified.
+//!
+
+use anyhow::{anyhow, bail, Result};
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility::msg;
+use humility::Diagnostic;
+use humility_cli::{ExecutionContext, Subcommand};
+use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+
+#[derive(Parser, Debug)]
+#[clap(name = "writemem", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct WritememArgs {
+    /// write without asking for confirmation
+    #[clap(long, short)]
+    yes: bool,
+
+    /// write to memory outside of the image's writable memory regions
+    #[clap(long, short = 'F')]
+    force: bool,
+
+    /// size of the value to write to an address, in bytes
+    #[clap(
+        long, short, value_name = "bytes",
+        parse(try_from_str = parse_int::parse)
+    )]
+    size: Option<usize>,
+
+    /// address or variable (e.g., "task::VARIABLE.member[2]") to write
+    target: String,
+
+    /// value to write
+    value: String,
+}
+
+///
+/// The memory to be written:  the address and size of the variable (or
+/// member) as a whole, and its type, if known.
+///
+struct Target {
+    name: String,
+    addr: u32,
+    size: usize,
+    goff: Option<HubrisGoff>,
+}
+
+fn plural(size: usize) -> &'static str {
+    if size == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+fn writemem_unsigned(value: &str, size: usize) -> Result<Vec<u8>> {
+    let val: u64 = parse_int::parse(value)
+        .map_err(|_| anyhow!("invalid unsigned value \"{value}\""))?;
+
+    if size > 8 || (size < 8 && val >> (size * 8) != 0) {
+        bail!("value {value} does not fit in {size} byte{}", plural(size));
+    }
+
+    Ok(val.to_le_bytes()[..size].to_vec())
+}
+
+fn writemem_signed(value: &str, size: usize) -> Result<Vec<u8>> {
+    let (negative, magnitude) = match value.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, value),
+    };
+
+    let magnitude: u64 = parse_int::parse(magnitude)
+        .map_err(|_| anyhow!("invalid signed value \"{value}\""))?;
+
+    if size == 0 || size > 8 {
+        bail!("cannot write {size}-byte signed value");
+    }
+
+    let max = (1u64 << (size * 8 - 1)) - 1;
+
+    if magnitude > max + u64::from(negative) {
+        bail!("value {value} does not fit in {size} byte{}", plural(size));
+    }
+
+    let val = if negative {
+        (magnitude as i64).wrapping_neg()
+    } else {
+        magnitude as i64
+    };
+
+    Ok(val.to_le_bytes()[..size].to_vec())
+}
+
+///
+/// Encodes the value to be written, returning the offset within the target
+/// at which it should be written along with the bytes to write.
+///
+fn writemem_encode(
+    hubris: &HubrisArchive,
+    target: &Target,
+    value: &str,
+) -> Result<(usize, Vec<u8>)> {
+    let goff = match target.goff {
+        Some(goff) => goff,
+        None => return Ok((0, writemem_unsigned(value, target.size)?)),
+    };
+
+    match hubris.lookup_type(goff)? {
+        HubrisType::Base(t) => {
+            let bytes = match (t.encoding, t.size) {
+                (HubrisEncoding::Signed, size) => writemem_signed(value, size)?,
+                (HubrisEncoding::Bool, size) => {
+                    let val = match value {
+                        "true" | "1" => "1",
+                        "false" | "0" => "0",
+                        _ => bail!("invalid boolean value \"{value}\""),
+                    };
+                    writemem_unsigned(val, size)?
+                }
+                (HubrisEncoding::Float, 4) => value
+                    .parse::<f32>()
+                    .map_err(|_| anyhow!("invalid float value \"{value}\""))?
+                    .to_le_bytes()
+                    .to_vec(),
+                (HubrisEncoding::Float, 8) => value
+                    .parse::<f64>()
+                    .map_err(|_| anyhow!("invalid float value \"{value}\""))?
+                    .to_le_bytes()
+                    .to_vec(),
+                (HubrisEncoding::Float, size) => {
+                    bail!("cannot write {size}-byte floating point value");
+                }
+                (_, size) => writemem_unsigned(value, size)?,
+            };
+
+            Ok((0, bytes))
+        }
+
+        HubrisType::Ptr(_) => Ok((0, writemem_unsigned(value, 4)?)),
+
+        HubrisType::Enum(e) => {
+            let variant = e.lookup_variant_byname(value).map_err(|_| {
+                let names = e
+                    .variants
+                    .iter()
+                    .map(|v| v.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                anyhow!(
+                    "{} has no variant {value}; expected one of: {names}",
+                    e.name
+                )
+            })?;
+
+            //
+            // We can only write the discriminant; a variant that carries
+            // data would require us to write that data as well.
+            //
+            if let Some(vgoff) = variant.goff {
+                match hubris.lookup_struct(vgoff) {
+                    Ok(s) if s.members.is_empty() => {}
+                    _ => bail!(
+                        "cannot write {}::{value}: variant has data",
+                        e.name
+                    ),
+                }
+            }
+
+            let (dgoff, offset, tag) = match (e.discriminant, variant.tag) {
+                (Some(HubrisDiscriminant::Value(dgoff, offset)), Some(tag)) => {
+                    (dgoff, offset, tag)
+                }
+                _ => bail!("cannot write {}::{value}: no discriminant", e.name),
+            };
+
+            let size = hubris.lookup_basetype(dgoff)?.size;
+
+            Ok((offset, writemem_unsigned(&tag.to_string(), size)?))
+        }
+
+        t => {
+            bail!(
+                "cannot write a value of type {}; specify a member or index",
+                t.name(hubris)?
+            );
+        }
+    }
+}
+
+fn writemem_display(
+    hubris: &HubrisArchive,
+    target: &Target,
+    buf: &[u8],
+) -> Result<String> {
+    let value = match target.goff {
+        Some(goff) => {
+            hubris.printfmt(buf, goff, HubrisPrintFormat::default())?
+        }
+        None => {
+            let mut val = [0u8; 8];
+            val[..buf.len()].copy_from_slice(buf);
+            format!(
+                "0x{:0width$x}",
+                u64::from_le_bytes(val),
+                width = buf.len() * 2
+            )
+        }
+    };
+
+    Ok(format!(
+        "{} (0x{:08x}, {} byte{}) = {}",
+        target.name,
+        target.addr,
+        target.size,
+        plural(target.size),
+        value
+    ))
+}
+
+fn writemem(context: &mut ExecutionContext) -> Result<()> {
+    let core = &mut **context.core.as_mut().unwrap();
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let hubris = context.archive.as_ref().unwrap();
+
+    let subargs = WritememArgs::try_parse_from(subargs)?;
+
+    let target = match parse_int::parse::<u32>(&subargs.target) {
+        Ok(addr) => {
+            let size = subargs.size.unwrap_or(4);
+
+            if !matches!(size, 1 | 2 | 4 | 8) {
+                bail!("size must be 1, 2, 4, or 8 bytes");
+            }
+
+            Target { name: format!("0x{addr:08x}"), addr, size, goff: None }
+        }
+        Err(_) => {
+            if subargs.size.is_some() {
+                bail!("--size can only be used when writing to an address");
+            }
+
+            let (name, v) = hubris.lookup_variable_path(&subargs.target)?;
+            Target { name, addr: v.addr, size: v.size, goff: Some(v.goff) }
+        }
+    };
+
+    let (offset, bytes) = writemem_encode(hubris, &target, &subargs.value)?;
+    let addr = target.addr + offset as u32;
+
+    let end = match addr.checked_add(bytes.len() as u32 - 1) {
+        Some(end) => end,
+        None => bail!("write extends beyond the end of the address space"),
+    };
+
+    if !subargs.force {
+        let regions = hubris.regions(core)?;
+
+        let contained = regions.values().any(|r| {
+            r.size > 0
+                && r.attr.write
+                && !r.attr.device
+                && !r.attr.external
+                && addr >= r.base
+                && end <= r.base + (r.size - 1)
+        });
+
+        if !contained {
+            bail!(Diagnostic::error(format!(
+                "0x{:08x}-0x{:08x} is not within a writable, non-device \
+                memory region of the image",
+                addr, end
+            ))
+            .code("outside-image")
+            .hint("use --force to write it anyway"));
+        }
+    }
+
+    let mut buf = vec![0u8; target.size];
+
+    core.halt()?;
+    let rval = core.read_8(target.addr, &mut buf);
+    core.run()?;
+    rval?;
+
+    msg!("{}", writemem_display(hubris, &target, &buf)?);
+
+    if !subargs.yes {
+        let hex =
+            bytes.iter().rev().map(|b| format!("{b:02x}")).collect::<String>();
+
+        let prompt =
+            format!("write {} (0x{}) to {}?", subargs.value, hex, target.name);
+
+        if !humility_cmd::confirm(&prompt)? {
+            bail!("write aborted");
+        }
+    }
+
+    //
+    // Write the value and read back the entire target with the core halted,
+    // so we can both verify the write and display the new value.
+    //
+    core.halt()?;
+
+    let rval = core
+        .write_8(addr, &bytes)
+        .and_then(|_| core.read_8(target.addr, &mut buf));
+
+    core.run()?;
+    rval?;
+
+    msg!("{}", writemem_display(hubris, &target, &buf)?);
+
+    if buf[offset..offset + bytes.len()] != bytes[..] {
+        bail!(
+            "read back of 0x{:08x} does not match written value; memory may \
+            not be writable",
+            addr
+        );
+    }
+
+    Ok(())
+}
+
+pub fn init() -> Command {
+    Command {
+        app: WritememArgs::command(),
+        name: "writemem",
+        run: writemem,
+        kind: CommandKind::Attached {
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+        },
+    }
+}
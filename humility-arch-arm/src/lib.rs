@@ -320,6 +320,79 @@ pub fn exception_stack_realign(regs: &BTreeMap<ARMRegister, u32>) -> u32 {
     0
 }
 
+//
+// On exception entry, LR is loaded with an EXC_RETURN value that describes
+// the context that was interrupted:  bit 2 indicates whether the exception
+// frame was stacked on the process stack (PSP) or the main stack (MSP), and
+// bit 3 whether we will return to thread mode or handler mode.  Bit 4
+// (when clear) indicates that the stacked frame was extended to include
+// the floating point context.  On ARMv8-M, bit 5 (when clear) additionally
+// indicates that the callee-saved registers were stacked below the frame;
+// this bit is reserved as 1 on ARMv6-M and ARMv7-M.
+//
+pub fn is_exc_return(val: u32) -> bool {
+    val >> 28 == 0xf
+}
+
+/// Returns true if the EXC_RETURN value denotes a frame stacked on the PSP
+pub fn exc_return_process_stack(exc_return: u32) -> bool {
+    exc_return & (1 << 2) != 0
+}
+
+/// Returns true if the EXC_RETURN value denotes a return to thread mode
+pub fn exc_return_thread_mode(exc_return: u32) -> bool {
+    exc_return & (1 << 3) != 0
+}
+
+/// Returns true if the EXC_RETURN value denotes an FP-extended frame
+pub fn exc_return_fp_extended(exc_return: u32) -> bool {
+    exc_return & (1 << 4) == 0
+}
+
+/// Returns true if the callee-saved registers were stacked (ARMv8-M only)
+pub fn exc_return_callee_stacked(exc_return: u32) -> bool {
+    exc_return & (1 << 5) == 0
+}
+
+///
+/// Returns the registers that are stacked in the basic exception frame,
+/// in the order in which they appear on the stack.
+///
+pub fn exception_frame_registers() -> [ARMRegister; 8] {
+    [
+        ARMRegister::R0,
+        ARMRegister::R1,
+        ARMRegister::R2,
+        ARMRegister::R3,
+        ARMRegister::R12,
+        ARMRegister::LR,
+        ARMRegister::PC,
+        ARMRegister::PSR,
+    ]
+}
+
+///
+/// Returns the size, in bytes, of the exception frame denoted by the
+/// specified EXC_RETURN value, not including any realignment.  (The
+/// extended frame consists of S0-S15, FPSCR, and a reserved word.)
+///
+pub fn exception_frame_size(exc_return: u32) -> u32 {
+    let nregs = if exc_return_fp_extended(exc_return) { 8 + 18 } else { 8 };
+    nregs * 4
+}
+
+///
+/// Describes the context denoted by the EXC_RETURN value.
+///
+pub fn exc_return_describe(exc_return: u32) -> String {
+    format!(
+        "{} mode, {} stack{}",
+        if exc_return_thread_mode(exc_return) { "thread" } else { "handler" },
+        if exc_return_process_stack(exc_return) { "process" } else { "main" },
+        if exc_return_fp_extended(exc_return) { ", FP context" } else { "" },
+    )
+}
+
 pub fn unhalted_read_regions() -> BTreeMap<u32, u32> {
     let mut map = BTreeMap::new();
 
@@ -464,6 +464,57 @@ register!(DEMCR, 0xe000_edfc,
     pub vc_corereset, set_vc_corereset: 0;
 );
 
+//
+// Flash Patch and Breakpoint Control Register
+//
+register!(FP_CTRL, 0xe000_2000,
+    #[derive(Copy, Clone)]
+    #[allow(non_camel_case_types)]
+    pub struct FP_CTRL(u32);
+    impl Debug;
+    /// Flash Patch and Breakpoint architecture revision
+    pub rev, _: 31, 28;
+    /// Most significant bits of the number of instruction comparators
+    pub num_code_hi, _: 14, 12;
+    /// Number of literal comparators
+    pub num_lit, _: 11, 8;
+    /// Least significant bits of the number of instruction comparators
+    pub num_code_lo, _: 7, 4;
+    /// Must be set for a write to take effect
+    pub key, set_key: 1;
+    /// Enable the FPB
+    pub enable, set_enable: 0;
+);
+
+impl FP_CTRL {
+    /// Returns the number of instruction address comparators
+    pub fn num_code(&self) -> u32 {
+        (self.num_code_hi() << 4) | self.num_code_lo()
+    }
+
+    /// Returns the value of an enabled instruction address comparator that
+    /// breaks at the specified address
+    pub fn comparator(&self, addr: u32) -> Result<u32> {
+        if self.rev() != 0 {
+            return Ok(addr | 1);
+        }
+
+        //
+        // Revision 1 comparators can only match in the code region, and
+        // match on a word address with the halfword selected by REPLACE.
+        //
+        if addr >= 0x2000_0000 {
+            return Err(anyhow!("address {addr:#x} is outside of code region"));
+        }
+
+        let replace = if addr & 0b10 != 0 { 0b10 } else { 0b01 };
+        Ok((replace << 30) | (addr & 0x1fff_fffc) | 1)
+    }
+}
+
+/// Address of the first FPB comparator register
+pub const FP_COMP0: u32 = 0xe000_2008;
+
 //
 // Media and FP Feature Register 0
 //
@@ -721,6 +772,10 @@ impl ARMCore {
     pub fn has_tz(&self) -> bool {
         matches!(*self, ARMCore::CortexM33)
     }
+
+    pub fn is_armv8m(&self) -> bool {
+        matches!(*self, ARMCore::CortexM23 | ARMCore::CortexM33)
+    }
 }
 
 pub fn corename(partno: ARMCore) -> String {
@@ -831,3 +886,59 @@ pub fn swoscaler(hubris: &HubrisArchive, core: &mut dyn Core) -> Result<u16> {
         Some(clock) => Ok(((clock * 1000) / debug_clock_mhz) as u16 - 1),
     }
 }
+
+///
+/// Decodes and prints the fault status registers in the System Control
+/// Block (CFSR and HFSR), along with any valid fault address.  Note that
+/// under Hubris, the kernel handles faults in tasks, so these will generally
+/// reflect only the most recent fault.
+///
+pub fn print_scb(core: &mut dyn Core) -> Result<()> {
+    let cfsr = CFSR::read(core)?;
+    let hfsr = HFSR::read(core)?;
+
+    if !cfsr.has_fault() && hfsr.0 == 0 {
+        println!("SCB: no faults recorded in CFSR or HFSR");
+        return Ok(());
+    }
+
+    println!("SCB: CFSR = 0x{:08x}, HFSR = 0x{:08x}", cfsr.0, hfsr.0);
+
+    if let Some(ufsr) = cfsr.get_ufsr() {
+        println!("     usage fault: {:?}", ufsr);
+    }
+
+    if let Some(bfsr) = cfsr.get_bfsr() {
+        print!("     bus fault: {:?}", bfsr);
+
+        if bfsr.bfarvalid() {
+            print!(" at 0x{:08x}", BFAR::read(core)?.address());
+        }
+
+        println!();
+    }
+
+    if let Some(mmfsr) = cfsr.get_mmfsr() {
+        print!("     memory management fault: {:?}", mmfsr);
+
+        if mmfsr.mmfarvalid() {
+            print!(" at 0x{:08x}", MMFAR::read(core)?.address());
+        }
+
+        println!();
+    }
+
+    if hfsr.forced_fault() {
+        println!("     hard fault: escalated from a configurable fault");
+    }
+
+    if hfsr.vector_fault() {
+        println!("     hard fault: vector table read failed");
+    }
+
+    if hfsr.debug_fault() {
+        println!("     hard fault: debug event");
+    }
+
+    Ok(())
+}
@@ -27,11 +27,11 @@ register!(DWT_CTRL, 0xe000_1000,
     pub exception_enabled, _: 18;
     pub cpi_enabled, _: 17;
     pub exception_trace_enabled, _: 16;
-    pub pc_sampling_enabled, _: 12;
+    pub pc_sampling_enabled, set_pc_sampling_enabled: 12;
     pub _synctap, _set_synctap: 11, 10;
-    pub postcnt_tap, _: 9;
+    pub postcnt_tap, set_postcnt_tap: 9;
     pub postcnt_init, _: 8, 5;
-    pub postcnt_reset, _: 4, 1;
+    pub postcnt_reset, set_postcnt_reset: 4, 1;
     pub cyccnt_enabled, set_cyccnt_enabled: 0;
 );
 
@@ -54,3 +54,18 @@ impl DWT_CTRL {
         self._set_synctap(val);
     }
 }
+
+/// Address of the DWT cycle count register
+pub const DWT_CYCCNT: u32 = 0xe000_1004;
+
+/// Address of the first DWT comparator register
+pub const DWT_COMP0: u32 = 0xe000_1020;
+
+/// Address of the first DWT comparator mask register (ARMv6-M and ARMv7-M)
+pub const DWT_MASK0: u32 = 0xe000_1024;
+
+/// Address of the first DWT comparator function register
+pub const DWT_FUNCTION0: u32 = 0xe000_1028;
+
+/// Distance between the registers of successive DWT comparators
+pub const DWT_COMP_STRIDE: u32 = 0x10;
@@ -724,7 +724,7 @@ pub fn etm_ingest(
     let mut hdr = ETM3Header::ASync;
     let mut runlen = 0;
 
-    tpiu_ingest(&valid, &mut readnext, |packet| {
+    let stats = tpiu_ingest(&valid, &mut readnext, |packet| {
         let payload = &mut vec;
 
         if state == IngestState::ASyncSearching {
@@ -802,5 +802,9 @@ pub fn etm_ingest(
         pstate = ETM3PacketState::AwaitingHeader;
 
         Ok(())
-    })
+    })?;
+
+    stats.report();
+
+    Ok(())
 }
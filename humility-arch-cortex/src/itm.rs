@@ -12,6 +12,7 @@ use anyhow::Result;
 use bitfield::bitfield;
 use humility::core::Core;
 use humility::hubris::HubrisArchive;
+use std::collections::BTreeMap;
 
 //
 // ITM Trace Enable Register
@@ -114,7 +115,6 @@ pub enum ITMPayload {
         port: u32,
         payload: Vec<u8>,
     },
-    #[allow(dead_code)]
     Hardware {
         source: u32,
         payload: [u8; 4],
@@ -151,6 +151,169 @@ pub struct ITMPacket {
     pub time: f64,
 }
 
+/// Per-stimulus port statistics gathered during ITM ingestion
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ITMPortStats {
+    /// Number of instrumentation packets received
+    pub packets: usize,
+
+    /// Number of payload bytes received
+    pub bytes: usize,
+
+    /// Number of overflows that immediately followed a packet on this port
+    pub overflows: usize,
+}
+
+impl ITMPortStats {
+    ///
+    /// Returns the estimated number of bytes lost on this port.  An overflow
+    /// indicates that at least one packet was dropped, but not how many; we
+    /// assume that each overflow dropped a single packet of the average size
+    /// for the port, making this a lower bound.
+    ///
+    pub fn lost(&self) -> usize {
+        if self.packets == 0 {
+            0
+        } else {
+            self.overflows * ((self.bytes + self.packets - 1) / self.packets)
+        }
+    }
+}
+
+///
+/// Statistics on the integrity of an ingested ITM stream:  a running count
+/// of overflow packets (which the ITM emits when its FIFO is full and data
+/// is dropped), of bytes discarded while resynchronizing, and of any gaps
+/// in the underlying TPIU stream.
+///
+#[derive(Clone, Debug, Default)]
+pub struct ITMStats {
+    /// Statistics for the TPIU stream, if the TPIU was not bypassed
+    pub tpiu: Option<TPIUStats>,
+
+    /// Number of overflow packets
+    pub overflows: usize,
+
+    /// Number of overflows that weren't preceded by any packet
+    pub unattributed: usize,
+
+    /// Number of times that synchronization was lost after being found
+    pub resyncs: usize,
+
+    /// Number of bytes discarded while resynchronizing
+    pub discarded: usize,
+
+    /// Statistics for each stimulus port that sent data
+    pub ports: BTreeMap<u32, ITMPortStats>,
+}
+
+impl ITMStats {
+    pub fn packets(&self) -> usize {
+        self.ports.values().map(|p| p.packets).sum()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.ports.values().map(|p| p.bytes).sum()
+    }
+
+    /// Returns the estimated number of bytes lost, over all ports
+    pub fn lost(&self) -> usize {
+        let (packets, bytes) = (self.packets(), self.bytes());
+
+        let unattributed = if packets == 0 {
+            0
+        } else {
+            self.unattributed * ((bytes + packets - 1) / packets)
+        };
+
+        self.ports.values().map(|p| p.lost()).sum::<usize>()
+            + unattributed
+            + self.discarded
+            + self.tpiu.map(|t| t.discarded).unwrap_or(0)
+    }
+
+    /// Reports the statistics, listing the ports most affected by loss
+    pub fn report(&self) {
+        let (packets, bytes) = (self.packets(), self.bytes());
+        let incomplete = self.tpiu.map(|t| t.incomplete()).unwrap_or(false);
+
+        humility::msg!(
+            "ITM: {packets} packets, {bytes} bytes on {} port{}",
+            self.ports.len(),
+            if self.ports.len() == 1 { "" } else { "s" }
+        );
+
+        if let Some(tpiu) = &self.tpiu {
+            if tpiu.triggers != 0 || tpiu.flushes != 0 {
+                humility::msg!(
+                    "ITM: {} TPIU triggers, {} TPIU flush synchronizations",
+                    tpiu.triggers,
+                    tpiu.flushes
+                );
+            }
+        }
+
+        if self.overflows == 0 && self.resyncs == 0 && !incomplete {
+            humility::msg!("ITM: no overflows or gaps detected");
+            return;
+        }
+
+        humility::msg!(
+            "ITM: {} overflows, {} resyncs ({} bytes discarded)",
+            self.overflows,
+            self.resyncs,
+            self.discarded
+        );
+
+        if let Some(tpiu) = &self.tpiu {
+            humility::msg!(
+                "ITM: {} TPIU gaps ({} bytes discarded) in {} frames",
+                tpiu.gaps,
+                tpiu.discarded,
+                tpiu.frames
+            );
+
+            if tpiu.errors != 0 {
+                humility::msg!(
+                    "ITM: {} TPIU frame errors ({} with reserved IDs)",
+                    tpiu.errors,
+                    tpiu.reserved
+                );
+            }
+        }
+
+        let lost = self.lost();
+
+        humility::msg!(
+            "ITM: estimated {lost} bytes lost ({:.1}% of trace)",
+            if bytes + lost == 0 {
+                0.0
+            } else {
+                (lost as f64 * 100.0) / (bytes + lost) as f64
+            }
+        );
+
+        let mut affected = self
+            .ports
+            .iter()
+            .filter(|(_, p)| p.overflows > 0)
+            .collect::<Vec<_>>();
+
+        affected.sort_by(|a, b| b.1.lost().cmp(&a.1.lost()));
+
+        for (port, p) in affected {
+            humility::msg!(
+                "ITM: port {port}: {} overflows, ~{} bytes lost \
+                ({} packets, {} bytes received)",
+                p.overflows,
+                p.lost(),
+                p.packets,
+                p.bytes
+            );
+        }
+    }
+}
+
 fn encode(hdr: ITMHeader) -> u8 {
     match hdr {
         ITMHeader::Sync => 0,
@@ -308,6 +471,17 @@ fn itm_payload_decode(hdr: ITMHeader, payload: &[u8]) -> ITMPayload {
             }
         }
 
+        ITMHeader::Hardware { a, .. } => {
+            let mut buf = [0; 4];
+            buf[..payload.len()].copy_from_slice(payload);
+
+            ITMPayload::Hardware {
+                source: a as u32,
+                payload: buf,
+                len: payload.len(),
+            }
+        }
+
         _ => ITMPayload::None,
     }
 }
@@ -316,7 +490,7 @@ pub fn itm_ingest(
     traceid: Option<u8>,
     mut readnext: impl FnMut() -> Result<Option<(u8, f64)>>,
     mut callback: impl FnMut(&ITMPacket) -> Result<()>,
-) -> Result<()> {
+) -> Result<ITMStats> {
     #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     enum IngestState {
         SyncSearching,
@@ -331,10 +505,18 @@ pub fn itm_ingest(
     let mut hdr = ITMHeader::Sync;
     let mut runlen = 0;
 
+    let mut stats = ITMStats::default();
+    let mut synced = false;
+    let mut last = None;
+
     let process = |packet: &TPIUPacket| -> Result<()> {
         let payload = &mut vec;
 
         if state == IngestState::SyncSearching {
+            if synced {
+                stats.discarded += 1;
+            }
+
             match packet.datum {
                 0 => runlen += 1,
                 0x80 => {
@@ -344,6 +526,7 @@ pub fn itm_ingest(
                             packet.offset
                         );
                         state = IngestState::Ingesting;
+                        synced = true;
                     }
                 }
                 _ => {
@@ -373,6 +556,7 @@ pub fn itm_ingest(
                         );
 
                         state = IngestState::SyncSearching;
+                        stats.resyncs += 1;
                         return Ok(());
                     }
                 };
@@ -400,9 +584,31 @@ pub fn itm_ingest(
         }
 
         if state == IngestState::Ingesting {
+            let payload = itm_payload_decode(hdr, payload);
+
+            match (&payload, hdr) {
+                (ITMPayload::Instrumentation { port, payload }, _) => {
+                    let p = stats.ports.entry(*port).or_default();
+                    p.packets += 1;
+                    p.bytes += payload.len();
+                    last = Some(*port);
+                }
+                (_, ITMHeader::Overflow) => {
+                    stats.overflows += 1;
+
+                    match last {
+                        Some(port) => {
+                            stats.ports.entry(port).or_default().overflows += 1
+                        }
+                        None => stats.unattributed += 1,
+                    }
+                }
+                _ => {}
+            }
+
             callback(&ITMPacket {
                 header: hdr,
-                payload: itm_payload_decode(hdr, payload),
+                payload,
                 offset: packet.offset,
                 time: packet.time,
             })?;
@@ -415,14 +621,19 @@ pub fn itm_ingest(
         Ok(())
     };
 
-    match traceid {
+    let tpiu = match traceid {
         Some(traceid) => {
             let mut valid = vec![false; 256];
             valid[traceid as usize] = true;
-            tpiu_ingest(&valid, &mut readnext, process)
+            Some(tpiu_ingest(&valid, &mut readnext, process)?)
         }
-        None => tpiu_ingest_bypass(&mut readnext, process),
-    }
+        None => {
+            tpiu_ingest_bypass(&mut readnext, process)?;
+            None
+        }
+    };
+
+    Ok(ITMStats { tpiu, ..stats })
 }
 
 ///
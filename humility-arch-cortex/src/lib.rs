@@ -6,6 +6,7 @@ pub mod debug;
 pub mod dwt;
 pub mod etm;
 pub mod itm;
+pub mod mpu;
 pub mod scs;
 pub mod swo;
 pub mod tpiu;
@@ -0,0 +1,145 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::debug::Register;
+use crate::register;
+use bitfield::bitfield;
+use humility::core::Core;
+
+//
+// MPU Type Register
+//
+register!(MPU_TYPE, 0xe000_ed90,
+    #[derive(Copy, Clone)]
+    #[allow(non_camel_case_types)]
+    pub struct MPU_TYPE(u32);
+    impl Debug;
+    /// Number of instruction regions (always 0: the MPU is unified)
+    pub iregion, _: 23, 16;
+    /// Number of data regions
+    pub dregion, _: 15, 8;
+    /// Separate instruction and data regions (always 0)
+    pub separate, _: 0;
+);
+
+//
+// MPU Control Register
+//
+register!(MPU_CTRL, 0xe000_ed94,
+    #[derive(Copy, Clone)]
+    #[allow(non_camel_case_types)]
+    pub struct MPU_CTRL(u32);
+    impl Debug;
+    /// Enable the default memory map as a background region for privileged
+    /// accesses
+    pub privdefena, _: 2;
+    /// Enable the MPU during HardFault, NMI and FAULTMASK handlers
+    pub hfnmiena, _: 1;
+    /// Enable the MPU
+    pub enable, _: 0;
+);
+
+//
+// MPU Region Number Register, which selects the region that is accessed
+// via MPU_RBAR and MPU_RASR (or MPU_RLAR)
+//
+register!(MPU_RNR, 0xe000_ed98,
+    #[derive(Copy, Clone)]
+    #[allow(non_camel_case_types)]
+    pub struct MPU_RNR(u32);
+    impl Debug;
+    pub region, set_region: 7, 0;
+);
+
+//
+// MPU Region Base Address Register (ARMv6-M and ARMv7-M)
+//
+register!(MPU_RBAR, 0xe000_ed9c,
+    #[derive(Copy, Clone)]
+    #[allow(non_camel_case_types)]
+    pub struct MPU_RBAR(u32);
+    impl Debug;
+    pub addr, _: 31, 5;
+    pub valid, _: 4;
+    pub region, _: 3, 0;
+);
+
+//
+// MPU Region Attribute and Size Register (ARMv6-M and ARMv7-M)
+//
+register!(MPU_RASR, 0xe000_eda0,
+    #[derive(Copy, Clone)]
+    #[allow(non_camel_case_types)]
+    pub struct MPU_RASR(u32);
+    impl Debug;
+    /// Execute never
+    pub xn, _: 28;
+    /// Access permissions
+    pub ap, _: 26, 24;
+    pub tex, _: 21, 19;
+    pub s, _: 18;
+    pub c, _: 17;
+    pub b, _: 16;
+    /// Subregion disable bits
+    pub srd, _: 15, 8;
+    /// Region size is 2^(size + 1) bytes
+    pub size, _: 5, 1;
+    pub enable, _: 0;
+);
+
+//
+// MPU Region Base Address Register (ARMv8-M)
+//
+register!(MPU_RBAR_V8, 0xe000_ed9c,
+    #[derive(Copy, Clone)]
+    #[allow(non_camel_case_types)]
+    pub struct MPU_RBAR_V8(u32);
+    impl Debug;
+    pub base, _: 31, 5;
+    /// Shareability
+    pub sh, _: 4, 3;
+    /// Access permissions
+    pub ap, _: 2, 1;
+    /// Execute never
+    pub xn, _: 0;
+);
+
+//
+// MPU Region Limit Address Register (ARMv8-M)
+//
+register!(MPU_RLAR, 0xe000_eda0,
+    #[derive(Copy, Clone)]
+    #[allow(non_camel_case_types)]
+    pub struct MPU_RLAR(u32);
+    impl Debug;
+    pub limit, _: 31, 5;
+    /// Index into MPU_MAIR0/MPU_MAIR1
+    pub attrindx, _: 3, 1;
+    pub enable, _: 0;
+);
+
+//
+// MPU Memory Attribute Indirection Registers (ARMv8-M)
+//
+register!(MPU_MAIR0, 0xe000_edc0,
+    #[derive(Copy, Clone)]
+    #[allow(non_camel_case_types)]
+    pub struct MPU_MAIR0(u32);
+    impl Debug;
+    pub attr3, _: 31, 24;
+    pub attr2, _: 23, 16;
+    pub attr1, _: 15, 8;
+    pub attr0, _: 7, 0;
+);
+
+register!(MPU_MAIR1, 0xe000_edc4,
+    #[derive(Copy, Clone)]
+    #[allow(non_camel_case_types)]
+    pub struct MPU_MAIR1(u32);
+    impl Debug;
+    pub attr7, _: 31, 24;
+    pub attr6, _: 23, 16;
+    pub attr5, _: 15, 8;
+    pub attr4, _: 7, 0;
+);
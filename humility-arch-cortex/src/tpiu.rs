@@ -137,6 +137,76 @@ pub struct TPIUPacket {
     pub time: f64,
 }
 
+///
+/// Statistics on the integrity of an ingested TPIU stream.  Once framing has
+/// been established, any byte that cannot be placed in a valid frame is
+/// discarded; each loss of framing is counted as a gap.  Auxiliary
+/// information from the formatter -- triggers and the half-word
+/// synchronization packets that pad the stream when it is flushed -- is
+/// counted rather than passed on.
+///
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TPIUStats {
+    /// Number of valid frames
+    pub frames: usize,
+
+    /// Number of times framing was lost after having been established
+    pub gaps: usize,
+
+    /// Number of bytes discarded after framing was first established
+    pub discarded: usize,
+
+    /// Number of frames that failed validation after framing was
+    /// established, each of which results in a gap
+    pub errors: usize,
+
+    /// Number of frames containing a reserved source ID
+    pub reserved: usize,
+
+    /// Number of trigger events
+    pub triggers: usize,
+
+    /// Number of half-word synchronization packets, which the formatter
+    /// emits to pad a frame when flushing
+    pub flushes: usize,
+}
+
+impl TPIUStats {
+    /// Returns true if any data is known to have been lost
+    pub fn incomplete(&self) -> bool {
+        self.gaps != 0 || self.discarded != 0 || self.errors != 0
+    }
+
+    pub fn report(&self) {
+        humility::msg!(
+            "TPIU: {} frames, {} gaps ({} bytes discarded), {} frame errors",
+            self.frames,
+            self.gaps,
+            self.discarded,
+            self.errors,
+        );
+
+        if self.reserved != 0 {
+            humility::msg!(
+                "TPIU: {} frames contained a reserved source ID",
+                self.reserved
+            );
+        }
+
+        if self.triggers != 0 || self.flushes != 0 {
+            humility::msg!(
+                "TPIU: {} triggers, {} flush synchronization packets",
+                self.triggers,
+                self.flushes
+            );
+        }
+
+        if self.incomplete() {
+            humility::warn!("TPIU: capture is incomplete; data has been lost");
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum TPIUState {
     Searching,
@@ -147,6 +217,16 @@ enum TPIUState {
 
 const TPIU_FRAME_SYNC: [u8; 4] = [0xff, 0xff, 0xff, 0x7f];
 const TPIU_ID_NULL: u8 = 0;
+const TPIU_ID_TRIGGER: u8 = 0x7d;
+const TPIU_HALFWORD_SYNC: u16 = 0x7fff;
+
+///
+/// Source IDs 0x70 through 0x7c are reserved, as is 0x7e; 0x7f can't appear
+/// as an ID, as it denotes a half-word synchronization packet.
+///
+fn tpiu_id_reserved(id: u8) -> bool {
+    (0x70..=0x7c).contains(&id) || id == 0x7e || id == 0x7f
+}
 
 fn tpiu_next_state(state: TPIUState, byte: u8, offset: usize) -> TPIUState {
     let sync = &TPIU_FRAME_SYNC;
@@ -214,11 +294,18 @@ fn tpiu_next_state(state: TPIUState, byte: u8, offset: usize) -> TPIUState {
     nstate
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TPIUFrameCheck {
+    Valid,
+    Invalid,
+    Reserved(u8),
+}
+
 fn tpiu_check_frame(
     frame: &[(u8, f64, usize)],
     valid: &[bool],
     intermixed: bool,
-) -> bool {
+) -> TPIUFrameCheck {
     //
     // To check a frame, we go through its half words, checking them for
     // inconsistency.  The false positive rate will very much depend on how
@@ -231,28 +318,44 @@ fn tpiu_check_frame(
         let base = i * 2;
         let half = TPIUFrameHalfWord::from((frame[base].0, frame[base + 1].0));
 
+        //
+        // A half-word synchronization packet pads the frame when the
+        // formatter is flushed; it can appear anywhere but in the last
+        // half-word (the second byte of which is the auxiliary byte).
+        //
+        if half.0 == TPIU_HALFWORD_SYNC && i < max - 1 {
+            continue;
+        }
+
         if half.f_control() {
+            let id = half.data_or_id() as u8;
+
             //
             // The NULL source identifier denotes data we need to explicitly
-            // chuck; check for it.
+            // chuck, and the trigger identifier denotes a trigger event;
+            // check for both.
             //
-            if half.data_or_id() == TPIU_ID_NULL as u16 {
+            if id == TPIU_ID_NULL || id == TPIU_ID_TRIGGER {
                 continue;
             }
 
+            if tpiu_id_reserved(id) {
+                return TPIUFrameCheck::Reserved(id);
+            }
+
             //
             // The two conditions under which we can reject a frame:  we
             // either have an ID that isn't expected, or we are not expecting
             // intermixed output and we have an ID on anything but the first
             // half-word of the frame.
             //
-            if !valid[half.data_or_id() as usize] || (i > 0 && !intermixed) {
-                return false;
+            if !valid[id as usize] || (i > 0 && !intermixed) {
+                return TPIUFrameCheck::Invalid;
             }
         }
     }
 
-    true
+    TPIUFrameCheck::Valid
 }
 
 fn tpiu_check_byte(byte: u8, valid: &[bool]) -> bool {
@@ -261,11 +364,32 @@ fn tpiu_check_byte(byte: u8, valid: &[bool]) -> bool {
     check.f_control() && valid[check.data_or_id() as usize]
 }
 
+///
+/// Passes a datum on to the caller, unless it is from the NULL source (in
+/// which case it is dropped) or denotes a trigger (in which case it is
+/// counted).
+///
+fn tpiu_emit(
+    packet: &TPIUPacket,
+    stats: &mut TPIUStats,
+    callback: &mut impl FnMut(&TPIUPacket) -> Result<()>,
+) -> Result<()> {
+    match packet.id {
+        Some(TPIU_ID_NULL) => Ok(()),
+        Some(TPIU_ID_TRIGGER) => {
+            stats.triggers += 1;
+            Ok(())
+        }
+        _ => callback(packet),
+    }
+}
+
 fn tpiu_process_frame(
     frame: &[(u8, f64, usize)],
     id: Option<u8>,
+    stats: &mut TPIUStats,
     mut callback: impl FnMut(&TPIUPacket) -> Result<()>,
-) -> Result<u8> {
+) -> Result<Option<u8>> {
     let high = frame.len() - 1;
     let aux = TPIUFrameHalfWord::from((frame[high - 1].0, frame[high].0));
     let max = frame.len() / 2;
@@ -277,6 +401,11 @@ fn tpiu_process_frame(
         let auxbit = ((aux.data_or_aux() & (1 << i)) >> i) as u8;
         let last = i == max - 1;
 
+        if half.0 == TPIU_HALFWORD_SYNC && !last {
+            stats.flushes += 1;
+            continue;
+        }
+
         if half.f_control() {
             //
             // If our bit is set, the sense of the auxiliary bit tells us
@@ -298,17 +427,17 @@ fn tpiu_process_frame(
                 // Specification), and applies to the subsequent record.  So
                 // in this case, we just return the ID.
                 //
-                return Ok(packet.id.unwrap());
+                return Ok(packet.id);
             }
 
             match (delay, current) {
                 (false, _) => {
-                    callback(&packet)?;
+                    tpiu_emit(&packet, stats, &mut callback)?;
                 }
                 (true, Some(current)) => {
                     let saved = packet.id;
                     packet.id = Some(current);
-                    callback(&packet)?;
+                    tpiu_emit(&packet, stats, &mut callback)?;
                     packet.id = saved;
                 }
                 (true, None) => {
@@ -317,6 +446,7 @@ fn tpiu_process_frame(
                     // byte, but also warn about it.
                     //
                     warn!("orphaned byte at offset {}", packet.offset);
+                    stats.discarded += 1;
                 }
             }
 
@@ -337,23 +467,27 @@ fn tpiu_process_frame(
                 }
             };
 
-            callback(&TPIUPacket {
+            let packet = TPIUPacket {
                 id: Some(id),
                 datum: (half.data_or_id() << 1) as u8 | auxbit,
                 time: frame[base].1,
                 offset: frame[base].2,
-            })?;
+            };
+
+            tpiu_emit(&packet, stats, &mut callback)?;
 
             if last {
-                return Ok(id);
+                return Ok(Some(id));
             }
 
-            callback(&TPIUPacket {
-                id: Some(id),
+            let packet = TPIUPacket {
                 datum: half.data_or_aux() as u8,
                 time: frame[base + 1].1,
                 offset: frame[base + 1].2,
-            })?;
+                ..packet
+            };
+
+            tpiu_emit(&packet, stats, &mut callback)?;
         }
     }
 
@@ -395,8 +529,9 @@ pub fn tpiu_ingest(
     valid: &[bool],
     mut readnext: impl FnMut() -> Result<Option<(u8, f64)>>,
     mut callback: impl FnMut(&TPIUPacket) -> Result<()>,
-) -> Result<()> {
+) -> Result<TPIUStats> {
     let mut state = TPIUState::Searching;
+    let mut stats = TPIUStats::default();
 
     let mut ndx = 0;
     let mut frame: Vec<(u8, f64, usize)> = vec![(0u8, 0.0, 0); 16];
@@ -408,14 +543,6 @@ pub fn tpiu_ingest(
     let mut datum: u8;
     let mut time: f64;
 
-    let mut filter = |packet: &TPIUPacket| {
-        if packet.id == Some(TPIU_ID_NULL) {
-            Ok(())
-        } else {
-            callback(packet)
-        }
-    };
-
     loop {
         if !replay.is_empty() {
             let popped = replay.pop().unwrap();
@@ -439,9 +566,14 @@ pub fn tpiu_ingest(
 
         match state {
             TPIUState::SearchingSyncing(_) | TPIUState::FramingSyncing(_) => {
+                let prev = state;
                 state = tpiu_next_state(state, datum, offs);
 
                 if state == TPIUState::Searching {
+                    if let TPIUState::FramingSyncing(_) = prev {
+                        stats.gaps += 1;
+                    }
+
                     //
                     // We just got kicked back into searching; we need to
                     // replay this datum to see if it starts a frame.
@@ -461,6 +593,10 @@ pub fn tpiu_ingest(
                         }
                         TPIUState::Searching => {
                             if !tpiu_check_byte(datum, valid) {
+                                if stats.frames > 0 {
+                                    stats.discarded += 1;
+                                }
+
                                 continue;
                             }
                         }
@@ -481,26 +617,39 @@ pub fn tpiu_ingest(
                 // We have a complete frame.  We need to now check the entire
                 // frame.
                 //
-                if tpiu_check_frame(&frame, valid, true) {
+                if tpiu_check_frame(&frame, valid, true)
+                    == TPIUFrameCheck::Valid
+                {
                     humility::msg!(
                         "valid TPIU frame starting at offset {}",
                         frame[0].2
                     );
-                    id = Some(tpiu_process_frame(&frame, id, &mut filter)?);
+                    id = tpiu_process_frame(
+                        &frame,
+                        id,
+                        &mut stats,
+                        &mut callback,
+                    )?;
                     state = TPIUState::Framing;
+                    stats.frames += 1;
                     nvalid = 1;
                     ndx = 0;
                     continue;
                 }
 
                 //
-                // That wasn't a valid frame; we need to replay.
+                // That wasn't a valid frame; we need to replay.  (The first
+                // byte of the frame is not replayed, and is discarded.)
                 //
                 while ndx > 1 {
                     replay.push(frame[ndx - 1]);
                     ndx -= 1;
                 }
 
+                if stats.frames > 0 {
+                    stats.discarded += 1;
+                }
+
                 ndx = 0;
             }
 
@@ -531,7 +680,17 @@ pub fn tpiu_ingest(
                 // be correct.  If this fails, we need to go back in time
                 // and resume our search for a frame.
                 //
-                if !tpiu_check_frame(&frame, valid, true) {
+                let check = tpiu_check_frame(&frame, valid, true);
+
+                if let TPIUFrameCheck::Reserved(reserved) = check {
+                    warn!(
+                        "reserved source ID 0x{:x} in frame at offset {}",
+                        reserved, frame[0].2
+                    );
+                    stats.reserved += 1;
+                }
+
+                if check != TPIUFrameCheck::Valid {
                     warn!(
                         "after {} frame{}, invalid frame at offset {}",
                         nvalid,
@@ -545,10 +704,18 @@ pub fn tpiu_ingest(
                     }
 
                     nvalid = 0;
+                    stats.gaps += 1;
+                    stats.errors += 1;
                     state = TPIUState::Searching;
                 } else {
                     nvalid += 1;
-                    id = Some(tpiu_process_frame(&frame, id, &mut filter)?);
+                    stats.frames += 1;
+                    id = tpiu_process_frame(
+                        &frame,
+                        id,
+                        &mut stats,
+                        &mut callback,
+                    )?;
                 }
 
                 ndx = 0;
@@ -558,5 +725,186 @@ pub fn tpiu_ingest(
 
     humility::msg!("{nvalid} valid TPIU frames");
 
-    Ok(())
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ID: u8 = 1;
+
+    fn valid() -> Vec<bool> {
+        let mut valid = vec![false; 128];
+        valid[ID as usize] = true;
+        valid
+    }
+
+    fn control(id: u8) -> u8 {
+        (id << 1) | 1
+    }
+
+    //
+    // A frame that sets the ID in its first half-word, and then carries only
+    // data:  0x10, followed by 0x20 through 0x2c.
+    //
+    fn data_frame() -> Vec<u8> {
+        let mut frame = vec![control(ID), 0x10];
+        frame.extend(0x20..=0x2c);
+        frame.push(0x00);
+        frame
+    }
+
+    //
+    // A frame that contains a trigger, a datum of 0x30 with our ID, five
+    // half-word synchronization packets (padding from a flush), and a final
+    // datum of 0x32.
+    //
+    fn flush_frame() -> Vec<u8> {
+        let mut frame = vec![control(TPIU_ID_TRIGGER), 0x00, control(ID), 0x30];
+
+        for _ in 0..5 {
+            frame.extend([0xff, 0x7f]);
+        }
+
+        frame.extend([0x32, 0x00]);
+        frame
+    }
+
+    fn ingest(stream: &[u8]) -> (Vec<(Option<u8>, u8)>, TPIUStats) {
+        let mut iter = stream.iter();
+        let mut packets = vec![];
+
+        let stats = tpiu_ingest(
+            &valid(),
+            || Ok(iter.next().map(|&b| (b, 0.0))),
+            |p| {
+                packets.push((p.id, p.datum));
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        (packets, stats)
+    }
+
+    fn data() -> Vec<(Option<u8>, u8)> {
+        [0x10].into_iter().chain(0x20..=0x2c).map(|d| (Some(ID), d)).collect()
+    }
+
+    #[test]
+    fn check_frame() {
+        let valid = valid();
+        let frame = |bytes: Vec<u8>| {
+            bytes.into_iter().map(|b| (b, 0.0, 0)).collect::<Vec<_>>()
+        };
+
+        let check = |bytes, intermixed| {
+            tpiu_check_frame(&frame(bytes), &valid, intermixed)
+        };
+
+        assert_eq!(check(data_frame(), false), TPIUFrameCheck::Valid);
+        assert_eq!(check(flush_frame(), true), TPIUFrameCheck::Valid);
+
+        let mut bad = data_frame();
+        bad[0] = control(2);
+        assert_eq!(check(bad, true), TPIUFrameCheck::Invalid);
+
+        let mut reserved = data_frame();
+        reserved[0] = control(0x70);
+        assert_eq!(check(reserved, true), TPIUFrameCheck::Reserved(0x70));
+
+        let mut late = data_frame();
+        late[2] = control(ID);
+        assert_eq!(check(late.clone(), false), TPIUFrameCheck::Invalid);
+        assert_eq!(check(late, true), TPIUFrameCheck::Valid);
+    }
+
+    #[test]
+    fn ingest_frames() {
+        let mut stream = TPIU_FRAME_SYNC.to_vec();
+        stream.extend(data_frame());
+        stream.extend(data_frame());
+
+        let (packets, stats) = ingest(&stream);
+
+        assert_eq!(packets, [data(), data()].concat());
+        assert_eq!(stats.frames, 2);
+        assert!(!stats.incomplete());
+    }
+
+    #[test]
+    fn ingest_without_sync() {
+        //
+        // Without a synchronization packet, framing is established by
+        // finding a valid frame.
+        //
+        let (packets, stats) = ingest(&data_frame());
+
+        assert_eq!(packets, data());
+        assert_eq!(stats.frames, 1);
+    }
+
+    #[test]
+    fn ingest_trigger_and_flush() {
+        let mut stream = TPIU_FRAME_SYNC.to_vec();
+        stream.extend(data_frame());
+        stream.extend(flush_frame());
+
+        let (packets, stats) = ingest(&stream);
+
+        let mut expected = data();
+        expected.extend([(Some(ID), 0x30), (Some(ID), 0x32)]);
+
+        assert_eq!(packets, expected);
+        assert_eq!(stats.frames, 2);
+        assert_eq!(stats.triggers, 1);
+        assert_eq!(stats.flushes, 5);
+        assert!(!stats.incomplete());
+    }
+
+    #[test]
+    fn ingest_gap() {
+        //
+        // A synchronization packet that is cut short loses framing; the
+        // byte that derailed it is discarded, and framing is reestablished
+        // by the next synchronization packet.
+        //
+        let mut stream = TPIU_FRAME_SYNC.to_vec();
+        stream.extend(data_frame());
+        stream.extend([0xff, 0x00]);
+        stream.extend(TPIU_FRAME_SYNC);
+        stream.extend(data_frame());
+
+        let (packets, stats) = ingest(&stream);
+
+        assert_eq!(packets, [data(), data()].concat());
+        assert_eq!(stats.frames, 2);
+        assert_eq!(stats.gaps, 1);
+        assert_eq!(stats.discarded, 1);
+        assert_eq!(stats.errors, 0);
+        assert!(stats.incomplete());
+    }
+
+    #[test]
+    fn ingest_invalid_frame() {
+        //
+        // A frame with an unexpected ID after framing has been established
+        // is an error, and results in a gap.
+        //
+        let mut bad = data_frame();
+        bad[0] = control(2);
+
+        let mut stream = TPIU_FRAME_SYNC.to_vec();
+        stream.extend(data_frame());
+        stream.extend(bad);
+
+        let (packets, stats) = ingest(&stream);
+
+        assert_eq!(packets, data());
+        assert_eq!(stats.frames, 1);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.gaps, 1);
+        assert!(stats.incomplete());
+    }
 }
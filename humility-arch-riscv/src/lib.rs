@@ -0,0 +1,183 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::ToPrimitive;
+use std::collections::BTreeMap;
+
+#[derive(Copy, Clone, Debug)]
+pub struct RVRegisterField {
+    pub highbit: u16,
+    pub lowbit: u16,
+    pub name: &'static str,
+}
+
+impl RVRegisterField {
+    fn field(highbit: u16, lowbit: u16, name: &'static str) -> Self {
+        Self { highbit, lowbit, name }
+    }
+    fn bit(bit: u16, name: &'static str) -> Self {
+        Self { highbit: bit, lowbit: bit, name }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Hash,
+    FromPrimitive,
+    ToPrimitive,
+    PartialEq,
+    Eq,
+    Ord,
+    PartialOrd,
+)]
+///
+/// The definition of a RISC-V register, as encoded in the register number
+/// (regno) of an Access Register abstract command; see (e.g.) 3.7.1.1 in
+/// the RISC-V External Debug Support specification.  CSRs are numbered by
+/// their CSR address, and the general purpose registers (which we name by
+/// their ABI names) start at 0x1000.  Note that the PC of a halted hart is
+/// found in DPC.
+///
+pub enum RVRegister {
+    MSTATUS = 0x300,
+    MISA = 0x301,
+    MIE = 0x304,
+    MTVEC = 0x305,
+    MSCRATCH = 0x340,
+    MEPC = 0x341,
+    MCAUSE = 0x342,
+    MTVAL = 0x343,
+    MIP = 0x344,
+    DCSR = 0x7b0,
+    PC = 0x7b1,
+    ZERO = 0x1000,
+    RA,
+    SP,
+    GP,
+    TP,
+    T0,
+    T1,
+    T2,
+    S0,
+    S1,
+    A0,
+    A1,
+    A2,
+    A3,
+    A4,
+    A5,
+    A6,
+    A7,
+    S2,
+    S3,
+    S4,
+    S5,
+    S6,
+    S7,
+    S8,
+    S9,
+    S10,
+    S11,
+    T3,
+    T4,
+    T5,
+    T6,
+}
+
+impl RVRegister {
+    pub fn is_general_purpose(&self) -> bool {
+        self.to_u16() >= RVRegister::ZERO.to_u16()
+    }
+
+    pub fn is_csr(&self) -> bool {
+        !self.is_general_purpose()
+    }
+
+    pub fn max() -> u16 {
+        RVRegister::T6.to_u16().unwrap()
+    }
+
+    pub fn fields(&self) -> Option<Vec<RVRegisterField>> {
+        match self {
+            RVRegister::MSTATUS => Some(vec![
+                RVRegisterField::bit(17, "MPRV"),
+                RVRegisterField::field(14, 13, "FS"),
+                RVRegisterField::field(12, 11, "MPP"),
+                RVRegisterField::bit(7, "MPIE"),
+                RVRegisterField::bit(3, "MIE"),
+            ]),
+            RVRegister::DCSR => Some(vec![
+                RVRegisterField::field(31, 28, "XDEBUGVER"),
+                RVRegisterField::bit(15, "EBREAKM"),
+                RVRegisterField::bit(12, "EBREAKU"),
+                RVRegisterField::bit(11, "STEPIE"),
+                RVRegisterField::bit(10, "STOPCOUNT"),
+                RVRegisterField::bit(9, "STOPTIME"),
+                RVRegisterField::field(8, 6, "CAUSE"),
+                RVRegisterField::bit(2, "STEP"),
+                RVRegisterField::field(1, 0, "PRV"),
+            ]),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Describes the trap denoted by the value of MCAUSE.
+///
+pub fn mcause_describe(mcause: u32) -> String {
+    let code = mcause & !(1 << 31);
+
+    let desc = if mcause & (1 << 31) != 0 {
+        match code {
+            1 => "supervisor software interrupt",
+            3 => "machine software interrupt",
+            5 => "supervisor timer interrupt",
+            7 => "machine timer interrupt",
+            9 => "supervisor external interrupt",
+            11 => "machine external interrupt",
+            _ => return format!("interrupt {code}"),
+        }
+    } else {
+        match code {
+            0 => "instruction address misaligned",
+            1 => "instruction access fault",
+            2 => "illegal instruction",
+            3 => "breakpoint",
+            4 => "load address misaligned",
+            5 => "load access fault",
+            6 => "store/AMO address misaligned",
+            7 => "store/AMO access fault",
+            8 => "environment call from U-mode",
+            9 => "environment call from S-mode",
+            11 => "environment call from M-mode",
+            12 => "instruction page fault",
+            13 => "load page fault",
+            15 => "store/AMO page fault",
+            _ => return format!("exception {code}"),
+        }
+    };
+
+    desc.to_string()
+}
+
+pub fn unhalted_read_regions() -> BTreeMap<u32, u32> {
+    //
+    // Unlike ARM, RISC-V has no architecturally defined region that can
+    // be read without halting the hart:  whether memory can be accessed
+    // while running depends on whether the debug module implements system
+    // bus access, which we don't assume.
+    //
+    BTreeMap::new()
+}
+
+impl std::fmt::Display for RVRegister {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.pad(&format!("{:?}", self))
+    }
+}
@@ -65,6 +65,15 @@ impl Environment {
         Ok(rval)
     }
 
+    ///
+    /// Returns each target in the environment along with its probe.
+    ///
+    pub fn probes(filename: &str) -> Result<Vec<(String, String)>> {
+        let env = Self::read(filename)?;
+
+        Ok(env.into_iter().map(|(target, e)| (target, e.probe)).collect())
+    }
+
     pub fn from_file(filename: &str, target: &str) -> Result<Self> {
         let env = Self::read(filename)?;
 
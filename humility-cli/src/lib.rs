@@ -4,10 +4,13 @@
 
 mod env;
 
+pub use env::Environment;
+
 use anyhow::Result;
 use clap::{AppSettings, ArgGroup, ArgMatches, Parser};
 use env::Environment;
 use humility::{core::Core, hubris::HubrisArchive, msg, warn};
+use humility_log::LogFormat;
 
 #[derive(Parser, Debug, Clone)]
 #[clap(
@@ -16,9 +19,23 @@ use humility::{core::Core, hubris::HubrisArchive, msg, warn};
 )]
 #[clap(global_setting(AppSettings::NoAutoVersion))]
 pub struct Cli {
-    /// verbose messages
-    #[clap(long, short)]
-    pub verbose: bool,
+    /// verbose messages; specify twice for trace-level log messages
+    #[clap(long, short, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// log filter, as a level (e.g., "debug") or as a subsystem and a level
+    /// (e.g., "hiffy=debug"); may be specified multiple times
+    #[clap(
+        long,
+        value_name = "filter",
+        multiple_occurrences = true,
+        use_value_delimiter = true
+    )]
+    pub log: Vec<String>,
+
+    /// format of log messages and failures: "text" or "json"
+    #[clap(long, value_name = "format", default_value = "text")]
+    pub log_format: LogFormat,
 
     /// terse output
     #[clap(long, short = 'T', hide = true)]
@@ -31,6 +48,39 @@ pub struct Cli {
     )]
     pub timeout: u32,
 
+    /// verify memory reads by performing each read twice, retrying on a
+    /// mismatch; useful for marginal debug or network links
+    #[clap(long)]
+    pub verify_reads: bool,
+
+    /// do not cache (and coalesce) memory reads made while the target is
+    /// halted
+    #[clap(long)]
+    pub no_read_cache: bool,
+
+    /// after the command completes, summarize its wall time and its impact
+    /// on the target (bytes read and written, HIF executions, and time that
+    /// the core was halted)
+    #[clap(long)]
+    pub stats: bool,
+
+    /// for a command that modifies the target, report the operations that
+    /// it would perform on the target rather than performing them
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// file to which a record of each command that modifies the target is
+    /// appended; absent this, records are appended to
+    /// ~/.humility/audit.log if it exists.  This may also be set via the
+    /// HUMILITY_AUDIT_LOG environment variable.
+    #[clap(
+        long,
+        value_name = "file",
+        env = "HUMILITY_AUDIT_LOG",
+        hide_env = true
+    )]
+    pub audit_log: Option<String>,
+
     /// print version information
     #[clap(long, short = 'V')]
     pub version: bool,
@@ -38,10 +88,11 @@ pub struct Cli {
     /// If a system has multiple debug probes attached, the specific probe to
     /// use.  If specifying a USB probe, its index can be used (e.g.,
     /// "usb-0"); if specifying an exact probe, this is of the form
-    /// "vid:pid[:serial]". If set to "archive", the archive is used rather
-    /// than any attached debug probe. This may also be set via the
-    /// HUMILITY_PROBE environment variable. Run "humility doc" for more
-    /// information on probes.
+    /// "vid:pid[:serial]"; if specifying a remote Humility serving a probe,
+    /// this is of the form "remote:host[:port]". If set to "archive", the
+    /// archive is used rather than any attached debug probe. This may also be
+    /// set via the HUMILITY_PROBE environment variable. Run "humility doc"
+    /// for more information on probes.
     #[clap(long, short, group = "hubris")]
     pub probe: Option<String>,
 
@@ -86,16 +137,32 @@ pub struct Cli {
     // even though it is only used for flashing (which we don't use probe-rs
     // to do).  Historically, we had a `-c` option to specify this, but its
     // presence was causing confusion and it has been deprecated.  However,
-    // Hubris uses Humility to flash, and specifies this option.  Because we
-    // may want to use this properly in the future (namely, if/when we use
-    // probe-rs to flash), we continue to accept this option (and test for its
-    // presence), thereby eliminating two potential Hubris flag days.  (Until
-    // it once again means something, we hide the option from the help
-    // output.)
+    // Hubris uses Humility to flash, and specifies this option, so we have
+    // continued to accept it.  It once again means something:  absent an
+    // archive, the chip determines the architecture of the target, and must
+    // be specified to attach to a target that isn't ARM (e.g., RISC-V).
     //
-    #[clap(long, short, env = "HUMILITY_CHIP", hide = true)]
+    /// chip to attach to, if not specified by the archive
+    #[clap(long, short, env = "HUMILITY_CHIP")]
     pub chip: Option<String>,
 
+    /// Index of the core to operate on, for targets with multiple cores
+    /// (e.g., the Cortex-M4 of an STM32H7 dual core part). The default is
+    /// core 0. This may also be set via the HUMILITY_CORE environment
+    /// variable.
+    #[clap(
+        long = "core",
+        value_name = "n",
+        env = "HUMILITY_CORE",
+        hide_env = true
+    )]
+    pub core_index: Option<usize>,
+
+    /// When halting the selected core of a target with multiple cores,
+    /// halt its other cores as well, resuming them when it is resumed
+    #[clap(long = "halt-all-cores")]
+    pub halt_all_cores: bool,
+
     /// List targets within an environment. Run "humility doc" for more
     /// information on Humility environments.
     #[clap(
@@ -105,6 +172,14 @@ pub struct Cli {
     )]
     pub list_targets: bool,
 
+    /// File naming several targets, one per line, against all of which the
+    /// command is to be run concurrently.  Each line consists of a probe (in
+    /// any form accepted by --probe, or a USB probe's serial number),
+    /// optionally preceded by a name for the target.  Run "humility doc"
+    /// for more information on running against multiple targets.
+    #[clap(long, value_name = "file", group = "hubris")]
+    pub targets: Option<String>,
+
     #[clap(subcommand)]
     pub cmd: Option<Subcommand>,
 }
@@ -261,6 +336,15 @@ impl ExecutionContext {
             _ => None,
         };
 
+        //
+        // A probe may be an alias for a specific probe (see `humility
+        // probes`); resolve it now so that everything downstream sees the
+        // probe itself.
+        //
+        if let Some(ref probe) = cli.probe {
+            cli.probe = Some(humility::probes::resolve(probe)?);
+        }
+
         if cli.cmd.is_none() {
             eprintln!("humility failed: subcommand expected (--help to list)");
             std::process::exit(1);
@@ -7,8 +7,17 @@ use clap::Command as ClapCommand;
 use humility::core::Core;
 use humility::hubris::*;
 use humility_cli::Cli;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use std::time::Duration;
 
+#[cfg(not(windows))]
+mod tty;
+
+#[cfg(not(windows))]
+pub use tty::UnrawTermiosGuard;
+
 #[allow(dead_code)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Archive {
@@ -63,6 +72,48 @@ pub enum CommandKind {
     Raw,
 }
 
+///
+/// Wraps the specified core as needed to account for its use (if we have
+/// been asked for statistics or for verbose output) and to verify reads (if
+/// we have been asked to do so).  Note that statistics reflect the reads
+/// actually made of the target, including those made to verify a read.
+/// Reads made while the target is halted are cached unless we have been
+/// asked not to -- or to verify reads, which caching would defeat.  Finally,
+/// if this is a dry run, operations that would modify the target are
+/// reported rather than performed (see [`dry_run`]).
+///
+fn wrap(args: &Cli, mut core: Box<dyn Core>) -> Box<dyn Core> {
+    core.set_read_cache(!args.no_read_cache && !args.verify_reads);
+
+    let core: Box<dyn Core> = if args.stats || args.verbose > 0 {
+        Box::new(humility::core::StatsCore::new(core))
+    } else {
+        core
+    };
+
+    let core: Box<dyn Core> = if args.verify_reads {
+        Box::new(humility::core::VerifyingCore::new(core))
+    } else {
+        core
+    };
+
+    dry_run(args, core)
+}
+
+///
+/// Wraps the specified core in a [`humility::core::DryRunCore`] if we have
+/// been asked to perform a dry run.  This is performed as part of attaching
+/// to a live system; commands that attach on their own (e.g., `flash` or
+/// `reset`) must call this explicitly.
+///
+pub fn dry_run(args: &Cli, core: Box<dyn Core>) -> Box<dyn Core> {
+    if args.dry_run {
+        Box::new(humility::core::DryRunCore::new(core))
+    } else {
+        core
+    }
+}
+
 pub fn attach_live(
     args: &Cli,
     hubris: &HubrisArchive,
@@ -77,7 +128,25 @@ pub fn attach_live(
             None => "auto",
         };
 
-        humility::core::attach(probe, hubris)
+        //
+        // The chip specified by the archive takes precedence; absent one, we
+        // use the chip on the command line (if any).
+        //
+        let core = match (&args.chip, hubris.chip()) {
+            (Some(chip), None) => {
+                humility::core::attach_to_chip(probe, hubris, Some(chip))?
+            }
+            _ => humility::core::attach(probe, hubris)?,
+        };
+
+        let mut core = wrap(args, core);
+
+        if args.core_index.is_some() || args.halt_all_cores {
+            let index = args.core_index.unwrap_or(0);
+            core.select_core(index, args.halt_all_cores)?;
+        }
+
+        Ok(core)
     }
 }
 
@@ -95,7 +164,8 @@ pub fn attach_dump(
 pub fn attach_net(args: &Cli, hubris: &HubrisArchive) -> Result<Box<dyn Core>> {
     if let Some(ip) = &args.ip {
         let timeout = Duration::from_millis(args.timeout as u64);
-        humility_net_core::attach_net(ip, hubris, timeout)
+        let core = humility_net_core::attach_net(ip, hubris, timeout)?;
+        Ok(wrap(args, core))
     } else {
         bail!("must be run against a specified IP address");
     }
@@ -173,6 +243,40 @@ pub fn attach(
     (run)(context)
 }
 
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static INTERRUPT_HANDLER: Mutex<bool> = Mutex::new(false);
+
+///
+/// Returns a flag that is set when the user hits ^C.  A process may only
+/// have one ^C handler, so it is installed on the first call; subsequent
+/// calls (e.g., by later commands run from the REPL) merely clear the flag.
+///
+pub fn interrupted() -> Result<&'static AtomicBool> {
+    let mut installed = INTERRUPT_HANDLER.lock().unwrap();
+
+    if !*installed {
+        ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst))?;
+        *installed = true;
+    }
+
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    Ok(&INTERRUPTED)
+}
+
+///
+/// Asks the user to confirm an operation, returning true only if the answer
+/// is in the affirmative.
+///
+pub fn confirm(prompt: &str) -> Result<bool> {
+    eprint!("humility: {prompt} [y/N] ");
+    io::stderr().flush()?;
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+
+    Ok(matches!(line.trim(), "y" | "Y" | "yes"))
+}
+
 pub struct Dumper {
     /// Word size, in bytes
     pub size: usize,
@@ -210,12 +314,32 @@ impl Dumper {
     }
 
     pub fn dump(&self, bytes: &[u8], addr: u32) {
+        self.dump_inner(bytes, addr, None);
+    }
+
+    ///
+    /// Dumps the specified bytes, highlighting (in reverse video) any units
+    /// that differ from the corresponding units in `prev`.
+    ///
+    pub fn dump_changes(&self, bytes: &[u8], addr: u32, prev: &[u8]) {
+        self.dump_inner(bytes, addr, Some(prev));
+    }
+
+    fn dump_inner(&self, bytes: &[u8], addr: u32, prev: Option<&[u8]>) {
         let size = self.size;
         let width = self.width;
         let mut addr = addr;
         let mut indent = if self.hanging { 0 } else { self.indent };
 
-        let print = |line: &[u8], addr, offs, indent| {
+        //
+        // Determines if the unit at the specified range of bytes has changed.
+        //
+        let changed = |range: std::ops::Range<usize>| match prev {
+            Some(prev) => prev.get(range.clone()) != bytes.get(range),
+            None => false,
+        };
+
+        let print = |line: &[u8], start: usize, addr, offs, indent| {
             print!(
                 "{:indent$}0x{:0width$x} | ",
                 "",
@@ -231,9 +355,14 @@ impl Dumper {
                 }
 
                 let slice = &line[i - offs..i - offs + size];
+                let pos = start + i - offs;
+
+                if changed(pos..pos + size) {
+                    print!("\x1b[7m");
+                }
 
                 print!(
-                    "{:0width$x} ",
+                    "{:0width$x}",
                     match size {
                         1 => u32::from(line[i - offs]),
                         2 => u32::from(u16::from_le_bytes(
@@ -246,6 +375,12 @@ impl Dumper {
                     },
                     width = size * 2
                 );
+
+                if changed(pos..pos + size) {
+                    print!("\x1b[0m");
+                }
+
+                print!(" ");
             }
 
             if self.ascii {
@@ -256,11 +391,17 @@ impl Dumper {
                         print!(" ");
                     } else {
                         let c = line[i - offs] as char;
+                        let pos = start + i - offs;
+                        let c = if c.is_ascii() && !c.is_ascii_control() {
+                            c
+                        } else {
+                            '.'
+                        };
 
-                        if c.is_ascii() && !c.is_ascii_control() {
-                            print!("{}", c);
+                        if changed(pos..pos + 1) {
+                            print!("\x1b[7m{}\x1b[0m", c);
                         } else {
-                            print!(".");
+                            print!("{}", c);
                         }
                     }
                 }
@@ -294,15 +435,15 @@ impl Dumper {
         // Print our first line.
         //
         let lim = std::cmp::min(width - offs, bytes.len());
-        print(&bytes[0..lim], addr, offs, indent);
+        print(&bytes[0..lim], 0, addr, offs, indent);
         indent = self.indent;
 
         if lim < bytes.len() {
             let lines = bytes[lim..].chunks(width);
 
-            for line in lines {
+            for (i, line) in lines.enumerate() {
                 addr += width as u32;
-                print(line, addr, 0, indent);
+                print(line, lim + i * width, addr, 0, indent);
             }
         }
     }
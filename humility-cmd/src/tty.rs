@@ -0,0 +1,34 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::Result;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use termios::Termios;
+
+///
+/// Puts stdout into raw mode, restoring its original settings when dropped.
+///
+pub struct UnrawTermiosGuard {
+    stdout: i32,
+    ios: Termios,
+}
+
+impl Drop for UnrawTermiosGuard {
+    fn drop(&mut self) {
+        termios::tcsetattr(self.stdout, termios::TCSAFLUSH, &self.ios).unwrap();
+    }
+}
+
+impl UnrawTermiosGuard {
+    pub fn make_stdout_raw() -> Result<Self> {
+        let stdout = io::stdout().as_raw_fd();
+        let orig_termios = termios::Termios::from_fd(stdout)?;
+        let mut termios = orig_termios;
+        termios::cfmakeraw(&mut termios);
+        termios::tcsetattr(stdout, termios::TCSANOW, &termios)?;
+        termios::tcflush(stdout, termios::TCIOFLUSH)?;
+        Ok(Self { stdout, ios: orig_termios })
+    }
+}
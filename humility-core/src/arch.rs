@@ -0,0 +1,167 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! The architecture of an attached target, as it pertains to its
+//! registers.  Registers are identified by the number with which the debug
+//! interface names them:  on ARM, the encoding in DCRSR (see
+//! [`ARMRegister`]); on RISC-V, the regno of an abstract command (see
+//! [`RVRegister`]).  Commands that are not architecture-specific can use
+//! the [`Arch`] of a core (see [`crate::core::Core::arch`]) to enumerate,
+//! name and decode registers.
+//!
+
+use humility_arch_arm::ARMRegister;
+use humility_arch_riscv::RVRegister;
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::collections::BTreeMap;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ArchKind {
+    Arm,
+    RiscV,
+}
+
+#[derive(Clone, Debug)]
+pub struct ArchRegister {
+    pub name: String,
+    pub id: u16,
+    pub general_purpose: bool,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ArchRegisterField {
+    pub highbit: u16,
+    pub lowbit: u16,
+    pub name: &'static str,
+}
+
+pub trait Arch {
+    fn kind(&self) -> ArchKind;
+
+    fn name(&self) -> &'static str;
+
+    /// All general purpose and special (but not floating point) registers
+    fn registers(&self) -> Vec<ArchRegister>;
+
+    /// Looks up a register by name, which is not case sensitive
+    fn lookup(&self, name: &str) -> Option<ArchRegister> {
+        self.registers().into_iter().find(|r| r.name.eq_ignore_ascii_case(name))
+    }
+
+    fn fields(&self, id: u16) -> Option<Vec<ArchRegisterField>>;
+
+    /// Describes the value of a register, if it can be described
+    fn describe(&self, _id: u16, _val: u32) -> Option<String> {
+        None
+    }
+
+    /// Regions that can be read without halting the core
+    fn unhalted_read_regions(&self) -> BTreeMap<u32, u32>;
+}
+
+pub struct ArmArch;
+pub struct RiscVArch;
+
+pub static ARM: ArmArch = ArmArch;
+pub static RISCV: RiscVArch = RiscVArch;
+
+impl Arch for ArmArch {
+    fn kind(&self) -> ArchKind {
+        ArchKind::Arm
+    }
+
+    fn name(&self) -> &'static str {
+        "ARM"
+    }
+
+    fn registers(&self) -> Vec<ArchRegister> {
+        (0..=ARMRegister::max())
+            .filter_map(ARMRegister::from_u16)
+            .filter(|r| !r.is_floating_point())
+            .map(|r| ArchRegister {
+                name: format!("{:?}", r),
+                id: r.to_u16().unwrap(),
+                general_purpose: r.is_general_purpose(),
+            })
+            .collect()
+    }
+
+    fn fields(&self, id: u16) -> Option<Vec<ArchRegisterField>> {
+        let fields = ARMRegister::from_u16(id)?.fields()?;
+
+        Some(
+            fields
+                .iter()
+                .map(|f| ArchRegisterField {
+                    highbit: f.highbit,
+                    lowbit: f.lowbit,
+                    name: f.name,
+                })
+                .collect(),
+        )
+    }
+
+    fn unhalted_read_regions(&self) -> BTreeMap<u32, u32> {
+        humility_arch_arm::unhalted_read_regions()
+    }
+}
+
+impl Arch for RiscVArch {
+    fn kind(&self) -> ArchKind {
+        ArchKind::RiscV
+    }
+
+    fn name(&self) -> &'static str {
+        "RISC-V"
+    }
+
+    fn registers(&self) -> Vec<ArchRegister> {
+        //
+        // We present the general purpose registers first, followed by the
+        // PC and the remaining CSRs.
+        //
+        let mut regs = (0..=RVRegister::max())
+            .filter_map(RVRegister::from_u16)
+            .collect::<Vec<_>>();
+
+        regs.sort_by_key(|r| (r.is_csr(), *r != RVRegister::PC, *r));
+
+        regs.iter()
+            .map(|r| ArchRegister {
+                name: format!("{:?}", r),
+                id: r.to_u16().unwrap(),
+                general_purpose: r.is_general_purpose(),
+            })
+            .collect()
+    }
+
+    fn fields(&self, id: u16) -> Option<Vec<ArchRegisterField>> {
+        let fields = RVRegister::from_u16(id)?.fields()?;
+
+        Some(
+            fields
+                .iter()
+                .map(|f| ArchRegisterField {
+                    highbit: f.highbit,
+                    lowbit: f.lowbit,
+                    name: f.name,
+                })
+                .collect(),
+        )
+    }
+
+    fn describe(&self, id: u16, val: u32) -> Option<String> {
+        match RVRegister::from_u16(id)? {
+            RVRegister::MCAUSE => {
+                Some(humility_arch_riscv::mcause_describe(val))
+            }
+            _ => None,
+        }
+    }
+
+    fn unhalted_read_regions(&self) -> BTreeMap<u32, u32> {
+        humility_arch_riscv::unhalted_read_regions()
+    }
+}
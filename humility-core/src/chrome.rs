@@ -0,0 +1,165 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Export of captured events in the Chrome trace event format, as consumed
+//! by `chrome://tracing` and by Perfetto (https://ui.perfetto.dev).  Events
+//! are placed on named tracks (e.g., one per task), which appear as threads
+//! within a single process named for the capture; times are expressed in
+//! seconds since the start of the capture.  Events are accumulated in
+//! memory and written out in their entirety by [`ChromeTrace::write`].
+//!
+
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+
+//
+// All of our tracks are within a single process.
+//
+const CHROME_TRACE_PID: u32 = 1;
+
+pub struct ChromeTrace {
+    tracks: BTreeMap<String, u32>,
+    events: Vec<Value>,
+}
+
+fn chrome_args(args: &[(&str, String)]) -> Value {
+    Value::Object(
+        args.iter()
+            .map(|(name, val)| (name.to_string(), Value::String(val.clone())))
+            .collect::<Map<_, _>>(),
+    )
+}
+
+///
+/// Chrome trace event times are in microseconds.
+///
+fn chrome_ts(time: f64) -> f64 {
+    time * 1_000_000.0
+}
+
+impl ChromeTrace {
+    pub fn new(name: &str) -> Self {
+        Self {
+            tracks: BTreeMap::new(),
+            events: vec![json!({
+                "name": "process_name",
+                "ph": "M",
+                "pid": CHROME_TRACE_PID,
+                "args": { "name": name },
+            })],
+        }
+    }
+
+    ///
+    /// Returns the thread identifier for the named track, creating it if
+    /// needed.  Tracks are displayed in the order in which they are created,
+    /// so a consumer that wants a particular order (e.g., tasks in task
+    /// order) should create its tracks before adding events to them.
+    ///
+    pub fn track(&mut self, name: &str) -> u32 {
+        if let Some(tid) = self.tracks.get(name) {
+            return *tid;
+        }
+
+        let tid = self.tracks.len() as u32 + 1;
+        self.tracks.insert(name.to_string(), tid);
+
+        self.events.push(json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": CHROME_TRACE_PID,
+            "tid": tid,
+            "args": { "name": name },
+        }));
+
+        self.events.push(json!({
+            "name": "thread_sort_index",
+            "ph": "M",
+            "pid": CHROME_TRACE_PID,
+            "tid": tid,
+            "args": { "sort_index": tid },
+        }));
+
+        tid
+    }
+
+    /// Adds an event that occurs at a single point in time
+    pub fn instant(
+        &mut self,
+        track: &str,
+        name: &str,
+        time: f64,
+        args: &[(&str, String)],
+    ) {
+        let tid = self.track(track);
+
+        self.events.push(json!({
+            "name": name,
+            "ph": "i",
+            "s": "t",
+            "pid": CHROME_TRACE_PID,
+            "tid": tid,
+            "ts": chrome_ts(time),
+            "args": chrome_args(args),
+        }));
+    }
+
+    /// Adds an event that spans an interval of time
+    pub fn slice(
+        &mut self,
+        track: &str,
+        name: &str,
+        start: f64,
+        end: f64,
+        args: &[(&str, String)],
+    ) {
+        let tid = self.track(track);
+
+        self.events.push(json!({
+            "name": name,
+            "ph": "X",
+            "pid": CHROME_TRACE_PID,
+            "tid": tid,
+            "ts": chrome_ts(start),
+            "dur": chrome_ts((end - start).max(0.0)),
+            "args": chrome_args(args),
+        }));
+    }
+
+    ///
+    /// Adds a sample of a counter, which is displayed as its own track; a
+    /// counter may have multiple series, which are stacked.
+    ///
+    pub fn counter(&mut self, name: &str, time: f64, series: &[(&str, f64)]) {
+        self.events.push(json!({
+            "name": name,
+            "ph": "C",
+            "pid": CHROME_TRACE_PID,
+            "ts": chrome_ts(time),
+            "args": series
+                .iter()
+                .map(|(s, val)| (s.to_string(), json!(val)))
+                .collect::<Map<_, _>>(),
+        }));
+    }
+
+    pub fn write(&self, filename: &str) -> Result<()> {
+        let file = File::create(filename)
+            .with_context(|| format!("failed to create {filename}"))?;
+
+        serde_json::to_writer(
+            BufWriter::new(file),
+            &json!({
+                "traceEvents": self.events,
+                "displayTimeUnit": "ms",
+            }),
+        )?;
+
+        Ok(())
+    }
+}
@@ -7,6 +7,7 @@ use probe_rs::{flashing, Probe};
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
 
+use crate::arch::{Arch, ArchKind};
 use crate::hubris::*;
 use humility_arch_arm::ARMRegister;
 use std::cell::RefCell;
@@ -27,6 +28,28 @@ use thiserror::Error;
 
 use goblin::elf::Elf;
 
+//
+// The Debug Exception and Monitor Control Register, and the mask of its
+// vector catch bits.
+//
+const DEMCR: u32 = 0xe000_edfc;
+const DEMCR_VC_MASK: u32 = 0x7f1;
+
+///
+/// The vector catch bits in DEMCR, along with the names by which OpenOCD
+/// knows them.
+///
+pub const VECTOR_CATCH: &[(u32, &str)] = &[
+    (1 << 0, "reset"),
+    (1 << 4, "mm_err"),
+    (1 << 5, "nocp_err"),
+    (1 << 6, "chk_err"),
+    (1 << 7, "state_err"),
+    (1 << 8, "bus_err"),
+    (1 << 9, "int_err"),
+    (1 << 10, "hard_err"),
+];
+
 pub trait Core {
     fn info(&self) -> (String, Option<String>);
 
@@ -34,9 +57,41 @@ pub trait Core {
         None
     }
 
+    ///
+    /// Returns the architecture of the target.  Cores that can only be
+    /// attached to ARM targets need not implement this.
+    ///
+    fn arch(&self) -> &'static dyn Arch {
+        &crate::arch::ARM
+    }
+
     fn read_8(&mut self, addr: u32, data: &mut [u8]) -> Result<()>;
     fn read_reg(&mut self, reg: ARMRegister) -> Result<u32>;
     fn write_reg(&mut self, reg: ARMRegister, value: u32) -> Result<()>;
+
+    ///
+    /// Reads a register identified by its number in the target's
+    /// architecture (see [`crate::arch`]).  By default, the register must be
+    /// an ARM register.
+    ///
+    fn read_reg_id(&mut self, id: u16) -> Result<u32> {
+        use num_traits::FromPrimitive;
+
+        match ARMRegister::from_u16(id) {
+            Some(reg) => self.read_reg(reg),
+            None => bail!("unknown register 0x{:x}", id),
+        }
+    }
+
+    fn write_reg_id(&mut self, id: u16, value: u32) -> Result<()> {
+        use num_traits::FromPrimitive;
+
+        match ARMRegister::from_u16(id) {
+            Some(reg) => self.write_reg(reg, value),
+            None => bail!("unknown register 0x{:x}", id),
+        }
+    }
+
     fn init_swv(&mut self) -> Result<()>;
     fn read_swv(&mut self) -> Result<Vec<u8>>;
     fn write_word_32(&mut self, addr: u32, data: u32) -> Result<()>;
@@ -57,6 +112,21 @@ pub trait Core {
         false
     }
 
+    /// Returns true if operations that modify the target are to be reported
+    /// rather than performed (see [`DryRunCore`])
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+
+    ///
+    /// On a dry-run core, returns the wrapped core, through which operations
+    /// known to leave the target unmodified may be performed -- namely, the
+    /// writes to the HIF mailbox required to run a program that only reads.
+    ///
+    fn dry_run_inner(&mut self) -> Option<&mut dyn Core> {
+        None
+    }
+
     fn set_timeout(&mut self, _timeout: Duration) -> Result<()> {
         Ok(())
     }
@@ -102,6 +172,58 @@ pub trait Core {
     /// Wait `duration` seconds for the targe to halt.
     fn wait_for_halt(&mut self, dur: std::time::Duration) -> Result<()>;
 
+    ///
+    /// Sets the exceptions on which the core will halt (vector catch), as a
+    /// mask of the vector catch bits in DEMCR.  By default, DEMCR is written
+    /// directly; a debugger that manages vector catch itself (as OpenOCD
+    /// does) must instead be told of the new configuration.
+    ///
+    fn vector_catch(&mut self, mask: u32) -> Result<()> {
+        if self.arch().kind() != ArchKind::Arm {
+            bail!("vector catch is not supported on {}", self.arch().name());
+        }
+
+        let demcr = self.read_word_32(DEMCR)?;
+        let demcr = (demcr & !DEMCR_VC_MASK) | (mask & DEMCR_VC_MASK);
+        self.write_word_32(DEMCR, demcr)
+    }
+
+    ///
+    /// Enables or disables the caching of memory reads while the target is
+    /// halted.  By default, reads are not cached; a core for which small
+    /// reads are expensive may cache (and coalesce) them, but must assure
+    /// that the cache is invalidated whenever target memory may change.
+    ///
+    fn set_read_cache(&mut self, _enabled: bool) {}
+
+    /// Returns the number of cores on the target
+    fn ncores(&self) -> usize {
+        1
+    }
+
+    /// Returns the index of the core that is being operated upon
+    fn core_index(&self) -> usize {
+        0
+    }
+
+    ///
+    /// Selects the core to operate upon, for targets with multiple cores.
+    /// If `halt_all` is set, halting the selected core will halt the other
+    /// cores as well, and they will be resumed when the selected core is
+    /// run.  By default, a target has only a single core.
+    ///
+    fn select_core(&mut self, index: usize, halt_all: bool) -> Result<()> {
+        if index != 0 {
+            bail!("core {index} does not exist; target has only one core");
+        }
+
+        if halt_all {
+            crate::warn!("target has only one core; ignoring halt-all");
+        }
+
+        Ok(())
+    }
+
     /// Send over network, if applicable
     fn send(&self, _buf: &[u8], _agent: NetAgent) -> Result<usize> {
         bail!("cannot send over network");
@@ -221,8 +343,15 @@ pub struct ProbeCore {
     pub serial_number: Option<String>,
     unhalted_reads: bool,
     halted: u32,
+    halted_at: Option<Instant>,
     unhalted_read: BTreeMap<u32, u32>,
     can_flash: bool,
+    cache_enabled: bool,
+    cache: BTreeMap<u32, Option<Vec<u8>>>,
+    arch: &'static dyn Arch,
+    core_index: usize,
+    halt_all: bool,
+    halted_others: Vec<usize>,
 }
 
 impl ProbeCore {
@@ -235,6 +364,11 @@ impl ProbeCore {
         unhalted_reads: bool,
         can_flash: bool,
     ) -> Self {
+        let arch: &'static dyn Arch = match session.architecture() {
+            probe_rs::Architecture::Riscv => &crate::arch::RISCV,
+            _ => &crate::arch::ARM,
+        };
+
         Self {
             session,
             identifier,
@@ -243,31 +377,141 @@ impl ProbeCore {
             serial_number,
             unhalted_reads,
             halted: 0,
-            unhalted_read: humility_arch_arm::unhalted_read_regions(),
+            halted_at: None,
+            unhalted_read: arch.unhalted_read_regions(),
             can_flash,
+            cache_enabled: false,
+            cache: BTreeMap::new(),
+            arch,
+            core_index: 0,
+            halt_all: false,
+            halted_others: vec![],
+        }
+    }
+
+    ///
+    /// Halts any cores other than the one being operated upon that are
+    /// running, recording them so that they can be resumed by
+    /// [`Self::run_others`].
+    ///
+    fn halt_others(&mut self) -> Result<()> {
+        for (index, _) in self.session.list_cores() {
+            if index == self.core_index {
+                continue;
+            }
+
+            let mut core = self.session.core(index)?;
+
+            if !core.core_halted()? {
+                core.halt(std::time::Duration::from_millis(1000))?;
+                self.halted_others.push(index);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_others(&mut self) -> Result<()> {
+        for index in std::mem::take(&mut self.halted_others) {
+            let mut core = self.session.core(index)?;
+            core.run()?;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Returns true if a read of the specified range can be satisfied from
+    /// our cache.  We only cache small reads, only while the core is halted,
+    /// and only of the Code and SRAM regions of the architectural memory map
+    /// (which contain Normal memory, and therefore have no side-effects on
+    /// a read) -- allowing us to read a block rather than only the bytes
+    /// requested.  (Other architectures have no such memory map, so we
+    /// don't cache their reads.)
+    ///
+    fn cacheable(&self, addr: u32, len: usize) -> bool {
+        self.cache_enabled
+            && self.arch.kind() == ArchKind::Arm
+            && self.halted > 0
+            && len < CACHE_BLOCK_SIZE
+            && addr as u64 + len as u64 <= CACHE_LIMIT
+    }
+
+    ///
+    /// Satisfies a read from our cache, reading (and caching) any blocks
+    /// that it spans.  Returns false if a block could not be read (e.g.,
+    /// because it spans unimplemented memory), in which case the read should
+    /// be performed directly.
+    ///
+    fn cached_read(&mut self, addr: u32, data: &mut [u8]) -> Result<bool> {
+        let mut offs = 0;
+
+        while offs < data.len() {
+            let a = addr + offs as u32;
+            let base = a & !(CACHE_BLOCK_SIZE as u32 - 1);
+
+            if !self.cache.contains_key(&base) {
+                let mut words = [0u32; CACHE_BLOCK_SIZE / 4];
+                let mut core = self.session.core(self.core_index)?;
+
+                let block = match core.read_32(base, &mut words) {
+                    Ok(_) => Some(
+                        words.iter().flat_map(|w| w.to_le_bytes()).collect(),
+                    ),
+                    Err(e) => {
+                        log::trace!("block read at {base:#x} failed: {e}");
+                        None
+                    }
+                };
+
+                self.cache.insert(base, block);
+            }
+
+            let block = match &self.cache[&base] {
+                Some(block) => block,
+                None => return Ok(false),
+            };
+
+            let start = (a - base) as usize;
+            let n = std::cmp::min(CACHE_BLOCK_SIZE - start, data.len() - offs);
+            data[offs..offs + n].copy_from_slice(&block[start..start + n]);
+            offs += n;
         }
+
+        Ok(true)
+    }
+
+    ///
+    /// Invalidates any cached blocks that overlap the specified range.
+    ///
+    fn invalidate(&mut self, addr: u32, len: usize) {
+        let first = addr & !(CACHE_BLOCK_SIZE as u32 - 1);
+        let last = addr as u64 + len as u64;
+
+        self.cache.retain(|&base, _| base < first || base as u64 >= last);
     }
 
     fn halt_and_read(
         &mut self,
         mut func: impl FnMut(&mut probe_rs::Core) -> Result<()>,
     ) -> Result<()> {
-        let mut core = self.session.core(0)?;
+        let mut core = self.session.core(self.core_index)?;
 
         if self.unhalted_reads {
             func(&mut core)
         } else {
             let halted = if self.halted == 0 && !core.core_halted()? {
                 core.halt(std::time::Duration::from_millis(1000))?;
-                true
+                Some(Instant::now())
             } else {
-                false
+                None
             };
 
             let rval = func(&mut core);
 
-            if halted {
+            if let Some(halted) = halted {
                 core.run()?;
+                crate::stats::halted(halted.elapsed());
             }
 
             rval
@@ -277,6 +521,18 @@ impl ProbeCore {
 
 pub const CORE_MAX_READSIZE: usize = 65536; // 64K ought to be enough for anyone
 
+///
+/// The size of a block read to satisfy a cached read; this must be a power
+/// of two.
+///
+pub const CACHE_BLOCK_SIZE: usize = 256;
+
+///
+/// The end of the memory that we will cache:  the Code and SRAM regions of
+/// the ARMv7-M/ARMv8-M memory map.
+///
+const CACHE_LIMIT: u64 = 0x4000_0000;
+
 #[rustfmt::skip::macros(anyhow, bail)]
 impl Core for ProbeCore {
     fn info(&self) -> (String, Option<String>) {
@@ -296,9 +552,17 @@ impl Core for ProbeCore {
         log::trace!("reading word at {:x}", addr);
         let mut rval = 0;
 
+        if self.cacheable(addr, 4) {
+            let mut buf = [0; 4];
+
+            if self.cached_read(addr, &mut buf)? {
+                return Ok(u32::from_le_bytes(buf));
+            }
+        }
+
         if let Some(range) = self.unhalted_read.range(..=addr).next_back() {
             if addr + 4 < range.0 + range.1 {
-                let mut core = self.session.core(0)?;
+                let mut core = self.session.core(self.core_index)?;
                 return core.read_word_32(addr).with_context(|| {
                     format!(
                         "failed to perform unhalted word read at address \
@@ -327,9 +591,13 @@ impl Core for ProbeCore {
                 data.len(), addr, CORE_MAX_READSIZE);
         }
 
+        if self.cacheable(addr, data.len()) && self.cached_read(addr, data)? {
+            return Ok(());
+        }
+
         if let Some(range) = self.unhalted_read.range(..=addr).next_back() {
             if addr + (data.len() as u32) < range.0 + range.1 {
-                let mut core = self.session.core(0)?;
+                let mut core = self.session.core(self.core_index)?;
                 return core.read_8(addr, data).with_context(|| {
                     format!(
                         "failed to perform unhalted read at address \
@@ -351,23 +619,76 @@ impl Core for ProbeCore {
         })
     }
 
+    fn arch(&self) -> &'static dyn Arch {
+        self.arch
+    }
+
+    fn ncores(&self) -> usize {
+        self.session.list_cores().len()
+    }
+
+    fn core_index(&self) -> usize {
+        self.core_index
+    }
+
+    fn select_core(&mut self, index: usize, halt_all: bool) -> Result<()> {
+        let cores = self.session.list_cores();
+
+        let Some((_, coretype)) = cores.iter().find(|(i, _)| *i == index)
+        else {
+            bail!(
+                "core {index} does not exist; target has {} core(s)",
+                cores.len()
+            );
+        };
+
+        if self.halted > 0 {
+            bail!("cannot select core {index} while core is halted");
+        }
+
+        if cores.len() > 1 {
+            crate::msg!("operating on core {index} ({:?})", coretype);
+        }
+
+        self.cache.clear();
+        self.core_index = index;
+        self.halt_all = halt_all;
+
+        Ok(())
+    }
+
     fn read_reg(&mut self, reg: ARMRegister) -> Result<u32> {
-        let mut core = self.session.core(0)?;
         use num_traits::ToPrimitive;
 
-        Ok(core.read_core_reg(Into::<probe_rs::CoreRegisterAddress>::into(
-            ARMRegister::to_u16(&reg).unwrap(),
-        ))?)
+        if self.arch.kind() != ArchKind::Arm {
+            bail!("cannot read ARM register {reg} on {}", self.arch.name());
+        }
+
+        self.read_reg_id(ARMRegister::to_u16(&reg).unwrap())
     }
 
     fn write_reg(&mut self, reg: ARMRegister, value: u32) -> Result<()> {
-        let mut core = self.session.core(0)?;
         use num_traits::ToPrimitive;
 
+        if self.arch.kind() != ArchKind::Arm {
+            bail!("cannot write ARM register {reg} on {}", self.arch.name());
+        }
+
+        self.write_reg_id(ARMRegister::to_u16(&reg).unwrap(), value)
+    }
+
+    fn read_reg_id(&mut self, id: u16) -> Result<u32> {
+        let mut core = self.session.core(self.core_index)?;
+
+        Ok(core
+            .read_core_reg(Into::<probe_rs::CoreRegisterAddress>::into(id))?)
+    }
+
+    fn write_reg_id(&mut self, id: u16, value: u32) -> Result<()> {
+        let mut core = self.session.core(self.core_index)?;
+
         core.write_core_reg(
-            Into::<probe_rs::CoreRegisterAddress>::into(
-                ARMRegister::to_u16(&reg).unwrap(),
-            ),
+            Into::<probe_rs::CoreRegisterAddress>::into(id),
             value,
         )?;
 
@@ -375,21 +696,28 @@ impl Core for ProbeCore {
     }
 
     fn write_word_32(&mut self, addr: u32, data: u32) -> Result<()> {
-        let mut core = self.session.core(0)?;
+        self.invalidate(addr, 4);
+        let mut core = self.session.core(self.core_index)?;
         core.write_word_32(addr, data)?;
         Ok(())
     }
 
     fn write_8(&mut self, addr: u32, data: &[u8]) -> Result<()> {
-        let mut core = self.session.core(0)?;
+        self.invalidate(addr, data.len());
+        let mut core = self.session.core(self.core_index)?;
         core.write_8(addr, data)?;
         Ok(())
     }
 
     fn halt(&mut self) -> Result<()> {
         if self.halted == 0 {
-            let mut core = self.session.core(0)?;
+            let mut core = self.session.core(self.core_index)?;
             core.halt(std::time::Duration::from_millis(1000))?;
+            self.halted_at = Some(Instant::now());
+
+            if self.halt_all {
+                self.halt_others()?;
+            }
         }
 
         self.halted += 1;
@@ -400,15 +728,28 @@ impl Core for ProbeCore {
         self.halted -= 1;
 
         if self.halted == 0 {
-            let mut core = self.session.core(0)?;
+            self.cache.clear();
+
+            let mut core = self.session.core(self.core_index)?;
             core.run()?;
+
+            self.run_others()?;
+
+            //
+            // If the core halted on its own (e.g., at a breakpoint), we did
+            // not impose the halt, and we don't account for it.
+            //
+            if let Some(halted_at) = self.halted_at.take() {
+                crate::stats::halted(halted_at.elapsed());
+            }
         }
 
         Ok(())
     }
 
     fn step(&mut self) -> Result<()> {
-        let mut core = self.session.core(0)?;
+        self.cache.clear();
+        let mut core = self.session.core(self.core_index)?;
         core.step()?;
         Ok(())
     }
@@ -416,6 +757,10 @@ impl Core for ProbeCore {
     fn init_swv(&mut self) -> Result<()> {
         use probe_rs::architecture::arm::swo::SwoConfig;
 
+        if self.arch.kind() != ArchKind::Arm {
+            bail!("SWV is not supported on {}", self.arch.name());
+        }
+
         let config = SwoConfig::new(0).set_baud(2_000_000);
         self.session.setup_swv(0, &config)?;
 
@@ -448,16 +793,18 @@ impl Core for ProbeCore {
             written: usize,
         }
 
-        use indicatif::{ProgressBar, ProgressStyle};
+        use indicatif::ProgressStyle;
 
         if !self.can_flash {
             bail!("cannot flash without explicitly attaching to flash");
         }
 
+        self.cache.clear();
+
         let progress =
             Rc::new(RefCell::new(LoadProgress { ..Default::default() }));
 
-        let bar = ProgressBar::new(0);
+        let bar = crate::progress_bar(0);
 
         let progress = flashing::FlashProgress::new(move |event| match event {
             flashing::ProgressEvent::Initialized { flash_layout } => {
@@ -522,13 +869,15 @@ impl Core for ProbeCore {
     }
 
     fn reset(&mut self) -> Result<()> {
-        let mut core = self.session.core(0)?;
+        self.cache.clear();
+        let mut core = self.session.core(self.core_index)?;
         core.reset()?;
         Ok(())
     }
 
     fn reset_and_halt(&mut self, dur: std::time::Duration) -> Result<()> {
-        let mut core = self.session.core(0)?;
+        self.cache.clear();
+        let mut core = self.session.core(self.core_index)?;
         core.reset_and_halt(dur)?;
         Ok(())
     }
@@ -551,13 +900,18 @@ impl Core for ProbeCore {
 
     fn wait_for_halt(&mut self, dur: std::time::Duration) -> Result<()> {
         if self.halted == 0 {
-            let mut core = self.session.core(0)?;
+            let mut core = self.session.core(self.core_index)?;
             core.wait_for_core_halted(dur)?;
         }
 
         self.halted += 1;
         Ok(())
     }
+
+    fn set_read_cache(&mut self, enabled: bool) {
+        self.cache_enabled = enabled;
+        self.cache.clear();
+    }
 }
 
 const OPENOCD_COMMAND_DELIMITER: u8 = 0x1a;
@@ -702,12 +1056,12 @@ impl Core for OpenOCDCore {
         Ok(())
     }
 
-    fn write_reg(&mut self, _reg: ARMRegister, _val: u32) -> Result<()> {
-        // This does not work right now, TODO?
-        //
-        Err(anyhow!(
-            "Writing registers is not currently supported with OpenOCD"
-        ))
+    fn write_reg(&mut self, reg: ARMRegister, val: u32) -> Result<()> {
+        use num_traits::ToPrimitive;
+
+        let reg = ARMRegister::to_u16(&reg).unwrap();
+        self.sendcmd(&format!("reg {} 0x{:x}", reg, val))?;
+        Ok(())
     }
 
     fn read_reg(&mut self, reg: ARMRegister) -> Result<u32> {
@@ -842,23 +1196,60 @@ impl Core for OpenOCDCore {
     }
 
     fn step(&mut self) -> Result<()> {
-        todo!();
+        self.sendcmd("step")?;
+        Ok(())
     }
 
-    fn load(&mut self, _path: &Path) -> Result<()> {
-        bail!("Flash loading is not supported with OpenOCD");
+    fn load(&mut self, path: &Path) -> Result<()> {
+        //
+        // We only ever connect to OpenOCD on the local machine, so it can
+        // read the image directly.  (The braces quote the path for TCL.)
+        //
+        let path = path.display();
+
+        self.sendcmd("reset halt")?;
+        self.sendcmd(&format!("flash write_image erase {{{path}}} 0 ihex"))?;
+        self.sendcmd(&format!("verify_image {{{path}}} 0 ihex"))?;
+
+        Ok(())
     }
 
     fn reset(&mut self) -> Result<()> {
-        bail!("Reset is not supported with OpenOCD");
+        self.sendcmd("reset run")?;
+        Ok(())
     }
 
-    fn reset_and_halt(&mut self, _dur: std::time::Duration) -> Result<()> {
-        bail!("Reset is not supported with OpenOCD");
+    fn reset_and_halt(&mut self, dur: std::time::Duration) -> Result<()> {
+        self.sendcmd("reset halt")?;
+        self.wait_for_halt(dur)
     }
 
-    fn wait_for_halt(&mut self, _dur: std::time::Duration) -> Result<()> {
-        bail!("Wait for halt is not supported with OpenOCD");
+    fn wait_for_halt(&mut self, dur: std::time::Duration) -> Result<()> {
+        self.sendcmd(&format!("wait_halt {}", dur.as_millis()))?;
+        Ok(())
+    }
+
+    fn vector_catch(&mut self, mask: u32) -> Result<()> {
+        //
+        // OpenOCD sets DEMCR itself whenever it resumes the target, so we
+        // must use its vector catch configuration rather than writing DEMCR.
+        //
+        let vectors = VECTOR_CATCH
+            .iter()
+            .filter(|(bit, _)| mask & bit != 0)
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>();
+
+        self.sendcmd(&format!(
+            "cortex_m vector_catch {}",
+            if vectors.is_empty() {
+                "none".to_string()
+            } else {
+                vectors.join(" ")
+            }
+        ))?;
+
+        Ok(())
     }
 }
 
@@ -1204,58 +1595,158 @@ impl Core for GDBCore {
     }
 }
 
+enum DumpContents {
+    /// An uncompressed dump, from which regions are read as they are needed
+    File(fs::File),
+    /// A compressed dump, which must be decompressed in its entirety
+    Memory(Vec<u8>),
+}
+
 pub struct DumpCore {
-    contents: Vec<u8>,
-    regions: BTreeMap<u32, (u32, usize)>,
+    contents: DumpContents,
+    /// Length of the (uncompressed) dump
+    len: usize,
+    /// Base address to size and offset (or None if elided as zero-filled)
+    regions: BTreeMap<u32, (u32, Option<usize>)>,
+    /// Regions that have been read from the dump, by base address
+    fetched: HashMap<u32, Vec<u8>>,
     registers: HashMap<ARMRegister, u32>,
 }
 
 impl DumpCore {
     fn new(dump: &str, hubris: &HubrisArchive) -> Result<DumpCore> {
-        let mut file = fs::File::open(dump)?;
-        let mut regions = BTreeMap::new();
+        use goblin::elf::program_header::{ProgramHeader, PT_LOAD};
+        use scroll::Pread;
+        use std::io::{Seek, SeekFrom};
 
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)?;
+        let mut file = fs::File::open(dump)
+            .with_context(|| format!("failed to open dump {dump}"))?;
 
-        let elf = Elf::parse(&contents).map_err(|e| {
-            anyhow!("failed to parse {} as an ELF file: {}", dump, e)
-        })?;
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)
+            .with_context(|| format!("failed to read dump {dump}"))?;
+
+        let (contents, len, phdrs) = if magic == crate::hubris::ZSTD_MAGIC {
+            let contents = crate::hubris::read_dumpfile(dump)?;
+
+            let elf = Elf::parse(&contents).map_err(|e| {
+                anyhow!("failed to parse {} as an ELF file: {}", dump, e)
+            })?;
+
+            let phdrs = elf.program_headers;
+            let len = contents.len();
+            (DumpContents::Memory(contents), len, phdrs)
+        } else {
+            //
+            // For an uncompressed dump, we read only the ELF header and the
+            // program headers here; the contents of each region will be read
+            // when (and if) it is needed.
+            //
+            let len = file.metadata()?.len() as usize;
+            let mut ehdr = vec![];
+            file.seek(SeekFrom::Start(0))?;
+            (&mut file).take(64).read_to_end(&mut ehdr)?;
+
+            let header: goblin::elf::Header = ehdr.pread(0).map_err(|e| {
+                anyhow!("failed to parse {} as an ELF file: {}", dump, e)
+            })?;
+
+            let ctx = goblin::container::Ctx::new(
+                header.container()?,
+                header.endianness()?,
+            );
+
+            let mut buf = vec![
+                0u8;
+                header.e_phnum as usize
+                    * header.e_phentsize as usize
+            ];
+            file.seek(SeekFrom::Start(header.e_phoff))?;
+            file.read_exact(&mut buf).with_context(|| {
+                format!("failed to read program headers of {dump}")
+            })?;
+
+            let phdrs =
+                ProgramHeader::parse(&buf, 0, header.e_phnum as usize, ctx)?;
+
+            (DumpContents::File(file), len, phdrs)
+        };
+
+        let mut regions = BTreeMap::new();
 
-        for phdr in elf.program_headers.iter() {
-            if phdr.p_type != goblin::elf::program_header::PT_LOAD {
+        for phdr in phdrs.iter() {
+            if phdr.p_type != PT_LOAD {
                 continue;
             }
 
             regions.insert(
                 phdr.p_vaddr as u32,
-                (phdr.p_memsz as u32, phdr.p_offset as usize),
+                (phdr.p_memsz as u32, Some(phdr.p_offset as usize)),
             );
         }
 
-        Ok(Self { contents, regions, registers: hubris.dump_registers() })
+        //
+        // If this is a sparse dump, add the ranges that were elided because
+        // they were zero-filled.
+        //
+        for (base, size) in hubris.dump_elided() {
+            regions.insert(base, (size, None));
+        }
+
+        Ok(Self {
+            contents,
+            len,
+            regions,
+            fetched: HashMap::new(),
+            registers: hubris.dump_registers(),
+        })
     }
 
-    fn check_offset(&self, addr: u32, rsize: usize, offs: usize) -> Result<()> {
-        if rsize + offs <= self.contents.len() {
-            return Ok(());
+    ///
+    /// Returns the contents of the region at the specified base address,
+    /// reading them from the dump if they have not already been read.
+    ///
+    fn fetch(&mut self, base: u32, size: u32, offset: usize) -> Result<&[u8]> {
+        use std::collections::hash_map::Entry;
+        use std::io::{Seek, SeekFrom};
+
+        let size = size as usize;
+
+        if offset + size > self.len {
+            //
+            // This really shouldn't happen, as it means that we have a
+            // defined region in a program header for memory that wasn't in
+            // fact dumped.  Still, this might occur if the dump is truncated
+            // or otherwise corrupt; offer a message pointing in that
+            // direction.
+            //
+            bail!(
+                "region at 0x{:x} is valid, but offset in dump (0x{:x}) + \
+                size (0x{:x}) exceeds max (0x{:x}); is the dump truncated \
+                or otherwise corrupt?",
+                base,
+                offset,
+                size,
+                self.len
+            );
         }
 
-        //
-        // This really shouldn't happen, as it means that we have a defined
-        // region in a program header for memory that wasn't in fact dumped.
-        // Still, this might occur if the dump is truncated or otherwise
-        // corrupt; offer a message pointing in that direction.
-        //
-        bail!(
-            "0x{:x} is valid, but offset in dump \
-            (0x{:x}) + size (0x{:x}) exceeds max (0x{:x}); \
-            is the dump truncated or otherwise corrupt?",
-            addr,
-            offs,
-            rsize,
-            self.contents.len()
-        );
+        match &mut self.contents {
+            DumpContents::Memory(contents) => {
+                Ok(&contents[offset..offset + size])
+            }
+            DumpContents::File(file) => match self.fetched.entry(base) {
+                Entry::Occupied(e) => Ok(e.into_mut()),
+                Entry::Vacant(e) => {
+                    let mut buf = vec![0u8; size];
+                    file.seek(SeekFrom::Start(offset as u64))?;
+                    file.read_exact(&mut buf).with_context(|| {
+                        format!("failed to read region at 0x{:x}", base)
+                    })?;
+                    Ok(e.insert(buf))
+                }
+            },
+        }
     }
 }
 
@@ -1294,16 +1785,53 @@ impl Core for DumpCore {
                     }
                 }
 
-                let offs = offset + (addr - base) as usize;
-                self.check_offset(addr, rsize, offs)?;
+                let rsize = rsize.min((size - (addr - base)) as usize);
+
+                match offset {
+                    Some(offset) => {
+                        let offs = (addr - base) as usize;
+                        let region = self.fetch(base, size, offset)?;
+                        data[..rsize]
+                            .copy_from_slice(&region[offs..offs + rsize]);
+                    }
+                    None => data[..rsize].fill(0),
+                }
 
-                data[..rsize]
-                    .copy_from_slice(&self.contents[offs..rsize + offs]);
                 return Ok(());
             }
         }
 
-        bail!("read of {} bytes from invalid address: 0x{:x}", rsize, addr);
+        //
+        // This address isn't in the dump; try to be helpful by indicating the
+        // nearest region that is.
+        //
+        let nearest = self
+            .regions
+            .iter()
+            .filter(|(_, &(size, _))| size > 0)
+            .map(|(&base, &(size, _))| {
+                let dist = if addr < base {
+                    base - addr
+                } else {
+                    addr - (base + size)
+                };
+
+                (dist, base, size)
+            })
+            .min();
+
+        match nearest {
+            Some((dist, base, size)) => bail!(
+                "read of {} bytes from 0x{:x}: address not in dump \
+                (nearest region in dump is 0x{:x}-0x{:x}, {} bytes {})",
+                rsize, addr, base, base + size - 1, dist,
+                if addr < base { "before its start" } else { "past its end" }
+            ),
+            None => bail!(
+                "read of {} bytes from 0x{:x}: address not in dump",
+                rsize, addr
+            ),
+        }
     }
 
     fn read_reg(&mut self, reg: ARMRegister) -> Result<u32> {
@@ -1372,7 +1900,7 @@ pub struct ArchiveCore {
 }
 
 impl ArchiveCore {
-    fn new(hubris: &HubrisArchive) -> Result<ArchiveCore> {
+    pub fn new(hubris: &HubrisArchive) -> Result<ArchiveCore> {
         Ok(Self { flash: HubrisFlashMap::new(hubris)? })
     }
 
@@ -1453,31 +1981,660 @@ impl Core for ArchiveCore {
     }
 }
 
-/// Something that you can talk to on the network
 ///
-/// `control-plane-agent` is deliberately skipped, because it's best talked to
-/// via `faux-mgs`.
-pub enum NetAgent {
-    UdpRpc,
-    DumpAgent,
+/// A core that wraps another core, verifying each memory read by performing
+/// it twice and comparing the results.  On a mismatch, the read is retried
+/// (up to [`VERIFY_RETRIES`] times) until two consecutive reads agree.  This
+/// is intended for marginal links where data may be silently corrupted; note
+/// that memory that is being actively modified by a running target will also
+/// fail to verify, and will be reported as such.
+///
+pub struct VerifyingCore {
+    core: Box<dyn Core>,
+    mismatches: u32,
 }
 
-#[derive(Error, Debug)]
-pub enum ProbeError {
-    #[error("no debug probe found; is it plugged in?")]
-    NoProbeFound,
-}
+pub const VERIFY_RETRIES: usize = 3;
 
-fn parse_probe(probe: &str) -> (&str, Option<usize>) {
-    if probe.contains('-') {
-        let str = probe.to_owned();
-        let pieces: Vec<&str> = str.split('-').collect();
+impl VerifyingCore {
+    pub fn new(core: Box<dyn Core>) -> VerifyingCore {
+        Self { core, mismatches: 0 }
+    }
 
-        if pieces[0] == "usb" && pieces.len() == 2 {
-            if let Ok(val) = pieces[1].parse::<usize>() {
-                ("usb", Some(val))
-            } else {
-                (probe, None)
+    ///
+    /// Reads a word with the specified function (which forwards to the
+    /// wrapped core, so that it can perform a word-sized access), retrying
+    /// until two consecutive reads agree.
+    ///
+    fn read_word<T: PartialEq>(
+        &mut self,
+        addr: u32,
+        read: fn(&mut dyn Core, u32) -> Result<T>,
+    ) -> Result<T> {
+        let mut value = read(&mut *self.core, addr)?;
+
+        for attempt in 0..=VERIFY_RETRIES {
+            let check = read(&mut *self.core, addr)?;
+
+            if check == value {
+                return Ok(value);
+            }
+
+            crate::warn!(
+                "read of {}-byte word at {addr:#x} failed verification{}",
+                std::mem::size_of::<T>(),
+                if attempt < VERIFY_RETRIES { "; retrying" } else { "" }
+            );
+
+            self.mismatches += 1;
+            value = check;
+        }
+
+        bail!(
+            "read of {}-byte word at {addr:#x} failed to verify after {} \
+            attempts",
+            std::mem::size_of::<T>(),
+            VERIFY_RETRIES + 1
+        );
+    }
+}
+
+impl Drop for VerifyingCore {
+    fn drop(&mut self) {
+        if self.mismatches > 0 {
+            crate::warn!(
+                "{} read(s) failed verification and were retried",
+                self.mismatches
+            );
+        }
+    }
+}
+
+impl Core for VerifyingCore {
+    fn info(&self) -> (String, Option<String>) {
+        self.core.info()
+    }
+
+    fn vid_pid(&self) -> Option<(u16, u16)> {
+        self.core.vid_pid()
+    }
+
+    fn read_8(&mut self, addr: u32, data: &mut [u8]) -> Result<()> {
+        let mut check = vec![0; data.len()];
+        self.core.read_8(addr, data)?;
+
+        for attempt in 0..=VERIFY_RETRIES {
+            self.core.read_8(addr, &mut check)?;
+
+            if check == data {
+                return Ok(());
+            }
+
+            let nbad = check.iter().zip(data.iter()).filter(|(a, b)| a != b);
+
+            crate::warn!(
+                "read of {} bytes at {addr:#x} failed verification \
+                ({} bytes differ){}",
+                data.len(),
+                nbad.count(),
+                if attempt < VERIFY_RETRIES { "; retrying" } else { "" }
+            );
+
+            self.mismatches += 1;
+            data.copy_from_slice(&check);
+        }
+
+        bail!(
+            "read of {} bytes at {addr:#x} failed to verify after {} attempts",
+            data.len(),
+            VERIFY_RETRIES + 1
+        );
+    }
+
+    fn read_word_32(&mut self, addr: u32) -> Result<u32> {
+        self.read_word(addr, |core, addr| core.read_word_32(addr))
+    }
+
+    fn read_word_64(&mut self, addr: u32) -> Result<u64> {
+        self.read_word(addr, |core, addr| core.read_word_64(addr))
+    }
+
+    fn arch(&self) -> &'static dyn Arch {
+        self.core.arch()
+    }
+
+    fn read_reg(&mut self, reg: ARMRegister) -> Result<u32> {
+        self.core.read_reg(reg)
+    }
+
+    fn write_reg(&mut self, reg: ARMRegister, value: u32) -> Result<()> {
+        self.core.write_reg(reg, value)
+    }
+
+    fn read_reg_id(&mut self, id: u16) -> Result<u32> {
+        self.core.read_reg_id(id)
+    }
+
+    fn write_reg_id(&mut self, id: u16, value: u32) -> Result<()> {
+        self.core.write_reg_id(id, value)
+    }
+
+    fn init_swv(&mut self) -> Result<()> {
+        self.core.init_swv()
+    }
+
+    fn read_swv(&mut self) -> Result<Vec<u8>> {
+        self.core.read_swv()
+    }
+
+    fn write_word_32(&mut self, addr: u32, data: u32) -> Result<()> {
+        self.core.write_word_32(addr, data)
+    }
+
+    fn write_8(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        self.core.write_8(addr, data)
+    }
+
+    fn halt(&mut self) -> Result<()> {
+        self.core.halt()
+    }
+
+    fn run(&mut self) -> Result<()> {
+        self.core.run()
+    }
+
+    fn step(&mut self) -> Result<()> {
+        self.core.step()
+    }
+
+    fn is_dump(&self) -> bool {
+        self.core.is_dump()
+    }
+
+    fn is_net(&self) -> bool {
+        self.core.is_net()
+    }
+
+    fn is_archive(&self) -> bool {
+        self.core.is_archive()
+    }
+
+    fn is_dry_run(&self) -> bool {
+        self.core.is_dry_run()
+    }
+
+    fn dry_run_inner(&mut self) -> Option<&mut dyn Core> {
+        self.core.dry_run_inner()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.core.set_timeout(timeout)
+    }
+
+    fn load(&mut self, path: &Path) -> Result<()> {
+        self.core.load(path)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.core.reset()
+    }
+
+    fn reset_and_halt(&mut self, dur: std::time::Duration) -> Result<()> {
+        self.core.reset_and_halt(dur)
+    }
+
+    fn op_start(&mut self) -> Result<()> {
+        self.core.op_start()
+    }
+
+    fn op_done(&mut self) -> Result<()> {
+        self.core.op_done()
+    }
+
+    fn wait_for_halt(&mut self, dur: std::time::Duration) -> Result<()> {
+        crate::msg!("would wait for halt (up to {dur:?})");
+        Ok(())
+    }
+
+    fn vector_catch(&mut self, mask: u32) -> Result<()> {
+        self.core.vector_catch(mask)
+    }
+
+    fn set_read_cache(&mut self, enabled: bool) {
+        self.core.set_read_cache(enabled)
+    }
+
+    fn ncores(&self) -> usize {
+        self.core.ncores()
+    }
+
+    fn core_index(&self) -> usize {
+        self.core.core_index()
+    }
+
+    fn select_core(&mut self, index: usize, halt_all: bool) -> Result<()> {
+        self.core.select_core(index, halt_all)
+    }
+
+    fn send(&self, buf: &[u8], agent: NetAgent) -> Result<usize> {
+        self.core.send(buf, agent)
+    }
+
+    fn recv(&self, buf: &mut [u8], agent: NetAgent) -> Result<usize> {
+        self.core.recv(buf, agent)
+    }
+}
+
+///
+/// A core that accounts for the bytes read from and written to the wrapped
+/// core (see [`crate::stats`]).
+///
+pub struct StatsCore {
+    core: Box<dyn Core>,
+}
+
+impl StatsCore {
+    pub fn new(core: Box<dyn Core>) -> StatsCore {
+        Self { core }
+    }
+}
+
+impl Core for StatsCore {
+    fn info(&self) -> (String, Option<String>) {
+        self.core.info()
+    }
+
+    fn vid_pid(&self) -> Option<(u16, u16)> {
+        self.core.vid_pid()
+    }
+
+    fn read_8(&mut self, addr: u32, data: &mut [u8]) -> Result<()> {
+        let started = Instant::now();
+        self.core.read_8(addr, data)?;
+        crate::stats::reading(started.elapsed());
+        crate::stats::read(data.len());
+        Ok(())
+    }
+
+    fn read_word_32(&mut self, addr: u32) -> Result<u32> {
+        let started = Instant::now();
+        let rval = self.core.read_word_32(addr)?;
+        crate::stats::reading(started.elapsed());
+        crate::stats::read(4);
+        Ok(rval)
+    }
+
+    fn read_word_64(&mut self, addr: u32) -> Result<u64> {
+        let started = Instant::now();
+        let rval = self.core.read_word_64(addr)?;
+        crate::stats::reading(started.elapsed());
+        crate::stats::read(8);
+        Ok(rval)
+    }
+
+    fn arch(&self) -> &'static dyn Arch {
+        self.core.arch()
+    }
+
+    fn read_reg(&mut self, reg: ARMRegister) -> Result<u32> {
+        self.core.read_reg(reg)
+    }
+
+    fn write_reg(&mut self, reg: ARMRegister, value: u32) -> Result<()> {
+        self.core.write_reg(reg, value)
+    }
+
+    fn read_reg_id(&mut self, id: u16) -> Result<u32> {
+        self.core.read_reg_id(id)
+    }
+
+    fn write_reg_id(&mut self, id: u16, value: u32) -> Result<()> {
+        self.core.write_reg_id(id, value)
+    }
+
+    fn init_swv(&mut self) -> Result<()> {
+        self.core.init_swv()
+    }
+
+    fn read_swv(&mut self) -> Result<Vec<u8>> {
+        self.core.read_swv()
+    }
+
+    fn write_word_32(&mut self, addr: u32, data: u32) -> Result<()> {
+        let started = Instant::now();
+        self.core.write_word_32(addr, data)?;
+        crate::stats::writing(started.elapsed());
+        crate::stats::written(4);
+        Ok(())
+    }
+
+    fn write_8(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        let started = Instant::now();
+        self.core.write_8(addr, data)?;
+        crate::stats::writing(started.elapsed());
+        crate::stats::written(data.len());
+        Ok(())
+    }
+
+    fn halt(&mut self) -> Result<()> {
+        self.core.halt()
+    }
+
+    fn run(&mut self) -> Result<()> {
+        self.core.run()
+    }
+
+    fn step(&mut self) -> Result<()> {
+        self.core.step()
+    }
+
+    fn is_dump(&self) -> bool {
+        self.core.is_dump()
+    }
+
+    fn is_net(&self) -> bool {
+        self.core.is_net()
+    }
+
+    fn is_archive(&self) -> bool {
+        self.core.is_archive()
+    }
+
+    fn is_dry_run(&self) -> bool {
+        self.core.is_dry_run()
+    }
+
+    fn dry_run_inner(&mut self) -> Option<&mut dyn Core> {
+        self.core.dry_run_inner()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.core.set_timeout(timeout)
+    }
+
+    fn load(&mut self, path: &Path) -> Result<()> {
+        self.core.load(path)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.core.reset()
+    }
+
+    fn reset_and_halt(&mut self, dur: std::time::Duration) -> Result<()> {
+        self.core.reset_and_halt(dur)
+    }
+
+    fn op_start(&mut self) -> Result<()> {
+        self.core.op_start()
+    }
+
+    fn op_done(&mut self) -> Result<()> {
+        self.core.op_done()
+    }
+
+    fn wait_for_halt(&mut self, dur: std::time::Duration) -> Result<()> {
+        self.core.wait_for_halt(dur)
+    }
+
+    fn vector_catch(&mut self, mask: u32) -> Result<()> {
+        self.core.vector_catch(mask)
+    }
+
+    fn set_read_cache(&mut self, enabled: bool) {
+        self.core.set_read_cache(enabled)
+    }
+
+    fn ncores(&self) -> usize {
+        self.core.ncores()
+    }
+
+    fn core_index(&self) -> usize {
+        self.core.core_index()
+    }
+
+    fn select_core(&mut self, index: usize, halt_all: bool) -> Result<()> {
+        self.core.select_core(index, halt_all)
+    }
+
+    fn send(&self, buf: &[u8], agent: NetAgent) -> Result<usize> {
+        let started = Instant::now();
+        let nbytes = self.core.send(buf, agent)?;
+        crate::stats::writing(started.elapsed());
+        crate::stats::written(nbytes);
+        Ok(nbytes)
+    }
+
+    fn recv(&self, buf: &mut [u8], agent: NetAgent) -> Result<usize> {
+        let started = Instant::now();
+        let nbytes = self.core.recv(buf, agent)?;
+        crate::stats::reading(started.elapsed());
+        crate::stats::read(nbytes);
+        Ok(nbytes)
+    }
+}
+
+///
+/// A core that reports -- rather than performs -- operations that would
+/// modify the target:  writes to memory and to registers, halting, running
+/// and stepping the core, loading a flash image, and resetting.  Reads are
+/// passed through to the wrapped core, allowing a command to determine what
+/// it would do.  HIF programs that only read (e.g., those consisting solely of
+/// I2C reads) are executed via [`Core::dry_run_inner`]; a HIF program that
+/// might modify the target is instead reported, and the command is stopped
+/// with a [`DryRun`] error (see `HiffyContext::start`).
+///
+pub struct DryRunCore {
+    core: Box<dyn Core>,
+}
+
+/// The number of bytes of a write that a [`DryRunCore`] will display
+const DRY_RUN_MAX_DISPLAY: usize = 16;
+
+impl DryRunCore {
+    pub fn new(core: Box<dyn Core>) -> DryRunCore {
+        Self { core }
+    }
+}
+
+///
+/// The error returned when a command running on a [`DryRunCore`] is stopped
+/// short of an operation that cannot be reported without being performed.
+///
+#[derive(Error, Debug)]
+#[error("dry run stopped before executing operations on the target")]
+pub struct DryRun;
+
+impl Core for DryRunCore {
+    fn info(&self) -> (String, Option<String>) {
+        self.core.info()
+    }
+
+    fn vid_pid(&self) -> Option<(u16, u16)> {
+        self.core.vid_pid()
+    }
+
+    fn read_8(&mut self, addr: u32, data: &mut [u8]) -> Result<()> {
+        self.core.read_8(addr, data)
+    }
+
+    fn read_word_32(&mut self, addr: u32) -> Result<u32> {
+        self.core.read_word_32(addr)
+    }
+
+    fn read_word_64(&mut self, addr: u32) -> Result<u64> {
+        self.core.read_word_64(addr)
+    }
+
+    fn arch(&self) -> &'static dyn Arch {
+        self.core.arch()
+    }
+
+    fn read_reg(&mut self, reg: ARMRegister) -> Result<u32> {
+        self.core.read_reg(reg)
+    }
+
+    fn write_reg(&mut self, reg: ARMRegister, value: u32) -> Result<()> {
+        crate::msg!("would write {value:#x} to register {reg:?}");
+        Ok(())
+    }
+
+    fn read_reg_id(&mut self, id: u16) -> Result<u32> {
+        self.core.read_reg_id(id)
+    }
+
+    fn write_reg_id(&mut self, id: u16, value: u32) -> Result<()> {
+        crate::msg!("would write {value:#x} to register {id:#x}");
+        Ok(())
+    }
+
+    fn init_swv(&mut self) -> Result<()> {
+        crate::msg!("would initialize SWV");
+        Ok(())
+    }
+
+    fn read_swv(&mut self) -> Result<Vec<u8>> {
+        self.core.read_swv()
+    }
+
+    fn write_word_32(&mut self, addr: u32, data: u32) -> Result<()> {
+        crate::msg!("would write {data:#010x} to {addr:#010x}");
+        Ok(())
+    }
+
+    fn write_8(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        if data.len() <= DRY_RUN_MAX_DISPLAY {
+            crate::msg!("would write {data:02x?} to {addr:#010x}");
+        } else {
+            crate::msg!(
+                "would write {} bytes to {addr:#010x}: {:02x?} ...",
+                data.len(),
+                &data[..DRY_RUN_MAX_DISPLAY]
+            );
+        }
+        Ok(())
+    }
+
+    fn halt(&mut self) -> Result<()> {
+        crate::msg!("would halt core");
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<()> {
+        crate::msg!("would run core");
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<()> {
+        crate::msg!("would step core");
+        Ok(())
+    }
+
+    fn is_dump(&self) -> bool {
+        self.core.is_dump()
+    }
+
+    fn is_net(&self) -> bool {
+        self.core.is_net()
+    }
+
+    fn is_archive(&self) -> bool {
+        self.core.is_archive()
+    }
+
+    fn is_dry_run(&self) -> bool {
+        true
+    }
+
+    fn dry_run_inner(&mut self) -> Option<&mut dyn Core> {
+        Some(&mut *self.core)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.core.set_timeout(timeout)
+    }
+
+    fn load(&mut self, path: &Path) -> Result<()> {
+        crate::msg!("would load flash image {}", path.display());
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        crate::msg!("would reset");
+        Ok(())
+    }
+
+    fn reset_and_halt(&mut self, dur: std::time::Duration) -> Result<()> {
+        crate::msg!("would reset and halt (waiting up to {dur:?})");
+        Ok(())
+    }
+
+    fn op_start(&mut self) -> Result<()> {
+        self.core.op_start()
+    }
+
+    fn op_done(&mut self) -> Result<()> {
+        self.core.op_done()
+    }
+
+    fn wait_for_halt(&mut self, dur: std::time::Duration) -> Result<()> {
+        self.core.wait_for_halt(dur)
+    }
+
+    fn vector_catch(&mut self, mask: u32) -> Result<()> {
+        crate::msg!("would set vector catch to {mask:#x}");
+        Ok(())
+    }
+
+    fn set_read_cache(&mut self, enabled: bool) {
+        self.core.set_read_cache(enabled)
+    }
+
+    fn ncores(&self) -> usize {
+        self.core.ncores()
+    }
+
+    fn core_index(&self) -> usize {
+        self.core.core_index()
+    }
+
+    fn select_core(&mut self, index: usize, halt_all: bool) -> Result<()> {
+        self.core.select_core(index, halt_all)
+    }
+
+    fn send(&self, buf: &[u8], agent: NetAgent) -> Result<usize> {
+        self.core.send(buf, agent)
+    }
+
+    fn recv(&self, buf: &mut [u8], agent: NetAgent) -> Result<usize> {
+        self.core.recv(buf, agent)
+    }
+}
+
+/// Something that you can talk to on the network
+///
+/// `control-plane-agent` is deliberately skipped, because it's best talked to
+/// via `faux-mgs`.
+pub enum NetAgent {
+    UdpRpc,
+    DumpAgent,
+}
+
+#[derive(Error, Debug)]
+pub enum ProbeError {
+    #[error("no debug probe found; is it plugged in?")]
+    NoProbeFound,
+}
+
+fn parse_probe(probe: &str) -> (&str, Option<usize>) {
+    if probe.contains('-') {
+        let str = probe.to_owned();
+        let pieces: Vec<&str> = str.split('-').collect();
+
+        if pieces[0] == "usb" && pieces.len() == 2 {
+            if let Ok(val) = pieces[1].parse::<usize>() {
+                ("usb", Some(val))
+            } else {
+                (probe, None)
             }
         } else {
             (probe, None)
@@ -1514,8 +2671,27 @@ fn get_usb_probe(index: Option<usize>) -> Result<probe_rs::DebugProbeInfo> {
     }
 }
 
+///
+/// Returns the probe (of the form "vid:pid:serial") that corresponds to the
+/// attached USB probe with the specified serial number.
+///
+pub fn usb_probe_by_serial(serial: &str) -> Result<String> {
+    let probes = Probe::list_all();
+
+    match probes.iter().find(|p| p.serial_number.as_deref() == Some(serial)) {
+        Some(p) => {
+            Ok(format!("{:04x}:{:04x}:{serial}", p.vendor_id, p.product_id))
+        }
+        None => bail!("no USB probe found with serial number {serial}"),
+    }
+}
+
 #[rustfmt::skip::macros(anyhow, bail)]
 pub fn attach_to_probe(probe: &str) -> Result<Box<dyn Core>> {
+    if probe.starts_with("remote:") {
+        bail!("Probe only attachment with a remote probe is not supported");
+    }
+
     let (probe, index) = parse_probe(probe);
 
     match probe {
@@ -1573,6 +2749,13 @@ pub fn attach_to_chip(
     hubris: &HubrisArchive,
     chip: Option<&str>,
 ) -> Result<Box<dyn Core>> {
+    if let Some(remote) = probe.strip_prefix("remote:") {
+        let core = crate::remote::RemoteCore::new(remote)?;
+        crate::msg!("attached via {}", core.info().0);
+
+        return Ok(Box::new(core));
+    }
+
     let (probe, index) = parse_probe(probe);
 
     match probe {
@@ -19,7 +19,7 @@ use std::path::Path;
 use std::str::{self, FromStr};
 use std::time::Instant;
 
-use crate::{msg, warn};
+use crate::{msg, warn, Diagnostic};
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use capstone::InsnGroupType;
 use fallible_iterator::FallibleIterator;
@@ -38,6 +38,14 @@ const OXIDE_NT_BASE: u32 = 0x1de << 20;
 const OXIDE_NT_HUBRIS_ARCHIVE: u32 = OXIDE_NT_BASE + 1;
 const OXIDE_NT_HUBRIS_REGISTERS: u32 = OXIDE_NT_BASE + 2;
 const OXIDE_NT_HUBRIS_TASK: u32 = OXIDE_NT_BASE + 3;
+const OXIDE_NT_HUBRIS_SPARSE: u32 = OXIDE_NT_BASE + 4;
+const OXIDE_NT_HUBRIS_METADATA: u32 = OXIDE_NT_BASE + 5;
+
+/// Magic number at the start of a zstd frame (and thus a compressed dump)
+pub(crate) const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression level used for compressed dumps
+const ZSTD_LEVEL: i32 = 9;
 
 const MAX_HUBRIS_VERSION: u32 = 8;
 
@@ -53,11 +61,15 @@ pub struct HubrisManifest {
     pub task_features: HashMap<String, Vec<String>>,
     pub task_irqs: HashMap<String, Vec<(u32, u32)>>,
     pub task_notifications: HashMap<String, Vec<String>>,
+    pub task_priorities: HashMap<String, u32>,
     pub peripherals: BTreeMap<String, u32>,
+    pub peripheral_irqs: BTreeMap<String, BTreeMap<String, u32>>,
     pub peripherals_byaddr: BTreeMap<u32, String>,
     pub i2c_devices: Vec<HubrisI2cDevice>,
     pub i2c_buses: Vec<HubrisI2cBus>,
     pub sensors: Vec<HubrisSensor>,
+    pub spi_devices: Vec<HubrisSpiDevice>,
+    pub pin_mux: Vec<HubrisPinMux>,
     pub auxflash: Option<HubrisConfigAuxflash>,
 }
 
@@ -105,6 +117,7 @@ struct HubrisConfigKernel {
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 struct HubrisConfigTask {
+    priority: Option<u32>,
     features: Option<Vec<String>>,
     extern_regions: Option<Vec<String>>,
     #[serde(default)]
@@ -131,6 +144,22 @@ struct HubrisConfigPeripheral {
 struct HubrisConfigI2cPort {
     name: Option<String>,
     description: Option<String>,
+    pins: Option<Vec<HubrisConfigPins>>,
+}
+
+///
+/// Pins configured for an alternate function, as found in both I2C port
+/// and SPI mux configuration.  (I2C uses `gpio_port` and `pins` where SPI
+/// uses `port` and either `pins` or `pin`.)
+///
+#[derive(Clone, Debug, Deserialize)]
+struct HubrisConfigPins {
+    gpio_port: Option<String>,
+    port: Option<String>,
+    #[serde(default)]
+    pins: Vec<u8>,
+    pin: Option<u8>,
+    af: Option<u8>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -237,9 +266,32 @@ impl HubrisConfigAuxflash {
     }
 }
 
+#[derive(Clone, Debug, Deserialize)]
+struct HubrisConfigSpi {
+    controller: u8,
+    #[serde(default)]
+    mux_options: BTreeMap<String, HubrisConfigSpiMux>,
+    #[serde(default)]
+    devices: IndexMap<String, HubrisConfigSpiDevice>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct HubrisConfigSpiMux {
+    #[serde(default)]
+    outputs: Vec<HubrisConfigPins>,
+    input: Option<HubrisConfigPins>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct HubrisConfigSpiDevice {
+    mux: Option<String>,
+    description: Option<String>,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 struct HubrisConfigConfig {
     i2c: Option<HubrisConfigI2c>,
+    spi: Option<IndexMap<String, HubrisConfigSpi>>,
     sensor: Option<HubrisConfigSensor>,
     auxflash: Option<HubrisConfigAuxflash>,
 }
@@ -271,6 +323,34 @@ pub struct HubrisI2cBus {
     pub target: bool,
 }
 
+///
+/// An SPI device, as identified by its controller and its index among the
+/// devices of that controller (which is how the SPI server refers to it).
+///
+#[derive(Clone, Debug, Serialize)]
+pub struct HubrisSpiDevice {
+    pub controller: u8,
+    pub index: u8,
+    pub name: String,
+    pub mux: Option<String>,
+    pub description: Option<String>,
+}
+
+///
+/// A GPIO pin that the configuration expects to be set to an alternate
+/// function on behalf of a peripheral.  If the peripheral has several
+/// ports (or mux options) among which it switches, only one of them is
+/// configured at any one time; such pins are marked as `muxed`.
+///
+#[derive(Clone, Debug, Serialize)]
+pub struct HubrisPinMux {
+    pub port: String,
+    pub pin: u8,
+    pub af: u8,
+    pub owner: String,
+    pub muxed: bool,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct HubrisPmbusRail {
     pub name: String,
@@ -615,6 +695,12 @@ pub struct HubrisArchive {
     // Current registers (if a dump)
     registers: HashMap<ARMRegister, u32>,
 
+    // Zero-filled ranges omitted from the dump (if a sparse dump)
+    sparse: Vec<(u32, u32)>,
+
+    // Metadata recorded when the dump was taken (if present)
+    dump_metadata: Option<HubrisDumpMetadata>,
+
     // Modules: text address to module
     modules: BTreeMap<u32, HubrisModule>,
 
@@ -702,6 +788,8 @@ impl HubrisArchive {
             instrs: HashMap::new(),
             syscall_pushes: HashMap::new(),
             registers: HashMap::new(),
+            sparse: Vec::new(),
+            dump_metadata: None,
             modules: BTreeMap::new(),
             tasks: HashMap::new(),
             frames: HashMap::new(),
@@ -813,6 +901,63 @@ impl HubrisArchive {
         inlined
     }
 
+    fn load_pin_mux(
+        &mut self,
+        pins: &[HubrisConfigPins],
+        default_port: Option<&str>,
+        owner: &str,
+        muxed: bool,
+    ) {
+        for p in pins {
+            let port = match (&p.gpio_port, &p.port, default_port) {
+                (Some(port), _, _) | (None, Some(port), _) => port.as_str(),
+                (None, None, Some(port)) => port,
+                (None, None, None) => continue,
+            };
+
+            let af = match p.af {
+                Some(af) => af,
+                None => continue,
+            };
+
+            for pin in p.pins.iter().chain(p.pin.iter()) {
+                self.manifest.pin_mux.push(HubrisPinMux {
+                    port: port.to_uppercase(),
+                    pin: *pin,
+                    af,
+                    owner: owner.to_string(),
+                    muxed,
+                });
+            }
+        }
+    }
+
+    fn load_spi_config(&mut self, spi: &IndexMap<String, HubrisConfigSpi>) {
+        for config in spi.values() {
+            let owner = format!("spi{}", config.controller);
+            let muxed = config.mux_options.len() > 1;
+
+            for mux in config.mux_options.values() {
+                self.load_pin_mux(&mux.outputs, None, &owner, muxed);
+
+                if let Some(ref input) = mux.input {
+                    let input = std::slice::from_ref(input);
+                    self.load_pin_mux(input, None, &owner, muxed);
+                }
+            }
+
+            for (index, (name, device)) in config.devices.iter().enumerate() {
+                self.manifest.spi_devices.push(HubrisSpiDevice {
+                    controller: config.controller,
+                    index: index as u8,
+                    name: name.clone(),
+                    mux: device.mux.clone(),
+                    description: device.description.clone(),
+                });
+            }
+        }
+    }
+
     fn load_sensor_config(
         &mut self,
         sensor: &HubrisConfigSensor,
@@ -853,6 +998,12 @@ impl HubrisArchive {
                         description: port.description.as_ref().cloned(),
                         target: controller.target.unwrap_or(false),
                     });
+
+                    if let Some(ref pins) = port.pins {
+                        let owner = format!("i2c{}", controller.controller);
+                        let muxed = controller.ports.len() > 1;
+                        self.load_pin_mux(pins, Some(name), &owner, muxed);
+                    }
                 }
             }
         }
@@ -1068,11 +1219,23 @@ impl HubrisArchive {
                         named_interrupts
                             .insert(format!("{}.{}", name, interrupt), *irq);
                     }
+
+                    self.manifest.peripheral_irqs.insert(
+                        name.clone(),
+                        interrupts
+                            .iter()
+                            .map(|(i, irq)| (i.clone(), *irq))
+                            .collect(),
+                    );
                 }
             }
         }
 
         for (name, task) in &config.tasks {
+            if let Some(priority) = task.priority {
+                self.manifest.task_priorities.insert(name.clone(), priority);
+            }
+
             if let Some(ref features) = task.features {
                 self.manifest
                     .task_features
@@ -1131,6 +1294,9 @@ impl HubrisArchive {
             if let Some(i2c) = config.i2c.as_ref() {
                 self.load_i2c_config(i2c)?;
             }
+            if let Some(spi) = config.spi.as_ref() {
+                self.load_spi_config(spi);
+            }
             if let Some(sensor) = config.sensor.as_ref() {
                 self.load_sensor_config(sensor)?;
             }
@@ -1668,15 +1834,30 @@ impl HubrisArchive {
         Ok(())
     }
 
+    fn load_sparse(&mut self, sparse: &[u8]) -> Result<()> {
+        if sparse.len() % 8 != 0 {
+            bail!("bad sparse index length ({})", sparse.len());
+        }
+
+        for chunk in sparse.chunks_exact(8) {
+            let base = u32::from_le_bytes(chunk[0..4].try_into().unwrap());
+            let size = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+            self.sparse.push((base, size));
+        }
+
+        Ok(())
+    }
+
     pub fn load_dump(
         &mut self,
         dumpfile: &str,
         doneness: HubrisArchiveDoneness,
     ) -> Result<()> {
         //
-        // We expect the dump to be an ELF core dump.
+        // We expect the dump to be an ELF core dump, albeit possibly
+        // compressed.
         //
-        let contents = fs::read(dumpfile)?;
+        let contents = read_dumpfile(dumpfile)?;
         let elf = Elf::parse(&contents).map_err(|e| {
             anyhow!("failed to parse {} as an ELF file: {}", dumpfile, e)
         })?;
@@ -1700,6 +1881,13 @@ impl HubrisArchive {
                             OXIDE_NT_HUBRIS_REGISTERS => {
                                 self.load_registers(note.desc)?;
                             }
+                            OXIDE_NT_HUBRIS_SPARSE => {
+                                self.load_sparse(note.desc)?;
+                            }
+                            OXIDE_NT_HUBRIS_METADATA => {
+                                self.dump_metadata =
+                                    Some(serde_json::from_slice(note.desc)?);
+                            }
                             OXIDE_NT_HUBRIS_TASK => {
                                 match DumpTask::read_from_prefix(note.desc) {
                                     Some(task) => {
@@ -1919,6 +2107,136 @@ impl HubrisArchive {
         })
     }
 
+    ///
+    /// Looks up a variable that is optionally followed by a path of
+    /// structure members (".member") and array indices ("[index]"),
+    /// returning its name and a variable that describes the designated
+    /// member or element.  The variable itself is matched against the
+    /// qualified variables either exactly or as a path suffix (e.g.,
+    /// "CONFIG" matches "task_thermal::CONFIG"), and can also be qualified
+    /// with the name of the task that contains it (e.g., "thermal::CONFIG").
+    ///
+    pub fn lookup_variable_path(
+        &self,
+        spec: &str,
+    ) -> Result<(String, HubrisVariable)> {
+        let ndx =
+            spec.find(|c: char| c == '.' || c == '[').unwrap_or(spec.len());
+        let var = &spec[..ndx];
+
+        let suffix =
+            |n: &str, v: &str| n == v || n.ends_with(&format!("::{v}"));
+
+        let mut found: Vec<_> = self
+            .qualified_variables()
+            .filter(|&(n, _)| suffix(n, var))
+            .collect();
+
+        if found.is_empty() {
+            if let Some((task, v)) = var.split_once("::") {
+                if let Some(task) = self.lookup_task(task) {
+                    found = self
+                        .qualified_variables()
+                        .filter(|&(n, variable)| {
+                            HubrisTask::from(variable.goff) == *task
+                                && suffix(n, v)
+                        })
+                        .collect();
+                }
+            }
+        }
+
+        let (name, variable) = match found.len() {
+            0 => bail!("variable {var} not found"),
+            1 => found[0],
+            _ => {
+                let names = found
+                    .iter()
+                    .map(|(n, _)| n.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                bail!("variable {var} is ambiguous; could be any of: {names}");
+            }
+        };
+
+        let mut addr = variable.addr;
+        let mut goff = variable.goff;
+        let mut path = &spec[ndx..];
+
+        while !path.is_empty() {
+            if let Some(rest) = path.strip_prefix('.') {
+                let end = rest
+                    .find(|c: char| c == '.' || c == '[')
+                    .unwrap_or(rest.len());
+                let member = &rest[..end];
+
+                let s = self.lookup_struct(goff).map_err(|_| {
+                    anyhow!("\"{member}\" is not a member of a structure")
+                })?;
+
+                //
+                // Tuple members are named "__0", "__1", etc.
+                //
+                let m = s
+                    .lookup_member(member)
+                    .or_else(|_| s.lookup_member(&format!("__{member}")))?;
+
+                addr += m.offset as u32;
+                goff = m.goff;
+                path = &rest[end..];
+            } else if let Some(rest) = path.strip_prefix('[') {
+                let end = match rest.find(']') {
+                    Some(end) => end,
+                    None => bail!("missing ']' in \"{spec}\""),
+                };
+
+                let index: usize =
+                    parse_int::parse(&rest[..end]).map_err(|_| {
+                        anyhow!("invalid index \"{}\"", &rest[..end])
+                    })?;
+
+                let array = self.lookup_array(goff).map_err(|_| {
+                    anyhow!("cannot index into a non-array with [{index}]")
+                })?;
+
+                if index >= array.count {
+                    let count = array.count;
+                    bail!("index {index} exceeds array size of {count}");
+                }
+
+                let size = self.lookup_type(array.goff)?.size(self)?;
+
+                addr += (index * size) as u32;
+                goff = array.goff;
+                path = &rest[end + 1..];
+            } else {
+                bail!("invalid member path \"{path}\"");
+            }
+        }
+
+        let size = self.lookup_type(goff)?.size(self)?;
+
+        Ok((
+            format!("{name}{}", &spec[ndx..]),
+            HubrisVariable { goff, addr, size },
+        ))
+    }
+
+    ///
+    /// Returns the name, address and size of each ELF symbol whose
+    /// (demangled) name matches the specified name, either exactly or as a
+    /// path suffix (e.g., "main" matches "task_thermal::main").
+    ///
+    pub fn lookup_symbols(&self, name: &str) -> Vec<(&str, u32, u32)> {
+        let suffix = format!("::{name}");
+
+        self.esyms
+            .iter()
+            .filter(|(_, (n, _))| n == name || n.ends_with(&suffix))
+            .map(|(&addr, (n, size))| (n.as_str(), addr, *size))
+            .collect()
+    }
+
     pub fn lookup_module(&self, task: HubrisTask) -> Result<&HubrisModule> {
         match self.modules.values().find(|m| m.task == task) {
             Some(module) => Ok(module),
@@ -1936,6 +2254,14 @@ impl HubrisArchive {
         self.modules.values()
     }
 
+    ///
+    /// Returns the regions loaded from the ELF objects in the archive (that
+    /// is, their loadable segments), in address order.
+    ///
+    pub fn loaded_regions(&self) -> impl Iterator<Item = &HubrisRegion> {
+        self.loaded.values()
+    }
+
     pub fn lookup_task(&self, name: &str) -> Option<&HubrisTask> {
         self.tasks.get(name)
     }
@@ -2232,15 +2558,15 @@ impl HubrisArchive {
             }
         }
 
-        bail!(
-            "target does not appear to be booted and may be panicking on \
-            boot; run \"humility registers -s\" for a kernel stack trace"
-        );
+        bail!(Diagnostic::error(
+            "target does not appear to be booted and may be panicking on boot"
+        )
+        .code("not-booted")
+        .hint("run \"humility registers -s\" for a kernel stack trace"));
     }
 
     pub fn verify(&self, core: &mut dyn crate::core::Core) -> Result<()> {
-        use indicatif::{HumanBytes, HumanDuration};
-        use indicatif::{ProgressBar, ProgressStyle};
+        use indicatif::{HumanBytes, HumanDuration, ProgressStyle};
 
         // The verification logic we use is:
         //
@@ -2306,7 +2632,7 @@ impl HubrisArchive {
         let mut problems = 0;
 
         let started = Instant::now();
-        let bar = ProgressBar::new(total as u64);
+        let bar = crate::progress_bar(total as u64);
         bar.set_style(
             ProgressStyle::default_bar().template(
                 "humility: verifying [{bar:30}] {buffer}/{total_bytes}",
@@ -2712,13 +3038,91 @@ impl HubrisArchive {
         self.registers.clone()
     }
 
+    /// Returns the zero-filled ranges elided from a sparse dump
+    pub fn dump_elided(&self) -> Vec<(u32, u32)> {
+        self.sparse.clone()
+    }
+
+    /// Returns the metadata recorded in a dump, if any
+    pub fn dump_metadata(&self) -> Option<&HubrisDumpMetadata> {
+        self.dump_metadata.as_ref()
+    }
+
+    ///
+    /// Returns true if the specified task is the current task and is
+    /// executing at user-level (that is, its PC falls within the task), in
+    /// which case its registers are those of the core rather than those
+    /// saved by the kernel.
+    ///
+    pub fn task_in_userland(
+        &self,
+        core: &mut dyn crate::core::Core,
+        t: HubrisTask,
+    ) -> Result<bool> {
+        if self.current_task(core)? != Some(t) {
+            return Ok(false);
+        }
+
+        let pc = core.read_reg(ARMRegister::PC)?;
+
+        Ok(if let Some(module) = self.modules.range(..=pc).next_back() {
+            pc < *module.0 + module.1.textsize && module.1.task == t
+        } else {
+            false
+        })
+    }
+
+    ///
+    /// Returns the addresses at which the kernel has saved the registers of
+    /// the specified task:  R4-R11 are found in the task's saved state, and
+    /// R0-R3, R12, LR, PC and PSR in the exception frame on its stack.  (If
+    /// the task is executing at user-level, these will not reflect its
+    /// current registers; see [`Self::task_in_userland`].)
+    ///
+    pub fn saved_registers(
+        &self,
+        core: &mut dyn crate::core::Core,
+        t: HubrisTask,
+    ) -> Result<BTreeMap<ARMRegister, u32>> {
+        let (base, _) = self.task_table(core)?;
+
+        let ndx = match self.lookup_module(t)?.task {
+            HubrisTask::Task(ndx) => ndx,
+            _ => {
+                bail!("must provide a user task")
+            }
+        };
+
+        let task = self.lookup_struct_byname("Task")?;
+        let save = task.lookup_member("save")?.offset as u32;
+        let state = self.lookup_struct_byname("SavedState")?;
+        let offset = base + (ndx * task.size as u32) + save;
+
+        let mut rval = BTreeMap::new();
+
+        for r in 4..=11 {
+            let o = state.lookup_member(&format!("r{}", r))?.offset as u32;
+            rval.insert(ARMRegister::from_usize(r).unwrap(), offset + o);
+        }
+
+        let psp = state.lookup_member("psp")?.offset as u32;
+        let sp = core.read_word_32(offset + psp)?;
+
+        let frame = humility_arch_arm::exception_frame_registers();
+
+        for (i, reg) in frame.iter().enumerate() {
+            rval.insert(*reg, sp + (i as u32 * 4));
+        }
+
+        Ok(rval)
+    }
+
     pub fn registers(
         &self,
         core: &mut dyn crate::core::Core,
         t: HubrisTask,
     ) -> Result<BTreeMap<ARMRegister, u32>> {
         let (base, _) = self.task_table(core)?;
-        let cur = self.current_task(core)?;
 
         let module = self.lookup_module(t)?;
         let mut rval = BTreeMap::new();
@@ -2741,39 +3145,24 @@ impl HubrisArchive {
         core.read_8(offset, regs.as_mut_slice())?;
 
         //
-        // If this is the current task, we want to pull the current PC.
+        // If this task is at user-level, we should take our register state
+        // directly rather than from the stack.
         //
-        if cur == Some(t) {
-            let pc = core.read_reg(ARMRegister::PC)?;
-
-            //
-            // If the PC falls within the task, then we are at user-level,
-            // and we should take our register state directly rather than
-            // from the stack.
-            //
-            let userland =
-                if let Some(module) = self.modules.range(..=pc).next_back() {
-                    pc < *module.0 + module.1.textsize && module.1.task == t
-                } else {
-                    false
+        if self.task_in_userland(core, t)? {
+            for i in 0..=31 {
+                let reg = match ARMRegister::from_u16(i) {
+                    Some(r) => r,
+                    None => {
+                        continue;
+                    }
                 };
 
-            if userland {
-                for i in 0..=31 {
-                    let reg = match ARMRegister::from_u16(i) {
-                        Some(r) => r,
-                        None => {
-                            continue;
-                        }
-                    };
-
-                    let val = core.read_reg(reg)?;
-                    rval.insert(reg, val);
-                }
-
-                return Ok(rval);
+                let val = core.read_reg(reg)?;
+                rval.insert(reg, val);
             }
-        };
+
+            return Ok(rval);
+        }
 
         let readreg = |rname| -> Result<u32> {
             let o = state.lookup_member(rname)?.offset;
@@ -2820,24 +3209,29 @@ impl HubrisArchive {
 
         //
         // Not all architectures have floating point -- and ARMv6 never has
-        // it.  (Note that that the FP contents pushed onto the stack is
-        // always 8-byte aligned; if we have our 17 floating point registers
-        // here, we also have an unstored pad.)
+        // it.  Where the kernel has saved the EXC_RETURN value, we can use
+        // it to know if the frame was extended with the FP context; if we
+        // don't have it, we assume that it was extended on any architecture
+        // that might have floating point.  (Note that that the FP contents
+        // pushed onto the stack is always 8-byte aligned; if we have our 17
+        // floating point registers here, we also have an unstored pad.)
         //
-        let (nregs_fp, align) =
-            if self.manifest.target.as_ref().unwrap() == "thumbv6m-none-eabi" {
-                (0, 0)
-            } else {
-                (17, 1)
-            };
-
-        let nregs_frame: usize = NREGS_CORE + nregs_fp + align;
+        let exc_return = match state.lookup_member("exc_return") {
+            Ok(_) => readreg("exc_return")?,
+            Err(_)
+                if self.manifest.target.as_deref()
+                    == Some("thumbv6m-none-eabi") =>
+            {
+                0xffff_fffd
+            }
+            Err(_) => 0xffff_ffed,
+        };
 
         //
         // We manually adjust our stack pointer to peel off the entire frame,
         // plus any needed re-alignment.
         //
-        let adjust = (nregs_frame as u32) * 4
+        let adjust = humility_arch_arm::exception_frame_size(exc_return)
             + humility_arch_arm::exception_stack_realign(&rval);
 
         rval.insert(ARMRegister::SP, sp + adjust);
@@ -2991,6 +3385,14 @@ impl HubrisArchive {
                 None => None,
             };
 
+            let lr = *frameregs.get(&ARMRegister::LR).unwrap();
+
+            let exc_return = if humility_arch_arm::is_exc_return(lr) {
+                Some(lr)
+            } else {
+                None
+            };
+
             //
             // Our frame is complete -- push it and continue!
             //
@@ -2999,16 +3401,54 @@ impl HubrisArchive {
                 sym,
                 inlined,
                 registers: frameregs.clone(),
+                exc_return,
             });
 
-            let lr = *frameregs.get(&ARMRegister::LR).unwrap();
+            if let Some(exc_return) = exc_return {
+                //
+                // We have hit an exception boundary, and the registers of
+                // the interrupted context are in the exception frame.  If
+                // we are unwinding the kernel and the frame was stacked on
+                // the process stack, the interrupted context is the current
+                // task, whose stack we unwind in its own right.  Otherwise,
+                // the frame was stacked on the stack that we are unwinding
+                // -- either the main stack, when the kernel has interrupted
+                // itself (e.g., a fault taken while in a system call), or a
+                // task's stack -- and we continue unwinding from it.
+                //
+                if humility_arch_arm::exc_return_process_stack(exc_return)
+                    && task == HubrisTask::Kernel
+                {
+                    let Some(&psp) = frameregs.get(&ARMRegister::PSP) else {
+                        break;
+                    };
 
-            //
-            // If this is a kernel stack and we have hit an EXC_RETURN, we're
-            // done.
-            //
-            if task == HubrisTask::Kernel && (lr >> 28 == 0xf) {
-                break;
+                    let Some(current) = self.current_task(core)? else {
+                        break;
+                    };
+
+                    let Some((_, region)) = regions.range(..=psp).next_back()
+                    else {
+                        break;
+                    };
+
+                    unstack(exc_return, psp, &mut frameregs, |addr| {
+                        core.read_word_32(addr)
+                    })?;
+
+                    let limit = region.base + region.size;
+                    rval.extend(self.stack(core, current, limit, &frameregs)?);
+                    break;
+                }
+
+                unstack(exc_return, cfa, &mut frameregs, &readval)?;
+
+                if *frameregs.get(&ARMRegister::SP).unwrap() >= limit {
+                    break;
+                }
+
+                prev = Some(cfa);
+                continue;
             }
 
             //
@@ -3220,6 +3660,74 @@ impl HubrisArchive {
         })
     }
 
+    ///
+    /// Reads the specified segments in their entirety, returning the
+    /// segments (and their contents) that remain after eliding any zero-filled
+    /// blocks of the specified granularity, along with the elided ranges.
+    ///
+    #[allow(clippy::type_complexity)]
+    fn dump_sparsify(
+        &self,
+        core: &mut dyn crate::core::Core,
+        segments: &[(u32, u32)],
+        granularity: usize,
+    ) -> Result<(Vec<(u32, u32)>, Vec<Vec<u8>>, Vec<(u32, u32)>)> {
+        use indicatif::ProgressStyle;
+
+        let total = segments.iter().fold(0, |ttl, (_, size)| ttl + size);
+        let bar = crate::progress_bar(total as u64);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("humility: reading [{bar:30}] {bytes}/{total_bytes}"),
+        );
+
+        let mut captured: Vec<(u32, u32)> = vec![];
+        let mut contents: Vec<Vec<u8>> = vec![];
+        let mut zeroes: Vec<(u32, u32)> = vec![];
+        let mut nread = 0;
+
+        for (base, size) in segments {
+            let mut buf = vec![0; *size as usize];
+
+            for (i, chunk) in buf.chunks_mut(1024).enumerate() {
+                core.read_8(*base + (i * 1024) as u32, chunk)?;
+                nread += chunk.len();
+                bar.set_position(nread as u64);
+            }
+
+            for (i, block) in buf.chunks(granularity).enumerate() {
+                let addr = *base + (i * granularity) as u32;
+                let len = block.len() as u32;
+
+                //
+                // Coalesce this block with the previous range of its kind if
+                // they are contiguous.
+                //
+                if block.iter().all(|&b| b == 0) {
+                    match zeroes.last_mut() {
+                        Some((b, s)) if *b + *s == addr => *s += len,
+                        _ => zeroes.push((addr, len)),
+                    }
+                } else {
+                    match (captured.last_mut(), contents.last_mut()) {
+                        (Some((b, s)), Some(c)) if *b + *s == addr => {
+                            *s += len;
+                            c.extend_from_slice(block);
+                        }
+                        _ => {
+                            captured.push((addr, len));
+                            contents.push(block.to_vec());
+                        }
+                    }
+                }
+            }
+        }
+
+        bar.finish_and_clear();
+
+        Ok((captured, contents, zeroes))
+    }
+
     pub fn dump(
         &self,
         core: &mut dyn crate::core::Core,
@@ -3227,11 +3735,54 @@ impl HubrisArchive {
         dumpfile: Option<&str>,
         started: Option<Instant>,
     ) -> Result<()> {
-        use indicatif::{HumanBytes, HumanDuration};
-        use indicatif::{ProgressBar, ProgressStyle};
+        self.dump_sparse(core, task, dumpfile, started, None)
+    }
+
+    ///
+    /// Writes a dump, optionally as a sparse dump:  if `sparse` is set, it
+    /// denotes a granularity at which zero-filled memory is omitted from the
+    /// dump, with the omitted ranges recorded in an index note.
+    ///
+    pub fn dump_sparse(
+        &self,
+        core: &mut dyn crate::core::Core,
+        task: Option<DumpTask>,
+        dumpfile: Option<&str>,
+        started: Option<Instant>,
+        sparse: Option<usize>,
+    ) -> Result<()> {
+        let options = HubrisDumpOptions { sparse, ..Default::default() };
+        self.dump_with_options(core, task, dumpfile, started, &options)
+    }
+
+    ///
+    /// Writes a dump with the specified options (see [`HubrisDumpOptions`]).
+    /// Metadata describing the dump is recorded in a note; if compression
+    /// is requested, the entire dump is compressed with zstd once written.
+    ///
+    pub fn dump_with_options(
+        &self,
+        core: &mut dyn crate::core::Core,
+        task: Option<DumpTask>,
+        dumpfile: Option<&str>,
+        started: Option<Instant>,
+        options: &HubrisDumpOptions,
+    ) -> Result<()> {
+        use indicatif::{HumanBytes, HumanDuration, ProgressStyle};
         use std::io::Write;
 
+        let sparse = options.sparse;
         let segments = self.dump_segments(core, task, true)?;
+
+        let (segments, contents, zeroes) = match sparse {
+            Some(granularity) => {
+                let (segments, contents, zeroes) =
+                    self.dump_sparsify(core, &segments, granularity)?;
+                (segments, Some(contents), zeroes)
+            }
+            None => (segments, None, vec![]),
+        };
+
         let nsegs = segments.len();
 
         macro_rules! pad {
@@ -3285,6 +3836,32 @@ impl HubrisArchive {
             }
         }
 
+        if !zeroes.is_empty() {
+            notes.push(goblin::elf::note::Nhdr32 {
+                n_namesz: (oxide.len() + 1) as u32,
+                n_descsz: zeroes.len() as u32 * 8,
+                n_type: OXIDE_NT_HUBRIS_SPARSE,
+            });
+        }
+
+        let metadata = serde_json::to_vec(&HubrisDumpMetadata {
+            image_id: self
+                .image_id()
+                .map(|id| id.iter().map(|b| format!("{b:02x}")).collect()),
+            time: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs(),
+            probe: options.probe.clone(),
+            serial: options.serial.clone(),
+            comment: options.comment.clone(),
+        })?;
+
+        notes.push(goblin::elf::note::Nhdr32 {
+            n_namesz: (oxide.len() + 1) as u32,
+            n_descsz: metadata.len() as u32,
+            n_type: OXIDE_NT_HUBRIS_METADATA,
+        });
+
         notes.push(goblin::elf::note::Nhdr32 {
             n_namesz: (oxide.len() + 1) as u32,
             n_descsz: self.archive.len() as u32,
@@ -3404,10 +3981,24 @@ impl HubrisArchive {
                     file.write_all(&self.archive)?;
                 }
 
+                OXIDE_NT_HUBRIS_METADATA => {
+                    file.write_all(&metadata)?;
+                }
+
                 OXIDE_NT_HUBRIS_TASK => {
                     file.write_all(task.unwrap().as_bytes())?;
                 }
 
+                OXIDE_NT_HUBRIS_SPARSE => {
+                    let mut bytes = [0x0u8; 8];
+
+                    for (base, size) in zeroes.iter() {
+                        bytes.pwrite_with(base, 0, ctx.le)?;
+                        bytes.pwrite_with(size, 4, ctx.le)?;
+                        file.write_all(&bytes)?;
+                    }
+                }
+
                 _ => {
                     panic!("unimplemented note");
                 }
@@ -3425,17 +4016,28 @@ impl HubrisArchive {
 
         let started = started.unwrap_or_else(Instant::now);
 
-        let bar = ProgressBar::new(total as u64);
+        let bar = crate::progress_bar(total as u64);
         bar.set_style(
             ProgressStyle::default_bar()
                 .template("humility: dumping [{bar:30}] {bytes}/{total_bytes}"),
         );
 
-        for (base, size) in &segments {
+        for (ndx, (base, size)) in segments.iter().enumerate() {
             let mut remain = *size as usize;
             let mut bytes = vec![0; 1024];
             let mut addr = *base;
 
+            //
+            // If we have already read our contents (as we do for a sparse
+            // dump), we write them directly.
+            //
+            if let Some(contents) = &contents {
+                file.write_all(&contents[ndx])?;
+                written += remain;
+                remain = 0;
+                bar.set_position(written as u64);
+            }
+
             while remain > 0 {
                 let nbytes =
                     if remain > bytes.len() { bytes.len() } else { remain };
@@ -3454,11 +4056,37 @@ impl HubrisArchive {
 
         bar.finish_and_clear();
 
-        msg!(
-            "dumped {} in {}",
-            HumanBytes(written as u64),
-            HumanDuration(started.elapsed())
-        );
+        if sparse.is_some() {
+            let elided = zeroes.iter().fold(0, |ttl, (_, size)| ttl + size);
+
+            msg!(
+                "dumped {} (eliding {} of zeroes in {} ranges) in {}",
+                HumanBytes(written as u64),
+                HumanBytes(elided as u64),
+                zeroes.len(),
+                HumanDuration(started.elapsed())
+            );
+        } else {
+            msg!(
+                "dumped {} in {}",
+                HumanBytes(written as u64),
+                HumanDuration(started.elapsed())
+            );
+        }
+
+        if options.compress {
+            drop(file);
+
+            let contents = fs::read(&filename)?;
+            let compressed = zstd::encode_all(contents.as_slice(), ZSTD_LEVEL)?;
+            fs::write(&filename, &compressed)?;
+
+            msg!(
+                "compressed {} to {}",
+                HumanBytes(contents.len() as u64),
+                HumanBytes(compressed.len() as u64)
+            );
+        }
 
         Ok(())
     }
@@ -3721,6 +4349,22 @@ impl HubrisArchive {
         self.read_file("img/CMPA.bin")
     }
 
+    /// Reads the CMSIS-SVD description of the chip from a Hubris archive,
+    /// returning its name within the archive and its contents
+    ///
+    /// Returns `Ok(None)` if the archive does not contain an SVD file.
+    pub fn read_svd(&self) -> Result<Option<(String, Vec<u8>)>> {
+        let cursor = Cursor::new(self.archive());
+        let archive = zip::ZipArchive::new(cursor)?;
+
+        let name = match archive.file_names().find(|n| n.ends_with(".svd")) {
+            Some(name) => name.to_string(),
+            None => return Ok(None),
+        };
+
+        Ok(self.read_file(&name)?.map(|contents| (name, contents)))
+    }
+
     /// Determine if two types conclusively differ from one another, performing
     /// a deep comparison.
     pub fn differ(&self, lhs: HubrisGoff, rhs: HubrisGoff) -> Result<bool> {
@@ -5876,6 +6520,48 @@ pub struct HubrisRegion {
     pub tasks: Vec<HubrisTask>,
 }
 
+///
+/// Metadata recorded in a dump when it is taken.
+///
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HubrisDumpMetadata {
+    /// Image ID of the archive, as a hex string
+    pub image_id: Option<String>,
+
+    /// Time at which the dump was taken, in seconds since the Unix epoch
+    pub time: u64,
+
+    /// Debug probe (or other means) via which the dump was taken
+    pub probe: Option<String>,
+
+    /// Serial number of the debug probe
+    pub serial: Option<String>,
+
+    /// Comment provided by the operator
+    pub comment: Option<String>,
+}
+
+///
+/// Options for writing a dump.
+///
+#[derive(Clone, Debug, Default)]
+pub struct HubrisDumpOptions {
+    /// Granularity at which zero-filled memory is elided, if any
+    pub sparse: Option<usize>,
+
+    /// Compress the dump with zstd
+    pub compress: bool,
+
+    /// Debug probe to record in the dump's metadata
+    pub probe: Option<String>,
+
+    /// Probe serial number to record in the dump's metadata
+    pub serial: Option<String>,
+
+    /// Comment to record in the dump's metadata
+    pub comment: Option<String>,
+}
+
 #[derive(Clone, Debug)]
 pub struct HubrisEnumVariant {
     pub name: String,
@@ -6208,12 +6894,56 @@ pub enum HubrisTarget {
     Return,
 }
 
+///
+/// Recovers the registers of an interrupted context from the exception frame
+/// (as denoted by `exc_return`) that was stacked at `addr`, including its
+/// stack pointer as of before the exception.
+///
+fn unstack(
+    exc_return: u32,
+    mut addr: u32,
+    regs: &mut BTreeMap<ARMRegister, u32>,
+    mut read: impl FnMut(u32) -> Result<u32>,
+) -> Result<()> {
+    //
+    // On ARMv8-M, the callee-saved registers may have been stacked below the
+    // frame, preceded by an integrity signature and a reserved word.
+    //
+    if humility_arch_arm::exc_return_callee_stacked(exc_return) {
+        for r in 4..=11 {
+            let val = read(addr + 8 + (r - 4) * 4)?;
+            regs.insert(ARMRegister::from_u32(r).unwrap(), val);
+        }
+
+        addr += 10 * 4;
+    }
+
+    for (i, reg) in
+        humility_arch_arm::exception_frame_registers().iter().enumerate()
+    {
+        let val = read(addr + (i * 4) as u32).with_context(|| {
+            format!("failed to read exception frame at 0x{:x}", addr)
+        })?;
+        regs.insert(*reg, val);
+    }
+
+    let sp = addr
+        + humility_arch_arm::exception_frame_size(exc_return)
+        + humility_arch_arm::exception_stack_realign(regs);
+
+    regs.insert(ARMRegister::SP, sp);
+
+    Ok(())
+}
+
 #[derive(Clone, Debug)]
 pub struct HubrisStackFrame<'a> {
     pub cfa: u32,
     pub sym: Option<&'a HubrisSymbol>,
     pub registers: BTreeMap<ARMRegister, u32>,
     pub inlined: Option<Vec<HubrisInlined<'a>>>,
+    /// EXC_RETURN value, if this frame is that of an exception handler
+    pub exc_return: Option<u32>,
 }
 
 #[derive(Clone, Debug)]
@@ -6356,6 +7086,8 @@ pub struct HubrisPrintFormat {
     pub hex: bool,
     pub no_name: bool,
     pub interpret_as_c_string: bool,
+    /// levels of nesting to display before eliding contents, if limited
+    pub depth: Option<usize>,
 }
 
 impl HubrisPrintFormat {
@@ -6494,6 +7226,37 @@ impl ExternRegions {
     }
 }
 
+/// The pattern with which the kernel fills each task's stack
+const STACK_UNINITIALIZED: u32 = 0xbaddcafe;
+
+///
+/// Returns the maximum depth that a task's stack has reached, given the base
+/// (lowest address) of its stack and its initial stack pointer.  The depth
+/// is found by walking up from the base of the stack, looking for the first
+/// word that does not contain the uninitialized pattern (`0xbaddcafe`); it
+/// is only valid for the task's lifetime, and will not be correct if the
+/// task has restarted due to a stack overflow.
+///
+pub fn stack_depth(
+    core: &mut dyn crate::core::Core,
+    base: u32,
+    initial: u32,
+) -> Result<u32> {
+    let size = initial - base;
+    let mut stack = vec![0u8; size as usize];
+    core.read_8(base, &mut stack)?;
+
+    let unused = stack
+        .chunks_exact(4)
+        .take_while(|w| {
+            u32::from_le_bytes([w[0], w[1], w[2], w[3]]) == STACK_UNINITIALIZED
+        })
+        .count() as u32
+        * 4;
+
+    Ok(size - unused)
+}
+
 //
 // When looking up a type by name, it is possible that we are looking for a
 // type that is present, but more explicitly scoped than the name we're using
@@ -6501,6 +7264,21 @@ impl ExternRegions {
 // the Right Way, the Wrong Way -- and the Max Power way.  It should be no
 // surprise which way we opt for here.
 //
+///
+/// Reads the specified dump file, decompressing it if it is compressed.
+///
+pub fn read_dumpfile(dumpfile: &str) -> Result<Vec<u8>> {
+    let contents = fs::read(dumpfile)
+        .with_context(|| format!("failed to read dump {dumpfile}"))?;
+
+    if contents.starts_with(&ZSTD_MAGIC) {
+        zstd::decode_all(contents.as_slice())
+            .with_context(|| format!("failed to decompress dump {dumpfile}"))
+    } else {
+        Ok(contents)
+    }
+}
+
 fn try_scoped<'a>(
     name: &'a str,
     map: &'a MultiMap<String, HubrisGoff>,
@@ -1,9 +1,74 @@
 // This Source Code Form is subject to the terms of the Mozilla Public
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! The core of Humility, usable as a library by programs (e.g., test
+//! frameworks) that want to operate on Hubris archives and targets without
+//! running the `humility` binary and parsing its output.  The principal
+//! pieces are:
+//!
+//! - [`hubris::HubrisArchive`], which loads a Hubris archive and answers
+//!   questions about it (tasks, symbols, types, the manifest)
+//! - the [`core::Core`] trait, which abstracts over a target -- be it
+//!   attached via a debug probe, over the network, or a dump -- along with
+//!   the functions in [`core`] that attach to one
+//! - [`reflect`], which turns target memory into values that can be
+//!   examined programmatically
+//!
+//! Executing Idol calls via Hiffy is in the `humility-hiffy` crate, which
+//! builds on these.  For example, to read a variable from a target:
+//!
+//! ```ignore
+//! use humility::hubris::{HubrisArchive, HubrisArchiveDoneness};
+//! use humility::hubris::HubrisValidate;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! humility::set_output(humility::Output::Log);
+//!
+//! let mut hubris = HubrisArchive::new()?;
+//! hubris.load("build-gimlet.zip", HubrisArchiveDoneness::Cook)?;
+//!
+//! let mut core = humility::core::attach("auto", &hubris)?;
+//! hubris.validate(core.as_mut(), HubrisValidate::ArchiveMatch)?;
+//!
+//! let variable = hubris.lookup_variable("CLOCK_FREQ_KHZ")?;
+//! let ty = hubris.lookup_type(variable.goff)?;
+//! let mut buf = vec![0u8; variable.size];
+//! core.read_8(variable.addr, &mut buf)?;
+//!
+//! let value = humility::reflect::load_value(&hubris, &buf, ty, 0)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Library functions return errors rather than exiting, and do not write
+//! to stdout.  Informational messages and progress bars are written to
+//! stderr by default; use [`set_output`] to send messages to the `log`
+//! crate (or to discard them) and to suppress progress bars.
+//!
+
+pub mod arch;
+pub mod chrome;
 pub mod core;
 pub mod hubris;
 pub mod net;
+pub mod probes;
 pub mod reflect;
+pub mod remote;
+pub mod rtt;
+pub mod stats;
+pub mod timebase;
+
+pub use humility_log::{msg, output, set_output, warn, Diagnostic, Output};
 
-pub use humility_log::{msg, warn};
+///
+/// Returns a progress bar of the specified length, which is drawn only if
+/// output is to the terminal.
+///
+pub(crate) fn progress_bar(len: u64) -> indicatif::ProgressBar {
+    match output() {
+        Output::Terminal => indicatif::ProgressBar::new(len),
+        Output::Log | Output::Silent => indicatif::ProgressBar::hidden(),
+    }
+}
@@ -0,0 +1,198 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Enumeration of attached USB debug probes, and probe aliases.  An alias
+//! is a name (e.g., `gimlet-3`) that may be given anywhere a probe is
+//! expected, and that stands for a specific probe; aliases are kept as a
+//! JSON object in `~/.humility_probes`, and are managed with `humility
+//! probes`.
+//!
+
+use anyhow::{bail, Context, Result};
+use probe_rs::Probe;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// A USB debug probe that is attached to this machine
+#[derive(Clone, Debug)]
+pub struct ProbeListing {
+    /// The probe as an index (e.g., `usb-0`)
+    pub probe: String,
+    /// The name of the probe, as determined by probe-rs
+    pub name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial: Option<String>,
+    /// The firmware version, as reported in the USB device descriptor
+    pub firmware: Option<String>,
+}
+
+impl ProbeListing {
+    ///
+    /// Returns the probe in the form "vid:pid:serial" if it has a serial
+    /// number, or as its index if not.
+    ///
+    pub fn specifier(&self) -> String {
+        match self.serial {
+            Some(ref serial) => format!(
+                "{:04x}:{:04x}:{serial}",
+                self.vendor_id, self.product_id
+            ),
+            None => self.probe.clone(),
+        }
+    }
+}
+
+///
+/// Returns the firmware version of a probe from its USB device descriptor.
+/// If there are several devices with the probe's vendor and product IDs,
+/// we must find the one with its serial number.
+///
+fn probe_firmware(vid: u16, pid: u16, serial: Option<&str>) -> Option<String> {
+    let devices = rusb::devices().ok()?;
+
+    let candidates = devices
+        .iter()
+        .filter_map(|device| {
+            let desc = device.device_descriptor().ok()?;
+
+            if desc.vendor_id() == vid && desc.product_id() == pid {
+                Some((device, desc))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let (_, desc) = match (&candidates[..], serial) {
+        ([found], _) => found,
+        (_, Some(serial)) => candidates.iter().find(|(device, desc)| {
+            device
+                .open()
+                .and_then(|h| h.read_serial_number_string_ascii(desc))
+                .map_or(false, |s| s == serial)
+        })?,
+        _ => return None,
+    };
+
+    let v = desc.device_version();
+    Some(format!("{}.{}.{}", v.major(), v.minor(), v.sub_minor()))
+}
+
+/// Returns all attached USB debug probes
+pub fn list() -> Vec<ProbeListing> {
+    Probe::list_all()
+        .iter()
+        .enumerate()
+        .map(|(ndx, info)| ProbeListing {
+            probe: format!("usb-{ndx}"),
+            name: info.identifier.clone(),
+            vendor_id: info.vendor_id,
+            product_id: info.product_id,
+            serial: info.serial_number.clone(),
+            firmware: probe_firmware(
+                info.vendor_id,
+                info.product_id,
+                info.serial_number.as_deref(),
+            ),
+        })
+        .collect()
+}
+
+fn aliases_file() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(".humility_probes"))
+}
+
+/// Returns all probe aliases, mapping each alias to its probe
+pub fn aliases() -> Result<BTreeMap<String, String>> {
+    let file = match aliases_file() {
+        Some(file) if file.exists() => file,
+        _ => return Ok(BTreeMap::new()),
+    };
+
+    let contents = std::fs::read_to_string(&file)
+        .with_context(|| format!("failed to read {}", file.display()))?;
+
+    serde_json::from_str(&contents)
+        .with_context(|| format!("failed to parse {}", file.display()))
+}
+
+fn aliases_write(aliases: &BTreeMap<String, String>) -> Result<()> {
+    let file = match aliases_file() {
+        Some(file) => file,
+        None => bail!("cannot determine home directory for probe aliases"),
+    };
+
+    std::fs::write(&file, serde_json::to_string_pretty(aliases)? + "\n")
+        .with_context(|| format!("failed to write {}", file.display()))
+}
+
+///
+/// Returns true if the probe is one that we interpret directly, rather than
+/// one that names a specific USB probe.
+///
+fn builtin(probe: &str) -> bool {
+    matches!(probe, "usb" | "ocd" | "ocdgdb" | "jlink" | "auto" | "archive")
+        || probe.contains(':')
+        || probe
+            .strip_prefix("usb-")
+            .map_or(false, |ndx| ndx.parse::<usize>().is_ok())
+}
+
+///
+/// Returns the probe for which the specified probe is an alias -- or the
+/// probe itself if it isn't an alias.
+///
+pub fn resolve(probe: &str) -> Result<String> {
+    if builtin(probe) {
+        return Ok(probe.to_string());
+    }
+
+    Ok(aliases()?.remove(probe).unwrap_or_else(|| probe.to_string()))
+}
+
+///
+/// Creates (or replaces) an alias for the specified probe, which may be an
+/// index (e.g., `usb-0`), a serial number, or any other probe.  A probe
+/// that refers to a specific USB probe is stored as "vid:pid:serial", so
+/// the alias remains valid as probes come and go.
+///
+pub fn alias(name: &str, probe: &str) -> Result<String> {
+    if builtin(name) {
+        bail!("\"{name}\" is itself a probe and cannot be an alias");
+    }
+
+    let probe = if let Some(ndx) = probe.strip_prefix("usb-") {
+        let ndx = ndx.parse::<usize>().context("invalid probe index")?;
+
+        match list().get(ndx) {
+            Some(listing) if listing.serial.is_some() => listing.specifier(),
+            Some(_) => bail!("probe {probe} has no serial number"),
+            None => bail!("no probe found at {probe}"),
+        }
+    } else if builtin(probe) {
+        probe.to_string()
+    } else {
+        crate::core::usb_probe_by_serial(probe)?
+    };
+
+    let mut aliases = aliases()?;
+    aliases.insert(name.to_string(), probe.clone());
+    aliases_write(&aliases)?;
+
+    Ok(probe)
+}
+
+/// Removes an alias
+pub fn unalias(name: &str) -> Result<()> {
+    let mut aliases = aliases()?;
+
+    if aliases.remove(name).is_none() {
+        bail!("no alias named \"{name}\"");
+    }
+
+    aliases_write(&aliases)
+}
@@ -58,6 +58,10 @@
 //! indirecting it, you can use `ptr.load_from`. Otherwise, you have an address
 //! that you can print in hex or embroider on a jacket.
 //!
+//! If you would rather traverse pointers (e.g., to display a value), `follow`
+//! walks a `Value` and replaces each pointer (and each slice) that can be read
+//! with a `Ref` that contains the value pointed to.
+//!
 //! # Compatibility
 //!
 //! The derived `Load` impls do not require _exact_ type match. In particular,
@@ -119,6 +123,8 @@ pub enum Value {
     Array(Array),
     /// A pointer of some kind.
     Ptr(Ptr),
+    /// A pointer along with the value it points to, as loaded by `follow`.
+    Ref(Ref),
 }
 
 impl Value {
@@ -178,12 +184,13 @@ impl Value {
         }
     }
 
-    /// "Down-casts" this to a `Ptr`, returning an error if it isn't one.
+    /// "Down-casts" this to a `Ptr`, returning an error if it isn't one.  A
+    /// pointer that has been followed is still a pointer.
     pub fn as_ptr(&self) -> Result<&Ptr> {
-        if let Self::Ptr(s) = self {
-            Ok(s)
-        } else {
-            bail!("not a pointer: {:?}", self)
+        match self {
+            Self::Ptr(s) => Ok(s),
+            Self::Ref(r) => Ok(&r.ptr),
+            _ => bail!("not a pointer: {:?}", self),
         }
     }
 
@@ -196,13 +203,78 @@ impl Value {
         match self {
             Value::Base(..) => bail!("Cannot reassign name of base value"),
             Value::Array(..) => bail!("Cannot reassign name of array value"),
-            Value::Ptr(..) => bail!("Cannot reassign name of pointer value"),
+            Value::Ptr(..) | Value::Ref(..) => {
+                bail!("Cannot reassign name of pointer value")
+            }
             Value::Enum(e) => e.0 = name,
             Value::Struct(s) => s.name = name,
             Value::Tuple(t) => t.0 = name,
         }
         Ok(())
     }
+
+    /// Converts this to JSON for consumption by other programs.  Structs
+    /// become objects, tuples and arrays become arrays, and pointers become
+    /// their addresses (or, if followed, the values they point to).  Enums
+    /// are represented as serde would by default:  a variant without
+    /// contents becomes its name, and a variant with contents becomes an
+    /// object with the variant name as its sole key.  Integers that do not
+    /// fit in 64 bits are represented as strings.
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::Value as Json;
+
+        match self {
+            Value::Base(b) => match *b {
+                Base::U0 => Json::Null,
+                Base::Bool(x) => Json::from(x),
+                Base::U8(x) => Json::from(x),
+                Base::U16(x) => Json::from(x),
+                Base::U32(x) => Json::from(x),
+                Base::U64(x) => Json::from(x),
+                Base::U128(x) => match u64::try_from(x) {
+                    Ok(x) => Json::from(x),
+                    Err(_) => Json::from(x.to_string()),
+                },
+                Base::I8(x) => Json::from(x),
+                Base::I16(x) => Json::from(x),
+                Base::I32(x) => Json::from(x),
+                Base::I64(x) => Json::from(x),
+                Base::I128(x) => match i64::try_from(x) {
+                    Ok(x) => Json::from(x),
+                    Err(_) => Json::from(x.to_string()),
+                },
+                Base::F32(x) => Json::from(x),
+                Base::F64(x) => Json::from(x),
+            },
+            Value::Enum(e) => match e.contents() {
+                None => Json::from(e.disc()),
+                Some(c) => {
+                    //
+                    // A newtype variant (e.g., `Foo(u32)`) has a 1-tuple as
+                    // its contents; we unwrap it.
+                    //
+                    let c = c.as_1tuple().unwrap_or(c);
+                    let mut map = serde_json::Map::new();
+                    map.insert(e.disc().to_string(), c.to_json());
+                    Json::Object(map)
+                }
+            },
+            Value::Struct(s) => Json::Object(
+                s.iter().map(|(n, v)| (n.to_string(), v.to_json())).collect(),
+            ),
+            Value::Tuple(t) => {
+                Json::Array(t.iter().map(Value::to_json).collect())
+            }
+            Value::Array(a) => {
+                Json::Array(a.iter().map(Value::to_json).collect())
+            }
+            Value::Ptr(p) => Json::from(p.addr()),
+            Value::Ref(r) => match r.as_str() {
+                Some(s) => Json::from(s),
+                None => r.target.to_json(),
+            },
+        }
+    }
 }
 
 impl Format for Value {
@@ -219,6 +291,7 @@ impl Format for Value {
             Self::Tuple(s) => s.format(hubris, fmt, out),
             Self::Array(s) => s.format(hubris, fmt, out),
             Self::Ptr(s) => s.format(hubris, fmt, out),
+            Self::Ref(s) => s.format(hubris, fmt, out),
         }
     }
 }
@@ -494,9 +567,16 @@ impl Format for Struct {
             return Ok(());
         }
 
+        let print_name = if fmt.no_name { "" } else { self.name() };
+
+        if fmt.depth == Some(0) {
+            write!(out, "{} {{ .. }}", print_name)?;
+            return Ok(());
+        }
+
+        fmt.depth = fmt.depth.map(|d| d - 1);
         fmt.indent += 4;
 
-        let print_name = if fmt.no_name { "" } else { self.name() };
         write!(out, "{} {{{}", print_name, fmt.delim())?;
         fmt.no_name = false;
 
@@ -566,6 +646,27 @@ impl Format for Tuple {
 
         let paren = !self.is_empty();
 
+        //
+        // Elide contents only if there is something to elide:  a tuple
+        // struct that wraps a scalar (e.g., a newtype) is still displayed.
+        //
+        let scalar = |v: &Value| match v {
+            Value::Base(_) => true,
+            Value::Enum(e) => match e.contents() {
+                Some(Value::Struct(s)) => s.is_empty(),
+                Some(_) => false,
+                None => true,
+            },
+            _ => false,
+        };
+
+        if paren && fmt.depth == Some(0) && !self.iter().all(scalar) {
+            write!(out, "(..)")?;
+            return Ok(());
+        }
+
+        fmt.depth = fmt.depth.map(|d| d.saturating_sub(1));
+
         if paren {
             write!(out, "(")?;
         }
@@ -626,6 +727,13 @@ impl Format for Array {
         if fmt.interpret_as_c_string {
             return self.format_as_c_string(out);
         }
+
+        if fmt.depth == Some(0) && !self.is_empty() {
+            write!(out, "[..]")?;
+            return Ok(());
+        }
+
+        fmt.depth = fmt.depth.map(|d| d.saturating_sub(1));
         fmt.indent += 4;
         fmt.no_name = false;
 
@@ -706,6 +814,77 @@ impl Format for Ptr {
     }
 }
 
+/// A pointer that has been followed (or a slice whose contents have been
+/// read), along with the value that it points to.
+///
+/// The name is that of the pointer type (e.g., `&u32` or `*const u32`) or of
+/// the slice type (e.g., `&[u8]` or `&str`); the target of a slice is an
+/// `Array` of its elements.
+#[derive(Clone, Debug)]
+pub struct Ref {
+    name: String,
+    ptr: Ptr,
+    target: Box<Value>,
+}
+
+impl Ref {
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Retrieves the pointer that was followed.
+    pub fn ptr(&self) -> &Ptr {
+        &self.ptr
+    }
+
+    /// Retrieves the value that is pointed to.
+    pub fn target(&self) -> &Value {
+        &self.target
+    }
+
+    /// Interprets this as a string slice, returning its contents (lossily
+    /// converted to UTF-8) if it is one.
+    pub fn as_str(&self) -> Option<String> {
+        if self.name != "&str" && self.name != "&mut str" {
+            return None;
+        }
+
+        let bytes = self
+            .target
+            .as_array()
+            .ok()?
+            .iter()
+            .map(|v| v.as_base().ok().and_then(Base::as_u8))
+            .collect::<Option<Vec<u8>>>()?;
+
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+impl Format for Ref {
+    fn format(
+        &self,
+        hubris: &HubrisArchive,
+        fmt: HubrisPrintFormat,
+        out: &mut dyn std::io::Write,
+    ) -> Result<()> {
+        if let Some(s) = self.as_str() {
+            write!(out, "{:?}", s)?;
+            return Ok(());
+        }
+
+        if self.name.starts_with("&mut ") {
+            write!(out, "&mut ")?;
+        } else if self.name.starts_with('&') {
+            write!(out, "&")?;
+        } else {
+            write!(out, "0x{:x} -> ", self.ptr.addr())?;
+        }
+
+        self.target.format(hubris, fmt, out)
+    }
+}
+
 /// Loads data from memory image `buf` at offset `addr` and maps it onto a Rust
 /// `T`.
 pub fn load<'a, T: Load>(
@@ -918,6 +1097,158 @@ pub fn load_base(buf: &[u8], ty: &HubrisBasetype, addr: usize) -> Result<Base> {
     Ok(v)
 }
 
+/// The largest object that `follow` will read through a single pointer.
+const FOLLOW_MAXSIZE: usize = 16 * 1024;
+
+/// Walks `value`, reading through each non-null pointer (and the data pointer
+/// of each slice) via `core` and replacing it with a `Ref` that contains the
+/// value pointed to.  Pointers are followed for up to `depth` levels of
+/// nesting, and are not followed if they would form a cycle or cannot be
+/// read; such pointers are left as they are.
+pub fn follow(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    value: &Value,
+    depth: usize,
+) -> Value {
+    follow_value(hubris, core, value, depth, &mut vec![])
+}
+
+fn follow_value(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    value: &Value,
+    depth: usize,
+    seen: &mut Vec<u32>,
+) -> Value {
+    if depth == 0 {
+        return value.clone();
+    }
+
+    match value {
+        Value::Struct(s) if s.name.starts_with('&') => {
+            match follow_slice(hubris, core, s, depth, seen) {
+                Some(r) => Value::Ref(r),
+                None => value.clone(),
+            }
+        }
+        Value::Struct(s) => {
+            let mut members = IndexMap::new();
+
+            for (n, v) in &s.members {
+                let v = follow_value(hubris, core, v, depth - 1, seen);
+                members.insert(n.clone(), Box::new(v));
+            }
+
+            Value::Struct(Struct { name: s.name.clone(), members })
+        }
+        Value::Tuple(t) => {
+            let contents = t
+                .iter()
+                .map(|v| follow_value(hubris, core, v, depth - 1, seen))
+                .collect();
+
+            Value::Tuple(Tuple(t.0.clone(), contents))
+        }
+        Value::Array(a) => Value::Array(Array(
+            a.iter()
+                .map(|v| follow_value(hubris, core, v, depth - 1, seen))
+                .collect(),
+        )),
+        Value::Enum(e) => {
+            let contents = e
+                .contents()
+                .map(|c| Box::new(follow_value(hubris, core, c, depth, seen)));
+
+            Value::Enum(Enum(e.0.clone(), contents))
+        }
+        Value::Ptr(p) => match follow_ptr(hubris, core, p, depth, seen) {
+            Some(r) => Value::Ref(r),
+            None => value.clone(),
+        },
+        Value::Base(_) | Value::Ref(_) => value.clone(),
+    }
+}
+
+fn follow_ptr(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    ptr: &Ptr,
+    depth: usize,
+    seen: &mut Vec<u32>,
+) -> Option<Ref> {
+    if ptr.addr() == 0 || seen.contains(&ptr.addr()) {
+        return None;
+    }
+
+    let ty = hubris.lookup_type(ptr.dest_goff(hubris).ok()?).ok()?;
+    let size = ty.size(hubris).ok()?;
+
+    if size == 0 || size > FOLLOW_MAXSIZE {
+        return None;
+    }
+
+    let mut buf = vec![0; size];
+    core.read_8(ptr.addr(), &mut buf).ok()?;
+    let target = load_value(hubris, &buf, ty, 0).ok()?;
+
+    seen.push(ptr.addr());
+    let target = follow_value(hubris, core, &target, depth, seen);
+    seen.pop();
+
+    let name = hubris.lookup_type(ptr.ptr_goff()).ok()?.name(hubris).ok()?;
+
+    Some(Ref { name: name.to_string(), ptr: *ptr, target: Box::new(target) })
+}
+
+///
+/// Slices (including string slices) are represented as structures that
+/// contain a data pointer and a length; we read the elements of the slice
+/// into an array.
+///
+fn follow_slice(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    s: &Struct,
+    depth: usize,
+    seen: &mut Vec<u32>,
+) -> Option<Ref> {
+    let member = |name: &str| s.members.get(name).map(|v| &**v);
+    let ptr = *member("data_ptr")?.as_ptr().ok()?;
+
+    let len = match member("length")?.as_base().ok()? {
+        Base::U32(len) => *len as usize,
+        Base::U64(len) => usize::try_from(*len).ok()?,
+        _ => return None,
+    };
+
+    let ty = hubris.lookup_type(ptr.dest_goff(hubris).ok()?).ok()?;
+    let size = ty.size(hubris).ok()?;
+    let total = size.checked_mul(len)?;
+
+    if total > FOLLOW_MAXSIZE || (len != 0 && ptr.addr() == 0) {
+        return None;
+    }
+
+    let mut buf = vec![0; total];
+
+    if total != 0 {
+        core.read_8(ptr.addr(), &mut buf).ok()?;
+    }
+
+    let elements = (0..len)
+        .map(|i| load_value(hubris, &buf, ty, i * size))
+        .collect::<Result<Vec<_>>>()
+        .ok()?;
+
+    seen.push(ptr.addr());
+    let target =
+        follow_value(hubris, core, &Value::Array(Array(elements)), depth, seen);
+    seen.pop();
+
+    Some(Ref { name: s.name.clone(), ptr, target: Box::new(target) })
+}
+
 /// Trait implemented by Rust types that can construct themselves from a
 /// `Value`.
 pub trait Load: Sized {
@@ -974,6 +1305,26 @@ impl Load for u64 {
     }
 }
 
+macro_rules! base_load_impl {
+    ($ty:ident, $variant:ident) => {
+        impl Load for $ty {
+            fn from_value(v: &Value) -> Result<Self> {
+                match v.as_base()? {
+                    Base::$variant(val) => Ok(*val),
+                    _ => bail!("not a {}: {:?}", stringify!($ty), v),
+                }
+            }
+        }
+    };
+}
+
+base_load_impl!(i8, I8);
+base_load_impl!(i16, I16);
+base_load_impl!(i32, I32);
+base_load_impl!(i64, I64);
+base_load_impl!(f32, F32);
+base_load_impl!(f64, F64);
+
 impl<T: Load> Load for Option<T> {
     fn from_value(v: &Value) -> Result<Self> {
         Ok(match v.as_enum()?.as_option()? {
@@ -0,0 +1,670 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Support for operating on a target that is attached to a remote machine.
+//! The remote machine runs Humility as a server (`humility probe --serve`)
+//! that exports its attached core; the local machine then attaches to it
+//! with a probe of the form `remote:host[:port]`, yielding a [`RemoteCore`].
+//!
+//! The protocol is deliberately simple:  each request and each response is
+//! a single line of JSON, and requests are serviced in order.  Byte buffers
+//! are encoded as hexadecimal strings.
+//!
+
+use crate::arch::{Arch, ARM, RISCV};
+use crate::core::{Core, CORE_MAX_READSIZE};
+use anyhow::{anyhow, bail, Context, Result};
+use humility_arch_arm::ARMRegister;
+use num_traits::{FromPrimitive, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// The port that we serve on (and connect to) if none is specified
+pub const REMOTE_DEFAULT_PORT: u16 = 7878;
+
+/// The version of our protocol, checked upon connection
+const REMOTE_PROTOCOL_VERSION: u32 = 2;
+
+mod hexdata {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        data: &[u8],
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        s.serialize_str(
+            &data.iter().map(|b| format!("{b:02x}")).collect::<String>(),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(d)?;
+
+        if s.len() % 2 != 0 {
+            return Err(serde::de::Error::custom("odd-length hex string"));
+        }
+
+        (0..s.len())
+            .step_by(2)
+            .map(|i| {
+                u8::from_str_radix(&s[i..i + 2], 16)
+                    .map_err(serde::de::Error::custom)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Request {
+    Hello {
+        version: u32,
+    },
+    Read8 {
+        addr: u32,
+        len: usize,
+    },
+    ReadWord32 {
+        addr: u32,
+    },
+    ReadReg {
+        reg: u16,
+    },
+    WriteReg {
+        reg: u16,
+        value: u32,
+    },
+    ReadRegId {
+        id: u16,
+    },
+    WriteRegId {
+        id: u16,
+        value: u32,
+    },
+    WriteWord32 {
+        addr: u32,
+        data: u32,
+    },
+    Write8 {
+        addr: u32,
+        #[serde(with = "hexdata")]
+        data: Vec<u8>,
+    },
+    Halt,
+    Run,
+    Step,
+    InitSwv,
+    ReadSwv,
+    Load {
+        #[serde(with = "hexdata")]
+        elf: Vec<u8>,
+    },
+    Reset,
+    ResetAndHalt {
+        ms: u64,
+    },
+    WaitForHalt {
+        ms: u64,
+    },
+    OpStart,
+    OpDone,
+    VectorCatch {
+        mask: u32,
+    },
+    SetReadCache {
+        enabled: bool,
+    },
+    SelectCore {
+        index: usize,
+        halt_all: bool,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Response {
+    Hello {
+        version: u32,
+        info: (String, Option<String>),
+        vid_pid: Option<(u16, u16)>,
+
+        // These are defaulted so that a server speaking an older version
+        // of the protocol fails on the version rather than on its response.
+        #[serde(default)]
+        arch: String,
+        #[serde(default)]
+        ncores: usize,
+        #[serde(default)]
+        core_index: usize,
+        #[serde(default)]
+        dry_run: bool,
+    },
+    Ok,
+    Word(u32),
+    Data(#[serde(with = "hexdata")] Vec<u8>),
+    Error(String),
+}
+
+fn send<T: Serialize>(stream: &mut TcpStream, msg: &T) -> Result<()> {
+    let mut line = serde_json::to_string(msg)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn recv<T: for<'de> Deserialize<'de>>(
+    reader: &mut BufReader<TcpStream>,
+) -> Result<Option<T>> {
+    let mut line = String::new();
+
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::from_str(&line)?))
+}
+
+pub struct RemoteCore {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    host: String,
+    info: (String, Option<String>),
+    vid_pid: Option<(u16, u16)>,
+    arch: &'static dyn Arch,
+    ncores: usize,
+    core_index: usize,
+    dry_run: bool,
+}
+
+#[rustfmt::skip::macros(anyhow, bail)]
+impl RemoteCore {
+    ///
+    /// Connects to a remote Humility, where `remote` is of the form
+    /// `host[:port]`.
+    ///
+    pub fn new(remote: &str) -> Result<RemoteCore> {
+        let host = if remote.contains(':') && !remote.ends_with(']') {
+            remote.to_string()
+        } else {
+            format!("{remote}:{REMOTE_DEFAULT_PORT}")
+        };
+
+        let addr = host
+            .to_socket_addrs()
+            .with_context(|| format!("failed to resolve \"{host}\""))?
+            .next()
+            .ok_or_else(|| anyhow!("no addresses for \"{host}\""))?;
+
+        let timeout = Duration::from_secs(5);
+
+        let stream =
+            TcpStream::connect_timeout(&addr, timeout).map_err(|err| {
+                anyhow!("can't connect to {host} ({err}); \
+                    is `humility probe --serve` running there?")
+            })?;
+
+        stream.set_nodelay(true)?;
+
+        let reader = BufReader::new(stream.try_clone()?);
+
+        let mut core = Self {
+            stream,
+            reader,
+            host,
+            info: (String::new(), None),
+            vid_pid: None,
+            arch: &ARM,
+            ncores: 1,
+            core_index: 0,
+            dry_run: false,
+        };
+
+        match core.call(Request::Hello { version: REMOTE_PROTOCOL_VERSION })? {
+            Response::Hello {
+                version,
+                info,
+                vid_pid,
+                arch,
+                ncores,
+                core_index,
+                dry_run,
+            } => {
+                if version != REMOTE_PROTOCOL_VERSION {
+                    bail!("{} speaks protocol version {version}; \
+                        expected {REMOTE_PROTOCOL_VERSION}", core.host);
+                }
+
+                core.arch = [&ARM as &'static dyn Arch, &RISCV]
+                    .into_iter()
+                    .find(|a| a.name() == arch)
+                    .ok_or_else(|| {
+                        anyhow!("{} has unknown architecture \
+                        \"{arch}\"", core.host)
+                    })?;
+
+                core.info = info;
+                core.vid_pid = vid_pid;
+                core.ncores = ncores;
+                core.core_index = core_index;
+                core.dry_run = dry_run;
+            }
+            r => bail!("unexpected response to hello: {r:?}"),
+        }
+
+        Ok(core)
+    }
+
+    fn call(&mut self, request: Request) -> Result<Response> {
+        send(&mut self.stream, &request)?;
+
+        match recv(&mut self.reader)? {
+            Some(Response::Error(err)) => Err(anyhow!("{}: {err}", self.host)),
+            Some(response) => Ok(response),
+            None => bail!("connection to {} closed", self.host),
+        }
+    }
+
+    fn call_ok(&mut self, request: Request) -> Result<()> {
+        match self.call(request)? {
+            Response::Ok => Ok(()),
+            r => bail!("unexpected response: {r:?}"),
+        }
+    }
+
+    fn call_word(&mut self, request: Request) -> Result<u32> {
+        match self.call(request)? {
+            Response::Word(val) => Ok(val),
+            r => bail!("unexpected response: {r:?}"),
+        }
+    }
+
+    fn call_data(&mut self, request: Request) -> Result<Vec<u8>> {
+        match self.call(request)? {
+            Response::Data(data) => Ok(data),
+            r => bail!("unexpected response: {r:?}"),
+        }
+    }
+}
+
+#[rustfmt::skip::macros(anyhow, bail)]
+impl Core for RemoteCore {
+    fn info(&self) -> (String, Option<String>) {
+        (format!("{} on {}", self.info.0, self.host), self.info.1.clone())
+    }
+
+    fn vid_pid(&self) -> Option<(u16, u16)> {
+        self.vid_pid
+    }
+
+    fn arch(&self) -> &'static dyn Arch {
+        self.arch
+    }
+
+    fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    fn read_word_32(&mut self, addr: u32) -> Result<u32> {
+        self.call_word(Request::ReadWord32 { addr })
+    }
+
+    fn read_8(&mut self, addr: u32, data: &mut [u8]) -> Result<()> {
+        if data.len() > CORE_MAX_READSIZE {
+            bail!("read of {} bytes at 0x{:x} exceeds max of {}",
+                data.len(), addr, CORE_MAX_READSIZE);
+        }
+
+        let rdata = self.call_data(Request::Read8 { addr, len: data.len() })?;
+
+        if rdata.len() != data.len() {
+            bail!("short read at {addr:#x}: {} of {} bytes",
+                rdata.len(), data.len());
+        }
+
+        data.copy_from_slice(&rdata);
+        Ok(())
+    }
+
+    fn read_reg(&mut self, reg: ARMRegister) -> Result<u32> {
+        let reg = reg.to_u16().unwrap();
+        self.call_word(Request::ReadReg { reg })
+    }
+
+    fn write_reg(&mut self, reg: ARMRegister, value: u32) -> Result<()> {
+        let reg = reg.to_u16().unwrap();
+        self.call_ok(Request::WriteReg { reg, value })
+    }
+
+    fn read_reg_id(&mut self, id: u16) -> Result<u32> {
+        self.call_word(Request::ReadRegId { id })
+    }
+
+    fn write_reg_id(&mut self, id: u16, value: u32) -> Result<()> {
+        self.call_ok(Request::WriteRegId { id, value })
+    }
+
+    fn write_word_32(&mut self, addr: u32, data: u32) -> Result<()> {
+        self.call_ok(Request::WriteWord32 { addr, data })
+    }
+
+    fn write_8(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        self.call_ok(Request::Write8 { addr, data: data.to_vec() })
+    }
+
+    fn halt(&mut self) -> Result<()> {
+        self.call_ok(Request::Halt)
+    }
+
+    fn run(&mut self) -> Result<()> {
+        self.call_ok(Request::Run)
+    }
+
+    fn step(&mut self) -> Result<()> {
+        self.call_ok(Request::Step)
+    }
+
+    fn init_swv(&mut self) -> Result<()> {
+        self.call_ok(Request::InitSwv)
+    }
+
+    fn read_swv(&mut self) -> Result<Vec<u8>> {
+        self.call_data(Request::ReadSwv)
+    }
+
+    fn load(&mut self, path: &Path) -> Result<()> {
+        let elf = std::fs::read(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+
+        self.call_ok(Request::Load { elf })
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.call_ok(Request::Reset)
+    }
+
+    fn reset_and_halt(&mut self, dur: Duration) -> Result<()> {
+        self.call_ok(Request::ResetAndHalt { ms: dur.as_millis() as u64 })
+    }
+
+    fn op_start(&mut self) -> Result<()> {
+        self.call_ok(Request::OpStart)
+    }
+
+    fn op_done(&mut self) -> Result<()> {
+        self.call_ok(Request::OpDone)
+    }
+
+    fn wait_for_halt(&mut self, dur: Duration) -> Result<()> {
+        self.call_ok(Request::WaitForHalt { ms: dur.as_millis() as u64 })
+    }
+
+    fn vector_catch(&mut self, mask: u32) -> Result<()> {
+        self.call_ok(Request::VectorCatch { mask })
+    }
+
+    fn set_read_cache(&mut self, enabled: bool) {
+        if let Err(err) = self.call_ok(Request::SetReadCache { enabled }) {
+            crate::warn!("failed to set read cache: {err:#}");
+        }
+    }
+
+    fn ncores(&self) -> usize {
+        self.ncores
+    }
+
+    fn core_index(&self) -> usize {
+        self.core_index
+    }
+
+    fn select_core(&mut self, index: usize, halt_all: bool) -> Result<()> {
+        self.call_ok(Request::SelectCore { index, halt_all })?;
+        self.core_index = index;
+        Ok(())
+    }
+}
+
+///
+/// Services a single request against our core.  `halts` tracks the depth of
+/// halts requested by the client, allowing us to undo them should the client
+/// disconnect with the target halted.
+///
+fn service(
+    core: &mut dyn Core,
+    request: Request,
+    halts: &mut u32,
+) -> Result<Response> {
+    let reg = |reg: u16| {
+        ARMRegister::from_u16(reg)
+            .ok_or_else(|| anyhow!("invalid register {reg}"))
+    };
+
+    Ok(match request {
+        Request::Hello { .. } => Response::Hello {
+            version: REMOTE_PROTOCOL_VERSION,
+            info: core.info(),
+            vid_pid: core.vid_pid(),
+            arch: core.arch().name().to_string(),
+            ncores: core.ncores(),
+            core_index: core.core_index(),
+            dry_run: core.is_dry_run(),
+        },
+        Request::Read8 { addr, len } => {
+            //
+            // The length comes from our client; don't let it have us
+            // allocate an arbitrarily large buffer.
+            //
+            if len > CORE_MAX_READSIZE {
+                bail!(
+                    "read of {len} bytes at 0x{addr:x} exceeds max of \
+                    {CORE_MAX_READSIZE}"
+                );
+            }
+
+            let mut data = vec![0; len];
+            core.read_8(addr, &mut data)?;
+            Response::Data(data)
+        }
+        Request::ReadWord32 { addr } => {
+            Response::Word(core.read_word_32(addr)?)
+        }
+        Request::ReadReg { reg: r } => Response::Word(core.read_reg(reg(r)?)?),
+        Request::WriteReg { reg: r, value } => {
+            core.write_reg(reg(r)?, value)?;
+            Response::Ok
+        }
+        Request::ReadRegId { id } => Response::Word(core.read_reg_id(id)?),
+        Request::WriteRegId { id, value } => {
+            core.write_reg_id(id, value)?;
+            Response::Ok
+        }
+        Request::WriteWord32 { addr, data } => {
+            core.write_word_32(addr, data)?;
+            Response::Ok
+        }
+        Request::Write8 { addr, data } => {
+            core.write_8(addr, &data)?;
+            Response::Ok
+        }
+        Request::Halt => {
+            core.halt()?;
+            *halts += 1;
+            Response::Ok
+        }
+        Request::Run => {
+            core.run()?;
+            *halts = halts.saturating_sub(1);
+            Response::Ok
+        }
+        Request::Step => {
+            core.step()?;
+            Response::Ok
+        }
+        Request::InitSwv => {
+            core.init_swv()?;
+            Response::Ok
+        }
+        Request::ReadSwv => Response::Data(core.read_swv()?),
+        Request::Load { elf } => {
+            let path = std::env::temp_dir()
+                .join(format!("humility-remote-{}.elf", std::process::id()));
+
+            std::fs::write(&path, elf)?;
+            let rval = core.load(&path);
+            std::fs::remove_file(&path)?;
+            rval?;
+            Response::Ok
+        }
+        Request::Reset => {
+            core.reset()?;
+            Response::Ok
+        }
+        Request::ResetAndHalt { ms } => {
+            core.reset_and_halt(Duration::from_millis(ms))?;
+            Response::Ok
+        }
+        Request::WaitForHalt { ms } => {
+            core.wait_for_halt(Duration::from_millis(ms))?;
+            *halts += 1;
+            Response::Ok
+        }
+        Request::OpStart => {
+            core.op_start()?;
+            Response::Ok
+        }
+        Request::OpDone => {
+            core.op_done()?;
+            Response::Ok
+        }
+        Request::VectorCatch { mask } => {
+            core.vector_catch(mask)?;
+            Response::Ok
+        }
+        Request::SetReadCache { enabled } => {
+            core.set_read_cache(enabled);
+            Response::Ok
+        }
+        Request::SelectCore { index, halt_all } => {
+            core.select_core(index, halt_all)?;
+            Response::Ok
+        }
+    })
+}
+
+/// How often a server blocked on its client (or awaiting one) checks whether
+/// it has been interrupted
+const REMOTE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn serve_client(
+    core: &mut dyn Core,
+    stream: TcpStream,
+    done: &AtomicBool,
+) -> Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REMOTE_POLL_INTERVAL))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+    let mut halts = 0;
+    let mut line = String::new();
+
+    let rval = loop {
+        if done.load(Ordering::SeqCst) {
+            break Ok(());
+        }
+
+        //
+        // A read that times out leaves whatever it has read thus far in
+        // our line, so we can simply pick up where we left off.
+        //
+        match reader.read_line(&mut line) {
+            Ok(0) => break Ok(()),
+            Ok(_) => {}
+            Err(err)
+                if err.kind() == std::io::ErrorKind::WouldBlock
+                    || err.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                continue;
+            }
+            Err(err) => break Err(err.into()),
+        }
+
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => request,
+            Err(err) => break Err(err.into()),
+        };
+
+        line.clear();
+
+        log::trace!("remote request: {request:?}");
+
+        let response = match service(core, request, &mut halts) {
+            Ok(response) => response,
+            Err(err) => Response::Error(format!("{err:#}")),
+        };
+
+        if let Err(err) = send(&mut stream, &response) {
+            break Err(err);
+        }
+    };
+
+    //
+    // If our client left the target halted, we resume it.
+    //
+    for _ in 0..halts {
+        core.run()?;
+    }
+
+    rval
+}
+
+///
+/// Serves the specified core to remote Humility instances at the specified
+/// address, servicing one client at a time.  There is no authentication:
+/// anyone who can connect can read and write the target's memory and
+/// registers, halt and reset it, and flash it.  This returns when `done` is
+/// set (e.g., by ^C) or upon an error in accepting connections.
+///
+pub fn serve(core: &mut dyn Core, addr: &str, done: &AtomicBool) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .with_context(|| format!("failed to bind to {addr}"))?;
+    let local = listener.local_addr()?;
+
+    listener.set_nonblocking(true)?;
+
+    crate::msg!("serving {} on {local}", core.info().0);
+
+    if !local.ip().is_loopback() {
+        crate::warn!(
+            "{local} is not a loopback address; anyone who can reach it \
+            can modify the target"
+        );
+    }
+
+    while !done.load(Ordering::SeqCst) {
+        let (stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(REMOTE_POLL_INTERVAL);
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        crate::msg!("accepted connection from {peer}");
+
+        match serve_client(core, stream, done) {
+            Ok(()) => crate::msg!("{peer} disconnected"),
+            Err(err) => crate::warn!("{peer} disconnected: {err:#}"),
+        }
+    }
+
+    crate::msg!("no longer serving on {local}");
+    Ok(())
+}
@@ -0,0 +1,228 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! SEGGER's Real-Time Transfer (RTT) protocol, in which the target writes
+//! into ring buffers in its own RAM that the debugger reads from (and, for
+//! input, writes into).  The ring buffers are described by a control block,
+//! which is located via the `_SEGGER_RTT` symbol.
+//!
+
+use crate::core::Core;
+use crate::hubris::HubrisArchive;
+use crate::Diagnostic;
+use anyhow::{bail, Result};
+
+//
+// The control block consists of a 16-byte identifier, the number of up and
+// down buffers, and then the descriptors for the up buffers followed by
+// those for the down buffers.
+//
+const RTT_ID: &[u8] = b"SEGGER RTT";
+const RTT_HEADER_SIZE: u32 = 24;
+const RTT_MAX_BUFFERS: u32 = 32;
+const RTT_MAX_NAME: usize = 32;
+
+//
+// Each buffer descriptor consists of pointers to the name and to the ring
+// buffer, the size of the ring buffer, the write and read offsets, and
+// flags.
+//
+const RTT_BUFFER_SIZE: u32 = 24;
+const RTT_BUFFER_WRITE: u32 = 12;
+const RTT_BUFFER_READ: u32 = 16;
+
+/// A ring buffer descriptor, for either an up or a down channel
+#[derive(Debug)]
+pub struct RttBuffer {
+    addr: u32,
+    pub name: String,
+    buffer: u32,
+    pub size: u32,
+    flags: u32,
+}
+
+impl RttBuffer {
+    pub fn read(core: &mut dyn Core, addr: u32) -> Result<Self> {
+        let mut desc = [0u8; RTT_BUFFER_SIZE as usize];
+        core.read_8(addr, &mut desc)?;
+
+        let word = |offs: usize| {
+            u32::from_le_bytes(desc[offs..offs + 4].try_into().unwrap())
+        };
+
+        let name = match word(0) {
+            0 => String::new(),
+            addr => {
+                let mut name = [0u8; RTT_MAX_NAME];
+                core.read_8(addr, &mut name)?;
+                let len =
+                    name.iter().position(|&c| c == 0).unwrap_or(name.len());
+                String::from_utf8_lossy(&name[..len]).to_string()
+            }
+        };
+
+        Ok(Self { addr, name, buffer: word(4), size: word(8), flags: word(20) })
+    }
+
+    pub fn mode(&self) -> &'static str {
+        match self.flags & 0b11 {
+            0 => "no-block-skip",
+            1 => "no-block-trim",
+            2 => "block",
+            _ => "<invalid>",
+        }
+    }
+
+    ///
+    /// Returns the write and read offsets, failing if either is outside of
+    /// the ring buffer.
+    ///
+    fn offsets(&self, core: &mut dyn Core) -> Result<(u32, u32)> {
+        let write = core.read_word_32(self.addr + RTT_BUFFER_WRITE)?;
+        let read = core.read_word_32(self.addr + RTT_BUFFER_READ)?;
+
+        if write >= self.size || read >= self.size {
+            bail!(
+                "RTT buffer {} has invalid offsets \
+                (write {write}, read {read}, size {})",
+                self.name,
+                self.size
+            );
+        }
+
+        Ok((write, read))
+    }
+
+    ///
+    /// Drains an up buffer, returning any data that the target had written.
+    ///
+    pub fn drain(&self, core: &mut dyn Core) -> Result<Vec<u8>> {
+        let (write, read) = self.offsets(core)?;
+
+        let mut data = vec![];
+
+        let mut copy = |from: u32, to: u32| -> Result<()> {
+            let mut chunk = vec![0u8; (to - from) as usize];
+            core.read_8(self.buffer + from, &mut chunk)?;
+            data.extend(chunk);
+            Ok(())
+        };
+
+        if write >= read {
+            copy(read, write)?;
+        } else {
+            copy(read, self.size)?;
+            copy(0, write)?;
+        }
+
+        if write != read {
+            core.write_word_32(self.addr + RTT_BUFFER_READ, write)?;
+        }
+
+        Ok(data)
+    }
+
+    ///
+    /// Writes as much of the data as will fit into a down buffer, returning
+    /// the number of bytes written.
+    ///
+    pub fn fill(&self, core: &mut dyn Core, data: &[u8]) -> Result<usize> {
+        let (write, read) = self.offsets(core)?;
+
+        //
+        // One byte is always left empty, as equal offsets denote an empty
+        // buffer.
+        //
+        let free = if read > write {
+            read - write - 1
+        } else {
+            self.size - write + read - 1
+        };
+
+        let len = data.len().min(free as usize);
+
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let first = len.min((self.size - write) as usize);
+
+        core.write_8(self.buffer + write, &data[..first])?;
+
+        if len > first {
+            core.write_8(self.buffer, &data[first..len])?;
+        }
+
+        let write = (write + len as u32) % self.size;
+        core.write_word_32(self.addr + RTT_BUFFER_WRITE, write)?;
+
+        Ok(len)
+    }
+}
+
+/// The RTT control block, consisting of the up and down channels
+pub struct RttControlBlock {
+    pub up: Vec<RttBuffer>,
+    pub down: Vec<RttBuffer>,
+}
+
+impl RttControlBlock {
+    pub fn read(core: &mut dyn Core, addr: u32) -> Result<Self> {
+        let mut header = [0u8; RTT_HEADER_SIZE as usize];
+        core.read_8(addr, &mut header)?;
+
+        if !header.starts_with(RTT_ID) {
+            bail!(
+                "no RTT control block at 0x{addr:x}; has the target \
+                initialized RTT?"
+            );
+        }
+
+        let nup = u32::from_le_bytes(header[16..20].try_into().unwrap());
+        let ndown = u32::from_le_bytes(header[20..24].try_into().unwrap());
+
+        if nup > RTT_MAX_BUFFERS || ndown > RTT_MAX_BUFFERS {
+            bail!("RTT control block has {nup} up and {ndown} down buffers");
+        }
+
+        let mut up = vec![];
+        let mut down = vec![];
+        let mut desc = addr + RTT_HEADER_SIZE;
+
+        for ndx in 0..nup + ndown {
+            let buffer = RttBuffer::read(core, desc)?;
+
+            if ndx < nup {
+                up.push(buffer);
+            } else {
+                down.push(buffer);
+            }
+
+            desc += RTT_BUFFER_SIZE;
+        }
+
+        Ok(Self { up, down })
+    }
+}
+
+///
+/// Returns the address of the RTT control block:  the specified address if
+/// there is one, or the address of the `_SEGGER_RTT` symbol in the archive.
+///
+pub fn address(hubris: &HubrisArchive, address: Option<u32>) -> Result<u32> {
+    if let Some(address) = address {
+        return Ok(address);
+    }
+
+    match hubris.lookup_symbols("_SEGGER_RTT")[..] {
+        [(_, addr, _)] => Ok(addr),
+        [] => bail!(Diagnostic::error("no RTT control block in archive")
+            .code("no-rtt")
+            .hint("use --address to specify the control block")),
+        _ => bail!(Diagnostic::error("multiple RTT control blocks in archive")
+            .code("ambiguous-rtt")
+            .hint("use --address to specify the control block")),
+    }
+}
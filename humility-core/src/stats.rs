@@ -0,0 +1,153 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Accounting of the impact that a command has had on the target:  bytes
+//! read from and written to it, HIF programs executed on it, and the time
+//! that its core has spent halted at our behest.  These are global (rather
+//! than per-core) because they are accumulated in several places, some of
+//! which (e.g., halting a probe-attached core to perform a read) are not
+//! visible through the [`Core`](crate::core::Core) interface.  The time
+//! spent reading and writing is also accounted for, allowing the throughput
+//! of the debug transport to be determined.
+//!
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+static BYTES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+static HIFFY_RUNS: AtomicU64 = AtomicU64::new(0);
+static HALTED_NS: AtomicU64 = AtomicU64::new(0);
+static READING_NS: AtomicU64 = AtomicU64::new(0);
+static WRITING_NS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Stats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub hiffy_runs: u64,
+    pub halted: Duration,
+    pub reading: Duration,
+    pub writing: Duration,
+}
+
+/// Records that the specified number of bytes has been read from the target
+pub fn read(nbytes: usize) {
+    BYTES_READ.fetch_add(nbytes as u64, Ordering::Relaxed);
+}
+
+/// Records that the specified number of bytes has been written to the target
+pub fn written(nbytes: usize) {
+    BYTES_WRITTEN.fetch_add(nbytes as u64, Ordering::Relaxed);
+}
+
+/// Records the execution of a HIF program
+pub fn hiffy_run() {
+    HIFFY_RUNS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records that the target was halted for the specified duration
+pub fn halted(duration: Duration) {
+    HALTED_NS.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// Records that the specified duration was spent reading from the target
+pub fn reading(duration: Duration) {
+    READING_NS.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// Records that the specified duration was spent writing to the target
+pub fn writing(duration: Duration) {
+    WRITING_NS.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+}
+
+///
+/// Returns the statistics accumulated since the last call to `take`,
+/// resetting them.
+///
+pub fn take() -> Stats {
+    Stats {
+        bytes_read: BYTES_READ.swap(0, Ordering::Relaxed),
+        bytes_written: BYTES_WRITTEN.swap(0, Ordering::Relaxed),
+        hiffy_runs: HIFFY_RUNS.swap(0, Ordering::Relaxed),
+        halted: Duration::from_nanos(HALTED_NS.swap(0, Ordering::Relaxed)),
+        reading: Duration::from_nanos(READING_NS.swap(0, Ordering::Relaxed)),
+        writing: Duration::from_nanos(WRITING_NS.swap(0, Ordering::Relaxed)),
+    }
+}
+
+fn bytes(f: &mut fmt::Formatter<'_>, nbytes: u64) -> fmt::Result {
+    match nbytes {
+        n if n < 1024 => write!(f, "{n} bytes"),
+        n if n < 1024 * 1024 => write!(f, "{:.1}KiB", n as f64 / 1024.0),
+        n => write!(f, "{:.1}MiB", n as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+///
+/// Returns the rate at which the specified number of bytes was transferred
+/// over the specified duration, formatted for human consumption.
+///
+pub fn rate(nbytes: u64, duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+
+    if secs == 0.0 {
+        return "-".to_string();
+    }
+
+    match nbytes as f64 / secs {
+        r if r < 1024.0 => format!("{r:.1} bytes/s"),
+        r if r < 1024.0 * 1024.0 => format!("{:.1}KiB/s", r / 1024.0),
+        r => format!("{:.1}MiB/s", r / (1024.0 * 1024.0)),
+    }
+}
+
+impl Stats {
+    ///
+    /// Returns a description of the throughput of the debug transport, as
+    /// determined by the time spent reading and writing.
+    ///
+    pub fn throughput(&self) -> String {
+        let mut rval = vec![];
+
+        if self.bytes_read != 0 {
+            rval.push(format!(
+                "reads at {}",
+                rate(self.bytes_read, self.reading)
+            ));
+        }
+
+        if self.bytes_written != 0 {
+            rval.push(format!(
+                "writes at {}",
+                rate(self.bytes_written, self.writing)
+            ));
+        }
+
+        if rval.is_empty() {
+            "no transfers".to_string()
+        } else {
+            rval.join(", ")
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "read ")?;
+        bytes(f, self.bytes_read)?;
+        write!(f, ", wrote ")?;
+        bytes(f, self.bytes_written)?;
+
+        write!(
+            f,
+            "; {} HIF execution{}; core halted for {:.3}s",
+            self.hiffy_runs,
+            if self.hiffy_runs == 1 { "" } else { "s" },
+            self.halted.as_secs_f64()
+        )
+    }
+}
@@ -0,0 +1,89 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! A common timebase for output that is gathered at different times and by
+//! different means (e.g., ITM packets that are timestamped by the host as
+//! they are ingested, or ring buffers that are read from the target).  The
+//! timebase is anchored by reading the kernel's tick count (milliseconds
+//! since boot) and -- for a live target -- noting the host's wall clock at
+//! the same moment; times can then be expressed in both kernel ticks and
+//! wall clock time, allowing output from different commands to be
+//! interleaved into a single timeline.
+//!
+
+use crate::core::Core;
+use crate::hubris::HubrisArchive;
+use anyhow::Result;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Timebase {
+    ticks: u64,
+    wallclock: Option<SystemTime>,
+}
+
+/// A time expressed in kernel ticks and (if known) wall clock time
+#[derive(Copy, Clone, Debug)]
+pub struct Timestamp {
+    pub ticks: u64,
+    pub wallclock: Option<SystemTime>,
+}
+
+impl Timebase {
+    ///
+    /// Anchors a timebase to the current time on the target.  For a dump,
+    /// we know the kernel's tick count but not the wall clock time at which
+    /// the dump was taken.
+    ///
+    pub fn new(hubris: &HubrisArchive, core: &mut dyn Core) -> Result<Self> {
+        let ticks = hubris.ticks(core)?;
+        let wallclock = if core.is_dump() || core.is_archive() {
+            None
+        } else {
+            Some(SystemTime::now())
+        };
+
+        Ok(Self { ticks, wallclock })
+    }
+
+    /// Returns the anchoring time itself
+    pub fn now(&self) -> Timestamp {
+        Timestamp { ticks: self.ticks, wallclock: self.wallclock }
+    }
+
+    /// Returns the time that is `elapsed` seconds after the anchoring time
+    pub fn elapsed(&self, elapsed: f64) -> Timestamp {
+        let elapsed = Duration::from_secs_f64(elapsed.max(0.0));
+
+        Timestamp {
+            ticks: self.ticks + elapsed.as_millis() as u64,
+            wallclock: self.wallclock.map(|w| w + elapsed),
+        }
+    }
+}
+
+///
+/// A timebase can be reconstituted from its anchoring time, allowing output
+/// that was captured with a timebase to be processed later.
+///
+impl From<Timestamp> for Timebase {
+    fn from(t: Timestamp) -> Self {
+        Self { ticks: t.ticks, wallclock: t.wallclock }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>10}", self.ticks)?;
+
+        match self.wallclock.and_then(|w| w.duration_since(UNIX_EPOCH).ok()) {
+            Some(d) => {
+                write!(f, " {}.{:03}", d.as_secs(), d.subsec_millis())
+            }
+            None => write!(f, " {:>14}", "-"),
+        }
+    }
+}
@@ -34,7 +34,10 @@
 //! interpret.
 
 use anyhow::{anyhow, bail, Result};
-use humility::reflect::{Load, Ptr, Value};
+use humility::core::Core;
+use humility::hubris::{HubrisArchive, HubrisPrintFormat, HubrisStruct};
+use humility::reflect::{self, Format, Load, Ptr, Value};
+use humility_arch_arm::ARMRegister;
 use std::convert::TryInto;
 use zerocopy::{AsBytes, LittleEndian, U16, U64};
 
@@ -140,6 +143,82 @@ pub enum FaultInfo {
     FromServer(TaskId, ReplyFaultReason),
 }
 
+impl FaultInfo {
+    ///
+    /// Describes the fault, as displayed by `humility tasks`.  For a panic,
+    /// the message is read from the task's memory, as denoted by its R4
+    /// (base) and R5 (length).
+    ///
+    pub fn describe(
+        &self,
+        hubris: &HubrisArchive,
+        core: &mut dyn Core,
+        reg: impl Fn(ARMRegister) -> Option<u32>,
+    ) -> Result<String> {
+        let source = |source: &FaultSource| match source {
+            FaultSource::User => " in task code",
+            FaultSource::Kernel => " in syscall",
+        };
+
+        let address = |address: &Option<u32>| match address {
+            Some(addr) => format!("precise: 0x{:x}", addr),
+            None => "imprecise".to_string(),
+        };
+
+        Ok(match self {
+            FaultInfo::DivideByZero => "divide by zero".to_string(),
+            FaultInfo::IllegalText => "jump to non-executable mem".to_string(),
+            FaultInfo::IllegalInstruction => "illegal instruction".to_string(),
+            FaultInfo::InvalidOperation(bits) => {
+                format!("general fault, cfsr=0x{:x}", bits)
+            }
+            FaultInfo::StackOverflow { address } => {
+                format!("stack overflow; sp=0x{:x}", address)
+            }
+            FaultInfo::Injected(task) => {
+                format!("killed by {}", task.describe(hubris))
+            }
+            FaultInfo::MemoryAccess { address: a, source: s } => {
+                format!("mem fault ({}){}", address(a), source(s))
+            }
+            FaultInfo::BusError { address: a, source: s } => {
+                format!("bus fault ({}){}", address(a), source(s))
+            }
+            FaultInfo::SyscallUsage(ue) => format!(
+                "in syscall: {}",
+                match ue {
+                    UsageError::BadSyscallNumber => "undefined syscall number",
+                    UsageError::InvalidSlice => {
+                        "sent malformed slice to kernel"
+                    }
+                    UsageError::TaskOutOfRange => "used bogus task index",
+                    UsageError::IllegalTask => "illegal task operation",
+                    UsageError::LeaseOutOfRange => "bad caller lease index",
+                    UsageError::OffsetOutOfRange => "bad caller lease offset",
+                    UsageError::NoIrq => "referred to undefined interrupt",
+                    UsageError::BadKernelMessage => {
+                        "sent nonsense IPC to kernel"
+                    }
+                }
+            ),
+            FaultInfo::Panic => {
+                let base = reg(ARMRegister::R4).unwrap_or(0);
+                let len = reg(ARMRegister::R5).unwrap_or(0);
+                let mut buf = vec![0; len.min(255) as usize];
+                core.read_8(base, &mut buf)?;
+
+                match std::str::from_utf8(&buf) {
+                    Ok(msg) => msg.to_string(),
+                    Err(_) => "panic with invalid message".to_string(),
+                }
+            }
+            FaultInfo::FromServer(task_id, reason) => {
+                format!("reply fault: task id {}, reason {:?}", task_id, reason)
+            }
+        })
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
 pub enum UsageError {
     /// A program used an undefined syscall number.
@@ -247,6 +326,16 @@ impl TaskId {
     pub fn generation(self) -> u8 {
         (self.0 >> 10) as u8
     }
+
+    /// Describes the task ID by the name of its task (if known)
+    pub fn describe(self, hubris: &HubrisArchive) -> String {
+        match hubris.task_name(self.index()) {
+            Some(n) => format!("{}/gen{}", n, self.generation()),
+            None => {
+                format!("unknown#{}/gen{}", self.index(), self.generation())
+            }
+        }
+    }
 }
 
 impl std::fmt::Display for TaskId {
@@ -300,6 +389,73 @@ pub struct Ringbuf {
     pub buffer: Vec<RingbufEntry>,
 }
 
+impl RingbufEntry {
+    /// The header for entries as displayed by [`RingbufEntry::display`]
+    pub const HEADER: &'static str = " NDX LINE      GEN    COUNT PAYLOAD";
+
+    ///
+    /// Displays the entry (which is in the specified slot) as `humility
+    /// ringbuf` does.
+    ///
+    pub fn display(
+        &self,
+        hubris: &HubrisArchive,
+        slot: usize,
+    ) -> Result<String> {
+        let fmt =
+            HubrisPrintFormat { hex: true, ..HubrisPrintFormat::default() };
+
+        let mut dumped = vec![];
+        self.payload.format(hubris, fmt, &mut dumped)?;
+        let dumped = String::from_utf8(dumped)?;
+
+        Ok(format!(
+            "{:4} {:4} {:8} {:8} {}",
+            slot, self.line, self.generation, self.count, dumped
+        ))
+    }
+}
+
+impl Ringbuf {
+    ///
+    /// Loads a ring buffer from its contents, given its definition.  There
+    /// are two possible shapes of ring buffers, depending on the age of the
+    /// firmware:  a raw `Ringbuf` that is not wrapped by anything, or one
+    /// that is inside of a `StaticCell`.  We handle both.
+    ///
+    pub fn load(
+        hubris: &HubrisArchive,
+        definition: &HubrisStruct,
+        buf: &[u8],
+    ) -> Result<Self> {
+        let value =
+            Value::Struct(reflect::load_struct(hubris, buf, definition, 0)?);
+
+        Ringbuf::from_value(&value).or_else(|_| {
+            let cell = StaticCell::from_value(&value)?;
+            Ringbuf::from_value(&cell.cell.value)
+        })
+    }
+
+    ///
+    /// Returns the slots of the ring buffer that contain entries, from
+    /// oldest to newest.
+    ///
+    pub fn slots(&self) -> Vec<usize> {
+        let ndx = match self.last {
+            Some(x) => x as usize,
+            None => return vec![],
+        };
+
+        let len = self.buffer.len();
+
+        (0..len)
+            .map(|i| (ndx + i + 1) % len)
+            .filter(|&slot| self.buffer[slot].generation != 0)
+            .collect()
+    }
+}
+
 #[derive(Clone, Debug, Load)]
 pub struct StaticCell {
     pub cell: UnsafeCell,
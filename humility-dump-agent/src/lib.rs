@@ -17,7 +17,7 @@
 
 use anyhow::{anyhow, bail, Context, Result};
 use core::mem::size_of;
-use humility::{core::Core, hubris::HubrisFlashMap, msg};
+use humility::{core::Core, hubris::HubrisFlashMap, msg, Diagnostic};
 use humility_arch_arm::ARMRegister;
 use humpty::{
     DumpAreaHeader, DumpRegister, DumpSegment, DumpSegmentData,
@@ -545,7 +545,11 @@ pub trait DumpAgentExt {
                     let areas = task_areas(&all);
                     match areas.get(&ndx) {
                         None => {
-                            bail!("area {ndx} is invalid (--list to list)");
+                            bail!(Diagnostic::error(format!(
+                                "area {ndx} is invalid"
+                            ))
+                            .code("no-such-area")
+                            .hint("use --list to list areas"));
                         }
                         Some((task, headers)) => {
                             (ndx, headers.clone(), Some(*task))
@@ -554,7 +558,11 @@ pub trait DumpAgentExt {
                 }
 
                 _ => {
-                    bail!("area must be explicitly specified (--list to list)");
+                    bail!(Diagnostic::error(
+                        "area must be explicitly specified"
+                    )
+                    .code("ambiguous-area")
+                    .hint("use --list to list areas"));
                 }
             }
         };
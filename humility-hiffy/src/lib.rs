@@ -19,6 +19,21 @@ use std::thread;
 use std::time::{Duration, Instant};
 use zerocopy::{AsBytes, U16, U64};
 
+///
+/// HIF functions that do not modify the target, and may therefore be executed
+/// on a dry run
+///
+const READONLY_FUNCTIONS: &[&str] = &[
+    "GpioInput",
+    "I2cRead",
+    "QspiHash",
+    "QspiRead",
+    "QspiReadId",
+    "QspiReadStatus",
+    "QspiVerify",
+    "Sleep",
+];
+
 #[derive(Debug, PartialEq)]
 enum State {
     Initialized,
@@ -53,6 +68,7 @@ pub struct HiffyFunction {
     pub id: TargetFunction,
     pub name: String,
     pub args: Vec<HubrisGoff>,
+    pub error: HubrisGoff,
     pub errmap: HashMap<u32, String>,
 }
 
@@ -282,19 +298,21 @@ impl<'a> HiffyContext<'a> {
                 anyhow!("function {} in {}: missing a type", f.name, goff)
             })?;
 
+            //
+            // We expect a 2-tuple that is our arguments and our error type
+            //
+            let sig = hubris.lookup_struct(goff)?;
+            let args = sig.lookup_member("__0")?.goff;
+            let err = sig.lookup_member("__1")?.goff;
+
             let mut func = HiffyFunction {
                 id: TargetFunction(u8::try_from(id)?),
                 name: f.name.to_string(),
                 args: Vec::new(),
+                error: err,
                 errmap: HashMap::new(),
             };
 
-            //
-            // We expect a 2-tuple that is our arguments and our error type
-            //
-            let sig = hubris.lookup_struct(goff)?;
-            let args = sig.lookup_member("__0")?.goff;
-
             if let Ok(args) = hubris.lookup_struct(args) {
                 for arg in &args.members {
                     func.args.push(arg.goff);
@@ -312,8 +330,6 @@ impl<'a> HiffyContext<'a> {
                 }
             }
 
-            let err = sig.lookup_member("__1")?.goff;
-
             //
             // We expect our error type to be 4-byte base type or an enum.
             //
@@ -412,6 +428,11 @@ impl<'a> HiffyContext<'a> {
         Ok(total)
     }
 
+    /// The archive that this context was created with
+    pub fn hubris(&self) -> &'a HubrisArchive {
+        self.hubris
+    }
+
     pub fn get_function(
         &self,
         name: &str,
@@ -876,6 +897,50 @@ impl<'a> HiffyContext<'a> {
         }
     }
 
+    ///
+    /// Returns true if the specified HIF program only calls functions that
+    /// are known not to modify the target, and can therefore be executed on
+    /// a dry run.
+    ///
+    fn is_readonly(&self, ops: &[Op]) -> bool {
+        ops.iter().all(|op| match op {
+            Op::Call(id) => self
+                .functions
+                .0
+                .values()
+                .find(|f| f.id.0 == id.0)
+                .map_or(false, |f| READONLY_FUNCTIONS.contains(&&*f.name)),
+            _ => true,
+        })
+    }
+
+    ///
+    /// Reports the HIF program that would be executed (and the size of any
+    /// data that would accompany it), with calls annotated with the name of
+    /// the function being called.
+    ///
+    fn dry_run(&self, ops: &[Op], data: Option<&[u8]>) {
+        humility::msg!("would execute HIF program ({} ops):", ops.len());
+
+        for (n, op) in ops.iter().enumerate() {
+            let name = match op {
+                Op::Call(id) => self
+                    .functions
+                    .0
+                    .values()
+                    .find(|f| f.id.0 == id.0)
+                    .map(|f| format!(" ({})", f.name)),
+                _ => None,
+            };
+
+            humility::msg!("{n:>5}: {op:?}{}", name.unwrap_or_default());
+        }
+
+        if let Some(data) = data {
+            humility::msg!("with {} bytes of data", data.len());
+        }
+    }
+
     /// Begins HIF execution.  This is potentially non-blocking with respect to
     /// the HIF program, so you will need to poll [Self::done] to check for
     /// completion.
@@ -892,6 +957,25 @@ impl<'a> HiffyContext<'a> {
             }
         }
 
+        //
+        // On a dry run, a program that only reads is executed as usual
+        // (commands that modify the target via HIF generally read before
+        // they write); any other program is reported and the command stopped.
+        //
+        let core = if core.is_dry_run() {
+            let readonly = self.is_readonly(ops);
+
+            match core.dry_run_inner() {
+                Some(inner) if readonly => inner,
+                _ => {
+                    self.dry_run(ops, data);
+                    bail!(humility::core::DryRun);
+                }
+            }
+        } else {
+            core
+        };
+
         if core.is_net() {
             if data.is_some() {
                 bail!(
@@ -900,6 +984,7 @@ impl<'a> HiffyContext<'a> {
                 );
             }
 
+            humility::stats::hiffy_run();
             return self.perform_rpc(core, ops);
         }
 
@@ -995,6 +1080,7 @@ impl<'a> HiffyContext<'a> {
         ));
 
         core.write_word_32(self.kick.addr, 1)?;
+        humility::stats::hiffy_run();
 
         self.kicked = Some(Instant::now());
 
@@ -1314,19 +1400,20 @@ pub fn hiffy_format_result(
     }
 }
 
-pub fn hiffy_print_result(
+/// Formats the result of a call to the given operation, e.g.
+/// `Sensor.get() => 42.5`.  This is returned rather than printed, leaving it
+/// to the caller to decide where (and whether) to emit it.
+pub fn hiffy_format_call(
     hubris: &HubrisArchive,
     op: &idol::IdolOperation,
     result: std::result::Result<humility::reflect::Value, String>,
-) -> Result<()> {
-    println!(
+) -> String {
+    format!(
         "{}.{}() => {}",
         op.name.0,
         op.name.1,
         hiffy_format_result(hubris, result)
-    );
-
-    Ok(())
+    )
 }
 
 /// Check that the given operation and provided leases are compatible, bailing
@@ -3,8 +3,14 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use anyhow::{bail, Context, Result};
+use hif::Op;
+use humility::core::Core;
 use humility::hubris::*;
+use humility::msg;
+use humility_hiffy::{HiffyContext, HiffyFunction};
 use std::fmt;
+use std::thread;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct I2cArgs<'a> {
@@ -289,3 +295,754 @@ impl<'a> I2cArgs<'a> {
         Ok(Self { controller, port, mux, device, address, class })
     }
 }
+
+/// A single message within an I2C transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum I2cMessage {
+    /// Write the specified bytes (which may be empty, denoting a zero-length
+    /// write that can be used to probe for a device)
+    Write(Vec<u8>),
+    /// Read the specified number of bytes
+    Read(usize),
+}
+
+impl fmt::Display for I2cMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            I2cMessage::Write(bytes) if bytes.is_empty() => {
+                write!(f, "write (zero-length)")
+            }
+            I2cMessage::Write(bytes) => {
+                write!(f, "write")?;
+
+                for b in bytes {
+                    write!(f, " 0x{:02x}", b)?;
+                }
+
+                Ok(())
+            }
+            I2cMessage::Read(nbytes) => {
+                write!(f, "read {} byte{}", nbytes, plural(*nbytes))
+            }
+        }
+    }
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// The maximum number of bytes in a single write of a transaction.  The
+/// bytes are pushed onto the HIF stack along with the length (and either the
+/// register or the number of bytes to read), and must be dropped from it in
+/// a single `DropN`.
+pub const I2C_WRITE_MAX: usize = u8::MAX as usize - 2;
+
+/// An operation that can be performed by the `I2cRead`, `I2cWrite` and
+/// `I2cWriteRead` HIF functions, as lowered from an [`I2cTransaction`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum I2cOperation {
+    /// A write of the specified bytes, followed by a stop
+    Write(Vec<u8>),
+    /// A read of the specified number of bytes.  If a register is present,
+    /// it is written first and the read follows a repeated start.
+    Read { register: Option<u8>, nbytes: u8 },
+    /// A write of the specified bytes, followed by a repeated start and a
+    /// read of the specified number of bytes
+    WriteRead { bytes: Vec<u8>, nbytes: u8 },
+}
+
+/// A sequence of messages to be sent to a single device.  Transactions are
+/// expressed as a sequence of messages separated by whitespace or
+/// semicolons, where each message is either `w` (optionally followed by a
+/// colon and a comma-separated list of bytes) or `r:nbytes`.  For example,
+/// `w:0x12,0x34 r:2` denotes a write of two bytes (e.g., a 16-bit register
+/// address) followed by a two-byte read; a lone `w` denotes a zero-length
+/// write.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct I2cTransaction {
+    pub messages: Vec<I2cMessage>,
+}
+
+impl std::str::FromStr for I2cTransaction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut messages = vec![];
+
+        for m in s.split(|c: char| c == ';' || c.is_whitespace()) {
+            if m.is_empty() {
+                continue;
+            }
+
+            let (kind, arg) = match m.split_once(':') {
+                Some((kind, arg)) => (kind, Some(arg)),
+                None => (m, None),
+            };
+
+            match (kind, arg) {
+                ("w", None) => messages.push(I2cMessage::Write(vec![])),
+                ("w", Some(bytes)) => {
+                    let bytes = bytes
+                        .split(',')
+                        .filter(|b| !b.is_empty())
+                        .map(|b| {
+                            parse_int::parse::<u8>(b)
+                                .with_context(|| format!("invalid byte {}", b))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+
+                    if bytes.len() > I2C_WRITE_MAX {
+                        bail!(
+                            "cannot write more than {} bytes in a message",
+                            I2C_WRITE_MAX
+                        );
+                    }
+
+                    messages.push(I2cMessage::Write(bytes));
+                }
+                ("r", Some(nbytes)) => {
+                    let nbytes = parse_int::parse::<usize>(nbytes)
+                        .with_context(|| {
+                            format!("invalid read length {}", nbytes)
+                        })?;
+
+                    if nbytes == 0 {
+                        bail!(
+                            "zero-length reads are not supported; \
+                            use \"w\" to probe with a zero-length write"
+                        );
+                    }
+
+                    messages.push(I2cMessage::Read(nbytes));
+                }
+                ("r", None) => {
+                    bail!("read \"{}\" must specify a length (e.g. r:1)", m);
+                }
+                _ => {
+                    bail!(
+                        "invalid message \"{}\"; expected \"w[:bytes]\" \
+                        or \"r:nbytes\"",
+                        m
+                    );
+                }
+            }
+        }
+
+        if messages.is_empty() {
+            bail!("transaction must contain at least one message");
+        }
+
+        Ok(Self { messages })
+    }
+}
+
+impl I2cTransaction {
+    ///
+    /// Lower this transaction into the operations that the `I2cRead` and
+    /// `I2cWrite` HIF functions can perform.  A single-byte write followed
+    /// by a read becomes a read with a repeated start.  A multi-byte write
+    /// followed by a read (e.g., 16-bit register addressing) becomes a
+    /// combined write and read with a repeated start if `write_read` is set
+    /// (that is, if the target has the `I2cWriteRead` HIF function), and is
+    /// otherwise performed as a write followed by a read.
+    ///
+    pub fn operations(&self, write_read: bool) -> Result<Vec<I2cOperation>> {
+        let mut ops = vec![];
+        let mut iter = self.messages.iter().peekable();
+
+        let nbytes = |n: usize| -> Result<u8> {
+            u8::try_from(n).map_err(|_| {
+                anyhow::anyhow!("cannot read more than {} bytes", u8::MAX)
+            })
+        };
+
+        while let Some(m) = iter.next() {
+            match m {
+                I2cMessage::Write(bytes) if bytes.len() == 1 => {
+                    if let Some(I2cMessage::Read(n)) = iter.peek() {
+                        ops.push(I2cOperation::Read {
+                            register: Some(bytes[0]),
+                            nbytes: nbytes(*n)?,
+                        });
+                        iter.next();
+                    } else {
+                        ops.push(I2cOperation::Write(bytes.clone()));
+                    }
+                }
+                I2cMessage::Write(bytes) if write_read && !bytes.is_empty() => {
+                    if let Some(I2cMessage::Read(n)) = iter.peek() {
+                        ops.push(I2cOperation::WriteRead {
+                            bytes: bytes.clone(),
+                            nbytes: nbytes(*n)?,
+                        });
+                        iter.next();
+                    } else {
+                        ops.push(I2cOperation::Write(bytes.clone()));
+                    }
+                }
+                I2cMessage::Write(bytes) => {
+                    ops.push(I2cOperation::Write(bytes.clone()));
+                }
+                I2cMessage::Read(n) => {
+                    ops.push(I2cOperation::Read {
+                        register: None,
+                        nbytes: nbytes(*n)?,
+                    });
+                }
+            }
+        }
+
+        Ok(ops)
+    }
+}
+
+impl fmt::Display for I2cTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, m) in self.messages.iter().enumerate() {
+            if i != 0 {
+                write!(f, "; ")?;
+            }
+
+            write!(f, "{}", m)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The stages of recovery from a transient I2C error, in order of
+/// escalation.  Note that Humility cannot itself drive the bus:  recovery is
+/// effected through the I2C driver in Hubris by performing a transaction on
+/// the affected bus outside of any mux segment.  The driver then deselects
+/// whatever segment is selected (isolating a segment that may be holding the
+/// bus) and -- should it find the bus locked -- resets the controller and
+/// clocks the bus to free it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum I2cRecovery {
+    /// Retry the program as it is
+    Retry,
+    /// Deselect the mux segments used by the program before retrying it,
+    /// forcing the retry to reselect them
+    Reselect,
+    /// Ask the driver to recover each bus used by the program, and allow the
+    /// bus to settle before retrying it
+    Recover,
+}
+
+impl I2cRecovery {
+    ///
+    /// Returns the stage of recovery called for by the specified I2C driver
+    /// error, or `None` if the error is not transient.  (Errors like
+    /// `NoDevice` and `NoRegister` are expected in scans, and are therefore
+    /// not considered transient.)
+    ///
+    pub fn from_error(err: &str) -> Option<Self> {
+        match err {
+            "ControllerBusy" | "BusError" => Some(I2cRecovery::Retry),
+            "SegmentDisconnected"
+            | "MuxDisconnected"
+            | "BusResetMux"
+            | "BusLockedMux" => Some(I2cRecovery::Reselect),
+            "BusReset" | "BusLocked" => Some(I2cRecovery::Recover),
+            _ => None,
+        }
+    }
+
+    fn next(&self) -> Option<Self> {
+        match self {
+            I2cRecovery::Retry => Some(I2cRecovery::Reselect),
+            I2cRecovery::Reselect => Some(I2cRecovery::Recover),
+            I2cRecovery::Recover => None,
+        }
+    }
+
+    fn delay(&self) -> Duration {
+        match self {
+            I2cRecovery::Retry => Duration::ZERO,
+            I2cRecovery::Reselect => Duration::from_millis(10),
+            I2cRecovery::Recover => Duration::from_millis(100),
+        }
+    }
+}
+
+impl fmt::Display for I2cRecovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                I2cRecovery::Retry => "retrying",
+                I2cRecovery::Reselect => "reselecting segment",
+                I2cRecovery::Recover => "recovering bus",
+            }
+        )
+    }
+}
+
+/// A bus as named in a HIF program:  controller, port index and, if any,
+/// mux and segment
+type I2cBus = (u8, u8, Option<(u8, u8)>);
+
+/// What can be determined about a HIF program from its text
+struct I2cProgram {
+    /// True if the program can be run more than once
+    retryable: bool,
+    /// The buses on which the program performs I2C operations
+    buses: Vec<I2cBus>,
+}
+
+/// A policy for retrying HIF programs consisting of I2C operations in the
+/// face of transient bus errors, escalating recovery (see [`I2cRecovery`])
+/// as retries fail.  Because a failed program is rerun in its entirety, a
+/// program is only retried if each of its calls is either an `I2cRead` or an
+/// `I2cWrite` that has been explicitly marked as idempotent (e.g., the
+/// selection of a PMBus page); any other program is run exactly once.
+#[derive(Clone, Debug)]
+pub struct I2cRetryPolicy {
+    /// Number of attempts at each stage of recovery before escalating
+    pub attempts: u32,
+    /// Writes that may be repeated, as (address, register) pairs; an address
+    /// of `None` denotes any device, and a register of `None` a raw write
+    idempotent: Vec<(Option<u8>, Option<u8>)>,
+}
+
+impl Default for I2cRetryPolicy {
+    fn default() -> Self {
+        Self { attempts: 2, idempotent: vec![] }
+    }
+}
+
+impl I2cRetryPolicy {
+    /// A policy that never retries
+    pub fn none() -> Self {
+        Self { attempts: 0, idempotent: vec![] }
+    }
+
+    ///
+    /// Marks writes to the specified register (or raw writes, if `None`) of
+    /// the specified device (or of any device, if `None`) as idempotent, such
+    /// that programs containing them may be retried.  Only writes that leave
+    /// the device in the same state no matter how many times they are
+    /// performed (e.g., selecting a PMBus page) should be so marked.
+    ///
+    pub fn idempotent(
+        mut self,
+        address: Option<u8>,
+        register: Option<u8>,
+    ) -> Self {
+        self.idempotent.push((address, register));
+        self
+    }
+
+    ///
+    /// Examines a program, following its stack to determine the arguments
+    /// to each I2C call.  Once the program does anything that we can't
+    /// follow (e.g., arithmetic or branching), the values on the stack are
+    /// unknown, and any subsequent write is assumed to not be idempotent.
+    ///
+    fn analyze(
+        &self,
+        ops: &[Op],
+        read: &HiffyFunction,
+        write: Option<&HiffyFunction>,
+    ) -> I2cProgram {
+        let mut stack: Vec<Option<Option<u32>>> = vec![];
+        let mut program = I2cProgram { retryable: true, buses: vec![] };
+
+        let bus = |args: &[Option<Option<u32>>]| -> Option<I2cBus> {
+            let byte = |v: Option<u32>| v.and_then(|v| u8::try_from(v).ok());
+
+            match args {
+                [Some(c), Some(p), Some(m), Some(s), ..] => {
+                    let mux = match (byte(*m), byte(*s)) {
+                        (Some(m), Some(s)) => Some((m, s)),
+                        _ => None,
+                    };
+
+                    Some((byte(*c)?, byte(*p)?, mux))
+                }
+                _ => None,
+            }
+        };
+
+        for op in ops {
+            let args = match op {
+                Op::Push(v) => {
+                    stack.push(Some(Some(u32::from(*v))));
+                    continue;
+                }
+                Op::Push16(v) => {
+                    stack.push(Some(Some(u32::from(*v))));
+                    continue;
+                }
+                Op::Push32(v) => {
+                    stack.push(Some(Some(*v)));
+                    continue;
+                }
+                Op::PushNone => {
+                    stack.push(Some(None));
+                    continue;
+                }
+                Op::Drop => {
+                    stack.pop();
+                    continue;
+                }
+                Op::DropN(n) => {
+                    stack.truncate(stack.len().saturating_sub(*n as usize));
+                    continue;
+                }
+                Op::Label(_) | Op::Done => continue,
+                Op::Call(id) if id.0 == read.id.0 => {
+                    stack.len().checked_sub(7).map(|base| &stack[base..])
+                }
+                Op::Call(id) if write.map_or(false, |w| w.id.0 == id.0) => {
+                    //
+                    // A write's arguments are the bus, the address, the
+                    // register, the bytes to write and the number of them.
+                    //
+                    let args = match stack.last() {
+                        Some(Some(Some(len))) => (*len as usize)
+                            .checked_add(7)
+                            .and_then(|n| stack.len().checked_sub(n))
+                            .map(|base| &stack[base..]),
+                        _ => None,
+                    };
+
+                    let idempotent = match args {
+                        Some(
+                            [_, _, _, _, Some(address), Some(register), ..],
+                        ) => {
+                            let address = address.map(|a| a as u8);
+                            let register = register.map(|r| r as u8);
+
+                            self.idempotent.iter().any(|&(a, r)| {
+                                a.map_or(true, |a| Some(a) == address)
+                                    && r == register
+                            })
+                        }
+                        _ => false,
+                    };
+
+                    if !idempotent {
+                        program.retryable = false;
+                    }
+
+                    args
+                }
+                Op::Call(_) => {
+                    program.retryable = false;
+                    continue;
+                }
+                _ => {
+                    stack.iter_mut().for_each(|v| *v = None);
+                    continue;
+                }
+            };
+
+            if let Some(bus) = args.and_then(bus) {
+                if !program.buses.contains(&bus) {
+                    program.buses.push(bus);
+                }
+            }
+        }
+
+        program
+    }
+
+    ///
+    /// Performs a read on the specified bus outside of any mux segment (see
+    /// [`I2cRecovery`]).  The read is of an address that has no device on the
+    /// bus in the manifest, and is expected to fail; the result is therefore
+    /// only of interest to the extent that it indicates that the driver has
+    /// reset the bus.
+    ///
+    fn deselect(
+        &self,
+        context: &mut HiffyContext,
+        core: &mut dyn Core,
+        read: &HiffyFunction,
+        bus: I2cBus,
+    ) -> Result<()> {
+        let (controller, port, _) = bus;
+        let devices = &context.hubris().manifest.i2c_devices;
+
+        //
+        // Muxes themselves are conventionally at 0x70 through 0x77, so we
+        // avoid those as well.
+        //
+        let address = (0x08..0x70).find(|&a| {
+            !devices.iter().any(|d| {
+                d.controller == controller
+                    && d.port.index == port
+                    && d.address == a
+            })
+        });
+
+        let Some(address) = address else {
+            return Ok(());
+        };
+
+        let ops = [
+            Op::Push(controller),
+            Op::Push(port),
+            Op::PushNone,
+            Op::PushNone,
+            Op::Push(address),
+            Op::PushNone,
+            Op::Push(1),
+            Op::Call(read.id),
+            Op::DropN(7),
+            Op::Done,
+        ];
+
+        let results = context.run(core, &ops, None)?;
+
+        if let Some(Err(err)) = results.first() {
+            let name = read.errmap.get(err).map(String::as_str);
+
+            if name.and_then(I2cRecovery::from_error)
+                == Some(I2cRecovery::Recover)
+            {
+                msg!("I2C{controller}: bus was reset by the I2C driver");
+            }
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Run the specified program, retrying (and escalating recovery) if it
+    /// can be retried and any operation fails with a transient error.  Once
+    /// recovery has been exhausted, the results of the last attempt are
+    /// returned, leaving it to the caller to report any errors.  `func` must
+    /// be `I2cRead`.
+    ///
+    pub fn run(
+        &self,
+        context: &mut HiffyContext,
+        core: &mut dyn Core,
+        ops: &[Op],
+        data: Option<&[u8]>,
+        func: &HiffyFunction,
+    ) -> Result<Vec<Result<Vec<u8>, u32>>> {
+        let write = context.get_function("I2cWrite", 8).ok();
+        let program = self.analyze(ops, func, write.as_ref());
+
+        let mut stage = I2cRecovery::Retry;
+        let mut attempts = 0;
+
+        loop {
+            let results = context.run(core, ops, data)?;
+
+            if !program.retryable || self.attempts == 0 {
+                return Ok(results);
+            }
+
+            //
+            // Determine the most severe recovery called for by any error.
+            //
+            let needed = results
+                .iter()
+                .filter_map(|r| r.as_ref().err())
+                .filter_map(|err| {
+                    let name = func
+                        .errmap
+                        .get(err)
+                        .or_else(|| write.as_ref()?.errmap.get(err))?;
+                    Some((I2cRecovery::from_error(name)?, name.clone()))
+                })
+                .max_by_key(|(recovery, _)| *recovery);
+
+            let Some((needed, err)) = needed else {
+                return Ok(results);
+            };
+
+            if needed > stage {
+                stage = needed;
+                attempts = 0;
+            } else if attempts >= self.attempts {
+                match stage.next() {
+                    Some(next) => {
+                        stage = next;
+                        attempts = 0;
+                    }
+                    None => return Ok(results),
+                }
+            }
+
+            //
+            // Reselection is only meaningful on buses that have a mux; should
+            // there be none, we move directly to recovery.
+            //
+            if stage == I2cRecovery::Reselect
+                && !program.buses.iter().any(|(_, _, mux)| mux.is_some())
+            {
+                stage = I2cRecovery::Recover;
+                attempts = 0;
+            }
+
+            attempts += 1;
+
+            msg!(
+                "I2C operation failed with {err}; {stage} (attempt {attempts})"
+            );
+
+            for &bus in &program.buses {
+                match stage {
+                    I2cRecovery::Retry => {}
+                    I2cRecovery::Reselect if bus.2.is_none() => {}
+                    I2cRecovery::Reselect | I2cRecovery::Recover => {
+                        self.deselect(context, core, func, bus)?;
+                    }
+                }
+            }
+
+            thread::sleep(stage.delay());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> I2cTransaction {
+        s.parse().unwrap()
+    }
+
+    fn ops(s: &str, write_read: bool) -> Vec<I2cOperation> {
+        parse(s).operations(write_read).unwrap()
+    }
+
+    fn write(n: usize) -> String {
+        let bytes = (0..n).map(|b| format!("{}", b as u8)).collect::<Vec<_>>();
+        format!("w:{}", bytes.join(","))
+    }
+
+    #[test]
+    fn parse_messages() {
+        assert_eq!(
+            parse("w:0x12,0x34 r:2").messages,
+            vec![I2cMessage::Write(vec![0x12, 0x34]), I2cMessage::Read(2)]
+        );
+
+        assert_eq!(parse("w:1;r:1"), parse("  w:1 ; r:0x1  "));
+    }
+
+    #[test]
+    fn repeated_start() {
+        assert_eq!(
+            ops("w:0x8b r:2", false),
+            vec![I2cOperation::Read { register: Some(0x8b), nbytes: 2 }]
+        );
+
+        assert_eq!(
+            ops("w:0x8b r:2", true),
+            vec![I2cOperation::Read { register: Some(0x8b), nbytes: 2 }]
+        );
+
+        assert_eq!(
+            ops("w:0x8b r:2 w:0x8c r:1", false),
+            vec![
+                I2cOperation::Read { register: Some(0x8b), nbytes: 2 },
+                I2cOperation::Read { register: Some(0x8c), nbytes: 1 },
+            ]
+        );
+
+        assert_eq!(
+            ops("w:0x8b w:0x8c r:1", false),
+            vec![
+                I2cOperation::Write(vec![0x8b]),
+                I2cOperation::Read { register: Some(0x8c), nbytes: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_length_write() {
+        assert_eq!(parse("w").messages, vec![I2cMessage::Write(vec![])]);
+        assert_eq!(parse("w:").messages, vec![I2cMessage::Write(vec![])]);
+        assert_eq!(ops("w", true), vec![I2cOperation::Write(vec![])]);
+
+        assert_eq!(
+            ops("w r:1", true),
+            vec![
+                I2cOperation::Write(vec![]),
+                I2cOperation::Read { register: None, nbytes: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn sixteen_bit_register() {
+        assert_eq!(
+            ops("w:0x12,0x34 r:2", true),
+            vec![I2cOperation::WriteRead {
+                bytes: vec![0x12, 0x34],
+                nbytes: 2
+            }]
+        );
+
+        assert_eq!(
+            ops("w:0x12,0x34 r:2", false),
+            vec![
+                I2cOperation::Write(vec![0x12, 0x34]),
+                I2cOperation::Read { register: None, nbytes: 2 },
+            ]
+        );
+
+        assert_eq!(
+            ops("w:0x12,0x34", true),
+            vec![I2cOperation::Write(vec![0x12, 0x34])]
+        );
+    }
+
+    #[test]
+    fn write_max() {
+        let max = parse(&write(I2C_WRITE_MAX));
+
+        match &max.messages[..] {
+            [I2cMessage::Write(bytes)] => {
+                assert_eq!(bytes.len(), I2C_WRITE_MAX)
+            }
+            _ => panic!("unexpected messages {:?}", max.messages),
+        }
+
+        assert!(write(I2C_WRITE_MAX + 1).parse::<I2cTransaction>().is_err());
+        assert!(write(u8::MAX as usize + 1).parse::<I2cTransaction>().is_err());
+    }
+
+    #[test]
+    fn read_max() {
+        assert_eq!(
+            ops("r:255", false),
+            vec![I2cOperation::Read { register: None, nbytes: 255 }]
+        );
+
+        assert!(parse("r:256").operations(false).is_err());
+        assert!(parse("w:0x10 r:256").operations(false).is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        for s in [
+            "", " ; ", "r", "r:", "r:0", "r:-1", "r:two", "w:0x100", "w:1,foo",
+            "x:1", "write:1", "w:1:2",
+        ] {
+            assert!(s.parse::<I2cTransaction>().is_err(), "parsed \"{s}\"");
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            parse("w:0x12,0x34 r:2 w").to_string(),
+            "write 0x12 0x34; read 2 bytes; write (zero-length)"
+        );
+    }
+}
@@ -27,7 +27,7 @@ pub struct IdolOperation<'a> {
     pub error: IdolError<'a>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum IdolArgument<'a> {
     String(&'a str),
     Scalar(u64),
@@ -0,0 +1,128 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use colored::Colorize;
+use serde::Serialize;
+use std::fmt;
+
+/// The severity of a [`Diagnostic`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Severity::Note => "note",
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            }
+        )
+    }
+}
+
+/// A diagnostic for the user, consisting of a severity, an optional error
+/// code, a message, and any number of hints as to how to proceed.
+///
+/// A `Diagnostic` implements [`std::error::Error`], and is intended to be
+/// returned as an error in lieu of a bare string when the failure has a
+/// known remedy that can be offered as a hint (e.g., an operation that must
+/// be forced, or an option that must be specified); other failures remain
+/// bare errors.  For example:
+///
+/// ```ignore
+/// bail!(Diagnostic::error("archive is already flashed")
+///     .code("already-flashed")
+///     .hint("use -F (\"--force\") to force re-flash"));
+/// ```
+///
+/// Diagnostics are rendered consistently (and in color, where supported)
+/// when a command fails, and as JSON when logging as JSON (that is, with
+/// `--log-format json`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<String>,
+    pub message: String,
+    pub hints: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self { severity, code: None, message: message.into(), hints: vec![] }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    pub fn note(message: impl Into<String>) -> Self {
+        Self::new(Severity::Note, message)
+    }
+
+    /// Attach an error code, which should be a short, kebab-cased identifier
+    /// that is stable across releases.
+    pub fn code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    /// Attach a hint as to how the user might proceed.
+    pub fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hints.push(hint.into());
+        self
+    }
+
+    /// Render this diagnostic for the terminal, with color if supported.
+    pub fn render(&self) -> String {
+        let tag = match &self.code {
+            Some(code) => format!("{}[{}]", self.severity, code),
+            None => self.severity.to_string(),
+        };
+
+        let tag = match self.severity {
+            Severity::Error => tag.red().bold(),
+            Severity::Warning => tag.yellow().bold(),
+            Severity::Note => tag.bold(),
+        };
+
+        let mut rval = format!("{}: {}", tag, self.message);
+
+        for hint in &self.hints {
+            rval += &format!("\n  {}: {}", "hint".cyan().bold(), hint);
+        }
+
+        rval
+    }
+
+    /// Render this diagnostic as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        for hint in &self.hints {
+            write!(f, "; hint: {}", hint)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
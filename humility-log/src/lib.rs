@@ -2,6 +2,14 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+mod diagnostic;
+mod logger;
+mod output;
+
+pub use diagnostic::{Diagnostic, Severity};
+pub use logger::{init_logger, LogFormat};
+pub use output::{output, set_output, Output};
+
 /// Give messages to the user.
 ///
 /// These macros are intended to be used whenever producing secondary output to the
@@ -14,34 +22,35 @@
 /// macros should be used in lieu of `log::error!`, `log::warn!` or direct
 /// `eprintln!` (`log::debug!` and `log::trace!` can be used for debugging
 /// output that is to be optionally enabled on the command line).
+///
+/// Where the output goes can be changed with [`set_output`], e.g. by a
+/// program that uses Humility as a library.
 #[macro_export]
 macro_rules! msg {
     ($fmt:expr) => ({
         let s = format!($fmt);
-        eprintln!("humility: {s}");
+        $crate::__private::msg(&s);
     });
     ($fmt:expr, $($arg:tt)*) => ({
         let s = format!($fmt, $($arg)*);
-        eprintln!("humility: {}", s)
+        $crate::__private::msg(&s)
     });
 }
 
 #[macro_export]
 macro_rules! warn {
     ($fmt:expr) => ({
-        use $crate::__private::Colorize;
-        eprint!("humility: {}: ", "WARNING".red());
-        eprintln!($fmt);
+        let s = format!($fmt);
+        $crate::__private::warn(&s);
     });
     ($fmt:expr, $($arg:tt)*) => ({
-        use $crate::__private::Colorize;
-        eprint!("humility: {}: ", "WARNING".red());
-        eprintln!($fmt, $($arg)*);
+        let s = format!($fmt, $($arg)*);
+        $crate::__private::warn(&s);
     });
 }
 
 // Not public API. Referenced by macro-generated code
 #[doc(hidden)]
 pub mod __private {
-    pub use colored::Colorize;
+    pub use crate::output::{msg, warn};
 }
@@ -0,0 +1,112 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::output::{set_output, Output};
+use std::io::Write;
+use std::str::FromStr;
+
+/// The format of log records
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines, with millisecond timestamps
+    Text,
+    /// A single line of JSON per record, containing `timestamp`, `level`,
+    /// `target` and `message`
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "unknown log format \"{s}\" (expected text or json)"
+            )),
+        }
+    }
+}
+
+///
+/// Expands a filter directive that names a subsystem (e.g., `hiffy=debug`)
+/// into directives for the crates that implement it (`humility_hiffy` and
+/// `humility_cmd_hiffy`).  Directives that name a module path or that are
+/// a bare level are passed through unmodified.
+///
+fn expand(directive: &str) -> Vec<String> {
+    let (name, level) = match directive.split_once('=') {
+        Some((name, level)) => (name, level),
+        None => return vec![directive.to_string()],
+    };
+
+    if name.contains("::") || name.starts_with("humility") {
+        return vec![directive.to_string()];
+    }
+
+    let name = name.replace('-', "_");
+
+    vec![
+        format!("{name}={level}"),
+        format!("humility_{name}={level}"),
+        format!("humility_cmd_{name}={level}"),
+    ]
+}
+
+///
+/// Initializes the logger.  The default level is `warn`, `debug` with a
+/// verbosity of 1 and `trace` with a verbosity of 2 or more; `RUST_LOG`,
+/// if set, overrides the default.  Each of `filters` is then applied in
+/// turn:  each is either a level, or a subsystem (or module path) and a
+/// level separated by `=` (e.g., `hiffy=debug`).
+///
+/// When logging as JSON, messages that would otherwise go to the terminal
+/// are logged as well, so that all output on stderr is structured.
+///
+pub fn init_logger(verbosity: u8, filters: &[String], format: LogFormat) {
+    let level = match verbosity {
+        0 => "warn",
+        1 => "debug",
+        _ => "trace",
+    };
+
+    let mut builder = env_logger::Builder::new();
+
+    match std::env::var("RUST_LOG") {
+        Ok(env) => builder.parse_filters(&env),
+        Err(_) => builder.parse_filters(level),
+    };
+
+    if format == LogFormat::Json {
+        set_output(Output::Log);
+        builder.parse_filters("humility_log=info");
+    }
+
+    for filter in filters {
+        for directive in expand(filter) {
+            builder.parse_filters(&directive);
+        }
+    }
+
+    match format {
+        LogFormat::Text => {
+            builder.format_timestamp_millis();
+        }
+        LogFormat::Json => {
+            builder.format(|buf, record| {
+                let record = serde_json::json!({
+                    "timestamp": buf.timestamp_millis().to_string(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                });
+
+                writeln!(buf, "{record}")
+            });
+        }
+    }
+
+    builder.init();
+}
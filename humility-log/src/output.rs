@@ -0,0 +1,56 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use colored::Colorize;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The destination of messages from [`msg!`](crate::msg) and
+/// [`warn!`](crate::warn), and of progress bars.
+///
+/// The command-line tool sends everything to the terminal; a program that
+/// instead uses Humility as a library will generally want messages to go to
+/// its own logger (or nowhere), and progress bars to not be drawn at all.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Output {
+    /// To stderr, prefixed with `humility: ` (the default)
+    Terminal,
+    /// To the `log` crate, as info-level (or, for warnings, warn-level)
+    /// records; progress bars are not drawn
+    Log,
+    /// Nowhere; progress bars are not drawn
+    Silent,
+}
+
+static OUTPUT: AtomicU8 = AtomicU8::new(Output::Terminal as u8);
+
+/// Sets the destination of messages for the remainder of the process
+pub fn set_output(output: Output) {
+    OUTPUT.store(output as u8, Ordering::Relaxed);
+}
+
+/// Returns the current destination of messages
+pub fn output() -> Output {
+    match OUTPUT.load(Ordering::Relaxed) {
+        x if x == Output::Log as u8 => Output::Log,
+        x if x == Output::Silent as u8 => Output::Silent,
+        _ => Output::Terminal,
+    }
+}
+
+pub fn msg(s: &str) {
+    match output() {
+        Output::Terminal => eprintln!("humility: {s}"),
+        Output::Log => log::info!("{s}"),
+        Output::Silent => {}
+    }
+}
+
+pub fn warn(s: &str) {
+    match output() {
+        Output::Terminal => eprintln!("humility: {}: {s}", "WARNING".red()),
+        Output::Log => log::warn!("{s}"),
+        Output::Silent => {}
+    }
+}
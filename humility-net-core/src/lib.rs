@@ -11,6 +11,10 @@
 //!
 //! In addition, it contains the contents of flash from the Hubris image,
 //! meaning it can handle flash reads locally.
+//!
+//! The target may be specified as a link-local IPv6 address with a scope id
+//! (e.g. `fe80::0c1d:9aff:fe64:b8c2%en0`), as any other IPv6 or IPv4
+//! address, or as a hostname.
 
 use anyhow::{anyhow, bail, Context, Result};
 use humility::{
@@ -18,17 +22,82 @@ use humility::{
     hubris::{HubrisArchive, HubrisFlashMap, HubrisRegion, HubrisTask},
     msg,
     net::decode_iface,
+    Diagnostic,
 };
 use humility_arch_arm::ARMRegister;
 use humility_dump_agent::{
     DumpAgent, DumpAgentCore, DumpAgentExt, DumpArea, UdpDumpAgent,
 };
 use std::{
-    net::{ToSocketAddrs, UdpSocket},
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
     path::Path,
     time::Duration,
 };
 
+/// UDP port of the `udprpc` task; see oxidecomputer/oana
+const UDPRPC_PORT: u16 = 998;
+
+/// UDP port of the dump agent; see oxidecomputer/oana
+const DUMP_AGENT_PORT: u16 = 11113;
+
+///
+/// Resolves the specified target and port into socket addresses.  A
+/// link-local IPv6 address requires a scope id (e.g. `%en0`) to identify the
+/// interface; other addresses and hostnames are resolved as usual.
+///
+fn resolve(ip: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let dest = match ip.split_once('%') {
+        Some((ip, iface)) => {
+            let scopeid = decode_iface(iface.trim_end_matches(']'))?;
+            let ip = ip.trim_start_matches('[').trim_end_matches(']');
+            format!("[{}%{}]:{}", ip, scopeid, port)
+                .to_socket_addrs()?
+                .collect::<Vec<_>>()
+        }
+        None => {
+            let addr = ip.trim_start_matches('[').trim_end_matches(']');
+
+            match addr.parse::<IpAddr>() {
+                Ok(IpAddr::V6(v6)) if v6.segments()[0] & 0xffc0 == 0xfe80 => {
+                    bail!(
+                        "Missing scope id in link-local IP \
+                         (e.g. '{}%en0')",
+                        addr
+                    );
+                }
+                Ok(addr) => vec![SocketAddr::new(addr, port)],
+                Err(_) => (ip, port)
+                    .to_socket_addrs()
+                    .with_context(|| format!("could not resolve {ip}"))?
+                    .collect::<Vec<_>>(),
+            }
+        }
+    };
+
+    if dest.is_empty() {
+        bail!("no addresses found for {ip}");
+    }
+
+    Ok(dest)
+}
+
+///
+/// Opens a UDP socket connected to the specified destination, binding to
+/// the unspecified address of the same family.
+///
+fn connect(dest: &[SocketAddr], timeout: Duration) -> Result<UdpSocket> {
+    let socket = if dest[0].is_ipv4() {
+        UdpSocket::bind("0.0.0.0:0")?
+    } else {
+        UdpSocket::bind("[::]:0")?
+    };
+
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(dest)?;
+
+    Ok(socket)
+}
+
 pub struct NetCore {
     udprpc_socket: UdpSocket,
     flash: HubrisFlashMap,
@@ -46,21 +115,11 @@ impl NetCore {
         hubris: &HubrisArchive,
         timeout: Duration,
     ) -> Result<Self> {
-        let mut iter = ip.split('%');
-        let ip = iter.next().expect("ip address is empty");
-        let iface = iter
-            .next()
-            .ok_or_else(|| anyhow!("Missing scope id in IP (e.g. '%en0')"))?;
-
-        let scopeid = decode_iface(iface)?;
-
-        // See oxidecomputer/oana for standard Hubris UDP ports
-        let target = format!("[{}%{}]:998", ip, scopeid);
+        if ip.is_empty() {
+            bail!("ip address is empty");
+        }
 
-        let dest = target.to_socket_addrs()?.collect::<Vec<_>>();
-        let udprpc_socket = UdpSocket::bind("[::]:0")?;
-        udprpc_socket.set_read_timeout(Some(timeout))?;
-        udprpc_socket.connect(&dest[..])?;
+        let udprpc_socket = connect(&resolve(ip, UDPRPC_PORT)?, timeout)?;
 
         let rpc_task = hubris.lookup_task("udprpc").ok_or_else(|| {
             anyhow!(
@@ -86,15 +145,8 @@ impl NetCore {
             .map(|t| hubris.does_task_have_feature(t, "net").unwrap())
             .unwrap_or(false);
 
-        //
-        // See oxidecomputer/oana for standard Hubris UDP ports
         let dump_agent_socket = if has_dump_agent {
-            let target = format!("[{}%{}]:11113", ip, scopeid);
-            let dest = target.to_socket_addrs()?.collect::<Vec<_>>();
-            let dump_agent_socket = UdpSocket::bind("[::]:0")?;
-            dump_agent_socket.set_read_timeout(Some(timeout))?;
-            dump_agent_socket.connect(&dest[..])?;
-            Some(dump_agent_socket)
+            Some(connect(&resolve(ip, DUMP_AGENT_PORT)?, timeout)?)
         } else {
             None
         };
@@ -242,10 +294,12 @@ impl NetCore {
             ) {
                 Ok(addr) => addr,
                 Err(e) if e.to_string().contains("DumpAreaInUse") => {
-                    bail!(
-                        "out of space for dump_agent; use \
-                         `humility dump --initialize-dump-agent` to free space"
-                    )
+                    bail!(Diagnostic::error("out of space for dump_agent")
+                        .code("dump-area-in-use")
+                        .hint(
+                            "use `humility dump --initialize-dump-agent` \
+                            to free space"
+                        ))
                 }
                 Err(e) => {
                     bail!("dump agent failed: {e:?}")
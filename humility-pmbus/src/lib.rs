@@ -3,7 +3,11 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use anyhow::{anyhow, bail, Result};
+use hif::*;
 use humility::{core::Core, hubris::*};
+use humility_hiffy::HiffyContext;
+use humility_i2c::{I2cArgs, I2cRetryPolicy};
+use pmbus::commands::*;
 use std::collections::BTreeMap;
 
 /// Returns a map from voltage `SensorId` to position in `CONTROLLER_CONFIG`
@@ -54,3 +58,175 @@ pub fn sensor_id_map(
     }
     Ok(sensor_id_to_index)
 }
+
+/// Status of a PMBus rail, as read directly from its controller
+#[derive(Clone, Debug, Default)]
+pub struct RailStatus {
+    /// The controller reports the rail as off
+    pub off: bool,
+
+    /// Descriptions of the faults latched in STATUS_WORD
+    pub faults: Vec<String>,
+
+    /// Output voltage (READ_VOUT)
+    pub vout: Option<f32>,
+
+    /// Commanded output voltage (VOUT_COMMAND)
+    pub vout_command: Option<f32>,
+
+    /// Error, if the rail could not be read
+    pub error: Option<String>,
+}
+
+impl RailStatus {
+    /// Returns the deviation of the output voltage from the commanded
+    /// voltage, in percent
+    pub fn deviation(&self) -> Option<f32> {
+        match (self.vout, self.vout_command) {
+            (Some(vout), Some(cmd)) if cmd > 0.0 => {
+                Some((vout - cmd) / cmd * 100.0)
+            }
+            _ => None,
+        }
+    }
+}
+
+///
+/// Reads the status of each of the specified rails, each denoted by its
+/// PMBus device and its index within that device's rails.  This is done
+/// with direct I2C operations in a single HIF program (selecting the rail
+/// via PAGE on a multi-rail device) rather than via the power task, as
+/// callers want to know about rails that the power task doesn't know (or
+/// care) about.  A status is returned for each rail, in the order given.
+///
+pub fn rail_status(
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    rails: &[(&HubrisI2cDevice, usize)],
+) -> Result<Vec<RailStatus>> {
+    if rails.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let read = context.get_function("I2cRead", 7)?;
+    let write = context.get_function("I2cWrite", 8)?;
+
+    let page = CommandCode::PAGE as u8;
+    let reads = [
+        (CommandCode::VOUT_MODE as u8, 1),
+        (CommandCode::STATUS_WORD as u8, 2),
+        (CommandCode::VOUT_COMMAND as u8, 2),
+        (CommandCode::READ_VOUT as u8, 2),
+    ];
+
+    let mut ops = vec![];
+    let mut paged = vec![];
+
+    for (device, rnum) in rails {
+        let HubrisI2cDeviceClass::Pmbus { rails: all } = &device.class else {
+            bail!("{} is not a PMBus device", device.device);
+        };
+
+        let hargs = I2cArgs::from_device(device);
+
+        ops.push(Op::Push(hargs.controller));
+        ops.push(Op::Push(hargs.port.index));
+
+        if let Some((mux, segment)) = hargs.mux {
+            ops.push(Op::Push(mux));
+            ops.push(Op::Push(segment));
+        } else {
+            ops.push(Op::PushNone);
+            ops.push(Op::PushNone);
+        }
+
+        ops.push(Op::Push(device.address));
+
+        if all.len() > 1 {
+            ops.push(Op::Push(page));
+            ops.push(Op::Push(*rnum as u8));
+            ops.push(Op::Push(1));
+            ops.push(Op::Call(write.id));
+            ops.push(Op::DropN(3));
+        }
+
+        for (code, nbytes) in reads {
+            ops.push(Op::Push(code));
+            ops.push(Op::Push(nbytes));
+            ops.push(Op::Call(read.id));
+            ops.push(Op::DropN(2));
+        }
+
+        ops.push(Op::DropN(5));
+        paged.push(all.len() > 1);
+    }
+
+    ops.push(Op::Done);
+
+    let retry = I2cRetryPolicy::default().idempotent(None, Some(page));
+    let results = retry.run(context, core, &ops, None, &read)?;
+    let mut results = results.iter();
+    let mut statuses = vec![];
+
+    for paged in paged {
+        let mut status = RailStatus::default();
+
+        if paged {
+            if let Some(Err(code)) = results.next() {
+                status.error = Some(write.strerror(*code));
+            }
+        }
+
+        let mut next = || match results.next() {
+            Some(Ok(val)) => Ok(val.as_slice()),
+            Some(Err(code)) => Err(read.strerror(*code)),
+            None => Err("missing result".to_string()),
+        };
+
+        let (mode, word, command, vout) = (next(), next(), next(), next());
+
+        if status.error.is_none() {
+            status.error = match (&mode, &word) {
+                (Err(err), _) | (_, Err(err)) => Some(err.clone()),
+                _ => None,
+            };
+        }
+
+        let mode = mode.ok().and_then(VOUT_MODE::CommandData::from_slice);
+        let getmode = || mode.unwrap_or(VOUT_MODE::CommandData(0));
+
+        if let Some(word) =
+            word.ok().and_then(STATUS_WORD::CommandData::from_slice)
+        {
+            let _ = word.interpret(getmode, |field, value| {
+                if value.raw() == 0 {
+                    return;
+                }
+
+                if field.name() == "Off" {
+                    status.off = true;
+                } else if field.name().contains("Fault") {
+                    status.faults.push(field.desc().to_string());
+                }
+            });
+        }
+
+        if let Some(mode) = mode {
+            status.vout_command = command
+                .ok()
+                .and_then(VOUT_COMMAND::CommandData::from_slice)
+                .and_then(|c| c.get(mode).ok())
+                .map(|v| v.0);
+
+            status.vout = vout
+                .ok()
+                .and_then(READ_VOUT::CommandData::from_slice)
+                .and_then(|v| v.get(mode).ok())
+                .map(|v| v.0);
+        }
+
+        statuses.push(status);
+    }
+
+    Ok(statuses)
+}
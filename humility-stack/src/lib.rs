@@ -2,8 +2,10 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use humility::core::Core;
 use humility::hubris::*;
 use humility_arch_arm::ARMRegister;
+use std::collections::BTreeMap;
 
 #[derive(Debug, Default)]
 pub struct StackPrinter {
@@ -71,6 +73,16 @@ impl StackPrinter {
                 println!("0x{:08x} 0x{:08x}", frame.cfa, *pc);
             }
 
+            if let Some(exc_return) = frame.exc_return {
+                print_indent();
+                println!(
+                    "{:11}<exception entry from {} (EXC_RETURN 0x{:08x})>",
+                    "",
+                    humility_arch_arm::exc_return_describe(exc_return),
+                    exc_return
+                );
+            }
+
             if ndx + 1 < stack.len() {
                 print_indent();
             }
@@ -82,4 +94,60 @@ impl StackPrinter {
             println!();
         }
     }
+
+    ///
+    /// Unwinds and prints the stack of the specified task from a set of
+    /// registers, using the memory region that contains SP to determine the
+    /// base of the stack.  Failure to unwind is reported rather than
+    /// returned, as it is generally not fatal to the caller's report.
+    ///
+    pub fn print_from_registers(
+        &self,
+        hubris: &HubrisArchive,
+        core: &mut dyn Core,
+        regions: &BTreeMap<u32, HubrisRegion>,
+        task: HubrisTask,
+        regs: &BTreeMap<ARMRegister, u32>,
+    ) {
+        let sp = match regs.get(&ARMRegister::SP) {
+            Some(sp) => *sp,
+            None => return,
+        };
+
+        match regions.range(..=sp).next_back() {
+            Some((_, region)) if sp < region.base + region.size => {
+                let top = region.base + region.size;
+
+                match hubris.stack(core, task, top, regs) {
+                    Ok(stack) => self.print(hubris, &stack),
+                    Err(e) => humility::msg!("stack unwind failed: {e:?}"),
+                }
+            }
+            _ => humility::msg!("unknown region for SP 0x{sp:08x}"),
+        }
+    }
+}
+
+///
+/// Prints the general purpose registers (four to a line) and PSR from a set
+/// of registers.
+///
+pub fn print_registers(regs: &BTreeMap<ARMRegister, u32>) {
+    let gprs = regs
+        .iter()
+        .filter(|(reg, _)| reg.is_general_purpose())
+        .collect::<Vec<_>>();
+
+    for row in gprs.chunks(4) {
+        let row = row
+            .iter()
+            .map(|(reg, val)| format!("{:>5} = 0x{:08x}", reg, val))
+            .collect::<Vec<_>>();
+
+        println!("{}", row.join(" "));
+    }
+
+    if let Some(psr) = regs.get(&ARMRegister::PSR) {
+        println!("{:>5} = 0x{:08x}", ARMRegister::PSR, psr);
+    }
 }
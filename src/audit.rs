@@ -0,0 +1,118 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! The audit log, recording each command that modifies the target.  The
+//! audit log is opt-in:  records are appended to the file specified via
+//! `--audit-log` (or `HUMILITY_AUDIT_LOG`), or -- absent that -- to
+//! `~/.humility/audit.log` if (and only if) it exists.  Each record is a
+//! single line of JSON.
+//!
+
+use anyhow::{Context, Result};
+use humility_cli::{ExecutionContext, Subcommand};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+fn audit_file(context: &ExecutionContext) -> Option<PathBuf> {
+    if let Some(file) = &context.cli.audit_log {
+        return Some(PathBuf::from(file));
+    }
+
+    let file = PathBuf::from(std::env::var_os("HOME")?)
+        .join(".humility")
+        .join("audit.log");
+
+    file.exists().then_some(file)
+}
+
+///
+/// Formats seconds since the epoch as an RFC 3339 timestamp in UTC.  (This
+/// is the days-to-civil algorithm from Howard Hinnant's "chrono-Compatible
+/// Low-Level Date Algorithms".)
+///
+fn audit_time(secs: u64) -> String {
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+fn audit_write(
+    context: &ExecutionContext,
+    file: &Path,
+    started: Instant,
+    rval: &Result<()>,
+) -> Result<()> {
+    let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+
+    let image_id = match context.archive.as_ref() {
+        Some(hubris) => hubris.image_id().map(|id| {
+            id.iter().map(|b| format!("{b:02x}")).collect::<String>()
+        }),
+        None => None,
+    };
+
+    let serial = context.core.as_ref().and_then(|core| core.info().1);
+
+    let record = serde_json::json!({
+        "time": audit_time(now.as_secs()),
+        "user": std::env::var("USER").ok(),
+        "command": subargs,
+        "archive": context.cli.archive,
+        "image_id": image_id,
+        "target": context.cli.target,
+        "probe": context.cli.probe,
+        "ip": context.cli.ip,
+        "serial": serial,
+        "elapsed": started.elapsed().as_secs_f64(),
+        "result": match rval {
+            Ok(()) => "ok".to_string(),
+            Err(err) => format!("failed: {err:#}"),
+        },
+    });
+
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut log = OpenOptions::new().create(true).append(true).open(file)?;
+    writeln!(log, "{record}")?;
+
+    Ok(())
+}
+
+///
+/// Appends a record of the command that has just been run to the audit log,
+/// if there is one.  Because the command has already been run, a failure to
+/// write the record is reported as a warning rather than an error.
+///
+pub fn record(context: &ExecutionContext, started: Instant, rval: &Result<()>) {
+    let Some(file) = audit_file(context) else {
+        return;
+    };
+
+    if let Err(err) = audit_write(context, &file, started, rval)
+        .with_context(|| format!("failed to write to {}", file.display()))
+    {
+        humility::warn!("audit log: {err:#}");
+    }
+}
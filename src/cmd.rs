@@ -8,6 +8,7 @@ use humility::hubris::*;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Command, CommandKind};
 use std::collections::HashMap;
+use std::time::Instant;
 
 //
 // Our build.rs creates cmds.rs, which looks at our workspace to assemble
@@ -39,18 +40,71 @@ pub fn init(
     (cmds, rval)
 }
 
+fn subcommand_archive(
+    context: &ExecutionContext,
+    archive: Archive,
+    doneness: HubrisArchiveDoneness,
+) -> Result<HubrisArchive> {
+    let mut hubris = HubrisArchive::new().context("failed to initialize")?;
+
+    if archive != Archive::Ignored {
+        if let Some(archive) = &context.cli.archive {
+            hubris.load(archive, doneness).with_context(|| {
+                format!("failed to load archive \"{}\"", archive)
+            })?;
+        } else if let Some(dump) = &context.cli.dump {
+            hubris
+                .load_dump(dump, doneness)
+                .with_context(|| format!("failed to load dump \"{}\"", dump))?;
+        }
+    }
+
+    if archive == Archive::Required
+        && doneness == HubrisArchiveDoneness::Cook
+        && !hubris.loaded()
+    {
+        if context.cli.environment.is_some() {
+            bail!("must provide a Hubris archive, dump, or name");
+        }
+
+        bail!("must provide a Hubris archive or dump");
+    }
+
+    Ok(hubris)
+}
+
 pub fn subcommand(
     context: &mut ExecutionContext,
     commands: &HashMap<&'static str, Command>,
 ) -> Result<()> {
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
     let cmd = subargs[0].as_str();
+    let started = Instant::now();
+
+    //
+    // Discard any statistics accumulated by a previous command.
+    //
+    humility::stats::take();
 
     let command = commands
         .get(cmd)
         .with_context(|| format!("command {} not found", cmd))?;
 
-    let mut hubris = HubrisArchive::new().context("failed to initialize")?;
+    //
+    // A dry run is only meaningful for a command that modifies the target;
+    // for any other command, it is more likely to reflect a misunderstanding
+    // than an intent, so we fail explicitly.
+    //
+    let mutating = MUTATING.contains(&cmd);
+
+    if context.cli.dry_run && !mutating {
+        bail!(
+            "{} does not modify the target; --dry-run is only supported for \
+            commands that do ({})",
+            cmd,
+            MUTATING.join(", ")
+        );
+    }
 
     let (archive, doneness) = match &command.kind {
         CommandKind::Attached { archive, .. } => {
@@ -67,34 +121,22 @@ pub fn subcommand(
         }
     };
 
-    if archive != Archive::Ignored {
-        if let Some(archive) = &context.cli.archive {
-            hubris.load(archive, doneness).with_context(|| {
-                format!("failed to load archive \"{}\"", archive)
-            })?;
-        } else if let Some(dump) = &context.cli.dump {
-            hubris
-                .load_dump(dump, doneness)
-                .with_context(|| format!("failed to load dump \"{}\"", dump))?;
-        }
-    }
-
-    if archive == Archive::Required
+    //
+    // When we are interactive, the archive that was loaded for a previous
+    // command can be used for this one; loading an archive is often the
+    // most expensive part of a short command.
+    //
+    let loaded = context.is_interactive
         && doneness == HubrisArchiveDoneness::Cook
-        && !hubris.loaded()
-    {
-        if context.cli.environment.is_some() {
-            bail!("must provide a Hubris archive, dump, or name");
-        }
+        && context.archive.as_ref().map_or(false, |hubris| hubris.loaded());
 
-        bail!("must provide a Hubris archive or dump");
+    if !loaded {
+        context.archive = Some(subcommand_archive(context, archive, doneness)?);
     }
 
-    context.archive = Some(hubris);
-
     let run = command.run;
 
-    match &command.kind {
+    let rval = match &command.kind {
         CommandKind::Attached { attach, validate, .. } => {
             humility_cmd::attach(context, *attach, *validate, |context| {
                 (run)(context)
@@ -117,5 +159,46 @@ pub fn subcommand(
             (run)(context)
         }
         CommandKind::Raw { .. } => (run)(context),
+    };
+
+    //
+    // If a dry run has been stopped short of an operation that it could not
+    // report without performing, the operations that would have been
+    // performed have already been reported; this is not an error.
+    //
+    let rval = match rval {
+        Err(err) if err.is::<humility::core::DryRun>() => Ok(()),
+        rval => rval,
+    };
+
+    if context.cli.dry_run {
+        if rval.is_ok() {
+            humility::msg!("dry run complete; target was not modified");
+        }
+    } else if mutating {
+        crate::audit::record(context, started, &rval);
     }
+
+    if context.cli.stats || context.cli.verbose > 0 {
+        let stats = humility::stats::take();
+
+        if context.cli.stats {
+            humility::msg!(
+                "{}: {:.3}s elapsed; {}",
+                command.name,
+                started.elapsed().as_secs_f64(),
+                stats
+            );
+        }
+
+        //
+        // When verbose, we also report the throughput of the debug transport,
+        // allowing a slow operation to be attributed to the probe (or not).
+        //
+        if context.cli.verbose > 0 {
+            humility::msg!("{}: {}", command.name, stats.throughput());
+        }
+    }
+
+    rval
 }
@@ -6,14 +6,18 @@
 //!
 //! read, eval, print, loop
 
+use std::collections::BTreeSet;
+use std::path::PathBuf;
 use std::{borrow::Cow, collections::HashMap};
 
 use anyhow::Result;
 use clap::Command as ClapCommand;
 use clap::CommandFactory;
+use humility::hubris::{HubrisArchive, HubrisI2cDeviceClass};
 use humility_cli::{Cli, ExecutionContext};
 use humility_cmd::CommandKind;
 use humility_cmd::{Archive, Attach, Command, Validate};
+use humility_log::Diagnostic;
 
 use reedline::DefaultPrompt;
 use reedline::Span;
@@ -69,16 +73,59 @@ impl reedline::Prompt for Prompt {
     }
 }
 
+const HISTORY_SIZE: usize = 1000;
+
+///
+/// Returns the names that we complete as arguments: tasks, variables and
+/// power rails.
+///
+fn repl_names(hubris: &HubrisArchive) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+
+    for module in hubris.modules() {
+        names.insert(module.name.clone());
+    }
+
+    for (name, _) in hubris.qualified_variables() {
+        names.insert(name.to_string());
+    }
+
+    for device in &hubris.manifest.i2c_devices {
+        if let HubrisI2cDeviceClass::Pmbus { rails } = &device.class {
+            for rail in rails {
+                names.insert(rail.name.clone());
+            }
+        }
+    }
+
+    names
+}
+
 fn repl(context: &mut ExecutionContext) -> Result<()> {
     context.is_interactive = true;
 
-    // hardcoding to 100 entries for now. Even though this is called
-    // FileBackedHistory, this constructor only uses one in-memory.
-    let history = Box::new(FileBackedHistory::new(100));
+    //
+    // Our history is kept in ~/.humility_history, if we can determine our
+    // home directory; otherwise, it is kept in memory.
+    //
+    let history = match std::env::var_os("HOME") {
+        Some(home) => FileBackedHistory::with_file(
+            HISTORY_SIZE,
+            PathBuf::from(home).join(".humility_history"),
+        )?,
+        None => FileBackedHistory::new(HISTORY_SIZE),
+    };
+
+    let history = Box::new(history);
 
     let (commands, _) = cmd::init(Cli::command());
 
-    let completer = Box::new(ClapCompleter::new(commands));
+    let names = match context.archive {
+        Some(ref hubris) => repl_names(hubris),
+        None => BTreeSet::new(),
+    };
+
+    let completer = Box::new(ClapCompleter::new(commands, names));
 
     // Use the interactive menu to select options from the completer
     let completion_menu =
@@ -145,7 +192,10 @@ fn eval(context: &mut ExecutionContext, user_input: &str) -> Result<String> {
     context.cli.cmd = cli.cmd;
 
     if let Err(e) = cmd::subcommand(context, &commands) {
-        Ok(e.to_string())
+        match e.downcast_ref::<Diagnostic>() {
+            Some(diag) => Ok(diag.render()),
+            None => Ok(e.to_string()),
+        }
     } else {
         Ok(String::new())
     }
@@ -207,11 +257,15 @@ pub fn init() -> Command {
 
 struct ClapCompleter {
     commands: HashMap<&'static str, Command>,
+    names: BTreeSet<String>,
 }
 
 impl ClapCompleter {
-    fn new(commands: HashMap<&'static str, Command>) -> ClapCompleter {
-        ClapCompleter { commands }
+    fn new(
+        commands: HashMap<&'static str, Command>,
+        names: BTreeSet<String>,
+    ) -> ClapCompleter {
+        ClapCompleter { commands, names }
     }
 }
 
@@ -264,6 +318,25 @@ impl Completer for ClapCompleter {
                         }
                     }
 
+                    //
+                    // Anything that isn't an option may be the name of a
+                    // task, variable or rail.
+                    //
+                    if !last_word.is_empty() && !last_word.starts_with('-') {
+                        completions.extend(
+                            self.names
+                                .iter()
+                                .filter(|name| name.starts_with(last_word))
+                                .map(|name| Suggestion {
+                                    value: name.clone(),
+                                    description: None,
+                                    extra: None,
+                                    span,
+                                    append_whitespace: true,
+                                }),
+                        );
+                    }
+
                     completions
                 } else {
                     Vec::new()
@@ -0,0 +1,217 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//!
+//! Running a command against multiple targets, as specified via
+//! `--targets`.  Each target is run as its own Humility process attached
+//! via the target's probe; all targets run concurrently, with each line of
+//! output prefixed with the name of the target that generated it.
+//!
+
+use anyhow::{bail, Context, Result};
+use humility::msg;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::thread;
+
+struct Target {
+    name: String,
+    probe: String,
+}
+
+///
+/// A probe may be an alias; a probe that contains no colon and is not
+/// otherwise recognized is taken to be the serial number of a USB probe.
+///
+fn targets_probe(probe: &str) -> Result<String> {
+    let probe = humility::probes::resolve(probe)?;
+    let probe = probe.as_str();
+
+    match probe {
+        "usb" | "ocd" | "ocdgdb" | "jlink" | "auto" | "archive" => {
+            Ok(probe.to_string())
+        }
+        _ if probe.contains(':') || probe.starts_with("usb-") => {
+            Ok(probe.to_string())
+        }
+        _ => humility::core::usb_probe_by_serial(probe),
+    }
+}
+
+fn targets_read(filename: &str) -> Result<Vec<Target>> {
+    let contents = std::fs::read_to_string(filename)
+        .with_context(|| format!("failed to read targets \"{filename}\""))?;
+
+    let mut targets: Vec<Target> = vec![];
+
+    for (ndx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+
+        let (name, probe) = match fields[..] {
+            [probe] => (probe, probe),
+            [name, probe] => (name, probe),
+            _ => {
+                bail!(
+                    "{filename}, line {}: expected a probe, optionally \
+                    preceded by a name",
+                    ndx + 1
+                );
+            }
+        };
+
+        if targets.iter().any(|t| t.name == name) {
+            bail!("{filename}, line {}: duplicate target {name}", ndx + 1);
+        }
+
+        targets.push(Target {
+            name: name.to_string(),
+            probe: targets_probe(probe)
+                .with_context(|| format!("target {name}"))?,
+        });
+    }
+
+    if targets.is_empty() {
+        bail!("no targets found in {filename}");
+    }
+
+    Ok(targets)
+}
+
+///
+/// Emits each line read from a child's output, prefixed with the name of
+/// its target.  (Output need not be UTF-8, so we read it as bytes.)
+///
+fn targets_relay(
+    prefix: String,
+    output: impl Read,
+    stderr: bool,
+) -> Result<()> {
+    let mut reader = BufReader::new(output);
+    let mut buf = vec![];
+
+    loop {
+        buf.clear();
+
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+
+        if stderr {
+            eprintln!("{prefix}{line}");
+        } else {
+            println!("{prefix}{line}");
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Runs our command against every target in the specified file, returning
+/// true if it succeeded on all of them.  `args` are our original arguments,
+/// of which the last `subargs` constitute the subcommand and its arguments.
+///
+pub(crate) fn run(
+    filename: &str,
+    args: &[OsString],
+    subargs: usize,
+) -> Result<bool> {
+    let targets = targets_read(filename)?;
+
+    let exe = std::env::current_exe()
+        .context("failed to determine path to humility")?;
+
+    //
+    // Our arguments are everything up to the subcommand, less --targets and
+    // its argument; to these, we add the probe for each target.
+    //
+    let (global, subcmd) = args[1..].split_at(args.len() - 1 - subargs);
+    let mut passed = vec![];
+    let mut iter = global.iter();
+
+    while let Some(arg) = iter.next() {
+        if arg == "--targets" {
+            iter.next();
+        } else if !arg.to_string_lossy().starts_with("--targets=") {
+            passed.push(arg.clone());
+        }
+    }
+
+    if subcmd.first().map_or(false, |cmd| cmd == "repl") {
+        bail!("cannot run the repl against multiple targets");
+    }
+
+    let width = targets.iter().map(|t| t.name.len()).max().unwrap() + 1;
+    let mut children = vec![];
+
+    msg!("running against {} targets", targets.len());
+
+    for target in &targets {
+        let mut child = Command::new(&exe)
+            .args(&passed)
+            .arg("--probe")
+            .arg(&target.probe)
+            .args(subcmd)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| {
+                format!("failed to run against {}", target.name)
+            })?;
+
+        let prefix = format!("{:width$} ", format!("{}:", target.name));
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+
+        let relays = [
+            thread::spawn({
+                let prefix = prefix.clone();
+                move || targets_relay(prefix, stdout, false)
+            }),
+            thread::spawn(move || targets_relay(prefix, stderr, true)),
+        ];
+
+        children.push((target, child, relays));
+    }
+
+    let mut failed = vec![];
+
+    for (target, mut child, relays) in children {
+        let status = child.wait()?;
+
+        for relay in relays {
+            if let Ok(Err(err)) = relay.join() {
+                msg!("{}: failed to relay output: {err}", target.name);
+            }
+        }
+
+        if !status.success() {
+            failed.push(target.name.as_str());
+        }
+    }
+
+    if failed.is_empty() {
+        msg!("succeeded on all {} targets", targets.len());
+        Ok(true)
+    } else {
+        msg!(
+            "failed on {} of {} targets: {}",
+            failed.len(),
+            targets.len(),
+            failed.join(", ")
+        );
+        Ok(false)
+    }
+}
@@ -9,17 +9,22 @@ use clap::ArgMatches;
 use humility_cli::Cli;
 use humility_cli::Subcommand;
 use humility_cmd::Command;
+use humility_log::{Diagnostic, LogFormat};
 
 use anyhow::Result;
 use clap::CommandFactory;
 use clap::FromArgMatches;
 use clap::Parser;
 
+mod audit;
 mod cmd;
 mod cmd_repl;
+mod cmd_targets;
 
 fn main() -> Result<()> {
-    let (commands, m, args) = match parse_args(&mut std::env::args_os()) {
+    let input = std::env::args_os().collect::<Vec<_>>();
+
+    let (commands, m, args) = match parse_args(input.clone()) {
         Some(s) => s,
         None => std::process::exit(1),
     };
@@ -29,14 +34,31 @@ fn main() -> Result<()> {
         std::process::exit(0);
     };
 
+    //
+    // If we have been given multiple targets, we run our command against
+    // each of them in its own process, and exit non-zero if it failed on
+    // any of them.
+    //
+    if let Some(ref targets) = args.targets {
+        let Some(Subcommand::Other(subargs)) = &args.cmd else {
+            eprintln!("humility failed: subcommand expected (--help to list)");
+            std::process::exit(1);
+        };
+
+        match cmd_targets::run(targets, &input, subargs.len()) {
+            Ok(true) => std::process::exit(0),
+            Ok(false) => std::process::exit(1),
+            Err(err) => {
+                eprintln!("humility failed: {:?}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
     let mut context =
         humility_cli::ExecutionContext::new(args.clone(), &m, false)?;
 
-    let log_level = if args.verbose { "trace" } else { "warn" };
-
-    let env = env_logger::Env::default().filter_or("RUST_LOG", log_level);
-
-    env_logger::init_from_env(env);
+    humility_log::init_logger(args.verbose, &args.log, args.log_format);
 
     // stash this away in case we fail
     let subcmd = match args.cmd.as_ref().unwrap() {
@@ -44,7 +66,21 @@ fn main() -> Result<()> {
     };
 
     if let Err(err) = cmd::subcommand(&mut context, &commands) {
-        eprintln!("humility {} failed: {:?}", subcmd, err);
+        let diag = err.downcast_ref::<Diagnostic>();
+
+        if args.log_format == LogFormat::Json {
+            let diag = match diag {
+                Some(diag) => diag.clone(),
+                None => Diagnostic::error(format!("{:#}", err)),
+            };
+
+            eprintln!("{}", diag.to_json());
+        } else if let Some(diag) = diag {
+            eprintln!("humility {} failed: {}", subcmd, diag.render());
+        } else {
+            eprintln!("humility {} failed: {:?}", subcmd, err);
+        }
+
         std::process::exit(1);
     }
 
@@ -54,9 +90,10 @@ fn main() -> Result<()> {
 pub(crate) fn version(cli: &Cli) -> Option<String> {
     if cli.version {
         Some(format!(
-            "{} {}",
+            "{} {}{}",
             env!("CARGO_BIN_NAME"),
-            env!("CARGO_PKG_VERSION")
+            env!("CARGO_PKG_VERSION"),
+            if cfg!(feature = "operator") { " (operator)" } else { "" }
         ))
     } else {
         None
@@ -16,9 +16,29 @@ enum Xtask {
     Readme,
 }
 
+///
+/// Substitutes the list of commands that are safe for operators (that is,
+/// those with `operator = true` in their `[package.metadata.humility]`) into
+/// the README.
+///
+fn operator_commands(input: &str, operator: &mut Vec<String>) -> String {
+    operator.sort();
+
+    if let Some(last) = operator.pop() {
+        if !operator.is_empty() {
+            operator.push(format!("and {}", last));
+        } else {
+            operator.push(last);
+        }
+    }
+
+    input.replace("@OPERATOR_COMMANDS@", &format!("{}.", operator.join(", ")))
+}
+
 fn make_readme() -> Result<()> {
     use cargo_metadata::MetadataCommand;
     let mut cmds = BTreeMap::new();
+    let mut operator = vec![];
 
     let metadata =
         MetadataCommand::new().manifest_path("./Cargo.toml").exec().unwrap();
@@ -35,12 +55,18 @@ fn make_readme() -> Result<()> {
                 }
             };
 
+            if package.metadata["humility"]["operator"].as_bool() == Some(true)
+            {
+                operator.push(format!("`{}`", cmd));
+            }
+
             cmds.insert(cmd.to_string(), (description, package.manifest_path));
         }
     }
 
     let root = std::path::PathBuf::from(metadata.workspace_root);
-    let input = std::fs::read(root.join("README.md.in"))?;
+    let input = std::fs::read_to_string(root.join("README.md.in"))?;
+    let input = operator_commands(&input, &mut operator);
     let mut output = File::create(root.join("README.md"))?;
 
     writeln!(
@@ -58,7 +84,7 @@ fn make_readme() -> Result<()> {
 "##
     )?;
 
-    output.write_all(&input)?;
+    output.write_all(input.as_bytes())?;
 
     writeln!(output, "## Commands\n")?;
 